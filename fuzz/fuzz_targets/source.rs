@@ -0,0 +1,13 @@
+//! Runs the analyses, the compiler and the VM on arbitrary source text, which
+//! should never make any of them panic. Seed it with the inputs in res/fuzz,
+//! and add any input that it finds there, so `tests/no_panic.rs` keeps
+//! checking it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/no_panic/exercise.rs"]
+mod exercise;
+
+fuzz_target!(|source: &str| exercise::exercise(source));
@@ -0,0 +1,229 @@
+//! Runs the VM on generated bytecode. The chunks are well-formed: every
+//! operand is in bounds and the stack is balanced, like the output of the
+//! compiler. Their values are arbitrary, though, so they hit type errors,
+//! undefined names and bad calls in ways that compiled Lox rarely does.
+//!
+//! The VM is built with `vm-checked`, so any unsound access it makes on such
+//! a chunk panics instead of silently corrupting memory.
+
+#![no_main]
+
+use arbitrary::{Result, Unstructured};
+use libfuzzer_sys::fuzz_target;
+use loxcraft::error::Error;
+use loxcraft::vm::{ChunkBuilder, Constant, VM, VmOptions, op};
+
+/// Generated chunks keep their stack shallower than this, well within what
+/// the VM allows for a single frame.
+const MAX_DEPTH: usize = 32;
+const MAX_NESTING: usize = 4;
+const MAX_STMTS: usize = 16;
+
+/// Names for globals and properties. Some of these are natives.
+const NAMES: &[&str] = &["a", "b", "bind", "clock", "inspect", "unbind"];
+
+const BINARY_OPS: &[u8] = &[
+    op::EQUAL,
+    op::NOT_EQUAL,
+    op::GREATER,
+    op::GREATER_EQUAL,
+    op::LESS,
+    op::LESS_EQUAL,
+    op::ADD,
+    op::SUBTRACT,
+    op::MULTIPLY,
+    op::DIVIDE,
+];
+
+fuzz_target!(|data: &[u8]| {
+    let mut generator = Generator::default();
+    let Ok(()) = generator.block(&mut Unstructured::new(data), 0) else {
+        return;
+    };
+    generator.chunk.emit(op::NIL).emit(op::RETURN);
+
+    // The generated chunks are well-formed, so the verifier should accept all
+    // of them.
+    let mut vm = VM::new(VmOptions { max_output: Some(4096), ..Default::default() });
+    if let Err((Error::BytecodeError(e), _)) = vm.run_chunk(&generator.chunk, &mut Vec::new()) {
+        panic!("verifier rejected a well-formed chunk: {e}");
+    }
+});
+
+#[derive(Default)]
+struct Generator {
+    chunk: ChunkBuilder,
+    /// The number of values on the stack.
+    depth: usize,
+    /// The number of values at the bottom of the stack that are locals. At
+    /// the start of each statement, `depth == locals`.
+    locals: usize,
+}
+
+impl Generator {
+    /// Emits a sequence of statements, and pops any locals they declare.
+    fn block(&mut self, u: &mut Unstructured, nesting: usize) -> Result<()> {
+        let locals = self.locals;
+        for _ in 0..u.int_in_range(0..=MAX_STMTS)? {
+            if u.is_empty() {
+                break;
+            }
+            self.stmt(u, nesting)?;
+        }
+        while self.locals > locals {
+            self.pop();
+            self.locals -= 1;
+        }
+        Ok(())
+    }
+
+    /// Emits ops that leave the stack as they found it, apart from declaring
+    /// locals.
+    fn stmt(&mut self, u: &mut Unstructured, nesting: usize) -> Result<()> {
+        match u.int_in_range(0..=6)? {
+            0 => {
+                self.expr(u, 0)?;
+                let print = *u.choose(&[op::PRINT, op::PRINT_SPACED])?;
+                self.emit_pop(print);
+            }
+            1 => {
+                self.expr(u, 0)?;
+                self.pop();
+            }
+            2 => {
+                self.expr(u, 0)?;
+                let name = self.name(u)?;
+                self.emit_pop(op::DEFINE_GLOBAL).chunk.emit(name);
+            }
+            3 => {
+                self.expr(u, 0)?;
+                let name = self.name(u)?;
+                self.chunk.emit(op::SET_GLOBAL).emit(name);
+                self.pop();
+            }
+            4 if self.locals > 0 => {
+                self.expr(u, 0)?;
+                let slot = u.int_in_range(0..=self.locals - 1)? as u8;
+                self.chunk.emit(op::SET_LOCAL).emit(slot);
+                self.pop();
+            }
+            5 if self.depth < MAX_DEPTH => {
+                self.expr(u, 0)?;
+                self.locals += 1;
+            }
+            6 if nesting < MAX_NESTING => {
+                self.expr(u, 0)?;
+                let else_jump = self.chunk.emit_jump(op::JUMP_IF_FALSE);
+                self.pop();
+                self.block(u, nesting + 1)?;
+                let end_jump = self.chunk.emit_jump(op::JUMP);
+                self.patch_jump(else_jump)?;
+                self.chunk.emit(op::POP);
+                self.block(u, nesting + 1)?;
+                self.patch_jump(end_jump)?;
+            }
+            _ => {
+                self.expr(u, 0)?;
+                self.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits ops that push exactly one value.
+    fn expr(&mut self, u: &mut Unstructured, nesting: usize) -> Result<()> {
+        let can_nest = nesting < MAX_NESTING && self.depth + 4 < MAX_DEPTH;
+        match u.int_in_range(0..=9)? {
+            0 => {
+                let constant = if u.arbitrary()? {
+                    Constant::Number(u.arbitrary()?)
+                } else {
+                    Constant::String(u.arbitrary()?)
+                };
+                match self.chunk.constant(constant) {
+                    Some(idx) => {
+                        self.emit_push(op::CONSTANT).chunk.emit(idx);
+                    }
+                    None => {
+                        self.emit_push(op::NIL);
+                    }
+                }
+            }
+            1 if u.arbitrary()? => {
+                self.emit_push(op::SMALL_INT).chunk.emit(u.arbitrary()?);
+            }
+            1 => {
+                let literal = *u.choose(&[op::NIL, op::TRUE, op::FALSE])?;
+                self.emit_push(literal);
+            }
+            2 if self.locals > 0 => {
+                let slot = u.int_in_range(0..=self.locals - 1)? as u8;
+                self.emit_push(op::GET_LOCAL).chunk.emit(slot);
+            }
+            3 => {
+                let name = self.name(u)?;
+                self.emit_push(op::GET_GLOBAL).chunk.emit(name);
+            }
+            4 if can_nest => {
+                self.expr(u, nesting + 1)?;
+                self.expr(u, nesting + 1)?;
+                let binary = *u.choose(BINARY_OPS)?;
+                self.emit_pop(binary);
+            }
+            5 if can_nest => {
+                self.expr(u, nesting + 1)?;
+                let unary = *u.choose(&[op::NOT, op::NEGATE])?;
+                self.chunk.emit(unary);
+            }
+            6 if can_nest => {
+                self.expr(u, nesting + 1)?;
+                let name = self.name(u)?;
+                self.chunk.emit(op::GET_PROPERTY).emit(name);
+            }
+            7 if can_nest => {
+                self.expr(u, nesting + 1)?;
+                self.expr(u, nesting + 1)?;
+                let name = self.name(u)?;
+                self.emit_pop(op::SET_PROPERTY).chunk.emit(name);
+            }
+            8 if can_nest => {
+                self.expr(u, nesting + 1)?;
+                let arg_count = u.int_in_range(0..=2)?;
+                for _ in 0..arg_count {
+                    self.expr(u, nesting + 1)?;
+                }
+                self.chunk.emit(op::CALL).emit(arg_count as u8);
+                self.depth -= arg_count;
+            }
+            _ => {
+                self.emit_push(op::NIL);
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&mut self, u: &mut Unstructured) -> Result<u8> {
+        let name = u.choose(NAMES)?.to_string();
+        self.chunk.constant(Constant::String(name)).ok_or(arbitrary::Error::NotEnoughData)
+    }
+
+    fn patch_jump(&mut self, offset_idx: usize) -> Result<()> {
+        self.chunk.patch_jump(offset_idx).ok_or(arbitrary::Error::NotEnoughData)
+    }
+
+    fn emit_push(&mut self, op: u8) -> &mut Self {
+        self.chunk.emit(op);
+        self.depth += 1;
+        self
+    }
+
+    fn emit_pop(&mut self, op: u8) -> &mut Self {
+        self.chunk.emit(op);
+        self.depth -= 1;
+        self
+    }
+
+    fn pop(&mut self) {
+        self.emit_pop(op::POP);
+    }
+}
@@ -15,6 +15,10 @@ export default function RootLayout({
 }) {
   return (
     <html lang="en" suppressHydrationWarning>
+      <head>
+        {/* Served by `lox playground`, relative to the base path. */}
+        <link rel="manifest" href="manifest.webmanifest" />
+      </head>
       <body>
         <ThemeProvider attribute="class" defaultTheme="dark">
           {children}
@@ -10,7 +10,18 @@ import {
 import { Github, Loader2, Lock, Play } from "lucide-react";
 import Link from "next/link";
 import { ScrollArea, ScrollBar } from "@/components/ui/scroll-area";
+import "ace-builds/src-noconflict/ext-language_tools";
+import "ace-builds/src-noconflict/theme-dracula";
+import "ace-builds/src-noconflict/theme-github";
+import "ace-builds/src-noconflict/theme-monokai";
+import "ace-builds/src-noconflict/theme-solarized_light";
 import "ace-builds/src-noconflict/theme-tomorrow_night_bright";
+import init, {
+  loxCompletions,
+  loxDiagnostics,
+  loxIsComplete,
+  loxQuickCheck,
+} from "lox-wasm";
 import dynamic from "next/dynamic";
 import React from "react";
 import { create } from "zustand";
@@ -29,14 +40,120 @@ type LoxOutMessageExitSuccess = {
   type: "ExitSuccess";
 };
 
+type LoxOutMessageOutputLimitExceeded = {
+  type: "OutputLimitExceeded";
+  limit: number;
+};
+
+type LoxOutMessageStats = {
+  type: "Stats";
+  ops: number;
+  peak_heap: number;
+  gc_count: number;
+  objects: Record<string, { allocated: number; live: number }>;
+  wall_time_ms: number;
+};
+
 type LoxOutMessage =
   | LoxOutMessageOutput
   | LoxOutMessageExitFailure
-  | LoxOutMessageExitSuccess;
+  | LoxOutMessageExitSuccess
+  | LoxOutMessageOutputLimitExceeded
+  | LoxOutMessageStats;
+
+type LoxAnnotation = {
+  row: number;
+  column: number;
+  text: string;
+  type: "error" | "warning";
+};
+
+// Analysis runs on the main thread, since it is fast and does not execute any
+// user code. The module is loaded on first use, since it can only be loaded in
+// the browser, and not while the page is prerendered.
+let analysisModule: ReturnType<typeof init> | undefined;
+const analysisReady = () => (analysisModule ??= init());
+
+// How long to wait after the last keystroke before the full analysis, which
+// resolves and lints the program, runs. Syntax errors are checked on every
+// keystroke.
+const ANALYSIS_DELAY_MS = 300;
+
+// The settings of the editor, which are kept by the server so that they are
+// the same in every browser. These must match the settings that the server
+// accepts, in src/playground.rs.
+type Settings = {
+  theme: string;
+  font_family: string;
+  font_size: number;
+};
+
+const THEMES = [
+  "tomorrow_night_bright",
+  "monokai",
+  "dracula",
+  "github",
+  "solarized_light",
+];
+
+const FONT_FAMILIES = [
+  "monospace",
+  '"Fira Code", monospace',
+  '"JetBrains Mono", monospace',
+];
+
+const FONT_SIZES = [12, 14, 16, 18, 20];
+
+const DEFAULT_SETTINGS: Settings = {
+  theme: THEMES[0],
+  font_family: FONT_FAMILIES[0],
+  font_size: 14,
+};
+
+// Settings are relative to the page, so that they are found under the base
+// path that the playground is served from. The playground still works without
+// them, e.g. when it is served as static files.
+const loadSettings = async (): Promise<Settings> => {
+  try {
+    const response = await fetch("settings");
+    return response.ok
+      ? { ...DEFAULT_SETTINGS, ...(await response.json()) }
+      : DEFAULT_SETTINGS;
+  } catch {
+    return DEFAULT_SETTINGS;
+  }
+};
+
+const saveSettings = (settings: Settings) =>
+  fetch("settings", {
+    method: "PUT",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(settings),
+  }).catch(() => {});
+
+const loxCompleter = {
+  getCompletions: (
+    _editor: unknown,
+    session: { getValue: () => string },
+    _pos: unknown,
+    _prefix: string,
+    callback: (error: null, completions: unknown[]) => void,
+  ) => {
+    analysisReady().then(() =>
+      callback(null, JSON.parse(loxCompletions(session.getValue()))),
+    );
+  },
+};
+
+// Programs can print without a trailing newline, e.g. with write(), so the
+// line is ended before anything else is shown after the output.
+const endLine = (text: string) =>
+  text === "" || text.endsWith("\n") ? text : `${text}\n`;
 
 type State = {
   editorText: string;
   outputText: string;
+  stats: LoxOutMessageStats | null;
   worker?: Worker;
   workerStartTime: number;
 };
@@ -53,6 +170,7 @@ const useStore = create<State & Action>()(
     (set, get) => ({
       editorText: "",
       outputText: "",
+      stats: null,
       worker: null,
       workerStartTime: 0,
 
@@ -71,10 +189,30 @@ const useStore = create<State & Action>()(
               set((state) => ({ outputText: state.outputText + msg.text }));
               break;
             }
+            case "Stats": {
+              set({ stats: msg });
+              break;
+            }
             case "ExitSuccess": {
               set((state) => {
                 const elapsedTime = (Date.now() - state.workerStartTime) / 1000;
-                const outputText = `${state.outputText}---\nProgram exited successfully (${elapsedTime}s).\n`;
+                const outputText = `${endLine(state.outputText)}---\nProgram exited successfully (${elapsedTime}s).\n`;
+
+                state.worker?.terminate();
+
+                return {
+                  outputText: outputText,
+                  worker: null,
+                  workerStartTime: 0,
+                };
+              });
+              break;
+            }
+            case "OutputLimitExceeded": {
+              set((state) => {
+                const elapsedTime = (Date.now() - state.workerStartTime) / 1000;
+                const limit = (msg.limit / (1024 * 1024)).toFixed(1);
+                const outputText = `${endLine(state.outputText)}---\nProgram stopped after printing more than ${limit} MiB of output (${elapsedTime}s).\n`;
 
                 state.worker?.terminate();
 
@@ -89,7 +227,7 @@ const useStore = create<State & Action>()(
             case "ExitFailure": {
               set((state) => {
                 const elapsedTime = (Date.now() - state.workerStartTime) / 1000;
-                const outputText = `${state.outputText}---\nProgram exited with errors (${elapsedTime}s).\n`;
+                const outputText = `${endLine(state.outputText)}---\nProgram exited with errors (${elapsedTime}s).\n`;
 
                 state.worker?.terminate();
 
@@ -106,6 +244,7 @@ const useStore = create<State & Action>()(
 
         set({
           outputText: "",
+          stats: null,
           worker: worker,
           workerStartTime: Date.now(),
         });
@@ -115,7 +254,7 @@ const useStore = create<State & Action>()(
       terminateVM: () => {
         set((state) => {
           const elapsedTime = (Date.now() - state.workerStartTime) / 1000;
-          const outputText = `${state.outputText}---\nProgram exited terminated (${elapsedTime}s).\n`;
+          const outputText = `${endLine(state.outputText)}---\nProgram exited terminated (${elapsedTime}s).\n`;
           return {
             outputText: outputText,
             worker: null,
@@ -136,10 +275,45 @@ const useStore = create<State & Action>()(
   ),
 );
 
+function formatBytes(bytes: number): string {
+  if (bytes < 1024) {
+    return `${bytes} B`;
+  }
+  if (bytes < 1024 * 1024) {
+    return `${(bytes / 1024).toFixed(1)} KiB`;
+  }
+  return `${(bytes / (1024 * 1024)).toFixed(1)} MiB`;
+}
+
+function Stats({ stats }: { stats: LoxOutMessageStats }) {
+  const items = [
+    ["Instructions", stats.ops.toLocaleString()],
+    ["Peak heap", formatBytes(stats.peak_heap)],
+    ["GC runs", stats.gc_count.toLocaleString()],
+    ["Wall time", `${stats.wall_time_ms.toFixed(1)} ms`],
+    [
+      "Objects",
+      Object.entries(stats.objects)
+        .map(([type, { allocated, live }]) => `${type} ${live}/${allocated}`)
+        .join(", "),
+    ],
+  ];
+  return (
+    <div className="border flex font-mono gap-4 mt-2 p-2 rounded-md text-xs">
+      {items.map(([label, value]) => (
+        <div key={label}>
+          <span className="text-muted-foreground">{label}:</span> {value}
+        </div>
+      ))}
+    </div>
+  );
+}
+
 function Page() {
   const {
     editorText,
     outputText,
+    stats,
     setEditorText,
     startVM,
     terminateVM,
@@ -147,6 +321,47 @@ function Page() {
   } = useStore();
   const isRunning = isVMRunning();
 
+  // `lox playground` serves a service worker, so that the page works offline
+  // once it has been loaded.
+  React.useEffect(() => {
+    navigator.serviceWorker?.register("sw.js").catch(() => {});
+  }, []);
+
+  const [annotations, setAnnotations] = React.useState<LoxAnnotation[]>([]);
+  const [settings, setSettings] = React.useState<Settings>(DEFAULT_SETTINGS);
+
+  React.useEffect(() => {
+    loadSettings().then(setSettings);
+  }, []);
+
+  const updateSettings = (update: Partial<Settings>) => {
+    const updated = { ...settings, ...update };
+    setSettings(updated);
+    saveSettings(updated);
+  };
+  React.useEffect(() => {
+    let cancelled = false;
+    let timeout: ReturnType<typeof setTimeout> | undefined;
+    analysisReady().then(() => {
+      if (cancelled) {
+        return;
+      }
+      setAnnotations(JSON.parse(loxQuickCheck(editorText)));
+      // An incomplete program has nothing more to report than its syntax
+      // errors.
+      if (loxIsComplete(editorText)) {
+        timeout = setTimeout(
+          () => setAnnotations(JSON.parse(loxDiagnostics(editorText))),
+          ANALYSIS_DELAY_MS,
+        );
+      }
+    });
+    return () => {
+      cancelled = true;
+      clearTimeout(timeout);
+    };
+  }, [editorText]);
+
   return (
     <div className="flex flex-col h-screen">
       <nav className="bg-background border-b flex items-center justify-between select-none p-4">
@@ -159,6 +374,44 @@ function Page() {
           </Link>
         </div>
         <div className="space-x-1">
+          <select
+            aria-label="Theme"
+            className="border h-10 px-2 rounded-md text-sm"
+            onChange={(e) => updateSettings({ theme: e.target.value })}
+            value={settings.theme}
+          >
+            {THEMES.map((theme) => (
+              <option key={theme} value={theme}>
+                {theme.replaceAll("_", " ")}
+              </option>
+            ))}
+          </select>
+          <select
+            aria-label="Font"
+            className="border h-10 px-2 rounded-md text-sm"
+            onChange={(e) => updateSettings({ font_family: e.target.value })}
+            value={settings.font_family}
+          >
+            {FONT_FAMILIES.map((fontFamily) => (
+              <option key={fontFamily} value={fontFamily}>
+                {fontFamily.split(",")[0].replaceAll('"', "")}
+              </option>
+            ))}
+          </select>
+          <select
+            aria-label="Font size"
+            className="border h-10 px-2 rounded-md text-sm"
+            onChange={(e) =>
+              updateSettings({ font_size: Number(e.target.value) })
+            }
+            value={settings.font_size}
+          >
+            {FONT_SIZES.map((fontSize) => (
+              <option key={fontSize} value={fontSize}>
+                {fontSize}px
+              </option>
+            ))}
+          </select>
           <Button
             className="min-w-28"
             variant={isRunning ? "destructive" : "default"}
@@ -195,30 +448,39 @@ function Page() {
       >
         <ResizablePanel className="h-full p-2 w-full">
           <AceEditor
+            annotations={annotations}
             className="rounded-md"
+            enableBasicAutocompletion
+            enableLiveAutocompletion
             height="100%"
             focus
+            fontSize={settings.font_size}
             mode={null}
             name="editor"
             onChange={setEditorText}
+            onLoad={(editor) => {
+              editor.completers = [loxCompleter];
+            }}
             setOptions={{
               cursorStyle: "slim",
+              fontFamily: settings.font_family,
             }}
             showPrintMargin={false}
-            theme="tomorrow_night_bright"
+            theme={settings.theme}
             value={editorText}
             width="100%"
           />
         </ResizablePanel>
         <ResizableHandle />
-        <ResizablePanel className="h-full p-2 w-full">
-          <ScrollArea className="border h-full p-1 rounded-md w-full">
+        <ResizablePanel className="flex flex-col h-full p-2 w-full">
+          <ScrollArea className="border flex-grow p-1 rounded-md w-full">
             <div
               className="font-mono text-sm whitespace-pre min-h-max min-w-max"
               dangerouslySetInnerHTML={{ __html: outputText }}
             ></div>
             <ScrollBar orientation="horizontal" />
           </ScrollArea>
+          {stats && <Stats stats={stats} />}
         </ResizablePanel>
       </ResizablePanelGroup>
     </div>
@@ -0,0 +1,58 @@
+// The service worker that lets the playground work offline. `lox playground`
+// serves it with the placeholders below filled in, so that the list of assets
+// and the name of the cache change whenever the embedded assets do.
+
+const CACHE = "loxcraft-playground-{{VERSION}}";
+const ASSETS = {{ASSETS}};
+
+self.addEventListener("install", (event) => {
+  event.waitUntil(
+    caches
+      .open(CACHE)
+      .then((cache) => cache.addAll(ASSETS))
+      .then(() => self.skipWaiting()),
+  );
+});
+
+// Caches from older versions of the assets are removed once this worker takes
+// over.
+self.addEventListener("activate", (event) => {
+  event.waitUntil(
+    caches
+      .keys()
+      .then((keys) =>
+        Promise.all(
+          keys
+            .filter((key) => key.startsWith("loxcraft-playground-") && key !== CACHE)
+            .map((key) => caches.delete(key)),
+        ),
+      )
+      .then(() => self.clients.claim()),
+  );
+});
+
+// Assets are served from the cache, falling back to the network. Pages that
+// are not cached get the index, so that the playground still loads offline.
+self.addEventListener("fetch", (event) => {
+  const request = event.request;
+  if (request.method !== "GET" || new URL(request.url).origin !== location.origin) {
+    return;
+  }
+  event.respondWith(
+    caches.open(CACHE).then(async (cache) => {
+      const cached = await cache.match(request, { ignoreSearch: true });
+      if (cached) {
+        return cached;
+      }
+      try {
+        return await fetch(request);
+      } catch (error) {
+        const index = request.mode === "navigate" && (await cache.match(ASSETS[0]));
+        if (index) {
+          return index;
+        }
+        throw error;
+      }
+    }),
+  );
+});
@@ -1,19 +1,76 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fmt::{self, Display, Formatter};
 use std::io::{self, Write};
 
-use loxcraft::error::report_error;
-use loxcraft::vm::VM;
+use loxcraft::analysis::{self, CompletionKind, Diagnostic, Severity};
+use loxcraft::error::{Error, IoError, report_error};
+use loxcraft::lint::LintOptions;
+use loxcraft::vfs::MemoryFs;
+use loxcraft::vm::{ObjectCount, VM, VmOptions};
 use serde::Serialize;
 use termcolor::{Color, WriteColor};
 use wasm_bindgen::prelude::*;
 
+/// The maximum number of bytes a program may print, so that a runaway program
+/// cannot freeze the page.
+const MAX_OUTPUT: usize = 1 << 20;
+
+thread_local! {
+    /// The files added with `loxAddFile`, which programs can read with
+    /// `read_file()`, since there is no file system in the browser.
+    static FILES: RefCell<MemoryFs> = RefCell::default();
+}
+
+/// Adds a file for programs to read with `read_file()`, replacing any file
+/// with the same name.
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn loxAddFile(name: &str, contents: &str) {
+    FILES.with(|files| files.borrow_mut().add_file(name, contents));
+}
+
 #[wasm_bindgen]
 #[allow(non_snake_case)]
 pub fn loxRun(source: &str) {
     let output = &mut Output::new();
-    match VM::default().run(source, output) {
+    let mut vm = VM::new(VmOptions {
+        max_output: Some(MAX_OUTPUT),
+        eliminate_dead_globals: true,
+        direct_native_calls: true,
+        ..Default::default()
+    });
+    vm.fs = Box::new(FILES.with(|files| files.borrow().clone()));
+    let start = now();
+    let result = vm.run(source, output);
+    postMessage(
+        &Message::Stats {
+            ops: vm.stats.ops,
+            peak_heap: vm.stats.peak_heap,
+            gc_count: vm.stats.gc_count,
+            objects: vm
+                .gc
+                .counts()
+                .iter()
+                .filter(|(_, count)| count.allocated != 0)
+                .map(|(type_, count)| (type_.id(), count))
+                .collect(),
+            wall_time_ms: now() - start,
+        }
+        .to_string(),
+    );
+    match result {
         Ok(()) => postMessage(&Message::ExitSuccess.to_string()),
         Err(errors) => {
+            if let [(Error::IoError(IoError::OutputLimitExceeded { limit }), _)] = &errors[..] {
+                postMessage(&Message::OutputLimitExceeded { limit: *limit }.to_string());
+                return;
+            }
+            // Errors are reported on a line of their own, even if the
+            // program did not end its last line.
+            if !output.at_line_start {
+                let _ = output.write_all(b"\n");
+            }
             let mut writer = HtmlWriter::new(output);
             for e in errors.iter() {
                 report_error(&mut writer, source, e);
@@ -23,6 +80,79 @@ pub fn loxRun(source: &str) {
     }
 }
 
+/// Returns the diagnostics for `source` as a JSON array of Ace annotations.
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn loxDiagnostics(source: &str) -> String {
+    let lint_options = LintOptions::default();
+    annotations(analysis::diagnostics(source, Default::default(), &lint_options, &mut None))
+}
+
+/// Like `loxDiagnostics`, but only reports syntax errors, which is cheap
+/// enough to do on every keystroke.
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn loxQuickCheck(source: &str) -> String {
+    annotations(analysis::quick_diagnostics(source))
+}
+
+/// Returns whether `source` is a complete program, rather than one that
+/// stops partway through a statement or block.
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn loxIsComplete(source: &str) -> bool {
+    loxcraft::syntax::is_complete(source)
+}
+
+fn annotations(diagnostics: Vec<Diagnostic>) -> String {
+    let annotations = diagnostics
+        .into_iter()
+        .map(|diagnostic| Annotation {
+            row: diagnostic.start.line,
+            column: diagnostic.start.character,
+            text: diagnostic.message,
+            type_: match diagnostic.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            },
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string(&annotations).expect("could not serialize diagnostics")
+}
+
+/// Returns the completions for `source` as a JSON array of Ace completions.
+#[wasm_bindgen]
+#[allow(non_snake_case)]
+pub fn loxCompletions(source: &str) -> String {
+    let completions = analysis::completions(source)
+        .into_iter()
+        .map(|completion| Completion {
+            value: completion.label,
+            meta: match completion.kind {
+                CompletionKind::Keyword => "keyword",
+                CompletionKind::Native => "native",
+                CompletionKind::Variable => "variable",
+            },
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string(&completions).expect("could not serialize completions")
+}
+
+#[derive(Debug, Serialize)]
+struct Annotation {
+    row: u32,
+    column: u32,
+    text: String,
+    #[serde(rename = "type")]
+    type_: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct Completion {
+    value: String,
+    meta: &'static str,
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
@@ -30,6 +160,16 @@ enum Message {
     ExitFailure,
     ExitSuccess,
     Output { text: String },
+    OutputLimitExceeded { limit: usize },
+    /// Sent once the program ends, before the message saying how it ended.
+    Stats {
+        ops: u64,
+        peak_heap: usize,
+        gc_count: u64,
+        /// The objects of each type that were allocated, by `ObjectType::id`.
+        objects: BTreeMap<&'static str, ObjectCount>,
+        wall_time_ms: f64,
+    },
 }
 
 impl Display for Message {
@@ -42,19 +182,30 @@ impl Display for Message {
 extern "C" {
     #[wasm_bindgen(js_namespace = self)]
     fn postMessage(s: &str);
+
+    /// `Date.now()`, since `std::time::Instant` is not available on
+    /// `wasm32-unknown-unknown`.
+    #[wasm_bindgen(js_namespace = Date)]
+    fn now() -> f64;
 }
 
 #[derive(Debug)]
-struct Output;
+struct Output {
+    /// Whether the output so far ends with a newline, or is empty.
+    at_line_start: bool,
+}
 
 impl Output {
     fn new() -> Self {
-        Self
+        Self { at_line_start: true }
     }
 }
 
 impl Write for Output {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(&last) = buf.last() {
+            self.at_line_start = last == b'\n';
+        }
         let text = String::from_utf8_lossy(buf).to_string();
         postMessage(&Message::Output { text }.to_string());
         Ok(buf.len())
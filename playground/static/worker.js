@@ -0,0 +1,6 @@
+import init, { loxRun } from "./lox_wasm.js";
+
+onmessage = async (event) => {
+  await init();
+  loxRun(event.data);
+};
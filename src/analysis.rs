@@ -0,0 +1,970 @@
+//! Static analysis of Lox source, and test runs of it, independent of how the
+//! results are delivered. This is shared by the language server and the playground, which
+//! runs it in the browser.
+
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Category, ErrorS};
+use crate::lint::{self, LintOptions, WarningS};
+use crate::metrics::FunctionMetrics;
+use crate::symbols::{Access, SymbolKind, SymbolTable};
+use crate::syntax::ast::{Expr, Node, Program, Stmt};
+use crate::syntax::lexer::{Lexer, Token};
+use crate::timings::{self, Timings};
+pub use crate::types::Position;
+use crate::types::{LineIndex, ResolvedSpan, Span};
+use crate::vm::{Compiler, CompilerOptions, Gc, Native, Step, VM, VmOptions};
+
+const KEYWORDS: &[&str] = &[
+    "and", "assert", "catch", "class", "const", "else", "extend", "false", "finally", "for", "fun",
+    "if", "is", "nil", "or", "print", "return", "super", "this", "true", "try", "var", "while",
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem in a document. This is part of the stable API of the crate, so
+/// fields may be added, but not removed.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    pub span: Span,
+    pub start: Position,
+    pub end: Position,
+    pub severity: Severity,
+    /// The phase that found the problem.
+    pub category: Category,
+    /// The code of an error, e.g. `"E0202"`. Warnings have no code.
+    pub code: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(
+        source: &str,
+        lines: &LineIndex,
+        span: &Span,
+        severity: Severity,
+        category: Category,
+        code: Option<String>,
+        message: String,
+    ) -> Self {
+        let ResolvedSpan { span, start, end } = lines.resolve(source, span);
+        Self { span, start, end, severity, category, code, message }
+    }
+
+    /// Returns the diagnostic for `error`, whose span is in `source`, which
+    /// `lines` indexes.
+    pub(crate) fn error(source: &str, lines: &LineIndex, (error, span): &ErrorS) -> Self {
+        let message = format!("{}: {}", error.name(), crate::catalog::error_message(error));
+        let code = Some(error.code().to_string());
+        Self::new(source, lines, span, Severity::Error, error.category(), code, message)
+    }
+}
+
+/// The problems found in a document, without running it.
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Errors that would prevent the document from running.
+    pub errors: Vec<ErrorS>,
+    /// Lints, which are only reported if the document parses.
+    pub warnings: Vec<WarningS>,
+    /// The size and complexity of each function, which are only measured if
+    /// the document parses.
+    pub metrics: Vec<FunctionMetrics>,
+}
+
+pub fn analyze(
+    source: &str,
+    options: CompilerOptions,
+    lint_options: &LintOptions,
+    timings: &mut Option<Timings>,
+) -> Analysis {
+    // The files named by `include` directives are only read when the program
+    // runs, so the directives are blanked out, keeping the spans of the rest.
+    let includes = crate::syntax::includes(source);
+    let without_includes = (!includes.is_empty()).then(|| {
+        let mut source = source.to_string();
+        for (_, span) in includes {
+            source.replace_range(span.clone(), &" ".repeat(span.len()));
+        }
+        source
+    });
+    let source = without_includes.as_deref().unwrap_or(source);
+    let program = match timings::record(timings, "parse", || crate::syntax::parse(source, 0)) {
+        Ok(program) => program,
+        Err(errors) => return Analysis { errors, ..Default::default() },
+    };
+    let warnings = timings::record(timings, "lint", || lint::lint(&program, lint_options));
+    let metrics = crate::metrics::functions(source, &program);
+
+    let mut gc = Gc::default();
+    let errors = timings::record(timings, "compile", || {
+        Compiler::compile_program(&program, &mut gc, options)
+    })
+    .err()
+    .unwrap_or_default();
+    Analysis { errors, warnings, metrics }
+}
+
+/// Like [`analyze`], but with positions that editors understand.
+pub fn diagnostics(
+    source: &str,
+    options: CompilerOptions,
+    lint_options: &LintOptions,
+    timings: &mut Option<Timings>,
+) -> Vec<Diagnostic> {
+    let analysis = analyze(source, options, lint_options, timings);
+    let lines = LineIndex::new(source);
+    let errors = analysis.errors.iter().map(|error| Diagnostic::error(source, &lines, error));
+    let warnings = analysis.warnings.into_iter().map(|(warning, span)| {
+        let message = crate::catalog::warning_message(&warning);
+        Diagnostic::new(source, &lines, &span, Severity::Warning, Category::Lint, None, message)
+    });
+    errors.chain(warnings).collect()
+}
+
+/// Like [`diagnostics`], but only reports the errors found by parsing, which
+/// is fast enough to run on every keystroke, even for large documents.
+pub fn quick_diagnostics(source: &str) -> Vec<Diagnostic> {
+    match crate::syntax::parse(source, 0) {
+        Ok(_) => Vec::new(),
+        Err(errors) => {
+            let lines = LineIndex::new(source);
+            errors.iter().map(|error| Diagnostic::error(source, &lines, error)).collect()
+        }
+    }
+}
+
+/// What happened when a document was run by [`run`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RunOutcome {
+    /// The document finished, or did not compile, which [`diagnostics`]
+    /// already reports.
+    Finished,
+    /// The document ran for longer than it was allowed to, and was stopped.
+    OutOfFuel,
+    /// The document failed with a runtime error.
+    Failed {
+        diagnostic: Diagnostic,
+        /// The calls that led to the error, innermost first.
+        traceback: Vec<TracebackEntry>,
+    },
+}
+
+/// A function that was running when a document failed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TracebackEntry {
+    pub function: String,
+    /// The span of the op that the function was running, which is the call
+    /// to the next function in, or the failing op itself.
+    pub span: Span,
+    pub start: Position,
+    pub end: Position,
+}
+
+/// Runs `source` for up to `fuel` ops, discarding its output, and reports the
+/// runtime error that stops it, if any.
+pub fn run(source: &str, options: VmOptions, fuel: usize) -> RunOutcome {
+    let mut vm = VM::new(options);
+    if vm.load(source).is_err() {
+        return RunOutcome::Finished;
+    }
+    let stdout = &mut io::sink();
+    for _ in 0..fuel {
+        match vm.step(stdout) {
+            Ok(Step::Running) => {}
+            Ok(Step::Finished(_)) => return RunOutcome::Finished,
+            Err(error) => {
+                let lines = LineIndex::new(source);
+                let traceback = vm
+                    .traceback()
+                    .into_iter()
+                    .map(|(function, span)| {
+                        let ResolvedSpan { span, start, end } = lines.resolve(source, &span);
+                        TracebackEntry { function: function.to_string(), span, start, end }
+                    })
+                    .collect();
+                return RunOutcome::Failed {
+                    diagnostic: Diagnostic::error(source, &lines, &error),
+                    traceback,
+                };
+            }
+        }
+    }
+    RunOutcome::OutOfFuel
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum CompletionKind {
+    Keyword,
+    Native,
+    Variable,
+}
+
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+}
+
+/// Returns the names that could be typed in `source`: keywords, natives,
+/// modules, and every identifier that appears in the document. This works on
+/// tokens rather than the syntax tree, so it still gives results while the
+/// user is typing and the document does not parse.
+pub fn completions(source: &str) -> Vec<Completion> {
+    let keywords = KEYWORDS
+        .iter()
+        .map(|&keyword| Completion { label: keyword.to_string(), kind: CompletionKind::Keyword });
+    let natives = Native::ALL
+        .iter()
+        .filter(|native| native.is_global())
+        .map(|native| Completion { label: native.to_string(), kind: CompletionKind::Native });
+    let modules = Native::MODULES
+        .iter()
+        .map(|(name, _)| Completion { label: name.to_string(), kind: CompletionKind::Native });
+    let natives_and_keywords = keywords.chain(natives).chain(modules).collect::<Vec<_>>();
+
+    let variables = Lexer::new(source)
+        .flatten()
+        .filter_map(|(_, token, _)| match token {
+            Token::Identifier(name) => Some(name),
+            _ => None,
+        })
+        .filter(|name| !natives_and_keywords.iter().any(|completion| completion.label == **name))
+        .map(|name| Completion { label: name.to_string(), kind: CompletionKind::Variable })
+        .collect::<BTreeSet<_>>();
+
+    natives_and_keywords.into_iter().chain(variables).collect()
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DocumentSymbolKind {
+    Class,
+    Constant,
+    Function,
+    Method,
+    Variable,
+}
+
+impl Display for DocumentSymbolKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Class => write!(f, "class"),
+            Self::Constant => write!(f, "constant"),
+            Self::Function => write!(f, "function"),
+            Self::Method => write!(f, "method"),
+            Self::Variable => write!(f, "variable"),
+        }
+    }
+}
+
+/// A declaration in the outline of a document.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct DocumentSymbol {
+    pub name: String,
+    pub kind: DocumentSymbolKind,
+    /// The span of the whole declaration.
+    pub span: Span,
+    /// The span of the name within the declaration.
+    pub name_span: Span,
+    pub start: Position,
+    pub end: Position,
+    /// The methods of a class.
+    pub children: Vec<DocumentSymbol>,
+}
+
+impl DocumentSymbol {
+    fn new(
+        source: &str,
+        lines: &LineIndex,
+        name: &str,
+        kind: DocumentSymbolKind,
+        span: &Span,
+    ) -> Self {
+        // The AST does not record where names are, but the name is always the
+        // first identifier in a declaration.
+        let name_span = Lexer::new(&source[span.clone()])
+            .flatten()
+            .find(|(_, token, _)| matches!(token, Token::Identifier(_)))
+            .map_or(span.clone(), |(start, _, end)| span.start + start..span.start + end);
+        Self {
+            name: name.to_string(),
+            kind,
+            span: span.clone(),
+            name_span,
+            start: lines.position(source, span.start),
+            end: lines.position(source, span.end),
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Returns the classes, functions and variables declared at the top level of
+/// `program`, in the order in which they appear, with the methods of each
+/// class as its children.
+pub fn document_symbols(source: &str, program: &Program) -> Vec<DocumentSymbol> {
+    let lines = &LineIndex::new(source);
+    program
+        .stmts
+        .iter()
+        .filter_map(|(stmt, span)| match stmt {
+            Stmt::Class(class) => {
+                let mut symbol = DocumentSymbol::new(
+                    source,
+                    lines,
+                    &class.name,
+                    DocumentSymbolKind::Class,
+                    span,
+                );
+                symbol.children = class
+                    .methods
+                    .iter()
+                    .map(|(method, span)| {
+                        let kind = DocumentSymbolKind::Method;
+                        DocumentSymbol::new(source, lines, &method.name, kind, span)
+                    })
+                    .collect();
+                Some(symbol)
+            }
+            Stmt::Fun(fun) => {
+                let kind = DocumentSymbolKind::Function;
+                Some(DocumentSymbol::new(source, lines, &fun.name, kind, span))
+            }
+            Stmt::Var(var) => {
+                let kind = if var.is_const {
+                    DocumentSymbolKind::Constant
+                } else {
+                    DocumentSymbolKind::Variable
+                };
+                Some(DocumentSymbol::new(source, lines, &var.var.name, kind, span))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// A region of the document that an editor can collapse, spanning whole
+/// lines.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FoldingRange {
+    pub start_line: u32,
+    pub end_line: u32,
+}
+
+/// Returns a folding range for every class, function and block in `program`
+/// that spans more than one line.
+pub fn folding_ranges(source: &str, program: &Program) -> Vec<FoldingRange> {
+    let lines = LineIndex::new(source);
+    let mut ranges = Vec::new();
+    let mut nodes = program.stmts.iter().map(Node::Stmt).rev().collect::<Vec<_>>();
+    while let Some(node) = nodes.pop() {
+        let foldable = match node {
+            Node::Stmt((stmt, _)) => {
+                matches!(stmt, Stmt::Block(_) | Stmt::Class(_) | Stmt::Extend(_) | Stmt::Fun(_))
+            }
+            Node::Method(_) => true,
+            Node::Expr(_) => false,
+        };
+        if foldable {
+            let span = node.span();
+            let start_line = lines.line(span.start) as u32;
+            let end_line = lines.line(span.end) as u32;
+            if end_line > start_line {
+                ranges.push(FoldingRange { start_line, end_line });
+            }
+        }
+        nodes.extend(node.children().into_iter().rev());
+    }
+    ranges
+}
+
+/// Returns the spans of the syntax nodes that contain `idx`, from the
+/// innermost to the outermost. Editors use these to grow the selection one
+/// node at a time.
+pub fn selection_ranges(program: &Program, idx: usize) -> Vec<Span> {
+    let mut spans = Vec::<Span>::new();
+    let mut nodes = program.stmts.iter().map(Node::Stmt).collect::<Vec<_>>();
+    while let Some(node) = nodes.into_iter().find(|node| node.span().contains(&idx)) {
+        let span = node.span();
+        if spans.last() != Some(span) {
+            spans.push(span.clone());
+        }
+        nodes = node.children();
+    }
+    spans.reverse();
+    spans
+}
+
+/// Returns the spans of the declaration and every use of the variable at
+/// `idx`. Declarations count as writes.
+pub fn highlights(source: &str, program: &Program, idx: usize) -> Vec<(Span, Access)> {
+    let table = SymbolTable::new(source, program);
+    let Some(symbol) = table.symbol_at(idx) else { return Vec::new() };
+    let decl = table.symbols[symbol].decl.clone().map(|span| (span, Access::Write));
+    let references =
+        table.references_to(symbol).map(|reference| (reference.span.clone(), reference.access));
+    let mut highlights = decl.into_iter().chain(references).collect::<Vec<_>>();
+    highlights.sort_by_key(|(span, _)| span.start);
+    highlights
+}
+
+/// What the name under the cursor resolves to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Hover {
+    /// The span of the name.
+    pub span: Span,
+    /// The declaration of the name, as Lox, e.g. `fun add(a, b)`.
+    pub signature: String,
+    /// Where the value of the name is stored, and how many arguments it takes
+    /// if it can be called, e.g. `global function, arity 2`.
+    pub detail: String,
+}
+
+/// Returns what the variable at `idx` resolves to: a global, a local, or an
+/// upvalue that a closure captured from an enclosing function.
+pub fn hover(source: &str, program: &Program, idx: usize) -> Option<Hover> {
+    let table = SymbolTable::new(source, program);
+    let contains = |span: &Span| span.start <= idx && idx <= span.end;
+    let symbol_idx = table.symbol_at(idx)?;
+    let symbol = &table.symbols[symbol_idx];
+    let (span, function) = match table.references.iter().find(|reference| contains(&reference.span))
+    {
+        Some(reference) => (reference.span.clone(), Some(reference.function)),
+        None => (symbol.decl.clone()?, None),
+    };
+
+    let name = symbol.name;
+    if symbol.decl.is_none() {
+        let native =
+            Native::ALL.iter().find(|native| native.is_global() && native.to_string() == *name);
+        let module = Native::MODULES.iter().find(|(module, _)| *module == name.as_str());
+        let (signature, detail) = match (native, module) {
+            (Some(native), _) => {
+                let arity = if native.is_variadic() {
+                    format!("at least {}", native.arity())
+                } else {
+                    native.arity().to_string()
+                };
+                (format!("fun {name}"), format!("native function, arity {arity}"))
+            }
+            (None, Some((_, natives))) => {
+                let natives = natives.iter().map(ToString::to_string).collect::<Vec<_>>();
+                (name.to_string(), format!("built-in module: {}", natives.join(", ")))
+            }
+            (None, None) => (name.to_string(), "undefined global".to_string()),
+        };
+        return Some(Hover { span, signature, detail });
+    }
+
+    let storage = if symbol.is_global {
+        "global"
+    } else {
+        // A reference from another function reads the variable through an
+        // upvalue. At the declaration, this is shown if any reference does.
+        let captured = match function {
+            Some(function) => function != symbol.function,
+            None => table.is_captured(symbol_idx),
+        };
+        if captured { "upvalue" } else { "local" }
+    };
+    let params = symbol.params.iter().map(|param| param.as_str()).collect::<Vec<_>>().join(", ");
+    let arity = symbol.params.len();
+    let (signature, detail) = match symbol.kind {
+        SymbolKind::Class => (format!("class {name}"), format!("{storage} class, arity {arity}")),
+        SymbolKind::Function => {
+            (format!("fun {name}({params})"), format!("{storage} function, arity {arity}"))
+        }
+        SymbolKind::Parameter => (name.to_string(), format!("{storage} parameter")),
+        SymbolKind::Variable => (format!("var {name}"), format!("{storage} variable")),
+    };
+    Some(Hover { span, signature, detail })
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InlayHintKind {
+    /// The name of the parameter that an argument is passed to.
+    Parameter,
+    /// Whether a variable is a global, a local, or a local that is captured
+    /// by a closure and becomes an upvalue.
+    Storage,
+}
+
+/// A label that an editor shows inline, without it being part of the source.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InlayHint {
+    /// The position in the source before which the label is shown.
+    pub idx: usize,
+    pub label: String,
+    pub kind: InlayHintKind,
+}
+
+/// Returns hints for the arguments of calls to functions and classes declared
+/// in `program`, and for the storage of every variable it declares.
+pub fn inlay_hints(source: &str, program: &Program) -> Vec<InlayHint> {
+    let table = SymbolTable::new(source, program);
+    let mut hints = Vec::new();
+
+    for (symbol, info) in table.symbols.iter().enumerate() {
+        let Some(decl) = &info.decl else { continue };
+        if info.kind == SymbolKind::Variable {
+            let label = if info.is_global {
+                "global"
+            } else if table.is_captured(symbol) {
+                "upvalue"
+            } else {
+                "local"
+            };
+            hints.push(InlayHint {
+                idx: decl.end,
+                label: label.to_string(),
+                kind: InlayHintKind::Storage,
+            });
+        }
+    }
+
+    let mut nodes = program.stmts.iter().map(Node::Stmt).collect::<Vec<_>>();
+    while let Some(node) = nodes.pop() {
+        if let Node::Expr((Expr::Call(call), _)) = node {
+            if let (Expr::Var(_), span) = &call.callee {
+                let params =
+                    table.symbol_at(span.start).map(|symbol| &table.symbols[symbol].params);
+                for (arg, param) in call.args.iter().zip(params.into_iter().flatten()) {
+                    // Naming an argument after its parameter already says it all.
+                    if matches!(&arg.0, Expr::Var(var) if &var.var.name == param) {
+                        continue;
+                    }
+                    let label = format!("{param}:");
+                    hints.push(InlayHint {
+                        idx: arg.1.start,
+                        label,
+                        kind: InlayHintKind::Parameter,
+                    });
+                }
+            }
+        }
+        nodes.extend(node.children());
+    }
+
+    hints.sort_by_key(|hint| hint.idx);
+    hints
+}
+
+/// A change to the document, replacing `span` with `text`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TextEdit {
+    pub span: Span,
+    pub text: String,
+}
+
+/// Returns the edits that re-indent the code around `idx`, just after `ch`
+/// was typed there. Typing `}` re-indents every line of the block that it
+/// closes, and typing `;` re-indents the current line. Each line is indented
+/// by one `indent` for every brace that is open at its start, not counting a
+/// brace that the line itself closes.
+///
+/// This only looks at tokens, so it works on source that does not parse,
+/// which is the usual state of a document while it is being typed.
+pub fn on_type_edits(source: &str, idx: usize, ch: char, indent: &str) -> Vec<TextEdit> {
+    let tokens = Lexer::new(source).flatten().collect::<Vec<_>>();
+    let lines = LineIndex::new(source);
+    let line = lines.line(idx);
+    let first_line = match ch {
+        '}' => {
+            // Find the brace that the `}` before `idx` closes.
+            let mut depth = 0;
+            let before = tokens.partition_point(|(start, _, _)| *start < idx);
+            let open = tokens[..before].iter().rev().find(|(_, token, _)| {
+                match token {
+                    Token::RtBrace => depth += 1,
+                    Token::LtBrace => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            });
+            match open {
+                Some(&(start, _, _)) => lines.line(start) + 1,
+                None => line,
+            }
+        }
+        ';' => line,
+        _ => return Vec::new(),
+    };
+    line_edits(source, &tokens, first_line..=line, indent, false)
+}
+
+/// Returns the edits that format the lines of `source` that overlap `span`,
+/// or every line if there is no span. Lines are re-indented as by
+/// [`on_type_edits`], and trailing spaces and tabs are removed, except inside
+/// multi-line strings. Like [`on_type_edits`], this only looks at tokens, so
+/// source that does not parse is formatted too.
+pub fn format_edits(source: &str, span: Option<Span>, indent: &str) -> Vec<TextEdit> {
+    let tokens = Lexer::new(source).flatten().collect::<Vec<_>>();
+    let index = LineIndex::new(source);
+    let lines = match span {
+        Some(span) => index.line(span.start)..=index.line(span.end.max(span.start)),
+        None => 0..=usize::MAX,
+    };
+    line_edits(source, &tokens, lines, indent, true)
+}
+
+/// Re-indents each line in `lines`, and if `trim` is set, removes its
+/// trailing whitespace.
+fn line_edits(
+    source: &str,
+    tokens: &[(usize, Token, usize)],
+    lines: RangeInclusive<usize>,
+    indent: &str,
+    trim: bool,
+) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    let mut line_start = 0;
+    let mut depth = 0usize;
+    // The end of the last token before the current line. If it is past the
+    // start of the line, the line is inside a multi-line string.
+    let mut tokens_end = 0;
+    let mut rest = tokens.iter().peekable();
+    for (line_idx, text) in source.split('\n').enumerate() {
+        if line_idx > *lines.end() {
+            break;
+        }
+        while let Some((_, token, end)) = rest.next_if(|(start, _, _)| *start < line_start) {
+            match token {
+                Token::LtBrace => depth += 1,
+                Token::RtBrace => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            tokens_end = *end;
+        }
+        if !lines.contains(&line_idx) {
+            line_start += text.len() + 1;
+            continue;
+        }
+
+        let content = text.trim_start_matches([' ', '\t']);
+        if tokens_end <= line_start && !content.trim_end().is_empty() {
+            let closes = rest.peek().is_some_and(|&&(start, ref token, _)| {
+                start == line_start + text.len() - content.len() && *token == Token::RtBrace
+            });
+            let expected = indent.repeat(depth - usize::from(closes && depth > 0));
+            let current = &text[..text.len() - content.len()];
+            if current != expected {
+                let span = line_start..line_start + current.len();
+                edits.push(TextEdit { span, text: expected });
+            }
+        }
+
+        let line_end = line_start + text.len();
+        let trailing = text.len() - text.trim_end_matches([' ', '\t']).len();
+        if trim && trailing != 0 {
+            // Tokens do not overlap, so only the last one to start before the
+            // end of the line can continue past it.
+            let before = tokens.partition_point(|&(start, _, _)| start < line_end);
+            let in_string = before != 0 && tokens[before - 1].2 > line_end;
+            if !in_string {
+                edits.push(TextEdit { span: line_end - trailing..line_end, text: String::new() });
+            }
+        }
+        line_start = line_end + 1;
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn diagnostic_positions() {
+        let source = "var a = 1;\nprint \"é\" + ;\n";
+        let diagnostics =
+            diagnostics(source, Default::default(), &LintOptions::default(), &mut None);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.start, Position { line: 1, character: 12 });
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(Position::new(source, 0), Position::default());
+        assert_eq!(Position::new(source, source.len()), Position { line: 2, character: 0 });
+        // An offset inside "é" is at the start of it.
+        assert_eq!(Position::new(source, 19), Position { line: 1, character: 7 });
+    }
+
+    #[test]
+    fn diagnostic_json() {
+        let diagnostics =
+            diagnostics("print 1 +;", Default::default(), &LintOptions::default(), &mut None);
+        let json = serde_json::to_value(&diagnostics).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([{
+                "span": { "start": 9, "end": 10 },
+                "start": { "line": 0, "character": 9 },
+                "end": { "line": 0, "character": 10 },
+                "severity": "error",
+                "category": "parse",
+                "code": "E0013",
+                "message": "SyntaxError: unexpected \";\"",
+            }])
+        );
+        assert_eq!(serde_json::from_value::<Vec<Diagnostic>>(json).unwrap(), diagnostics);
+    }
+
+    #[test]
+    fn includes_are_not_errors() {
+        let source = "include \"lib.lox\";\nprint lib;";
+        assert_eq!(diagnostics(source, Default::default(), &LintOptions::default(), &mut None), []);
+    }
+
+    #[test]
+    fn quick_diagnostics_only_parse() {
+        let source = "print 1 +;";
+        let full = diagnostics(source, Default::default(), &LintOptions::default(), &mut None);
+        assert_eq!(quick_diagnostics(source), full);
+        // Resolving would find that `x` is read in its own initializer.
+        assert_eq!(quick_diagnostics("{ var x = x; }"), []);
+    }
+
+    #[test]
+    fn run_outcomes() {
+        let run = |source| run(source, VmOptions::default(), 1000);
+        assert_eq!(run("print 1;"), RunOutcome::Finished);
+        assert_eq!(run("print 1 +;"), RunOutcome::Finished);
+        assert_eq!(run("while (true) {}"), RunOutcome::OutOfFuel);
+
+        let source = "fun f() {\n  return nil.x;\n}\nf();";
+        let RunOutcome::Failed { diagnostic, traceback } = run(source) else {
+            panic!("program should fail");
+        };
+        assert_eq!(diagnostic.code.as_deref(), Some("E0301"));
+        assert_eq!(diagnostic.start, Position { line: 1, character: 9 });
+        let traceback = traceback
+            .iter()
+            .map(|entry| (entry.function.as_str(), entry.start.line))
+            .collect::<Vec<_>>();
+        assert_eq!(traceback, [("f", 1), ("<script>", 3)]);
+    }
+
+    #[test]
+    fn completions_include_identifiers() {
+        let source = "var foo = 1; fun bar() { return foo +";
+        let labels = completions(source)
+            .into_iter()
+            .filter(|completion| completion.kind != CompletionKind::Keyword)
+            .map(|completion| completion.label)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            [
+                "bind",
+                "char",
+                "clock",
+                "inspect",
+                "len",
+                "lower",
+                "printf",
+                "split",
+                "substr",
+                "trim",
+                "type",
+                "unbind",
+                "upper",
+                "write",
+                "arity",
+                "hash",
+                "read_file",
+                "map",
+                "filter",
+                "reduce",
+                "sort",
+                "chars",
+                "math",
+                "string",
+                "bar",
+                "foo"
+            ]
+        );
+    }
+
+    #[test]
+    fn on_type_edits() {
+        let apply = |source: &str, ch: char| {
+            let idx = source.find('|').unwrap();
+            let mut source = source.replace('|', "");
+            for edit in super::on_type_edits(&source, idx, ch, "  ").into_iter().rev() {
+                source.replace_range(edit.span, &edit.text);
+            }
+            source
+        };
+
+        // `}` re-indents the whole block that it closes.
+        assert_eq!(
+            apply("fun f() {\nprint 1;\n      if (true) {\n print \"a\n  b\";\n}\n    }|", '}'),
+            "fun f() {\n  print 1;\n  if (true) {\n    print \"a\n  b\";\n  }\n}"
+        );
+        // `;` only re-indents the current line, even if the source does not
+        // parse.
+        assert_eq!(
+            apply("{\nprint 1;\n    {\nprint (2;|", ';'),
+            "{\nprint 1;\n    {\n    print (2;"
+        );
+        assert_eq!(apply("print 1;|", ';'), "print 1;");
+        assert!(super::on_type_edits("{\n  print 1;", 12, 'x', "  ").is_empty());
+    }
+
+    #[test]
+    fn format_edits() {
+        let format = |source: &str, span: Option<Span>| {
+            let mut source = source.to_string();
+            for edit in super::format_edits(&source, span, "  ").into_iter().rev() {
+                source.replace_range(edit.span, &edit.text);
+            }
+            source
+        };
+
+        let source =
+            "class A {  \n    m() {\nprint \"a  \n  b\";   \n\t}\n  \n  }\nprint 1; // x \n";
+        assert_eq!(
+            format(source, None),
+            "class A {\n  m() {\n    print \"a  \n  b\";\n  }\n\n}\nprint 1; // x\n"
+        );
+        // Only the lines that overlap the span are formatted.
+        assert_eq!(
+            format(source, Some(15..17)),
+            "class A {  \n  m() {\nprint \"a  \n  b\";   \n\t}\n  \n  }\nprint 1; // x \n"
+        );
+        assert_eq!(format("{\nprint (;\n", None), "{\n  print (;\n");
+    }
+
+    #[test]
+    fn position_to_idx() {
+        let source = "var a = 1;\nprint \"é\" + a;\n";
+        for idx in [0, 5, 11, 18, 20, source.len()] {
+            assert_eq!(Position::new(source, idx).to_idx(source), idx);
+        }
+        assert_eq!(Position { line: 0, character: 100 }.to_idx(source), 10);
+        assert_eq!(Position { line: 100, character: 0 }.to_idx(source), source.len());
+    }
+
+    #[test]
+    fn document_symbols() {
+        let source = "class A {\n  f() {}\n}\nfun g() {}\nconst b = 1;\nvar c;\nprint c;\n";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let symbols = super::document_symbols(source, &program);
+        let summary = |symbol: &DocumentSymbol| {
+            (symbol.name.clone(), symbol.kind, symbol.name_span.clone(), symbol.start.line)
+        };
+        assert_eq!(
+            symbols.iter().map(summary).collect::<Vec<_>>(),
+            [
+                ("A".to_string(), DocumentSymbolKind::Class, 6..7, 0),
+                ("g".to_string(), DocumentSymbolKind::Function, 25..26, 3),
+                ("b".to_string(), DocumentSymbolKind::Constant, 38..39, 4),
+                ("c".to_string(), DocumentSymbolKind::Variable, 49..50, 5),
+            ]
+        );
+        assert_eq!(symbols[0].span, 0..20);
+        assert_eq!(
+            symbols[0].children.iter().map(summary).collect::<Vec<_>>(),
+            [("f".to_string(), DocumentSymbolKind::Method, 12..13, 1)]
+        );
+    }
+
+    #[test]
+    fn document_symbols_with_errors() {
+        // A document that is still being typed has an outline too.
+        let source = "fun g(a) { return a + ; }\nclass A {\n  f() {\n    this.";
+        let (program, errors) = crate::syntax::parse_tolerant(source);
+        assert_eq!(errors.len(), 2);
+        let symbols = super::document_symbols(source, &program);
+        let names = |symbols: &[DocumentSymbol]| {
+            symbols.iter().map(|symbol| symbol.name.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&symbols), ["g", "A"]);
+        assert_eq!(names(&symbols[1].children), ["f"]);
+    }
+
+    #[test]
+    fn folding_ranges() {
+        let source = "class A {\n  f() {\n    print 1;\n  }\n}\nfun g() { {} }\nif (true) {\n}\n";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let ranges = super::folding_ranges(source, &program)
+            .into_iter()
+            .map(|range| (range.start_line, range.end_line))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [(0, 4), (1, 3), (6, 7)]);
+    }
+
+    #[test]
+    fn selection_ranges() {
+        let source = "fun f() { return 1 + g(2); }";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let ranges = super::selection_ranges(&program, 23);
+        assert_eq!(ranges, [23..24, 21..25, 17..25, 10..26, 0..28]);
+        assert!(super::selection_ranges(&program, 100).is_empty());
+    }
+
+    #[test]
+    fn highlights() {
+        let source = "var a = 1;\nfun f() { a = a + 1; }";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let highlights = super::highlights(source, &program, 21);
+        assert_eq!(
+            highlights,
+            [(4..5, Access::Write), (21..22, Access::Write), (25..26, Access::Read)]
+        );
+        assert_eq!(super::highlights(source, &program, 8), []);
+    }
+
+    #[test]
+    fn hover() {
+        let source = "fun add(a, b) { var sum = a + b; fun get() { return sum; } return get; }\n\
+                      class P { init(x) {} } var p = P(1); add(len(\"\"), p); nope;";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let hover = |idx| {
+            let hover = super::hover(source, &program, idx)?;
+            Some(format!("{}: {}: {}", &source[hover.span], hover.signature, hover.detail))
+        };
+        assert_eq!(hover(5).as_deref(), Some("add: fun add(a, b): global function, arity 2"));
+        assert_eq!(hover(8).as_deref(), Some("a: a: local parameter"));
+        assert_eq!(hover(21).as_deref(), Some("sum: var sum: upvalue variable"));
+        assert_eq!(hover(26).as_deref(), Some("a: a: local parameter"));
+        assert_eq!(hover(53).as_deref(), Some("sum: var sum: upvalue variable"));
+        assert_eq!(hover(66).as_deref(), Some("get: fun get(): local function, arity 0"));
+        assert_eq!(hover(79).as_deref(), Some("P: class P: global class, arity 1"));
+        assert_eq!(hover(114).as_deref(), Some("len: fun len: native function, arity 1"));
+        assert_eq!(hover(127).as_deref(), Some("nope: nope: undefined global"));
+        assert_eq!(hover(0), None);
+    }
+
+    #[test]
+    fn inlay_hints() {
+        let source = "fun add(a, b) { var sum = a + b; fun get() { return sum; } return get; }\n\
+                      var b = 2; var c; add(1, b);";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let hints = super::inlay_hints(source, &program)
+            .into_iter()
+            .map(|hint| (hint.idx, hint.label))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hints,
+            [
+                (23, "upvalue".to_string()),
+                (78, "global".to_string()),
+                (89, "global".to_string()),
+                (95, "a:".to_string()),
+            ]
+        );
+    }
+}
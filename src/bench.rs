@@ -0,0 +1,147 @@
+//! Times Lox programs in the VM, and compares the results with those of an
+//! earlier run, so that the effect of a change to the VM can be measured.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::{fs, io};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::vm::{VM, VmOptions};
+
+/// The benchmarks that are run when no paths are given.
+pub const DEFAULT_DIR: &str = "res/benchmarks";
+
+/// How a benchmark performed over several runs.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BenchResult {
+    /// The file name of the benchmark, without its extension.
+    pub name: String,
+    /// The wall time of the fastest run, in milliseconds.
+    pub min_ms: f64,
+    /// The mean wall time of the runs, in milliseconds.
+    pub mean_ms: f64,
+    /// The number of garbage collections in a run.
+    pub gc_count: u64,
+    /// The most memory allocated at once during a run, in bytes.
+    pub peak_heap: usize,
+    /// The number of objects allocated by a run.
+    pub objects: u64,
+}
+
+/// Runs each program in `paths` `runs` times, each in a new [`VM`], with its
+/// output discarded.
+pub fn run(paths: &[PathBuf], runs: usize, options: &VmOptions) -> Result<Vec<BenchResult>> {
+    let mut results = Vec::with_capacity(paths.len());
+    for path in paths {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("could not read source from file: {}", path.display()))?;
+        let name = path.file_stem().unwrap_or(path.as_os_str()).to_string_lossy().into_owned();
+
+        let mut times = Vec::with_capacity(runs);
+        let mut last = None;
+        for _ in 0..runs.max(1) {
+            let mut vm = VM::new(options.clone());
+            let start = Instant::now();
+            if vm.run(&source, &mut io::sink()).is_err() {
+                bail!("benchmark failed: {}", path.display());
+            }
+            times.push(start.elapsed().as_secs_f64() * 1000.0);
+            last = Some(vm);
+        }
+        let vm = last.expect("benchmark did not run");
+
+        results.push(BenchResult {
+            name,
+            min_ms: times.iter().copied().fold(f64::INFINITY, f64::min),
+            mean_ms: times.iter().sum::<f64>() / times.len() as f64,
+            gc_count: vm.stats.gc_count,
+            peak_heap: vm.stats.peak_heap,
+            objects: vm.gc.counts().iter().map(|(_, count)| count.allocated).sum(),
+        });
+    }
+    Ok(results)
+}
+
+/// Reads results written by [`save`].
+pub fn load(path: &Path) -> Result<Vec<BenchResult>> {
+    let json = fs::read_to_string(path)
+        .with_context(|| format!("could not read baseline: {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("invalid baseline: {}", path.display()))
+}
+
+/// Writes `results` as JSON, so that a later run can be compared with them.
+pub fn save(path: &Path, results: &[BenchResult]) -> Result<()> {
+    let json = serde_json::to_string_pretty(results).context("could not serialize results")?;
+    fs::write(path, json).with_context(|| format!("could not write results: {}", path.display()))
+}
+
+/// Formats `results` as a table. Each benchmark that is also in `baseline` is
+/// shown with its time there, and how much faster or slower it has become,
+/// comparing the fastest runs, which are the least affected by noise.
+pub fn report(results: &[BenchResult], baseline: &[BenchResult]) -> String {
+    let width = results.iter().map(|result| result.name.len()).chain([9]).max().unwrap_or(0);
+    let mut table = String::new();
+    let _ = write!(table, "{:<width$} {:>10} {:>10}", "benchmark", "min (ms)", "mean (ms)");
+    if !baseline.is_empty() {
+        let _ = write!(table, " {:>10} {:>8}", "base (ms)", "change");
+    }
+    let _ = writeln!(table, " {:>8} {:>12} {:>12}", "gc runs", "peak heap", "objects");
+
+    for result in results {
+        let _ = write!(
+            table,
+            "{:<width$} {:>10.2} {:>10.2}",
+            result.name, result.min_ms, result.mean_ms
+        );
+        if !baseline.is_empty() {
+            match baseline.iter().find(|base| base.name == result.name) {
+                Some(base) => {
+                    let change = (result.min_ms / base.min_ms - 1.0) * 100.0;
+                    let _ = write!(table, " {:>10.2} {:>7}%", base.min_ms, format!("{change:+.1}"));
+                }
+                None => {
+                    let _ = write!(table, " {:>10} {:>8}", "-", "-");
+                }
+            }
+        }
+        let peak_heap = format!("{} KiB", (result.peak_heap + 1023) / 1024);
+        let _ = writeln!(table, " {:>8} {:>12} {:>12}", result.gc_count, peak_heap, result.objects);
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn result(name: &str, min_ms: f64) -> BenchResult {
+        BenchResult {
+            name: name.to_string(),
+            min_ms,
+            mean_ms: min_ms + 1.0,
+            gc_count: 2,
+            peak_heap: 4096,
+            objects: 100,
+        }
+    }
+
+    #[test]
+    fn report_with_baseline() {
+        let results = [result("fib", 90.0), result("zoo", 10.0)];
+        let baseline = [result("fib", 100.0)];
+        assert_eq!(
+            report(&results, &baseline),
+            "\
+benchmark   min (ms)  mean (ms)  base (ms)   change  gc runs    peak heap      objects
+fib            90.00      91.00     100.00   -10.0%        2        4 KiB          100
+zoo            10.00      11.00          -        -        2        4 KiB          100
+"
+        );
+        assert!(!report(&results, &[]).contains("change"));
+    }
+}
@@ -0,0 +1,50 @@
+//! An on-disk cache of compiled programs, so that `lox run` can skip parsing
+//! and compiling a script that it has run before. The cache is only an
+//! optimization, so failures to read or write it are ignored.
+
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use rustc_hash::FxHasher;
+
+use crate::vm::VmOptions;
+
+/// Returns the cached program for `source`, if it was compiled before with
+/// the same `options`. It may still be from another version of loxcraft, in
+/// which case [`VM::run_bytecode`](crate::vm::VM::run_bytecode) rejects it.
+pub fn load(source: &str, options: &VmOptions) -> Option<Vec<u8>> {
+    fs::read(path(source, options)?).ok()
+}
+
+/// Caches the program compiled from `source` with `options`.
+pub fn store(source: &str, options: &VmOptions, bytecode: &[u8]) {
+    let Some(path) = path(source, options) else { return };
+    let Some(dir) = path.parent() else { return };
+    if fs::create_dir_all(dir).is_err() {
+        return;
+    }
+    // Write to a temporary file first, so that another run never reads a
+    // partially written program.
+    let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+    if fs::write(&tmp_path, bytecode).is_err() || fs::rename(&tmp_path, &path).is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+}
+
+/// Returns the path of the cached program for `source`. Programs are keyed by
+/// a hash of their source, the version of loxcraft, and the options that
+/// change how they are compiled or which warnings they have.
+fn path(source: &str, options: &VmOptions) -> Option<PathBuf> {
+    let mut hasher = FxHasher::default();
+    source.hash(&mut hasher);
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    options.strict.hash(&mut hasher);
+    options.strict_types.hash(&mut hasher);
+    options.per_iteration_bindings.hash(&mut hasher);
+    options.class_extensions.hash(&mut hasher);
+    options.eliminate_dead_globals.hash(&mut hasher);
+    options.direct_native_calls.hash(&mut hasher);
+    let key = hasher.finish();
+    Some(dirs::cache_dir()?.join("loxcraft").join(format!("{key:016x}.loxc")))
+}
@@ -0,0 +1,394 @@
+//! A static call graph of a whole program, built on the [`SymbolTable`].
+//!
+//! Calls through variables are resolved to the function or class that the
+//! variable is declared as, so a call through a variable that is reassigned,
+//! or through a parameter, is missed. Method calls are resolved by name, to
+//! every method with that name, since the class of the receiver is not known.
+//! Calls through `super` are resolved to the method of the superclass.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+use crate::symbols::{SymbolKind, SymbolTable};
+use crate::syntax::ast::{Expr, ExprS, Program, Stmt, StmtFun, StmtS};
+use crate::types::Span;
+
+/// Functions that are called from outside the program, and so are always
+/// referenced.
+const ROOTS: &[&str] = &["main"];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Function {
+    /// The name of the function, `Class.name` for methods, or `<script>` for
+    /// the top level of the program.
+    pub name: String,
+    /// The span of the declaration, or [`None`] for the top level.
+    pub span: Option<Span>,
+    /// Whether anything other than the function itself refers to it.
+    pub is_referenced: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CallGraph {
+    /// The top level of the program, followed by every function and method
+    /// declared with a name, in source order. Anonymous functions are part of
+    /// the function they appear in.
+    pub functions: Vec<Function>,
+    /// Pairs of indices into [`CallGraph::functions`], from caller to callee,
+    /// sorted and without duplicates.
+    pub calls: Vec<(usize, usize)>,
+}
+
+impl CallGraph {
+    pub fn new(source: &str, program: &Program) -> Self {
+        let table = SymbolTable::new(source, program);
+        let mut walker = Walker {
+            table: &table,
+            functions: vec![Function {
+                name: "<script>".to_string(),
+                span: None,
+                is_referenced: true,
+            }],
+            decls: HashMap::new(),
+            methods: Vec::new(),
+            uses: Vec::new(),
+            function: 0,
+            super_: None,
+        };
+        program.stmts.iter().for_each(|stmt| walker.stmt(stmt));
+        walker.finish()
+    }
+
+    /// Returns the functions that nothing refers to, other than themselves.
+    pub fn unreferenced(&self) -> impl Iterator<Item = &Function> {
+        self.functions.iter().filter(|function| !function.is_referenced)
+    }
+
+    /// Renders the graph in the Graphviz DOT language. Unreferenced functions
+    /// are drawn with dashed outlines.
+    pub fn to_dot(&self) -> String {
+        let mut dot = "digraph calls {\n".to_string();
+        for (idx, function) in self.functions.iter().enumerate() {
+            let style = if function.is_referenced { "" } else { ", style=dashed" };
+            writeln!(dot, "    n{idx} [label={:?}{style}];", function.name).unwrap();
+        }
+        for (caller, callee) in &self.calls {
+            writeln!(dot, "    n{caller} -> n{callee};").unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// What a name in the program may refer to.
+#[derive(Clone, Copy, Debug)]
+enum Decl<'a> {
+    Function(usize),
+    Class(&'a str),
+}
+
+/// A use of something that may be a function.
+#[derive(Clone, Copy, Debug)]
+enum Use<'a> {
+    /// A variable, by its index in [`SymbolTable::symbols`].
+    Symbol(usize),
+    /// A method by name, and the class to look it up in, if known.
+    Method { name: &'a str, class: Option<&'a str> },
+}
+
+struct Walker<'a> {
+    table: &'a SymbolTable,
+    functions: Vec<Function>,
+    /// The function or class that each symbol is declared as.
+    decls: HashMap<usize, Decl<'a>>,
+    /// The class, name and index of every method.
+    methods: Vec<(&'a str, &'a str, usize)>,
+    /// The function in which each use appears, what it uses, and whether it
+    /// is called.
+    uses: Vec<(usize, Use<'a>, bool)>,
+    /// The function being walked.
+    function: usize,
+    /// The name of the superclass of the class being walked.
+    super_: Option<&'a str>,
+}
+
+impl<'a> Walker<'a> {
+    fn stmt(&mut self, (stmt, span): &'a StmtS) {
+        match stmt {
+            Stmt::Assert(assert) => self.expr(&assert.value),
+            Stmt::Block(block) => block.stmts.iter().for_each(|stmt| self.stmt(stmt)),
+            Stmt::Class(class) => {
+                if let Some(symbol) = self.symbol(&class.name, SymbolKind::Class, span) {
+                    self.decls.insert(symbol, Decl::Class(&class.name));
+                }
+                class.super_.iter().for_each(|super_| self.expr(super_));
+                let super_ = match &class.super_ {
+                    Some((Expr::Var(var), _)) => Some(var.var.name.as_str()),
+                    _ => None,
+                };
+                let enclosing = std::mem::replace(&mut self.super_, super_);
+                for (method, span) in &class.methods {
+                    let idx = self.declare(format!("{}.{}", class.name, method.name), span);
+                    self.methods.push((&class.name, &method.name, idx));
+                    self.fun(method, idx);
+                }
+                self.super_ = enclosing;
+            }
+            Stmt::Expr(expr) => self.expr(&expr.value),
+            Stmt::Extend(extend) => {
+                self.expr(&extend.class);
+                let Expr::Var(var) = &extend.class.0 else { return };
+                let enclosing = self.super_.take();
+                for (method, span) in &extend.methods {
+                    let idx = self.declare(format!("{}.{}", var.var.name, method.name), span);
+                    self.methods.push((&var.var.name, &method.name, idx));
+                    self.fun(method, idx);
+                }
+                self.super_ = enclosing;
+            }
+            Stmt::For(for_) => {
+                for_.init.iter().for_each(|init| self.stmt(init));
+                for_.cond.iter().chain(&for_.incr).for_each(|expr| self.expr(expr));
+                self.stmt(&for_.body);
+            }
+            Stmt::Fun(fun) => {
+                let idx = self.declare(fun.name.to_string(), span);
+                if let Some(symbol) = self.symbol(&fun.name, SymbolKind::Function, span) {
+                    self.decls.insert(symbol, Decl::Function(idx));
+                }
+                self.fun(fun, idx);
+            }
+            Stmt::If(if_) => {
+                self.expr(&if_.cond);
+                self.stmt(&if_.then);
+                if_.else_.iter().for_each(|else_| self.stmt(else_));
+            }
+            Stmt::Print(print) => print.values.iter().for_each(|value| self.expr(value)),
+            Stmt::Return(return_) => return_.value.iter().for_each(|value| self.expr(value)),
+            Stmt::Try(try_) => {
+                self.stmt(&try_.body);
+                try_.catch.iter().for_each(|catch| self.stmt(&catch.body));
+                try_.finally.iter().for_each(|finally| self.stmt(finally));
+            }
+            Stmt::Var(var) => var.value.iter().for_each(|value| self.expr(value)),
+            Stmt::While(while_) => {
+                self.expr(&while_.cond);
+                self.stmt(&while_.body);
+            }
+            Stmt::Error => {}
+        }
+    }
+
+    fn expr(&mut self, (expr, span): &'a ExprS) {
+        match expr {
+            Expr::Assign(assign) => self.expr(&assign.value),
+            Expr::Call(call) => {
+                match &call.callee.0 {
+                    Expr::Get(get) => {
+                        self.expr(&get.object);
+                        self.use_method(&get.name, None, true);
+                    }
+                    Expr::Super(super_) => self.use_method(&super_.name, self.super_, true),
+                    Expr::Var(_) => self.use_var(&call.callee.1, true),
+                    _ => self.expr(&call.callee),
+                }
+                call.args.iter().for_each(|arg| self.expr(arg));
+            }
+            Expr::Fun(fun) => {
+                let function = self.function;
+                self.fun(fun, function);
+            }
+            Expr::Get(get) => {
+                self.expr(&get.object);
+                self.use_method(&get.name, None, false);
+            }
+            Expr::Index(index) => {
+                self.expr(&index.object);
+                self.expr(&index.index);
+            }
+            Expr::Infix(infix) => {
+                self.expr(&infix.lt);
+                self.expr(&infix.rt);
+            }
+            Expr::List(list) => list.elements.iter().for_each(|element| self.expr(element)),
+            Expr::Literal(_) | Expr::Missing => {}
+            Expr::Map(map) => map.entries.iter().for_each(|(key, value)| {
+                self.expr(key);
+                self.expr(value);
+            }),
+            Expr::Prefix(prefix) => self.expr(&prefix.rt),
+            Expr::Set(set) => {
+                self.expr(&set.object);
+                self.expr(&set.value);
+            }
+            Expr::SetIndex(set) => {
+                self.expr(&set.object);
+                self.expr(&set.index);
+                self.expr(&set.value);
+            }
+            Expr::Super(super_) => self.use_method(&super_.name, self.super_, false),
+            Expr::Var(_) => self.use_var(span, false),
+        }
+    }
+
+    /// Walks the body of a function, as the function at `idx`.
+    fn fun(&mut self, fun: &'a StmtFun, idx: usize) {
+        let enclosing = std::mem::replace(&mut self.function, idx);
+        fun.defaults.iter().for_each(|default| self.expr(default));
+        fun.body.stmts.iter().for_each(|stmt| self.stmt(stmt));
+        self.function = enclosing;
+    }
+
+    fn declare(&mut self, name: String, span: &Span) -> usize {
+        let is_referenced = ROOTS.contains(&name.as_str());
+        self.functions.push(Function { name, span: Some(span.clone()), is_referenced });
+        self.functions.len() - 1
+    }
+
+    /// Returns the symbol declared by the statement at `span`, which is the
+    /// first one with this name and kind.
+    fn symbol(&self, name: &str, kind: SymbolKind, span: &Span) -> Option<usize> {
+        self.table
+            .symbols
+            .iter()
+            .enumerate()
+            .filter(|(_, symbol)| symbol.name == name && symbol.kind == kind)
+            .filter_map(|(idx, symbol)| Some((idx, symbol.decl.as_ref()?.start)))
+            .filter(|(_, start)| span.contains(start))
+            .min_by_key(|&(_, start)| start)
+            .map(|(idx, _)| idx)
+    }
+
+    fn use_var(&mut self, span: &Span, is_call: bool) {
+        let symbol = self
+            .table
+            .references
+            .iter()
+            .find(|reference| reference.span == *span)
+            .map(|reference| reference.symbol);
+        if let Some(symbol) = symbol {
+            self.uses.push((self.function, Use::Symbol(symbol), is_call));
+        }
+    }
+
+    fn use_method(&mut self, name: &'a str, class: Option<&'a str>, is_call: bool) {
+        self.uses.push((self.function, Use::Method { name, class }, is_call));
+    }
+
+    /// Returns the methods that `name` may refer to in `class`, or in any
+    /// class if that is not known or does not declare it, e.g. because it is
+    /// inherited.
+    fn methods(&self, name: &str, class: Option<&str>) -> Vec<usize> {
+        let matching = |class: Option<&str>| {
+            self.methods
+                .iter()
+                .filter(move |&&(c, n, _)| n == name && class.map_or(true, |class| class == c))
+                .map(|&(_, _, idx)| idx)
+                .collect::<Vec<_>>()
+        };
+        match matching(class) {
+            methods if methods.is_empty() && class.is_some() => matching(None),
+            methods => methods,
+        }
+    }
+
+    fn finish(mut self) -> CallGraph {
+        let mut calls = BTreeSet::new();
+        for &(caller, use_, is_call) in &self.uses {
+            let callees = match use_ {
+                Use::Symbol(symbol) => match self.decls.get(&symbol) {
+                    Some(&Decl::Function(idx)) => vec![idx],
+                    // Calling a class calls its initializer.
+                    Some(&Decl::Class(class)) => self
+                        .methods
+                        .iter()
+                        .filter(|&&(c, name, _)| c == class && name == "init")
+                        .map(|&(_, _, idx)| idx)
+                        .collect(),
+                    None => Vec::new(),
+                },
+                Use::Method { name, class } => self.methods(name, class),
+            };
+            for callee in callees {
+                if callee != caller {
+                    self.functions[callee].is_referenced = true;
+                }
+                if is_call {
+                    calls.insert((caller, callee));
+                }
+            }
+        }
+        CallGraph { functions: self.functions, calls: calls.into_iter().collect() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn graph(source: &str) -> CallGraph {
+        CallGraph::new(source, &crate::syntax::parse(source, 0).unwrap())
+    }
+
+    /// Returns the calls of `graph` by name.
+    fn calls(graph: &CallGraph) -> Vec<(&str, &str)> {
+        let name = |idx: usize| graph.functions[idx].name.as_str();
+        graph.calls.iter().map(|&(caller, callee)| (name(caller), name(callee))).collect()
+    }
+
+    #[test]
+    fn calls_and_unreferenced() {
+        let source = r#"
+            fun f() { return g() + h; }
+            fun g() { var p = Point(1); return p.norm(); }
+            fun h() {}
+            fun unused() { unused(); }
+            class Point {
+                init(x) { this.x = x; }
+                norm() { return this.abs(); }
+                abs() { return () -> f(); }
+                dead() {}
+            }
+            class Point3 < Point {
+                abs() { return super.abs(); }
+            }
+            fun main() {}
+            f();
+        "#;
+        let graph = graph(source);
+        assert_eq!(
+            calls(&graph),
+            [
+                ("<script>", "f"),
+                ("f", "g"),
+                ("g", "Point.init"),
+                ("g", "Point.norm"),
+                ("unused", "unused"),
+                ("Point.norm", "Point.abs"),
+                ("Point.norm", "Point3.abs"),
+                ("Point.abs", "f"),
+                ("Point3.abs", "Point.abs"),
+            ]
+        );
+        let unreferenced = graph.unreferenced().map(|f| f.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(unreferenced, ["unused", "Point.dead"]);
+    }
+
+    #[test]
+    fn to_dot() {
+        let graph = graph("fun f() {} fun g() { f(); } g();");
+        assert_eq!(
+            graph.to_dot(),
+            "digraph calls {
+    n0 [label=\"<script>\"];
+    n1 [label=\"f\"];
+    n2 [label=\"g\"];
+    n0 -> n2;
+    n2 -> n1;
+}
+"
+        );
+    }
+}
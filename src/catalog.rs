@@ -0,0 +1,205 @@
+//! Translations of error and warning messages.
+//!
+//! A [`Catalog`] maps the code of each message to a template. Codes are the
+//! name of the error type and its variant, e.g. `TypeError.ArityMismatch`, or
+//! `Warning.InfiniteEmptyLoop` for lints. Templates refer to the fields of the
+//! error in braces, e.g. `{name}() takes {exp_args} arguments`. Messages
+//! without a template stay in English.
+//!
+//! Catalogs are written in TOML, with a table for each error type:
+//!
+//! ```toml
+//! [TypeError]
+//! ArityMismatch = "{name}() recibe {exp_args} argumentos, pero se pasaron {got_args}"
+//! ```
+//!
+//! Only the messages in reports are translated. The codes of diagnostics stay
+//! the same, so that tools can rely on them, and the `message` of an error
+//! caught by a Lox program is always in English.
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::lint::Warning;
+
+/// The catalogs bundled with loxcraft, by language code.
+const BUILTIN: &[(&str, &str)] = &[("es", include_str!("../res/catalogs/es.toml"))];
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Catalog {
+    /// Templates by code.
+    pub messages: BTreeMap<String, String>,
+}
+
+impl Catalog {
+    /// The codes of the bundled languages. English is always available, since
+    /// it needs no catalog.
+    pub fn languages() -> impl Iterator<Item = &'static str> {
+        std::iter::once("en").chain(BUILTIN.iter().map(|&(lang, _)| lang))
+    }
+
+    /// Returns the bundled catalog for `lang`, e.g. `"es"`.
+    pub fn builtin(lang: &str) -> Option<Self> {
+        if lang == "en" {
+            return Some(Self::default());
+        }
+        let &(_, source) = BUILTIN.iter().find(|&&(name, _)| name == lang)?;
+        Some(Self::parse(source).expect("bundled catalog should be valid"))
+    }
+
+    /// Parses a catalog from TOML.
+    pub fn parse(source: &str) -> Result<Self, toml::de::Error> {
+        let tables = toml::from_str::<BTreeMap<String, BTreeMap<String, String>>>(source)?;
+        let messages = tables
+            .into_iter()
+            .flat_map(|(type_, messages)| {
+                messages
+                    .into_iter()
+                    .map(move |(variant, template)| (format!("{type_}.{variant}"), template))
+            })
+            .collect();
+        Ok(Self { messages })
+    }
+
+    /// Returns the message of `error`, without the name of its type.
+    pub fn error_message(&self, error: &Error) -> String {
+        let (variant, fields) = variant_fields(error);
+        match self.messages.get(&format!("{}.{variant}", error.name())) {
+            Some(template) => render(template, &fields),
+            None => error.message(),
+        }
+    }
+
+    pub fn warning_message(&self, warning: &Warning) -> String {
+        let (variant, fields) = variant_fields(warning);
+        match self.messages.get(&format!("Warning.{variant}")) {
+            Some(template) => render(template, &fields),
+            None => warning.to_string(),
+        }
+    }
+}
+
+/// Sets the catalog used to report errors for the rest of the process. If one
+/// was already set, it is returned instead.
+pub fn set_catalog(catalog: Catalog) -> Result<(), Catalog> {
+    CATALOG.set(catalog)
+}
+
+/// Returns the message of `error` from the catalog set by [`set_catalog`].
+pub fn error_message(error: &Error) -> String {
+    CATALOG.get().map_or_else(|| error.message(), |catalog| catalog.error_message(error))
+}
+
+/// Returns the message of `warning` from the catalog set by [`set_catalog`].
+pub fn warning_message(warning: &Warning) -> String {
+    CATALOG.get().map_or_else(|| warning.to_string(), |catalog| catalog.warning_message(warning))
+}
+
+/// Returns the name of the innermost variant of `value`, and its fields. Every
+/// error is an enum of enums, so this relies on how serde encodes those.
+fn variant_fields(value: &impl Serialize) -> (String, serde_json::Map<String, Value>) {
+    let mut value = serde_json::to_value(value).expect("errors should be serializable");
+    loop {
+        value = match value {
+            Value::String(variant) => return (variant, serde_json::Map::new()),
+            Value::Object(object) if object.len() == 1 => {
+                let (variant, inner) = object.into_iter().next().unwrap();
+                match inner {
+                    Value::Object(fields) if !fields.values().any(Value::is_object) => {
+                        return (variant, fields);
+                    }
+                    inner => inner,
+                }
+            }
+            _ => return (String::new(), serde_json::Map::new()),
+        }
+    }
+}
+
+/// Replaces each `{field}` in `template`. Unknown fields are left as they are.
+fn render(template: &str, fields: &serde_json::Map<String, Value>) -> String {
+    let mut output = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else { break };
+        match fields.get(&rest[1..end]) {
+            Some(Value::String(value)) => output.push_str(value),
+            Some(Value::Array(values)) => {
+                let values = values.iter().map(|value| match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                });
+                output.push_str(&values.collect::<Vec<_>>().join(", "));
+            }
+            Some(value) => output.push_str(&value.to_string()),
+            None => output.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::error::{BytecodeError, TypeError};
+
+    #[test]
+    fn messages() {
+        let catalog = Catalog::parse(
+            r#"
+            [TypeError]
+            ArityMismatch = "{name}() wants {exp_args}, got {got_args} {unknown}"
+
+            [BytecodeError]
+            Malformed = "broken"
+
+            [Warning]
+            InfiniteEmptyLoop = "spins forever"
+            "#,
+        )
+        .unwrap();
+
+        let error = Error::TypeError(TypeError::ArityMismatch {
+            name: "f".to_string(),
+            exp_args: 1,
+            got_args: 2,
+        });
+        assert_eq!(catalog.error_message(&error), "f() wants 1, got 2 {unknown}");
+        assert_eq!(catalog.error_message(&BytecodeError::Malformed.into()), "broken");
+        let error = Error::TypeError(TypeError::NotCallable { type_: "nil".to_string() });
+        assert_eq!(catalog.error_message(&error), error.message());
+        assert_eq!(catalog.warning_message(&Warning::InfiniteEmptyLoop), "spins forever");
+    }
+
+    #[test]
+    fn builtin() {
+        assert_eq!(Catalog::languages().collect::<Vec<_>>(), ["en", "es"]);
+        for lang in Catalog::languages() {
+            assert!(Catalog::builtin(lang).is_some());
+        }
+        assert_eq!(Catalog::builtin("xx"), None);
+
+        let catalog = Catalog::builtin("es").unwrap();
+        let error = Error::TypeError(TypeError::UnsupportedOperandInfix {
+            op: "+".to_string(),
+            lt_type: "number".to_string(),
+            rt_type: "nil".to_string(),
+        });
+        assert_eq!(
+            catalog.error_message(&error),
+            r#"tipos de operandos no admitidos para +: "number" y "nil""#
+        );
+    }
+}
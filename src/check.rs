@@ -0,0 +1,162 @@
+use std::io::{self, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::{fs, thread};
+
+use anyhow::{Context, Result};
+
+use crate::analysis::Analysis;
+use crate::lint::LintOptions;
+use crate::metrics::FileMetrics;
+use crate::vm::CompilerOptions;
+
+/// The result of checking a set of files for errors.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Summary {
+    pub files: usize,
+    pub files_with_errors: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    /// The metrics of each file that could be read, in the order in which
+    /// the files were checked. Files that do not parse have no functions.
+    pub metrics: Vec<FileMetrics>,
+}
+
+/// Parses and compiles every Lox file in `paths`, without running them, and
+/// reports all errors and warnings to stderr. Directories are searched
+/// recursively for files ending in `.lox`, and files are checked in parallel.
+pub fn check(
+    paths: &[PathBuf],
+    options: &CompilerOptions,
+    lint_options: &LintOptions,
+) -> Result<Summary> {
+    let files = find_files(paths)?;
+    let mut summary = Summary { files: files.len(), ..Default::default() };
+
+    let mut buffer = termcolor::Buffer::ansi();
+    for (path, result) in files.iter().zip(check_files(&files, options, lint_options)) {
+        let name = path.display().to_string();
+        match result {
+            Ok((source, analysis)) => {
+                for error in &analysis.errors {
+                    crate::error::report_file_error(&mut buffer, &name, &source, error);
+                }
+                for warning in &analysis.warnings {
+                    crate::lint::report_warning(&mut buffer, &name, &source, warning);
+                }
+                summary.errors += analysis.errors.len();
+                summary.warnings += analysis.warnings.len();
+                if !analysis.errors.is_empty() {
+                    summary.files_with_errors += 1;
+                }
+                summary.metrics.push(FileMetrics { path: name, functions: analysis.metrics });
+            }
+            Err(e) => {
+                writeln!(buffer, "error: {e:#}")?;
+                summary.errors += 1;
+                summary.files_with_errors += 1;
+            }
+        }
+    }
+    io::stderr().write_all(buffer.as_slice()).context("failed to write to stderr")?;
+
+    Ok(summary)
+}
+
+/// Expands directories in `paths` into the Lox files within them, in a stable
+/// order. Paths to files are kept as-is, whatever their extension.
+pub fn find_files(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            find_files_in_dir(path, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn find_files_in_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect::<io::Result<Vec<_>>>())
+        .with_context(|| format!("could not read directory: {}", dir.display()))?;
+    entries.sort_unstable();
+
+    for path in entries {
+        if path.is_dir() {
+            find_files_in_dir(&path, files)?;
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Checks each file on a pool of threads, returning the results in the same
+/// order as `files`.
+fn check_files(
+    files: &[PathBuf],
+    options: &CompilerOptions,
+    lint_options: &LintOptions,
+) -> Vec<Result<(String, Analysis)>> {
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let chunk_size = ((files.len() + threads - 1) / threads).max(1);
+    thread::scope(|scope| {
+        let handles = files
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(|| {
+                    chunk
+                        .iter()
+                        .map(|path| check_file(path, options.clone(), lint_options))
+                        .collect()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| -> Vec<_> { handle.join().expect("checker thread panicked") })
+            .collect()
+    })
+}
+
+fn check_file(
+    path: &Path,
+    options: CompilerOptions,
+    lint_options: &LintOptions,
+) -> Result<(String, Analysis)> {
+    let source = fs::read_to_string(path)
+        .with_context(|| format!("could not read source from file: {}", path.display()))?;
+    let analysis = crate::analysis::analyze(&source, options, lint_options, &mut None);
+    Ok((source, analysis))
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn check_examples() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("res/examples/return");
+        let files = find_files(std::slice::from_ref(&dir)).unwrap();
+        assert!(files.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(files.iter().all(|file| file.starts_with(&dir)));
+
+        let summary = check(
+            &[dir.join("at_top_level.lox"), dir.join("after_if.lox")],
+            &CompilerOptions::default(),
+            &LintOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            (summary.files, summary.files_with_errors, summary.errors, summary.warnings),
+            (2, 1, 1, 0)
+        );
+        let paths = summary.metrics.iter().map(|file| file.path.as_str()).collect::<Vec<_>>();
+        assert!(paths[0].ends_with("at_top_level.lox") && paths[1].ends_with("after_if.lox"));
+        assert_eq!(summary.metrics[1].functions[0].complexity, 2);
+    }
+}
@@ -1,23 +1,348 @@
-use std::fs;
 use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::{fs, process};
 
 use anyhow::{Context, Result, bail};
-use clap::Parser;
+use clap::builder::RangedU64ValueParser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum, ValueHint};
+use clap_complete::Shell;
 
-use crate::error::ErrorS;
-use crate::vm::VM;
+use crate::catalog::Catalog;
+use crate::error::{Error, ErrorS};
+use crate::lint::LintOptions;
+use crate::modules::{FsLoader, Resolver};
+use crate::project::{Fmt, MANIFEST_FILE, Project};
+use crate::source_map::SourceMap;
+use crate::syntax::ast::Program;
+use crate::types::Span;
+use crate::vm::{
+    CompilerOptions, DEFAULT_MAX_CALL_DEPTH, Gc, MAX_CALL_DEPTH_LIMIT, VM, VmOptions, serialize,
+};
 
 #[derive(Debug, Parser)]
 #[command(about, author, disable_help_subcommand = true, propagate_version = true, version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub cmd: Cmd,
+    /// Write logs to this file, instead of stderr. Which logs are written is
+    /// controlled by $LOXCRAFT_LOG, e.g. "debug" or "loxcraft::vm=trace".
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+}
+
+impl Cli {
+    pub fn run(&self) -> Result<()> {
+        crate::log::init(self.log_file.as_deref())?;
+        self.cmd.run()
+    }
+}
+
+#[derive(Debug, Subcommand)]
 pub enum Cmd {
-    Lsp,
+    /// List the functions in a Lox file that are never referenced.
+    Analyze {
+        path: String,
+        /// Also write the call graph of the file to this path, as a Graphviz
+        /// DOT file.
+        #[arg(long, value_name = "PATH")]
+        call_graph: Option<PathBuf>,
+    },
+    /// Connect to a program run with `lox run --inspect`, to pause it, look
+    /// at its globals and evaluate expressions in it, and resume it.
+    Attach {
+        /// The address that the program is listening on.
+        #[arg(default_value = "127.0.0.1:9229")]
+        addr: String,
+    },
+    /// Time Lox programs, and compare the results with an earlier run.
+    /// Directories are searched recursively for .lox files.
+    Bench {
+        /// Defaults to res/benchmarks.
+        paths: Vec<PathBuf>,
+        /// How many times to run each program.
+        #[arg(long, default_value_t = 3)]
+        runs: usize,
+        /// Compare with results saved by --save.
+        #[arg(long, value_name = "PATH")]
+        baseline: Option<PathBuf>,
+        /// Save the results as JSON, to be compared with later.
+        #[arg(long, value_name = "PATH")]
+        save: Option<PathBuf>,
+        /// Print the results as JSON instead of a table.
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        options: VmArgs,
+    },
+    /// Report errors in Lox files without running them. Directories are
+    /// searched recursively for .lox files.
+    Check {
+        /// Defaults to the source directories of the current project.
+        paths: Vec<PathBuf>,
+        /// Check again whenever a file changes.
+        #[arg(long)]
+        watch: bool,
+        /// The language of error messages: a bundled language, such as "es",
+        /// or the path of a TOML message catalog.
+        #[arg(long, value_name = "LANG")]
+        lang: Option<String>,
+        /// Print the length, nesting depth and cyclomatic complexity of each
+        /// function, as a table, or as JSON with `--metrics=json`.
+        #[arg(
+            long,
+            value_name = "FORMAT",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "table"
+        )]
+        metrics: Option<MetricsFormat>,
+        #[command(flatten)]
+        lints: LintArgs,
+    },
+    /// Print a script that completes commands in a shell.
+    Completions { shell: Shell },
+    /// Compile a Lox file into a program that `lox run` can run without
+    /// parsing or compiling it again.
+    Compile {
+        path: String,
+        /// Defaults to the path of the file, with a .loxc extension.
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Report accesses to undefined globals at compile time.
+        #[arg(long)]
+        strict: bool,
+        /// Give each iteration of a `for` loop a fresh copy of the loop
+        /// variable.
+        #[arg(long)]
+        per_iteration_bindings: bool,
+        /// Allow `extend` statements, which add methods to existing classes.
+        #[arg(long)]
+        class_extensions: bool,
+    },
+    /// Run a Lox file under an interactive debugger, which pauses at the
+    /// first line, or at the first breakpoint if any are given.
+    Debug {
+        path: String,
+        /// Pause at this line. Can be given more than once.
+        #[arg(long = "break", short, value_name = "LINE")]
+        breakpoints: Vec<usize>,
+        #[command(flatten)]
+        options: VmArgs,
+    },
+    /// Explain an error code, such as E0202, with examples.
+    Explain { code: String },
+    /// Vendor the dependencies of the current project into lox_modules/.
+    Fetch,
+    /// Format Lox files in place, re-indenting each line by the braces around
+    /// it and removing trailing whitespace. Directories are searched
+    /// recursively for .lox files.
+    Fmt {
+        /// Defaults to the source directories of the current project.
+        paths: Vec<PathBuf>,
+        /// Format the source read from stdin, and write it to stdout.
+        #[arg(long, conflicts_with = "paths")]
+        stdin: bool,
+        /// Only format the lines that overlap these byte offsets, as in
+        /// `120:480`. Needs a single file, or --stdin.
+        #[arg(long, value_name = "START:END", value_parser = parse_span)]
+        range: Option<Span>,
+        /// The number of spaces to indent by. Defaults to the setting in the
+        /// current project, or 2.
+        #[arg(long)]
+        indent_width: Option<usize>,
+    },
+    /// Print a syntax-highlighted Lox file, as ANSI text or HTML.
+    Highlight {
+        path: String,
+        #[arg(long, value_enum, default_value_t = HighlightFormat::Ansi)]
+        format: HighlightFormat,
+        /// A TOML file mapping highlight names to colors. Defaults to
+        /// theme.toml in the data directory, if it exists.
+        #[arg(long, value_name = "PATH")]
+        theme: Option<PathBuf>,
+    },
+    Lsp {
+        /// Log how long each phase of analyzing a document takes.
+        #[arg(long)]
+        timings: bool,
+        /// Serve a client over stdio. This is the default, but clients often
+        /// pass it anyway.
+        #[arg(long, conflicts_with_all = ["tcp", "ws"])]
+        stdio: bool,
+        /// Serve clients over TCP on this address, instead of stdio.
+        #[arg(long, value_name = "ADDR", conflicts_with = "ws")]
+        tcp: Option<SocketAddr>,
+        /// Serve clients over WebSocket on this address, instead of stdio.
+        #[arg(long, value_name = "ADDR")]
+        ws: Option<SocketAddr>,
+        /// The language of error messages: a bundled language, such as "es",
+        /// or the path of a TOML message catalog.
+        #[arg(long, value_name = "LANG")]
+        lang: Option<String>,
+        #[command(flatten)]
+        lints: LintArgs,
+    },
+    /// Print the man page of loxcraft, in roff.
+    Man {
+        /// Instead, write a man page for loxcraft and for each of its
+        /// subcommands into this directory.
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+    /// Create a new project.
+    New { path: PathBuf },
+    /// Parse a Lox file, and print its syntax tree as JSON.
+    Parse {
+        path: String,
+        /// Print the tree as an s-expression in the style of tree-sitter,
+        /// with the byte range of each node, to compare it with the tree
+        /// from tree-sitter-lox.
+        #[arg(long)]
+        sexpr: bool,
+    },
     Playground {
-        #[arg(long, default_value = "4000")]
-        port: u16,
+        /// The port to listen on. Defaults to 4000, or to any free port if
+        /// that one is taken.
+        #[arg(long, conflicts_with = "unix")]
+        port: Option<u16>,
+        /// Listen on this Unix domain socket instead of a port, e.g. behind a
+        /// reverse proxy.
+        #[arg(long, value_name = "PATH")]
+        unix: Option<PathBuf>,
+        /// Serve the playground under this path instead of the root.
+        #[arg(long, value_name = "PATH", default_value = "/")]
+        base_path: String,
+        /// The file in which to keep the settings of the editor, such as its
+        /// theme and font. Defaults to a file in the data directory.
+        #[arg(long, value_name = "PATH")]
+        settings: Option<PathBuf>,
+    },
+    /// Start an interactive session. Defaults for the flags below can be set
+    /// in repl.toml in the data directory.
+    Repl {
+        /// Record usage statistics for this session in the data directory.
+        #[arg(long)]
+        stats: bool,
+        /// The file in which to store history. Defaults to $LOXCRAFT_HISTORY,
+        /// then .lox_history in the current directory if it exists, then a
+        /// file in the data directory.
+        #[arg(long, value_name = "PATH")]
+        history: Option<PathBuf>,
+        /// Don't read or save history.
+        #[arg(long, conflicts_with = "history")]
+        no_history: bool,
+        /// A TOML file mapping highlight names to colors. Defaults to
+        /// theme.toml in the data directory, if it exists.
+        #[arg(long, value_name = "PATH")]
+        theme: Option<PathBuf>,
+        /// The prompt shown before each input. Defaults to ">>> ".
+        #[arg(long, value_name = "TEXT")]
+        prompt: Option<String>,
+        /// The prompt shown before each continuation line. Defaults to "... ".
+        #[arg(long, value_name = "TEXT")]
+        continuation_prompt: Option<String>,
+        /// The text shown when the REPL starts. Defaults to the version of
+        /// loxcraft and a hint on how to exit.
+        #[arg(long, value_name = "TEXT")]
+        banner: Option<String>,
+        /// Don't show the banner, e.g. when scripting the REPL.
+        #[arg(long, short)]
+        quiet: bool,
+        /// Read plain lines from stdin instead of using a line editor. This is
+        /// the default when stdin or stdout is not a terminal.
+        #[arg(long)]
+        no_editor: bool,
+        /// Replay this file as if it were typed, echoing each prompt and input
+        /// along with the output, to write a transcript of the session.
+        #[arg(long, value_name = "PATH", conflicts_with = "no_editor")]
+        script: Option<PathBuf>,
+        #[command(flatten)]
+        options: VmArgs,
     },
-    Repl,
+    /// Run a Lox file. If it defines a `main` function, it is called after the
+    /// top-level code with the arguments as strings, and its return value is
+    /// the exit code.
+    ///
+    /// Further .lox files after the first are run as part of the same
+    /// program, in order, as if they were concatenated, so they share globals.
+    /// A top-level `include "path";` in a file is replaced by the file at that
+    /// path, relative to it, unless that file has already been included. In a
+    /// project, a path that is not a file there may name one of its modules.
     Run {
+        /// Defaults to the entry point of the current project. A .loxc file
+        /// from `lox compile` is run without compiling it again.
+        #[arg(value_hint = ValueHint::FilePath)]
+        path: Option<String>,
+        /// More .lox files, followed by the arguments passed to `main`, which
+        /// start at the first argument that does not end in .lox.
+        #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+        args: Vec<String>,
+        /// Run the program again whenever the file changes.
+        #[arg(long)]
+        watch: bool,
+        /// Always compile the program, instead of reusing it from the cache.
+        #[arg(long)]
+        no_cache: bool,
+        /// Print how many garbage collections ran, and how many objects of
+        /// each type were allocated, once the program ends. With the
+        /// `vm-stats` feature, also print the functions called most often.
+        #[arg(long)]
+        stats: bool,
+        /// Print the functions that cost the most to run, with how many times
+        /// each was called, and how many ops each ran and objects each
+        /// allocated, not counting the functions that it called. Needs the
+        /// `vm-stats` feature.
+        #[arg(long)]
+        hot_report: bool,
+        /// Listen on this address for `lox attach`, which can then pause the
+        /// top-level code of the program and run debugger commands in it.
+        /// Only loopback addresses are allowed, unless --inspect-remote is
+        /// given.
+        #[arg(long, value_name = "ADDR", conflicts_with = "watch")]
+        inspect: Option<SocketAddr>,
+        /// Allow --inspect to listen on an address that other machines can
+        /// reach. Anyone who can connect may read and change the program's
+        /// globals, since the inspector does not authenticate clients.
+        #[arg(long, requires = "inspect")]
+        inspect_remote: bool,
+        #[command(flatten)]
+        options: VmArgs,
+    },
+    /// Show usage statistics recorded by `repl --stats`.
+    Stats,
+    /// List the classes, methods, functions and globals declared in a Lox
+    /// file, for editors that do not speak the Language Server Protocol.
+    Symbols {
         path: String,
+        /// Print the symbols as JSON, with their spans and positions.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a tags file for the classes, methods and functions in Lox files,
+    /// for editors that do not speak the Language Server Protocol.
+    /// Directories are searched recursively for .lox files.
+    Tags {
+        /// Defaults to the source directories of the current project.
+        paths: Vec<PathBuf>,
+        /// Write an etags file for Emacs, instead of a ctags file.
+        #[arg(long)]
+        etags: bool,
+        /// Defaults to "tags", or "TAGS" with --etags.
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Check that a compiled Lox program is well-formed and safe to run, and
+    /// print the version of loxcraft that compiled it and the hash of its
+    /// source.
+    Verify {
+        path: PathBuf,
+        /// Also check that the program was compiled from this source file.
+        #[arg(long, value_name = "PATH")]
+        source: Option<PathBuf>,
+        /// Print the disassembly of the program.
+        #[arg(long)]
+        disassemble: bool,
     },
 }
 
@@ -25,51 +350,908 @@ impl Cmd {
     pub fn run(&self) -> Result<()> {
         #[allow(unused_variables)]
         match self {
+            Cmd::Analyze { path, call_graph } => analyze(path, call_graph.as_deref()),
+
+            Cmd::Attach { addr } => crate::debugger::attach(addr),
+
+            Cmd::Bench { paths, runs, baseline, save, json, options } => {
+                let paths = match &paths[..] {
+                    [] => vec![PathBuf::from(crate::bench::DEFAULT_DIR)],
+                    paths => paths.to_vec(),
+                };
+                let baseline = match baseline {
+                    Some(path) => crate::bench::load(path)?,
+                    None => Vec::new(),
+                };
+                let paths = crate::check::find_files(&paths)?;
+                let results = crate::bench::run(&paths, *runs, &options.into())?;
+                if let Some(path) = save {
+                    crate::bench::save(path, &results)?;
+                }
+                if *json {
+                    let json =
+                        serde_json::to_string(&results).context("could not serialize results")?;
+                    println!("{json}");
+                } else {
+                    print!("{}", crate::bench::report(&results, &baseline));
+                }
+                Ok(())
+            }
+
+            Cmd::Check { paths, watch, lang, metrics, lints } => {
+                set_lang(lang.as_deref())?;
+                let project = Project::discover(Path::new("."))?;
+                let paths = match &project {
+                    _ if !paths.is_empty() => paths.clone(),
+                    Some(project) => project.src(),
+                    None => bail!("no paths given, and no {MANIFEST_FILE} found"),
+                };
+                let manifest = project.map(|project| project.manifest).unwrap_or_default();
+                let package = manifest.package;
+                let options =
+                    if package.strict { CompilerOptions::strict() } else { Default::default() };
+                let mut lint_options =
+                    LintOptions { strict_types: package.strict_types, ..LintOptions::from(lints) };
+                lint_options.shadowing |= manifest.lints.shadowing;
+                lint_options.use_before_init |= manifest.lints.use_before_init;
+                lint_options.private_members |= manifest.lints.private_members;
+                lint_options.nested_this |= manifest.lints.nested_this;
+                lint_options.constant_conditions |= manifest.lints.constant_conditions;
+
+                if *watch {
+                    watch_paths(&paths, || check(&paths, &options, &lint_options, *metrics))
+                } else {
+                    check(&paths, &options, &lint_options, *metrics)
+                }
+            }
+
+            Cmd::Completions { shell } => {
+                let mut command = Cli::command();
+                let name = command.get_name().to_string();
+                clap_complete::generate(*shell, &mut command, name, &mut io::stdout());
+                Ok(())
+            }
+
+            Cmd::Compile { path, output, strict, per_iteration_bindings, class_extensions } => {
+                let project = Project::discover(Path::new("."))?;
+                let output = match output {
+                    Some(output) => output.clone(),
+                    None if path == "-" => bail!("no output path given for stdin"),
+                    None => Path::new(path).with_extension("loxc"),
+                };
+                let options = VmOptions {
+                    strict: *strict
+                        || project.is_some_and(|project| project.manifest.package.strict),
+                    per_iteration_bindings: *per_iteration_bindings,
+                    class_extensions: *class_extensions,
+                    // Compiled programs run in a VM of their own, like scripts.
+                    direct_native_calls: true,
+                    ..Default::default()
+                };
+                compile(path, &output, &options)
+            }
+
+            Cmd::Debug { path, breakpoints, options } => {
+                set_lang(options.lang.as_deref())?;
+                if path == "-" {
+                    bail!("cannot debug stdin, since commands are read from it");
+                }
+                let source = read_source(path)?;
+                let mut map = SourceMap::default();
+                map.add(path.as_str(), &source);
+                if let Err(e) = crate::debugger::run(&source, breakpoints, options.into())? {
+                    report_err(&map, e);
+                    bail!("program exited with errors");
+                }
+                Ok(())
+            }
+
+            Cmd::Explain { code } => match crate::explain::explain(code) {
+                Some(explanation) => {
+                    print!("{explanation}");
+                    Ok(())
+                }
+                None => bail!("{code} is not a valid error code"),
+            },
+
+            Cmd::Fetch => match Project::discover(Path::new("."))? {
+                Some(project) => crate::modules::fetch(&project),
+                None => bail!("no {MANIFEST_FILE} found"),
+            },
+
+            Cmd::Fmt { paths, stdin, range, indent_width } => {
+                let project = Project::discover(Path::new("."))?;
+                let fmt = project
+                    .as_ref()
+                    .map_or_else(Fmt::default, |project| project.manifest.fmt.clone());
+                let indent = " ".repeat(indent_width.unwrap_or(fmt.indent_width));
+                if *stdin {
+                    let source = read_source("-")?;
+                    print!("{}", format_source(&source, range.clone(), &indent));
+                    return Ok(());
+                }
+                let paths = match project {
+                    _ if !paths.is_empty() => paths.clone(),
+                    Some(project) => project.src(),
+                    None => bail!("no paths given, and no {MANIFEST_FILE} found"),
+                };
+                let files = crate::check::find_files(&paths)?;
+                if range.is_some() && files.len() != 1 {
+                    bail!("--range needs a single file, but {} were given", files.len());
+                }
+                let mut changed = 0;
+                for path in &files {
+                    let source = fs::read_to_string(path).with_context(|| {
+                        format!("could not read source from file: {}", path.display())
+                    })?;
+                    let formatted = format_source(&source, range.clone(), &indent);
+                    if formatted != source {
+                        fs::write(path, formatted)
+                            .with_context(|| format!("could not write file: {}", path.display()))?;
+                        changed += 1;
+                    }
+                }
+                eprintln!("formatted {}, {changed} changed", plural(files.len(), "file"));
+                Ok(())
+            }
+
+            #[cfg(feature = "repl")]
+            Cmd::Highlight { path, format, theme } => {
+                let source = read_source(path)?;
+                let theme = crate::theme::Theme::load(theme.as_deref())?;
+                let highlighter = crate::highlight::Highlighter::new(theme)?;
+                match format {
+                    HighlightFormat::Ansi => print!("{}", highlighter.to_ansi(&source)),
+                    HighlightFormat::Html => print!("{}", highlighter.to_html(&source)),
+                }
+                Ok(())
+            }
+            #[cfg(not(feature = "repl"))]
+            Cmd::Highlight { .. } => bail!("loxcraft was not compiled with the `repl` feature"),
+
             #[cfg(feature = "lsp")]
-            Cmd::Lsp => crate::lsp::serve(),
+            Cmd::Lsp { timings, stdio: _, tcp, ws, lang, lints } => {
+                set_lang(lang.as_deref())?;
+                use crate::lsp::Transport;
+                let transport = match (tcp, ws) {
+                    (Some(addr), _) => Transport::Tcp(*addr),
+                    (_, Some(addr)) => Transport::WebSocket(*addr),
+                    (None, None) => Transport::Stdio,
+                };
+                crate::lsp::serve(transport, *timings, lints.into())
+            }
             #[cfg(not(feature = "lsp"))]
-            Cmd::Lsp => bail!("loxcraft was not compiled with the `lsp` feature"),
+            Cmd::Lsp { .. } => bail!("loxcraft was not compiled with the `lsp` feature"),
 
             #[cfg(feature = "playground")]
-            Cmd::Playground { port } => crate::playground::serve(*port),
+            Cmd::Playground { port, unix, base_path, settings } => {
+                crate::playground::serve(crate::playground::PlaygroundOptions {
+                    port: *port,
+                    unix_socket: unix.clone(),
+                    base_path: base_path.clone(),
+                    settings: settings.clone(),
+                })
+            }
             #[cfg(not(feature = "playground"))]
             Cmd::Playground { .. } => {
                 bail!("loxcraft was not compiled with the `playground` feature")
             }
 
             #[cfg(feature = "repl")]
-            Cmd::Repl => crate::repl::run(),
+            Cmd::Repl {
+                stats,
+                history,
+                no_history,
+                theme,
+                prompt,
+                continuation_prompt,
+                banner,
+                quiet,
+                no_editor,
+                script,
+                options,
+            } => {
+                set_lang(options.lang.as_deref())?;
+                let mut repl_options = crate::repl::ReplConfig::load()?.into_options();
+                repl_options.stats = *stats;
+                if history.is_some() || *no_history {
+                    repl_options.history.clone_from(history);
+                    repl_options.no_history = *no_history;
+                }
+                if theme.is_some() {
+                    repl_options.theme.clone_from(theme);
+                }
+                if let Some(prompt) = prompt {
+                    repl_options.prompt.clone_from(prompt);
+                }
+                if let Some(continuation_prompt) = continuation_prompt {
+                    repl_options.continuation_prompt.clone_from(continuation_prompt);
+                }
+                if banner.is_some() {
+                    repl_options.banner.clone_from(banner);
+                }
+                repl_options.quiet |= *quiet;
+                repl_options.no_editor = *no_editor;
+                repl_options.script.clone_from(script);
+                crate::repl::run(repl_options, options.into())
+            }
             #[cfg(not(feature = "repl"))]
-            Cmd::Repl => bail!("loxcraft was not compiled with the `repl` feature"),
-
-            Cmd::Run { path } => {
-                let source = if path == "-" {
-                    let mut source = String::new();
-                    io::stdin()
-                        .read_to_string(&mut source)
-                        .context("could not read source from stdin")?;
-                    source
+            Cmd::Repl { .. } => bail!("loxcraft was not compiled with the `repl` feature"),
+
+            Cmd::Man { out_dir: Some(out_dir) } => {
+                clap_mangen::generate_to(Cli::command(), out_dir)
+                    .with_context(|| format!("could not write man pages to: {}", out_dir.display()))
+            }
+            Cmd::Man { out_dir: None } => clap_mangen::Man::new(Cli::command())
+                .render(&mut io::stdout().lock())
+                .context("could not write man page"),
+
+            Cmd::New { path } => crate::project::new(path),
+
+            Cmd::Parse { path, sexpr } => {
+                let source = read_source(path)?;
+                let program = parse(path, &source)?;
+                if *sexpr {
+                    println!("{}", program.to_sexpr());
                 } else {
-                    fs::read_to_string(path)
-                        .with_context(|| format!("could not read source from file: {path}"))?
+                    let json =
+                        serde_json::to_string(&program).context("could not serialize program")?;
+                    println!("{json}");
+                }
+                Ok(())
+            }
+
+            Cmd::Run {
+                path,
+                args,
+                watch,
+                no_cache,
+                stats,
+                hot_report,
+                inspect,
+                inspect_remote,
+                options,
+            } => {
+                set_lang(options.lang.as_deref())?;
+                if let Some(addr) = inspect {
+                    check_inspect_addr(*addr, *inspect_remote)?;
+                }
+                let project = Project::discover(Path::new("."))?;
+                let path = match (path, &project) {
+                    (Some(path), _) => path.clone(),
+                    (None, Some(project)) => project.entry().to_string_lossy().into_owned(),
+                    (None, None) => bail!("no path given, and no {MANIFEST_FILE} found"),
                 };
+                let files = args.iter().take_while(|arg| arg.ends_with(".lox")).count();
+                let (files, args) = args.split_at(files);
+                let paths = [&[path][..], files].concat();
+                if paths.len() > 1 && paths[0].ends_with(".loxc") {
+                    bail!("a compiled program cannot be run with other files");
+                }
+                if inspect.is_some() && paths[0].ends_with(".loxc") {
+                    bail!("a compiled program cannot be inspected, since its source is needed");
+                }
+                let mut options = VmOptions::from(options);
+                // The script is the only program that runs in its VM.
+                options.direct_native_calls = true;
+                if let Some(project) = project {
+                    options.strict |= project.manifest.package.strict;
+                    options.strict_types |= project.manifest.package.strict_types;
+                }
 
-                let mut vm = VM::default();
-                let stdout = &mut io::stdout().lock();
-                if let Err(e) = vm.run(&source, stdout) {
-                    report_err(&source, e);
-                    bail!("program exited with errors");
+                if *watch {
+                    if paths.iter().any(|path| path == "-") {
+                        bail!("cannot watch stdin for changes");
+                    }
+                    let watched = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+                    watch_paths(&watched, || {
+                        match run(&paths, args, &options, !no_cache, *stats, *hot_report, None)? {
+                            0 => {}
+                            code => eprintln!("program exited with code {code}"),
+                        }
+                        Ok(())
+                    })
+                } else {
+                    match run(&paths, args, &options, !no_cache, *stats, *hot_report, *inspect)? {
+                        0 => Ok(()),
+                        code => process::exit(code),
+                    }
                 }
+            }
+
+            #[cfg(feature = "repl")]
+            Cmd::Stats => {
+                let stats = crate::stats::Stats::load()?;
+                print!("{stats}");
                 Ok(())
             }
+            #[cfg(not(feature = "repl"))]
+            Cmd::Stats => bail!("loxcraft was not compiled with the `repl` feature"),
+
+            Cmd::Symbols { path, json } => symbols(path, *json),
+
+            Cmd::Tags { paths, etags, output } => {
+                let paths = match Project::discover(Path::new("."))? {
+                    _ if !paths.is_empty() => paths.clone(),
+                    Some(project) => project.src(),
+                    None => bail!("no paths given, and no {MANIFEST_FILE} found"),
+                };
+                let default_output = if *etags { "TAGS" } else { "tags" };
+                tags(&paths, *etags, output.as_deref().unwrap_or(Path::new(default_output)))
+            }
+
+            Cmd::Verify { path, source, disassemble } => {
+                verify(path, source.as_deref(), *disassemble)
+            }
+        }
+    }
+}
+
+fn analyze(path: &str, call_graph: Option<&Path>) -> Result<()> {
+    let source = read_source(path)?;
+    let program = parse(path, &source)?;
+    let graph = crate::callgraph::CallGraph::new(&source, &program);
+    let lines = crate::types::LineIndex::new(&source);
+
+    if let Some(call_graph) = call_graph {
+        fs::write(call_graph, graph.to_dot())
+            .with_context(|| format!("could not write call graph: {}", call_graph.display()))?;
+    }
+    for function in graph.unreferenced() {
+        let span = function.span.clone().unwrap_or_default();
+        let position = lines.position(&source, span.start);
+        println!(
+            "{path}:{}:{}: {} is never referenced",
+            position.line + 1,
+            position.character + 1,
+            function.name
+        );
+    }
+    Ok(())
+}
+
+fn symbols(path: &str, json: bool) -> Result<()> {
+    let source = read_source(path)?;
+    let program = parse(path, &source)?;
+    let symbols = crate::analysis::document_symbols(&source, &program);
+    let lines = crate::types::LineIndex::new(&source);
+
+    if json {
+        println!("{}", serde_json::to_string(&symbols).context("could not serialize symbols")?);
+        return Ok(());
+    }
+    let print = |symbol: &crate::analysis::DocumentSymbol, name: &str| {
+        let position = lines.position(&source, symbol.name_span.start);
+        println!("{path}:{}:{}: {} {name}", position.line + 1, position.character + 1, symbol.kind);
+    };
+    for symbol in &symbols {
+        print(symbol, &symbol.name);
+        for child in &symbol.children {
+            print(child, &format!("{}.{}", symbol.name, child.name));
+        }
+    }
+    Ok(())
+}
+
+fn tags(paths: &[PathBuf], etags: bool, output: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    for path in crate::check::find_files(paths)? {
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("could not read source from file: {}", path.display()))?;
+        match crate::syntax::parse(&source, 0) {
+            Ok(program) => files.push(crate::tags::FileTags::new(&path, &source, &program)),
+            Err(_) => eprintln!("skipping {}, which could not be parsed", path.display()),
+        }
+    }
+    let tags = if etags { crate::tags::to_etags(&files) } else { crate::tags::to_ctags(&files) };
+    fs::write(output, tags)
+        .with_context(|| format!("could not write tags file: {}", output.display()))?;
+    let count = files.iter().map(|file| file.tags.len()).sum();
+    eprintln!("wrote {} to {}", plural(count, "tag"), output.display());
+    Ok(())
+}
+
+fn check(
+    paths: &[PathBuf],
+    options: &CompilerOptions,
+    lint_options: &LintOptions,
+    metrics: Option<MetricsFormat>,
+) -> Result<()> {
+    let summary = crate::check::check(paths, options, lint_options)?;
+    match metrics {
+        Some(MetricsFormat::Table) => print!("{}", crate::metrics::to_table(&summary.metrics)),
+        Some(MetricsFormat::Json) => println!(
+            "{}",
+            serde_json::to_string_pretty(&summary.metrics)
+                .context("could not serialize metrics")?
+        ),
+        None => {}
+    }
+    if summary.errors != 0 {
+        bail!(
+            "found {} in {} of {}",
+            plural(summary.errors, "error"),
+            summary.files_with_errors,
+            plural(summary.files, "file"),
+        );
+    }
+    match summary.warnings {
+        0 => eprintln!("checked {}, no errors found", plural(summary.files, "file")),
+        warnings => eprintln!(
+            "checked {}, no errors found, {}",
+            plural(summary.files, "file"),
+            plural(warnings, "warning")
+        ),
+    }
+    Ok(())
+}
+
+fn verify(path: &Path, source: Option<&Path>, disassemble: bool) -> Result<()> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("could not read program from file: {}", path.display()))?;
+    let mut gc = Gc::default();
+    let (header, script) = serialize::decode_unchecked(&bytes, &mut gc)
+        .with_context(|| format!("invalid program: {}", path.display()))?;
+    println!("version: {}", header.version);
+    println!("source hash: {:016x}", header.source_hash);
+
+    if let Some(source) = source {
+        let source = fs::read_to_string(source)
+            .with_context(|| format!("could not read source from file: {}", source.display()))?;
+        if serialize::source_hash(&source) != header.source_hash {
+            bail!("program was not compiled from the given source");
+        }
+    }
+    if header.version != serialize::VERSION {
+        bail!(
+            "program was compiled by loxcraft {}, but this is loxcraft {}",
+            header.version,
+            serialize::VERSION
+        );
+    }
+    let script = unsafe { &*script };
+    if let Err((e, _)) = crate::vm::verify(script) {
+        bail!("invalid program: {}: {e}", path.display());
+    }
+    if disassemble {
+        print!("{}", script.disassemble());
+    }
+    eprintln!("verified {}", path.display());
+    Ok(())
+}
+
+/// Compiles the file at `path` into a program at `output`, which
+/// [`run`] can run later.
+fn compile(path: &str, output: &Path, options: &VmOptions) -> Result<()> {
+    let source = read_source(path)?;
+    let mut map = SourceMap::default();
+    map.add(path, &source);
+    report_warnings(&map, &LintOptions::default());
+    let bytecode = match VM::new(options.clone()).compile(&source) {
+        Ok(bytecode) => bytecode,
+        Err(errors) => {
+            report_err(&map, errors);
+            bail!("could not compile program");
+        }
+    };
+    fs::write(output, bytecode)
+        .with_context(|| format!("could not write program to file: {}", output.display()))?;
+    eprintln!("compiled {path} to {}", output.display());
+    Ok(())
+}
+
+/// The number of functions printed by `lox run --hot-report`.
+const HOT_REPORT_LEN: usize = 10;
+
+/// Runs files as one program, followed by its `main` function if it has one,
+/// and returns the exit code. If `cache` is set, the compiled program is
+/// reused from the [`cache`](crate::cache) when possible. A single .loxc file
+/// is run as a compiled program.
+fn run(
+    paths: &[String],
+    args: &[String],
+    options: &VmOptions,
+    cache: bool,
+    stats: bool,
+    hot_report: bool,
+    inspect: Option<SocketAddr>,
+) -> Result<i32> {
+    let mut vm = VM::new(options.clone());
+    let stdout = &mut io::stdout().lock();
+    let (map, result) = match paths {
+        [path] if path.ends_with(".loxc") => run_compiled(&mut vm, path, stdout)?,
+        _ => run_source(&mut vm, paths, options, cache, inspect, stdout)?,
+    };
+    if let Some(timings) = &vm.timings {
+        eprintln!("{timings}");
+    }
+    let result = result.and_then(|()| vm.run_main(args, stdout));
+    stdout.flush().context("could not write to stdout")?;
+    if stats {
+        eprintln!("{}\n{}", vm.stats, vm.gc.counts());
+        for (name, calls) in vm.hot_functions(5) {
+            eprintln!("{:<12} {:>12}", format!("{name}()"), calls);
+        }
+    }
+    if hot_report && cfg!(feature = "vm-stats") {
+        eprintln!(
+            "{:<20} {:>10} {:>12} {:>10} {:>10}",
+            "function", "calls", "ops", "ops/call", "allocs"
+        );
+        for cost in vm.function_costs(HOT_REPORT_LEN) {
+            eprintln!("{cost}");
+        }
+    }
+    // Both of these report on the functions, which are only counted with the
+    // feature, but the rest of --stats is still worth printing without it.
+    if (stats || hot_report) && !cfg!(feature = "vm-stats") {
+        eprintln!("functions are only counted with the `vm-stats` feature");
+    }
+    match result {
+        Ok(code) => Ok(code.unwrap_or(0)),
+        Err(e) => {
+            if !vm.history.is_empty() {
+                eprintln!("{}", vm.history);
+            }
+            report_err(&map, e);
+            bail!("program exited with errors");
+        }
+    }
+}
+
+/// Compiles and runs the top-level code of the files at `paths`, and returns
+/// them as a [`SourceMap`] for reporting errors. Included files are read from
+/// disk, and in a project, may also be its modules. With `inspect`, the code
+/// runs under [`crate::debugger::serve`].
+fn run_source(
+    vm: &mut VM,
+    paths: &[String],
+    options: &VmOptions,
+    cache: bool,
+    inspect: Option<SocketAddr>,
+    stdout: &mut impl Write,
+) -> Result<(SourceMap, Result<(), Vec<ErrorS>>)> {
+    let resolver =
+        Project::discover(Path::new("."))?.map(|project| Resolver::for_project(&project));
+    let mut loader = FsLoader { resolver };
+    let mut map = SourceMap::default();
+    for path in paths {
+        let source = read_source(path)?;
+        // A file that is cut short would otherwise swallow the start of the
+        // next one, and its errors would be reported there.
+        if paths.len() > 1 && !crate::syntax::is_complete(&source) {
+            if let Err(errors) = crate::syntax::parse(&source, 0) {
+                let mut file = SourceMap::default();
+                file.add(path.as_str(), &source);
+                report_err(&file, errors);
+                bail!("could not parse program");
+            }
+        }
+        map.add_with_includes(path.as_str(), &source, &mut loader)?;
+    }
+    let source = map.source();
+
+    // Timings are meant to measure every phase, so they skip the cache, and
+    // the inspector needs the source to step through.
+    let cache = cache && !options.timings && inspect.is_none();
+    if let Some(bytecode) = cache.then(|| crate::cache::load(source, options)).flatten() {
+        match vm.run_bytecode(source, &bytecode, stdout) {
+            // Programs from other versions, or that are corrupt, are compiled
+            // again.
+            Err(errors) if matches!(errors[..], [(Error::BytecodeError(_), _)]) => {}
+            result => return Ok((map, result)),
+        }
+    }
+    let warnings = report_warnings(
+        &map,
+        &LintOptions { strict_types: options.strict_types, ..Default::default() },
+    );
+    // Programs with warnings are not cached, so that the warnings are reported
+    // every time they run.
+    let result = if cache && warnings == 0 {
+        vm.compile(source).and_then(|bytecode| {
+            crate::cache::store(source, options, &bytecode);
+            vm.run_bytecode(source, &bytecode, stdout)
+        })
+    } else if let Some(addr) = inspect {
+        crate::debugger::serve(vm, source, addr, stdout)?
+    } else {
+        vm.run(source, stdout)
+    };
+    Ok((map, result))
+}
+
+/// Runs the top-level code of the compiled program at `path`. If its source is
+/// next to it, with a .lox extension, it is used to report errors.
+fn run_compiled(
+    vm: &mut VM,
+    path: &str,
+    stdout: &mut impl Write,
+) -> Result<(SourceMap, Result<(), Vec<ErrorS>>)> {
+    let bytecode =
+        fs::read(path).with_context(|| format!("could not read program from file: {path}"))?;
+    let source_path = Path::new(path).with_extension("lox");
+    let source = fs::read_to_string(&source_path).ok().filter(|source| {
+        serialize::decode_header(&bytecode)
+            .is_ok_and(|header| header.source_hash == serialize::source_hash(source))
+    });
+
+    let mut map = SourceMap::default();
+    let result = match &source {
+        Some(source) => {
+            map.add(source_path.to_string_lossy(), source);
+            vm.run_bytecode(source, &bytecode, stdout)
+        }
+        // Without the source, errors are reported without it.
+        None => {
+            map.add(path, "");
+            vm.run_compiled(&bytecode, stdout)
+        }
+    };
+    Ok((map, result))
+}
+
+#[cfg(feature = "watch")]
+fn watch_paths(paths: &[PathBuf], f: impl FnMut() -> Result<()>) -> Result<()> {
+    crate::watch::watch(paths, f)
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch_paths(_: &[PathBuf], _: impl FnMut() -> Result<()>) -> Result<()> {
+    bail!("loxcraft was not compiled with the `watch` feature")
+}
+
+#[derive(Debug, Parser)]
+pub struct VmArgs {
+    /// Report accesses to undefined globals at compile time.
+    #[arg(long)]
+    strict: bool,
+    /// Give each iteration of a `for` loop a fresh copy of the loop variable.
+    #[arg(long)]
+    per_iteration_bindings: bool,
+    /// Allow `extend` statements, which add methods to existing classes.
+    #[arg(long)]
+    class_extensions: bool,
+    /// Print how long each phase of running the program takes.
+    #[arg(long)]
+    timings: bool,
+    /// The maximum number of nested function calls.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = DEFAULT_MAX_CALL_DEPTH,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..=MAX_CALL_DEPTH_LIMIT as u64),
+    )]
+    max_call_depth: usize,
+    /// Stop the program once it has printed this many bytes.
+    #[arg(long, value_name = "BYTES")]
+    max_output: Option<usize>,
+    /// Check the compiled bytecode before running it.
+    #[arg(long)]
+    verify: bool,
+    /// Require conditions to be bools, and `==` to compare values of the same
+    /// type.
+    #[arg(long)]
+    strict_types: bool,
+    /// Log each op and the stack as the program runs.
+    #[arg(long)]
+    trace: bool,
+    /// When the program fails, print the last N ops that it ran, and how
+    /// each changed the stack and globals.
+    #[arg(long = "op-history", id = "op_history", value_name = "N", default_value_t = 0)]
+    history: usize,
+    /// Print numbers rounded to N digits after the decimal point.
+    #[arg(long, value_name = "N")]
+    precision: Option<usize>,
+    /// The language of error messages: a bundled language, such as "es", or
+    /// the path of a TOML message catalog.
+    #[arg(long, value_name = "LANG")]
+    lang: Option<String>,
+}
+
+impl From<&VmArgs> for VmOptions {
+    fn from(args: &VmArgs) -> Self {
+        VmOptions {
+            strict: args.strict,
+            per_iteration_bindings: args.per_iteration_bindings,
+            class_extensions: args.class_extensions,
+            timings: args.timings,
+            max_call_depth: args.max_call_depth,
+            max_output: args.max_output,
+            verify: args.verify,
+            strict_types: args.strict_types,
+            trace: args.trace,
+            history: args.history,
+            precision: args.precision,
+            ..Default::default()
         }
     }
 }
 
-fn report_err(source: &str, errors: Vec<ErrorS>) {
+#[derive(Debug, clap::Args)]
+pub struct LintArgs {
+    /// Warn about declarations that shadow a variable in an outer scope.
+    #[arg(long)]
+    warn_shadowing: bool,
+    /// Warn about variables that are read before they are assigned a value.
+    #[arg(long)]
+    warn_use_before_init: bool,
+    /// Warn about fields and methods starting with an underscore that are
+    /// used other than through this or super.
+    #[arg(long)]
+    warn_private_members: bool,
+    /// Warn about this and super in functions declared inside a method.
+    #[arg(long)]
+    warn_nested_this: bool,
+    /// Warn about conditions that always hold or never do.
+    #[arg(long)]
+    warn_constant_conditions: bool,
+}
+
+impl From<&LintArgs> for LintOptions {
+    fn from(args: &LintArgs) -> Self {
+        LintOptions {
+            shadowing: args.warn_shadowing,
+            use_before_init: args.warn_use_before_init,
+            private_members: args.warn_private_members,
+            nested_this: args.warn_nested_this,
+            constant_conditions: args.warn_constant_conditions,
+            ..Default::default()
+        }
+    }
+}
+
+/// Reports errors in `lang` for the rest of the process. See [`VmArgs::lang`].
+fn set_lang(lang: Option<&str>) -> Result<()> {
+    let Some(lang) = lang else { return Ok(()) };
+    let catalog = match Catalog::builtin(lang) {
+        Some(catalog) => catalog,
+        None if lang.ends_with(".toml") => {
+            let source = fs::read_to_string(lang)
+                .with_context(|| format!("could not read message catalog: {lang}"))?;
+            Catalog::parse(&source)
+                .with_context(|| format!("could not parse message catalog: {lang}"))?
+        }
+        None => {
+            let languages = Catalog::languages().collect::<Vec<_>>().join(", ");
+            bail!("unknown language: {lang} (expected one of {languages}, or a .toml file)");
+        }
+    };
+    // Only the first catalog set in a process is used.
+    let _ = crate::catalog::set_catalog(catalog);
+    Ok(())
+}
+
+/// Checks that `run --inspect` may listen on `addr`. The inspector lets any
+/// client evaluate code in the program, so it only listens on loopback
+/// addresses, unless `remote` is set.
+fn check_inspect_addr(addr: SocketAddr, remote: bool) -> Result<()> {
+    if !remote && !addr.ip().is_loopback() {
+        bail!(
+            "refusing to inspect on {addr}, which other machines may reach; listen on a loopback \
+             address such as 127.0.0.1, or pass --inspect-remote"
+        );
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum HighlightFormat {
+    Ansi,
+    Html,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum MetricsFormat {
+    Table,
+    Json,
+}
+
+/// Reads source code from a file, or from stdin if the path is `-`.
+/// Parses `source`, reporting any errors against `path`.
+fn parse(path: &str, source: &str) -> Result<Program> {
+    crate::syntax::parse(source, 0).or_else(|errors| {
+        let mut map = SourceMap::default();
+        map.add(path, source);
+        report_err(&map, errors);
+        bail!("could not parse program");
+    })
+}
+
+fn format_source(source: &str, range: Option<Span>, indent: &str) -> String {
+    let mut source = source.to_string();
+    for edit in crate::analysis::format_edits(&source, range, indent).into_iter().rev() {
+        source.replace_range(edit.span, &edit.text);
+    }
+    source
+}
+
+/// Parses a span written as `START:END`, for [`Cmd::Fmt`].
+fn parse_span(span: &str) -> Result<Span, String> {
+    let parse = |idx: &str| idx.trim().parse::<usize>().map_err(|e| format!("{e}: {idx}"));
+    match span.split_once(':') {
+        Some((start, end)) => match (parse(start)?, parse(end)?) {
+            (start, end) if start <= end => Ok(start..end),
+            _ => Err(format!("the start of the range is after its end: {span}")),
+        },
+        None => Err(format!("expected START:END, found: {span}")),
+    }
+}
+
+fn read_source(path: &str) -> Result<String> {
+    if path == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).context("could not read source from stdin")?;
+        Ok(source)
+    } else {
+        fs::read_to_string(path).with_context(|| format!("could not read source from file: {path}"))
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 { format!("{count} {noun}") } else { format!("{count} {noun}s") }
+}
+
+/// Reports lints for the program in `map`, and returns the number of warnings.
+/// Errors are left to the VM, which reports them when it runs the program.
+fn report_warnings(map: &SourceMap, options: &LintOptions) -> usize {
+    let Ok(program) = crate::syntax::parse(map.source(), 0) else { return 0 };
+    let warnings = crate::lint::lint(&program, options);
+    let count = warnings.len();
     let mut buffer = termcolor::Buffer::ansi();
-    for err in errors {
-        crate::error::report_error(&mut buffer, source, &err);
+    for (warning, span) in warnings {
+        let (name, source, span) = map.locate(&span);
+        crate::lint::report_warning(&mut buffer, name, source, &(warning, span));
     }
     io::stderr().write_all(buffer.as_slice()).expect("failed to write to stderr");
+    count
+}
+
+fn report_err(map: &SourceMap, errors: Vec<ErrorS>) {
+    let mut buffer = termcolor::Buffer::ansi();
+    for (error, span) in errors {
+        let (name, source, span) = map.locate(&span);
+        crate::error::report_file_error(&mut buffer, name, source, &(error, span));
+    }
+    io::stderr().write_all(buffer.as_slice()).expect("failed to write to stderr");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn lsp_flags() {
+        // Clients check the version of the server, and often pass --stdio.
+        let err = Cli::try_parse_from(["loxcraft", "lsp", "--version"]).unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::DisplayVersion);
+        assert!(Cli::try_parse_from(["loxcraft", "lsp", "--stdio"]).is_ok());
+        assert!(Cli::try_parse_from(["loxcraft", "lsp", "--stdio", "--tcp", "[::1]:0"]).is_err());
+    }
+
+    #[test]
+    fn inspect_addr() {
+        let addr = |addr: &str| addr.parse::<SocketAddr>().unwrap();
+        assert!(check_inspect_addr(addr("127.0.0.1:9229"), false).is_ok());
+        assert!(check_inspect_addr(addr("[::1]:9229"), false).is_ok());
+        assert!(check_inspect_addr(addr("0.0.0.0:9229"), false).is_err());
+        assert!(check_inspect_addr(addr("192.168.1.2:9229"), false).is_err());
+        assert!(check_inspect_addr(addr("0.0.0.0:9229"), true).is_ok());
+    }
+
+    #[test]
+    fn completions() {
+        let mut script = Vec::new();
+        clap_complete::generate(Shell::Zsh, &mut Cli::command(), "loxcraft", &mut script);
+        let script = String::from_utf8(script).unwrap();
+        // The file to run is completed from the paths of files.
+        let run_path =
+            script.lines().find(|line| line.starts_with("'::path -- Defaults to the entry"));
+        assert!(run_path.is_some_and(|line| line.ends_with(":_files' \\")), "{script}");
+    }
 }
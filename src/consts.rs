@@ -0,0 +1,117 @@
+//! Constant propagation: finding the values of expressions that can be known
+//! without running the program. The compiler folds these into its chunks and
+//! leaves out the branches that their conditions skip, and the linter warns
+//! about those conditions, so both see the same conditions as constant.
+
+use std::mem;
+
+use crate::syntax::ast::{Expr, ExprLiteral, ExprS, Name, OpInfix, OpPrefix};
+use crate::vm::Value;
+
+/// Evaluates `expr` if it is pure and cannot fail: literals, constants whose
+/// values are given by `consts`, and the operators that the VM would apply to
+/// them without an error. Returns [`None`] for anything else, which must then
+/// be left to run, so that any error still happens at runtime.
+pub fn eval(
+    (expr, _): &ExprS,
+    consts: &impl Fn(Name) -> Option<ExprLiteral>,
+) -> Option<ExprLiteral> {
+    use ExprLiteral::{Bool, Nil, Number, String};
+    match expr {
+        Expr::Literal(literal) => Some(literal.clone()),
+        Expr::Var(var) => consts(var.var.name),
+        Expr::Prefix(prefix) => match (prefix.op, eval(&prefix.rt, consts)?) {
+            (OpPrefix::Negate, Number(number)) => Some(Number(-number)),
+            (OpPrefix::Not, literal) => Some(Bool(!is_truthy(&literal))),
+            _ => None,
+        },
+        Expr::Infix(infix) => {
+            let lt = eval(&infix.lt, consts)?;
+            // With strict types, conditions must be bools, so `and` and `or`
+            // are only folded when that holds.
+            match (infix.op, &lt) {
+                (OpInfix::LogicAnd, Bool(true)) | (OpInfix::LogicOr, Bool(false)) => {
+                    return eval(&infix.rt, consts);
+                }
+                (OpInfix::LogicAnd | OpInfix::LogicOr, Bool(_)) => return Some(lt),
+                (OpInfix::LogicAnd | OpInfix::LogicOr | OpInfix::Is, _) => return None,
+                _ => {}
+            }
+            let rt = eval(&infix.rt, consts)?;
+            match (lt, infix.op, rt) {
+                (String(lt), OpInfix::Add, String(rt)) => Some(String(lt + &rt)),
+                (Number(lt), op, Number(rt)) => match op {
+                    OpInfix::Add => Some(Number(lt + rt)),
+                    OpInfix::Subtract => Some(Number(lt - rt)),
+                    OpInfix::Multiply => Some(Number(lt * rt)),
+                    OpInfix::Divide => Some(Number(lt / rt)),
+                    OpInfix::Modulo => Some(Number(lt % rt)),
+                    OpInfix::Power => Some(Number(lt.powf(rt))),
+                    OpInfix::Less => Some(Bool(lt < rt)),
+                    OpInfix::LessEqual => Some(Bool(lt <= rt)),
+                    OpInfix::Greater => Some(Bool(lt > rt)),
+                    OpInfix::GreaterEqual => Some(Bool(lt >= rt)),
+                    // The VM compares numbers by their bits.
+                    OpInfix::Equal => Some(Bool(Value::from(lt) == Value::from(rt))),
+                    OpInfix::NotEqual => Some(Bool(Value::from(lt) != Value::from(rt))),
+                    _ => None,
+                },
+                // With strict types, values of different types cannot be
+                // compared, except with nil.
+                (lt, OpInfix::Equal | OpInfix::NotEqual, rt)
+                    if mem::discriminant(&lt) == mem::discriminant(&rt)
+                        || lt == Nil
+                        || rt == Nil =>
+                {
+                    Some(Bool((lt == rt) == (infix.op == OpInfix::Equal)))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the condition `expr` always holds or never does, if it can
+/// be known without running the program. Only conditions that evaluate to a
+/// bool count, since with strict types any other value is an error.
+pub fn cond(expr: &ExprS, consts: &impl Fn(Name) -> Option<ExprLiteral>) -> Option<bool> {
+    match eval(expr, consts)? {
+        ExprLiteral::Bool(value) => Some(value),
+        _ => None,
+    }
+}
+
+pub fn is_truthy(literal: &ExprLiteral) -> bool {
+    !matches!(literal, ExprLiteral::Bool(false) | ExprLiteral::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn cond_source(source: &str) -> Option<bool> {
+        let program = crate::syntax::parse(&format!("print {source};"), 0).unwrap();
+        let crate::syntax::ast::Stmt::Print(print) = &program.stmts[0].0 else { unreachable!() };
+        let consts = |name: Name| (name.as_str() == "debug").then_some(ExprLiteral::Bool(false));
+        cond(&print.values[0], &consts)
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(cond_source("true"), Some(true));
+        assert_eq!(cond_source("1 < 2 and !false"), Some(true));
+        assert_eq!(cond_source("debug"), Some(false));
+        assert_eq!(cond_source("debug or \"a\" + \"b\" == \"ab\""), Some(true));
+        assert_eq!(cond_source("debug and x"), Some(false));
+
+        // Values that are not bools, and expressions that may fail or whose
+        // values are not known, are left to run.
+        assert_eq!(cond_source("1"), None);
+        assert_eq!(cond_source("x or true"), None);
+        assert_eq!(cond_source("1 < nil"), None);
+        assert_eq!(cond_source("nil and true"), None);
+    }
+}
@@ -0,0 +1,71 @@
+#![cfg(any(feature = "repl", feature = "playground"))]
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use anyhow::{Context, Result};
+
+/// Returns the directory in which loxcraft persists data across sessions,
+/// creating it if it doesn't already exist.
+pub fn data_dir() -> Result<PathBuf> {
+    let data_dir = dirs::data_dir().context("could not find data directory")?.join("loxcraft");
+    fs::create_dir_all(&data_dir)
+        .with_context(|| format!("could not create data directory: {}", data_dir.display()))?;
+    Ok(data_dir)
+}
+
+/// The environment variable that overrides the location of the history file.
+pub const HISTORY_ENV: &str = "LOXCRAFT_HISTORY";
+
+/// The name of a per-project history file. If a file with this name exists in
+/// the current directory, it is used instead of the global history file, so
+/// that history from unrelated projects doesn't get mixed together.
+pub const LOCAL_HISTORY_FILE: &str = ".lox_history";
+
+/// Returns the path of the REPL history file. In order of precedence, this is:
+/// - the path in the `LOXCRAFT_HISTORY` environment variable, if set.
+/// - `.lox_history` in the current directory, if it exists.
+/// - `history.txt` in the data directory.
+pub fn history_path() -> Result<PathBuf> {
+    if let Some(path) = env::var_os(HISTORY_ENV).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+
+    let local_path = Path::new(LOCAL_HISTORY_FILE);
+    if local_path.is_file() {
+        return Ok(local_path.to_path_buf());
+    }
+
+    Ok(data_dir()?.join("history.txt"))
+}
+
+/// Returns the path of the syntax highlighting theme, if one has been created.
+pub fn theme_path() -> Option<PathBuf> {
+    existing_data_file("theme.toml")
+}
+
+/// The name of the file in the data directory that configures the REPL.
+pub const CONFIG_FILE: &str = "repl.toml";
+
+/// Returns the path of the REPL configuration, if one has been created.
+pub fn config_path() -> Option<PathBuf> {
+    existing_data_file(CONFIG_FILE)
+}
+
+/// Returns the path of `name` in the data directory, if that file exists.
+/// Unlike [`data_dir`], this does not create the directory, so reading
+/// optional files works even where the directory cannot be created.
+fn existing_data_file(name: &str) -> Option<PathBuf> {
+    let path = dirs::data_dir()?.join("loxcraft").join(name);
+    path.is_file().then_some(path)
+}
+
+/// Returns the path of the file in which the playground keeps the settings of
+/// its editor, such as its theme and font.
+pub fn playground_settings_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("playground.json"))
+}
+
+pub fn stats_path() -> Result<PathBuf> {
+    Ok(data_dir()?.join("stats.json"))
+}
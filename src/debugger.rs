@@ -0,0 +1,439 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::ops::ControlFlow;
+use std::thread;
+
+use anyhow::{Context, Result};
+
+use crate::error::ErrorS;
+use crate::vm::{DebugHook, Debugger, VM, VmOptions, inspect};
+
+/// The commands understood at the debugger prompt, with their usage.
+const COMMANDS: &[(&str, &str)] = &[
+    ("step, s", "run to the next line, stepping into calls"),
+    ("next, n", "run to the next line, stepping over calls"),
+    ("finish, f", "run until the current function returns"),
+    ("continue, c", "run until the next breakpoint"),
+    ("break, b [LINE]", "set a breakpoint, or list the breakpoints"),
+    ("delete, d LINE", "remove a breakpoint"),
+    ("locals, l", "show the stack slots of the current function"),
+    ("backtrace, bt", "show the functions that are running"),
+    ("print, p NAME", "show the value of a global"),
+    ("globals, g", "show the globals defined so far"),
+    ("eval, e EXPR", "evaluate an expression in the current function"),
+    ("help, h", "show this list of commands"),
+    ("quit, q", "stop the program"),
+];
+
+/// Runs `source` under the debugger, reading commands from stdin each time
+/// it pauses. Program output goes to stdout, and the prompt to stderr.
+pub fn run(
+    source: &str,
+    breakpoints: &[usize],
+    options: VmOptions,
+) -> Result<Result<(), Vec<ErrorS>>> {
+    let mut vm = VM::new(options);
+    let mut prompt = Prompt::new(source, io::stdin().lock(), io::stderr().lock());
+    for &line in breakpoints {
+        prompt.debugger.add_breakpoint(line);
+    }
+    if !breakpoints.is_empty() {
+        prompt.debugger.continue_();
+    }
+    let result = vm.run_with_hook(source, &mut prompt, &mut io::stdout().lock());
+    if let Some(e) = prompt.error {
+        return Err(e).context("could not read debugger command");
+    }
+    Ok(result)
+}
+
+/// How many ops run between checks for a client, and for commands from it,
+/// while the program is running under [`serve`].
+const POLL_INTERVAL: u32 = 1024;
+
+/// Runs `source` like [`VM::run`], while listening on `addr` for a client,
+/// such as `lox attach`, that can pause the program and run debugger
+/// commands. The program runs as usual until a client sends `pause`, or
+/// until it reaches a breakpoint set by a client. Program output goes to
+/// `stdout`, and the prompt to the client.
+pub fn serve(
+    vm: &mut VM,
+    source: &str,
+    addr: SocketAddr,
+    stdout: &mut impl Write,
+) -> Result<Result<(), Vec<ErrorS>>> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("could not listen on {addr}"))?;
+    listener.set_nonblocking(true).context("could not listen for clients")?;
+    eprintln!("inspector listening on {}", listener.local_addr()?);
+    let mut inspector = Inspector { source, listener, client: None, ops: 0 };
+    Ok(vm.run_with_hook(source, &mut inspector, stdout))
+}
+
+/// Connects to a program run with `lox run --inspect` at `addr`, and relays
+/// commands from stdin to it, and its replies to stdout, until the program
+/// ends.
+pub fn attach(addr: &str) -> Result<()> {
+    let mut stream =
+        TcpStream::connect(addr).with_context(|| format!("could not connect to {addr}"))?;
+    let mut writer = stream.try_clone().context("could not connect to program")?;
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if writeln!(writer, "{line}").is_err() {
+                return;
+            }
+        }
+        // Detach, leaving the program running.
+        let _ = writer.shutdown(Shutdown::Write);
+    });
+
+    // Replies are copied as they arrive, since prompts do not end in a
+    // newline.
+    let mut stdout = io::stdout().lock();
+    let mut buf = [0; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(len) => {
+                stdout.write_all(&buf[..len])?;
+                stdout.flush()?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("lost connection to program"),
+        }
+    }
+}
+
+/// A [`DebugHook`] that serves one client at a time over TCP.
+struct Inspector<'a> {
+    source: &'a str,
+    listener: TcpListener,
+    client: Option<Prompt<'a, BufReader<TcpStream>, TcpStream>>,
+    /// The number of ops run since the last poll.
+    ops: u32,
+}
+
+impl Inspector<'_> {
+    /// Accepts a client if there is none, or otherwise runs the command that
+    /// it sent while the program was running, if any.
+    fn poll(&mut self, vm: &mut VM) -> io::Result<ControlFlow<()>> {
+        let Some(prompt) = &mut self.client else {
+            match self.listener.accept() {
+                Ok((stream, _)) => self.connect(stream)?,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+            return Ok(ControlFlow::Continue(()));
+        };
+
+        if prompt.input.buffer().is_empty() {
+            let stream = prompt.input.get_ref();
+            stream.set_nonblocking(true)?;
+            let pending = stream.peek(&mut [0]);
+            stream.set_nonblocking(false)?;
+            match pending {
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(ControlFlow::Continue(()));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let mut command = String::new();
+        if prompt.input.read_line(&mut command)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        match command.trim() {
+            // The debugger pauses at the next statement, as after a step.
+            "pause" => prompt.debugger.step(),
+            "" => {}
+            command => {
+                if let Some(ControlFlow::Break(())) = prompt.run_command(vm, command)? {
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    fn connect(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        let input = BufReader::new(stream.try_clone()?);
+        let mut prompt = Prompt::new(self.source, input, stream);
+        prompt.debugger.continue_();
+        writeln!(prompt.output, "attached; send pause to pause the program, or help")?;
+        self.client = Some(prompt);
+        Ok(())
+    }
+}
+
+impl DebugHook for Inspector<'_> {
+    fn before_op(&mut self, vm: &mut VM) -> ControlFlow<()> {
+        self.ops += 1;
+        if self.ops >= POLL_INTERVAL {
+            self.ops = 0;
+            match self.poll(vm) {
+                Ok(ControlFlow::Continue(())) => {}
+                Ok(ControlFlow::Break(())) => return ControlFlow::Break(()),
+                // The program carries on without the client.
+                Err(_) => self.client = None,
+            }
+        }
+        let Some(prompt) = &mut self.client else {
+            return ControlFlow::Continue(());
+        };
+        let flow = prompt.before_op(vm);
+        if prompt.is_closed || prompt.error.is_some() {
+            self.client = None;
+            return ControlFlow::Continue(());
+        }
+        flow
+    }
+}
+
+/// A [`DebugHook`] that asks for commands whenever the [`Debugger`] pauses.
+struct Prompt<'a, R, W> {
+    debugger: Debugger,
+    lines: Vec<&'a str>,
+    input: R,
+    output: W,
+    /// The last command run, which an empty line runs again.
+    last_command: String,
+    /// The error that stopped the program, if reading a command failed.
+    error: Option<io::Error>,
+    /// Set once the input has ended, which stops the program.
+    is_closed: bool,
+}
+
+impl<'a, R: BufRead, W: Write> Prompt<'a, R, W> {
+    fn new(source: &'a str, input: R, output: W) -> Self {
+        Self {
+            debugger: Debugger::new(source),
+            lines: source.lines().collect(),
+            input,
+            output,
+            last_command: String::new(),
+            error: None,
+            is_closed: false,
+        }
+    }
+
+    /// Shows where the program paused, and runs commands until one of them
+    /// resumes it.
+    fn pause(&mut self, vm: &mut VM, line: usize) -> io::Result<ControlFlow<()>> {
+        self.show_line(line)?;
+        loop {
+            write!(self.output, "(lox) ")?;
+            self.output.flush()?;
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                self.is_closed = true;
+                return Ok(ControlFlow::Break(()));
+            }
+            let command = match command.trim() {
+                "" => self.last_command.clone(),
+                command => command.to_string(),
+            };
+            self.last_command.clone_from(&command);
+            if let Some(flow) = self.run_command(vm, &command)? {
+                return Ok(flow);
+            }
+        }
+    }
+
+    /// Runs a command. Returns what the program should do next, if the
+    /// command resumes or stops it.
+    fn run_command(&mut self, vm: &mut VM, command: &str) -> io::Result<Option<ControlFlow<()>>> {
+        let (name, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        match (name, arg.trim()) {
+            ("step" | "s", "") => self.debugger.step(),
+            ("next" | "n", "") => self.debugger.next(),
+            ("finish" | "f", "") => self.debugger.finish(),
+            ("continue" | "c", "") => self.debugger.continue_(),
+            ("quit" | "q", "") => return Ok(Some(ControlFlow::Break(()))),
+            ("break" | "b", "") => {
+                let lines = self.debugger.breakpoints().map(|line| line.to_string());
+                match lines.collect::<Vec<_>>().join(", ") {
+                    lines if lines.is_empty() => writeln!(self.output, "no breakpoints")?,
+                    lines => writeln!(self.output, "breakpoints: {lines}")?,
+                }
+                return Ok(None);
+            }
+            ("break" | "b" | "delete" | "d", line) => {
+                let Ok(line) = line.parse::<usize>() else {
+                    writeln!(self.output, "not a line number: {line}")?;
+                    return Ok(None);
+                };
+                let changed = if matches!(name, "break" | "b") {
+                    self.debugger.add_breakpoint(line)
+                } else {
+                    self.debugger.remove_breakpoint(line)
+                };
+                if !changed {
+                    let has = if matches!(name, "break" | "b") { "already has" } else { "has no" };
+                    writeln!(self.output, "line {line} {has} a breakpoint")?;
+                }
+                return Ok(None);
+            }
+            ("locals" | "l", "") => {
+                if let Some(frame) = vm.current_frame() {
+                    for (slot, &value) in frame.slots.iter().enumerate() {
+                        writeln!(self.output, "{slot:>4}: {}", inspect(value))?;
+                    }
+                }
+                return Ok(None);
+            }
+            ("backtrace" | "bt", "") => {
+                for frame in vm.debug_frames() {
+                    let line = self.debugger.line_of(frame.span.start);
+                    writeln!(self.output, "  {} at line {line}", frame.name)?;
+                }
+                return Ok(None);
+            }
+            ("print" | "p", name) if !name.is_empty() => {
+                let globals = vm.defined_globals();
+                match globals.iter().find(|&&(global, _)| global == name) {
+                    Some(&(_, value)) => writeln!(self.output, "{}", inspect(value))?,
+                    None => writeln!(self.output, "no global named {name}")?,
+                }
+                return Ok(None);
+            }
+            ("globals" | "g", "") => {
+                for (name, value) in vm.defined_globals() {
+                    writeln!(self.output, "{name} = {}", inspect(value))?;
+                }
+                return Ok(None);
+            }
+            ("eval" | "e", source) if !source.is_empty() => {
+                // Anything that the expression prints goes to the prompt.
+                let source = format!("{};", source.trim_end_matches(';'));
+                match vm.eval_in_frame(0, &source, &mut self.output) {
+                    Ok(value) => writeln!(self.output, "{}", inspect(value))?,
+                    Err(errors) => {
+                        for (error, _) in errors {
+                            writeln!(self.output, "{error}")?;
+                        }
+                    }
+                }
+                return Ok(None);
+            }
+            ("help" | "h", "") => {
+                for (usage, description) in COMMANDS {
+                    writeln!(self.output, "{usage:<18}{description}")?;
+                }
+                return Ok(None);
+            }
+            _ => {
+                writeln!(self.output, "unknown command: {command}, see help")?;
+                return Ok(None);
+            }
+        }
+        Ok(Some(ControlFlow::Continue(())))
+    }
+
+    fn show_line(&mut self, line: usize) -> io::Result<()> {
+        let text = self.lines.get(line - 1).copied().unwrap_or_default();
+        writeln!(self.output, "{line:>4} | {text}")
+    }
+}
+
+impl<R: BufRead, W: Write> DebugHook for Prompt<'_, R, W> {
+    fn before_op(&mut self, vm: &mut VM) -> ControlFlow<()> {
+        let Some(frame) = vm.current_frame() else {
+            return ControlFlow::Continue(());
+        };
+        let Some(line) = self.debugger.should_pause(&frame) else {
+            return ControlFlow::Continue(());
+        };
+        match self.pause(vm, line) {
+            Ok(flow) => flow,
+            Err(e) => {
+                self.error = Some(e);
+                ControlFlow::Break(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Debugs `source`, answering each pause with the next of `commands`, and
+    /// returns the debugger output followed by the program output.
+    fn debug(source: &str, breakpoints: &[usize], commands: &str) -> (String, String) {
+        let mut output = Vec::new();
+        let mut stdout = Vec::new();
+        let mut prompt = Prompt::new(source, commands.as_bytes(), &mut output);
+        for &line in breakpoints {
+            prompt.debugger.add_breakpoint(line);
+        }
+        if !breakpoints.is_empty() {
+            prompt.debugger.continue_();
+        }
+        VM::default().run_with_hook(source, &mut prompt, &mut stdout).unwrap();
+        (String::from_utf8(output).unwrap(), String::from_utf8(stdout).unwrap())
+    }
+
+    const SOURCE: &str = "\
+fun add(a, b) {
+  var sum = a + b;
+  return sum;
+}
+var x = add(1, 2);
+print x;
+print add(x, 4);
+";
+
+    #[test]
+    fn step_and_next() {
+        let (output, stdout) = debug(SOURCE, &[], "s\ns\nlocals\nn\n\nbt\nc\n");
+        assert_eq!(
+            output,
+            "   1 | fun add(a, b) {
+(lox)    5 | var x = add(1, 2);
+(lox)    2 |   var sum = a + b;
+(lox)    0: <function add arity=2>
+   1: 1
+   2: 2
+(lox)    3 |   return sum;
+(lox)    6 | print x;
+(lox)   <script> at line 6
+(lox) "
+        );
+        assert_eq!(stdout, "3\n7\n");
+    }
+
+    #[test]
+    fn eval() {
+        let (output, stdout) = debug(SOURCE, &[3], "e sum * 2\ne print a;\ne x\nq\n");
+        assert_eq!(
+            output,
+            "   3 |   return sum;
+(lox) 6
+(lox) 1
+nil
+(lox) NameError: name \"x\" is not defined
+(lox) "
+        );
+        assert_eq!(stdout, "");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (output, stdout) = debug(SOURCE, &[3], "p x\nc\nb 6\nb\np x\nfinish\n");
+        assert_eq!(
+            output,
+            "   3 |   return sum;
+(lox) no global named x
+(lox)    3 |   return sum;
+(lox) (lox) breakpoints: 3, 6
+(lox) 3
+(lox) "
+        );
+        assert_eq!(stdout, "3\n7\n");
+    }
+}
@@ -3,18 +3,25 @@ use std::io;
 use codespan_reporting::diagnostic::{Diagnostic, Label};
 use codespan_reporting::files::SimpleFile;
 use codespan_reporting::term;
+use serde::{Deserialize, Serialize};
 use termcolor::WriteColor;
 use thiserror::Error;
 
-use crate::types::{Span, Spanned};
+use crate::types::{self, Span, Spanned};
 
 pub type Result<T, E = ErrorS> = std::result::Result<T, E>;
 pub type ErrorS = Spanned<Error>;
 
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
 pub enum Error {
+    #[error("AssertionError: {0}")]
+    AssertionError(AssertionError),
     #[error("AttributeError: {0}")]
     AttributeError(AttributeError),
+    #[error("BytecodeError: {0}")]
+    BytecodeError(BytecodeError),
+    #[error("IndexError: {0}")]
+    IndexError(IndexError),
     #[error("IOError: {0}")]
     IoError(IoError),
     #[error("NameError: {0}")]
@@ -27,10 +34,92 @@ pub enum Error {
     TypeError(TypeError),
 }
 
+impl Error {
+    /// Returns the name of the error type, e.g. `"TypeError"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Error::AssertionError(_) => "AssertionError",
+            Error::AttributeError(_) => "AttributeError",
+            Error::BytecodeError(_) => "BytecodeError",
+            Error::IndexError(_) => "IndexError",
+            Error::IoError(_) => "IOError",
+            Error::NameError(_) => "NameError",
+            Error::OverflowError(_) => "OverflowError",
+            Error::SyntaxError(_) => "SyntaxError",
+            Error::TypeError(_) => "TypeError",
+        }
+    }
+}
+
+impl Error {
+    /// Returns the stable code of the error, e.g. `"E0202"`. Each type of error
+    /// has its own block of a hundred codes, numbered in the order of its
+    /// variants. Codes are never reused, so new variants get the next free code
+    /// in their block, wherever they are declared. Run `lox explain` with a
+    /// code for an explanation of the error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::AssertionError(e) => e.code(),
+            Error::AttributeError(e) => e.code(),
+            Error::BytecodeError(e) => e.code(),
+            Error::IndexError(e) => e.code(),
+            Error::IoError(e) => e.code(),
+            Error::NameError(e) => e.code(),
+            Error::OverflowError(e) => e.code(),
+            Error::SyntaxError(e) => e.code(),
+            Error::TypeError(e) => e.code(),
+        }
+    }
+
+    /// Returns the phase that raises the error. An undefined variable is a
+    /// [`Category::Resolve`] error, although it is only found at runtime
+    /// outside of strict mode.
+    pub fn category(&self) -> Category {
+        match self {
+            Error::SyntaxError(
+                SyntaxError::ExtendNotEnabled
+                | SyntaxError::ReturnInInitializer
+                | SyntaxError::ReturnOutsideFunction
+                | SyntaxError::SuperOutsideClass
+                | SyntaxError::SuperWithoutSuperclass
+                | SyntaxError::ThisOutsideClass,
+            ) => Category::Resolve,
+            Error::SyntaxError(_) => Category::Parse,
+            Error::NameError(_) => Category::Resolve,
+            Error::OverflowError(OverflowError::StackOverflow) => Category::Runtime,
+            Error::OverflowError(_) => Category::Resolve,
+            Error::AssertionError(_)
+            | Error::AttributeError(_)
+            | Error::BytecodeError(_)
+            | Error::IndexError(_)
+            | Error::IoError(_)
+            | Error::TypeError(_) => Category::Runtime,
+        }
+    }
+
+    /// Returns the message of the error, without the name of its type.
+    pub fn message(&self) -> String {
+        match self {
+            Error::AssertionError(e) => e.to_string(),
+            Error::AttributeError(e) => e.to_string(),
+            Error::BytecodeError(e) => e.to_string(),
+            Error::IndexError(e) => e.to_string(),
+            Error::IoError(e) => e.to_string(),
+            Error::NameError(e) => e.to_string(),
+            Error::OverflowError(e) => e.to_string(),
+            Error::SyntaxError(e) => e.to_string(),
+            Error::TypeError(e) => e.to_string(),
+        }
+    }
+}
+
 impl AsDiagnostic for Error {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         match self {
+            Error::AssertionError(e) => e.as_diagnostic(span),
             Error::AttributeError(e) => e.as_diagnostic(span),
+            Error::BytecodeError(e) => e.as_diagnostic(span),
+            Error::IndexError(e) => e.as_diagnostic(span),
             Error::IoError(e) => e.as_diagnostic(span),
             Error::NameError(e) => e.as_diagnostic(span),
             Error::OverflowError(e) => e.as_diagnostic(span),
@@ -40,6 +129,34 @@ impl AsDiagnostic for Error {
     }
 }
 
+/// The phase that finds a problem in a program, so that editors can tell
+/// diagnostics apart by it.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    /// The source does not parse.
+    Parse,
+    /// The program parses, but does not compile, e.g. because it uses `this`
+    /// outside of a class.
+    Resolve,
+    /// A warning from [`lint`](crate::lint).
+    Lint,
+    /// The program fails while it runs.
+    Runtime,
+}
+
+impl Category {
+    /// The name shown as the source of a diagnostic in an editor.
+    pub fn source(self) -> &'static str {
+        match self {
+            Category::Parse => "lox-parser",
+            Category::Resolve => "lox-resolver",
+            Category::Lint => "lox-linter",
+            Category::Runtime => "lox-runtime",
+        }
+    }
+}
+
 macro_rules! impl_from_error {
     ($($error:tt),+) => {$(
         impl From<$error> for Error {
@@ -50,60 +167,236 @@ macro_rules! impl_from_error {
     )+};
 }
 
-impl_from_error!(AttributeError, IoError, NameError, OverflowError, SyntaxError, TypeError);
+impl_from_error!(
+    AssertionError,
+    AttributeError,
+    BytecodeError,
+    IndexError,
+    IoError,
+    NameError,
+    OverflowError,
+    SyntaxError,
+    TypeError
+);
 
-#[derive(Debug, Error, Eq, PartialEq)]
+/// Raised by `assert`. `expr` is the source of the asserted expression, and
+/// `lt` and `rt` are the operands of a comparison, as shown by `inspect()`.
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
+pub enum AssertionError {
+    #[error("assertion failed: {expr}")]
+    Failed { expr: String },
+    #[error("assertion failed: {expr} (left: {lt}, right: {rt})")]
+    FailedInfix { expr: String, lt: String, rt: String },
+}
+
+impl AssertionError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AssertionError::Failed { .. } => "E0501",
+            AssertionError::FailedInfix { .. } => "E0502",
+        }
+    }
+}
+
+impl AsDiagnostic for AssertionError {
+    fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
+        Diagnostic::error()
+            .with_code(self.code())
+            .with_message(self.to_string())
+            .with_labels(vec![Label::primary((), span.clone())])
+    }
+}
+
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
 pub enum AttributeError {
     #[error("{type_:?} object has no attribute {name:?}")]
     NoSuchAttribute { type_: String, name: String },
 }
 
+impl AttributeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AttributeError::NoSuchAttribute { .. } => "E0301",
+        }
+    }
+}
+
 impl AsDiagnostic for AttributeError {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         Diagnostic::error()
-            .with_code("AttributeError")
+            .with_code(self.code())
+            .with_message(self.to_string())
+            .with_labels(vec![Label::primary((), span.clone())])
+    }
+}
+
+/// Problems found by [`verify`](crate::vm::verify), which checks bytecode
+/// before it runs, and by [`VM::run_bytecode`](crate::vm::VM::run_bytecode),
+/// which decodes it. Offsets are into the ops of the function being checked.
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
+pub enum BytecodeError {
+    #[error("constant index {idx} out of bounds at offset {offset}")]
+    ConstantOutOfBounds { offset: usize, idx: usize },
+    #[error("constant {idx} at offset {offset} should be of type {exp_type:?}, not {got_type:?}")]
+    ConstantTypeMismatch { offset: usize, idx: usize, exp_type: String, got_type: String },
+    #[error("entry point at offset {offset} does not land on an op")]
+    InvalidEntry { offset: usize },
+    #[error("jump at offset {offset} does not land on an op")]
+    InvalidJump { offset: usize },
+    #[error("unknown op {op:#04x} at offset {offset}")]
+    InvalidOp { offset: usize, op: u8 },
+    #[error("local slot {slot} out of bounds at offset {offset}")]
+    LocalOutOfBounds { offset: usize, slot: usize },
+    #[error("compiled program is truncated or malformed")]
+    Malformed,
+    #[error("function {name:?} can reach the end of its chunk without returning")]
+    MissingReturn { name: String },
+    #[error("native index {idx} out of bounds at offset {offset}")]
+    NativeOutOfBounds { offset: usize, idx: usize },
+    #[error("op at offset {offset} is reached with stack depths {depth1} and {depth2}")]
+    StackMismatch { offset: usize, depth1: usize, depth2: usize },
+    #[error("compiled program does not match its source")]
+    SourceMismatch,
+    #[error("stack exceeds {max} values at offset {offset}")]
+    StackOverflow { offset: usize, max: usize },
+    #[error("stack underflow at offset {offset}")]
+    StackUnderflow { offset: usize },
+    #[error("op at offset {offset} is missing its operands")]
+    Truncated { offset: usize },
+    #[error("upvalue index {idx} out of bounds at offset {offset}")]
+    UpvalueOutOfBounds { offset: usize, idx: usize },
+    #[error("compiled program is from loxcraft {version}, not {}", env!("CARGO_PKG_VERSION"))]
+    VersionMismatch { version: String },
+}
+
+impl BytecodeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            BytecodeError::ConstantOutOfBounds { .. } => "E0801",
+            BytecodeError::ConstantTypeMismatch { .. } => "E0802",
+            BytecodeError::InvalidJump { .. } => "E0803",
+            BytecodeError::InvalidOp { .. } => "E0804",
+            BytecodeError::LocalOutOfBounds { .. } => "E0805",
+            BytecodeError::Malformed => "E0806",
+            BytecodeError::MissingReturn { .. } => "E0807",
+            BytecodeError::NativeOutOfBounds { .. } => "E0808",
+            BytecodeError::StackMismatch { .. } => "E0809",
+            BytecodeError::SourceMismatch => "E0810",
+            BytecodeError::StackOverflow { .. } => "E0811",
+            BytecodeError::StackUnderflow { .. } => "E0812",
+            BytecodeError::Truncated { .. } => "E0813",
+            BytecodeError::UpvalueOutOfBounds { .. } => "E0814",
+            BytecodeError::VersionMismatch { .. } => "E0815",
+            BytecodeError::InvalidEntry { .. } => "E0816",
+        }
+    }
+}
+
+impl AsDiagnostic for BytecodeError {
+    fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
+        Diagnostic::error()
+            .with_code(self.code())
+            .with_message(self.to_string())
+            .with_labels(vec![Label::primary((), span.clone())])
+    }
+}
+
+/// Raised when indexing into a string, a list or a map. Strings are indexed by
+/// Unicode scalar values, not bytes.
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
+pub enum IndexError {
+    #[error("key {key} is not in the map")]
+    KeyNotFound { key: String },
+    #[error("{type_} index should be an integer, not {index}")]
+    NotAnInteger { type_: String, index: String },
+    #[error("{type_} index {index} is out of range for length {len}")]
+    OutOfRange { type_: String, index: String, len: usize },
+}
+
+impl IndexError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            IndexError::KeyNotFound { .. } => "E0403",
+            IndexError::NotAnInteger { .. } => "E0401",
+            IndexError::OutOfRange { .. } => "E0402",
+        }
+    }
+}
+
+impl AsDiagnostic for IndexError {
+    fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
+        Diagnostic::error()
+            .with_code(self.code())
             .with_message(self.to_string())
             .with_labels(vec![Label::primary((), span.clone())])
     }
 }
 
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
 pub enum IoError {
+    #[error("output limit of {limit} bytes exceeded")]
+    OutputLimitExceeded { limit: usize },
     #[error("unable to write to file: {file:?}")]
     WriteError { file: String },
+    #[error("unable to read file: {file:?}")]
+    ReadError { file: String },
+}
+
+impl IoError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            IoError::OutputLimitExceeded { .. } => "E0701",
+            IoError::WriteError { .. } => "E0702",
+            IoError::ReadError { .. } => "E0703",
+        }
+    }
 }
 
 impl AsDiagnostic for IoError {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         Diagnostic::error()
-            .with_code("IOError")
+            .with_code(self.code())
             .with_message(self.to_string())
             .with_labels(vec![Label::primary((), span.clone())])
     }
 }
 
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
 pub enum NameError {
     #[error("cannot access variable {name:?} in its own initializer")]
     AccessInsideInitializer { name: String },
     #[error("name {name:?} is already defined")]
     AlreadyDefined { name: String },
+    #[error("cannot assign to constant {name:?}")]
+    AssignToConst { name: String },
     #[error("class {name:?} inherits from itself")]
     ClassInheritFromSelf { name: String },
     #[error("name {name:?} is not defined")]
     NotDefined { name: String },
 }
 
+impl NameError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            NameError::AccessInsideInitializer { .. } => "E0101",
+            NameError::AlreadyDefined { .. } => "E0102",
+            NameError::AssignToConst { .. } => "E0103",
+            NameError::ClassInheritFromSelf { .. } => "E0104",
+            NameError::NotDefined { .. } => "E0105",
+        }
+    }
+}
+
 impl AsDiagnostic for NameError {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         Diagnostic::error()
-            .with_code("NameError")
+            .with_code(self.code())
             .with_message(self.to_string())
             .with_labels(vec![Label::primary((), span.clone())])
     }
 }
 
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
 pub enum OverflowError {
     #[error("jump body is too large")]
     JumpTooLarge,
@@ -113,6 +406,8 @@ pub enum OverflowError {
     TooManyArgs,
     #[error("cannot define more than 256 constants in a function")]
     TooManyConstants,
+    #[error("cannot use more than 255 elements in a list or map literal")]
+    TooManyElements,
     #[error("cannot define more than 256 local variables in a function")]
     TooManyLocals,
     #[error("cannot define more than 256 parameters in a function")]
@@ -121,19 +416,46 @@ pub enum OverflowError {
     TooManyUpvalues,
 }
 
+impl OverflowError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            OverflowError::JumpTooLarge => "E0601",
+            OverflowError::StackOverflow => "E0602",
+            OverflowError::TooManyArgs => "E0603",
+            OverflowError::TooManyConstants => "E0604",
+            OverflowError::TooManyElements => "E0608",
+            OverflowError::TooManyLocals => "E0605",
+            OverflowError::TooManyParams => "E0606",
+            OverflowError::TooManyUpvalues => "E0607",
+        }
+    }
+}
+
 impl AsDiagnostic for OverflowError {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         Diagnostic::error()
-            .with_code("OverflowError")
+            .with_code(self.code())
             .with_message(self.to_string())
             .with_labels(vec![Label::primary((), span.clone())])
     }
 }
 
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
 pub enum SyntaxError {
+    #[error("{keyword:?} declaration cannot be the body of a statement")]
+    DeclarationInBody { keyword: String },
     #[error("extraneous input: {token:?}")]
     ExtraToken { token: String },
+    #[error("invalid escape sequence: {escape}")]
+    InvalidEscape { escape: String },
+    #[error("invalid number literal: {token:?}")]
+    InvalidNumber { token: String },
+    #[error("expected a parameter name")]
+    InvalidParam,
+    #[error(r#""extend" is only allowed with class extensions enabled"#)]
+    ExtendNotEnabled,
+    #[error("parameter {name:?} without a default value follows one with a default value")]
+    ParamWithoutDefault { name: String },
     #[error("invalid input")]
     InvalidToken,
     #[error(r#"init() should not return a value"#)]
@@ -146,23 +468,65 @@ pub enum SyntaxError {
     SuperWithoutSuperclass,
     #[error(r#""this" used outside class"#)]
     ThisOutsideClass,
+    /// Spans the first statement or expression that is nested too deeply. See
+    /// [`ParseOptions::max_depth`](crate::syntax::ParseOptions).
+    #[error("expression too deeply nested")]
+    TooDeeplyNested,
     #[error("unexpected input")]
     UnexpectedInput { token: String },
     #[error("unexpected end of file")]
     UnrecognizedEof { expected: Vec<String> },
     #[error("unexpected {token:?}")]
     UnrecognizedToken { token: String, expected: Vec<String> },
+    /// Spans from the opening quote to the end of that line, or the end of the
+    /// file if there is no newline after it.
     #[error("unterminated string")]
     UnterminatedString,
 }
 
+impl SyntaxError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SyntaxError::DeclarationInBody { .. } => "E0015",
+            SyntaxError::ExtendNotEnabled => "E0018",
+            SyntaxError::ExtraToken { .. } => "E0001",
+            SyntaxError::InvalidEscape { .. } => "E0002",
+            SyntaxError::InvalidNumber { .. } => "E0003",
+            SyntaxError::InvalidParam => "E0004",
+            SyntaxError::ParamWithoutDefault { .. } => "E0016",
+            SyntaxError::InvalidToken => "E0005",
+            SyntaxError::ReturnInInitializer => "E0006",
+            SyntaxError::ReturnOutsideFunction => "E0007",
+            SyntaxError::SuperOutsideClass => "E0008",
+            SyntaxError::SuperWithoutSuperclass => "E0009",
+            SyntaxError::ThisOutsideClass => "E0010",
+            SyntaxError::TooDeeplyNested => "E0017",
+            SyntaxError::UnexpectedInput { .. } => "E0011",
+            SyntaxError::UnrecognizedEof { .. } => "E0012",
+            SyntaxError::UnrecognizedToken { .. } => "E0013",
+            SyntaxError::UnterminatedString => "E0014",
+        }
+    }
+}
+
 impl AsDiagnostic for SyntaxError {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         let mut diagnostic = Diagnostic::error()
-            .with_code("SyntaxError")
+            .with_code(self.code())
             .with_message(self.to_string())
             .with_labels(vec![Label::primary((), span.clone())]);
         match self {
+            SyntaxError::DeclarationInBody { keyword } => {
+                diagnostic =
+                    diagnostic.with_notes(vec![format!("put it in braces: {{ {keyword} ... }}")]);
+            }
+            SyntaxError::InvalidNumber { token } if token.contains([',', '.']) => {
+                diagnostic = diagnostic.with_notes(vec![
+                    "use a dot as the decimal separator, and underscores to group digits, as in \
+                     1_234.5"
+                        .to_string(),
+                ]);
+            }
             SyntaxError::UnrecognizedEof { expected, .. }
             | SyntaxError::UnrecognizedToken { expected, .. } => {
                 diagnostic = diagnostic.with_notes(vec![format!("expected: {}", one_of(expected))]);
@@ -173,26 +537,72 @@ impl AsDiagnostic for SyntaxError {
     }
 }
 
-#[derive(Debug, Error, Eq, PartialEq)]
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
 pub enum TypeError {
+    #[error("{name}() argument {pos} must be of type {exp_type:?}, not {got_type:?}")]
+    ArgTypeMismatch { name: String, pos: usize, exp_type: String, got_type: String },
     #[error("{name}() takes {exp_args} arguments but {got_args} were given")]
     ArityMismatch { name: String, exp_args: usize, got_args: usize },
+    #[error("{name}() takes from {min_args} to {max_args} arguments but {got_args} were given")]
+    ArityRangeMismatch { name: String, min_args: usize, max_args: usize, got_args: usize },
+    #[error(r#"condition should be of type "bool", not {type_:?}"#)]
+    ConditionInvalidType { type_: String },
+    #[error(r#"extended value should be of type "class", not {type_:?}"#)]
+    ExtendInvalidType { type_: String },
+    #[error("format string takes {exp_args} arguments but {got_args} were given")]
+    FormatArityMismatch { exp_args: usize, got_args: usize },
     #[error("init() should use an empty return, not {type_:?}")]
     InitInvalidReturnType { type_: String },
+    #[error("invalid format specifier: {spec:?}")]
+    InvalidFormatSpec { spec: String },
+    #[error("main() should return an integer or nil, not {value}")]
+    MainInvalidReturn { value: String },
     #[error("{type_:?} object is not callable")]
     NotCallable { type_: String },
+    #[error("{type_:?} object does not support index assignment")]
+    NotIndexAssignable { type_: String },
+    #[error("{type_:?} object is not indexable")]
+    NotIndexable { type_: String },
     #[error(r#"superclass should be of type "class", not {type_:?}"#)]
     SuperclassInvalidType { type_: String },
+    #[error("{name}() takes at least {exp_args} arguments but {got_args} were given")]
+    TooFewArgs { name: String, exp_args: usize, got_args: usize },
+    #[error("method {name}() should be bound to an instance before it is called")]
+    UnboundMethodCall { name: String },
     #[error("unsupported operand type(s) for {op}: {lt_type:?} and {rt_type:?}")]
     UnsupportedOperandInfix { op: String, lt_type: String, rt_type: String },
     #[error("unsupported operand type for {op}: {rt_type:?}")]
     UnsupportedOperandPrefix { op: String, rt_type: String },
 }
 
+impl TypeError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeError::ArgTypeMismatch { .. } => "E0201",
+            TypeError::ArityMismatch { .. } => "E0202",
+            TypeError::ArityRangeMismatch { .. } => "E0213",
+            TypeError::ConditionInvalidType { .. } => "E0203",
+            TypeError::ExtendInvalidType { .. } => "E0216",
+            TypeError::FormatArityMismatch { .. } => "E0204",
+            TypeError::InitInvalidReturnType { .. } => "E0205",
+            TypeError::InvalidFormatSpec { .. } => "E0206",
+            TypeError::MainInvalidReturn { .. } => "E0207",
+            TypeError::NotCallable { .. } => "E0208",
+            TypeError::NotIndexAssignable { .. } => "E0215",
+            TypeError::NotIndexable { .. } => "E0214",
+            TypeError::SuperclassInvalidType { .. } => "E0209",
+            TypeError::TooFewArgs { .. } => "E0210",
+            TypeError::UnboundMethodCall { .. } => "E0217",
+            TypeError::UnsupportedOperandInfix { .. } => "E0211",
+            TypeError::UnsupportedOperandPrefix { .. } => "E0212",
+        }
+    }
+}
+
 impl AsDiagnostic for TypeError {
     fn as_diagnostic(&self, span: &Span) -> Diagnostic<()> {
         Diagnostic::error()
-            .with_code("TypeError")
+            .with_code(self.code())
             .with_message(self.to_string())
             .with_labels(vec![Label::primary((), span.clone())])
     }
@@ -205,6 +615,7 @@ trait AsDiagnostic {
 fn one_of(tokens: &[String]) -> String {
     let (token_last, tokens) = match tokens.split_last() {
         Some((token_last, &[])) => return token_last.to_string(),
+        Some((token_last, [token])) => return format!("{token} or {token_last}"),
         Some((token_last, tokens)) => (token_last, tokens),
         None => return "nothing".to_string(),
     };
@@ -227,9 +638,70 @@ pub fn report_errors(writer: &mut impl io::Write, source: &str, errors: &[ErrorS
     writer.write_all(buffer.as_slice()).expect("failed to write to output");
 }
 
-pub fn report_error(writer: &mut impl WriteColor, source: &str, (error, span): &ErrorS) {
-    let file = SimpleFile::new("<script>", source);
+pub fn report_error(writer: &mut impl WriteColor, source: &str, error: &ErrorS) {
+    report_file_error(writer, "<script>", source, error);
+}
+
+/// Like [`report_error`], but labels the source with the name of the file it
+/// came from.
+pub fn report_file_error(
+    writer: &mut impl WriteColor,
+    name: &str,
+    source: &str,
+    (error, span): &ErrorS,
+) {
+    let file = SimpleFile::new(name, source);
     let config = term::Config::default();
-    let diagnostic = error.as_diagnostic(span);
+    let mut diagnostic = error.as_diagnostic(span);
+    // Spans may not line up with the source they are reported against, such
+    // as those of a cached chunk whose source has changed since, and slicing
+    // the source inside a char would panic.
+    for label in &mut diagnostic.labels {
+        label.range = types::char_span(source, &label.range);
+    }
+    diagnostic.message = format!("{}: {}", error.name(), crate::catalog::error_message(error));
+    diagnostic.notes.push(format!("for more information, run `lox explain {}`", error.code()));
     term::emit(writer, &config, &file, &diagnostic).expect("failed to write to output");
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn report_non_ascii() {
+        let source = "print \"é🦀\" + nil;";
+        let report = |span: Span| {
+            let mut buffer = termcolor::Buffer::no_color();
+            let error = TypeError::UnsupportedOperandInfix {
+                op: "+".to_string(),
+                lt_type: "string".to_string(),
+                rt_type: "nil".to_string(),
+            };
+            report_error(&mut buffer, source, &(error.into(), span));
+            String::from_utf8(buffer.into_inner()).unwrap()
+        };
+        assert!(report(6..18).contains(source));
+        // Spans that fall inside a char, or past the end of the source, are
+        // widened and clamped instead of panicking.
+        assert_eq!(report(8..10), report(7..11));
+        assert!(report(9..100).contains("é🦀\" + nil;"));
+    }
+
+    #[test]
+    fn error_json() {
+        let error: ErrorS = (
+            TypeError::UnsupportedOperandPrefix { op: "-".to_string(), rt_type: "nil".to_string() }
+                .into(),
+            3..7,
+        );
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"TypeError":{"UnsupportedOperandPrefix":{"op":"-","rt_type":"nil"}}},{"start":3,"end":7}]"#
+        );
+        assert_eq!(serde_json::from_str::<ErrorS>(&json).unwrap(), error);
+    }
+}
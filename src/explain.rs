@@ -0,0 +1,115 @@
+//! Extended explanations of error codes, as shown by `lox explain`.
+//!
+//! Each explanation is a Markdown file in res/explain, named after its code.
+//! Most of them include an example of code that raises the error, and how to
+//! fix it.
+
+/// The explanation of each code, sorted by code.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    ("E0001", include_str!("../res/explain/E0001.md")),
+    ("E0002", include_str!("../res/explain/E0002.md")),
+    ("E0003", include_str!("../res/explain/E0003.md")),
+    ("E0004", include_str!("../res/explain/E0004.md")),
+    ("E0005", include_str!("../res/explain/E0005.md")),
+    ("E0006", include_str!("../res/explain/E0006.md")),
+    ("E0007", include_str!("../res/explain/E0007.md")),
+    ("E0008", include_str!("../res/explain/E0008.md")),
+    ("E0009", include_str!("../res/explain/E0009.md")),
+    ("E0010", include_str!("../res/explain/E0010.md")),
+    ("E0011", include_str!("../res/explain/E0011.md")),
+    ("E0012", include_str!("../res/explain/E0012.md")),
+    ("E0013", include_str!("../res/explain/E0013.md")),
+    ("E0014", include_str!("../res/explain/E0014.md")),
+    ("E0015", include_str!("../res/explain/E0015.md")),
+    ("E0016", include_str!("../res/explain/E0016.md")),
+    ("E0017", include_str!("../res/explain/E0017.md")),
+    ("E0018", include_str!("../res/explain/E0018.md")),
+    ("E0101", include_str!("../res/explain/E0101.md")),
+    ("E0102", include_str!("../res/explain/E0102.md")),
+    ("E0103", include_str!("../res/explain/E0103.md")),
+    ("E0104", include_str!("../res/explain/E0104.md")),
+    ("E0105", include_str!("../res/explain/E0105.md")),
+    ("E0201", include_str!("../res/explain/E0201.md")),
+    ("E0202", include_str!("../res/explain/E0202.md")),
+    ("E0203", include_str!("../res/explain/E0203.md")),
+    ("E0204", include_str!("../res/explain/E0204.md")),
+    ("E0205", include_str!("../res/explain/E0205.md")),
+    ("E0206", include_str!("../res/explain/E0206.md")),
+    ("E0207", include_str!("../res/explain/E0207.md")),
+    ("E0208", include_str!("../res/explain/E0208.md")),
+    ("E0209", include_str!("../res/explain/E0209.md")),
+    ("E0210", include_str!("../res/explain/E0210.md")),
+    ("E0211", include_str!("../res/explain/E0211.md")),
+    ("E0212", include_str!("../res/explain/E0212.md")),
+    ("E0213", include_str!("../res/explain/E0213.md")),
+    ("E0214", include_str!("../res/explain/E0214.md")),
+    ("E0215", include_str!("../res/explain/E0215.md")),
+    ("E0216", include_str!("../res/explain/E0216.md")),
+    ("E0217", include_str!("../res/explain/E0217.md")),
+    ("E0301", include_str!("../res/explain/E0301.md")),
+    ("E0401", include_str!("../res/explain/E0401.md")),
+    ("E0402", include_str!("../res/explain/E0402.md")),
+    ("E0403", include_str!("../res/explain/E0403.md")),
+    ("E0501", include_str!("../res/explain/E0501.md")),
+    ("E0502", include_str!("../res/explain/E0502.md")),
+    ("E0601", include_str!("../res/explain/E0601.md")),
+    ("E0602", include_str!("../res/explain/E0602.md")),
+    ("E0603", include_str!("../res/explain/E0603.md")),
+    ("E0604", include_str!("../res/explain/E0604.md")),
+    ("E0605", include_str!("../res/explain/E0605.md")),
+    ("E0606", include_str!("../res/explain/E0606.md")),
+    ("E0607", include_str!("../res/explain/E0607.md")),
+    ("E0608", include_str!("../res/explain/E0608.md")),
+    ("E0701", include_str!("../res/explain/E0701.md")),
+    ("E0702", include_str!("../res/explain/E0702.md")),
+    ("E0703", include_str!("../res/explain/E0703.md")),
+    ("E0801", include_str!("../res/explain/E0801.md")),
+    ("E0802", include_str!("../res/explain/E0802.md")),
+    ("E0803", include_str!("../res/explain/E0803.md")),
+    ("E0804", include_str!("../res/explain/E0804.md")),
+    ("E0805", include_str!("../res/explain/E0805.md")),
+    ("E0806", include_str!("../res/explain/E0806.md")),
+    ("E0807", include_str!("../res/explain/E0807.md")),
+    ("E0808", include_str!("../res/explain/E0808.md")),
+    ("E0809", include_str!("../res/explain/E0809.md")),
+    ("E0810", include_str!("../res/explain/E0810.md")),
+    ("E0811", include_str!("../res/explain/E0811.md")),
+    ("E0812", include_str!("../res/explain/E0812.md")),
+    ("E0813", include_str!("../res/explain/E0813.md")),
+    ("E0814", include_str!("../res/explain/E0814.md")),
+    ("E0815", include_str!("../res/explain/E0815.md")),
+    ("E0816", include_str!("../res/explain/E0816.md")),
+];
+
+/// Returns the explanation of `code`, e.g. `"E0202"`. Codes are accepted
+/// without their leading `E`, and in lowercase.
+pub fn explain(code: &str) -> Option<&'static str> {
+    let code = code.strip_prefix(['E', 'e']).unwrap_or(code);
+    EXPLANATIONS.iter().find(|&&(name, _)| name[1..] == *code).map(|&(_, explanation)| explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::error::{Error, NameError, TypeError};
+
+    #[test]
+    fn explain_codes() {
+        assert!(EXPLANATIONS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        for &(code, explanation) in EXPLANATIONS {
+            assert!(code.len() == 5 && code.starts_with('E'), "invalid code: {code}");
+            assert!(explanation.ends_with('\n') && !explanation.ends_with("\n\n"), "{code}");
+        }
+
+        let error = Error::NameError(NameError::AlreadyDefined { name: "a".to_string() });
+        assert_eq!(error.code(), "E0102");
+        assert_eq!(explain("E0102"), explain("0102"));
+        assert!(explain(error.code()).unwrap().starts_with("A name is declared twice"));
+        let error = Error::TypeError(TypeError::NotCallable { type_: "nil".to_string() });
+        assert_eq!(explain(&error.code().to_lowercase()), explain("E0208"));
+        assert_eq!(explain("E9999"), None);
+        assert_eq!(explain(""), None);
+    }
+}
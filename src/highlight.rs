@@ -0,0 +1,187 @@
+#![cfg(feature = "repl")]
+
+use std::fmt::Write;
+
+use anyhow::{Context, Result};
+use nu_ansi_term::{Color, Style};
+use tree_sitter_highlight::{self, HighlightConfiguration, HighlightEvent};
+use tree_sitter_lox::{self, HIGHLIGHTS_QUERY};
+
+use crate::theme::Theme;
+
+/// Highlights Lox source code using tree-sitter, for both the REPL and the
+/// `highlight` command.
+pub struct Highlighter {
+    config: HighlightConfiguration,
+    theme: Theme,
+}
+
+impl Highlighter {
+    pub fn new(theme: Theme) -> Result<Self> {
+        let mut config =
+            HighlightConfiguration::new(tree_sitter_lox::language(), HIGHLIGHTS_QUERY, "", "")
+                .context("failed to create highlight configuration")?;
+        config.configure(&theme.names());
+        Ok(Self { config, theme })
+    }
+
+    /// Splits `source` into consecutive segments, each with the color that it
+    /// should be highlighted with. If highlighting fails partway through, the
+    /// rest of the source is returned as a single unhighlighted segment.
+    pub fn highlight<'a>(&self, source: &'a str) -> Vec<(Color, &'a str)> {
+        let mut output = Vec::new();
+
+        let mut highlighter = tree_sitter_highlight::Highlighter::new();
+        let Ok(highlights) = highlighter.highlight(&self.config, source.as_bytes(), None, |_| None)
+        else {
+            output.push((self.theme.fg(0), source));
+            return output;
+        };
+
+        let mut curr_fg = self.theme.fg(0);
+        let mut curr_end = 0;
+
+        for event in highlights {
+            match event {
+                Ok(HighlightEvent::HighlightStart(highlight)) => {
+                    curr_fg = self.theme.fg(highlight.0);
+                }
+                Ok(HighlightEvent::Source { start, end }) => {
+                    output.push((curr_fg, &source[start..end]));
+                    curr_end = end;
+                }
+                Ok(HighlightEvent::HighlightEnd) => {
+                    curr_fg = self.theme.fg(0);
+                }
+                Err(_) => {
+                    output.push((self.theme.fg(0), source.get(curr_end..).unwrap_or_default()));
+                    break;
+                }
+            }
+        }
+
+        output
+    }
+
+    /// Renders `source` as text highlighted with ANSI escape codes.
+    pub fn to_ansi(&self, source: &str) -> String {
+        let mut output = String::new();
+        for (fg, text) in self.highlight(source) {
+            let _ = write!(output, "{}", Style::new().fg(fg).paint(text));
+        }
+        output
+    }
+
+    /// Renders `source` as a standalone HTML `<pre>` block, with inline styles
+    /// so that it can be embedded without any accompanying CSS.
+    pub fn to_html(&self, source: &str) -> String {
+        let background = css_color(Color::Black);
+        let mut output = format!(r#"<pre style="background-color: {background}; padding: 1em">"#);
+        output.push_str("<code>");
+        for (fg, text) in self.highlight(source) {
+            let _ = write!(output, r#"<span style="color: {}">"#, css_color(fg));
+            escape_html(&mut output, text);
+            output.push_str("</span>");
+        }
+        output.push_str("</code></pre>\n");
+        output
+    }
+}
+
+fn escape_html(output: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+    }
+}
+
+/// Converts a terminal color to a CSS color, using the default xterm palette
+/// for the 16 named colors and the 8-bit color codes. Note that nu-ansi-term
+/// uses `White` for the standard white, and `LightGray` for the bright one.
+fn css_color(color: Color) -> String {
+    let (r, g, b) = match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Default => return "inherit".to_string(),
+        Color::Black => xterm_color(0),
+        Color::Red => xterm_color(1),
+        Color::Green => xterm_color(2),
+        Color::Yellow => xterm_color(3),
+        Color::Blue => xterm_color(4),
+        Color::Purple | Color::Magenta => xterm_color(5),
+        Color::Cyan => xterm_color(6),
+        Color::White => xterm_color(7),
+        Color::DarkGray => xterm_color(8),
+        Color::LightRed => xterm_color(9),
+        Color::LightGreen => xterm_color(10),
+        Color::LightYellow => xterm_color(11),
+        Color::LightBlue => xterm_color(12),
+        Color::LightPurple | Color::LightMagenta => xterm_color(13),
+        Color::LightCyan => xterm_color(14),
+        Color::LightGray => xterm_color(15),
+        Color::Fixed(code) => xterm_color(code),
+    };
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn xterm_color(code: u8) -> (u8, u8, u8) {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00),
+        (0xcd, 0x00, 0x00),
+        (0x00, 0xcd, 0x00),
+        (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee),
+        (0xcd, 0x00, 0xcd),
+        (0x00, 0xcd, 0xcd),
+        (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f),
+        (0xff, 0x00, 0x00),
+        (0x00, 0xff, 0x00),
+        (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff),
+        (0xff, 0x00, 0xff),
+        (0x00, 0xff, 0xff),
+        (0xff, 0xff, 0xff),
+    ];
+    match code {
+        0..=15 => BASIC[code as usize],
+        // 6x6x6 color cube.
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = code - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        // Grayscale ramp.
+        232..=255 => {
+            let level = 8 + (code - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn highlight_html() {
+        let highlighter = Highlighter::new(Theme::default()).unwrap();
+        let html = highlighter.to_html(r#"print "<b>";"#);
+        assert!(html.contains(r#"<span style="color: #ff00ff">print</span>"#), "{html}");
+        assert!(html.contains("&quot;&lt;b&gt;&quot;"), "{html}");
+    }
+
+    #[test]
+    fn xterm_colors() {
+        assert_eq!(xterm_color(9), (0xff, 0x00, 0x00));
+        assert_eq!(xterm_color(208), (0xff, 0x87, 0x00));
+        assert_eq!(xterm_color(244), (0x80, 0x80, 0x80));
+    }
+}
@@ -1,8 +1,103 @@
+//! A bytecode interpreter for Lox, and the tools around it.
+//!
+//! The items at the root of this crate are its stable API: [`run`], [`check`],
+//! [`Vm`], and the [`Diagnostic`] type that they report problems with. These
+//! only change in incompatible ways with a new major version, so tools such as
+//! graders and plugins should stick to them. The modules below are the
+//! internals of the interpreter and of the `lox` binary, and may change in any
+//! release.
+//!
+//! ```
+//! let mut stdout = Vec::new();
+//! loxcraft::run("print 1 + 2;", &mut stdout).unwrap();
+//! assert_eq!(stdout, b"3\n");
+//!
+//! let errors = loxcraft::run("print nil + 1;", &mut stdout).unwrap_err();
+//! assert_eq!(errors[0].code.as_deref(), Some("E0211"));
+//! ```
+
+use std::io::Write;
+
+pub use crate::analysis::{Diagnostic, Position, Severity};
+pub use crate::error::Category;
+use crate::lint::LintOptions;
+use crate::types::LineIndex;
+use crate::vm::{VM, VmOptions};
+
+pub mod analysis;
+pub mod bench;
+pub mod cache;
+pub mod callgraph;
+pub mod catalog;
+pub mod check;
 pub mod cmd;
+pub mod consts;
+pub mod data;
+pub mod debugger;
 pub mod error;
+pub mod explain;
+pub mod highlight;
+pub mod lint;
+pub mod log;
 pub mod lsp;
+pub mod metrics;
+pub mod modules;
+pub mod number;
 pub mod playground;
+pub mod project;
 pub mod repl;
+pub mod source_map;
+pub mod stats;
+pub mod symbols;
 pub mod syntax;
+pub mod tags;
+pub mod theme;
+pub mod timings;
 pub mod types;
+pub mod vfs;
 pub mod vm;
+pub mod watch;
+
+/// Runs a Lox program, writing what it prints to `stdout`. Returns the errors
+/// that stopped it from compiling, or the runtime error that ended it.
+pub fn run(source: &str, stdout: &mut impl Write) -> Result<(), Vec<Diagnostic>> {
+    Vm::new().run(source, stdout)
+}
+
+/// Returns the problems in a Lox program that can be found without running
+/// it: the errors that stop it from compiling, and warnings about likely
+/// mistakes.
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    analysis::diagnostics(source, Default::default(), &LintOptions::default(), &mut None)
+}
+
+/// A virtual machine that keeps its globals between runs, like the REPL does.
+#[derive(Debug)]
+pub struct Vm {
+    vm: VM,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self { vm: VM::new(VmOptions::default()) }
+    }
+
+    /// Runs `source`, which can use the globals declared by earlier runs.
+    ///
+    /// Positions in the diagnostics are relative to the sources of every run
+    /// so far, each followed by a newline, since an error can be raised in
+    /// code from an earlier run. For the first run, these are just the
+    /// positions in `source`.
+    pub fn run(&mut self, source: &str, stdout: &mut impl Write) -> Result<(), Vec<Diagnostic>> {
+        self.vm.run(source, stdout).map_err(|errors| {
+            let lines = LineIndex::new(&self.vm.source);
+            errors.iter().map(|error| Diagnostic::error(&self.vm.source, &lines, error)).collect()
+        })
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,673 @@
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use codespan_reporting::files::SimpleFile;
+use codespan_reporting::term;
+use serde::{Deserialize, Serialize};
+use termcolor::WriteColor;
+use thiserror::Error;
+
+use crate::consts;
+use crate::syntax::ast::{
+    Expr, ExprLiteral, ExprS, OpInfix, OpPrefix, Program, Stmt, StmtFun, StmtS,
+};
+use crate::syntax::name::Name;
+use crate::types::{Span, Spanned};
+
+pub type WarningS = Spanned<Warning>;
+
+/// Problems that do not stop a program from running, but are probably
+/// mistakes.
+#[derive(Debug, Deserialize, Error, Eq, PartialEq, Serialize)]
+pub enum Warning {
+    #[error(r#"condition should be of type "bool", not {type_:?}"#)]
+    ConditionInvalidType { type_: String },
+    #[error("condition is always {value}")]
+    ConstantCondition { value: bool },
+    #[error("loop never ends, and does nothing")]
+    InfiniteEmptyLoop,
+    #[error("comparison between {lt_type:?} and {rt_type:?} with {op}")]
+    MixedTypeEquality { op: String, lt_type: String, rt_type: String },
+    #[error("{keyword:?} in a nested function refers to the enclosing method")]
+    NestedThis { keyword: String },
+    #[error("{name:?} is private to its class")]
+    PrivateAccess { name: String },
+    #[error("{name:?} shadows a variable in an outer scope")]
+    Shadowing { name: String },
+    #[error("{name:?} is used before it is assigned a value")]
+    UseBeforeInit { name: String },
+}
+
+impl Warning {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Warning::ConditionInvalidType { .. } => "ConditionInvalidType",
+            Warning::ConstantCondition { .. } => "ConstantCondition",
+            Warning::InfiniteEmptyLoop => "InfiniteEmptyLoop",
+            Warning::MixedTypeEquality { .. } => "MixedTypeEquality",
+            Warning::NestedThis { .. } => "NestedThis",
+            Warning::PrivateAccess { .. } => "PrivateAccess",
+            Warning::Shadowing { .. } => "Shadowing",
+            Warning::UseBeforeInit { .. } => "UseBeforeInit",
+        }
+    }
+
+    fn notes(&self) -> Vec<String> {
+        match self {
+            Warning::ConditionInvalidType { .. } => {
+                vec!["values other than false and nil are always true".to_string()]
+            }
+            Warning::ConstantCondition { value: true } => {
+                vec!["the code that runs when it is false never runs".to_string()]
+            }
+            Warning::ConstantCondition { value: false } => {
+                vec!["the code that runs when it is true never runs".to_string()]
+            }
+            Warning::InfiniteEmptyLoop => {
+                vec!["this will hang the program once it is reached".to_string()]
+            }
+            Warning::MixedTypeEquality { .. } => {
+                vec!["values of different types are never equal".to_string()]
+            }
+            Warning::NestedThis { .. } => {
+                vec![
+                    "assign what the function needs to a local in the method first, e.g. \
+                      `var self = this;`"
+                        .to_string(),
+                ]
+            }
+            Warning::PrivateAccess { .. } => {
+                vec![
+                    "names starting with an underscore should only be used through this or super"
+                        .to_string(),
+                ]
+            }
+            Warning::Shadowing { .. } => {
+                vec!["the outer variable cannot be used in this scope".to_string()]
+            }
+            Warning::UseBeforeInit { .. } => vec!["its value is nil until then".to_string()],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct LintOptions {
+    /// Warn about conditions that are not bools, and about `==` and `!=`
+    /// between values of different types, where these can be seen without
+    /// running the program. [`VmOptions::strict_types`] checks the rest at
+    /// runtime.
+    ///
+    /// [`VmOptions::strict_types`]: crate::vm::VmOptions::strict_types
+    pub strict_types: bool,
+    /// Warn about fields and methods whose names start with an underscore
+    /// that are used other than through `this` or `super`. The class of an
+    /// object cannot be known without running the program, so this also
+    /// warns when a method uses them on another instance of its own class.
+    pub private_members: bool,
+    /// Warn about `this` and `super` in functions declared inside a method.
+    /// They refer to the instance that the method was called on, even when
+    /// the function is called later, or stored on another object. Lambdas
+    /// are not reported, since they are expected to capture `this`.
+    pub nested_this: bool,
+    /// Warn about conditions of `if`, `while` and `for` that always hold or
+    /// never do, given the literals and global constants in them, so that a
+    /// branch or loop is never run, or an `else` never is. The compiler leaves
+    /// such code out. `while (true)` is not reported, since it is how a loop
+    /// that ends by returning is written.
+    pub constant_conditions: bool,
+    /// Warn about locals, parameters, functions and classes that have the
+    /// same name as a variable in an enclosing scope.
+    pub shadowing: bool,
+    /// Warn about variables declared without a value that are read before
+    /// anything assigns to them. Any assignment that comes earlier in the
+    /// source counts, even in a branch that may not run, and reads from
+    /// nested functions are ignored, since those may run later.
+    pub use_before_init: bool,
+}
+
+pub fn lint(program: &Program, options: &LintOptions) -> Vec<WarningS> {
+    let mut linter = Linter {
+        options,
+        warnings: Vec::new(),
+        scopes: vec![Vec::new()],
+        fun_depth: 0,
+        nested_funs: None,
+    };
+    for stmt in &program.stmts {
+        linter.lint_stmt(stmt);
+    }
+    linter.warnings
+}
+
+struct Linter<'a> {
+    options: &'a LintOptions,
+    warnings: Vec<WarningS>,
+    /// The variables declared so far in each enclosing scope, starting with
+    /// the globals.
+    scopes: Vec<Vec<Decl>>,
+    /// The number of functions that enclose the current statement.
+    fun_depth: usize,
+    /// The number of functions declared with `fun` between the current
+    /// statement and the innermost method enclosing it, if there is one.
+    nested_funs: Option<usize>,
+}
+
+struct Decl {
+    name: String,
+    is_init: bool,
+    fun_depth: usize,
+    /// The value of a global constant, if it is known. Like the compiler,
+    /// this ignores constants in other scopes.
+    value: Option<ExprLiteral>,
+}
+
+impl Linter<'_> {
+    fn lint_stmt(&mut self, (stmt, span): &StmtS) {
+        match stmt {
+            Stmt::Assert(assert) => self.lint_cond(&assert.value),
+            Stmt::Block(block) => {
+                self.begin_scope();
+                block.stmts.iter().for_each(|stmt| self.lint_stmt(stmt));
+                self.end_scope();
+            }
+            Stmt::Class(class) => {
+                self.declare(&class.name, true, span);
+                class.super_.iter().for_each(|expr| self.lint_expr(expr));
+                for (method, span) in &class.methods {
+                    let nested_funs = self.nested_funs.replace(0);
+                    self.lint_fun(method, span);
+                    self.nested_funs = nested_funs;
+                }
+            }
+            Stmt::Expr(expr) => self.lint_expr(&expr.value),
+            Stmt::Extend(extend) => {
+                self.lint_expr(&extend.class);
+                for (method, span) in &extend.methods {
+                    let nested_funs = self.nested_funs.replace(0);
+                    self.lint_fun(method, span);
+                    self.nested_funs = nested_funs;
+                }
+            }
+            Stmt::For(for_) => {
+                let is_infinite = for_.cond.as_ref().map_or(true, is_truthy_literal);
+                if is_infinite && for_.incr.is_none() && is_empty(&for_.body) {
+                    self.warnings.push((Warning::InfiniteEmptyLoop, span.clone()));
+                }
+                self.begin_scope();
+                for_.init.iter().for_each(|stmt| self.lint_stmt(stmt));
+                for_.cond.iter().for_each(|cond| self.lint_branch_cond(cond, true));
+                for_.incr.iter().for_each(|expr| self.lint_expr(expr));
+                self.lint_stmt(&for_.body);
+                self.end_scope();
+            }
+            Stmt::Fun(fun) => {
+                self.declare(&fun.name, true, span);
+                let nested_funs = self.nested_funs;
+                self.nested_funs = nested_funs.map(|count| count + 1);
+                self.lint_fun(fun, span);
+                self.nested_funs = nested_funs;
+            }
+            Stmt::If(if_) => {
+                self.lint_branch_cond(&if_.cond, false);
+                self.lint_stmt(&if_.then);
+                if_.else_.iter().for_each(|stmt| self.lint_stmt(stmt));
+            }
+            Stmt::Print(print) => print.values.iter().for_each(|expr| self.lint_expr(expr)),
+            Stmt::Return(return_) => return_.value.iter().for_each(|expr| self.lint_expr(expr)),
+            Stmt::Try(try_) => {
+                self.lint_stmt(&try_.body);
+                if let Some(catch) = &try_.catch {
+                    self.begin_scope();
+                    self.declare(&catch.name, true, span);
+                    self.lint_stmt(&catch.body);
+                    self.end_scope();
+                }
+                try_.finally.iter().for_each(|finally| self.lint_stmt(finally));
+            }
+            Stmt::Var(var) => {
+                var.value.iter().for_each(|expr| self.lint_expr(expr));
+                let value = match &var.value {
+                    Some(value) if var.is_const && self.scopes.len() == 1 => {
+                        consts::eval(value, &|name| self.const_value(name))
+                    }
+                    _ => None,
+                };
+                self.declare(&var.var.name, var.value.is_some(), span);
+                if value.is_some() {
+                    self.scopes[0].last_mut().expect("constant should be declared").value = value;
+                }
+            }
+            Stmt::While(while_) => {
+                if is_truthy_literal(&while_.cond) && is_empty(&while_.body) {
+                    self.warnings.push((Warning::InfiniteEmptyLoop, span.clone()));
+                }
+                self.lint_branch_cond(&while_.cond, true);
+                self.lint_stmt(&while_.body);
+            }
+            Stmt::Error => {}
+        }
+    }
+
+    fn lint_expr(&mut self, (expr, span): &ExprS) {
+        match expr {
+            Expr::Assign(assign) => {
+                self.lint_expr(&assign.value);
+                if let Some(decl) = self.resolve(&assign.var.name) {
+                    decl.is_init = true;
+                }
+            }
+            Expr::Call(call) => {
+                self.lint_expr(&call.callee);
+                call.args.iter().for_each(|arg| self.lint_expr(arg));
+            }
+            Expr::Fun(fun) => self.lint_fun(fun, span),
+            Expr::Get(get) => {
+                self.lint_member(&get.object, &get.name, span);
+                self.lint_expr(&get.object);
+            }
+            Expr::Index(index) => {
+                self.lint_expr(&index.object);
+                self.lint_expr(&index.index);
+            }
+            Expr::Infix(infix) => {
+                match infix.op {
+                    OpInfix::Equal | OpInfix::NotEqual if self.options.strict_types => {
+                        let types = (static_type(&infix.lt), static_type(&infix.rt));
+                        if let (Some(lt_type), Some(rt_type)) = types {
+                            if lt_type != rt_type && lt_type != "nil" && rt_type != "nil" {
+                                let warning = Warning::MixedTypeEquality {
+                                    op: infix.op.to_string(),
+                                    lt_type: lt_type.to_string(),
+                                    rt_type: rt_type.to_string(),
+                                };
+                                self.warnings.push((warning, span.clone()));
+                            }
+                        }
+                    }
+                    // The left operand is tested like a condition.
+                    OpInfix::LogicAnd | OpInfix::LogicOr => self.lint_cond(&infix.lt),
+                    _ => {}
+                }
+                if !matches!(infix.op, OpInfix::LogicAnd | OpInfix::LogicOr) {
+                    self.lint_expr(&infix.lt);
+                }
+                self.lint_expr(&infix.rt);
+            }
+            Expr::List(list) => list.elements.iter().for_each(|element| self.lint_expr(element)),
+            Expr::Literal(_) | Expr::Missing => {}
+            Expr::Super(_) => self.lint_nested_this("super", span),
+            Expr::Map(map) => map.entries.iter().for_each(|(key, value)| {
+                self.lint_expr(key);
+                self.lint_expr(value);
+            }),
+            Expr::Var(var) if var.var.name == Name::THIS => self.lint_nested_this("this", span),
+            Expr::Var(var) => {
+                let (fun_depth, check) = (self.fun_depth, self.options.use_before_init);
+                if let Some(decl) = self.resolve(&var.var.name) {
+                    if check && !decl.is_init && decl.fun_depth == fun_depth {
+                        // Only the first read is reported.
+                        decl.is_init = true;
+                        let warning = Warning::UseBeforeInit { name: var.var.name.to_string() };
+                        self.warnings.push((warning, span.clone()));
+                    }
+                }
+            }
+            Expr::Prefix(prefix) => self.lint_expr(&prefix.rt),
+            Expr::Set(set) => {
+                self.lint_member(&set.object, &set.name, span);
+                self.lint_expr(&set.object);
+                self.lint_expr(&set.value);
+            }
+            Expr::SetIndex(set) => {
+                self.lint_expr(&set.object);
+                self.lint_expr(&set.index);
+                self.lint_expr(&set.value);
+            }
+        }
+    }
+
+    /// Lints the body of a function, in a new scope with its parameters.
+    /// Parameters have no spans of their own, so warnings about them point at
+    /// the whole function.
+    fn lint_fun(&mut self, fun: &StmtFun, span: &Span) {
+        self.begin_scope();
+        self.fun_depth += 1;
+        fun.params.iter().for_each(|param| self.declare(param, true, span));
+        fun.defaults.iter().for_each(|default| self.lint_expr(default));
+        fun.body.stmts.iter().for_each(|stmt| self.lint_stmt(stmt));
+        self.fun_depth -= 1;
+        self.end_scope();
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Declares `name` in the current scope. Globals can be declared again,
+    /// so only declarations in inner scopes can shadow.
+    fn declare(&mut self, name: &str, is_init: bool, span: &Span) {
+        let (scope, outer) = self.scopes.split_last_mut().expect("there is always a global scope");
+        if self.options.shadowing
+            && !outer.is_empty()
+            && outer.iter().flatten().any(|decl| decl.name == name)
+        {
+            self.warnings.push((Warning::Shadowing { name: name.to_string() }, span.clone()));
+        }
+        scope.push(Decl {
+            name: name.to_string(),
+            is_init,
+            fun_depth: self.fun_depth,
+            value: None,
+        });
+    }
+
+    /// Returns the innermost declaration of `name`, if there is one.
+    fn resolve(&mut self, name: &str) -> Option<&mut Decl> {
+        self.scopes
+            .iter_mut()
+            .rev()
+            .flat_map(|scope| scope.iter_mut().rev())
+            .find(|decl| decl.name == name)
+    }
+
+    /// Lints an access to the field or method `name` of `object`.
+    fn lint_member(&mut self, (object, _): &ExprS, name: &Name, span: &Span) {
+        let is_this = matches!(object, Expr::Var(var) if var.var.name == Name::THIS);
+        if self.options.private_members && name.starts_with('_') && !is_this {
+            self.warnings.push((Warning::PrivateAccess { name: name.to_string() }, span.clone()));
+        }
+    }
+
+    fn lint_nested_this(&mut self, keyword: &str, span: &Span) {
+        if self.options.nested_this && self.nested_funs.is_some_and(|count| count > 0) {
+            let warning = Warning::NestedThis { keyword: keyword.to_string() };
+            self.warnings.push((warning, span.clone()));
+        }
+    }
+
+    /// Returns the value of the constant `name`, if it is known.
+    fn const_value(&self, name: Name) -> Option<ExprLiteral> {
+        let decl = self.scopes.iter().flatten().rfind(|decl| decl.name == name.as_str())?;
+        decl.value.clone()
+    }
+
+    /// Lints the condition of a branch or a loop.
+    fn lint_branch_cond(&mut self, cond: &ExprS, is_loop: bool) {
+        if self.options.constant_conditions && !(is_loop && is_truthy_literal(cond)) {
+            if let Some(value) = consts::cond(cond, &|name| self.const_value(name)) {
+                self.warnings.push((Warning::ConstantCondition { value }, cond.1.clone()));
+            }
+        }
+        self.lint_cond(cond);
+    }
+
+    /// Lints an expression that is used as a condition.
+    fn lint_cond(&mut self, cond: &ExprS) {
+        if self.options.strict_types {
+            if let Some(type_) = static_type(cond).filter(|&type_| type_ != "bool") {
+                let warning = Warning::ConditionInvalidType { type_: type_.to_string() };
+                self.warnings.push((warning, cond.1.clone()));
+            }
+        }
+        self.lint_expr(cond);
+    }
+}
+
+/// Returns the type that `expr` always evaluates to, if it can be known
+/// without running the program. Type names match those in runtime errors.
+fn static_type((expr, _): &ExprS) -> Option<&'static str> {
+    match expr {
+        Expr::Assign(assign) => static_type(&assign.value),
+        Expr::Fun(_) => Some("function"),
+        Expr::Infix(infix) => match infix.op {
+            OpInfix::Subtract
+            | OpInfix::Multiply
+            | OpInfix::Divide
+            | OpInfix::Modulo
+            | OpInfix::Power => Some("number"),
+            OpInfix::Less
+            | OpInfix::LessEqual
+            | OpInfix::Greater
+            | OpInfix::GreaterEqual
+            | OpInfix::Equal
+            | OpInfix::NotEqual
+            | OpInfix::Is => Some("bool"),
+            OpInfix::Concat => Some("string"),
+            // These evaluate to one of their operands.
+            OpInfix::Add | OpInfix::LogicAnd | OpInfix::LogicOr => {
+                let lt_type = static_type(&infix.lt)?;
+                (static_type(&infix.rt)? == lt_type).then_some(lt_type)
+            }
+        },
+        Expr::List(_) => Some("list"),
+        Expr::Literal(literal) => Some(match literal {
+            ExprLiteral::Bool(_) => "bool",
+            ExprLiteral::Nil => "nil",
+            ExprLiteral::Number(_) => "number",
+            ExprLiteral::String(_) => "string",
+        }),
+        Expr::Map(_) => Some("map"),
+        Expr::Prefix(prefix) => Some(match prefix.op {
+            OpPrefix::Negate => "number",
+            OpPrefix::Not => "bool",
+        }),
+        Expr::Call(_)
+        | Expr::Get(_)
+        | Expr::Index(_)
+        | Expr::Missing
+        | Expr::Set(_)
+        | Expr::SetIndex(_)
+        | Expr::Super(_)
+        | Expr::Var(_) => None,
+    }
+}
+
+/// Returns true if `expr` is a literal that is always truthy.
+fn is_truthy_literal((expr, _): &ExprS) -> bool {
+    match expr {
+        Expr::Literal(literal) => consts::is_truthy(literal),
+        _ => false,
+    }
+}
+
+/// Returns true if `stmt` is a block containing nothing but empty blocks.
+fn is_empty((stmt, _): &StmtS) -> bool {
+    match stmt {
+        Stmt::Block(block) => block.stmts.iter().all(is_empty),
+        _ => false,
+    }
+}
+
+pub fn report_warning(
+    writer: &mut impl WriteColor,
+    name: &str,
+    source: &str,
+    (warning, span): &WarningS,
+) {
+    let file = SimpleFile::new(name, source);
+    let config = term::Config::default();
+    let diagnostic = Diagnostic::warning()
+        .with_code(warning.name())
+        .with_message(crate::catalog::warning_message(warning))
+        .with_labels(vec![Label::primary((), span.clone())])
+        .with_notes(warning.notes());
+    term::emit(writer, &config, &file, &diagnostic).expect("failed to write to output");
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn lint_source(source: &str) -> Vec<WarningS> {
+        lint(&crate::syntax::parse(source, 0).unwrap(), &LintOptions::default())
+    }
+
+    #[test]
+    fn infinite_empty_loop() {
+        let warning = |span| vec![(Warning::InfiniteEmptyLoop, span)];
+        assert_eq!(lint_source("while (true) {}"), warning(0..15));
+        assert_eq!(lint_source("while (1) { {} }"), warning(0..16));
+        assert_eq!(lint_source("fun f() { for (;;) {} }"), warning(10..21));
+        assert_eq!(lint_source("for (var i = 0; \"yes\";) {}"), warning(0..26));
+
+        assert_eq!(lint_source("while (false) {}"), []);
+        assert_eq!(lint_source("while (x) {}"), []);
+        assert_eq!(lint_source("while (true) { print 1; }"), []);
+        assert_eq!(lint_source("for (var i = 0; i < 10; i = i + 1) {}"), []);
+    }
+
+    #[test]
+    fn strict_types() {
+        let lint_strict = |source: &str| {
+            let program = crate::syntax::parse(source, 0).unwrap();
+            lint(&program, &LintOptions { strict_types: true, ..Default::default() })
+        };
+        let condition = |type_: &str, span| {
+            vec![(Warning::ConditionInvalidType { type_: type_.to_string() }, span)]
+        };
+        assert_eq!(lint_strict("if (1) {}"), condition("number", 4..5));
+        assert_eq!(lint_strict("while (\"a\" + \"b\") {}"), condition("string", 7..16));
+        assert_eq!(lint_strict("print nil or x;"), condition("nil", 6..9));
+        assert_eq!(
+            lint_strict("print 1 != \"1\";"),
+            vec![(
+                Warning::MixedTypeEquality {
+                    op: "!=".to_string(),
+                    lt_type: "number".to_string(),
+                    rt_type: "string".to_string(),
+                },
+                6..14
+            )]
+        );
+
+        assert_eq!(lint_strict("if (x) {} if (1 < 2) {} print x == nil; print 1 == x;"), []);
+        assert_eq!(lint_strict("while (!1) {} print true and 1;"), []);
+        assert_eq!(lint_source("if (1) {} print 1 == \"1\";"), []);
+    }
+
+    #[test]
+    fn shadowing() {
+        let lint_shadowing = |source: &str| {
+            let program = crate::syntax::parse(source, 0).unwrap();
+            let warnings = lint(&program, &LintOptions { shadowing: true, ..Default::default() });
+            warnings
+                .into_iter()
+                .map(|(warning, span)| match warning {
+                    Warning::Shadowing { name } => (name, span),
+                    warning => panic!("unexpected warning: {warning:?}"),
+                })
+                .collect::<Vec<_>>()
+        };
+        let shadows = |name: &str, span| vec![(name.to_string(), span)];
+        assert_eq!(lint_shadowing("var a; { var a; }"), shadows("a", 9..15));
+        assert_eq!(lint_shadowing("var a; fun f(a) {}"), shadows("a", 7..18));
+        assert_eq!(lint_shadowing("fun f() { var x; var g = (x) -> x; }"), shadows("x", 25..33));
+        assert_eq!(lint_shadowing("var e; try {} catch (e) {}"), shadows("e", 7..26));
+        assert_eq!(lint_shadowing("{ var a; } { var a; }"), []);
+        assert_eq!(lint_shadowing("var a; var a; fun f() {} fun f() {}"), []);
+        assert_eq!(lint_source("var a; { var a; }"), []);
+    }
+
+    #[test]
+    fn nested_this() {
+        let lint_nested = |source: &str| {
+            let program = crate::syntax::parse(source, 0).unwrap();
+            lint(&program, &LintOptions { nested_this: true, ..Default::default() })
+        };
+        let nested = |keyword: &str, span| {
+            vec![(Warning::NestedThis { keyword: keyword.to_string() }, span)]
+        };
+        assert_eq!(
+            lint_nested("class A { m() { fun f() { return this; } } }"),
+            nested("this", 33..37)
+        );
+        assert_eq!(
+            lint_nested("class A < B { m() { fun f() { return super.m; } } }"),
+            nested("super", 37..44)
+        );
+        assert_eq!(
+            lint_nested("class A { m() { fun f() { var g = () -> this; } } }"),
+            nested("this", 40..44)
+        );
+        assert_eq!(
+            lint_nested(
+                "class A { m() { print this; var f = () -> this; class B { n() { print this; } } } }"
+            ),
+            []
+        );
+        assert_eq!(lint_nested("fun f() { fun g() { class C { m() { return this; } } } }"), []);
+        assert_eq!(lint_source("class A { m() { fun f() { return this; } } }"), []);
+    }
+
+    #[test]
+    fn constant_conditions() {
+        let lint_constant = |source: &str| {
+            let program = crate::syntax::parse(source, 0).unwrap();
+            lint(&program, &LintOptions { constant_conditions: true, ..Default::default() })
+        };
+        let constant = |value, span| vec![(Warning::ConstantCondition { value }, span)];
+        assert_eq!(lint_constant("if (1 < 2) print 1;"), constant(true, 4..9));
+        assert_eq!(lint_constant("const debug = false; if (debug) {}"), constant(false, 25..30));
+        assert_eq!(
+            lint_constant("const n = 2; fun f() { while (n * 2 == 4 and true) print 1; }"),
+            constant(true, 30..49)
+        );
+        assert_eq!(lint_constant("for (; false;) {}"), constant(false, 7..12));
+
+        // Loops written to run until they return are not reported, nor are
+        // constants that are not global, or conditions that are not bools.
+        assert_eq!(lint_constant("while (true) { print 1; } for (;;) { print 1; }"), []);
+        assert_eq!(lint_constant("{ const debug = false; if (debug) {} }"), []);
+        assert_eq!(lint_constant("const debug = false; fun f(debug) { if (debug) {} }"), []);
+        assert_eq!(lint_constant("var debug = false; if (debug) {} if (1) {} if (x) {}"), []);
+        assert_eq!(lint_source("if (false) {}"), []);
+    }
+
+    #[test]
+    fn private_members() {
+        let lint_private = |source: &str| {
+            let program = crate::syntax::parse(source, 0).unwrap();
+            lint(&program, &LintOptions { private_members: true, ..Default::default() })
+        };
+        let private =
+            |name: &str, span| vec![(Warning::PrivateAccess { name: name.to_string() }, span)];
+        assert_eq!(lint_private("var p = Point(); print p._x;"), private("_x", 23..27));
+        assert_eq!(lint_private("p._x = 1;"), private("_x", 0..8));
+        assert_eq!(lint_private("p._reset();"), private("_reset", 0..8));
+        assert_eq!(
+            lint_private("class A { eq(other) { return this._x == other._x; } }"),
+            private("_x", 40..48)
+        );
+        assert_eq!(
+            lint_private(
+                "class A { init() { this._x = 1; this._reset(); } }
+                 class B < A { init() { super._reset(); } }"
+            ),
+            []
+        );
+        assert_eq!(lint_private("print p.x; p.__ = 1;"), private("__", 11..19));
+        assert_eq!(lint_source("print p._x;"), []);
+    }
+
+    #[test]
+    fn use_before_init() {
+        let lint_uninit = |source: &str| {
+            let program = crate::syntax::parse(source, 0).unwrap();
+            lint(&program, &LintOptions { use_before_init: true, ..Default::default() })
+        };
+        let uninit =
+            |name: &str, span| vec![(Warning::UseBeforeInit { name: name.to_string() }, span)];
+        assert_eq!(lint_uninit("var a; print a; print a;"), uninit("a", 13..14));
+        assert_eq!(lint_uninit("var a; { a = a + 1; }"), uninit("a", 13..14));
+        assert_eq!(lint_uninit("{ var a; while (true) { print a; a = 1; } }"), uninit("a", 30..31));
+        assert_eq!(lint_uninit("var a; if (true) a = 1; print a;"), []);
+        assert_eq!(lint_uninit("var a; fun f() { print a; } a = 1; f();"), []);
+        assert_eq!(lint_uninit("var a = nil; print a; var b; { var b = 1; print b; }"), []);
+        assert_eq!(lint_source("var a; print a;"), []);
+    }
+}
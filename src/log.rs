@@ -0,0 +1,42 @@
+use std::fs::OpenOptions;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// The environment variable that controls which logs are written, as a list of
+/// `tracing` directives, e.g. `debug` or `loxcraft::vm=trace,warn`.
+pub const LOG_ENV: &str = "LOXCRAFT_LOG";
+
+/// The directives used when [`LOG_ENV`] is not set.
+const DEFAULT_DIRECTIVES: &str = "info";
+
+/// Sends logs for the rest of the process to `log_file`, or to stderr if it is
+/// not given. The language server and the playground should log to a file,
+/// since stdout is part of the protocol they speak.
+pub fn init(log_file: Option<&Path>) -> Result<()> {
+    let filter = match std::env::var(LOG_ENV) {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(&directives)
+            .with_context(|| format!("invalid {LOG_ENV}: {directives}"))?,
+        _ => EnvFilter::new(DEFAULT_DIRECTIVES),
+    };
+    // Spans are logged when they close, with how long they took, so that the
+    // phases of compiling and running a program can be timed.
+    let builder =
+        tracing_subscriber::fmt().with_env_filter(filter).with_span_events(FmtSpan::CLOSE);
+    match log_file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("could not open log file: {}", path.display()))?;
+            builder.with_ansi(false).with_writer(Mutex::new(file)).init();
+        }
+        None => builder.with_ansi(io::stderr().is_terminal()).with_writer(io::stderr).init(),
+    }
+    Ok(())
+}
@@ -1,36 +1,363 @@
 #![cfg(feature = "lsp")]
 
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
 use anyhow::{Context, Result};
+use futures_util::stream::{self, BoxStream};
+use futures_util::{SinkExt, StreamExt, future};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
 use tower_lsp::lsp_types::{
-    Diagnostic, DiagnosticSeverity, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
-    InitializeParams, InitializeResult, Position, Range, ServerCapabilities, ServerInfo,
-    TextDocumentSyncKind,
+    ConfigurationItem, Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity,
+    DidChangeConfigurationParams, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, DocumentFormattingParams,
+    DocumentHighlight, DocumentHighlightKind, DocumentHighlightParams,
+    DocumentOnTypeFormattingOptions, DocumentOnTypeFormattingParams, DocumentRangeFormattingParams,
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, FoldingRange, FoldingRangeParams,
+    FoldingRangeProviderCapability, FormattingOptions, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, InitializeResult, InitializedParams, InlayHint,
+    InlayHintKind, InlayHintLabel, InlayHintParams, Location, MarkupContent, MarkupKind,
+    MessageType, NumberOrString, OneOf, Position, Range, SelectionRange, SelectionRangeParams,
+    SelectionRangeProviderCapability, ServerCapabilities, ServerInfo, SymbolKind,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextDocumentSyncOptions,
+    TextDocumentSyncSaveOptions, TextEdit, Url,
 };
-use tower_lsp::{Client, LanguageServer, LspService, Server, jsonrpc};
+use tower_lsp::{Client, ClientSocket, LanguageServer, Loopback, LspService, Server, jsonrpc};
 
-use crate::types::Span;
-use crate::vm::{Compiler, Gc};
+use crate::analysis::{self, Severity};
+use crate::error::Category;
+use crate::lint::LintOptions;
+use crate::symbols::Access;
+use crate::syntax::ast::Program;
+use crate::timings::Timings;
+use crate::types::{LineIndex, Span};
+use crate::vm::{CompilerOptions, VmOptions};
 
 #[derive(Debug)]
 struct Backend {
     client: Client,
+    /// Whether to log how long each phase of analyzing a document takes.
+    timings: bool,
+    settings: Mutex<Settings>,
+    /// Whether the client answers `workspace/configuration` requests.
+    can_pull_settings: AtomicBool,
+    /// The open documents, so that requests which only need the syntax tree
+    /// do not have to parse the source again.
+    documents: Mutex<HashMap<Url, Document>>,
+    /// Counts the edits to any document, so that each version of a document
+    /// gets a distinct generation.
+    generations: AtomicU64,
+    /// Wakes up the analyses waiting in [`Backend::did_change`] when a newer
+    /// edit arrives.
+    edited: Notify,
+}
+
+/// How long typing must pause before an edited document is analyzed again.
+const ANALYSIS_DELAY: Duration = Duration::from_millis(150);
+
+/// Settings that the client can change while the server runs, under the
+/// `lox` section of its configuration:
+///
+/// ```json
+/// { "lox": { "strict": true, "lints": { "shadowing": true }, "run": { "onSave": true } } }
+/// ```
+///
+/// The server reads them from the initialization options, and again whenever
+/// the client reports that its configuration changed. Settings from the client
+/// replace the lints given on the command line.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+struct Settings {
+    /// Report globals that are not defined anywhere as errors.
+    strict: bool,
+    lints: LintOptions,
+    /// The most diagnostics to publish for a document. Errors come first.
+    max_diagnostics: Option<usize>,
+    format: FormatSettings,
+    run: RunSettings,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+struct FormatSettings {
+    /// Reindent lines and insert missing semicolons while typing.
+    on_type: bool,
+    /// Indent with this instead of what the editor asks for.
+    indent: Option<String>,
+}
+
+impl Default for FormatSettings {
+    fn default() -> Self {
+        Self { on_type: true, indent: None }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+struct RunSettings {
+    /// Run documents when they are saved, and report the runtime error that
+    /// stops them, if any, until the next edit.
+    on_save: bool,
+    /// The most ops that a run may take before it is stopped.
+    fuel: usize,
+}
+
+impl Default for RunSettings {
+    fn default() -> Self {
+        Self { on_save: false, fuel: 1_000_000 }
+    }
+}
+
+impl Settings {
+    /// Reads the settings from the configuration of the client, which either
+    /// is the `lox` section itself, or contains it.
+    fn parse(value: &Value) -> Result<Self, serde_json::Error> {
+        let value = value.get("lox").unwrap_or(value);
+        if value.is_null() {
+            return Ok(Self::default());
+        }
+        Self::deserialize(value)
+    }
+}
+
+#[derive(Debug)]
+struct Document {
+    source: String,
+    /// Parsed on first use, since most versions of a document are replaced
+    /// while the user types, before anything needs their syntax tree. A
+    /// source with syntax errors still has a tree, with the parts that could
+    /// not be parsed left out. Holds [`None`] if the parser panicked.
+    program: OnceCell<Option<Program>>,
+    /// For converting between the offsets that analyses use and the
+    /// positions that the client uses.
+    lines: LineIndex,
+    /// The generation of this version of the document.
+    generation: u64,
 }
 
 impl Backend {
-    pub fn new(client: Client) -> Self {
-        Self { client }
+    pub fn new(client: Client, timings: bool, lint_options: LintOptions) -> Self {
+        let settings = Settings { lints: lint_options, ..Default::default() };
+        Self {
+            client,
+            timings,
+            settings: Mutex::new(settings),
+            can_pull_settings: AtomicBool::default(),
+            documents: Mutex::default(),
+            generations: AtomicU64::default(),
+            edited: Notify::new(),
+        }
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings.lock().unwrap_or_else(PoisonError::into_inner).clone()
     }
 
-    pub fn get_diagnostics(&self, source: &str) -> Vec<Diagnostic> {
-        let mut gc = Gc::default();
-        Compiler::compile(source, 0, &mut gc)
-            .err()
-            .unwrap_or_default()
+    /// Applies settings sent by the client, and analyzes the open documents
+    /// again with them. Invalid settings are reported and otherwise ignored.
+    async fn update_settings(&self, value: &Value) {
+        let settings = match Settings::parse(value) {
+            Ok(settings) => settings,
+            Err(e) => {
+                let message = format!("invalid settings: {e}");
+                self.client.show_message(MessageType::WARNING, message).await;
+                return;
+            }
+        };
+        {
+            let mut current = self.settings.lock().unwrap_or_else(PoisonError::into_inner);
+            if *current == settings {
+                return;
+            }
+            *current = settings;
+        }
+        let documents = self
+            .documents()
             .iter()
-            .map(|(err, span)| Diagnostic {
-                range: get_range(source, span),
-                severity: Some(DiagnosticSeverity::ERROR),
-                message: err.to_string(),
+            .map(|(uri, document)| (uri.clone(), document.source.clone()))
+            .collect::<Vec<_>>();
+        for (uri, source) in documents {
+            self.publish_diagnostics(uri, &source, None, false).await;
+        }
+    }
+
+    /// Asks the client for the `lox` section of its configuration, if it
+    /// supports that.
+    async fn pull_settings(&self) {
+        if !self.can_pull_settings.load(Ordering::Relaxed) {
+            return;
+        }
+        let item = ConfigurationItem { scope_uri: None, section: Some("lox".to_string()) };
+        match self.client.configuration(vec![item]).await {
+            Ok(values) => {
+                if let Some(value) = values.first() {
+                    self.update_settings(value).await;
+                }
+            }
+            Err(e) => {
+                let message = format!("could not get settings: {e}");
+                self.client.log_message(MessageType::WARNING, message).await;
+            }
+        }
+    }
+
+    /// Returns the open documents. A request that panicked while holding the
+    /// lock leaves the documents as they were, so they are still usable.
+    fn documents(&self) -> MutexGuard<'_, HashMap<Url, Document>> {
+        self.documents.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Stores a new version of a document, and returns its generation.
+    fn update_document(&self, uri: Url, source: String) -> u64 {
+        let generation = self.generations.fetch_add(1, Ordering::Relaxed);
+        let lines = LineIndex::new(&source);
+        let document = Document { source, program: OnceCell::new(), lines, generation };
+        self.documents().insert(uri, document);
+        generation
+    }
+
+    /// Returns whether `generation` is still the latest version of a document.
+    fn is_latest(&self, uri: &Url, generation: u64) -> bool {
+        self.documents().get(uri).is_some_and(|document| document.generation == generation)
+    }
+
+    /// Calls `f` with the source of a document, if it is open.
+    /// The indentation to format with: the one in the settings if there is
+    /// one, or else the one that the editor asks for.
+    fn indent(&self, options: &FormattingOptions) -> String {
+        self.settings().format.indent.unwrap_or_else(|| {
+            if options.insert_spaces {
+                " ".repeat(options.tab_size as usize)
+            } else {
+                "\t".to_string()
+            }
+        })
+    }
+
+    fn with_source<T>(&self, uri: &Url, f: impl FnOnce(&str, &LineIndex) -> T) -> Option<T> {
+        let documents = self.documents();
+        let document = documents.get(uri)?;
+        catch_panic(|| f(&document.source, &document.lines))
+    }
+
+    /// Calls `f` with the source, line index and syntax tree of a document,
+    /// if it is open.
+    fn with_program<T>(
+        &self,
+        uri: &Url,
+        f: impl FnOnce(&str, &LineIndex, &Program) -> T,
+    ) -> Option<T> {
+        let documents = self.documents();
+        let document = documents.get(uri)?;
+        let program = document
+            .program
+            .get_or_init(|| catch_panic(|| crate::syntax::parse_tolerant(&document.source).0))
+            .as_ref()?;
+        catch_panic(|| f(&document.source, &document.lines, program))
+    }
+
+    /// Publishes the diagnostics for a document. If `run` is set, the
+    /// document is also run, and the runtime error that stops it is published
+    /// with them.
+    pub async fn publish_diagnostics(
+        &self,
+        uri: Url,
+        source: &str,
+        version: Option<i32>,
+        run: bool,
+    ) {
+        let mut timings = self.timings.then(Timings::default);
+        let Some(mut diagnostics) = catch_panic(|| self.get_diagnostics(source, &mut timings))
+        else {
+            let message = format!("internal error while analyzing {uri}");
+            self.client.log_message(MessageType::ERROR, message).await;
+            return;
+        };
+        if let Some(timings) = timings {
+            let message = format!("timings for {uri}:\n{timings}");
+            self.client.log_message(MessageType::LOG, message).await;
+        }
+        if run {
+            let fuel = self.settings().run.fuel;
+            match catch_panic(|| self.get_run_diagnostic(&uri, source, fuel)) {
+                Some(Ok(diagnostic)) => diagnostics.extend(diagnostic),
+                Some(Err(())) => {
+                    let message = format!("stopped running {uri} after {fuel} ops");
+                    self.client.log_message(MessageType::INFO, message).await;
+                }
+                None => {
+                    let message = format!("internal error while running {uri}");
+                    self.client.log_message(MessageType::ERROR, message).await;
+                }
+            }
+        }
+        self.client.publish_diagnostics(uri, diagnostics, version).await;
+    }
+
+    /// Runs a document, and returns a diagnostic for the runtime error that
+    /// stops it, with the calls that led to it as related information. This
+    /// returns an error if the document runs out of fuel.
+    fn get_run_diagnostic(
+        &self,
+        uri: &Url,
+        source: &str,
+        fuel: usize,
+    ) -> Result<Option<Diagnostic>, ()> {
+        let options = VmOptions { strict: self.settings().strict, ..Default::default() };
+        let (diagnostic, traceback) = match analysis::run(source, options, fuel) {
+            analysis::RunOutcome::Finished => return Ok(None),
+            analysis::RunOutcome::OutOfFuel => return Err(()),
+            analysis::RunOutcome::Failed { diagnostic, traceback } => (diagnostic, traceback),
+        };
+        let related = traceback
+            .into_iter()
+            .map(|entry| DiagnosticRelatedInformation {
+                location: Location {
+                    uri: uri.clone(),
+                    range: Range { start: entry.start.into(), end: entry.end.into() },
+                },
+                message: format!("in {}", entry.function),
+            })
+            .collect();
+        Ok(Some(Diagnostic {
+            range: Range { start: diagnostic.start.into(), end: diagnostic.end.into() },
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: diagnostic.code.map(NumberOrString::String),
+            // Runtime errors only show up when the document runs, so they are
+            // marked as such, whatever the type of the error.
+            source: Some(Category::Runtime.source().to_string()),
+            message: diagnostic.message,
+            related_information: Some(related),
+            ..Default::default()
+        }))
+    }
+
+    pub fn get_diagnostics(&self, source: &str, timings: &mut Option<Timings>) -> Vec<Diagnostic> {
+        let settings = self.settings();
+        let options = if settings.strict { CompilerOptions::strict() } else { Default::default() };
+        analysis::diagnostics(source, options, &settings.lints, timings)
+            .into_iter()
+            .take(settings.max_diagnostics.unwrap_or(usize::MAX))
+            .map(|diagnostic| Diagnostic {
+                range: Range { start: diagnostic.start.into(), end: diagnostic.end.into() },
+                severity: Some(match diagnostic.severity {
+                    Severity::Error => DiagnosticSeverity::ERROR,
+                    Severity::Warning => DiagnosticSeverity::WARNING,
+                }),
+                code: diagnostic.code.map(NumberOrString::String),
+                source: Some(diagnostic.category.source().to_string()),
+                message: diagnostic.message,
                 ..Default::default()
             })
             .collect()
@@ -39,10 +366,46 @@ impl Backend {
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> jsonrpc::Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
+        let can_pull_settings = params
+            .capabilities
+            .workspace
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or_default();
+        self.can_pull_settings.store(can_pull_settings, Ordering::Relaxed);
+        if let Some(options) = &params.initialization_options {
+            match Settings::parse(options) {
+                Ok(settings) => {
+                    *self.settings.lock().unwrap_or_else(PoisonError::into_inner) = settings
+                }
+                Err(e) => {
+                    let message = format!("invalid settings: {e}");
+                    self.client.show_message(MessageType::WARNING, message).await;
+                }
+            }
+        }
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncKind::FULL.into()),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::FULL),
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    },
+                )),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "}".to_string(),
+                    more_trigger_character: Some(vec![";".to_string()]),
+                }),
                 ..Default::default()
             },
             server_info: Some(ServerInfo {
@@ -52,51 +415,491 @@ impl LanguageServer for Backend {
         })
     }
 
+    async fn initialized(&self, _: InitializedParams) {
+        self.pull_settings().await;
+    }
+
     async fn shutdown(&self) -> jsonrpc::Result<()> {
         Ok(())
     }
 
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // Clients that support pulling settings often send nothing here, and
+        // only use this to say that the settings changed.
+        if params.settings.is_null() || params.settings == Value::Object(Default::default()) {
+            self.pull_settings().await;
+        } else {
+            self.update_settings(&params.settings).await;
+        }
+    }
+
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         let source = &params.text_document.text;
         let uri = params.text_document.uri;
         let version = Some(params.text_document.version);
-        let diagnostics = self.get_diagnostics(source);
-        self.client.publish_diagnostics(uri, diagnostics, version).await;
+        self.update_document(uri.clone(), source.clone());
+        self.publish_diagnostics(uri, source, version, false).await;
     }
 
-    async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let source = &params.content_changes.first().unwrap().text;
+    async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
+        // Documents are synced in full, so the last change has the whole text.
+        let Some(change) = params.content_changes.pop() else { return };
         let uri = params.text_document.uri;
         let version = Some(params.text_document.version);
-        let diagnostics = self.get_diagnostics(source);
-        self.client.publish_diagnostics(uri, diagnostics, version).await;
+        let generation = self.update_document(uri.clone(), change.text);
+        self.edited.notify_waiters();
+
+        // Wait for typing to pause, and give up if another edit arrives in the
+        // meantime, so that only the latest version is analyzed.
+        let delay = tokio::time::sleep(ANALYSIS_DELAY);
+        tokio::pin!(delay);
+        loop {
+            tokio::select! {
+                () = &mut delay => break,
+                () = self.edited.notified() => {
+                    if !self.is_latest(&uri, generation) {
+                        return;
+                    }
+                }
+            }
+        }
+        let Some(source) = self.with_source(&uri, |source, _| source.to_string()) else { return };
+        self.publish_diagnostics(uri, &source, version, false).await;
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        if !self.settings().run.on_save {
+            return;
+        }
+        let uri = params.text_document.uri;
+        let Some(source) = self.with_source(&uri, |source, _| source.to_string()) else { return };
+        self.publish_diagnostics(uri, &source, None, true).await;
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        self.documents().remove(&params.text_document.uri);
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> jsonrpc::Result<Option<Vec<DocumentHighlight>>> {
+        let params = params.text_document_position_params;
+        Ok(self.with_program(&params.text_document.uri, |source, lines, program| {
+            let idx = lines.offset(source, params.position.into());
+            analysis::highlights(source, program, idx)
+                .into_iter()
+                .map(|(span, access)| DocumentHighlight {
+                    range: get_range(source, lines, &span),
+                    kind: Some(match access {
+                        Access::Read => DocumentHighlightKind::READ,
+                        Access::Write => DocumentHighlightKind::WRITE,
+                    }),
+                })
+                .collect()
+        }))
+    }
+
+    async fn hover(&self, params: HoverParams) -> jsonrpc::Result<Option<Hover>> {
+        let params = params.text_document_position_params;
+        Ok(self
+            .with_program(&params.text_document.uri, |source, lines, program| {
+                let idx = lines.offset(source, params.position.into());
+                let hover = analysis::hover(source, program, idx)?;
+                Some(Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: format!("```lox\n{}\n```\n{}", hover.signature, hover.detail),
+                    }),
+                    range: Some(get_range(source, lines, &hover.span)),
+                })
+            })
+            .flatten())
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let position = params.text_document_position;
+        if !self.settings().format.on_type {
+            return Ok(None);
+        }
+        let indent = self.indent(&params.options);
+        let Some(ch) = params.ch.chars().next() else { return Ok(None) };
+        Ok(self.with_source(&position.text_document.uri, |source, lines| {
+            let idx = lines.offset(source, position.position.into());
+            analysis::on_type_edits(source, idx, ch, &indent)
+                .into_iter()
+                .map(|edit| TextEdit {
+                    range: get_range(source, lines, &edit.span),
+                    new_text: edit.text,
+                })
+                .collect()
+        }))
+    }
+
+    async fn formatting(
+        &self,
+        params: DocumentFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let indent = self.indent(&params.options);
+        Ok(self.with_source(&params.text_document.uri, |source, lines| {
+            format_edits(source, lines, None, &indent)
+        }))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let indent = self.indent(&params.options);
+        Ok(self.with_source(&params.text_document.uri, |source, lines| {
+            let start = lines.offset(source, params.range.start.into());
+            let end = lines.offset(source, params.range.end.into());
+            format_edits(source, lines, Some(start..end), &indent)
+        }))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> jsonrpc::Result<Option<Vec<InlayHint>>> {
+        Ok(self.with_program(&params.text_document.uri, |source, lines, program| {
+            let start = lines.offset(source, params.range.start.into());
+            let end = lines.offset(source, params.range.end.into());
+            analysis::inlay_hints(source, program)
+                .into_iter()
+                .filter(|hint| (start..=end).contains(&hint.idx))
+                .map(|hint| {
+                    let (kind, padding_left, padding_right) = match hint.kind {
+                        analysis::InlayHintKind::Parameter => {
+                            (InlayHintKind::PARAMETER, false, true)
+                        }
+                        analysis::InlayHintKind::Storage => (InlayHintKind::TYPE, true, false),
+                    };
+                    InlayHint {
+                        position: lines.position(source, hint.idx).into(),
+                        label: InlayHintLabel::String(hint.label),
+                        kind: Some(kind),
+                        text_edits: None,
+                        tooltip: None,
+                        padding_left: Some(padding_left),
+                        padding_right: Some(padding_right),
+                        data: None,
+                    }
+                })
+                .collect()
+        }))
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> jsonrpc::Result<Option<DocumentSymbolResponse>> {
+        Ok(self.with_program(&params.text_document.uri, |source, lines, program| {
+            let symbols = analysis::document_symbols(source, program);
+            DocumentSymbolResponse::Nested(
+                symbols
+                    .into_iter()
+                    .map(|symbol| get_document_symbol(source, lines, symbol))
+                    .collect(),
+            )
+        }))
+    }
+
+    async fn folding_range(
+        &self,
+        params: FoldingRangeParams,
+    ) -> jsonrpc::Result<Option<Vec<FoldingRange>>> {
+        Ok(self.with_program(&params.text_document.uri, |source, _, program| {
+            analysis::folding_ranges(source, program)
+                .into_iter()
+                .map(|range| FoldingRange {
+                    start_line: range.start_line,
+                    end_line: range.end_line,
+                    ..Default::default()
+                })
+                .collect()
+        }))
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> jsonrpc::Result<Option<Vec<SelectionRange>>> {
+        Ok(self.with_program(&params.text_document.uri, |source, lines, program| {
+            params
+                .positions
+                .iter()
+                .map(|&position| {
+                    let idx = lines.offset(source, position.into());
+                    let empty =
+                        SelectionRange { range: Range::new(position, position), parent: None };
+                    // Build the chain from the outermost node inwards, since
+                    // each range points to its parent.
+                    analysis::selection_ranges(program, idx)
+                        .into_iter()
+                        .rev()
+                        .fold(None, |parent, span| {
+                            Some(SelectionRange {
+                                range: get_range(source, lines, &span),
+                                parent: parent.map(Box::new),
+                            })
+                        })
+                        .unwrap_or(empty)
+                })
+                .collect()
+        }))
+    }
+}
+
+/// Runs `f`, and returns [`None`] if it panics, so that a bug in handling one
+/// request does not take down the server. The panic is still printed to
+/// stderr, which editors keep as the log of the server.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).ok()
+}
+
+impl From<analysis::Position> for Position {
+    fn from(position: analysis::Position) -> Self {
+        Position { line: position.line, character: position.character }
+    }
+}
+
+impl From<Position> for analysis::Position {
+    fn from(position: Position) -> Self {
+        analysis::Position { line: position.line, character: position.character }
     }
 }
 
-fn get_range(source: &str, span: &Span) -> Range {
-    Range { start: get_position(source, span.start), end: get_position(source, span.end) }
+#[allow(deprecated)]
+fn get_document_symbol(
+    source: &str,
+    lines: &LineIndex,
+    symbol: analysis::DocumentSymbol,
+) -> DocumentSymbol {
+    let kind = match symbol.kind {
+        analysis::DocumentSymbolKind::Class => SymbolKind::CLASS,
+        analysis::DocumentSymbolKind::Constant => SymbolKind::CONSTANT,
+        analysis::DocumentSymbolKind::Function => SymbolKind::FUNCTION,
+        analysis::DocumentSymbolKind::Method => SymbolKind::METHOD,
+        analysis::DocumentSymbolKind::Variable => SymbolKind::VARIABLE,
+    };
+    let children = symbol
+        .children
+        .into_iter()
+        .map(|child| get_document_symbol(source, lines, child))
+        .collect();
+    DocumentSymbol {
+        name: symbol.name,
+        detail: None,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: Range { start: symbol.start.into(), end: symbol.end.into() },
+        selection_range: get_range(source, lines, &symbol.name_span),
+        children: Some(children),
+    }
+}
+
+fn format_edits(
+    source: &str,
+    lines: &LineIndex,
+    span: Option<Span>,
+    indent: &str,
+) -> Vec<TextEdit> {
+    analysis::format_edits(source, span, indent)
+        .into_iter()
+        .map(|edit| TextEdit { range: get_range(source, lines, &edit.span), new_text: edit.text })
+        .collect()
 }
 
-fn get_position(source: &str, idx: usize) -> Position {
-    let before = &source[..idx];
-    let line = before.lines().count() - 1;
-    let character = before.lines().last().unwrap().len();
-    Position { line: line as _, character: character as _ }
+fn get_range(source: &str, lines: &LineIndex, span: &Span) -> Range {
+    let span = lines.resolve(source, span);
+    Range { start: span.start.into(), end: span.end.into() }
 }
 
-pub fn serve() -> Result<()> {
-    tokio::runtime::Builder::new_current_thread()
+/// How the language server communicates with its client.
+#[derive(Clone, Copy, Debug)]
+pub enum Transport {
+    Stdio,
+    /// Listens for TCP connections, and serves each one with a separate
+    /// language server.
+    Tcp(SocketAddr),
+    /// Listens for WebSocket connections, where each text message carries one
+    /// JSON-RPC message, without the headers of the LSP base protocol. This is
+    /// for clients like web IDEs, which cannot spawn processes.
+    WebSocket(SocketAddr),
+}
+
+pub fn serve(transport: Transport, timings: bool, lint_options: LintOptions) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
-        .context("failed to start async runtime")?
-        .block_on(serve_async());
-    Ok(())
+        .context("failed to start async runtime")?;
+    let result = runtime.block_on(serve_async(transport, timings, lint_options));
+    // Stdin is read on a blocking thread, which cannot be cancelled. Waiting
+    // for it would keep the server running after the client sends `exit`,
+    // until the client also closes stdin.
+    runtime.shutdown_background();
+    result
+}
+
+async fn serve_async(transport: Transport, timings: bool, lint_options: LintOptions) -> Result<()> {
+    let addr = match transport {
+        Transport::Stdio => {
+            let stdin = tokio::io::stdin();
+            let stdout = tokio::io::stdout();
+            let (service, socket) =
+                LspService::new(|client| Backend::new(client, timings, lint_options));
+            let exited = Arc::new(Notify::new());
+            let socket = ExitSocket { socket, exited: Arc::clone(&exited) };
+            let server = Server::new(stdin, stdout, socket).serve(service);
+            // tower-lsp only stops once it reads another message after `exit`,
+            // which the client never sends.
+            future::select(pin!(server), pin!(exited.notified())).await;
+            return Ok(());
+        }
+        Transport::Tcp(addr) | Transport::WebSocket(addr) => addr,
+    };
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("could not listen on address: {addr}"))?;
+    let addr = listener.local_addr().context("could not get local address")?;
+    match transport {
+        Transport::WebSocket(_) => tracing::info!("listening on ws://{addr}"),
+        _ => tracing::info!("listening on tcp://{addr}"),
+    }
+
+    loop {
+        let (stream, peer) = listener.accept().await.context("could not accept connection")?;
+        let lint_options = lint_options.clone();
+        tokio::spawn(async move {
+            let result = match transport {
+                Transport::WebSocket(_) => serve_ws(stream, timings, lint_options).await,
+                _ => {
+                    let (read, write) = stream.into_split();
+                    let (service, socket) =
+                        LspService::new(|client| Backend::new(client, timings, lint_options));
+                    Server::new(read, write, socket).serve(service).await;
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                tracing::error!("connection from {peer} failed: {e:?}");
+            }
+        });
+    }
+}
+
+/// Wraps the socket of a language server to notify `exited` once the server
+/// receives `exit`, which ends the stream of requests to the client.
+struct ExitSocket {
+    socket: ClientSocket,
+    exited: Arc<Notify>,
+}
+
+impl Loopback for ExitSocket {
+    type RequestStream = BoxStream<'static, jsonrpc::Request>;
+    type ResponseSink = <ClientSocket as Loopback>::ResponseSink;
+
+    fn split(self) -> (Self::RequestStream, Self::ResponseSink) {
+        let (requests, responses) = self.socket.split();
+        let exited = self.exited;
+        let exit = stream::once(async move { exited.notify_one() });
+        (requests.chain(exit.filter_map(|()| future::ready(None))).boxed(), responses)
+    }
+}
+
+/// Serves a WebSocket connection by translating between WebSocket messages
+/// and the LSP base protocol, which tower-lsp expects.
+async fn serve_ws(stream: TcpStream, timings: bool, lint_options: LintOptions) -> Result<()> {
+    let ws = tokio_tungstenite::accept_async(stream).await.context("WebSocket handshake failed")?;
+    let (mut ws_write, mut ws_read) = ws.split();
+
+    let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+    let (server_read, server_write) = tokio::io::split(server_io);
+    let (client_read, mut client_write) = tokio::io::split(client_io);
+
+    // When the client disconnects, `client_write` is dropped, which shuts down
+    // the server, which in turn ends `outgoing`.
+    let incoming = async move {
+        while let Some(message) = ws_read.next().await {
+            let text = match message.context("could not read WebSocket message")? {
+                Message::Text(text) => text,
+                Message::Binary(bytes) => String::from_utf8(bytes).context("invalid message")?,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let header = format!("Content-Length: {}\r\n\r\n", text.len());
+            client_write.write_all(header.as_bytes()).await?;
+            client_write.write_all(text.as_bytes()).await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    let outgoing = async move {
+        let mut reader = BufReader::new(client_read);
+        let mut line = String::new();
+        loop {
+            let mut content_length = None;
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).await? == 0 {
+                    return Ok(());
+                }
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("Content-Length:") {
+                    content_length = Some(value.trim().parse::<usize>()?);
+                }
+            }
+
+            let content_length = content_length.context("missing Content-Length header")?;
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).await?;
+            let text = String::from_utf8(body).context("invalid message")?;
+            ws_write.send(Message::Text(text)).await.context("could not send message")?;
+        }
+    };
+
+    let (service, socket) = LspService::new(|client| Backend::new(client, timings, lint_options));
+    let server = Server::new(server_read, server_write, socket).serve(service);
+
+    let (incoming, (), outgoing) = future::join3(incoming, server, outgoing).await;
+    incoming.and(outgoing)
 }
 
-async fn serve_async() {
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
 
-    let (service, socket) = LspService::new(Backend::new);
-    Server::new(stdin, stdout, socket).serve(service).await;
+    use super::*;
+
+    #[test]
+    fn settings() {
+        let settings = Settings {
+            strict: true,
+            lints: LintOptions { shadowing: true, ..Default::default() },
+            max_diagnostics: Some(10),
+            format: FormatSettings { on_type: false, indent: Some("\t".to_string()) },
+            run: RunSettings { on_save: true, ..Default::default() },
+        };
+        let value = json!({
+            "strict": true,
+            "lints": { "shadowing": true },
+            "maxDiagnostics": 10,
+            "format": { "onType": false, "indent": "\t" },
+            "run": { "onSave": true },
+        });
+        assert_eq!(Settings::parse(&value).unwrap(), settings);
+        assert_eq!(Settings::parse(&json!({ "lox": value })).unwrap(), settings);
+
+        assert_eq!(Settings::parse(&Value::Null).unwrap(), Settings::default());
+        assert_eq!(Settings::parse(&json!({ "lox": null })).unwrap(), Settings::default());
+        assert_eq!(Settings::parse(&json!({})).unwrap(), Settings::default());
+        assert!(Settings::parse(&json!({ "strict": "yes" })).is_err());
+    }
 }
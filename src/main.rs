@@ -1,7 +1,18 @@
+use std::panic;
+
 use anyhow::Result;
 use clap::Parser;
-use loxcraft::cmd::Cmd;
+use loxcraft::cmd::Cli;
 
 fn main() -> Result<()> {
-    Cmd::parse().run()
+    // No input should make loxcraft panic, so a panic is always a bug.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        eprintln!(
+            "note: this is a bug in loxcraft, please report it at {}/issues",
+            env!("CARGO_PKG_REPOSITORY")
+        );
+    }));
+    Cli::parse().run()
 }
@@ -0,0 +1,243 @@
+//! Size and complexity measures of each function in a program, reported by
+//! `lox check --metrics`. These are meant for finding functions that are worth
+//! splitting up, and for grading exercises, not as a measure of correctness.
+
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::syntax::ast::{Expr, ExprS, Node, OpInfix, Program, Stmt, StmtFun, StmtS};
+use crate::types::{LineIndex, Span};
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FunctionMetrics {
+    /// The name of the function, prefixed with its class if it is a method.
+    /// Lambdas are named `lambda`.
+    pub name: String,
+    /// The one-indexed line on which the function starts.
+    pub line: u32,
+    /// The number of lines that the function spans.
+    pub lines: u32,
+    /// How deeply the function nests control flow: 0 for a function without
+    /// any, 1 for one with an `if` or a loop, 2 for a loop in a loop, and so
+    /// on. An `else if` is as deep as the `if` it follows.
+    pub depth: usize,
+    /// The cyclomatic complexity of the function: one more than the number of
+    /// places where it branches, which are `if`, `while`, `for`, `catch`,
+    /// `and` and `or`. Nested functions are measured on their own, and do not
+    /// add to this.
+    pub complexity: usize,
+}
+
+/// The metrics of the functions in a file.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileMetrics {
+    pub path: String,
+    pub functions: Vec<FunctionMetrics>,
+}
+
+/// Measures every function, method and lambda in `program`, in the order in
+/// which they start.
+pub fn functions(source: &str, program: &Program) -> Vec<FunctionMetrics> {
+    let mut walker = Walker { lines: LineIndex::new(source), functions: Vec::new() };
+    let mut script = Counts::default();
+    for stmt in &program.stmts {
+        walker.stmt(stmt, 0, &mut script);
+    }
+    walker.functions
+}
+
+/// Formats the metrics of `files` as a table, with a row for each function.
+pub fn to_table(files: &[FileMetrics]) -> String {
+    let rows = files
+        .iter()
+        .flat_map(|file| {
+            file.functions
+                .iter()
+                .map(|function| (format!("{}:{}", file.path, function.line), function))
+        })
+        .collect::<Vec<_>>();
+    let location =
+        rows.iter().map(|(location, _)| location.len()).fold("location".len(), usize::max);
+    let function = rows.iter().map(|(_, f)| f.name.len()).fold("function".len(), usize::max);
+
+    let mut table = format!(
+        "{:<location$}  {:<function$}  {:>5}  {:>5}  {:>10}\n",
+        "location", "function", "lines", "depth", "complexity"
+    );
+    for (loc, metrics) in &rows {
+        let _ = writeln!(
+            table,
+            "{loc:<location$}  {:<function$}  {:>5}  {:>5}  {:>10}",
+            metrics.name, metrics.lines, metrics.depth, metrics.complexity
+        );
+    }
+    table
+}
+
+#[derive(Default)]
+struct Counts {
+    depth: usize,
+    complexity: usize,
+}
+
+struct Walker {
+    lines: LineIndex,
+    functions: Vec<FunctionMetrics>,
+}
+
+impl Walker {
+    fn fun(&mut self, name: String, fun: &StmtFun, span: &Span) {
+        let start = self.lines.line(span.start) as u32;
+        let end = self.lines.line(span.end) as u32;
+        let idx = self.functions.len();
+        self.functions.push(FunctionMetrics {
+            name,
+            line: start + 1,
+            lines: end - start + 1,
+            depth: 0,
+            complexity: 0,
+        });
+
+        let mut counts = Counts { depth: 0, complexity: 1 };
+        for default in &fun.defaults {
+            self.expr(default, &mut counts);
+        }
+        for stmt in &fun.body.stmts {
+            self.stmt(stmt, 0, &mut counts);
+        }
+        self.functions[idx].depth = counts.depth;
+        self.functions[idx].complexity = counts.complexity;
+    }
+
+    /// Walks a statement that is nested `depth` levels deep in control flow.
+    fn stmt(&mut self, (stmt, span): &StmtS, depth: usize, counts: &mut Counts) {
+        counts.depth = counts.depth.max(depth);
+        match stmt {
+            Stmt::Assert(assert) => self.expr(&assert.value, counts),
+            Stmt::Block(block) => {
+                block.stmts.iter().for_each(|stmt| self.stmt(stmt, depth, counts))
+            }
+            Stmt::Class(class) => {
+                class.super_.iter().for_each(|super_| self.expr(super_, counts));
+                for (method, span) in &class.methods {
+                    self.fun(format!("{}.{}", class.name, method.name), method, span);
+                }
+            }
+            Stmt::Expr(expr) => self.expr(&expr.value, counts),
+            Stmt::Extend(extend) => {
+                self.expr(&extend.class, counts);
+                let class = match &extend.class.0 {
+                    Expr::Var(var) => var.var.name.as_str(),
+                    _ => "",
+                };
+                for (method, span) in &extend.methods {
+                    self.fun(format!("{class}.{}", method.name), method, span);
+                }
+            }
+            Stmt::For(for_) => {
+                counts.complexity += 1;
+                for_.init.iter().for_each(|init| self.stmt(init, depth, counts));
+                for_.cond.iter().chain(&for_.incr).for_each(|expr| self.expr(expr, counts));
+                self.stmt(&for_.body, depth + 1, counts);
+            }
+            Stmt::Fun(fun) => self.fun(fun.name.to_string(), fun, span),
+            Stmt::If(if_) => {
+                counts.complexity += 1;
+                self.expr(&if_.cond, counts);
+                self.stmt(&if_.then, depth + 1, counts);
+                match &if_.else_ {
+                    Some(else_ @ (Stmt::If(_), _)) => self.stmt(else_, depth, counts),
+                    Some(else_) => self.stmt(else_, depth + 1, counts),
+                    None => {}
+                }
+            }
+            Stmt::Print(print) => print.values.iter().for_each(|value| self.expr(value, counts)),
+            Stmt::Return(return_) => {
+                return_.value.iter().for_each(|value| self.expr(value, counts))
+            }
+            Stmt::Try(try_) => {
+                self.stmt(&try_.body, depth + 1, counts);
+                if let Some(catch) = &try_.catch {
+                    counts.complexity += 1;
+                    self.stmt(&catch.body, depth + 1, counts);
+                }
+                try_.finally.iter().for_each(|finally| self.stmt(finally, depth + 1, counts));
+            }
+            Stmt::Var(var) => var.value.iter().for_each(|value| self.expr(value, counts)),
+            Stmt::While(while_) => {
+                counts.complexity += 1;
+                self.expr(&while_.cond, counts);
+                self.stmt(&while_.body, depth + 1, counts);
+            }
+            Stmt::Error => {}
+        }
+    }
+
+    fn expr(&mut self, expr: &ExprS, counts: &mut Counts) {
+        match &expr.0 {
+            Expr::Fun(fun) => return self.fun(fun.name.to_string(), fun, &expr.1),
+            Expr::Infix(infix) if matches!(infix.op, OpInfix::LogicAnd | OpInfix::LogicOr) => {
+                counts.complexity += 1;
+            }
+            _ => {}
+        }
+        for child in Node::Expr(expr).children() {
+            if let Node::Expr(child) = child {
+                self.expr(child, counts);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn function_metrics() {
+        let source = r#"fun simple() { print 1; }
+
+fun nested(list) {
+  for (var i = 0; i < len(list); i = i + 1) {
+    if (list[i] > 0 and list[i] < 10) {
+      while (false) {}
+    } else if (list[i] == 0) {
+      print "zero";
+    }
+  }
+  var f = (x) -> x or 0;
+}
+
+class A {
+  m() {
+    try { nil.x; } catch (e) { return; }
+  }
+}
+"#;
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let metrics = functions(source, &program)
+            .into_iter()
+            .map(|f| (f.name, f.line, f.lines, f.depth, f.complexity))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            metrics,
+            [
+                ("simple".to_string(), 1, 1, 0, 1),
+                // for, if, and, else if, while.
+                ("nested".to_string(), 3, 10, 3, 6),
+                ("lambda".to_string(), 11, 1, 0, 2),
+                ("A.m".to_string(), 15, 3, 1, 2),
+            ]
+        );
+
+        let table = to_table(&[FileMetrics {
+            path: "a.lox".to_string(),
+            functions: functions(source, &program),
+        }]);
+        assert_eq!(table.lines().count(), 5);
+        assert!(table.lines().nth(1).unwrap().starts_with("a.lox:1"), "{table}");
+    }
+}
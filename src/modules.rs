@@ -0,0 +1,254 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+
+use crate::project::{Dependency, Package, Project};
+use crate::vfs::{self, FileSystem, MemoryFs, OsFs};
+
+/// The directory, relative to the project root, into which dependencies are
+/// vendored by `lox fetch`.
+pub const MODULES_DIR: &str = "lox_modules";
+
+/// Maps module names to files, by searching a list of directories in order.
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    pub search_path: Vec<PathBuf>,
+}
+
+impl Resolver {
+    /// Resolves modules relative to the project root first, then to its
+    /// vendored dependencies.
+    pub fn for_project(project: &Project) -> Self {
+        Self { search_path: vec![project.root.clone(), project.root.join(MODULES_DIR)] }
+    }
+
+    /// Returns the file for the module `name`. In each directory on the search
+    /// path, this is either `<name>.lox`, or the entry point of the package in
+    /// `<name>/`.
+    pub fn resolve(&self, name: &str) -> Option<PathBuf> {
+        if !is_valid_name(name) {
+            return None;
+        }
+        self.search_path.iter().find_map(|dir| {
+            let file = dir.join(name).with_extension("lox");
+            if file.is_file() {
+                return Some(file);
+            }
+            let dir = dir.join(name);
+            let entry = match Project::load(&dir) {
+                Ok(project) => project.entry(),
+                Err(_) => dir.join(Package::default().entry),
+            };
+            entry.is_file().then_some(entry)
+        })
+    }
+}
+
+/// A file loaded by a [`ModuleLoader`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Module {
+    Source(String),
+    /// A program compiled by `lox compile`.
+    Compiled(Vec<u8>),
+}
+
+/// Finds and reads the files named by `include` directives, so that the host
+/// decides where they come from: [`FsLoader`] reads them from disk, and a
+/// [`MemoryFs`] from files held in memory, such as those of the playground or
+/// of a test.
+pub trait ModuleLoader {
+    /// Returns the name of the file that `path`, in a directive in the file
+    /// `from`, refers to. A file is only included once for each name.
+    fn resolve(&mut self, from: &str, path: &str) -> Result<String>;
+
+    /// Reads the file `name`, as returned by [`ModuleLoader::resolve`].
+    fn load(&mut self, name: &str) -> Result<Module>;
+}
+
+/// Loads files from disk. Paths are relative to the directory of the file
+/// that includes them, and those that do not name a file there are looked up
+/// as modules with the [`Resolver`], if there is one.
+#[derive(Clone, Debug, Default)]
+pub struct FsLoader {
+    pub resolver: Option<Resolver>,
+}
+
+impl ModuleLoader for FsLoader {
+    fn resolve(&mut self, from: &str, path: &str) -> Result<String> {
+        let dir = Path::new(from).parent().unwrap_or(Path::new(""));
+        let file = dir.join(path);
+        let file = match &self.resolver {
+            Some(resolver) if !file.is_file() => resolver.resolve(path).unwrap_or(file),
+            _ => file,
+        };
+        Ok(file.to_string_lossy().into_owned())
+    }
+
+    fn load(&mut self, name: &str) -> Result<Module> {
+        load(&OsFs, name)
+    }
+}
+
+/// Paths are resolved with [`vfs::join`].
+impl ModuleLoader for MemoryFs {
+    fn resolve(&mut self, from: &str, path: &str) -> Result<String> {
+        Ok(vfs::join(from, path))
+    }
+
+    fn load(&mut self, name: &str) -> Result<Module> {
+        load(self, name)
+    }
+}
+
+/// Reads the file `name` from `fs`. Files with a .loxc extension are loaded
+/// as compiled programs.
+fn load(fs: &impl FileSystem, name: &str) -> Result<Module> {
+    if name.ends_with(".loxc") {
+        let bytecode =
+            fs.read(name).with_context(|| format!("could not read program from file: {name}"))?;
+        return Ok(Module::Compiled(bytecode));
+    }
+    let source = fs
+        .read_to_string(name)
+        .with_context(|| format!("could not read source from file: {name}"))?;
+    Ok(Module::Source(source))
+}
+
+/// Vendors every dependency of `project` into its modules directory,
+/// replacing any earlier copies.
+pub fn fetch(project: &Project) -> Result<()> {
+    let modules_dir = project.root.join(MODULES_DIR);
+    for (name, dependency) in &project.manifest.dependencies {
+        if !is_valid_name(name) {
+            bail!("invalid dependency name: {name:?}");
+        }
+
+        let dest = modules_dir.join(name);
+        if dest.exists() {
+            fs::remove_dir_all(&dest)
+                .with_context(|| format!("could not remove directory: {}", dest.display()))?;
+        }
+        fs::create_dir_all(&modules_dir)
+            .with_context(|| format!("could not create directory: {}", modules_dir.display()))?;
+
+        match dependency {
+            Dependency::Path { path } => {
+                let src = project.root.join(path);
+                copy_dir(&src, &dest).with_context(|| {
+                    format!("could not copy dependency {name:?} from: {}", src.display())
+                })?;
+            }
+            Dependency::Git { git, rev } => {
+                let mut clone = Command::new("git");
+                clone.args(["clone", "--quiet"]);
+                if rev.is_none() {
+                    clone.args(["--depth", "1"]);
+                }
+                run_git(clone.arg(git).arg(&dest))
+                    .with_context(|| format!("could not clone dependency {name:?} from: {git}"))?;
+                if let Some(rev) = rev {
+                    run_git(Command::new("git").arg("-C").arg(&dest).args(["checkout", "-q", rev]))
+                        .with_context(|| format!("could not check out {rev:?} of {name:?}"))?;
+                }
+            }
+        }
+        eprintln!("fetched {name}");
+    }
+    Ok(())
+}
+
+/// Module names must be a single path component, so that they cannot refer
+/// to files outside the search path.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn run_git(command: &mut Command) -> Result<()> {
+    let status = command.status().context("could not run git")?;
+    if !status.success() {
+        bail!("git exited with {status}");
+    }
+    Ok(())
+}
+
+/// Copies a directory recursively, skipping version control and the modules
+/// directory of the dependency itself.
+fn copy_dir(src: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == ".git" || name == MODULES_DIR {
+            continue;
+        }
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            copy_dir(&path, &dest.join(&name))?;
+        } else {
+            fs::copy(&path, dest.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn fetch_and_resolve() {
+        let root = env::temp_dir().join(format!("loxcraft-modules-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("app")).unwrap();
+        fs::create_dir_all(root.join("greet/src")).unwrap();
+        fs::write(root.join("greet/src/main.lox"), "").unwrap();
+        fs::write(root.join("app/util.lox"), "").unwrap();
+        fs::write(root.join("app/lox.toml"), "[dependencies]\ngreet = { path = \"../greet\" }")
+            .unwrap();
+
+        let project = Project::load(&root.join("app")).unwrap();
+        fetch(&project).unwrap();
+
+        let resolver = Resolver::for_project(&project);
+        assert_eq!(resolver.resolve("util"), Some(root.join("app/util.lox")));
+        assert_eq!(
+            resolver.resolve("greet"),
+            Some(root.join("app/lox_modules/greet/src/main.lox"))
+        );
+        assert_eq!(resolver.resolve("missing"), None);
+        assert_eq!(resolver.resolve("../app/util"), None);
+
+        // Paths that are not files next to the including file are modules.
+        let mut loader = FsLoader { resolver: Some(resolver) };
+        let from = root.join("app/main.lox").to_string_lossy().into_owned();
+        let resolve = |loader: &mut FsLoader, path| loader.resolve(&from, path).unwrap();
+        assert_eq!(resolve(&mut loader, "util.lox"), root.join("app/util.lox").to_string_lossy());
+        assert_eq!(
+            resolve(&mut loader, "greet"),
+            root.join("app/lox_modules/greet/src/main.lox").to_string_lossy()
+        );
+        let util = resolve(&mut loader, "util");
+        assert_eq!(loader.load(&util).unwrap(), Module::Source(String::new()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn memory_loader() {
+        let mut fs = MemoryFs::default();
+        fs.add_file("lib/a.lox", "var a = 1;");
+        fs.add_file("lib/a.loxc", [0]);
+        assert_eq!(fs.resolve("lib/b.lox", "a.lox").unwrap(), "lib/a.lox");
+        assert_eq!(fs.resolve("main.lox", "../lib/a.lox").unwrap(), "lib/a.lox");
+
+        assert_eq!(fs.load("lib/a.lox").unwrap(), Module::Source("var a = 1;".to_string()));
+        assert_eq!(fs.load("lib/a.loxc").unwrap(), Module::Compiled(vec![0]));
+        assert!(fs.load("lib/b.lox").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+//! Parsing and formatting of numbers, shared by the lexer, the VM and the
+//! tools built on them, so that they all agree on how numbers are written.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Parses a number literal: a decimal number, with an optional fraction and
+/// exponent, or a hexadecimal (`0x`) or binary (`0b`) integer. Digits may be
+/// grouped with underscores. Only a dot is accepted as the decimal separator,
+/// whatever the locale.
+pub fn parse(literal: &str) -> Option<f64> {
+    if let Some(digits) = literal.strip_prefix("0x").or(literal.strip_prefix("0X")) {
+        parse_radix(digits, 16)
+    } else if let Some(digits) = literal.strip_prefix("0b").or(literal.strip_prefix("0B")) {
+        parse_radix(digits, 2)
+    } else if literal.starts_with(|c: char| c.is_ascii_digit()) {
+        literal.replace('_', "").parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Parses an integer literal in the given radix, ignoring underscores. This
+/// converts directly to an [`f64`], so it does not overflow on large inputs.
+fn parse_radix(digits: &str, radix: u32) -> Option<f64> {
+    let mut number = None;
+    for c in digits.chars().filter(|&c| c != '_') {
+        let digit = c.to_digit(radix)?;
+        number = Some(number.unwrap_or(0.0) * radix as f64 + digit as f64);
+    }
+    number
+}
+
+/// Formats `number` as Lox prints it. With a `precision`, the number is
+/// rounded to that many digits after the decimal point, and trailing zeros
+/// are left out, so that whole numbers still print without a fraction.
+pub fn display(number: f64, precision: Option<usize>) -> impl Display {
+    Number { number, precision }
+}
+
+struct Number {
+    number: f64,
+    precision: Option<usize>,
+}
+
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Some(precision) = self.precision else {
+            return write!(f, "{}", self.number);
+        };
+        let rounded = format!("{:.precision$}", self.number);
+        if !rounded.contains('.') {
+            return f.write_str(&rounded);
+        }
+        f.write_str(rounded.trim_end_matches('0').trim_end_matches('.'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_literals() {
+        assert_eq!(parse("1_000.5"), Some(1000.5));
+        assert_eq!(parse("0xff"), Some(255.0));
+        assert_eq!(parse("0b1010"), Some(10.0));
+        assert_eq!(parse("2.5e-3"), Some(2.5e-3));
+        for literal in ["1,5", "1.000.000", "0x", "1e", "inf", "NaN", "+1"] {
+            assert_eq!(parse(literal), None, "{literal}");
+        }
+    }
+
+    #[test]
+    fn display_with_precision() {
+        let display = |number, precision| display(number, precision).to_string();
+        assert_eq!(display(1.0 / 3.0, None), "0.3333333333333333");
+        assert_eq!(display(1.0 / 3.0, Some(3)), "0.333");
+        assert_eq!(display(0.1 + 0.2, Some(10)), "0.3");
+        assert_eq!(display(2.0, Some(3)), "2");
+        assert_eq!(display(2.5, Some(0)), "2");
+        assert_eq!(display(1234.5678, Some(2)), "1234.57");
+        assert_eq!(display(f64::INFINITY, Some(2)), "inf");
+    }
+}
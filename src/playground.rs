@@ -1,32 +1,440 @@
 #![cfg(feature = "playground")]
 
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::fs;
+use std::hash::Hasher;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::{Context as _, Result};
-use rust_embed::Embed;
+use anyhow::{Context as _, Result, bail};
+use rust_embed::{Embed, EmbeddedFile};
+use rustc_hash::FxHasher;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use warp::filters::BoxedFilter;
+use warp::filters::path::{FullPath, Tail};
+use warp::http::header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use warp::http::{HeaderValue, StatusCode};
+use warp::reply::Response;
+use warp::{Filter, Rejection};
 
 #[derive(Debug, Embed)]
 #[folder = "playground/out/"]
 struct Asset;
 
-pub fn serve(port: u16) -> Result<()> {
-    let url = format!("http://127.0.0.1:{port}");
+/// The service worker that caches the playground for offline use, with
+/// placeholders for the version of the cache and the assets to put in it.
+const SERVICE_WORKER: &str = include_str!("../playground/pwa/sw.js");
 
-    let ip_address = Ipv4Addr::new(127, 0, 0, 1);
-    let socket_address = SocketAddrV4::new(ip_address, port);
+/// The port to listen on if none is given. If it is taken, any free port is
+/// used instead.
+pub const DEFAULT_PORT: u16 = 4000;
 
-    let serve = warp_embed::embed(&Asset);
-    let server = warp::serve(serve).run(socket_address);
+#[derive(Clone, Debug, Default)]
+pub struct PlaygroundOptions {
+    /// The port to listen on. Defaults to [`DEFAULT_PORT`], falling back to
+    /// any free port.
+    pub port: Option<u16>,
+    /// Listen on this Unix domain socket instead of a port.
+    pub unix_socket: Option<PathBuf>,
+    /// The path under which the playground is served, e.g. `/lox/` when it
+    /// sits behind a reverse proxy. See [`normalize_base_path`].
+    pub base_path: String,
+    /// The file in which the settings of the editor are kept. If unset, this
+    /// is determined by [`crate::data::playground_settings_path`].
+    pub settings: Option<PathBuf>,
+}
 
-    eprintln!("Running playground on {url}");
-    if let Err(e) = webbrowser::open(&url) {
-        eprintln!("Failed to open browser: {e}");
+/// The themes that the playground's editor can use, the first of which is the
+/// default. The UI bundles each of these.
+pub const THEMES: &[&str] =
+    &["tomorrow_night_bright", "monokai", "dracula", "github", "solarized_light"];
+
+/// The settings of the playground's editor. These are kept by the server,
+/// rather than by each browser, so that they follow the user into every
+/// browser that opens the playground. Unset fields use the defaults of the UI.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// One of [`THEMES`].
+    pub theme: Option<String>,
+    /// A CSS font family, e.g. `"Fira Code", monospace`.
+    pub font_family: Option<String>,
+    /// The size of the font, in pixels.
+    pub font_size: Option<u8>,
+}
+
+impl Settings {
+    /// Reads the settings at `path`, or returns the defaults if there are
+    /// none, or if they cannot be read.
+    pub fn load(path: &Path) -> Self {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                tracing::warn!("could not read settings from {}: {e}", path.display());
+                return Self::default();
+            }
+        };
+        let settings = serde_json::from_str::<Self>(&contents)
+            .map_err(anyhow::Error::from)
+            .and_then(|settings| settings.validate().map(|()| settings));
+        settings.unwrap_or_else(|e| {
+            tracing::warn!("ignoring invalid settings in {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("could not serialize settings")?;
+        fs::write(path, json)
+            .with_context(|| format!("could not write settings: {}", path.display()))
+    }
+
+    /// Checks that the settings can be used by the UI. The font family is
+    /// limited to the characters that font names need, since it ends up in
+    /// CSS.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(theme) = &self.theme {
+            if !THEMES.contains(&theme.as_str()) {
+                bail!("unknown theme: {theme:?}");
+            }
+        }
+        if let Some(family) = &self.font_family {
+            let is_valid_char =
+                |c: char| c.is_ascii_alphanumeric() || matches!(c, ' ' | ',' | '-' | '"' | '\'');
+            if family.trim().is_empty() || family.len() > 100 || !family.chars().all(is_valid_char)
+            {
+                bail!("invalid font family: {family:?}");
+            }
+        }
+        if let Some(size) = self.font_size {
+            if !(8..=48).contains(&size) {
+                bail!("font size must be between 8 and 48, not {size}");
+            }
+        }
+        Ok(())
     }
+}
 
+/// Serves the playground until interrupted with Ctrl-C.
+pub fn serve(options: PlaygroundOptions) -> Result<()> {
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .context("failed to start async runtime")?
-        .block_on(server);
+        .block_on(serve_async(options))
+}
+
+async fn serve_async(options: PlaygroundOptions) -> Result<()> {
+    let base_path = normalize_base_path(&options.base_path);
+    let settings_path = match options.settings {
+        Some(path) => Some(path),
+        None => crate::data::playground_settings_path()
+            .map_err(|e| tracing::warn!("settings will not be saved: {e:#}"))
+            .ok(),
+    };
+    let routes = routes(&base_path, settings_path);
+
+    if let Some(path) = options.unix_socket {
+        return serve_unix(routes, path).await;
+    }
+
+    let bind = |port| {
+        warp::serve(routes.clone())
+            .try_bind_with_graceful_shutdown((Ipv4Addr::LOCALHOST, port), shutdown_signal())
+    };
+    let (addr, server) = match (options.port, bind(options.port.unwrap_or(DEFAULT_PORT))) {
+        (_, Ok(bound)) => bound,
+        (None, Err(_)) => bind(0).context("could not start server")?,
+        (Some(port), Err(e)) => {
+            return Err(e).with_context(|| format!("could not listen on port: {port}"));
+        }
+    };
+
+    let url = format!("http://{addr}{base_path}");
+    tracing::info!("running playground on {url}");
+    if let Err(e) = webbrowser::open(&url) {
+        tracing::warn!("failed to open browser: {e}");
+    }
+
+    server.await;
     Ok(())
 }
+
+#[cfg(unix)]
+async fn serve_unix(routes: BoxedFilter<(Response,)>, path: PathBuf) -> Result<()> {
+    use std::fs;
+    use std::os::unix::fs::FileTypeExt;
+
+    // A socket left behind by a previous run would make binding fail.
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(&path)
+            .with_context(|| format!("could not remove stale socket: {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("could not listen on socket: {}", path.display()))?;
+    let incoming = futures_util::stream::poll_fn(move |cx| {
+        listener.poll_accept(cx).map(|result| Some(result.map(|(stream, _)| stream)))
+    });
+
+    tracing::info!("running playground on unix:{}", path.display());
+    warp::serve(routes).serve_incoming_with_graceful_shutdown(incoming, shutdown_signal()).await;
+    let _ = fs::remove_file(&path);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_: BoxedFilter<(Response,)>, _: PathBuf) -> Result<()> {
+    anyhow::bail!("Unix domain sockets are not supported on this platform")
+}
+
+/// Resolves on Ctrl-C, after which the server finishes the requests in flight
+/// and stops.
+async fn shutdown_signal() {
+    if tokio::signal::ctrl_c().await.is_ok() {
+        tracing::info!("shutting down playground");
+    }
+}
+
+/// Returns `path` with exactly one slash at the start and at the end, so that
+/// it can be joined with asset paths.
+pub fn normalize_base_path(path: &str) -> String {
+    let segments = path.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>();
+    if segments.is_empty() { "/".to_string() } else { format!("/{}/", segments.join("/")) }
+}
+
+/// Serves the embedded assets under `base_path`, which must be normalized,
+/// along with the settings at `settings_path`, which the UI reads with a GET
+/// of `settings` and changes with a PUT.
+fn routes(base_path: &str, settings_path: Option<PathBuf>) -> BoxedFilter<(Response,)> {
+    let mut prefix = warp::any().boxed();
+    for segment in base_path.split('/').filter(|segment| !segment.is_empty()) {
+        prefix = prefix.and(warp::path(segment.to_string())).boxed();
+    }
+
+    let settings_path = Arc::new(settings_path);
+    let settings_route = prefix.clone().and(warp::path("settings")).and(warp::path::end());
+    let get_settings = settings_route.clone().and(warp::get()).map({
+        let settings_path = Arc::clone(&settings_path);
+        move || {
+            let settings = match settings_path.as_deref() {
+                Some(path) => Settings::load(path),
+                None => Settings::default(),
+            };
+            let json = serde_json::to_string(&settings).expect("settings should serialize");
+            let mut response = text_response(json, "application/json");
+            response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+            response
+        }
+    });
+    let put_settings = settings_route
+        .and(warp::put())
+        .and(warp::body::content_length_limit(4096))
+        .and(warp::body::json())
+        .map(move |settings: Settings| {
+            let save = || {
+                let path = settings_path.as_deref().context("settings cannot be saved")?;
+                settings.save(path)
+            };
+            let result = settings
+                .validate()
+                .map_err(|e| (StatusCode::BAD_REQUEST, e))
+                .and_then(|()| save().map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e)));
+            let (status, text) = match result {
+                Ok(()) => (StatusCode::NO_CONTENT, String::new()),
+                Err((status, e)) => (status, format!("{e:#}")),
+            };
+            let mut response = text_response(text, "text/plain");
+            *response.status_mut() = status;
+            response
+        });
+
+    let base_path = Arc::<str>::from(base_path);
+    let assets = prefix.and(warp::path::full()).and(warp::path::tail()).and_then(
+        move |full: FullPath, tail: Tail| {
+            let base_path = Arc::clone(&base_path);
+            async move { serve_asset(&base_path, full.as_str(), tail.as_str()) }
+        },
+    );
+    get_settings.or(put_settings).unify().or(assets).unify().boxed()
+}
+
+fn serve_asset(base_path: &str, full: &str, tail: &str) -> Result<Response, Rejection> {
+    // These are generated rather than embedded, since they depend on the base
+    // path and on every other asset.
+    match tail {
+        "sw.js" => {
+            let mut response = text_response(service_worker(base_path), "application/javascript");
+            // Browsers check for a new version of the worker on every visit.
+            response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            return Ok(response);
+        }
+        "manifest.webmanifest" => {
+            return Ok(text_response(manifest(base_path), "application/manifest+json"));
+        }
+        _ => {}
+    }
+
+    if let Some(file) = Asset::get(tail).filter(|_| !tail.is_empty()) {
+        return Ok(asset_response(file, base_path));
+    }
+
+    // Directories are served by their index, but only with a trailing slash,
+    // so that relative URLs in the index resolve correctly.
+    let dir = tail.trim_end_matches('/');
+    let index = if dir.is_empty() { "index.html".to_string() } else { format!("{dir}/index.html") };
+    let file = Asset::get(&index).ok_or_else(warp::reject::not_found)?;
+    if full.ends_with('/') {
+        return Ok(asset_response(file, base_path));
+    }
+    let mut response = Response::default();
+    *response.status_mut() = StatusCode::MOVED_PERMANENTLY;
+    let location = HeaderValue::from_str(&format!("{full}/")).map_err(|_| warp::reject())?;
+    response.headers_mut().insert(LOCATION, location);
+    Ok(response)
+}
+
+/// Returns the service worker, which caches every embedded asset under a name
+/// that changes whenever any of them do.
+fn service_worker(base_path: &str) -> String {
+    let mut hasher = FxHasher::default();
+    hasher.write(base_path.as_bytes());
+    let mut paths = Vec::new();
+    for path in Asset::iter() {
+        if let Some(file) = Asset::get(&path) {
+            hasher.write(path.as_bytes());
+            hasher.write(&file.metadata.sha256_hash());
+            paths.push(path);
+        }
+    }
+    let assets = precache_urls(base_path, paths.iter().map(|path| path.as_ref()));
+    SERVICE_WORKER
+        .replace("{{VERSION}}", &format!("{:016x}", hasher.finish()))
+        .replace("{{ASSETS}}", &serde_json::to_string(&assets).expect("URLs should serialize"))
+}
+
+/// Returns the URLs under which the assets at `paths` are served, starting
+/// with the index. Indexes are served at their directory instead.
+fn precache_urls<'a>(base_path: &str, paths: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut urls = vec![base_path.to_string()];
+    for path in paths {
+        match path.strip_suffix("index.html") {
+            Some("") => {}
+            Some(dir) if dir.ends_with('/') => urls.push(format!("{base_path}{dir}")),
+            _ => urls.push(format!("{base_path}{path}")),
+        }
+    }
+    urls
+}
+
+/// Returns the web app manifest, which lets browsers install the playground.
+fn manifest(base_path: &str) -> String {
+    let icons = match Asset::get("icon.png") {
+        Some(_) => json!([{ "src": format!("{base_path}icon.png"), "type": "image/png" }]),
+        None => json!([]),
+    };
+    json!({
+        "name": "Loxcraft Playground",
+        "short_name": "Lox",
+        "description": "Run Lox code in your browser.",
+        "start_url": base_path,
+        "scope": base_path,
+        "display": "standalone",
+        "background_color": "#09090b",
+        "theme_color": "#09090b",
+        "icons": icons,
+    })
+    .to_string()
+}
+
+fn text_response(text: String, mimetype: &'static str) -> Response {
+    let mut response = Response::new(text.into());
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static(mimetype));
+    response
+}
+
+/// The assets are built to be served from the root, so when there is a base
+/// path, the absolute URLs of Next.js assets in text files are rewritten to
+/// point under it.
+fn asset_response(file: EmbeddedFile, base_path: &str) -> Response {
+    let mimetype = file.metadata.mimetype();
+    let is_text = mimetype.starts_with("text/") || mimetype == "application/javascript";
+    let body = match std::str::from_utf8(&file.data) {
+        Ok(text) if is_text && base_path != "/" => {
+            text.replace("/_next/", &format!("{base_path}_next/")).into_bytes()
+        }
+        _ => file.data.into_owned(),
+    };
+
+    let mut response = Response::new(body.into());
+    if let Ok(mimetype) = HeaderValue::from_str(mimetype) {
+        response.headers_mut().insert(CONTENT_TYPE, mimetype);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn normalize_base_path() {
+        assert_eq!(super::normalize_base_path(""), "/");
+        assert_eq!(super::normalize_base_path("/"), "/");
+        assert_eq!(super::normalize_base_path("lox"), "/lox/");
+        assert_eq!(super::normalize_base_path("/lox/"), "/lox/");
+        assert_eq!(super::normalize_base_path("//class//lox"), "/class/lox/");
+    }
+
+    #[test]
+    fn settings() {
+        use super::Settings;
+
+        let settings: Settings =
+            serde_json::from_str(r#"{"theme": "monokai", "font_size": 16}"#).unwrap();
+        assert!(settings.validate().is_ok());
+        assert_eq!(settings.font_family, None);
+        assert!(serde_json::from_str::<Settings>(r#"{"colour": "red"}"#).is_err());
+
+        let invalid = [
+            Settings { theme: Some("neon".to_string()), ..Default::default() },
+            Settings { font_size: Some(100), ..Default::default() },
+            Settings { font_family: Some("x; color: red".to_string()), ..Default::default() },
+        ];
+        for settings in invalid {
+            assert!(settings.validate().is_err(), "{settings:?}");
+        }
+
+        let path = std::env::temp_dir().join(format!("playground-{}.json", std::process::id()));
+        let settings = Settings {
+            font_family: Some("\"Fira Code\", monospace".to_string()),
+            ..Default::default()
+        };
+        settings.save(&path).unwrap();
+        assert_eq!(Settings::load(&path), settings);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(Settings::load(&path), Settings::default());
+    }
+
+    #[test]
+    fn precache_urls() {
+        let paths = ["index.html", "worker.js", "docs/index.html", "_next/static/a.wasm"];
+        assert_eq!(
+            super::precache_urls("/lox/", paths.into_iter()),
+            ["/lox/", "/lox/worker.js", "/lox/docs/", "/lox/_next/static/a.wasm"]
+        );
+    }
+
+    #[test]
+    fn offline() {
+        let worker = super::service_worker("/lox/");
+        assert!(!worker.contains("{{"), "{worker}");
+        assert!(worker.contains(r#"const ASSETS = ["/lox/""#), "{worker}");
+
+        let manifest =
+            serde_json::from_str::<serde_json::Value>(&super::manifest("/lox/")).unwrap();
+        assert_eq!(manifest["start_url"], "/lox/");
+        assert_eq!(manifest["scope"], "/lox/");
+    }
+}
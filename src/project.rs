@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// The name of the file that marks the root of a project.
+pub const MANIFEST_FILE: &str = "lox.toml";
+
+/// The contents of a `lox.toml` file. Every field is optional, so an empty
+/// file is a valid manifest.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default)]
+    pub package: Package,
+    /// Modules that the project depends on, keyed by the name they are
+    /// imported as.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, Dependency>,
+    #[serde(default, skip_serializing_if = "Lints::is_empty")]
+    pub lints: Lints,
+    #[serde(default, skip_serializing_if = "Fmt::is_default")]
+    pub fmt: Fmt,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Package {
+    pub name: Option<String>,
+    /// The file that `lox run` runs when it is not given a path.
+    pub entry: PathBuf,
+    /// The directories that `lox check` checks when it is not given a path.
+    pub src: Vec<PathBuf>,
+    /// Report accesses to undefined globals at compile time.
+    pub strict: bool,
+    /// Require conditions to be bools, and `==` to compare values of the same
+    /// type. See [`VmOptions::strict_types`](crate::vm::VmOptions::strict_types).
+    pub strict_types: bool,
+}
+
+impl Default for Package {
+    fn default() -> Self {
+        Self {
+            name: None,
+            entry: PathBuf::from("src/main.lox"),
+            src: vec![PathBuf::from("src")],
+            strict: false,
+            strict_types: false,
+        }
+    }
+}
+
+/// The lints that `lox check` enables for the project, as well as those given
+/// on the command line. See [`LintOptions`](crate::lint::LintOptions).
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lints {
+    pub shadowing: bool,
+    pub use_before_init: bool,
+    pub private_members: bool,
+    pub nested_this: bool,
+    pub constant_conditions: bool,
+}
+
+impl Lints {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The settings that `lox fmt` formats the project with, unless they are
+/// overridden on the command line.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fmt {
+    /// The number of spaces to indent by.
+    pub indent_width: usize,
+}
+
+impl Fmt {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for Fmt {
+    fn default() -> Self {
+        Self { indent_width: 2 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum Dependency {
+    Path { path: PathBuf },
+    Git { git: String, rev: Option<String> },
+}
+
+/// A directory containing a `lox.toml` file.
+#[derive(Clone, Debug)]
+pub struct Project {
+    pub root: PathBuf,
+    pub manifest: Manifest,
+}
+
+impl Project {
+    /// Finds the project containing `path`, by searching it and its ancestors
+    /// for a `lox.toml` file.
+    pub fn discover(path: &Path) -> Result<Option<Self>> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("could not resolve path: {}", path.display()))?;
+        for dir in path.ancestors() {
+            let manifest_path = dir.join(MANIFEST_FILE);
+            if manifest_path.is_file() {
+                return Self::load(dir).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn load(root: &Path) -> Result<Self> {
+        let path = root.join(MANIFEST_FILE);
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("could not read manifest: {}", path.display()))?;
+        let manifest = toml::from_str(&contents)
+            .with_context(|| format!("could not parse manifest: {}", path.display()))?;
+        Ok(Self { root: root.to_path_buf(), manifest })
+    }
+
+    pub fn entry(&self) -> PathBuf {
+        self.root.join(&self.manifest.package.entry)
+    }
+
+    pub fn src(&self) -> Vec<PathBuf> {
+        self.manifest.package.src.iter().map(|dir| self.root.join(dir)).collect()
+    }
+}
+
+/// Creates a new project in a directory called `name`.
+pub fn new(name: &Path) -> Result<()> {
+    if name.exists() {
+        bail!("destination already exists: {}", name.display());
+    }
+
+    let package_name = name.file_name().map(|name| name.to_string_lossy().into_owned());
+    let manifest = Manifest {
+        package: Package { name: package_name, ..Default::default() },
+        ..Default::default()
+    };
+    let manifest = toml::to_string(&manifest).context("could not serialize manifest")?;
+
+    let entry = name.join(&Package::default().entry);
+    let src = entry.parent().expect("entry should be inside a directory");
+    fs::create_dir_all(src)
+        .with_context(|| format!("could not create directory: {}", src.display()))?;
+    fs::write(name.join(MANIFEST_FILE), manifest)
+        .with_context(|| format!("could not write manifest in: {}", name.display()))?;
+    fs::write(&entry, "print \"Hello, world!\";\n")
+        .with_context(|| format!("could not write file: {}", entry.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_manifest() {
+        let manifest: Manifest = toml::from_str("").unwrap();
+        assert_eq!(manifest, Manifest::default());
+
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [package]
+            name = "hello"
+            strict = true
+
+            [fmt]
+            indent_width = 4
+
+            [dependencies]
+            local = { path = "../local" }
+            remote = { git = "https://example.com/remote.git", rev = "v1" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.package.name.as_deref(), Some("hello"));
+        assert_eq!(manifest.package.entry, PathBuf::from("src/main.lox"));
+        assert!(manifest.package.strict);
+        assert_eq!(manifest.fmt, Fmt { indent_width: 4 });
+        assert_eq!(manifest.dependencies["local"], Dependency::Path { path: "../local".into() });
+        assert_eq!(
+            manifest.dependencies["remote"],
+            Dependency::Git {
+                git: "https://example.com/remote.git".to_string(),
+                rev: Some("v1".to_string())
+            }
+        );
+
+        assert!(toml::from_str::<Manifest>("[package]\nentrypoint = \"main.lox\"").is_err());
+    }
+}
@@ -1,33 +1,226 @@
 #![cfg(feature = "repl")]
 
 use std::borrow::Cow;
-use std::io;
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::{Arc, Mutex};
+use std::{env, fs, vec};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use nu_ansi_term::{Color, Style};
 use reedline::{
-    EditCommand, Emacs, FileBackedHistory, KeyCode, KeyModifiers, PromptEditMode,
-    PromptHistorySearch, Reedline, ReedlineEvent, Signal, StyledText, ValidationResult,
+    DefaultHinter, EditCommand, Emacs, FileBackedHistory, KeyCode, KeyModifiers, ListMenu,
+    MenuBuilder, PromptEditMode, PromptHistorySearch, Reedline, ReedlineEvent, ReedlineMenu,
+    ReedlineRawEvent, Signal, StyledText, ValidationResult,
 };
-use tree_sitter_highlight::{self, HighlightConfiguration, HighlightEvent};
-use tree_sitter_lox::{self, HIGHLIGHTS_QUERY};
+use serde::Deserialize;
 
-use crate::vm::VM;
+use crate::error::ErrorS;
+use crate::stats::Stats;
+use crate::syntax::Open;
+use crate::syntax::ast::Stmt;
+use crate::theme::Theme;
+use crate::vm::{Snapshot, VM, VmOptions, inspect};
 
-pub fn run() -> Result<()> {
-    let mut vm = VM::default();
-    let mut editor = editor().context("could not start REPL")?;
-    let stdout = &mut io::stdout().lock();
+/// Configuration for the REPL.
+#[derive(Debug)]
+pub struct ReplOptions {
+    /// Record usage statistics for this session in the data directory.
+    pub stats: bool,
+    /// The file in which history is stored. If unset, this is determined by
+    /// [`crate::data::history_path`].
+    pub history: Option<PathBuf>,
+    /// Keep history in memory only, so that it is forgotten on exit. This is
+    /// also done, with a warning, when the history file cannot be opened.
+    pub no_history: bool,
+    /// The file from which to load the syntax highlighting theme. If unset,
+    /// this is determined by [`crate::data::theme_path`].
+    pub theme: Option<PathBuf>,
+    /// The prompt shown before each input.
+    pub prompt: String,
+    /// The prompt shown before each continuation line of an incomplete input.
+    pub continuation_prompt: String,
+    /// The text shown when the REPL starts. If unset, this is the version of
+    /// loxcraft and a hint on how to exit.
+    pub banner: Option<String>,
+    /// Don't show the banner.
+    pub quiet: bool,
+    /// Read plain lines from stdin instead of using a line editor. This is
+    /// also done when stdin or stdout is not a terminal.
+    pub no_editor: bool,
+    /// Read input from this file instead, echoing each prompt and line of
+    /// input to stdout, so that the output is a transcript of the session.
+    pub script: Option<PathBuf>,
+}
+
+impl Default for ReplOptions {
+    fn default() -> Self {
+        Self {
+            stats: false,
+            history: None,
+            no_history: false,
+            theme: None,
+            prompt: ">>> ".to_string(),
+            continuation_prompt: "... ".to_string(),
+            banner: None,
+            quiet: false,
+            no_editor: false,
+            script: None,
+        }
+    }
+}
+
+/// Defaults for the REPL, read from [`CONFIG_FILE`](crate::data::CONFIG_FILE)
+/// in the data directory. Flags given on the command line override these.
+#[derive(Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplConfig {
+    pub history: Option<PathBuf>,
+    pub no_history: bool,
+    pub theme: Option<PathBuf>,
+    pub prompt: Option<String>,
+    pub continuation_prompt: Option<String>,
+    pub banner: Option<String>,
+    pub quiet: bool,
+}
+
+impl ReplConfig {
+    /// Reads the configuration file, if one has been created.
+    pub fn load() -> Result<Self> {
+        let Some(path) = crate::data::config_path() else {
+            return Ok(Self::default());
+        };
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("could not read REPL config: {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid REPL config: {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config = toml::from_str::<Self>(contents)?;
+        if config.no_history && config.history.is_some() {
+            bail!("history cannot be set along with no_history");
+        }
+        if let Some(history) = config.history.as_ref().filter(|history| history.is_dir()) {
+            bail!("history is a directory: {}", history.display());
+        }
+        for (name, prompt) in
+            [("prompt", &config.prompt), ("continuation_prompt", &config.continuation_prompt)]
+        {
+            if prompt.as_deref().is_some_and(|prompt| prompt.contains('\n')) {
+                bail!("{name} cannot contain a newline");
+            }
+        }
+        Ok(config)
+    }
+
+    /// Returns the options that this configures, with defaults for the rest.
+    pub fn into_options(self) -> ReplOptions {
+        let defaults = ReplOptions::default();
+        ReplOptions {
+            history: self.history,
+            no_history: self.no_history,
+            theme: self.theme,
+            prompt: self.prompt.unwrap_or(defaults.prompt),
+            continuation_prompt: self.continuation_prompt.unwrap_or(defaults.continuation_prompt),
+            banner: self.banner,
+            quiet: self.quiet,
+            ..defaults
+        }
+    }
+}
+
+pub fn run(options: ReplOptions, vm_options: VmOptions) -> Result<()> {
+    let mut vm = VM::new(vm_options);
+    let mut editor = if let Some(script) = &options.script {
+        let source = fs::read_to_string(script)
+            .with_context(|| format!("could not read script: {}", script.display()))?;
+        Editor::script(&source)
+    } else if options.no_editor || !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+        Editor::Plain
+    } else {
+        Editor::Reedline(Box::new(editor(&options).context("could not start REPL")?))
+    };
+    let stdout = &mut Stdout { inner: io::stdout().lock(), at_line_start: true };
     let stderr = &mut io::stderr().lock();
+    let prompt = Prompt {
+        prompt: options.prompt.clone(),
+        continuation_prompt: options.continuation_prompt.clone(),
+    };
+
+    if !options.quiet {
+        match &options.banner {
+            Some(banner) => eprintln!("{banner}"),
+            None => {
+                eprintln!("loxcraft {}", env!("CARGO_PKG_VERSION"));
+                eprintln!("Press Ctrl+D to exit.");
+            }
+        }
+    }
+
+    let mut stats = if options.stats {
+        let mut stats = Stats::load()?;
+        stats.start_session();
+        Some(stats)
+    } else {
+        None
+    };
 
+    // The globals before each input, most recent last, for :undo.
+    let mut undo = Vec::new();
+    let mut definitions = Definitions::default();
+    let mut history_failed = false;
     loop {
-        let line = editor.read_line(&Prompt);
-        editor.sync_history().context("could not sync history file")?;
+        let line = editor.read_line(&prompt, stdout);
+        if let Editor::Reedline(editor) = &mut editor {
+            // The history file may stop being writable during the session,
+            // which is only worth a warning, once.
+            if let Err(e) = editor.sync_history() {
+                if !history_failed {
+                    eprintln!("warning: could not save history: {e}");
+                    history_failed = true;
+                }
+            }
+        }
 
         match line {
+            Ok(Signal::Success(line)) if line.trim_start().starts_with(':') => {
+                let command = &line.trim_start()[1..];
+                let errors = run_command(&mut vm, &mut undo, &mut definitions, command, stdout)
+                    .context("could not write to stdout")?;
+                if editor.shows_prompt() {
+                    stdout.end_line().context("could not write to stdout")?;
+                }
+                if let Some(errors) = errors {
+                    report_errors(&vm, &editor, &errors, stdout, stderr)?;
+                }
+            }
             Ok(Signal::Success(line)) => {
-                if let Err(errors) = vm.run(&line, stdout) {
-                    crate::error::report_errors(stderr, &vm.source, &errors)
+                undo.push(vm.snapshot_globals());
+                if undo.len() > UNDO_LIMIT {
+                    vm.drop_snapshot(undo.remove(0));
+                }
+                let result = vm.run(&line, stdout);
+                definitions.record(&line);
+                // The prompt is drawn where the cursor is, so it would follow
+                // output that did not end its line.
+                if editor.shows_prompt() {
+                    stdout.end_line().context("could not write to stdout")?;
+                }
+                if let Some(timings) = &vm.timings {
+                    eprintln!("{timings}");
+                }
+                if let Some(stats) = &mut stats {
+                    let session = stats.current_session();
+                    session.record_command();
+                    for (error, _) in result.as_ref().err().into_iter().flatten() {
+                        session.record_error(error);
+                    }
+                    stats.save()?;
+                }
+                if let Err(errors) = result {
+                    report_errors(&vm, &editor, &errors, stdout, stderr)?;
                 }
             }
             Ok(Signal::CtrlC) => eprintln!("^C"),
@@ -42,127 +235,478 @@ pub fn run() -> Result<()> {
     Ok(())
 }
 
-fn editor() -> Result<Reedline> {
+fn report_errors(
+    vm: &VM,
+    editor: &Editor,
+    errors: &[ErrorS],
+    stdout: &mut impl Write,
+    stderr: &mut impl Write,
+) -> Result<()> {
+    // Plain input usually comes from another program, which would not expect
+    // escape codes.
+    let mut buffer = match editor {
+        Editor::Reedline(_) => termcolor::Buffer::ansi(),
+        Editor::Plain | Editor::Script(_) => termcolor::Buffer::no_color(),
+    };
+    for error in errors {
+        crate::error::report_error(&mut buffer, &vm.source, error);
+    }
+    // Errors are part of the transcript of a script.
+    match editor {
+        Editor::Script(_) => {
+            stdout.write_all(buffer.as_slice()).context("could not write to stdout")
+        }
+        _ => stderr.write_all(buffer.as_slice()).context("could not write to stderr"),
+    }
+}
+
+/// Returns whether `input` is ready to run, or needs more lines. Commands
+/// always fit on a single line.
+fn is_complete(input: &str) -> bool {
+    input.trim_start().starts_with(':') || crate::syntax::is_complete(input)
+}
+
+/// The commands understood by [`run_command`], with their usage.
+const COMMANDS: &[(&str, &str)] = &[
+    (":clear", "forget every definition, as if the REPL had just started"),
+    (":edit [name]", "change a function or class in $EDITOR, by default the last one"),
+    (":globals", "list every global, including the natives and error classes"),
+    (":help", "show this list of commands"),
+    (":precision [N|off]", "show or set how many decimal places numbers print with"),
+    (":trace [on|off]", "show or set whether each op is logged as it runs"),
+    (":type <expr>", "evaluate an expression, and show the type of its value"),
+    (":undo", "forget the definitions and changes made by the last input"),
+    (":vars", "list the globals defined so far, with their types and values"),
+];
+
+/// The number of inputs that :undo can go back through.
+const UNDO_LIMIT: usize = 32;
+
+/// Runs a REPL command, i.e. a line starting with `:`. Lox code never starts
+/// with a colon, so these cannot be confused with programs. If the command
+/// runs code that fails, this returns the errors.
+fn run_command(
+    vm: &mut VM,
+    undo: &mut Vec<Snapshot>,
+    definitions: &mut Definitions,
+    command: &str,
+    stdout: &mut impl Write,
+) -> io::Result<Option<Vec<ErrorS>>> {
+    let command = command.trim();
+    let (name, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    match (name, arg.trim()) {
+        ("clear", "") => {
+            *vm = VM::new(vm.options.clone());
+            undo.clear();
+            *definitions = Definitions::default();
+        }
+        ("edit", name) => {
+            let name = if name.is_empty() { definitions.last.as_deref() } else { Some(name) };
+            let Some((name, source)) =
+                name.and_then(|name| Some((name.to_string(), definitions.sources.get(name)?)))
+            else {
+                match name {
+                    Some(name) => eprintln!("no function or class named {name} was defined"),
+                    None => eprintln!("no function or class has been defined yet"),
+                }
+                return Ok(None);
+            };
+            let source = match edit(&name, source) {
+                Ok(Some(source)) => source,
+                Ok(None) => {
+                    eprintln!("{name} was not changed");
+                    return Ok(None);
+                }
+                Err(e) => {
+                    eprintln!("error: {e:?}");
+                    return Ok(None);
+                }
+            };
+            undo.push(vm.snapshot_globals());
+            if undo.len() > UNDO_LIMIT {
+                vm.drop_snapshot(undo.remove(0));
+            }
+            let result = vm.run(&source, stdout);
+            definitions.record(&source);
+            if let Err(errors) = result {
+                return Ok(Some(errors));
+            }
+        }
+        ("help", "") => {
+            for (usage, description) in COMMANDS {
+                writeln!(stdout, "{usage:<20}{description}")?;
+            }
+        }
+        ("precision", "") => match vm.options.precision {
+            Some(precision) => eprintln!("precision is {precision}"),
+            None => eprintln!("precision is off"),
+        },
+        ("precision", "off") => vm.options.precision = None,
+        ("precision", arg) => match arg.parse() {
+            Ok(precision) => vm.options.precision = Some(precision),
+            Err(_) => eprintln!("usage: :precision [N|off]"),
+        },
+        ("trace", "") => eprintln!("trace is {}", if vm.options.trace { "on" } else { "off" }),
+        ("trace", "on") => vm.options.trace = true,
+        ("trace", "off") => vm.options.trace = false,
+        ("trace", _) => eprintln!("usage: :trace [on|off]"),
+        ("type", "") => eprintln!("usage: :type <expr>"),
+        ("type", expr) => {
+            // The expression is run as a statement, which may already end
+            // with a semicolon.
+            let expr = expr.strip_suffix(';').unwrap_or(expr);
+            match vm.eval(&format!("{expr};"), stdout) {
+                Ok(value) => writeln!(stdout, "{}", value.type_())?,
+                Err(errors) => return Ok(Some(errors)),
+            }
+        }
+        ("globals", "") => {
+            let mut globals = vm.globals().collect::<Vec<_>>();
+            globals.sort_unstable_by_key(|&(name, _)| name);
+            for (name, value) in globals {
+                writeln!(stdout, "{name}: {} = {}", value.type_(), inspect(value))?;
+            }
+        }
+        ("vars", "") => {
+            for (name, value) in vm.defined_globals() {
+                writeln!(stdout, "{name}: {} = {}", value.type_(), inspect(value))?;
+            }
+        }
+        ("undo", "") => match undo.pop() {
+            Some(snapshot) => {
+                vm.restore(snapshot);
+                vm.drop_snapshot(snapshot);
+            }
+            None => eprintln!("nothing to undo"),
+        },
+        ("clear" | "globals" | "help" | "undo" | "vars", _) => eprintln!("usage: :{name}"),
+        _ => eprintln!("unknown command: :{command}, see :help"),
+    }
+    Ok(None)
+}
+
+/// The source of each function and class defined at the top level of an
+/// input, so that :edit can open it again.
+#[derive(Debug, Default)]
+struct Definitions {
+    sources: HashMap<String, String>,
+    /// The name defined most recently, which :edit opens when given none.
+    last: Option<String>,
+}
+
+impl Definitions {
+    /// Records the definitions in `input`. Inputs that do not parse defined
+    /// nothing, since they did not run.
+    fn record(&mut self, input: &str) {
+        let Ok(program) = crate::syntax::parse(input, 0) else {
+            return;
+        };
+        for (stmt, span) in program.stmts {
+            let name = match &stmt {
+                Stmt::Class(class) => class.name,
+                Stmt::Fun(fun) => fun.name,
+                _ => continue,
+            };
+            self.sources.insert(name.to_string(), input[span].to_string());
+            self.last = Some(name.to_string());
+        }
+    }
+}
+
+/// Opens `source` in the editor named by `$VISUAL` or `$EDITOR`, or `vi` if
+/// neither is set, and returns the source once the editor exits, or [`None`]
+/// if it was not changed.
+fn edit(name: &str, source: &str) -> Result<Option<String>> {
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_default();
+    // Editors are often given with their flags, like `code --wait`.
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+
+    let path = env::temp_dir().join(format!("loxcraft-{}-{name}.lox", process::id()));
+    fs::write(&path, format!("{source}\n"))
+        .with_context(|| format!("could not write to file: {}", path.display()))?;
+    let status = Command::new(program).args(words).arg(&path).status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    let status = status.with_context(|| format!("could not run editor: {program}"))?;
+    if !status.success() {
+        bail!("editor exited with {status}");
+    }
+    let edited = edited.with_context(|| format!("could not read file: {}", path.display()))?;
+    let edited = edited.trim_end();
+    Ok((edited != source).then(|| edited.to_string()))
+}
+
+/// Stdout for programs run in the REPL, which tracks whether the output ended
+/// its last line.
+struct Stdout<W> {
+    inner: W,
+    at_line_start: bool,
+}
+
+impl<W: Write> Stdout<W> {
+    /// Ends the last line of output, if it was not already.
+    fn end_line(&mut self) -> io::Result<()> {
+        if !self.at_line_start {
+            self.write_all(b"\n")?;
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Stdout<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        if let Some(&last) = buf[..len].last() {
+            self.at_line_start = last == b'\n';
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads input for the REPL.
+enum Editor {
+    /// A line editor with highlighting, history, and auto-indentation.
+    Reedline(Box<Reedline>),
+    /// Reads plain lines from stdin, without writing any escape codes, so
+    /// that the REPL can be driven by other programs.
+    Plain,
+    /// Reads the lines of a script, echoing each one after its prompt, as if
+    /// it had been typed.
+    Script(vec::IntoIter<String>),
+}
+
+impl Editor {
+    fn script(source: &str) -> Self {
+        Editor::Script(source.lines().map(str::to_string).collect::<Vec<_>>().into_iter())
+    }
+
+    /// Whether a prompt is shown after the output of each input, which must
+    /// then end its line.
+    fn shows_prompt(&self) -> bool {
+        !matches!(self, Editor::Plain)
+    }
+
+    /// Reads the next input. Scripts echo their input to `stdout`.
+    fn read_line(&mut self, prompt: &Prompt, stdout: &mut impl Write) -> io::Result<Signal> {
+        match self {
+            Editor::Reedline(editor) => editor.read_line(prompt),
+            Editor::Script(lines) => {
+                let mut input = String::new();
+                for line in lines {
+                    // Blank lines between inputs are kept, to space out the
+                    // transcript as the script is.
+                    if input.is_empty() && line.trim().is_empty() {
+                        writeln!(stdout)?;
+                        continue;
+                    }
+                    let prompt =
+                        if input.is_empty() { &prompt.prompt } else { &prompt.continuation_prompt };
+                    writeln!(stdout, "{}", format!("{prompt}{line}").trim_end())?;
+                    input.push_str(&line);
+                    input.push('\n');
+                    if is_complete(&input) {
+                        return Ok(Signal::Success(input));
+                    }
+                }
+                Ok(if input.is_empty() { Signal::CtrlD } else { Signal::Success(input) })
+            }
+            Editor::Plain => {
+                // Prompts would only get mixed into the output of programs
+                // that pipe input into the REPL.
+                let show_prompt = io::stdin().is_terminal();
+                let mut input = String::new();
+                loop {
+                    if show_prompt {
+                        let prompt = if input.is_empty() {
+                            &prompt.prompt
+                        } else {
+                            &prompt.continuation_prompt
+                        };
+                        eprint!("{prompt}");
+                    }
+                    if io::stdin().read_line(&mut input)? == 0 {
+                        // An incomplete input at the end is still run, so that
+                        // its errors are reported.
+                        return Ok(if input.trim().is_empty() {
+                            Signal::CtrlD
+                        } else {
+                            Signal::Success(input)
+                        });
+                    }
+                    if is_complete(&input) {
+                        return Ok(Signal::Success(input));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn editor(options: &ReplOptions) -> Result<Reedline> {
+    let buffer = SharedBuffer::default();
+
     let mut keybindings = reedline::default_emacs_keybindings();
     keybindings.add_binding(
         KeyModifiers::ALT,
         KeyCode::Enter,
         ReedlineEvent::Edit(vec![EditCommand::InsertNewline]),
     );
-    let edit_mode = Box::new(Emacs::new(keybindings));
+    keybindings.add_binding(
+        KeyModifiers::CONTROL,
+        KeyCode::Char('r'),
+        ReedlineEvent::UntilFound(vec![
+            ReedlineEvent::Menu(HISTORY_MENU.to_string()),
+            ReedlineEvent::MenuPageNext,
+        ]),
+    );
+    let edit_mode =
+        Box::new(AutoIndent { inner: Emacs::new(keybindings), buffer: Arc::clone(&buffer) });
 
-    let highlighter = Box::new(Highlighter::new()?);
+    let theme = Theme::load(options.theme.as_deref())?;
+    let highlighter =
+        Box::new(Highlighter { inner: crate::highlight::Highlighter::new(theme)?, buffer });
 
-    let data_dir = dirs::data_dir().context("could not find data directory")?;
-    let history_path = data_dir.join("loxcraft/history.txt");
-    let history = Box::new(
-        FileBackedHistory::with_file(10000, history_path.clone())
-            .with_context(|| format!("could not open history file: {}", history_path.display()))?,
-    );
+    let history = Box::new(history(options)?);
+
+    let hinter = Box::new(DefaultHinter::default().with_style(Style::new().fg(Color::DarkGray)));
+    let history_menu =
+        ReedlineMenu::HistoryMenu(Box::new(ListMenu::default().with_name(HISTORY_MENU)));
 
     let validator = Box::new(Validator);
 
     let editor = Reedline::create()
+        .use_bracketed_paste(true)
         .with_edit_mode(edit_mode)
         .with_highlighter(highlighter)
+        .with_hinter(hinter)
         .with_history(history)
+        .with_menu(history_menu)
         .with_validator(validator);
     Ok(editor)
 }
 
-#[derive(Debug)]
-struct PaletteItem<'a> {
-    name: &'a str,
-    fg: Color,
-}
-
-// Color scheme inspired by base16-google-dark.
-//
-// The base16 style guide tells you which base16 color code to use for each
-// language construct:
-// https://github.com/chriskempson/base16/blob/39fb23df970d4d6190d000271dec260250986012/styling.md
-//
-// The base16-vim theme contains the 8-bit ANSI codes associated with each
-// base16 color code (assume we are not working in a 256-color terminal):
-// https://github.com/chriskempson/base16-vim/blob/c156b909af619cdd097d8d1e2cd1dce1f45dfba1/colors/base16-google-dark.vim#L52
-//
-// This page gives you an idea of what color is associated with a particular
-// 8-bit ANSI code:
-// https://en.wikipedia.org/wiki/ANSI_escape_code#8-bit
-//
-// Since this color scheme makes use of both Red and LightRed, we replace
-// LightRed with LightCyan to better distinguish between the two.
-//
-// Then, we replace each color with its high-intensity variant, since the
-// standard colors can be harder to read on some terminals.
-//
-const PALETTE: &[PaletteItem] = &[
-    PaletteItem { name: "", fg: Color::LightGray },
-    PaletteItem { name: "class", fg: Color::LightYellow },
-    PaletteItem { name: "comment", fg: Color::DarkGray },
-    PaletteItem { name: "constant", fg: Color::LightCyan },
-    PaletteItem { name: "function", fg: Color::LightBlue },
-    PaletteItem { name: "keyword", fg: Color::LightPurple },
-    PaletteItem { name: "operator", fg: Color::LightGray },
-    PaletteItem { name: "punctuation", fg: Color::LightGray },
-    PaletteItem { name: "string", fg: Color::LightGreen },
-    PaletteItem { name: "variable", fg: Color::LightRed },
-];
+/// The most inputs that history keeps.
+const HISTORY_CAPACITY: usize = 10000;
 
-struct Highlighter {
-    config: HighlightConfiguration,
+/// Returns the history file, or if it cannot be opened, or history is turned
+/// off, a history that is forgotten when the REPL exits.
+fn history(options: &ReplOptions) -> Result<FileBackedHistory> {
+    if !options.no_history {
+        let path = match &options.history {
+            Some(path) => Ok(path.clone()),
+            None => crate::data::history_path(),
+        };
+        let history = path.and_then(|path| {
+            FileBackedHistory::with_file(HISTORY_CAPACITY, path.clone())
+                .with_context(|| format!("could not open history file: {}", path.display()))
+        });
+        match history {
+            Ok(history) => return Ok(history),
+            Err(e) => eprintln!("warning: {e:#}, so history will not be saved"),
+        }
+    }
+    FileBackedHistory::new(HISTORY_CAPACITY).context("could not create history")
 }
 
-impl Highlighter {
-    pub fn new() -> Result<Self> {
-        let highlight_names = PALETTE.iter().map(|item| item.name).collect::<Vec<_>>();
-        let mut config =
-            HighlightConfiguration::new(tree_sitter_lox::language(), HIGHLIGHTS_QUERY, "", "")
-                .context("failed to create highlight configuration")?;
-        config.configure(&highlight_names);
-        Ok(Self { config })
-    }
+/// The name of the menu used to search history.
+const HISTORY_MENU: &str = "history_menu";
+
+struct Highlighter {
+    inner: crate::highlight::Highlighter,
+    buffer: SharedBuffer,
 }
 
 impl reedline::Highlighter for Highlighter {
-    fn highlight(&self, line: &str, _: usize) -> StyledText {
+    fn highlight(&self, line: &str, cursor: usize) -> StyledText {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.line.clear();
+            buffer.line.push_str(line);
+            buffer.cursor = cursor;
+        }
+
         let mut output = StyledText::new();
+        for (fg, text) in self.inner.highlight(line) {
+            output.push((Style::new().fg(fg), text.to_string()));
+        }
+        output
+    }
+}
 
-        let mut highlighter = tree_sitter_highlight::Highlighter::new();
-        let Ok(highlights) = highlighter.highlight(&self.config, line.as_bytes(), None, |_| None)
-        else {
-            let style = Style::new().fg(PALETTE[0].fg);
-            output.push((style, line.to_string()));
-            return output;
-        };
+const INDENT: &str = "    ";
 
-        let mut curr_fg = PALETTE[0].fg;
-        let mut curr_end = 0;
+/// The contents of the line buffer, as last rendered by the [`Highlighter`].
+///
+/// Reedline does not give edit modes access to the line buffer, but the
+/// buffer is highlighted every time it is repainted, which happens after each
+/// keypress. This lets [`AutoIndent`] see the buffer as it was just before
+/// the current keypress.
+#[derive(Debug, Default)]
+struct BufferState {
+    line: String,
+    cursor: usize,
+}
 
-        for event in highlights {
-            match event {
-                Ok(HighlightEvent::HighlightStart(highlight)) => {
-                    curr_fg = PALETTE[highlight.0].fg;
-                }
-                Ok(HighlightEvent::Source { start, end }) => {
-                    let style = Style::new().fg(curr_fg);
-                    let text = line[start..end].to_string();
-                    output.push((style, text));
-                    curr_end = end;
-                }
-                Ok(HighlightEvent::HighlightEnd) => {
-                    curr_fg = PALETTE[0].fg;
+type SharedBuffer = Arc<Mutex<BufferState>>;
+
+/// An edit mode that indents continuation lines based on the number of open
+/// brackets, and dedents closing braces typed at the start of a line.
+struct AutoIndent {
+    inner: Emacs,
+    buffer: SharedBuffer,
+}
+
+impl reedline::EditMode for AutoIndent {
+    fn parse_event(&mut self, event: ReedlineRawEvent) -> ReedlineEvent {
+        let event = self.inner.parse_event(event);
+        let Ok(buffer) = self.buffer.lock() else {
+            return event;
+        };
+        let before_cursor = buffer.line.get(..buffer.cursor).unwrap_or(&buffer.line);
+
+        match event {
+            // Submitting a complete input is left to the validator.
+            ReedlineEvent::Enter if !crate::syntax::is_complete(&buffer.line) => {
+                // Indenting inside a string would change its contents.
+                let partial = crate::syntax::parse_partial(before_cursor);
+                let indent = match partial.open.last() {
+                    Some((Open::String, _)) => String::new(),
+                    _ => INDENT.repeat(partial.open.len()),
+                };
+                ReedlineEvent::Multiple(vec![
+                    ReedlineEvent::Enter,
+                    ReedlineEvent::Edit(vec![EditCommand::InsertString(indent)]),
+                ])
+            }
+            ReedlineEvent::Edit(commands) if commands == [EditCommand::InsertChar('}')] => {
+                let line_start = before_cursor.rfind('\n').map_or(0, |idx| idx + 1);
+                let current_indent = &before_cursor[line_start..];
+                if current_indent.is_empty() || !current_indent.chars().all(|c| c == ' ') {
+                    return ReedlineEvent::Edit(commands);
                 }
-                Err(_) => {
-                    let style = Style::new().fg(PALETTE[0].fg);
-                    let text = line.get(curr_end..).unwrap_or_default().to_string();
-                    output.push((style, text));
-                    break;
+                let partial = crate::syntax::parse_partial(before_cursor);
+                if let Some((Open::String, _)) = partial.open.last() {
+                    return ReedlineEvent::Edit(commands);
                 }
+
+                let depth = partial.open.len().saturating_sub(1);
+                let mut commands = vec![EditCommand::Backspace; current_indent.len()];
+                commands.push(EditCommand::InsertString(INDENT.repeat(depth)));
+                commands.push(EditCommand::InsertChar('}'));
+                ReedlineEvent::Edit(commands)
             }
+            event => event,
         }
+    }
 
-        output
+    fn edit_mode(&self) -> PromptEditMode {
+        self.inner.edit_mode()
     }
 }
 
@@ -171,20 +715,19 @@ struct Validator;
 
 impl reedline::Validator for Validator {
     fn validate(&self, line: &str) -> ValidationResult {
-        if crate::syntax::is_complete(line) {
-            ValidationResult::Complete
-        } else {
-            ValidationResult::Incomplete
-        }
+        if is_complete(line) { ValidationResult::Complete } else { ValidationResult::Incomplete }
     }
 }
 
 #[derive(Debug)]
-pub struct Prompt;
+pub struct Prompt {
+    prompt: String,
+    continuation_prompt: String,
+}
 
 impl reedline::Prompt for Prompt {
     fn render_prompt_left(&self) -> Cow<str> {
-        Cow::Borrowed(">>> ")
+        Cow::Borrowed(&self.prompt)
     }
 
     fn render_prompt_right(&self) -> Cow<str> {
@@ -196,10 +739,63 @@ impl reedline::Prompt for Prompt {
     }
 
     fn render_prompt_multiline_indicator(&self) -> Cow<str> {
-        Cow::Borrowed("... ")
+        Cow::Borrowed(&self.continuation_prompt)
     }
 
     fn render_prompt_history_search_indicator(&self, _: PromptHistorySearch) -> Cow<str> {
         Cow::Borrowed("")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn record_definitions() {
+        let mut definitions = Definitions::default();
+        definitions.record("fun f(a) {\n  return a;\n}\nvar x = 1;\nclass C < B { m() {} }");
+        assert_eq!(definitions.sources["f"], "fun f(a) {\n  return a;\n}");
+        assert_eq!(definitions.sources["C"], "class C < B { m() {} }");
+        assert!(!definitions.sources.contains_key("x"));
+        assert_eq!(definitions.last.as_deref(), Some("C"));
+
+        definitions.record("fun f() { return 1; } print f(");
+        assert_eq!(definitions.sources["f"], "fun f(a) {\n  return a;\n}");
+    }
+
+    #[test]
+    fn script() {
+        let prompt = Prompt { prompt: ">>> ".to_string(), continuation_prompt: "... ".to_string() };
+        let mut editor = Editor::script("var a = 1;\n\nfun f() {\n\n  return a;\n}\nprint f(");
+        let mut transcript = Vec::new();
+        let mut inputs = Vec::new();
+        while let Signal::Success(input) = editor.read_line(&prompt, &mut transcript).unwrap() {
+            inputs.push(input);
+        }
+        assert_eq!(inputs, ["var a = 1;\n", "fun f() {\n\n  return a;\n}\n", "print f(\n"]);
+        assert_eq!(
+            String::from_utf8(transcript).unwrap(),
+            ">>> var a = 1;\n\n>>> fun f() {\n...\n...   return a;\n... }\n>>> print f(\n"
+        );
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = ReplConfig::parse("prompt = \"lox> \"\nquiet = true").unwrap();
+        let options = config.into_options();
+        assert_eq!(options.prompt, "lox> ");
+        assert_eq!(options.continuation_prompt, "... ");
+        assert!(options.quiet);
+
+        let error = |contents| ReplConfig::parse(contents).unwrap_err().to_string();
+        assert!(error("promt = \"> \"").contains("unknown field `promt`"));
+        assert_eq!(error("prompt = \"a\\nb\""), "prompt cannot contain a newline");
+        assert_eq!(
+            error("history = \"h.txt\"\nno_history = true"),
+            "history cannot be set along with no_history"
+        );
+    }
+}
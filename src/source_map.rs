@@ -0,0 +1,192 @@
+//! A program made of several files, which are run as one source.
+//!
+//! `lox run a.lox b.lox` concatenates its files, so that they share globals
+//! and are compiled together. Spans in errors and warnings are offsets into
+//! the concatenated source, and a [`SourceMap`] maps them back to the file
+//! they came from, so that diagnostics name that file.
+
+use std::collections::HashSet;
+
+use anyhow::{Context, Result, bail};
+
+use crate::modules::{Module, ModuleLoader};
+use crate::types::Span;
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    source: String,
+    files: Vec<File>,
+    /// The pieces of files that make up the source, in order. A file is split
+    /// into several pieces where it includes another.
+    segments: Vec<Segment>,
+    /// The names of the files that have been included, so that each is only
+    /// included once.
+    included: HashSet<String>,
+}
+
+#[derive(Debug)]
+struct File {
+    name: String,
+    source: String,
+}
+
+#[derive(Debug)]
+struct Segment {
+    /// The span of the segment in the concatenated source.
+    span: Span,
+    file: usize,
+    /// Where the segment starts in its file.
+    offset: usize,
+}
+
+impl SourceMap {
+    /// Appends a file to the program. Each file is followed by a newline, so
+    /// that a comment on its last line does not run into the next file.
+    pub fn add(&mut self, name: impl Into<String>, source: &str) {
+        let file = self.push_file(name.into(), source);
+        self.push_segment(file, 0..source.len());
+        self.source.push('\n');
+    }
+
+    /// Appends a file like [`SourceMap::add`], replacing each directive found
+    /// by [`includes`](crate::syntax::includes) with the file that `loader`
+    /// resolves it to. Each file is only included once, so later directives
+    /// for it, and cycles of files that include each other, are left out.
+    pub fn add_with_includes(
+        &mut self,
+        name: impl Into<String>,
+        source: &str,
+        loader: &mut impl ModuleLoader,
+    ) -> Result<()> {
+        let name = name.into();
+        self.included.insert(name.clone());
+        self.add_included(name, source, loader)?;
+        self.source.push('\n');
+        Ok(())
+    }
+
+    fn add_included(
+        &mut self,
+        name: String,
+        source: &str,
+        loader: &mut impl ModuleLoader,
+    ) -> Result<()> {
+        let file = self.push_file(name, source);
+        let mut offset = 0;
+        for (path, directive) in crate::syntax::includes(source) {
+            self.push_segment(file, offset..directive.start);
+            offset = directive.end;
+            let from = self.files[file].name.clone();
+            let context = || format!("could not include file in {from}: {path}");
+            let name = loader.resolve(&from, &path).with_context(context)?;
+            if !self.included.insert(name.clone()) {
+                continue;
+            }
+            let source = match loader.load(&name).with_context(context)? {
+                Module::Source(source) => source,
+                Module::Compiled(_) => {
+                    bail!("could not include {name}: compiled programs cannot be included")
+                }
+            };
+            // A file that is cut short would otherwise swallow the rest of
+            // the file that includes it.
+            if !crate::syntax::is_complete(&source) {
+                bail!("could not include {name}: the file ends partway through a statement");
+            }
+            self.add_included(name, &source, loader)?;
+            self.source.push('\n');
+        }
+        self.push_segment(file, offset..source.len());
+        Ok(())
+    }
+
+    fn push_file(&mut self, name: String, source: &str) -> usize {
+        self.files.push(File { name, source: source.to_string() });
+        self.files.len() - 1
+    }
+
+    fn push_segment(&mut self, file: usize, span: Span) {
+        let start = self.source.len();
+        self.source.push_str(&self.files[file].source[span.clone()]);
+        self.segments.push(Segment { span: start..self.source.len(), file, offset: span.start });
+    }
+
+    /// The source of the whole program.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the name and source of the file that `span` starts in, and
+    /// `span` relative to that file. Spans that run past the end of the file,
+    /// or into a file that it includes, are cut short.
+    pub fn locate(&self, span: &Span) -> (&str, &str, Span) {
+        let idx = self.segments.partition_point(|segment| segment.span.start <= span.start);
+        let Some(segment) = self.segments.get(idx.saturating_sub(1)) else {
+            return ("<script>", "", 0..0);
+        };
+        let file = &self.files[segment.file];
+        let start = span.start.min(segment.span.end);
+        let end = span.end.clamp(start, segment.span.end);
+        let offset = segment.offset;
+        (
+            &file.name,
+            &file.source,
+            start - segment.span.start + offset..end - segment.span.start + offset,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::vfs::MemoryFs;
+
+    #[test]
+    fn locate() {
+        let mut map = SourceMap::default();
+        map.add("a.lox", "var a = 1;");
+        map.add("b.lox", "print a;\nprint b;");
+        assert_eq!(map.source(), "var a = 1;\nprint a;\nprint b;\n");
+
+        assert_eq!(map.locate(&(4..5)), ("a.lox", "var a = 1;", 4..5));
+        assert_eq!(map.locate(&(17..18)), ("b.lox", "print a;\nprint b;", 6..7));
+        // A span that runs into the next file stays within the first.
+        assert_eq!(map.locate(&(8..14)), ("a.lox", "var a = 1;", 8..10));
+        // The newline after the last file belongs to it.
+        assert_eq!(map.locate(&(28..28)), ("b.lox", "print a;\nprint b;", 17..17));
+        assert_eq!(SourceMap::default().locate(&(0..1)), ("<script>", "", 0..0));
+    }
+
+    #[test]
+    fn locate_includes() {
+        let files = [("a.lox", "include \"b.lox\";\nprint b;"), ("b.lox", "var b = 1; // b")];
+        let mut loader = MemoryFs::default();
+        for (name, source) in files {
+            loader.add_file(name, source);
+        }
+        let mut map = SourceMap::default();
+        map.add_with_includes("a.lox", files[0].1, &mut loader).unwrap();
+        // The directive is replaced by the file, on a line of its own.
+        assert_eq!(map.source(), "var b = 1; // b\n\nprint b;\n");
+
+        assert_eq!(map.locate(&(4..5)), ("b.lox", files[1].1, 4..5));
+        assert_eq!(map.locate(&(23..24)), ("a.lox", files[0].1, 23..24));
+
+        // A file that includes itself is only included once.
+        let mut map = SourceMap::default();
+        let source = "include \"a.lox\"; print 1;";
+        map.add_with_includes("a.lox", source, &mut loader).unwrap();
+        assert_eq!(map.source(), " print 1;\n");
+
+        // Compiled programs are run by themselves, and cannot be included.
+        loader.add_file("c.loxc", Vec::new());
+        let mut map = SourceMap::default();
+        let error = map.add_with_includes("a.lox", "include \"c.loxc\";", &mut loader).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "could not include c.loxc: compiled programs cannot be included"
+        );
+    }
+}
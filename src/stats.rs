@@ -0,0 +1,93 @@
+#![cfg(feature = "repl")]
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+/// Usage statistics collected by the REPL when run with `--stats`. These are
+/// only ever stored locally, in the data directory.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Stats {
+    pub sessions: Vec<Session>,
+}
+
+impl Stats {
+    pub fn load() -> Result<Self> {
+        let path = crate::data::stats_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("could not read stats file: {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("could not parse stats file: {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = crate::data::stats_path()?;
+        let contents = serde_json::to_string_pretty(self).context("could not serialize stats")?;
+        fs::write(&path, contents)
+            .with_context(|| format!("could not write stats file: {}", path.display()))
+    }
+
+    pub fn start_session(&mut self) {
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.sessions.push(Session { started_at, ..Default::default() });
+    }
+
+    pub fn current_session(&mut self) -> &mut Session {
+        self.sessions.last_mut().expect("tried to access a session before starting one")
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let commands = self.sessions.iter().map(|session| session.commands).sum::<u64>();
+
+        let mut errors = BTreeMap::<&str, u64>::new();
+        for session in &self.sessions {
+            for (name, count) in &session.errors {
+                *errors.entry(name).or_default() += count;
+            }
+        }
+        let mut errors = errors.into_iter().collect::<Vec<_>>();
+        errors.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        writeln!(f, "sessions: {}", self.sessions.len())?;
+        writeln!(f, "commands: {commands}")?;
+        writeln!(f, "errors:   {}", errors.iter().map(|(_, count)| count).sum::<u64>())?;
+
+        let width = errors.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+        for (name, count) in errors {
+            writeln!(f, "  {name:width$}  {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Session {
+    /// The time at which the session was started, in seconds since the Unix
+    /// epoch.
+    pub started_at: u64,
+    /// The number of commands run in the session.
+    pub commands: u64,
+    /// The number of errors encountered in the session, keyed by error type.
+    pub errors: BTreeMap<String, u64>,
+}
+
+impl Session {
+    pub fn record_command(&mut self) {
+        self.commands += 1;
+    }
+
+    pub fn record_error(&mut self, error: &Error) {
+        *self.errors.entry(error.name().to_string()).or_default() += 1;
+    }
+}
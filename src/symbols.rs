@@ -0,0 +1,416 @@
+//! Resolves every variable in a program to the declaration it refers to,
+//! without compiling it. Editor features like highlighting, renaming and
+//! hover are built on the resulting [`SymbolTable`].
+
+use std::collections::HashMap;
+
+use crate::syntax::ast::{Expr, ExprS, Name, Program, Stmt, StmtFun, StmtS};
+use crate::syntax::lexer::{Lexer, Token};
+use crate::types::Span;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SymbolKind {
+    Class,
+    Function,
+    Parameter,
+    Variable,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    pub name: Name,
+    pub kind: SymbolKind,
+    /// The span of the name where the symbol is declared. This is [`None`] for
+    /// globals that are used but never declared, such as natives.
+    pub decl: Option<Span>,
+    /// Whether the symbol is a global, rather than local to a block or
+    /// function.
+    pub is_global: bool,
+    /// The function in which the symbol is declared, where 0 is the top level
+    /// of the script and every other function is numbered in the order in
+    /// which it appears.
+    pub function: usize,
+    /// The names of the parameters, if the symbol is a function, or a class
+    /// with an initializer.
+    pub params: Vec<Name>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// A use of a symbol, other than its declaration.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reference {
+    /// The index of the symbol in [`SymbolTable::symbols`].
+    pub symbol: usize,
+    /// The span of the name.
+    pub span: Span,
+    pub access: Access,
+    /// The function in which the reference appears, numbered like
+    /// [`Symbol::function`].
+    pub function: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    pub symbols: Vec<Symbol>,
+    pub references: Vec<Reference>,
+}
+
+impl SymbolTable {
+    pub fn new(source: &str, program: &Program) -> Self {
+        let mut resolver = Resolver { source, ..Default::default() };
+        resolver.declare_globals(&program.stmts);
+        program.stmts.iter().for_each(|stmt| resolver.resolve_stmt(stmt));
+        resolver.table
+    }
+
+    /// Returns the symbol whose name is at `idx`, either in its declaration or
+    /// in a reference to it.
+    pub fn symbol_at(&self, idx: usize) -> Option<usize> {
+        let contains = |span: &Span| span.start <= idx && idx <= span.end;
+        self.references
+            .iter()
+            .find(|reference| contains(&reference.span))
+            .map(|reference| reference.symbol)
+            .or_else(|| {
+                self.symbols.iter().position(|symbol| symbol.decl.as_ref().is_some_and(contains))
+            })
+    }
+
+    pub fn references_to(&self, symbol: usize) -> impl Iterator<Item = &Reference> {
+        self.references.iter().filter(move |reference| reference.symbol == symbol)
+    }
+
+    /// Returns true if `symbol` is a local that is used by a function nested
+    /// inside the one that declares it, so that it lives on as an upvalue.
+    pub fn is_captured(&self, symbol: usize) -> bool {
+        let declared_in = self.symbols[symbol].function;
+        !self.symbols[symbol].is_global
+            && self.references_to(symbol).any(|reference| reference.function != declared_in)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Resolver<'a> {
+    source: &'a str,
+    table: SymbolTable,
+    globals: HashMap<Name, usize>,
+    /// The local scopes enclosing the current node, innermost last. Each maps
+    /// names to symbols.
+    scopes: Vec<HashMap<Name, usize>>,
+    /// The function being resolved.
+    function: usize,
+    /// The number of functions seen so far.
+    functions: usize,
+}
+
+impl Resolver<'_> {
+    /// Globals can be used before they are declared, e.g. in the body of a
+    /// function, so they are all declared up front.
+    fn declare_globals(&mut self, stmts: &[StmtS]) {
+        for (stmt, span) in stmts {
+            let (name, kind) = match stmt {
+                Stmt::Class(class) => (class.name, SymbolKind::Class),
+                Stmt::Fun(fun) => (fun.name, SymbolKind::Function),
+                Stmt::Var(var) => (var.var.name, SymbolKind::Variable),
+                _ => continue,
+            };
+            if !self.globals.contains_key(&name) {
+                let decl = self.name_spans(span, 1).pop();
+                let symbol = self.add_symbol(name, kind, decl, true);
+                self.table.symbols[symbol].params = params(stmt);
+                self.globals.insert(name, symbol);
+            }
+        }
+    }
+
+    fn resolve_stmt(&mut self, (stmt, span): &StmtS) {
+        match stmt {
+            Stmt::Assert(assert) => self.resolve_expr(&assert.value),
+            Stmt::Block(block) => {
+                self.scopes.push(HashMap::new());
+                block.stmts.iter().for_each(|stmt| self.resolve_stmt(stmt));
+                self.scopes.pop();
+            }
+            Stmt::Class(class) => {
+                self.declare(class.name, SymbolKind::Class, params(stmt), span);
+                class.super_.iter().for_each(|super_| self.resolve_expr(super_));
+                for (method, span) in &class.methods {
+                    // Unlike functions, methods are not variables.
+                    let name_spans = self.param_spans(span, method, 1);
+                    self.resolve_function(method, name_spans.into_iter().skip(1).collect());
+                }
+            }
+            Stmt::Expr(expr) => self.resolve_expr(&expr.value),
+            Stmt::Extend(extend) => {
+                self.resolve_expr(&extend.class);
+                for (method, span) in &extend.methods {
+                    let name_spans = self.param_spans(span, method, 1);
+                    self.resolve_function(method, name_spans.into_iter().skip(1).collect());
+                }
+            }
+            Stmt::For(for_) => {
+                self.scopes.push(HashMap::new());
+                for_.init.iter().for_each(|init| self.resolve_stmt(init));
+                for_.cond.iter().for_each(|cond| self.resolve_expr(cond));
+                for_.incr.iter().for_each(|incr| self.resolve_expr(incr));
+                self.resolve_stmt(&for_.body);
+                self.scopes.pop();
+            }
+            Stmt::Fun(fun) => {
+                // Functions can call themselves, so the name is declared first.
+                self.declare(fun.name, SymbolKind::Function, params(stmt), span);
+                let name_spans = self.param_spans(span, fun, 1);
+                self.resolve_function(fun, name_spans.into_iter().skip(1).collect());
+            }
+            Stmt::If(if_) => {
+                self.resolve_expr(&if_.cond);
+                self.resolve_stmt(&if_.then);
+                if_.else_.iter().for_each(|else_| self.resolve_stmt(else_));
+            }
+            Stmt::Print(print) => print.values.iter().for_each(|value| self.resolve_expr(value)),
+            Stmt::Return(return_) => {
+                return_.value.iter().for_each(|value| self.resolve_expr(value))
+            }
+            Stmt::Try(try_) => {
+                self.resolve_stmt(&try_.body);
+                if let Some(catch) = &try_.catch {
+                    self.scopes.push(HashMap::new());
+                    // The name of the error is between the two blocks.
+                    let decl = self.name_spans(&(try_.body.1.end..catch.body.1.start), 1).pop();
+                    let symbol = self.add_symbol(catch.name, SymbolKind::Variable, decl, false);
+                    self.scopes.last_mut().unwrap().insert(catch.name, symbol);
+                    self.resolve_stmt(&catch.body);
+                    self.scopes.pop();
+                }
+                try_.finally.iter().for_each(|finally| self.resolve_stmt(finally));
+            }
+            Stmt::Var(var) => {
+                // The initializer cannot see the variable it initializes.
+                var.value.iter().for_each(|value| self.resolve_expr(value));
+                self.declare(var.var.name, SymbolKind::Variable, Vec::new(), span);
+            }
+            Stmt::While(while_) => {
+                self.resolve_expr(&while_.cond);
+                self.resolve_stmt(&while_.body);
+            }
+            Stmt::Error => {}
+        }
+    }
+
+    /// Resolves the parameters and body of a function. `param_spans` are the
+    /// spans of its parameters.
+    fn resolve_function(&mut self, fun: &StmtFun, param_spans: Vec<Span>) {
+        self.functions += 1;
+        let enclosing = std::mem::replace(&mut self.function, self.functions);
+        self.scopes.push(HashMap::new());
+        for (idx, &param) in fun.params.iter().enumerate() {
+            let decl = param_spans.get(idx).cloned();
+            let symbol = self.add_symbol(param, SymbolKind::Parameter, decl, false);
+            self.scopes.last_mut().unwrap().insert(param, symbol);
+        }
+        fun.defaults.iter().for_each(|default| self.resolve_expr(default));
+        fun.body.stmts.iter().for_each(|stmt| self.resolve_stmt(stmt));
+        self.scopes.pop();
+        self.function = enclosing;
+    }
+
+    fn resolve_expr(&mut self, (expr, span): &ExprS) {
+        match expr {
+            Expr::Assign(assign) => {
+                self.resolve_expr(&assign.value);
+                if let Some(name_span) = self.name_spans(span, 1).pop() {
+                    self.reference(assign.var.name, name_span, Access::Write);
+                }
+            }
+            Expr::Call(call) => {
+                self.resolve_expr(&call.callee);
+                call.args.iter().for_each(|arg| self.resolve_expr(arg));
+            }
+            // The parameters of an anonymous function come before its body.
+            Expr::Fun(fun) => self.resolve_function(fun, self.param_spans(span, fun, 0)),
+            Expr::Get(get) => self.resolve_expr(&get.object),
+            Expr::Index(index) => {
+                self.resolve_expr(&index.object);
+                self.resolve_expr(&index.index);
+            }
+            Expr::Infix(infix) => {
+                self.resolve_expr(&infix.lt);
+                self.resolve_expr(&infix.rt);
+            }
+            Expr::List(list) => list.elements.iter().for_each(|element| self.resolve_expr(element)),
+            Expr::Literal(_) | Expr::Missing | Expr::Super(_) => {}
+            Expr::Map(map) => map.entries.iter().for_each(|(key, value)| {
+                self.resolve_expr(key);
+                self.resolve_expr(value);
+            }),
+            Expr::Prefix(prefix) => self.resolve_expr(&prefix.rt),
+            Expr::Set(set) => {
+                self.resolve_expr(&set.object);
+                self.resolve_expr(&set.value);
+            }
+            Expr::SetIndex(set) => {
+                self.resolve_expr(&set.object);
+                self.resolve_expr(&set.index);
+                self.resolve_expr(&set.value);
+            }
+            Expr::Var(var) => {
+                if var.var.name != Name::THIS {
+                    self.reference(var.var.name, span.clone(), Access::Read);
+                }
+            }
+        }
+    }
+
+    /// Declares a symbol in the current scope. Top-level declarations were
+    /// already added by [`Resolver::declare_globals`], so redeclaring a global
+    /// counts as writing to it.
+    fn declare(&mut self, name: Name, kind: SymbolKind, params: Vec<Name>, stmt_span: &Span) {
+        let decl = self.name_spans(stmt_span, 1).pop();
+        match self.scopes.last_mut() {
+            Some(_) => {
+                let symbol = self.add_symbol(name, kind, decl, false);
+                self.table.symbols[symbol].params = params;
+                self.scopes.last_mut().unwrap().insert(name, symbol);
+            }
+            None => {
+                let symbol = self.globals[&name];
+                if let Some(decl) = decl {
+                    if self.table.symbols[symbol].decl.as_ref() != Some(&decl) {
+                        self.add_reference(symbol, decl, Access::Write);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reference(&mut self, name: Name, span: Span, access: Access) {
+        let local = self.scopes.iter().rev().find_map(|scope| scope.get(&name)).copied();
+        let symbol = match local.or_else(|| self.globals.get(&name).copied()) {
+            Some(symbol) => symbol,
+            None => {
+                let symbol = self.add_symbol(name, SymbolKind::Variable, None, true);
+                self.globals.insert(name, symbol);
+                symbol
+            }
+        };
+        self.add_reference(symbol, span, access);
+    }
+
+    fn add_symbol(
+        &mut self,
+        name: Name,
+        kind: SymbolKind,
+        decl: Option<Span>,
+        is_global: bool,
+    ) -> usize {
+        let function = if is_global { 0 } else { self.function };
+        let params = Vec::new();
+        self.table.symbols.push(Symbol { name, kind, decl, is_global, function, params });
+        self.table.symbols.len() - 1
+    }
+
+    fn add_reference(&mut self, symbol: usize, span: Span, access: Access) {
+        let function = self.function;
+        self.table.references.push(Reference { symbol, span, access, function });
+    }
+
+    /// The AST does not record where names are within a node, so this finds
+    /// them by lexing the node again. Returns the spans of the first `count`
+    /// identifiers in `span`.
+    fn name_spans(&self, span: &Span, count: usize) -> Vec<Span> {
+        self.names(span).take(count).collect()
+    }
+
+    /// Returns the spans of the first `skip` identifiers in `span`, followed
+    /// by those of the parameters of `fun`. Names in the default values of
+    /// the parameters are left out.
+    fn param_spans(&self, span: &Span, fun: &StmtFun, skip: usize) -> Vec<Span> {
+        self.names(span)
+            .filter(|name| !fun.defaults.iter().any(|(_, default)| default.contains(&name.start)))
+            .take(skip + fun.params.len())
+            .collect()
+    }
+
+    fn names<'b>(&'b self, span: &'b Span) -> impl Iterator<Item = Span> + 'b {
+        Lexer::new(&self.source[span.clone()])
+            .flatten()
+            .filter(|(_, token, _)| matches!(token, Token::Identifier(_)))
+            .map(|(start, _, end)| span.start + start..span.start + end)
+    }
+}
+
+/// The parameters of a function declaration, or of the initializer of a
+/// class declaration.
+fn params(stmt: &Stmt) -> Vec<Name> {
+    match stmt {
+        Stmt::Class(class) => class
+            .methods
+            .iter()
+            .find(|(method, _)| method.name == Name::INIT)
+            .map(|(init, _)| init.params.clone())
+            .unwrap_or_default(),
+        Stmt::Fun(fun) => fun.params.clone(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn table(source: &str) -> SymbolTable {
+        SymbolTable::new(source, &crate::syntax::parse(source, 0).unwrap())
+    }
+
+    /// Returns the spans of the declaration and references of the symbol at
+    /// `idx`, as source text.
+    fn uses<'a>(source: &'a str, table: &SymbolTable, idx: usize) -> Vec<(&'a str, usize)> {
+        let symbol = table.symbol_at(idx).unwrap();
+        let decl =
+            table.symbols[symbol].decl.iter().map(|span| (&source[span.clone()], span.start));
+        let references = table
+            .references_to(symbol)
+            .map(|reference| (&source[reference.span.clone()], reference.span.start));
+        decl.chain(references).collect()
+    }
+
+    #[test]
+    fn scopes() {
+        let source = "var a = 1; { var a = a; print a; } fun f(a) { return a; } print a;";
+        let table = table(source);
+        assert_eq!(uses(source, &table, 4), [("a", 4), ("a", 21), ("a", 64)]);
+        assert_eq!(uses(source, &table, 17), [("a", 17), ("a", 30)]);
+        assert_eq!(uses(source, &table, 41), [("a", 41), ("a", 53)]);
+        assert_eq!(table.symbols[table.symbol_at(41).unwrap()].kind, SymbolKind::Parameter);
+    }
+
+    #[test]
+    fn globals() {
+        let source = "fun f() { return g() + clock(); } fun g() { return 1; } var g = 2; g = 3;";
+        let table = table(source);
+        assert_eq!(uses(source, &table, 17), [("g", 38), ("g", 17), ("g", 60), ("g", 67)]);
+        assert_eq!(
+            table.references_to(table.symbol_at(38).unwrap()).map(|r| r.access).collect::<Vec<_>>(),
+            [Access::Read, Access::Write, Access::Write]
+        );
+        let clock = table.symbol_at(23).unwrap();
+        assert_eq!(table.symbols[clock].decl, None);
+        assert!(table.symbols[clock].is_global);
+    }
+
+    #[test]
+    fn captured() {
+        let source = "fun f() { var a; var b; fun g() { a = 1; } print b; }";
+        let table = table(source);
+        assert!(table.is_captured(table.symbol_at(14).unwrap()));
+        assert!(!table.is_captured(table.symbol_at(21).unwrap()));
+        assert!(!table.is_captured(table.symbol_at(4).unwrap()));
+    }
+}
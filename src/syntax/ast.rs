@@ -1,49 +1,79 @@
 use std::fmt::{self, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::mem;
 
+use serde::{Deserialize, Serialize};
+
+pub use crate::syntax::name::Name;
+use crate::types::Span;
 pub use crate::types::Spanned;
 
 pub type StmtS = Spanned<Stmt>;
 pub type ExprS = Spanned<Expr>;
 
-#[derive(Debug, Default)]
+/// A parsed program. Trees compare, hash and serialize with their spans, so
+/// that tests and tools can snapshot them and tell when a parse changes.
+#[derive(Clone, Debug, Default, Deserialize, Hash, PartialEq, Serialize)]
 pub struct Program {
     pub stmts: Vec<StmtS>,
+    /// The comments in the program, in order, for tools that need to
+    /// reproduce them. The text of each comment excludes the leading `//`.
+    pub comments: Vec<Spanned<String>>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub enum Stmt {
+    Assert(StmtAssert),
     Block(StmtBlock),
     Class(StmtClass),
     Expr(StmtExpr),
+    Extend(StmtExtend),
     For(Box<StmtFor>),
     Fun(StmtFun),
     If(Box<StmtIf>),
     Print(StmtPrint),
     Return(StmtReturn),
+    Try(Box<StmtTry>),
     Var(StmtVar),
     While(Box<StmtWhile>),
     Error,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Fails with an `AssertionError` if `value` is falsey. The error shows the
+/// source of `value`, and the operands of a comparison.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct StmtAssert {
+    pub value: ExprS,
+}
+
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtBlock {
     pub stmts: Vec<StmtS>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtClass {
-    pub name: String,
+    pub name: Name,
     pub super_: Option<ExprS>,
     pub methods: Vec<Spanned<StmtFun>>,
 }
 
+/// Adds methods to an existing class, replacing any with the same names.
+/// Subclasses that inherited a replaced method, or that did not have one with
+/// that name, get the new method too.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct StmtExtend {
+    pub class: ExprS,
+    pub methods: Vec<Spanned<StmtFun>>,
+}
+
 /// An expression statement evaluates an expression and discards the result.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtExpr {
     pub value: ExprS,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtFor {
     pub init: Option<StmtS>,
     pub cond: Option<ExprS>,
@@ -51,74 +81,147 @@ pub struct StmtFor {
     pub body: StmtS,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtFun {
-    pub name: String,
-    pub params: Vec<String>,
+    pub name: Name,
+    pub params: Vec<Name>,
+    /// The default values of the last `defaults.len()` parameters, which are
+    /// computed when a call leaves those parameters out.
+    pub defaults: Vec<ExprS>,
     pub body: StmtBlock,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtIf {
     pub cond: ExprS,
     pub then: StmtS,
     pub else_: Option<StmtS>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtPrint {
-    pub value: ExprS,
+    /// The values to print, separated by spaces. There is always at least
+    /// one.
+    pub values: Vec<ExprS>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtReturn {
     pub value: Option<ExprS>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// Runs `body`, and if it fails with a runtime error, runs `catch`. Then,
+/// however `body` and `catch` were left, runs `finally`. There is always a
+/// `catch`, a `finally`, or both. All the bodies are blocks.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct StmtTry {
+    pub body: StmtS,
+    pub catch: Option<Catch>,
+    pub finally: Option<StmtS>,
+}
+
+/// The `catch` clause of a `try` statement, which runs `body` with the error
+/// bound to `name`.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct Catch {
+    pub name: Name,
+    pub body: StmtS,
+}
+
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtVar {
     pub var: Var,
     pub value: Option<ExprS>,
+    /// Whether this was declared with `const`, in which case it cannot be
+    /// assigned to, and `value` is always set.
+    pub is_const: bool,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct StmtWhile {
     pub cond: ExprS,
     pub body: StmtS,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub enum Expr {
     Assign(Box<ExprAssign>),
     Call(Box<ExprCall>),
+    /// An anonymous function, written `(a, b) -> a + b`. It is parsed into a
+    /// function named `lambda`, whose body returns the expression.
+    Fun(Box<StmtFun>),
     Get(Box<ExprGet>),
+    Index(Box<ExprIndex>),
     Infix(Box<ExprInfix>),
+    List(ExprList),
     Literal(ExprLiteral),
+    Map(ExprMap),
+    /// An expression that could not be parsed, such as the value of
+    /// `var a = ;`. It is only produced alongside an error, so that the rest
+    /// of the tree can still be used by editors.
+    Missing,
     Prefix(Box<ExprPrefix>),
     Set(Box<ExprSet>),
+    SetIndex(Box<ExprSetIndex>),
     Super(ExprSuper),
     Var(ExprVar),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Expr {
+    /// Desugars an interpolated string, such as `"a ${b} c"`, into a chain of
+    /// [`OpInfix::Concat`]s, such as `("a " + b) + " c"`. `start` is the part
+    /// of the string before the first interpolation, and each of `parts` is
+    /// an interpolated expression followed by the string after it. Empty
+    /// strings are left out, except for `start`, so that the result is always
+    /// a string.
+    pub fn interpolate(start: Spanned<String>, parts: Vec<(ExprS, Spanned<String>)>) -> Self {
+        let literal = |(string, span)| (Expr::Literal(ExprLiteral::String(string)), span);
+        let mut expr = literal(start);
+        for (value, string) in parts {
+            let string = (!string.0.is_empty()).then(|| literal(string));
+            for rt in [Some(value), string].into_iter().flatten() {
+                let span = expr.1.start..rt.1.end;
+                expr =
+                    (Expr::Infix(Box::new(ExprInfix { lt: expr, op: OpInfix::Concat, rt })), span);
+            }
+        }
+        expr.0
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct ExprAssign {
     pub var: Var,
     pub value: ExprS,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct ExprCall {
     pub callee: ExprS,
     pub args: Vec<ExprS>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct ExprGet {
     pub object: ExprS,
-    pub name: String,
+    pub name: Name,
+}
+
+/// Reads an element of a list or a map, or a character of a string, written
+/// `object[index]`.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct ExprIndex {
+    pub object: ExprS,
+    pub index: ExprS,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// A list literal, written `[a, b, c]`.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct ExprList {
+    pub elements: Vec<ExprS>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum ExprLiteral {
     Bool(bool),
     Nil,
@@ -126,27 +229,52 @@ pub enum ExprLiteral {
     String(String),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl Hash for ExprLiteral {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            ExprLiteral::Bool(bool) => bool.hash(state),
+            ExprLiteral::Nil => {}
+            // 0.0 and -0.0 are equal, so they should hash the same.
+            ExprLiteral::Number(number) => {
+                let number = if *number == 0.0 { 0.0 } else { *number };
+                number.to_bits().hash(state);
+            }
+            ExprLiteral::String(string) => string.hash(state),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct ExprInfix {
     pub lt: ExprS,
     pub op: OpInfix,
     pub rt: ExprS,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum OpInfix {
     Add,
     Subtract,
     Multiply,
     Divide,
+    /// The remainder of dividing the left operand by the right, which has
+    /// the sign of the left operand, as in C.
+    Modulo,
+    Power,
     Less,
     LessEqual,
     Greater,
     GreaterEqual,
     Equal,
     NotEqual,
+    Is,
     LogicAnd,
     LogicOr,
+    /// Concatenates the operands as they would be printed, whatever their
+    /// types. This has no syntax of its own: interpolated strings are
+    /// desugared into it.
+    Concat,
 }
 
 impl Display for OpInfix {
@@ -156,26 +284,37 @@ impl Display for OpInfix {
             OpInfix::Subtract => "-",
             OpInfix::Multiply => "*",
             OpInfix::Divide => "/",
+            OpInfix::Modulo => "%",
+            OpInfix::Power => "**",
             OpInfix::Less => "<",
             OpInfix::LessEqual => "<=",
             OpInfix::Greater => ">",
             OpInfix::GreaterEqual => ">=",
             OpInfix::Equal => "==",
             OpInfix::NotEqual => "!=",
+            OpInfix::Is => "is",
             OpInfix::LogicAnd => "and",
             OpInfix::LogicOr => "or",
+            OpInfix::Concat => "+",
         };
         write!(f, "{op}")
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// A map literal, written `{"a": 1, "b": 2}`. Each entry is a key and a
+/// value, in order.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct ExprMap {
+    pub entries: Vec<(ExprS, ExprS)>,
+}
+
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct ExprPrefix {
     pub op: OpPrefix,
     pub rt: ExprS,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum OpPrefix {
     Negate,
     Not,
@@ -191,28 +330,304 @@ impl Display for OpPrefix {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
 pub struct ExprSet {
     pub object: ExprS,
-    pub name: String,
+    pub name: Name,
+    pub value: ExprS,
+}
+
+/// Assigns to an element of a list or a map, written `object[index] = value`.
+#[derive(Clone, Debug, Deserialize, Hash, PartialEq, Serialize)]
+pub struct ExprSetIndex {
+    pub object: ExprS,
+    pub index: ExprS,
     pub value: ExprS,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct ExprSuper {
     pub super_: Var,
-    pub name: String,
+    pub name: Name,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct ExprVar {
     pub var: Var,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct Var {
-    pub name: String,
+    pub name: Name,
     /// This field is initialized as [`None`] by the parser, and is later
     /// filled by the resolver.
     pub depth: Option<usize>,
 }
+
+/// A node that is being dropped.
+///
+/// Dropping a tree recurses into each level of it, so a tree too deep for the
+/// parser to accept could overflow the stack just by being dropped, e.g. when
+/// the parser gives up on it. Instead, [`Expr`] and [`Stmt`] move their
+/// children onto a stack of their own, and drop them from there one by one.
+/// Leaves are left in place, since dropping them does not recurse.
+enum Garbage {
+    Expr(Expr),
+    Stmt(Stmt),
+}
+
+impl Garbage {
+    fn drop_all(mut garbage: Vec<Garbage>) {
+        while let Some(mut node) = garbage.pop() {
+            match &mut node {
+                Garbage::Expr(expr) => expr.take_children(&mut garbage),
+                Garbage::Stmt(stmt) => stmt.take_children(&mut garbage),
+            }
+        }
+    }
+
+    fn take_expr(garbage: &mut Vec<Garbage>, (expr, _): &mut ExprS) {
+        if !matches!(expr, Expr::Literal(_) | Expr::Missing | Expr::Super(_) | Expr::Var(_)) {
+            garbage.push(Garbage::Expr(mem::replace(expr, Expr::Literal(ExprLiteral::Nil))));
+        }
+    }
+
+    fn take_stmt(garbage: &mut Vec<Garbage>, (stmt, _): &mut StmtS) {
+        if !matches!(stmt, Stmt::Error) {
+            garbage.push(Garbage::Stmt(mem::replace(stmt, Stmt::Error)));
+        }
+    }
+
+    fn take_fun(garbage: &mut Vec<Garbage>, fun: &mut StmtFun) {
+        for expr in &mut fun.defaults {
+            Garbage::take_expr(garbage, expr);
+        }
+        for stmt in &mut fun.body.stmts {
+            Garbage::take_stmt(garbage, stmt);
+        }
+    }
+}
+
+impl Expr {
+    /// Moves the children of this expression onto `garbage`, except for
+    /// leaves.
+    fn take_children(&mut self, garbage: &mut Vec<Garbage>) {
+        let mut take = |expr: &mut ExprS| Garbage::take_expr(garbage, expr);
+        match self {
+            Expr::Assign(assign) => take(&mut assign.value),
+            Expr::Call(call) => {
+                take(&mut call.callee);
+                call.args.iter_mut().for_each(take);
+            }
+            Expr::Fun(fun) => Garbage::take_fun(garbage, fun),
+            Expr::Get(get) => take(&mut get.object),
+            Expr::Index(index) => {
+                take(&mut index.object);
+                take(&mut index.index);
+            }
+            Expr::Infix(infix) => {
+                take(&mut infix.lt);
+                take(&mut infix.rt);
+            }
+            Expr::List(list) => list.elements.iter_mut().for_each(take),
+            Expr::Map(map) => {
+                for (key, value) in &mut map.entries {
+                    take(key);
+                    take(value);
+                }
+            }
+            Expr::Prefix(prefix) => take(&mut prefix.rt),
+            Expr::Set(set) => {
+                take(&mut set.object);
+                take(&mut set.value);
+            }
+            Expr::SetIndex(set) => {
+                take(&mut set.object);
+                take(&mut set.index);
+                take(&mut set.value);
+            }
+            Expr::Literal(_) | Expr::Missing | Expr::Super(_) | Expr::Var(_) => {}
+        }
+    }
+}
+
+impl Stmt {
+    /// Moves the children of this statement onto `garbage`, except for
+    /// leaves.
+    fn take_children(&mut self, garbage: &mut Vec<Garbage>) {
+        match self {
+            Stmt::Assert(assert) => Garbage::take_expr(garbage, &mut assert.value),
+            Stmt::Block(block) => {
+                for stmt in &mut block.stmts {
+                    Garbage::take_stmt(garbage, stmt);
+                }
+            }
+            Stmt::Class(class) => {
+                if let Some(super_) = &mut class.super_ {
+                    Garbage::take_expr(garbage, super_);
+                }
+                for (method, _) in &mut class.methods {
+                    Garbage::take_fun(garbage, method);
+                }
+            }
+            Stmt::Expr(expr) => Garbage::take_expr(garbage, &mut expr.value),
+            Stmt::Extend(extend) => {
+                Garbage::take_expr(garbage, &mut extend.class);
+                for (method, _) in &mut extend.methods {
+                    Garbage::take_fun(garbage, method);
+                }
+            }
+            Stmt::For(for_) => {
+                if let Some(init) = &mut for_.init {
+                    Garbage::take_stmt(garbage, init);
+                }
+                for expr in for_.cond.iter_mut().chain(&mut for_.incr) {
+                    Garbage::take_expr(garbage, expr);
+                }
+                Garbage::take_stmt(garbage, &mut for_.body);
+            }
+            Stmt::Fun(fun) => Garbage::take_fun(garbage, fun),
+            Stmt::If(if_) => {
+                Garbage::take_expr(garbage, &mut if_.cond);
+                Garbage::take_stmt(garbage, &mut if_.then);
+                if let Some(else_) = &mut if_.else_ {
+                    Garbage::take_stmt(garbage, else_);
+                }
+            }
+            Stmt::Print(print) => {
+                for expr in &mut print.values {
+                    Garbage::take_expr(garbage, expr);
+                }
+            }
+            Stmt::Return(return_) => {
+                if let Some(value) = &mut return_.value {
+                    Garbage::take_expr(garbage, value);
+                }
+            }
+            Stmt::Try(try_) => {
+                Garbage::take_stmt(garbage, &mut try_.body);
+                if let Some(catch) = &mut try_.catch {
+                    Garbage::take_stmt(garbage, &mut catch.body);
+                }
+                if let Some(finally) = &mut try_.finally {
+                    Garbage::take_stmt(garbage, finally);
+                }
+            }
+            Stmt::Var(var) => {
+                if let Some(value) = &mut var.value {
+                    Garbage::take_expr(garbage, value);
+                }
+            }
+            Stmt::While(while_) => {
+                Garbage::take_expr(garbage, &mut while_.cond);
+                Garbage::take_stmt(garbage, &mut while_.body);
+            }
+            Stmt::Error => {}
+        }
+    }
+}
+
+impl Drop for Expr {
+    fn drop(&mut self) {
+        let mut garbage = Vec::new();
+        self.take_children(&mut garbage);
+        Garbage::drop_all(garbage);
+    }
+}
+
+impl Drop for Stmt {
+    fn drop(&mut self) {
+        let mut garbage = Vec::new();
+        self.take_children(&mut garbage);
+        Garbage::drop_all(garbage);
+    }
+}
+
+/// A node of the syntax tree that has a span of its own.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Node<'a> {
+    Stmt(&'a StmtS),
+    Expr(&'a ExprS),
+    Method(&'a Spanned<StmtFun>),
+}
+
+impl<'a> Node<'a> {
+    pub(crate) fn span(self) -> &'a Span {
+        match self {
+            Node::Stmt((_, span)) | Node::Expr((_, span)) | Node::Method((_, span)) => span,
+        }
+    }
+
+    /// The nodes directly below this one, in source order.
+    pub(crate) fn children(self) -> Vec<Node<'a>> {
+        let stmts = |stmts: &'a [StmtS]| stmts.iter().map(Node::Stmt).collect();
+        let fun = |fun: &'a StmtFun| {
+            fun.defaults
+                .iter()
+                .map(Node::Expr)
+                .chain(fun.body.stmts.iter().map(Node::Stmt))
+                .collect()
+        };
+        match self {
+            Node::Stmt((stmt, _)) => match stmt {
+                Stmt::Assert(assert) => vec![Node::Expr(&assert.value)],
+                Stmt::Block(block) => stmts(&block.stmts),
+                Stmt::Class(class) => class
+                    .super_
+                    .iter()
+                    .map(Node::Expr)
+                    .chain(class.methods.iter().map(Node::Method))
+                    .collect(),
+                Stmt::Expr(expr) => vec![Node::Expr(&expr.value)],
+                Stmt::Extend(extend) => std::iter::once(Node::Expr(&extend.class))
+                    .chain(extend.methods.iter().map(Node::Method))
+                    .collect(),
+                Stmt::For(for_) => {
+                    let mut children = for_.init.iter().map(Node::Stmt).collect::<Vec<_>>();
+                    children.extend(for_.cond.iter().chain(&for_.incr).map(Node::Expr));
+                    children.push(Node::Stmt(&for_.body));
+                    children
+                }
+                Stmt::Fun(f) => fun(f),
+                Stmt::If(if_) => {
+                    let mut children = vec![Node::Expr(&if_.cond), Node::Stmt(&if_.then)];
+                    children.extend(if_.else_.iter().map(Node::Stmt));
+                    children
+                }
+                Stmt::Print(print) => print.values.iter().map(Node::Expr).collect(),
+                Stmt::Return(return_) => return_.value.iter().map(Node::Expr).collect(),
+                Stmt::Try(try_) => std::iter::once(&try_.body)
+                    .chain(try_.catch.iter().map(|catch| &catch.body))
+                    .chain(&try_.finally)
+                    .map(Node::Stmt)
+                    .collect(),
+                Stmt::Var(var) => var.value.iter().map(Node::Expr).collect(),
+                Stmt::While(while_) => vec![Node::Expr(&while_.cond), Node::Stmt(&while_.body)],
+                Stmt::Error => Vec::new(),
+            },
+            Node::Expr((expr, _)) => match expr {
+                Expr::Assign(assign) => vec![Node::Expr(&assign.value)],
+                Expr::Call(call) => {
+                    std::iter::once(&call.callee).chain(&call.args).map(Node::Expr).collect()
+                }
+                Expr::Fun(f) => fun(f),
+                Expr::Get(get) => vec![Node::Expr(&get.object)],
+                Expr::Index(index) => vec![Node::Expr(&index.object), Node::Expr(&index.index)],
+                Expr::Infix(infix) => vec![Node::Expr(&infix.lt), Node::Expr(&infix.rt)],
+                Expr::List(list) => list.elements.iter().map(Node::Expr).collect(),
+                Expr::Map(map) => map
+                    .entries
+                    .iter()
+                    .flat_map(|(key, value)| [Node::Expr(key), Node::Expr(value)])
+                    .collect(),
+                Expr::Prefix(prefix) => vec![Node::Expr(&prefix.rt)],
+                Expr::Set(set) => vec![Node::Expr(&set.object), Node::Expr(&set.value)],
+                Expr::SetIndex(set) => {
+                    vec![Node::Expr(&set.object), Node::Expr(&set.index), Node::Expr(&set.value)]
+                }
+                Expr::Literal(_) | Expr::Missing | Expr::Super(_) | Expr::Var(_) => Vec::new(),
+            },
+            Node::Method((method, _)) => fun(method),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use thiserror::Error;
+
+use crate::types::Span;
+
+/// A single change to the source text: the text in `span` is replaced with
+/// `replacement`. Insertions and deletions are represented by an empty span
+/// and an empty replacement respectively.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceEdit {
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// Collects [`SourceEdit`]s computed from the AST, so that they can be applied
+/// to the source in one go. Since every edit refers to spans in the original
+/// source, edits can be added in any order without adjusting for the effects
+/// of previous edits.
+#[derive(Clone, Debug, Default)]
+pub struct SourceEdits {
+    edits: Vec<SourceEdit>,
+}
+
+impl SourceEdits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn replace(&mut self, span: Span, replacement: impl Into<String>) {
+        self.edits.push(SourceEdit { span, replacement: replacement.into() });
+    }
+
+    pub fn insert(&mut self, idx: usize, text: impl Into<String>) {
+        self.replace(idx..idx, text);
+    }
+
+    pub fn delete(&mut self, span: Span) {
+        self.replace(span, "");
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Returns the edits sorted by position, or an error if any two of them
+    /// overlap. Multiple insertions at the same position are kept in the order
+    /// in which they were added.
+    pub fn into_sorted(mut self) -> Result<Vec<SourceEdit>, EditError> {
+        self.edits.sort_by_key(|edit| (edit.span.start, edit.span.end));
+        for pair in self.edits.windows(2) {
+            let [prev, next] = pair else { unreachable!() };
+            if prev.span.end > next.span.start {
+                return Err(EditError::Overlapping {
+                    first: prev.span.clone(),
+                    second: next.span.clone(),
+                });
+            }
+        }
+        Ok(self.edits)
+    }
+
+    /// Applies the edits to `source`, whose spans start at `offset` (see
+    /// [`crate::syntax::parse`]), and returns the rebuilt source.
+    pub fn apply(self, source: &str, offset: usize) -> Result<String, EditError> {
+        let mut output = String::with_capacity(source.len());
+        let mut idx = 0;
+        for edit in self.into_sorted()? {
+            let start = edit.span.start.checked_sub(offset);
+            let end = edit.span.end.checked_sub(offset);
+            let (Some(start), Some(end)) = (start, end) else {
+                return Err(EditError::OutOfBounds { span: edit.span });
+            };
+            let Some(text) = source.get(idx..start) else {
+                return Err(EditError::OutOfBounds { span: edit.span });
+            };
+            if end < start || !source.is_char_boundary(end) {
+                return Err(EditError::OutOfBounds { span: edit.span });
+            }
+            output.push_str(text);
+            output.push_str(&edit.replacement);
+            idx = end;
+        }
+        output.push_str(&source[idx..]);
+        Ok(output)
+    }
+}
+
+impl Extend<SourceEdit> for SourceEdits {
+    fn extend<T: IntoIterator<Item = SourceEdit>>(&mut self, iter: T) {
+        self.edits.extend(iter);
+    }
+}
+
+impl FromIterator<SourceEdit> for SourceEdits {
+    fn from_iter<T: IntoIterator<Item = SourceEdit>>(iter: T) -> Self {
+        Self { edits: iter.into_iter().collect() }
+    }
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum EditError {
+    #[error("edits at {first:?} and {second:?} overlap")]
+    Overlapping { first: Span, second: Span },
+    #[error("edit at {span:?} is out of bounds")]
+    OutOfBounds { span: Span },
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::syntax::ast::{Expr, Stmt};
+
+    #[test]
+    fn apply_edits_out_of_order() {
+        let mut edits = SourceEdits::new();
+        edits.replace(10..13, "baz");
+        edits.insert(0, "// renamed\n");
+        edits.delete(3..4);
+        edits.replace(4..7, "bar");
+        assert_eq!(edits.apply("var foo = foo;", 0), Ok("// renamed\nvarbar = baz;".to_string()));
+    }
+
+    #[test]
+    fn apply_edits_from_ast() {
+        let source = "print a + b;";
+        let program = crate::syntax::parse(source, 10).unwrap();
+        let Stmt::Print(print) = &program.stmts[0].0 else { panic!("expected print statement") };
+        let Expr::Infix(infix) = &print.values[0].0 else { panic!("expected infix expression") };
+
+        let mut edits = SourceEdits::new();
+        edits.replace(infix.lt.1.clone(), "b");
+        edits.replace(infix.rt.1.clone(), "a");
+        assert_eq!(edits.apply(source, 10), Ok("print b + a;".to_string()));
+    }
+
+    #[test]
+    fn apply_overlapping_edits() {
+        let mut edits = SourceEdits::new();
+        edits.replace(0..5, "foo");
+        edits.replace(3..7, "bar");
+        assert_eq!(
+            edits.apply("0123456789", 0),
+            Err(EditError::Overlapping { first: 0..5, second: 3..7 })
+        );
+    }
+
+    #[test]
+    fn apply_out_of_bounds_edit() {
+        let mut edits = SourceEdits::new();
+        edits.replace(3..20, "foo");
+        assert_eq!(edits.apply("0123456789", 0), Err(EditError::OutOfBounds { span: 3..20 }));
+    }
+}
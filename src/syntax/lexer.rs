@@ -1,18 +1,90 @@
-use std::num::ParseFloatError;
-
 use logos::Logos;
 
 use crate::error::{Error, ErrorS, SyntaxError};
+use crate::syntax::name::Name;
+use crate::types::{self, Spanned};
 
 #[derive(Debug)]
 pub struct Lexer<'a> {
     inner: logos::Lexer<'a, Token>,
-    pending: Option<(usize, Token, usize)>,
+    /// A token that has been read from `inner`, but not yet emitted. Its span
+    /// is that of `inner`.
+    pending: Option<Token>,
+    comments: Vec<Spanned<String>>,
+    /// For each `${` in an interpolated string that has not yet been closed,
+    /// the number of braces opened since then and not yet closed.
+    interpolations: Vec<usize>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str) -> Self {
-        Self { inner: Token::lexer(source), pending: None }
+        Self {
+            inner: Token::lexer(source),
+            pending: None,
+            comments: Vec::new(),
+            interpolations: Vec::new(),
+        }
+    }
+
+    /// Returns the comments that have been skipped so far, in order. The text
+    /// of each comment excludes the leading `//`.
+    pub fn comments(&self) -> &[Spanned<String>] {
+        &self.comments
+    }
+
+    fn push_comment(&mut self) {
+        let text = self.inner.slice()[2..].to_string();
+        self.comments.push((text, self.inner.span()));
+    }
+
+    /// Lexes the contents of a string literal, starting just after the
+    /// current token, which is either the opening `"` or the `}` that closes
+    /// an interpolation. The string runs until the closing `"`, or until the
+    /// next `${`.
+    fn lex_string(&mut self, is_continuation: bool) -> Result<(usize, Token, usize), ErrorS> {
+        let start = self.inner.span().start;
+        let rest = self.inner.remainder();
+        let mut chars = rest.char_indices().peekable();
+        let (len, is_interpolated) = loop {
+            match chars.next() {
+                Some((_, '\\')) => {
+                    chars.next();
+                }
+                Some((idx, '"')) => break (idx, false),
+                Some((idx, '$')) if chars.peek().is_some_and(|&(_, c)| c == '{') => {
+                    break (idx, true);
+                }
+                Some(_) => {}
+                None => {
+                    // The string runs until the end of the file, but since
+                    // strings can span multiple lines, the missing quote is
+                    // most likely on the same line as the opening quote.
+                    self.inner.bump(rest.len());
+                    let len = self.inner.slice().find('\n').unwrap_or(self.inner.slice().len());
+                    return Err((
+                        Error::SyntaxError(SyntaxError::UnterminatedString),
+                        start..start + len,
+                    ));
+                }
+            }
+        };
+
+        let contents = unescape(&rest[..len]).map_err(|(err, span)| {
+            let offset = self.inner.span().end;
+            (err, span.start + offset..span.end + offset)
+        });
+        self.inner.bump(if is_interpolated { len + 2 } else { len + 1 });
+        if is_interpolated {
+            self.interpolations.push(0);
+        }
+        let contents = contents?;
+        let token = match (is_continuation, is_interpolated) {
+            (false, false) => Token::String(contents),
+            (false, true) => Token::StringStart(contents),
+            (true, true) => Token::StringMiddle(contents),
+            (true, false) => Token::StringEnd(contents),
+        };
+        Ok((start, token, self.inner.span().end))
     }
 }
 
@@ -20,33 +92,67 @@ impl<'a> Iterator for Lexer<'a> {
     type Item = Result<(usize, Token, usize), ErrorS>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(token) = self.pending.take() {
-            return Some(Ok(token));
-        }
+        let token = match self.pending.take() {
+            Some(token) => token,
+            None => loop {
+                match self.inner.next()? {
+                    Token::Comment => self.push_comment(),
+                    token => break token,
+                }
+            },
+        };
 
-        match self.inner.next()? {
+        match token {
+            Token::Quote => Some(self.lex_string(false)),
+            Token::LtBrace if !self.interpolations.is_empty() => {
+                *self.interpolations.last_mut().expect("interpolation should be open") += 1;
+                let span = self.inner.span();
+                Some(Ok((span.start, token, span.end)))
+            }
+            Token::RtBrace if self.interpolations.last() == Some(&0) => {
+                self.interpolations.pop();
+                Some(self.lex_string(true))
+            }
+            Token::RtBrace if !self.interpolations.is_empty() => {
+                *self.interpolations.last_mut().expect("interpolation should be open") -= 1;
+                let span = self.inner.span();
+                Some(Ok((span.start, token, span.end)))
+            }
+            Token::UnterminatedString => {
+                // Raw strings can span multiple lines too, so this is reported
+                // in the same way as in `lex_string`.
+                let span = self.inner.span();
+                let len = self.inner.slice().find('\n').unwrap_or(span.len());
+                Some(Err((
+                    Error::SyntaxError(SyntaxError::UnterminatedString),
+                    span.start..span.start + len,
+                )))
+            }
             Token::Error => {
-                let mut span = self.inner.span();
-
-                // Check for unterminated string.
-                if self.inner.slice().starts_with('"') {
-                    return Some(Err((Error::SyntaxError(SyntaxError::UnterminatedString), span)));
+                // Check for errors reported by the token callbacks.
+                if let Some(err) = self.inner.extras.take() {
+                    return Some(Err(err));
                 }
 
+                let mut span = self.inner.span();
+
                 // Recover error.
                 while let Some(token) = self.inner.next() {
                     let span_new = self.inner.span();
-                    if span.end == span_new.start {
+                    if token == Token::Comment {
+                        self.push_comment();
+                        break;
+                    } else if span.end == span_new.start {
                         span.end = span_new.end;
                     } else {
-                        self.pending = Some((span_new.start, token, span_new.end));
+                        self.pending = Some(token);
                         break;
                     }
                 }
 
                 Some(Err((
                     Error::SyntaxError(SyntaxError::UnexpectedInput {
-                        token: self.inner.source()[span.start..span.end].to_string(),
+                        token: types::slice(self.inner.source(), &span).to_string(),
                     }),
                     span,
                 )))
@@ -60,6 +166,7 @@ impl<'a> Iterator for Lexer<'a> {
 }
 
 #[derive(Clone, Debug, Logos, PartialEq)]
+#[logos(extras = Option<ErrorS>)]
 pub enum Token {
     // Single-character tokens.
     #[token("(")]
@@ -70,10 +177,16 @@ pub enum Token {
     LtBrace,
     #[token("}")]
     RtBrace,
+    #[token("[")]
+    LtBracket,
+    #[token("]")]
+    RtBracket,
     #[token(",")]
     Comma,
     #[token(".")]
     Dot,
+    #[token(":")]
+    Colon,
     #[token("-")]
     Minus,
     #[token("+")]
@@ -84,6 +197,8 @@ pub enum Token {
     Slash,
     #[token("*")]
     Asterisk,
+    #[token("%")]
+    Percent,
 
     // One or two character tokens.
     #[token("!")]
@@ -102,30 +217,58 @@ pub enum Token {
     Less,
     #[token("<=")]
     LessEqual,
+    #[token("->")]
+    Arrow,
+    #[token("**")]
+    AsteriskAsterisk,
 
     // Literals.
     #[regex("[a-zA-Z_][a-zA-Z0-9_]*", lex_identifier)]
-    Identifier(String),
-    #[regex(r#""[^"]*""#, lex_string)]
+    Identifier(Name),
+    #[regex(r#"r"[^"]*""#, lex_raw_string)]
     String(String),
-    #[regex(r#"[0-9]+(\.[0-9]+)?"#, lex_number)]
+    /// The start of an interpolated string, up to its first `${`, as in
+    /// `"a ${`. This is followed by the tokens of the interpolated expression.
+    StringStart(String),
+    /// The part of an interpolated string between two interpolations, as in
+    /// `} b ${`.
+    StringMiddle(String),
+    /// The end of an interpolated string, after its last interpolation, as in
+    /// `} c"`.
+    StringEnd(String),
+    // Anything that starts with a digit and continues with identifier
+    // characters is lexed as a number, so that malformed literals like `0xZZ`
+    // or `123abc` are reported as a single token.
+    #[regex(r#"[0-9][0-9_]*(\.[0-9][0-9_]*)?([eE][+-]?[0-9_]*)?[0-9a-zA-Z_]*"#, lex_number)]
     Number(f64),
 
     // Keywords.
     #[token("and")]
     And,
+    #[token("assert")]
+    Assert,
+    #[token("catch")]
+    Catch,
     #[token("class")]
     Class,
+    #[token("const")]
+    Const,
     #[token("else")]
     Else,
+    #[token("extend")]
+    Extend,
     #[token("false")]
     False,
+    #[token("finally")]
+    Finally,
     #[token("for")]
     For,
     #[token("fun")]
     Fun,
     #[token("if")]
     If,
+    #[token("is")]
+    Is,
     #[token("nil")]
     Nil,
     #[token("or")]
@@ -140,30 +283,92 @@ pub enum Token {
     This,
     #[token("true")]
     True,
+    #[token("try")]
+    Try,
     #[token("var")]
     Var,
     #[token("while")]
     While,
 
-    #[regex(r"//.*", logos::skip)]
+    /// A comment, which is never emitted by [`Lexer`]. It is skipped and
+    /// recorded in [`Lexer::comments`] instead.
+    #[regex(r"//.*")]
+    Comment,
     #[regex(r"[ \r\n\t\f]+", logos::skip)]
     #[error]
     Error,
+    /// The opening quote of a string literal. This is never emitted by
+    /// [`Lexer`], which lexes the rest of the string itself, since strings
+    /// can contain interpolated expressions, which can contain strings.
+    #[token("\"")]
+    Quote,
+    /// A raw string that is missing its closing quote, and hence runs until
+    /// the end of the file. This is never emitted by [`Lexer`], which
+    /// converts it into a [`SyntaxError::UnterminatedString`].
+    #[regex(r#"r"[^"]*"#)]
+    UnterminatedString,
 }
 
-fn lex_number(lexer: &mut logos::Lexer<Token>) -> Result<f64, ParseFloatError> {
+fn lex_number(lexer: &mut logos::Lexer<Token>) -> Option<f64> {
     let slice = lexer.slice();
-    slice.parse::<f64>()
+    let number = crate::number::parse(slice);
+    if number.is_none() {
+        let token = slice.to_string();
+        lexer.extras =
+            Some((Error::SyntaxError(SyntaxError::InvalidNumber { token }), lexer.span()));
+    }
+    number
 }
 
-fn lex_string(lexer: &mut logos::Lexer<Token>) -> String {
-    let slice = lexer.slice();
-    slice[1..slice.len() - 1].to_string()
+/// Replaces escape sequences in the contents of a string literal with the
+/// characters they represent. On failure, returns the span of the invalid
+/// escape sequence, relative to the start of `string`.
+fn unescape(string: &str) -> Result<String, ErrorS> {
+    let mut output = String::with_capacity(string.len());
+    let mut idx = 0;
+    while let Some(pos) = string[idx..].find('\\') {
+        let start = idx + pos;
+        output.push_str(&string[idx..start]);
+
+        let rest = &string[start + 1..];
+        let (c, len) = match rest.chars().next() {
+            Some('n') => (Some('\n'), 1),
+            Some('t') => (Some('\t'), 1),
+            Some('"') => (Some('"'), 1),
+            Some('\\') => (Some('\\'), 1),
+            Some('$') => (Some('$'), 1),
+            Some('u') if rest[1..].starts_with('{') => {
+                let len = rest.find('}').map_or(2, |end| end + 1);
+                let c = rest
+                    .get(2..len - 1)
+                    .filter(|digits| (1..=6).contains(&digits.len()))
+                    .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                    .and_then(char::from_u32);
+                (c, len)
+            }
+            Some(c) => (None, c.len_utf8()),
+            None => (None, 0),
+        };
+
+        let end = start + 1 + len;
+        let Some(c) = c else {
+            let escape = string[start..end].to_string();
+            return Err((Error::SyntaxError(SyntaxError::InvalidEscape { escape }), start..end));
+        };
+        output.push(c);
+        idx = end;
+    }
+    output.push_str(&string[idx..]);
+    Ok(output)
 }
 
-fn lex_identifier(lexer: &mut logos::Lexer<Token>) -> String {
+fn lex_raw_string(lexer: &mut logos::Lexer<Token>) -> String {
     let slice = lexer.slice();
-    slice.to_string()
+    slice[2..slice.len() - 1].to_string()
+}
+
+fn lex_identifier(lexer: &mut logos::Lexer<Token>) -> Name {
+    Name::new(lexer.slice())
 }
 
 #[cfg(test)]
@@ -179,16 +384,125 @@ mod tests {
                 Error::SyntaxError(SyntaxError::UnexpectedInput { token: "@foo".to_string() }),
                 0..4,
             )),
-            Ok((5, Token::Identifier("bar".to_string()), 8)),
+            Ok((5, Token::Identifier(Name::new("bar")), 8)),
         ];
         let got = Lexer::new("@foo bar").collect::<Vec<_>>();
         assert_eq!(exp, got);
     }
 
+    #[test]
+    fn lex_comments() {
+        let mut lexer = Lexer::new("// one\nfoo // two\n@//three");
+        let got = lexer.by_ref().collect::<Vec<_>>();
+        assert_eq!(got.len(), 2);
+        assert_eq!(got[0], Ok((7, Token::Identifier(Name::new("foo")), 10)));
+        let exp = vec![
+            (" one".to_string(), 0..6),
+            (" two".to_string(), 11..17),
+            ("three".to_string(), 19..26),
+        ];
+        assert_eq!(lexer.comments(), exp);
+    }
+
     #[test]
     fn lex_unterminated_string() {
-        let exp = vec![Err((Error::SyntaxError(SyntaxError::UnterminatedString), 0..5))];
-        let got = Lexer::new("\"\nfoo").collect::<Vec<_>>();
+        let exp = vec![
+            Ok((0, Token::Identifier(Name::new("foo")), 3)),
+            Err((Error::SyntaxError(SyntaxError::UnterminatedString), 4..9)),
+        ];
+        let got = Lexer::new("foo \"bar;\nbaz").collect::<Vec<_>>();
+        assert_eq!(exp, got);
+
+        let exp = vec![Err((Error::SyntaxError(SyntaxError::UnterminatedString), 0..6))];
+        let got = Lexer::new("r\"foo;").collect::<Vec<_>>();
+        assert_eq!(exp, got);
+    }
+
+    #[test]
+    fn lex_escaped_string() {
+        let exp = vec![Ok((0, Token::String("a\nb\t\"c\"\\ \u{e9}\u{1F600}".to_string()), 31))];
+        let got = Lexer::new(r#""a\nb\t\"c\"\\ \u{e9}\u{1F600}""#).collect::<Vec<_>>();
+        assert_eq!(exp, got);
+    }
+
+    #[test]
+    fn lex_interpolated_string() {
+        let exp = vec![
+            Ok((0, Token::StringStart("a".to_string()), 4)),
+            Ok((4, Token::LtBrace, 5)),
+            Ok((5, Token::RtBrace, 6)),
+            Ok((6, Token::StringMiddle(" b ".to_string()), 12)),
+            Ok((12, Token::String("c".to_string()), 15)),
+            Ok((15, Token::StringEnd("$d".to_string()), 19)),
+        ];
+        let got = Lexer::new(r#""a${{}} b ${"c"}$d""#).collect::<Vec<_>>();
+        assert_eq!(exp, got);
+
+        let exp = vec![Ok((0, Token::String("${a}".to_string()), 7))];
+        let got = Lexer::new(r#""\${a}""#).collect::<Vec<_>>();
+        assert_eq!(exp, got);
+    }
+
+    #[test]
+    fn lex_invalid_escape() {
+        let invalid_escape = |escape: &str, span| {
+            let escape = escape.to_string();
+            vec![Err((Error::SyntaxError(SyntaxError::InvalidEscape { escape }), span))]
+        };
+
+        let got = Lexer::new(r#"x = "foo\q";"#).collect::<Vec<_>>();
+        assert_eq!(got[2..3], invalid_escape(r"\q", 8..10));
+
+        let got = Lexer::new(r#""\u{110000}""#).collect::<Vec<_>>();
+        assert_eq!(got, invalid_escape(r"\u{110000}", 1..11));
+
+        let got = Lexer::new(r#""\u{}""#).collect::<Vec<_>>();
+        assert_eq!(got, invalid_escape(r"\u{}", 1..5));
+
+        let got = Lexer::new(r#""\u41""#).collect::<Vec<_>>();
+        assert_eq!(got, invalid_escape(r"\u", 1..3));
+    }
+
+    #[test]
+    fn lex_number() {
+        let exp = vec![
+            Ok((0, Token::Number(1_000_000.0), 9)),
+            Ok((10, Token::Number(255.0), 14)),
+            Ok((15, Token::Number(10.0), 25)),
+            Ok((26, Token::Number(1e9), 29)),
+            Ok((30, Token::Number(2.5e-3), 36)),
+            Ok((37, Token::Number(14.0), 40)),
+            Ok((40, Token::Plus, 41)),
+            Ok((41, Token::Number(5.0), 42)),
+        ];
+        let got = Lexer::new("1_000_000 0xFF 0b000_1010 1e9 2.5E-3 0xe+5").collect::<Vec<_>>();
+        assert_eq!(exp, got);
+    }
+
+    #[test]
+    fn lex_invalid_number() {
+        let invalid_number = |token: &str, span| {
+            let token = token.to_string();
+            Err((Error::SyntaxError(SyntaxError::InvalidNumber { token }), span))
+        };
+        let exp = vec![
+            invalid_number("123abc", 0..6),
+            invalid_number("0xZZ", 7..11),
+            invalid_number("0b102", 12..17),
+            invalid_number("1e", 18..20),
+            invalid_number("0x", 21..23),
+        ];
+        let got = Lexer::new("123abc 0xZZ 0b102 1e 0x").collect::<Vec<_>>();
+        assert_eq!(exp, got);
+    }
+
+    #[test]
+    fn lex_raw_string() {
+        let exp = vec![
+            Ok((0, Token::String(r"C:\foo\n".to_string()), 11)),
+            Ok((12, Token::Identifier(Name::new("r")), 13)),
+        ];
+        let got = Lexer::new(r#"r"C:\foo\n" r"#).collect::<Vec<_>>();
         assert_eq!(exp, got);
     }
 }
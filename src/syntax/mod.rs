@@ -1,26 +1,147 @@
 pub mod ast;
+pub mod edit;
 pub mod lexer;
+pub mod name;
 pub mod parser;
+mod sexpr;
 
 use lalrpop_util::ParseError;
 
 use crate::error::{Error, ErrorS, SyntaxError};
-use crate::syntax::ast::Program;
-use crate::syntax::lexer::Lexer;
+use crate::syntax::ast::{Node, Program};
+use crate::syntax::lexer::{Lexer, Token};
 use crate::syntax::parser::Parser;
+use crate::types::{self, Span, Spanned};
+
+/// The default for [`ParseOptions::max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Limits on the programs that [`parse_with`] accepts.
+#[derive(Clone, Copy, Debug)]
+pub struct ParseOptions {
+    /// How deeply statements and expressions may be nested. The compiler and
+    /// the analyses recurse into each level of the syntax tree, so a deeper
+    /// program would overflow their stack. Instead, it fails with a
+    /// [`SyntaxError::TooDeeplyNested`].
+    pub max_depth: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self { max_depth: DEFAULT_MAX_DEPTH }
+    }
+}
+
+/// A construct that is still open at the end of an input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Open {
+    /// A `{`, e.g. a block or a class body.
+    Brace,
+    /// A `[`, e.g. a list or an index.
+    Bracket,
+    /// A `(`, e.g. a call or a condition.
+    Paren,
+    /// A string literal that is missing its closing quote.
+    String,
+}
+
+/// The result of [`parse_partial`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartialParse {
+    /// Whether the input can be run as it is. An input is incomplete if the
+    /// parser ran out of tokens, or if it ends inside a string. Inputs with
+    /// other errors are complete, so that the errors get reported.
+    pub is_complete: bool,
+    /// The constructs that are open at the end of the input, from outermost to
+    /// innermost, each with the span of the token that opened it.
+    pub open: Vec<Spanned<Open>>,
+}
+
+/// Parses an input that may still be being typed, and returns what is left
+/// open at its end. This is used by editors to decide how to continue the
+/// input, e.g. whether pressing Enter should run it, and how far to indent the
+/// next line.
+pub fn parse_partial(source: &str) -> PartialParse {
+    let mut open = Vec::new();
+    for token in Lexer::new(source) {
+        match token {
+            Ok((start, Token::LtBrace, end)) => open.push((Open::Brace, start..end)),
+            Ok((start, Token::LtBracket, end)) => open.push((Open::Bracket, start..end)),
+            Ok((start, Token::LtParen, end)) => open.push((Open::Paren, start..end)),
+            Ok((_, Token::RtBrace | Token::RtBracket | Token::RtParen, _)) => {
+                open.pop();
+            }
+            // An unterminated string runs until the end of the input.
+            Err((Error::SyntaxError(SyntaxError::UnterminatedString), span)) => {
+                open.push((Open::String, span));
+            }
+            _ => {}
+        }
+    }
 
-pub fn is_complete(source: &str) -> bool {
-    let lexer = Lexer::new(source);
     let parser = Parser::new();
     let mut errors = Vec::new();
-    if let Err(e) = parser.parse(&mut errors, lexer) {
+    if let Err(e) = parser.parse(&mut errors, Lexer::new(source)) {
         errors.push(e);
     };
-    !errors.iter().any(|e| matches!(e, ParseError::UnrecognizedEof { .. }))
+    let is_eof = errors.iter().any(|e| matches!(e, ParseError::UnrecognizedEof { .. }));
+    let is_string = matches!(open.last(), Some((Open::String, _)));
+    PartialParse { is_complete: !is_eof && !is_string, open }
+}
+
+pub fn is_complete(source: &str) -> bool {
+    parse_partial(source).is_complete
+}
+
+/// Returns the path named by each `include "path";` directive at the top level
+/// of `source`, with the span of the directive. Directives are not part of
+/// the grammar: when a program is run, each is replaced by the contents of the
+/// file that it names, before the program is parsed.
+pub fn includes(source: &str) -> Vec<Spanned<String>> {
+    let tokens = Lexer::new(source).filter_map(Result::ok).collect::<Vec<_>>();
+    let mut includes = Vec::new();
+    let mut depth = 0_usize;
+    let mut at_stmt_start = true;
+    let mut idx = 0;
+    while idx < tokens.len() {
+        if let [
+            (start, Token::Identifier(name), _),
+            (_, Token::String(path), _),
+            (_, Token::Semicolon, end),
+            ..,
+        ] = &tokens[idx..]
+        {
+            if depth == 0 && at_stmt_start && name.as_str() == "include" {
+                includes.push((path.clone(), *start..*end));
+                idx += 3;
+                continue;
+            }
+        }
+        let (_, token, _) = &tokens[idx];
+        match token {
+            Token::LtBrace | Token::LtBracket | Token::LtParen => depth += 1,
+            Token::RtBrace | Token::RtBracket | Token::RtParen => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        at_stmt_start = depth == 0 && matches!(token, Token::Semicolon | Token::RtBrace);
+        idx += 1;
+    }
+    includes
 }
 
 pub fn parse(source: &str, offset: usize) -> Result<Program, Vec<ErrorS>> {
-    let lexer = Lexer::new(source).map(|token| match token {
+    parse_with(source, offset, &ParseOptions::default())
+}
+
+/// Parses `source`, whose spans start at `offset`, rejecting programs that
+/// exceed the limits in `options`.
+pub fn parse_with(
+    source: &str,
+    offset: usize,
+    options: &ParseOptions,
+) -> Result<Program, Vec<ErrorS>> {
+    let mut lexer = Lexer::new(source);
+    let tokens = lexer.by_ref().map(|token| match token {
         Ok((l, token, r)) => Ok((l + offset, token, r + offset)),
         Err((e, span)) => Err((e, span.start + offset..span.end + offset)),
     });
@@ -28,36 +149,428 @@ pub fn parse(source: &str, offset: usize) -> Result<Program, Vec<ErrorS>> {
     let mut errors = Vec::new();
 
     let mut parser_errors = Vec::new();
-    let program = match parser.parse(&mut parser_errors, lexer) {
+    let mut program = match parser.parse(&mut parser_errors, tokens) {
         Ok(program) => program,
         Err(err) => {
             parser_errors.push(err);
             Program::default()
         }
     };
+    program.comments = lexer
+        .comments()
+        .iter()
+        .map(|(text, span)| (text.clone(), span.start + offset..span.end + offset))
+        .collect();
 
     errors.extend(parser_errors.into_iter().map(|err| match err {
         ParseError::ExtraToken { token: (start, _, end) } => (
             Error::SyntaxError(SyntaxError::ExtraToken {
-                token: source[start - offset..end - offset].to_string(),
+                token: types::slice(source, &(start - offset..end - offset)).to_string(),
             }),
             start..end,
         ),
         ParseError::InvalidToken { location } => {
             (Error::SyntaxError(SyntaxError::InvalidToken), location..location)
         }
-        ParseError::UnrecognizedEof { location, expected } => {
-            (Error::SyntaxError(SyntaxError::UnrecognizedEof { expected }), location..location)
-        }
-        ParseError::UnrecognizedToken { token: (start, _, end), expected } => (
-            Error::SyntaxError(SyntaxError::UnrecognizedToken {
-                token: source[start - offset..end - offset].to_string(),
-                expected,
-            }),
-            start..end,
+        ParseError::UnrecognizedEof { location, expected } => (
+            Error::SyntaxError(SyntaxError::UnrecognizedEof { expected: describe(expected) }),
+            location..location,
         ),
+        ParseError::UnrecognizedToken { token: (start, _, end), expected } => {
+            match separated_number(source, start - offset..end - offset) {
+                Some(span) => (
+                    Error::SyntaxError(SyntaxError::InvalidNumber {
+                        token: types::slice(source, &span).to_string(),
+                    }),
+                    span.start + offset..span.end + offset,
+                ),
+                None => (
+                    Error::SyntaxError(SyntaxError::UnrecognizedToken {
+                        token: types::slice(source, &(start - offset..end - offset)).to_string(),
+                        expected: describe(expected),
+                    }),
+                    start..end,
+                ),
+            }
+        }
         ParseError::User { error } => error,
     }));
 
+    if let Some(span) = too_deep(&program, options.max_depth) {
+        errors.push((SyntaxError::TooDeeplyNested.into(), span));
+    }
+
     if errors.is_empty() { Ok(program) } else { Err(errors) }
 }
+
+/// Parses `source` as far as it can, for editors, which need a syntax tree
+/// even while the user is typing. Returns the tree with the errors that
+/// [`parse`] reports. Statements that could not be parsed are left in the tree
+/// as [`Stmt::Error`](ast::Stmt::Error)s, and expressions as
+/// [`Expr::Missing`](ast::Expr::Missing)es. Tokens that do not lex are left
+/// out, and brackets that are still open at the end of `source` are closed, so
+/// that a function whose body is unterminated is still a function.
+pub fn parse_tolerant(source: &str) -> (Program, Vec<ErrorS>) {
+    let errors = match parse(source, 0) {
+        Ok(program) => return (program, Vec::new()),
+        Err(errors) => errors,
+    };
+
+    let mut lexer = Lexer::new(source);
+    let mut tokens = lexer.by_ref().filter_map(Result::ok).collect::<Vec<_>>();
+    tokens.extend(closing_tokens(&tokens, source.len()));
+    let parser = Parser::new();
+    let mut program = parser.parse(&mut Vec::new(), tokens.into_iter().map(Ok)).unwrap_or_default();
+    if too_deep(&program, DEFAULT_MAX_DEPTH).is_some() {
+        program = Program::default();
+    }
+    program.comments = lexer.comments().to_vec();
+    (program, errors)
+}
+
+/// Returns the tokens that close the brackets left open by `tokens`, at
+/// `end`. A statement that is left unterminated, at the end of a block or of
+/// `tokens`, is terminated with a `;`.
+fn closing_tokens(tokens: &[(usize, Token, usize)], end: usize) -> Vec<(usize, Token, usize)> {
+    // Each open bracket, with whether it is the `{` of a block, rather than
+    // of a map.
+    let mut open = Vec::new();
+    // Whether the tokens so far end a statement, or open a block.
+    let mut is_terminated = true;
+    let mut prev: Option<&Token> = None;
+    for (_, token, _) in tokens {
+        is_terminated = match token {
+            Token::LtBrace => {
+                let is_block = matches!(
+                    prev,
+                    None | Some(
+                        Token::RtParen
+                            | Token::Semicolon
+                            | Token::LtBrace
+                            | Token::RtBrace
+                            | Token::Else
+                            | Token::Try
+                            | Token::Finally
+                            | Token::Identifier(_)
+                    )
+                );
+                open.push((Token::RtBrace, is_block));
+                is_block
+            }
+            Token::LtBracket => {
+                open.push((Token::RtBracket, false));
+                false
+            }
+            Token::LtParen => {
+                open.push((Token::RtParen, false));
+                false
+            }
+            Token::RtBrace => open.pop().map_or(true, |(_, is_block)| is_block),
+            Token::RtBracket | Token::RtParen => {
+                open.pop();
+                false
+            }
+            Token::Semicolon => true,
+            _ => false,
+        };
+        prev = Some(token);
+    }
+
+    let mut closing = Vec::new();
+    for (token, is_block) in open.into_iter().rev() {
+        if is_block && !is_terminated {
+            closing.push((end, Token::Semicolon, end));
+        }
+        closing.push((end, token, end));
+        is_terminated = is_block;
+    }
+    if !is_terminated {
+        closing.push((end, Token::Semicolon, end));
+    }
+    closing
+}
+
+/// Returns the span of a number written with a comma as the decimal separator,
+/// or with separators between groups of digits, like `1,5` or `1.000.000`, if
+/// the unexpected token at `span` is part of one. Such numbers lex as several
+/// tokens, so they would otherwise be reported as an unexpected `,` or `.`.
+fn separated_number(source: &str, span: Span) -> Option<Span> {
+    let is_part = |c: char| c.is_ascii_digit() || matches!(c, '.' | ',' | '_');
+    if !source[span.clone()].chars().all(is_part) {
+        return None;
+    }
+    let start = source[..span.start].rfind(|c| !is_part(c)).map_or(0, |idx| idx + 1);
+    let end = source[span.end..].find(|c| !is_part(c)).map_or(source.len(), |idx| span.end + idx);
+    let number = &source[start..end];
+    let is_separated = number.contains(',') || number.matches('.').count() > 1;
+    (start < span.start
+        && is_separated
+        && number.starts_with(|c: char| c.is_ascii_digit())
+        && number.ends_with(|c: char| c.is_ascii_digit()))
+    .then_some(start..end)
+}
+
+/// Returns the span of the first statement or expression in `program` that
+/// is nested more than `max_depth` deep. The tree is walked with a stack of
+/// its own, since it may be too deep to recurse into.
+fn too_deep(program: &Program, max_depth: usize) -> Option<Span> {
+    let mut nodes =
+        program.stmts.iter().rev().map(|stmt| (Node::Stmt(stmt), 1)).collect::<Vec<_>>();
+    while let Some((node, depth)) = nodes.pop() {
+        if depth > max_depth {
+            return Some(node.span().clone());
+        }
+        nodes.extend(node.children().into_iter().rev().map(|child| (child, depth + 1)));
+    }
+    None
+}
+
+/// Tokens that can start an expression.
+const EXPR: &[&str] = &[
+    "!",
+    "(",
+    "-",
+    "[",
+    "{",
+    "false",
+    "nil",
+    "super",
+    "this",
+    "true",
+    "identifier",
+    "number",
+    "string",
+    "string start",
+];
+
+/// Tokens that can start a statement, other than those in [`EXPR`].
+const STMT: &[&str] = &[
+    "assert", "class", "const", "extend", "for", "fun", "if", "print", "return", "try", "var",
+    "while", "{",
+];
+
+/// Tokens that can continue an expression.
+const OPERATOR: &[&str] = &[
+    "!=", "%", "(", "*", "**", "+", "-", ".", "/", "<", "<=", "==", ">", ">=", "[", "and", "is",
+    "or",
+];
+
+/// Replaces the tokens expected by the parser with descriptions of what they
+/// start, e.g. "an expression" instead of every token that can start one.
+/// Tokens that are not part of a larger group are left as they are.
+fn describe(mut expected: Vec<String>) -> Vec<String> {
+    const GROUPS: &[(&str, &[&[&str]])] =
+        &[("a statement", &[STMT, EXPR]), ("an expression", &[EXPR]), ("an operator", &[OPERATOR])];
+    // LALRPOP quotes the names of terminals that are not identifiers.
+    let is_token = |expected: &str, token: &str| {
+        expected == token
+            || expected.strip_prefix('"').and_then(|e| e.strip_suffix('"')) == Some(token)
+    };
+
+    let mut descriptions = Vec::new();
+    for (description, tokens) in GROUPS {
+        let tokens = || tokens.iter().copied().flatten();
+        if tokens().all(|token| expected.iter().any(|expected| is_token(expected, token))) {
+            expected.retain(|expected| !tokens().any(|token| is_token(expected, token)));
+            descriptions.push(description.to_string());
+        }
+    }
+    descriptions.extend(expected);
+    descriptions
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_partial_open() {
+        let partial = parse_partial("fun f() {\n  if (x) {\n    print (1");
+        assert!(!partial.is_complete);
+        assert_eq!(
+            partial.open,
+            vec![(Open::Brace, 8..9), (Open::Brace, 19..20), (Open::Paren, 31..32)]
+        );
+
+        let partial = parse_partial("print [1,\n  {2: (3");
+        assert_eq!(
+            partial.open,
+            vec![(Open::Bracket, 6..7), (Open::Brace, 12..13), (Open::Paren, 16..17)]
+        );
+
+        let partial = parse_partial("print \"a\nb");
+        assert!(!partial.is_complete);
+        assert_eq!(partial.open, vec![(Open::String, 6..8)]);
+
+        let partial = parse_partial("print 1");
+        assert_eq!(partial, PartialParse { is_complete: false, open: vec![] });
+
+        // Inputs with errors other than running out of tokens are complete, so
+        // that running them reports the errors.
+        let partial = parse_partial("{ print 1; } }");
+        assert_eq!(partial, PartialParse { is_complete: true, open: vec![] });
+        assert!(parse_partial("print 1;").is_complete);
+    }
+
+    #[test]
+    fn find_includes() {
+        let source = r#"include "a.lox"; print 1; include "b.lox";
+            { include "c.lox"; } f(include "d.lox"); include "${e}.lox";"#;
+        let includes = includes(source);
+        assert_eq!(includes, [("a.lox".to_string(), 0..16), ("b.lox".to_string(), 26..42)]);
+    }
+
+    #[test]
+    fn program_snapshot() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        use crate::syntax::ast::{Expr, ExprInfix, ExprLiteral, Name, OpInfix, Stmt, StmtVar, Var};
+
+        let program = parse("var a = 1 + 2; // sum", 0).unwrap();
+        let value = Expr::Infix(Box::new(ExprInfix {
+            lt: (Expr::Literal(ExprLiteral::Number(1.0)), 8..9),
+            op: OpInfix::Add,
+            rt: (Expr::Literal(ExprLiteral::Number(2.0)), 12..13),
+        }));
+        let stmt = Stmt::Var(StmtVar {
+            var: Var { name: Name::new("a"), depth: None },
+            value: Some((value, 8..13)),
+            is_const: false,
+        });
+        let comments = vec![(" sum".to_string(), 15..21)];
+        assert_eq!(program, Program { stmts: vec![(stmt, 0..14)], comments });
+
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+
+        fn hash(value: &impl Hash) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            value.hash(&mut hasher);
+            hasher.finish()
+        }
+        assert_eq!(hash(&program), hash(&parse("var a = 1 + 2; // sum", 0).unwrap()));
+        assert_ne!(hash(&program), hash(&parse("var a = 1 +  2; // sum", 0).unwrap()));
+        assert_eq!(hash(&ExprLiteral::Number(0.0)), hash(&ExprLiteral::Number(-0.0)));
+    }
+
+    #[test]
+    fn describe_expected() {
+        let expected = |source: &str| match &parse(source, 0).unwrap_err()[0].0 {
+            Error::SyntaxError(
+                SyntaxError::UnrecognizedEof { expected }
+                | SyntaxError::UnrecognizedToken { expected, .. },
+            ) => expected.clone(),
+            error => panic!("unexpected error: {error}"),
+        };
+        // Missing operand.
+        assert_eq!(expected("print 1 + ;"), ["an expression"]);
+        // Missing semicolon.
+        assert_eq!(expected("var x = 1 print x;"), ["an operator", r#"";""#]);
+        assert_eq!(expected("print 1"), ["an operator", r#"",""#, r#"";""#]);
+        // Missing closing paren or brace.
+        assert_eq!(expected("if (x {}"), ["an operator", r#"")""#, r#""=""#]);
+        assert_eq!(expected("{ print 1;"), ["a statement", r#""}""#]);
+        // Stray tokens.
+        assert_eq!(expected(")"), ["a statement"]);
+        assert_eq!(expected("return return;"), ["an expression", r#"";""#]);
+        // Lists that are not part of a group are left as they are.
+        assert_eq!(expected("var = 1;"), ["identifier"]);
+        assert_eq!(expected("fun f( {}"), [r#"")""#, "identifier"]);
+    }
+
+    #[test]
+    fn non_ascii_errors() {
+        let errors = |source: &str| parse(source, 0).unwrap_err();
+        assert_eq!(
+            errors("print \"🦀\" +;"),
+            [(
+                SyntaxError::UnrecognizedToken {
+                    token: ";".to_string(),
+                    expected: vec!["an expression".to_string()]
+                }
+                .into(),
+                14..15
+            )]
+        );
+        assert_eq!(
+            errors("var é🦀 = 1;")[0],
+            (SyntaxError::UnexpectedInput { token: "é🦀".to_string() }.into(), 4..10)
+        );
+        assert_eq!(
+            errors("print 1 🦀 é;")[0],
+            (SyntaxError::UnexpectedInput { token: "🦀".to_string() }.into(), 8..12)
+        );
+        assert!(parse("print \"é🦀", 3).is_err());
+    }
+
+    #[test]
+    fn too_deeply_nested() {
+        let options = ParseOptions { max_depth: 3 };
+        assert!(parse_with("print 1 + 2;", 0, &options).is_ok());
+        assert_eq!(
+            parse_with("print 1 + 2 + 3;", 10, &options).unwrap_err(),
+            [(SyntaxError::TooDeeplyNested.into(), 16..17)]
+        );
+
+        // Each of these used to overflow the stack. Without its last character,
+        // each is also a syntax error, which drops the tree while it is parsed.
+        const DEPTH: usize = 20_000;
+        let sources = [
+            format!("print {}1;", "-".repeat(DEPTH)),
+            format!("print 1{};", " + 1".repeat(DEPTH)),
+            format!("print {}1{};", "(1 + ".repeat(DEPTH), ")".repeat(DEPTH)),
+            format!("print {}{};", "[".repeat(DEPTH), "]".repeat(DEPTH)),
+            format!("{}{}", "{".repeat(DEPTH), "}".repeat(DEPTH)),
+            format!("{}print 1;", "if (a) ".repeat(DEPTH)),
+            format!("f{};", "()".repeat(DEPTH)),
+            format!("var f = {}1;", "() -> ".repeat(DEPTH)),
+        ];
+        for source in &sources {
+            let errors = parse(source, 0).unwrap_err();
+            assert_eq!(errors[0].0, SyntaxError::TooDeeplyNested.into(), "{source:.20}");
+            assert!(parse(&source[..source.len() - 1], 0).is_err());
+            assert!(parse_tolerant(&source[..source.len() - 1]).0.stmts.is_empty());
+        }
+    }
+
+    #[test]
+    fn tolerant() {
+        let (program, errors) = parse_tolerant("var a = 1;");
+        assert_eq!((program.stmts.len(), errors), (1, Vec::new()));
+
+        // Unterminated bodies are closed, and expressions that are missing are
+        // kept as such.
+        let source = "var a = ;\nfun f(b) {\n  var m = {\"k\": b +";
+        let (program, errors) = parse_tolerant(source);
+        assert_eq!(errors, parse(source, 0).unwrap_err());
+        assert_eq!(
+            program.to_sexpr(),
+            r#"(program [0..40]
+  (decl_var [0..9] a
+    value: (MISSING [7..8]))
+  (decl_fun [10..40] f (params b)
+    body: (decl_var [23..40] m
+      value: (expr_map [31..40]
+        key: (string [32..35] "k")
+        value: (expr_infix [37..40] "+"
+          lt: (var [37..38] b)
+          rt: (MISSING [40..40]))))))"#
+        );
+
+        // Statements that cannot be parsed are left out, as are tokens that do
+        // not lex.
+        let (program, errors) = parse_tolerant("print 1 2; @\nfun g() {}");
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            program.to_sexpr(),
+            r#"(program [0..23]
+  (ERROR [0..7])
+  (stmt_expr [8..10]
+    value: (number [8..9] 2))
+  (decl_fun [13..23] g (params)))"#
+        );
+    }
+}
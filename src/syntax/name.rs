@@ -0,0 +1,218 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+use hashbrown::HashMap;
+use rustc_hash::FxHasher;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An interned identifier. The lexer interns every identifier it reads, so
+/// that the resolver and compiler can copy and compare names without
+/// allocating or comparing strings.
+///
+/// Names live for the rest of the process. The text of each distinct name is
+/// stored once, in an arena shared by every thread, and each name keeps a
+/// reference to it, so that reading it takes no lock. Names compare by id, but
+/// ids depend on the order in which names were interned, so names hash by
+/// their text, like the rest of the syntax tree.
+#[derive(Clone, Copy)]
+pub struct Name {
+    id: u32,
+    text: &'static str,
+}
+
+/// Names that the compiler uses itself, interned ahead of time so that they
+/// can be constants.
+const PREDEFINED: &[&str] = &["", "this", "super", "init", "lambda"];
+
+impl Name {
+    /// The name of the hidden locals that hold the function being called, and
+    /// temporaries that the compiler keeps on the stack.
+    pub const EMPTY: Self = Self { id: 0, text: "" };
+    pub const THIS: Self = Self { id: 1, text: "this" };
+    pub const SUPER: Self = Self { id: 2, text: "super" };
+    pub const INIT: Self = Self { id: 3, text: "init" };
+    pub const LAMBDA: Self = Self { id: 4, text: "lambda" };
+
+    pub fn new(name: &str) -> Self {
+        let interner = interner();
+        if let Some(&name) = interner.read().unwrap_or_else(PoisonError::into_inner).ids.get(name) {
+            return name;
+        }
+        interner.write().unwrap_or_else(PoisonError::into_inner).intern(name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        self.text
+    }
+}
+
+impl Default for Name {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<Name> for String {
+    fn from(name: Name) -> Self {
+        name.as_str().to_string()
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl Debug for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for Name {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl Visitor<'_> for NameVisitor {
+            type Value = Name;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str("a name")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Name, E> {
+                Ok(Name::new(name))
+            }
+        }
+
+        deserializer.deserialize_str(NameVisitor)
+    }
+}
+
+fn interner() -> &'static RwLock<Interner> {
+    static INTERNER: OnceLock<RwLock<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| {
+        let mut interner = Interner::default();
+        for &name in PREDEFINED {
+            interner.intern(name);
+        }
+        RwLock::new(interner)
+    })
+}
+
+#[derive(Default)]
+struct Interner {
+    ids: HashMap<&'static str, Name, BuildHasherDefault<FxHasher>>,
+    /// The chunk of the arena that new names are copied into. Chunks are never
+    /// freed, so the names in them can be borrowed for `'static`.
+    chunk: &'static mut [u8],
+}
+
+impl Interner {
+    /// The size of each chunk of the arena. Longer names get a chunk of their
+    /// own.
+    const CHUNK_SIZE: usize = 16 * 1024;
+
+    fn intern(&mut self, name: &str) -> Name {
+        // Another thread may have interned the name while this one waited for
+        // the lock.
+        if let Some(&name) = self.ids.get(name) {
+            return name;
+        }
+        if self.chunk.len() < name.len() {
+            let size = name.len().max(Self::CHUNK_SIZE);
+            self.chunk = Box::leak(vec![0; size].into_boxed_slice());
+        }
+        let (text, rest) = std::mem::take(&mut self.chunk).split_at_mut(name.len());
+        text.copy_from_slice(name.as_bytes());
+        self.chunk = rest;
+        let text: &'static str = std::str::from_utf8(text).expect("names should be valid UTF-8");
+
+        let name = Name { id: self.ids.len().try_into().expect("too many names"), text };
+        self.ids.insert(text, name);
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn intern() {
+        let foo = Name::new("foo");
+        assert_eq!(foo, Name::new("foo"));
+        assert_ne!(foo, Name::new("bar"));
+        assert_eq!(foo.as_str(), "foo");
+        assert_eq!(foo, "foo");
+        assert_eq!(format!("{foo} {foo:?}"), r#"foo "foo""#);
+        assert_eq!(Name::new("this"), Name::THIS);
+        assert_eq!(Name::LAMBDA.as_str(), "lambda");
+
+        let long = "x".repeat(Interner::CHUNK_SIZE + 1);
+        assert_eq!(Name::new(&long).as_str(), long);
+
+        let json = serde_json::to_string(&foo).unwrap();
+        assert_eq!(json, r#""foo""#);
+        assert_eq!(serde_json::from_str::<Name>(&json).unwrap(), foo);
+        assert_eq!(serde_json::from_str::<Name>(r#""f\u006fo""#).unwrap(), foo);
+    }
+}
@@ -0,0 +1,342 @@
+//! Writes a [`Program`] as an s-expression in the style of tree-sitter, with
+//! the byte range of each node, so that trees from the parser can be compared
+//! with those from tree-sitter-lox.
+//!
+//! Nodes are named after those of tree-sitter-lox where the two grammars
+//! agree, like `stmt_if` and `expr_call`, and in the same style where they do
+//! not, like `stmt_try` and `expr_list`. Children are labelled with the same
+//! fields. Nodes that tree-sitter-lox only uses to wrap others, like `decl`,
+//! `expr_primary` and `grouping`, have no counterpart in the AST, and are left
+//! out. Names have no spans of their own, so they are written as atoms after
+//! the range of the node that declares or uses them. Comments are left out.
+
+use std::fmt::{Display, Write};
+
+use crate::syntax::ast::{Expr, ExprLiteral, ExprS, Program, Stmt, StmtFun, StmtS};
+use crate::types::Span;
+
+impl Program {
+    /// Returns the tree as an s-expression, with each node on a line of its
+    /// own, indented by its depth.
+    pub fn to_sexpr(&self) -> String {
+        let span = match (self.stmts.first(), self.stmts.last()) {
+            (Some((_, first)), Some((_, last))) => first.start..last.end,
+            _ => 0..0,
+        };
+        let mut writer = Writer::default();
+        writer.open(None, "program", &span);
+        for stmt in &self.stmts {
+            writer.stmt(None, stmt);
+        }
+        writer.close();
+        writer.out
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    out: String,
+    depth: usize,
+}
+
+impl Writer {
+    fn open(&mut self, field: Option<&str>, kind: &str, span: &Span) {
+        if self.depth != 0 {
+            self.out.push('\n');
+            self.out.push_str(&"  ".repeat(self.depth));
+        }
+        if let Some(field) = field {
+            let _ = write!(self.out, "{field}: ");
+        }
+        let _ = write!(self.out, "({kind} [{}..{}]", span.start, span.end);
+        self.depth += 1;
+    }
+
+    fn atom(&mut self, atom: impl Display) {
+        let _ = write!(self.out, " {atom}");
+    }
+
+    fn close(&mut self) {
+        self.out.push(')');
+        self.depth -= 1;
+    }
+
+    fn leaf(&mut self, field: Option<&str>, kind: &str, span: &Span, atom: Option<&dyn Display>) {
+        self.open(field, kind, span);
+        if let Some(atom) = atom {
+            self.atom(atom);
+        }
+        self.close();
+    }
+
+    fn stmt(&mut self, field: Option<&str>, (stmt, span): &StmtS) {
+        match stmt {
+            Stmt::Assert(assert) => {
+                self.open(field, "stmt_assert", span);
+                self.expr(Some("value"), &assert.value);
+            }
+            Stmt::Block(block) => {
+                self.open(field, "stmt_block", span);
+                for stmt in &block.stmts {
+                    self.stmt(Some("body"), stmt);
+                }
+            }
+            Stmt::Class(class) => {
+                self.open(field, "decl_class", span);
+                self.atom(class.name);
+                if let Some(super_) = &class.super_ {
+                    self.expr(Some("base"), super_);
+                }
+                for (method, span) in &class.methods {
+                    self.open(Some("method"), "function", span);
+                    self.fun(method);
+                    self.close();
+                }
+            }
+            Stmt::Expr(expr) => {
+                self.open(field, "stmt_expr", span);
+                self.expr(Some("value"), &expr.value);
+            }
+            Stmt::Extend(extend) => {
+                self.open(field, "decl_extend", span);
+                self.expr(Some("class"), &extend.class);
+                for (method, span) in &extend.methods {
+                    self.open(Some("method"), "function", span);
+                    self.fun(method);
+                    self.close();
+                }
+            }
+            Stmt::For(for_) => {
+                self.open(field, "stmt_for", span);
+                if let Some(init) = &for_.init {
+                    self.stmt(Some("init"), init);
+                }
+                if let Some(cond) = &for_.cond {
+                    self.expr(Some("cond"), cond);
+                }
+                if let Some(incr) = &for_.incr {
+                    self.expr(Some("incr"), incr);
+                }
+                self.stmt(Some("body"), &for_.body);
+            }
+            Stmt::Fun(fun) => {
+                self.open(field, "decl_fun", span);
+                self.fun(fun);
+            }
+            Stmt::If(if_) => {
+                self.open(field, "stmt_if", span);
+                self.expr(Some("cond"), &if_.cond);
+                self.stmt(Some("then"), &if_.then);
+                if let Some(else_) = &if_.else_ {
+                    self.stmt(Some("else"), else_);
+                }
+            }
+            Stmt::Print(print) => {
+                self.open(field, "stmt_print", span);
+                for value in &print.values {
+                    self.expr(Some("value"), value);
+                }
+            }
+            Stmt::Return(return_) => {
+                self.open(field, "stmt_return", span);
+                if let Some(value) = &return_.value {
+                    self.expr(Some("value"), value);
+                }
+            }
+            Stmt::Try(try_) => {
+                self.open(field, "stmt_try", span);
+                if let Some(catch) = &try_.catch {
+                    self.atom(catch.name);
+                }
+                self.stmt(Some("body"), &try_.body);
+                if let Some(catch) = &try_.catch {
+                    self.stmt(Some("catch"), &catch.body);
+                }
+                if let Some(finally) = &try_.finally {
+                    self.stmt(Some("finally"), finally);
+                }
+            }
+            Stmt::Var(var) => {
+                self.open(field, if var.is_const { "decl_const" } else { "decl_var" }, span);
+                self.atom(var.var.name);
+                if let Some(value) = &var.value {
+                    self.expr(Some("value"), value);
+                }
+            }
+            Stmt::While(while_) => {
+                self.open(field, "stmt_while", span);
+                self.expr(Some("cond"), &while_.cond);
+                self.stmt(Some("body"), &while_.body);
+            }
+            Stmt::Error => self.open(field, "ERROR", span),
+        }
+        self.close();
+    }
+
+    /// Writes the name, parameters and body of a function into the node that
+    /// has been opened for it.
+    fn fun(&mut self, fun: &StmtFun) {
+        self.atom(fun.name);
+        let params = fun.params.iter().map(|param| param.as_str()).collect::<Vec<_>>();
+        self.atom(format_args!(
+            "(params{}{})",
+            if params.is_empty() { "" } else { " " },
+            params.join(" ")
+        ));
+        for default in &fun.defaults {
+            self.expr(Some("default"), default);
+        }
+        for stmt in &fun.body.stmts {
+            self.stmt(Some("body"), stmt);
+        }
+    }
+
+    fn expr(&mut self, field: Option<&str>, (expr, span): &ExprS) {
+        match expr {
+            Expr::Assign(assign) => {
+                self.open(field, "expr_assign", span);
+                self.atom(assign.var.name);
+                self.expr(Some("value"), &assign.value);
+            }
+            Expr::Call(call) => {
+                self.open(field, "expr_call", span);
+                self.expr(Some("callee"), &call.callee);
+                for arg in &call.args {
+                    self.expr(Some("arg"), arg);
+                }
+            }
+            Expr::Fun(fun) => {
+                self.open(field, "expr_fun", span);
+                self.fun(fun);
+            }
+            Expr::Get(get) => {
+                self.open(field, "expr_field", span);
+                self.atom(get.name);
+                self.expr(Some("object"), &get.object);
+            }
+            Expr::Index(index) => {
+                self.open(field, "expr_index", span);
+                self.expr(Some("object"), &index.object);
+                self.expr(Some("index"), &index.index);
+            }
+            Expr::Infix(infix) => {
+                self.open(field, "expr_infix", span);
+                self.atom(format_args!("\"{}\"", infix.op));
+                self.expr(Some("lt"), &infix.lt);
+                self.expr(Some("rt"), &infix.rt);
+            }
+            Expr::List(list) => {
+                self.open(field, "expr_list", span);
+                for element in &list.elements {
+                    self.expr(Some("element"), element);
+                }
+            }
+            Expr::Literal(literal) => {
+                return match literal {
+                    ExprLiteral::Bool(bool) => self.leaf(field, "bool", span, Some(bool)),
+                    ExprLiteral::Nil => self.leaf(field, "nil", span, None),
+                    ExprLiteral::Number(number) => self.leaf(field, "number", span, Some(number)),
+                    ExprLiteral::String(string) => {
+                        self.leaf(field, "string", span, Some(&format_args!("{string:?}")))
+                    }
+                };
+            }
+            Expr::Map(map) => {
+                self.open(field, "expr_map", span);
+                for (key, value) in &map.entries {
+                    self.expr(Some("key"), key);
+                    self.expr(Some("value"), value);
+                }
+            }
+            Expr::Missing => return self.leaf(field, "MISSING", span, None),
+            Expr::Prefix(prefix) => {
+                self.open(field, "expr_prefix", span);
+                self.atom(format_args!("\"{}\"", prefix.op));
+                self.expr(Some("rt"), &prefix.rt);
+            }
+            Expr::Set(set) => {
+                self.open(field, "expr_set", span);
+                self.atom(set.name);
+                self.expr(Some("object"), &set.object);
+                self.expr(Some("value"), &set.value);
+            }
+            Expr::SetIndex(set) => {
+                self.open(field, "expr_set_index", span);
+                self.expr(Some("object"), &set.object);
+                self.expr(Some("index"), &set.index);
+                self.expr(Some("value"), &set.value);
+            }
+            Expr::Super(super_) => return self.leaf(field, "super", span, Some(&super_.name)),
+            Expr::Var(var) if var.var.name.as_str() == "this" => {
+                return self.leaf(field, "this", span, None);
+            }
+            Expr::Var(var) => return self.leaf(field, "var", span, Some(&var.var.name)),
+        }
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use crate::syntax::parse;
+
+    #[test]
+    fn sexpr() {
+        let source = r#"class A < B {
+  f(x, y = 2) { return this.x + super.f(-y); }
+}
+for (var i = 0; i < 3; i = i + 1) print [i, "é"], nil;
+"#;
+        let program = parse(source, 0).unwrap();
+        assert_eq!(
+            program.to_sexpr(),
+            r#"(program [0..118]
+  (decl_class [0..62] A
+    base: (var [10..11] B)
+    method: (function [16..60] f (params x y)
+      default: (number [25..26] 2)
+      body: (stmt_return [30..58]
+        value: (expr_infix [37..57] "+"
+          lt: (expr_field [37..43] x
+            object: (this [37..41]))
+          rt: (expr_call [46..57]
+            callee: (super [46..53] f)
+            arg: (expr_prefix [54..56] "-"
+              rt: (var [55..56] y)))))))
+  (stmt_for [63..118]
+    init: (decl_var [68..78] i
+      value: (number [76..77] 0))
+    cond: (expr_infix [79..84] "<"
+      lt: (var [79..80] i)
+      rt: (number [83..84] 3))
+    incr: (expr_assign [86..95] i
+      value: (expr_infix [90..95] "+"
+        lt: (var [90..91] i)
+        rt: (number [94..95] 1)))
+    body: (stmt_print [97..118]
+      value: (expr_list [103..112]
+        element: (var [104..105] i)
+        element: (string [107..111] "é"))
+      value: (nil [114..117]))))"#
+        );
+
+        // Lambdas are functions named `lambda`, whose body returns the
+        // expression.
+        let program = parse("try {} catch (e) {}\nvar f = (a) -> {\"k\": a};\n", 0).unwrap();
+        assert_eq!(
+            program.to_sexpr(),
+            r#"(program [0..44]
+  (stmt_try [0..19] e
+    body: (stmt_block [4..6])
+    catch: (stmt_block [17..19]))
+  (decl_var [20..44] f
+    value: (expr_fun [28..43] lambda (params a)
+      body: (stmt_return [35..43]
+        value: (expr_map [35..43]
+          key: (string [36..39] "k")
+          value: (var [41..42] a))))))"#
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! Tags files, which let editors without a language server jump to the
+//! classes, functions and methods declared in a project. Vim and most other
+//! editors read the ctags format, and Emacs reads the etags format.
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::{DocumentSymbol, DocumentSymbolKind};
+use crate::syntax::ast::Program;
+use crate::types::LineIndex;
+
+/// A declaration that an editor can jump to.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub kind: DocumentSymbolKind,
+    /// The class that a method belongs to.
+    pub class: Option<String>,
+    /// The line of the name, counting from 1.
+    pub line: usize,
+    /// The offset of the start of that line.
+    pub line_start: usize,
+    /// The text of that line, up to the end of the name.
+    pub text: String,
+}
+
+/// The tags of a file, in the order in which they appear.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileTags {
+    pub path: PathBuf,
+    pub tags: Vec<Tag>,
+}
+
+impl FileTags {
+    /// Returns a tag for every class, method and function declared at the
+    /// top level of `program`.
+    pub fn new(path: &Path, source: &str, program: &Program) -> Self {
+        let lines = LineIndex::new(source);
+        let mut tags = Vec::new();
+        for symbol in crate::analysis::document_symbols(source, program) {
+            match symbol.kind {
+                DocumentSymbolKind::Class => {
+                    tags.push(Tag::new(source, &lines, &symbol, None));
+                    for method in &symbol.children {
+                        tags.push(Tag::new(source, &lines, method, Some(&symbol.name)));
+                    }
+                }
+                DocumentSymbolKind::Function => tags.push(Tag::new(source, &lines, &symbol, None)),
+                _ => {}
+            }
+        }
+        Self { path: path.to_path_buf(), tags }
+    }
+}
+
+impl Tag {
+    fn new(source: &str, lines: &LineIndex, symbol: &DocumentSymbol, class: Option<&str>) -> Self {
+        let line = lines.line(symbol.name_span.start);
+        let line_start = lines.line_start(line).unwrap_or_default();
+        Self {
+            name: symbol.name.clone(),
+            kind: symbol.kind,
+            class: class.map(ToString::to_string),
+            line: line + 1,
+            line_start,
+            text: source[line_start..symbol.name_span.end].to_string(),
+        }
+    }
+}
+
+/// Formats tags as a ctags file, sorted by name so that editors can binary
+/// search it. Tags point to lines by number, rather than by search pattern.
+pub fn to_ctags(files: &[FileTags]) -> String {
+    let mut lines = Vec::new();
+    for file in files {
+        for tag in &file.tags {
+            let kind = match tag.kind {
+                DocumentSymbolKind::Class => "c",
+                DocumentSymbolKind::Method => "m",
+                _ => "f",
+            };
+            let mut line =
+                format!("{}\t{}\t{};\"\t{kind}", tag.name, file.path.display(), tag.line);
+            if let Some(class) = &tag.class {
+                let _ = write!(line, "\tclass:{class}");
+            }
+            lines.push(line);
+        }
+    }
+    lines.sort_unstable();
+
+    let mut output = String::from(
+        "!_TAG_FILE_FORMAT\t2\t/extended format/\n!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted/\n",
+    );
+    for line in lines {
+        output += &line;
+        output.push('\n');
+    }
+    output
+}
+
+/// Formats tags as an etags file, with a section for each file.
+pub fn to_etags(files: &[FileTags]) -> String {
+    let mut output = String::new();
+    for file in files {
+        let mut section = String::new();
+        for tag in &file.tags {
+            let _ = writeln!(
+                section,
+                "{}\x7f{}\x01{},{}",
+                tag.text, tag.name, tag.line, tag.line_start
+            );
+        }
+        let _ = write!(output, "\x0c\n{},{}\n{section}", file.path.display(), section.len());
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn tags() {
+        let source = "class A {\n  f() {}\n}\nvar b;\nfun g() {}\n";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let files = [FileTags::new(Path::new("a.lox"), source, &program)];
+        assert_eq!(
+            to_ctags(&files),
+            "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+             !_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted/\n\
+             A\ta.lox\t1;\"\tc\n\
+             f\ta.lox\t2;\"\tm\tclass:A\n\
+             g\ta.lox\t5;\"\tf\n"
+        );
+        assert_eq!(
+            to_etags(&files),
+            "\x0c\na.lox,38\nclass A\x7fA\x011,0\n  f\x7ff\x012,10\nfun g\x7fg\x015,28\n"
+        );
+    }
+}
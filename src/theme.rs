@@ -0,0 +1,187 @@
+#![cfg(feature = "repl")]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use nu_ansi_term::Color;
+
+/// The name used in theme files for text that is not highlighted.
+const TEXT: &str = "text";
+
+#[derive(Clone, Debug)]
+struct PaletteItem {
+    name: &'static str,
+    fg: Color,
+}
+
+// Color scheme inspired by base16-google-dark.
+//
+// The base16 style guide tells you which base16 color code to use for each
+// language construct:
+// https://github.com/chriskempson/base16/blob/39fb23df970d4d6190d000271dec260250986012/styling.md
+//
+// The base16-vim theme contains the 8-bit ANSI codes associated with each
+// base16 color code (assume we are not working in a 256-color terminal):
+// https://github.com/chriskempson/base16-vim/blob/c156b909af619cdd097d8d1e2cd1dce1f45dfba1/colors/base16-google-dark.vim#L52
+//
+// This page gives you an idea of what color is associated with a particular
+// 8-bit ANSI code:
+// https://en.wikipedia.org/wiki/ANSI_escape_code#8-bit
+//
+// Since this color scheme makes use of both Red and LightRed, we replace
+// LightRed with LightCyan to better distinguish between the two.
+//
+// Then, we replace each color with its high-intensity variant, since the
+// standard colors can be harder to read on some terminals.
+//
+const PALETTE: &[PaletteItem] = &[
+    PaletteItem { name: "", fg: Color::LightGray },
+    PaletteItem { name: "class", fg: Color::LightYellow },
+    PaletteItem { name: "comment", fg: Color::DarkGray },
+    PaletteItem { name: "constant", fg: Color::LightCyan },
+    PaletteItem { name: "function", fg: Color::LightBlue },
+    PaletteItem { name: "keyword", fg: Color::LightPurple },
+    PaletteItem { name: "operator", fg: Color::LightGray },
+    PaletteItem { name: "punctuation", fg: Color::LightGray },
+    PaletteItem { name: "string", fg: Color::LightGreen },
+    PaletteItem { name: "variable", fg: Color::LightRed },
+];
+
+/// A syntax highlighting theme, mapping tree-sitter highlight names to colors.
+///
+/// Themes are loaded from TOML files, where each key is a highlight name (or
+/// `text`, for text that is not highlighted), and each value is a color. Colors
+/// can be named ANSI colors (`"red"`, `"light_blue"`), 8-bit color codes
+/// (`"208"`), or RGB hex codes (`"#ff8800"`). Highlights that are not
+/// mentioned keep their default color.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    palette: Vec<PaletteItem>,
+}
+
+impl Theme {
+    /// Loads the theme at `path`, falling back to the theme in the data
+    /// directory, or the default theme if there is none.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match crate::data::theme_path() {
+                Some(path) => path,
+                None => return Ok(Self::default()),
+            },
+        };
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("could not read theme file: {}", path.display()))?;
+        Self::parse(&contents)
+            .with_context(|| format!("could not parse theme file: {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let colors = toml::from_str::<BTreeMap<String, String>>(contents)?;
+        let mut theme = Self::default();
+        for (name, color) in colors {
+            let key = if name == TEXT { "" } else { name.as_str() };
+            let Some(item) = theme.palette.iter_mut().find(|item| item.name == key) else {
+                let names = PALETTE.iter().map(|item| item.name).map(|name| match name {
+                    "" => TEXT,
+                    name => name,
+                });
+                bail!(
+                    "unknown highlight {name:?}, expected one of: {}",
+                    names.collect::<Vec<_>>().join(", ")
+                );
+            };
+            item.fg = parse_color(&color)
+                .with_context(|| format!("invalid color for {name:?}: {color:?}"))?;
+        }
+        Ok(theme)
+    }
+
+    /// Returns the highlight names recognized by this theme, in order. These
+    /// are meant to be passed to tree-sitter, which reports highlights as
+    /// indices into this list.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.palette.iter().map(|item| item.name).collect()
+    }
+
+    /// Returns the foreground color for the highlight at `idx` in
+    /// [`Theme::names`]. Index 0 is used for text that is not highlighted.
+    pub fn fg(&self, idx: usize) -> Color {
+        self.palette[idx].fg
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self { palette: PALETTE.to_vec() }
+    }
+}
+
+fn parse_color(color: &str) -> Result<Color> {
+    if let Some(hex) = color.strip_prefix('#') {
+        let rgb = u32::from_str_radix(hex, 16).ok().filter(|_| hex.len() == 6);
+        let Some(rgb) = rgb else { bail!("expected a color of the form #rrggbb") };
+        return Ok(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8));
+    }
+    if let Ok(code) = color.parse::<u8>() {
+        return Ok(Color::Fixed(code));
+    }
+
+    let color = match color {
+        "default" => Color::Default,
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "purple" => Color::Purple,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "white" => Color::White,
+        "dark_gray" => Color::DarkGray,
+        "light_red" => Color::LightRed,
+        "light_green" => Color::LightGreen,
+        "light_yellow" => Color::LightYellow,
+        "light_blue" => Color::LightBlue,
+        "light_purple" => Color::LightPurple,
+        "light_magenta" => Color::LightMagenta,
+        "light_cyan" => Color::LightCyan,
+        "light_gray" => Color::LightGray,
+        _ => bail!("unknown color name"),
+    };
+    Ok(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn parse_theme() {
+        let theme = Theme::parse(
+            r##"
+            text = "white"
+            keyword = "#ff8800"
+            string = "208"
+            "##,
+        )
+        .unwrap();
+        assert_eq!(theme.fg(0), Color::White);
+        assert_eq!(theme.fg(5), Color::Rgb(0xff, 0x88, 0x00));
+        assert_eq!(theme.fg(8), Color::Fixed(208));
+        assert_eq!(theme.fg(1), Color::LightYellow);
+    }
+
+    #[test]
+    fn parse_theme_invalid() {
+        let err = Theme::parse(r#"keywords = "red""#).unwrap_err();
+        assert!(err.to_string().starts_with(r#"unknown highlight "keywords""#));
+
+        let err = Theme::parse(r##"keyword = "#ff88""##).unwrap_err();
+        assert_eq!(err.to_string(), r##"invalid color for "keyword": "#ff88""##);
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Durations of each phase of running a program, as reported by `--timings`.
+#[derive(Debug, Default)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    /// Runs `f`, recording the time it took under the given phase.
+    pub fn record<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        self.phases.push((phase, start.elapsed()));
+        value
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    pub fn total(&self) -> Duration {
+        self.phases.iter().map(|&(_, duration)| duration).sum()
+    }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (phase, duration) in &self.phases {
+            writeln!(f, "{phase:<8} {duration:>12.3?}")?;
+        }
+        write!(f, "{:<8} {:>12.3?}", "total", self.total())
+    }
+}
+
+/// Like [`Timings::record`], but only records the phase if timings are
+/// enabled. This avoids reading the clock at all when timings are disabled,
+/// which matters on targets like WebAssembly where [`Instant`] is not
+/// available.
+pub fn record<T>(timings: &mut Option<Timings>, phase: &'static str, f: impl FnOnce() -> T) -> T {
+    match timings {
+        Some(timings) => timings.record(phase, f),
+        None => f(),
+    }
+}
@@ -1,4 +1,195 @@
 use std::ops::Range;
 
+use serde::{Deserialize, Serialize};
+
 pub type Spanned<T> = (T, Span);
+/// A range of byte offsets into the source. With serde, it is represented as
+/// `{ "start": .., "end": .. }`, so it can be sent as part of errors and
+/// diagnostics.
 pub type Span = Range<usize>;
+
+/// A zero-indexed position in a document. `character` is measured in UTF-16
+/// code units, which is what both LSP clients and JavaScript editors expect.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+impl Position {
+    /// The position of the byte offset `idx`. An offset inside a char is
+    /// taken to be at the start of that char. This scans `source`, so use a
+    /// [`LineIndex`] to convert more than one offset.
+    pub fn new(source: &str, idx: usize) -> Self {
+        LineIndex::new(source).position(source, idx)
+    }
+
+    /// The inverse of [`Position::new`]. Positions past the end of a line are
+    /// clamped to the end of that line, and positions past the last line to
+    /// the end of the document.
+    pub fn to_idx(self, source: &str) -> usize {
+        LineIndex::new(source).offset(source, self)
+    }
+}
+
+/// A span, with the positions of its ends.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ResolvedSpan {
+    pub span: Span,
+    pub start: Position,
+    pub end: Position,
+}
+
+/// The offsets at which the lines of a source start, for converting between
+/// byte offsets, lines and [`Position`]s. Each conversion only scans the line
+/// that it is on, so tools build one index per version of a source, and use
+/// it for every conversion in that version. Methods that take the source must
+/// be given the one that the index was built from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LineIndex {
+    /// The offset of the start of each line. The first is always 0.
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let line_starts =
+            std::iter::once(0).chain(source.match_indices('\n').map(|(idx, _)| idx + 1)).collect();
+        Self { line_starts }
+    }
+
+    /// The number of lines. A source has one more line than it has newlines,
+    /// so that an empty source has one line, and a source that ends in a
+    /// newline has an empty last line.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// The zero-indexed line that the byte offset `idx` is on. Offsets past
+    /// the end of the source are on the last line.
+    pub fn line(&self, idx: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= idx) - 1
+    }
+
+    /// The offset at which the zero-indexed `line` starts, or [`None`] if the
+    /// source has no such line.
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.line_starts.get(line).copied()
+    }
+
+    /// The position of the byte offset `idx`. An offset inside a char is
+    /// taken to be at the start of that char.
+    pub fn position(&self, source: &str, idx: usize) -> Position {
+        let idx = floor_char_boundary(source, idx);
+        let line = self.line(idx);
+        let character = source.get(self.line_starts[line]..idx).unwrap_or_default();
+        Position { line: line as _, character: character.encode_utf16().count() as _ }
+    }
+
+    /// The inverse of [`LineIndex::position`]. Positions past the end of a
+    /// line are clamped to the end of that line, and positions past the last
+    /// line to the end of the source.
+    pub fn offset(&self, source: &str, position: Position) -> usize {
+        let Some(line_start) = self.line_start(position.line as usize) else {
+            return source.len();
+        };
+        let line = source.get(line_start..).unwrap_or_default();
+        let line = &line[..line.find('\n').unwrap_or(line.len())];
+        let mut character = 0;
+        for (idx, c) in line.char_indices() {
+            if character >= position.character as usize {
+                return line_start + idx;
+            }
+            character += c.len_utf16();
+        }
+        line_start + line.len()
+    }
+
+    /// Returns `span` with the positions of its ends.
+    pub fn resolve(&self, source: &str, span: &Span) -> ResolvedSpan {
+        ResolvedSpan {
+            span: span.clone(),
+            start: self.position(source, span.start),
+            end: self.position(source, span.end),
+        }
+    }
+}
+
+/// Returns the largest char boundary of `source` at or before `idx`, so that
+/// offsets from elsewhere, such as a stale span or one that was computed in
+/// bytes, can be used to slice it without panicking.
+pub fn floor_char_boundary(source: &str, idx: usize) -> usize {
+    let mut idx = idx.min(source.len());
+    while !source.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    idx
+}
+
+/// Returns the smallest char boundary of `source` at or after `idx`, or the
+/// end of `source` if `idx` is past it.
+pub fn ceil_char_boundary(source: &str, idx: usize) -> usize {
+    let mut idx = idx.min(source.len());
+    while !source.is_char_boundary(idx) {
+        idx += 1;
+    }
+    idx
+}
+
+/// Widens `span` to the nearest char boundaries of `source`, and clamps it to
+/// the end of `source`, so that it covers every char that it touches.
+pub fn char_span(source: &str, span: &Span) -> Span {
+    let start = floor_char_boundary(source, span.start);
+    start..ceil_char_boundary(source, span.end.max(start))
+}
+
+/// Returns the text of `source` at `span`, widened with [`char_span`].
+pub fn slice<'a>(source: &'a str, span: &Span) -> &'a str {
+    &source[char_span(source, span)]
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn char_boundaries() {
+        // "é" is 2 bytes, and "🦀" is 4.
+        let source = "é🦀x";
+        assert_eq!(floor_char_boundary(source, 1), 0);
+        assert_eq!(ceil_char_boundary(source, 1), 2);
+        assert_eq!(floor_char_boundary(source, 4), 2);
+        assert_eq!(ceil_char_boundary(source, 4), 6);
+        assert_eq!(floor_char_boundary(source, 100), 7);
+        assert_eq!(slice(source, &(1..3)), "é🦀");
+        assert_eq!(slice(source, &(6..7)), "x");
+        assert_eq!(slice(source, &(9..12)), "");
+    }
+
+    #[test]
+    fn line_index() {
+        let source = "a\né🦀\n\nb";
+        let lines = LineIndex::new(source);
+        assert_eq!(lines.line_count(), 4);
+        assert_eq!(
+            (0..=source.len()).map(|idx| lines.line(idx)).collect::<Vec<_>>(),
+            [0, 0, 1, 1, 1, 1, 1, 1, 1, 2, 3, 3]
+        );
+        assert_eq!(lines.line(100), 3);
+        assert_eq!((lines.line_start(3), lines.line_start(4)), (Some(10), None));
+
+        for idx in 0..=source.len() {
+            let position = lines.position(source, idx);
+            assert_eq!(position, Position::new(source, idx));
+            if source.is_char_boundary(idx) {
+                assert_eq!(lines.offset(source, position), idx);
+            }
+        }
+        // "é" is 1 UTF-16 code unit, and "🦀" is 2.
+        let resolved = lines.resolve(source, &(2..8));
+        assert_eq!(resolved.start, Position { line: 1, character: 0 });
+        assert_eq!(resolved.end, Position { line: 1, character: 3 });
+    }
+}
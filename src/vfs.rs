@@ -0,0 +1,107 @@
+//! The files that a program and its host can read. Natives like `read_file`
+//! and the [`ModuleLoader`](crate::modules::ModuleLoader)s read through a
+//! [`FileSystem`], so that a host without one, like the playground, or a test
+//! that should not touch the disk, can serve files from memory instead.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::{fs, io};
+
+pub trait FileSystem: Debug {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+
+    fn read_to_string(&self, path: &str) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// The file system of the OS, with paths relative to the current directory.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OsFs;
+
+impl FileSystem for OsFs {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+}
+
+/// Files held in memory, by their paths. Paths are separated by `/`, and are
+/// all relative to the same root, so `/a.lox`, `a.lox` and `./a.lox` are the
+/// same file. As on Unix, `..` in the root is the root itself.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFs {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemoryFs {
+    /// Adds a file, replacing any earlier file at the same path.
+    pub fn add_file(&mut self, path: &str, contents: impl Into<Vec<u8>>) {
+        self.files.insert(join("", path), contents.into());
+    }
+
+    /// The paths of the files, in order.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(String::as_str)
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        self.files
+            .get(&join("", path))
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no such file: {path}")))
+    }
+}
+
+/// Returns the path of `path` in a [`MemoryFs`], relative to the directory of
+/// the file `from`, with `.` and `..` components resolved. Paths that start
+/// with `/` are relative to the root instead.
+pub fn join(from: &str, path: &str) -> String {
+    let mut components = match from.rsplit_once('/') {
+        Some((dir, _)) if !path.starts_with('/') => dir.split('/').collect(),
+        _ => Vec::new(),
+    };
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    components.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn memory_fs() {
+        let mut fs = MemoryFs::default();
+        fs.add_file("./lib/a.lox", "var a = 1;");
+        fs.add_file("/b.bin", [0xff]);
+        assert_eq!(fs.paths().collect::<Vec<_>>(), ["b.bin", "lib/a.lox"]);
+
+        assert_eq!(fs.read_to_string("lib/../lib/a.lox").unwrap(), "var a = 1;");
+        assert_eq!(fs.read("b.bin").unwrap(), [0xff]);
+        let kind = |result: io::Result<String>| result.unwrap_err().kind();
+        assert_eq!(kind(fs.read_to_string("b.bin")), io::ErrorKind::InvalidData);
+        assert_eq!(kind(fs.read_to_string("c.lox")), io::ErrorKind::NotFound);
+        assert_eq!(fs.read("../b.bin").unwrap(), [0xff]);
+    }
+
+    #[test]
+    fn join_paths() {
+        assert_eq!(join("main.lox", "lib/a.lox"), "lib/a.lox");
+        assert_eq!(join("lib/b.lox", "./a.lox"), "lib/a.lox");
+        assert_eq!(join("lib/b.lox", "../main.lox"), "main.lox");
+        assert_eq!(join("lib/b.lox", "/main.lox"), "main.lox");
+        assert_eq!(join("main.lox", "../a.lox"), "a.lox");
+    }
+}
@@ -0,0 +1,266 @@
+use crate::error::Result;
+use crate::vm::gc::Gc;
+use crate::vm::object::ObjectFunction;
+use crate::vm::value::Value;
+use crate::vm::{op, serialize, verify};
+
+/// A constant in a [`ChunkBuilder`]. Strings and functions are only allocated
+/// when the chunk is loaded into a [`VM`](crate::vm::VM), so a builder is not
+/// tied to any particular heap.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constant {
+    Number(f64),
+    String(String),
+    /// A function, which a `CLOSURE` op turns into a closure.
+    Function(Box<FunctionChunk>),
+}
+
+/// A function whose body was assembled with a [`ChunkBuilder`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionChunk {
+    pub name: String,
+    pub arity: u8,
+    /// The number of upvalues that the function captures. The `CLOSURE` op
+    /// that creates it is followed by a pair of operands for each: whether
+    /// the upvalue is a local of the enclosing function (`1`) or one of its
+    /// upvalues (`0`), and its index there.
+    pub upvalue_count: u16,
+    pub body: ChunkBuilder,
+}
+
+/// Assembles the bytecode for a script by hand, without going through Lox
+/// source, so that other languages can be compiled to run on the VM. Run the
+/// result with [`VM::run_chunk`](crate::vm::VM::run_chunk), or turn it into a
+/// program with [`ChunkBuilder::finish`].
+///
+/// The builder only guarantees that operands are encoded correctly. Whether
+/// the bytecode makes sense, e.g. that every pop has a matching push, is
+/// checked by [`verify`] when the chunk is loaded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkBuilder {
+    pub(super) ops: Vec<u8>,
+    pub(super) constants: Vec<Constant>,
+}
+
+impl ChunkBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an op or an operand.
+    pub fn emit(&mut self, byte: u8) -> &mut Self {
+        self.ops.push(byte);
+        self
+    }
+
+    /// Adds a constant, and returns its index. Returns [`None`] if the chunk
+    /// already has 256 constants.
+    pub fn constant(&mut self, constant: Constant) -> Option<u8> {
+        let idx = self.constants.len().try_into().ok()?;
+        self.constants.push(constant);
+        Some(idx)
+    }
+
+    /// Appends a forward jump op, or either kind of `TRY` op, with a
+    /// placeholder offset, and returns the position of the offset, to be
+    /// filled in by [`ChunkBuilder::patch_jump`].
+    pub fn emit_jump(&mut self, jump: u8) -> usize {
+        debug_assert!(matches!(
+            jump,
+            op::JUMP
+                | op::JUMP_IF_FALSE
+                | op::JUMP_IF_FALSE_POP
+                | op::JUMP_IF_TRUE_POP
+                | op::TRY
+                | op::TRY_FINALLY
+        ));
+        self.emit(jump).emit(0xff).emit(0xff);
+        self.ops.len() - 2
+    }
+
+    /// Makes the jump whose offset is at `offset_idx` land on the next op to
+    /// be emitted. Returns [`None`] if the jump is too long to encode.
+    pub fn patch_jump(&mut self, offset_idx: usize) -> Option<()> {
+        let offset = self.ops.len().checked_sub(offset_idx + 2)?;
+        let offset = u16::try_from(offset).ok()?.to_le_bytes();
+        self.ops[offset_idx..offset_idx + 2].copy_from_slice(&offset);
+        Some(())
+    }
+
+    /// Adds a function as a constant, and returns its index, like
+    /// [`ChunkBuilder::constant`]. `body` must end each path through it
+    /// with a `RETURN`.
+    pub fn function(
+        &mut self,
+        name: impl Into<String>,
+        arity: u8,
+        upvalue_count: u16,
+        body: ChunkBuilder,
+    ) -> Option<u8> {
+        let function = FunctionChunk { name: name.into(), arity, upvalue_count, body };
+        self.constant(Constant::Function(Box::new(function)))
+    }
+
+    /// The bytecode emitted so far.
+    pub fn ops(&self) -> &[u8] {
+        &self.ops
+    }
+
+    /// Checks the chunk with [`verify`], and returns it as a program like
+    /// those from [`VM::compile`](crate::vm::VM::compile), which can be saved
+    /// and run later with [`VM::run_compiled`](crate::vm::VM::run_compiled).
+    /// Errors have empty spans, since there is no source to point into.
+    pub fn finish(&self) -> Result<Vec<u8>> {
+        let mut gc = Gc::default();
+        let script = self.load(&mut gc)?;
+        Ok(serialize::encode(unsafe { &*script }, ""))
+    }
+
+    /// Allocates the chunk as a script in `gc`, with the functions that it
+    /// defines, and checks it with [`verify`].
+    pub(super) fn load(&self, gc: &mut Gc) -> Result<*mut ObjectFunction> {
+        // The frame of a script starts out empty.
+        let script = self.alloc("<script>", 0, 0, 0, gc)?;
+        verify(unsafe { &*script })?;
+        Ok(script)
+    }
+
+    /// Allocates the chunk as a function whose frame starts out with
+    /// `frame_size` values, and records how deep its stack gets.
+    fn alloc(
+        &self,
+        name: &str,
+        arity: u8,
+        upvalue_count: u16,
+        frame_size: usize,
+        gc: &mut Gc,
+    ) -> Result<*mut ObjectFunction> {
+        let name = gc.alloc(name);
+        let function = gc.alloc(ObjectFunction::new(name, arity));
+        let function_ref = unsafe { &mut *function };
+        function_ref.upvalue_count = upvalue_count;
+        for &byte in &self.ops {
+            function_ref.chunk.write_u8(byte, &(0..0));
+        }
+        for constant in &self.constants {
+            let value = match constant {
+                // A NaN with an arbitrary payload could look like a boxed
+                // object, so only the canonical NaN is allowed in.
+                Constant::Number(number) if number.is_nan() => Value::from(f64::NAN),
+                Constant::Number(number) => Value::from(*number),
+                Constant::String(string) => Value::from(gc.alloc(string.as_str())),
+                // The frame of a function starts out with the callee and its
+                // arguments.
+                Constant::Function(f) => {
+                    let frame_size = f.arity as usize + 1;
+                    f.body.alloc(&f.name, f.arity, f.upvalue_count, frame_size, gc)?.into()
+                }
+            };
+            function_ref.chunk.constants.push(value);
+        }
+        function_ref.max_stack = verify::max_stack(function_ref, frame_size)?;
+        Ok(function)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::error::{BytecodeError, Error, TypeError};
+    use crate::vm::VM;
+
+    #[test]
+    fn run_chunk() {
+        let mut chunk = ChunkBuilder::new();
+        let greeting = chunk.constant(Constant::String("hello".to_string())).unwrap();
+        chunk.emit(op::TRUE);
+        let jump = chunk.emit_jump(op::JUMP_IF_FALSE);
+        chunk.emit(op::POP).emit(op::CONSTANT).emit(greeting).emit(op::PRINT);
+        let end_jump = chunk.emit_jump(op::JUMP);
+        chunk.patch_jump(jump).unwrap();
+        chunk.emit(op::POP);
+        chunk.patch_jump(end_jump).unwrap();
+        chunk.emit(op::NIL).emit(op::RETURN);
+
+        let mut output = Vec::new();
+        assert_eq!(VM::default().run_chunk(&chunk, &mut output), Ok(()));
+        assert_eq!(output, b"hello\n");
+
+        let mut chunk = ChunkBuilder::new();
+        let greeting = chunk.constant(Constant::String("hello".to_string())).unwrap();
+        chunk.emit(op::CONSTANT).emit(greeting).emit(op::NIL).emit(op::ADD);
+        chunk.emit(op::POP).emit(op::NIL).emit(op::RETURN);
+        let (error, _) = VM::default().run_chunk(&chunk, &mut Vec::new()).unwrap_err();
+        assert_eq!(
+            error,
+            Error::TypeError(TypeError::UnsupportedOperandInfix {
+                op: "+".to_string(),
+                lt_type: "string".to_string(),
+                rt_type: "nil".to_string()
+            })
+        );
+
+        // NaNs that share their bits with a boxed object must not be treated
+        // as one.
+        let mut chunk = ChunkBuilder::new();
+        let nan = chunk.constant(Constant::Number(f64::from_bits(0xffff_0000_0000_0001))).unwrap();
+        chunk.emit(op::CONSTANT).emit(nan).emit(op::PRINT).emit(op::NIL).emit(op::RETURN);
+        let mut output = Vec::new();
+        assert_eq!(VM::default().run_chunk(&chunk, &mut output), Ok(()));
+        assert_eq!(output, b"NaN\n");
+    }
+
+    #[test]
+    fn run_chunk_stack_ops() {
+        let mut chunk = ChunkBuilder::new();
+        let [a, b, c] =
+            ["a", "b", "c"].map(|name| chunk.constant(Constant::String(name.to_string())).unwrap());
+        chunk.emit(op::CONSTANT).emit(a).emit(op::CONSTANT).emit(b).emit(op::CONSTANT).emit(c);
+        // a b c -> b c a -> b a c -> b a c c
+        chunk.emit(op::ROT).emit(op::SWAP).emit(op::DUP);
+        for _ in 0..3 {
+            chunk.emit(op::PRINT_SPACED);
+        }
+        chunk.emit(op::PRINT).emit(op::NIL).emit(op::RETURN);
+
+        let mut output = Vec::new();
+        assert_eq!(VM::default().run_chunk(&chunk, &mut output), Ok(()));
+        assert_eq!(output, b"c c a b\n");
+    }
+
+    #[test]
+    fn functions() {
+        // fun add(a, b) { return a + b; } print add(1, 2);
+        let mut add = ChunkBuilder::new();
+        add.emit(op::GET_LOCAL).emit(1).emit(op::GET_LOCAL).emit(2).emit(op::ADD).emit(op::RETURN);
+        let mut chunk = ChunkBuilder::new();
+        let function = chunk.function("add", 2, 0, add.clone()).unwrap();
+        let [one, two] = [1.0, 2.0].map(|number| chunk.constant(Constant::Number(number)).unwrap());
+        chunk.emit(op::CLOSURE).emit(function).emit(op::CONSTANT).emit(one);
+        chunk.emit(op::CONSTANT).emit(two).emit(op::CALL).emit(2);
+        chunk.emit(op::PRINT).emit(op::NIL).emit(op::RETURN);
+
+        let mut output = Vec::new();
+        assert_eq!(VM::default().run_chunk(&chunk, &mut output), Ok(()));
+        assert_eq!(output, b"3\n");
+
+        // The program can be run later, by another VM.
+        let program = chunk.finish().unwrap();
+        let mut output = Vec::new();
+        assert_eq!(VM::default().run_compiled(&program, &mut output), Ok(()));
+        assert_eq!(output, b"3\n");
+
+        // Functions are verified along with the script.
+        add.ops.pop();
+        let mut chunk = ChunkBuilder::new();
+        chunk.function("add", 2, 0, add).unwrap();
+        chunk.emit(op::NIL).emit(op::RETURN);
+        let (error, _) = chunk.finish().unwrap_err();
+        assert_eq!(
+            error,
+            Error::BytecodeError(BytecodeError::MissingReturn { name: "add".to_string() })
+        );
+    }
+}
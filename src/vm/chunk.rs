@@ -1,9 +1,12 @@
-use std::ops::Index;
+use std::fmt::Write;
+use std::ops::{Index, Range};
 
 use arrayvec::ArrayVec;
 
 use crate::error::{OverflowError, Result};
-use crate::types::Span;
+use crate::syntax::ast::Name;
+use crate::types::{LineIndex, Span};
+use crate::vm::object::Native;
 use crate::vm::op;
 use crate::vm::value::Value;
 
@@ -12,6 +15,25 @@ pub struct Chunk {
     pub ops: Vec<u8>,
     pub constants: ArrayVec<Value, 256>,
     pub spans: VecRun<Span>,
+    /// The indices of the ops that start a statement, in order. A debugger
+    /// steps from one of these to the next, rather than one op at a time.
+    pub safe_points: Vec<usize>,
+    /// The named locals of the function, in the order they were declared, so
+    /// that a debugger can find them in a frame.
+    pub locals: Vec<LocalInfo>,
+    /// The names of the upvalues of the function, by index.
+    pub upvalue_names: Vec<Name>,
+}
+
+/// A local variable, as seen by a debugger.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalInfo {
+    pub name: Name,
+    /// The stack slot that holds the variable, counted from the start of the
+    /// frame.
+    pub slot: u8,
+    /// The indices of the ops that the variable is in scope for.
+    pub ops: Range<usize>,
 }
 
 impl Chunk {
@@ -20,6 +42,43 @@ impl Chunk {
         self.spans.push(span.clone());
     }
 
+    /// Marks the next op written as a safe point.
+    pub fn add_safe_point(&mut self) {
+        let idx = self.ops.len();
+        if self.safe_points.last() != Some(&idx) {
+            self.safe_points.push(idx);
+        }
+    }
+
+    /// Drops the ops from `len` onwards, along with their spans, safe points
+    /// and locals. Constants are kept, since other ops may share them.
+    pub fn truncate(&mut self, len: usize) {
+        self.ops.truncate(len);
+        self.spans.truncate(len);
+        self.safe_points.retain(|&idx| idx < len);
+        self.locals.retain(|local| local.ops.start < len);
+    }
+
+    /// Returns the locals that are in scope at the op `idx`. Where a name is
+    /// shadowed, only the innermost local with that name is returned.
+    pub fn locals_at(&self, idx: usize) -> Vec<&LocalInfo> {
+        let mut locals = Vec::<&LocalInfo>::new();
+        for local in self.locals.iter().filter(|local| local.ops.contains(&idx)) {
+            match locals.iter_mut().find(|other| other.name == local.name) {
+                Some(other) if other.slot < local.slot => *other = local,
+                Some(_) => {}
+                None => locals.push(local),
+            }
+        }
+        locals.sort_unstable_by_key(|local| local.slot);
+        locals
+    }
+
+    /// Returns whether the op at `idx` starts a statement.
+    pub fn is_safe_point(&self, idx: usize) -> bool {
+        self.safe_points.binary_search(&idx).is_ok()
+    }
+
     /// Writes a constant to the [`Chunk`] and returns its index. If an equal
     /// [`Value`] is already present, then its index is returned instead.
     pub fn write_constant(&mut self, value: Value, span: &Span) -> Result<u8> {
@@ -35,56 +94,165 @@ impl Chunk {
         Ok(idx.try_into().expect("constant index overflow"))
     }
 
-    pub fn debug(&self, name: &str) {
-        eprintln!("== {name} ==");
+    /// Returns the source span of the op at `idx`, if it exists.
+    pub fn span_at(&self, idx: usize) -> Option<&Span> {
+        self.spans.get(idx)
+    }
+
+    /// Returns the indices of the ops whose spans start on the given line, in
+    /// order. Lines are 1-indexed, matching error messages.
+    ///
+    /// Since spans are offsets into the source of the [`VM`](crate::vm::VM),
+    /// `lines` must index the full source that this chunk was compiled from.
+    pub fn ops_for_line(&self, lines: &LineIndex, line: usize) -> Vec<usize> {
+        let Some(start) = line.checked_sub(1).and_then(|line| lines.line_start(line)) else {
+            return Vec::new();
+        };
+        let end = lines.line_start(line).unwrap_or(usize::MAX);
+
+        let mut ops = Vec::new();
         let mut idx = 0;
         while idx < self.ops.len() {
-            idx = self.debug_op(idx);
+            if let Some(span) = self.span_at(idx) {
+                if (start..end).contains(&span.start) {
+                    ops.push(idx);
+                }
+            }
+            idx += self.op_len(idx);
         }
+        ops
     }
 
-    pub fn debug_op(&self, idx: usize) -> usize {
-        eprint!("{idx:04} ");
+    /// Returns the length in bytes of the op at `idx`, including its operands.
+    pub fn op_len(&self, idx: usize) -> usize {
         match self.ops[idx] {
-            op::CONSTANT => self.debug_op_constant("OP_CONSTANT", idx),
-            op::NIL => self.debug_op_simple("OP_NIL", idx),
-            op::TRUE => self.debug_op_simple("OP_TRUE", idx),
-            op::FALSE => self.debug_op_simple("OP_FALSE", idx),
-            op::POP => self.debug_op_simple("OP_POP", idx),
-            op::GET_LOCAL => self.debug_op_byte("OP_GET_LOCAL", idx),
-            op::SET_LOCAL => self.debug_op_byte("OP_SET_LOCAL", idx),
-            op::GET_GLOBAL => self.debug_op_constant("OP_GET_GLOBAL", idx),
-            op::DEFINE_GLOBAL => self.debug_op_constant("OP_DEFINE_GLOBAL", idx),
-            op::SET_GLOBAL => self.debug_op_constant("OP_SET_GLOBAL", idx),
-            op::GET_UPVALUE => self.debug_op_byte("OP_GET_UPVALUE", idx),
-            op::SET_UPVALUE => self.debug_op_byte("OP_SET_UPVALUE", idx),
-            op::GET_PROPERTY => self.debug_op_constant("OP_GET_PROPERTY", idx),
-            op::SET_PROPERTY => self.debug_op_constant("OP_SET_PROPERTY", idx),
-            op::GET_SUPER => self.debug_op_constant("OP_GET_SUPER", idx),
-            op::EQUAL => self.debug_op_simple("OP_EQUAL", idx),
-            op::NOT_EQUAL => self.debug_op_simple("OP_NOT_EQUAL", idx),
-            op::GREATER => self.debug_op_simple("OP_GREATER", idx),
-            op::GREATER_EQUAL => self.debug_op_simple("OP_GREATER_EQUAL", idx),
-            op::LESS => self.debug_op_simple("OP_LESS", idx),
-            op::LESS_EQUAL => self.debug_op_simple("OP_LESS_EQUAL", idx),
-            op::ADD => self.debug_op_simple("OP_ADD", idx),
-            op::SUBTRACT => self.debug_op_simple("OP_SUBTRACT", idx),
-            op::MULTIPLY => self.debug_op_simple("OP_MULTIPLY", idx),
-            op::DIVIDE => self.debug_op_simple("OP_DIVIDE", idx),
-            op::NOT => self.debug_op_simple("OP_NOT", idx),
-            op::NEGATE => self.debug_op_simple("OP_NEGATE", idx),
-            op::PRINT => self.debug_op_simple("OP_PRINT", idx),
-            op::JUMP => self.debug_op_jump("OP_JUMP", idx, true),
-            op::JUMP_IF_FALSE => self.debug_op_jump("OP_JUMP_IF_FALSE", idx, true),
-            op::LOOP => self.debug_op_jump("OP_LOOP", idx, false),
-            op::CALL => self.debug_op_byte("OP_CALL", idx),
-            op::INVOKE => self.debug_op_invoke("OP_INVOKE", idx),
-            op::SUPER_INVOKE => self.debug_op_invoke("OP_SUPER_INVOKE", idx),
+            op::CONSTANT
+            | op::SMALL_INT
+            | op::GET_LOCAL
+            | op::SET_LOCAL
+            | op::GET_GLOBAL
+            | op::DEFINE_GLOBAL
+            | op::SET_GLOBAL
+            | op::GET_UPVALUE
+            | op::SET_UPVALUE
+            | op::GET_PROPERTY
+            | op::SET_PROPERTY
+            | op::GET_SUPER
+            | op::CALL
+            | op::CLASS
+            | op::METHOD
+            | op::EXTEND
+            | op::ASSERT_INFIX
+            | op::LIST
+            | op::MAP => 2,
+            op::JUMP
+            | op::JUMP_IF_FALSE
+            | op::JUMP_IF_FALSE_POP
+            | op::JUMP_IF_TRUE_POP
+            | op::LOOP
+            | op::TRY
+            | op::TRY_FINALLY
+            | op::CALL_NATIVE
+            | op::INVOKE
+            | op::SUPER_INVOKE => 3,
+            op::CLOSURE => {
+                let constant = &self.constants[self.ops[idx + 1] as usize];
+                let function = unsafe { constant.as_object().function };
+                2 + 2 * unsafe { (*function).upvalue_count } as usize
+            }
+            _ => 1,
+        }
+    }
+
+    /// Returns the disassembly of the chunk, one op per line.
+    pub fn disassemble(&self, name: &str) -> String {
+        let mut out = format!("== {name} ==\n");
+        let mut idx = 0;
+        while idx < self.ops.len() {
+            idx = self.disassemble_op(&mut out, idx);
+        }
+        out
+    }
+
+    /// Returns the disassembly of the op at `idx`, without a trailing newline.
+    pub fn disassemble_at(&self, idx: usize) -> String {
+        let mut out = String::new();
+        self.disassemble_op(&mut out, idx);
+        out.truncate(out.trim_end().len());
+        out
+    }
+
+    fn disassemble_op(&self, out: &mut String, idx: usize) -> usize {
+        write!(out, "{idx:04} ").unwrap();
+        match self.ops[idx] {
+            op::CONSTANT => self.debug_op_constant(out, "OP_CONSTANT", idx),
+            op::SMALL_INT => self.debug_op_byte(out, "OP_SMALL_INT", idx),
+            op::NIL => self.debug_op_simple(out, "OP_NIL", idx),
+            op::TRUE => self.debug_op_simple(out, "OP_TRUE", idx),
+            op::FALSE => self.debug_op_simple(out, "OP_FALSE", idx),
+            op::POP => self.debug_op_simple(out, "OP_POP", idx),
+            op::DUP => self.debug_op_simple(out, "OP_DUP", idx),
+            op::SWAP => self.debug_op_simple(out, "OP_SWAP", idx),
+            op::ROT => self.debug_op_simple(out, "OP_ROT", idx),
+            op::GET_LOCAL => self.debug_op_byte(out, "OP_GET_LOCAL", idx),
+            op::SET_LOCAL => self.debug_op_byte(out, "OP_SET_LOCAL", idx),
+            op::GET_GLOBAL => self.debug_op_constant(out, "OP_GET_GLOBAL", idx),
+            op::DEFINE_GLOBAL => self.debug_op_constant(out, "OP_DEFINE_GLOBAL", idx),
+            op::SET_GLOBAL => self.debug_op_constant(out, "OP_SET_GLOBAL", idx),
+            op::GET_UPVALUE => self.debug_op_byte(out, "OP_GET_UPVALUE", idx),
+            op::SET_UPVALUE => self.debug_op_byte(out, "OP_SET_UPVALUE", idx),
+            op::GET_PROPERTY => self.debug_op_constant(out, "OP_GET_PROPERTY", idx),
+            op::SET_PROPERTY => self.debug_op_constant(out, "OP_SET_PROPERTY", idx),
+            op::GET_SUPER => self.debug_op_constant(out, "OP_GET_SUPER", idx),
+            op::EQUAL => self.debug_op_simple(out, "OP_EQUAL", idx),
+            op::NOT_EQUAL => self.debug_op_simple(out, "OP_NOT_EQUAL", idx),
+            op::GREATER => self.debug_op_simple(out, "OP_GREATER", idx),
+            op::GREATER_EQUAL => self.debug_op_simple(out, "OP_GREATER_EQUAL", idx),
+            op::LESS => self.debug_op_simple(out, "OP_LESS", idx),
+            op::LESS_EQUAL => self.debug_op_simple(out, "OP_LESS_EQUAL", idx),
+            op::IS => self.debug_op_simple(out, "OP_IS", idx),
+            op::ADD => self.debug_op_simple(out, "OP_ADD", idx),
+            op::SUBTRACT => self.debug_op_simple(out, "OP_SUBTRACT", idx),
+            op::MULTIPLY => self.debug_op_simple(out, "OP_MULTIPLY", idx),
+            op::DIVIDE => self.debug_op_simple(out, "OP_DIVIDE", idx),
+            op::MODULO => self.debug_op_simple(out, "OP_MODULO", idx),
+            op::POWER => self.debug_op_simple(out, "OP_POWER", idx),
+            op::NOT => self.debug_op_simple(out, "OP_NOT", idx),
+            op::NEGATE => self.debug_op_simple(out, "OP_NEGATE", idx),
+            op::ASSERT => self.debug_op_simple(out, "OP_ASSERT", idx),
+            op::ASSERT_INFIX => self.debug_op_byte(out, "OP_ASSERT_INFIX", idx),
+            op::PRINT => self.debug_op_simple(out, "OP_PRINT", idx),
+            op::PRINT_SPACED => self.debug_op_simple(out, "OP_PRINT_SPACED", idx),
+            op::JUMP => self.debug_op_jump(out, "OP_JUMP", idx, true),
+            op::JUMP_IF_FALSE => self.debug_op_jump(out, "OP_JUMP_IF_FALSE", idx, true),
+            op::JUMP_IF_FALSE_POP => self.debug_op_jump(out, "OP_JUMP_IF_FALSE_POP", idx, true),
+            op::JUMP_IF_TRUE_POP => self.debug_op_jump(out, "OP_JUMP_IF_TRUE_POP", idx, true),
+            op::LOOP => self.debug_op_jump(out, "OP_LOOP", idx, false),
+            op::TRY => self.debug_op_jump(out, "OP_TRY", idx, true),
+            op::TRY_FINALLY => self.debug_op_jump(out, "OP_TRY_FINALLY", idx, true),
+            op::END_TRY => self.debug_op_simple(out, "OP_END_TRY", idx),
+            op::RETHROW => self.debug_op_simple(out, "OP_RETHROW", idx),
+            op::CALL => self.debug_op_byte(out, "OP_CALL", idx),
+            op::CALL_NATIVE => {
+                let native_idx = self.ops[idx + 1];
+                let native = Native::ALL[native_idx as usize];
+                let arg_count = self.ops[idx + 2];
+                writeln!(
+                    out,
+                    "{name:16} ({arg_count} args) {native_idx:>4} '{native}'",
+                    name = "OP_CALL_NATIVE"
+                )
+                .unwrap();
+                idx + 3
+            }
+            op::INVOKE => self.debug_op_invoke(out, "OP_INVOKE", idx),
+            op::SUPER_INVOKE => self.debug_op_invoke(out, "OP_SUPER_INVOKE", idx),
             op::CLOSURE => {
                 let mut idx = idx + 1;
                 let constant_idx = self.ops[idx];
                 let constant = &self.constants[constant_idx as usize];
-                eprintln!("{name:16} {constant_idx:>4} '{constant}'", name = "OP_CLOSURE");
+                writeln!(out, "{name:16} {constant_idx:>4} '{constant}'", name = "OP_CLOSURE")
+                    .unwrap();
 
                 let function = unsafe { constant.as_object().function };
                 for _ in 0..unsafe { (*function).upvalue_count } {
@@ -97,52 +265,60 @@ impl Chunk {
                     idx += 1;
                     let upvalue_idx = self.ops[idx];
 
-                    eprintln!("{offset:04} |                     {label} {upvalue_idx}");
+                    writeln!(out, "{offset:04} |                     {label} {upvalue_idx}")
+                        .unwrap();
                 }
 
                 idx + 1
             }
-            op::CLOSE_UPVALUE => self.debug_op_simple("OP_CLOSE_UPVALUE", idx),
-            op::RETURN => self.debug_op_simple("OP_RETURN", idx),
-            op::CLASS => self.debug_op_constant("OP_CLASS", idx),
-            op::INHERIT => self.debug_op_simple("OP_INHERIT", idx),
-            op::METHOD => self.debug_op_constant("OP_METHOD", idx),
-            byte => self.debug_op_simple(&format!("OP_UNKNOWN({byte:#X})"), idx),
+            op::CLOSE_UPVALUE => self.debug_op_simple(out, "OP_CLOSE_UPVALUE", idx),
+            op::RETURN => self.debug_op_simple(out, "OP_RETURN", idx),
+            op::CLASS => self.debug_op_constant(out, "OP_CLASS", idx),
+            op::INHERIT => self.debug_op_simple(out, "OP_INHERIT", idx),
+            op::METHOD => self.debug_op_constant(out, "OP_METHOD", idx),
+            op::LIST => self.debug_op_byte(out, "OP_LIST", idx),
+            op::MAP => self.debug_op_byte(out, "OP_MAP", idx),
+            op::GET_INDEX => self.debug_op_simple(out, "OP_GET_INDEX", idx),
+            op::SET_INDEX => self.debug_op_simple(out, "OP_SET_INDEX", idx),
+            op::CONCAT => self.debug_op_simple(out, "OP_CONCAT", idx),
+            op::RETURN_NIL => self.debug_op_simple(out, "OP_RETURN_NIL", idx),
+            op::EXTEND => self.debug_op_constant(out, "OP_EXTEND", idx),
+            byte => self.debug_op_simple(out, &format!("OP_UNKNOWN({byte:#X})"), idx),
         }
     }
 
-    fn debug_op_simple(&self, name: &str, idx: usize) -> usize {
-        eprintln!("{name}");
+    fn debug_op_simple(&self, out: &mut String, name: &str, idx: usize) -> usize {
+        writeln!(out, "{name}").unwrap();
         idx + 1
     }
 
-    fn debug_op_byte(&self, name: &str, idx: usize) -> usize {
+    fn debug_op_byte(&self, out: &mut String, name: &str, idx: usize) -> usize {
         let byte = self.ops[idx + 1];
-        eprintln!("{name:16} {byte:>4}");
+        writeln!(out, "{name:16} {byte:>4}").unwrap();
         idx + 2
     }
 
-    fn debug_op_constant(&self, name: &str, idx: usize) -> usize {
+    fn debug_op_constant(&self, out: &mut String, name: &str, idx: usize) -> usize {
         let constant_idx = self.ops[idx + 1];
         let constant = &self.constants[constant_idx as usize];
-        eprintln!("{name:16} {constant_idx:>4} '{constant}'");
+        writeln!(out, "{name:16} {constant_idx:>4} '{constant}'").unwrap();
         idx + 2
     }
 
-    fn debug_op_invoke(&self, name: &str, idx: usize) -> usize {
+    fn debug_op_invoke(&self, out: &mut String, name: &str, idx: usize) -> usize {
         let constant_idx = self.ops[idx + 1];
         let constant = &self.constants[constant_idx as usize];
         let arg_count = self.ops[idx + 2];
-        eprintln!("{name:16} ({arg_count} args) {constant_idx:>4} '{constant}'");
+        writeln!(out, "{name:16} ({arg_count} args) {constant_idx:>4} '{constant}'").unwrap();
         idx + 3
     }
 
-    fn debug_op_jump(&self, name: &str, idx: usize, is_forward: bool) -> usize {
+    fn debug_op_jump(&self, out: &mut String, name: &str, idx: usize, is_forward: bool) -> usize {
         let to_offset = u16::from_le_bytes([self.ops[idx + 1], self.ops[idx + 2]]);
         let offset_sign = if is_forward { 1 } else { -1 };
         // The +3 is to account for the 3 byte jump instruction.
         let to_idx = (idx as isize) + (to_offset as isize) * offset_sign + 3;
-        eprintln!("{name:16} {idx:>4} -> {to_idx}");
+        writeln!(out, "{name:16} {idx:>4} -> {to_idx}").unwrap();
         idx + 3
     }
 }
@@ -154,6 +330,32 @@ pub struct VecRun<T> {
     values: Vec<Run<T>>,
 }
 
+impl<T> VecRun<T> {
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let mut count = index;
+        for run in &self.values {
+            match count.checked_sub(run.count as usize) {
+                Some(remaining) => count = remaining,
+                None => return Some(&run.value),
+            }
+        }
+        None
+    }
+
+    /// Keeps only the first `len` values.
+    fn truncate(&mut self, mut len: usize) {
+        for (idx, run) in self.values.iter_mut().enumerate() {
+            if len <= run.count as usize {
+                run.count = len as u8;
+                let end = if len == 0 { idx } else { idx + 1 };
+                self.values.truncate(end);
+                return;
+            }
+            len -= run.count as usize;
+        }
+    }
+}
+
 impl<T: Eq> VecRun<T> {
     fn push(&mut self, value: T) {
         match self.values.last_mut() {
@@ -169,14 +371,7 @@ impl<T> Index<usize> for VecRun<T> {
     type Output = T;
 
     fn index(&self, index: usize) -> &Self::Output {
-        let mut count = index;
-        for run in &self.values {
-            match count.checked_sub(run.count as usize) {
-                Some(remaining) => count = remaining,
-                None => return &run.value,
-            }
-        }
-        panic!("index out of bounds");
+        self.get(index).expect("index out of bounds")
     }
 }
 
@@ -185,3 +380,63 @@ struct Run<T> {
     value: T,
     count: u8,
 }
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::vm::{Compiler, Gc};
+
+    #[test]
+    fn map_ops_to_source() {
+        let source = "var a = 1;\nprint a;\n\nfun f(x) {\n  return x;\n}\n";
+        let mut gc = Gc::default();
+        let function = Compiler::compile(source, 0, &mut gc).unwrap();
+        let chunk = unsafe { &(*function).chunk };
+        let lines = LineIndex::new(source);
+
+        // print a;
+        let ops = chunk.ops_for_line(&lines, 2);
+        assert_eq!(
+            ops.iter().map(|&idx| chunk.ops[idx]).collect::<Vec<_>>(),
+            [op::GET_GLOBAL, op::PRINT]
+        );
+        assert_eq!(chunk.span_at(ops[0]), Some(&(17..18)));
+        assert_eq!(chunk.span_at(ops[1]), Some(&(11..19)));
+
+        // The body of f is compiled into a separate chunk.
+        assert!(chunk.ops_for_line(&lines, 3).is_empty());
+        assert!(chunk.ops_for_line(&lines, 5).is_empty());
+        assert!(chunk.ops_for_line(&lines, 100).is_empty());
+        assert_eq!(chunk.span_at(chunk.ops.len()), None);
+    }
+
+    #[test]
+    fn safe_points() {
+        let source = "var a = 1;\n{ print a; }\nfor (var i = 0; i < 2; i = i + 1) a = i;\n";
+        let mut gc = Gc::default();
+        let function = Compiler::compile(source, 0, &mut gc).unwrap();
+        let chunk = unsafe { &(*function).chunk };
+
+        // Each statement starts a new safe point, as do the condition and the
+        // increment of the loop, since they run on every iteration. The block
+        // starts with the statement inside it.
+        let ops = chunk.safe_points.iter().map(|&idx| chunk.ops[idx]).collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            [
+                op::SMALL_INT,
+                op::GET_GLOBAL,
+                op::SMALL_INT,
+                op::GET_LOCAL,
+                op::GET_LOCAL,
+                op::GET_LOCAL
+            ]
+        );
+        assert_eq!(chunk.span_at(chunk.safe_points[4]), Some(&(62..63)));
+        assert_eq!(chunk.span_at(chunk.safe_points[5]), Some(&(51..52)));
+        assert!(chunk.safe_points.iter().all(|&idx| chunk.is_safe_point(idx)));
+        assert!(!chunk.is_safe_point(1));
+    }
+}
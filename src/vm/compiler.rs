@@ -1,27 +1,82 @@
 use std::convert::TryInto;
+use std::hash::BuildHasherDefault;
 use std::mem;
 
 use arrayvec::ArrayVec;
+use hashbrown::{HashMap, HashSet};
+use rustc_hash::FxHasher;
 
+use crate::consts;
 use crate::error::{ErrorS, NameError, OverflowError, Result, SyntaxError};
 use crate::syntax::ast::{
-    Expr, ExprLiteral, ExprS, OpInfix, OpPrefix, Stmt, StmtFun, StmtReturn, StmtS,
+    Catch, Expr, ExprLiteral, ExprS, Name, OpInfix, OpPrefix, Program, Stmt, StmtFun, StmtReturn,
+    StmtS,
 };
 use crate::types::Span;
+use crate::vm::chunk::LocalInfo;
 use crate::vm::gc::Gc;
-use crate::vm::object::ObjectFunction;
-use crate::vm::op;
+use crate::vm::object::{Native, ObjectClosure, ObjectFunction};
 use crate::vm::value::Value;
+use crate::vm::{op, shrink, verify};
+
+/// Options that change the semantics of the compiled program.
+#[derive(Clone, Debug, Default)]
+pub struct CompilerOptions {
+    /// If set, the compiler runs in strict mode, where accessing a global that
+    /// is not defined anywhere is a compile-time error instead of a runtime
+    /// error. This contains the globals that are defined outside the script,
+    /// such as natives, or definitions from previous REPL inputs.
+    pub strict_globals: Option<HashSet<String, BuildHasherDefault<FxHasher>>>,
+    /// Gives each iteration of a `for` loop a fresh copy of the loop variable,
+    /// so that closures created in the loop body capture the value for that
+    /// iteration, instead of sharing a single variable across iterations.
+    pub per_iteration_bindings: bool,
+    /// Allow `extend` statements, which add methods to classes that have
+    /// already been declared.
+    pub class_extensions: bool,
+    /// Globals that were declared with `const` outside the script, such as in
+    /// previous REPL inputs. The script may not assign to or redefine them.
+    pub const_globals: HashSet<String, BuildHasherDefault<FxHasher>>,
+    /// Leave out functions and classes declared at the top level that the
+    /// program never uses. See [`shrink::live_stmts`].
+    pub eliminate_dead_globals: bool,
+    /// Natives whose globals still hold them, and that nothing outside the
+    /// script will assign to. Calls to these that the script cannot have
+    /// reassigned compile to `CALL_NATIVE`, which skips looking up the global.
+    pub native_globals: Vec<Native>,
+}
+
+impl CompilerOptions {
+    /// Options for checking a standalone program in strict mode, where only
+    /// the natives, the error classes and the modules are defined outside the
+    /// script.
+    pub fn strict() -> Self {
+        let natives =
+            Native::ALL.iter().filter(|native| native.is_global()).map(ToString::to_string);
+        let error_classes = crate::vm::ERROR_CLASSES.iter().map(ToString::to_string);
+        let modules = Native::MODULES.iter().map(|(name, _)| name.to_string());
+        let globals = natives.chain(error_classes).chain(modules).collect();
+        Self { strict_globals: Some(globals), ..Default::default() }
+    }
+}
 
 #[derive(Debug)]
 pub struct Compiler {
     ctx: CompilerCtx,
     class_ctx: Vec<ClassCtx>,
+    options: CompilerOptions,
+    /// Global constants declared so far in the script, with their values if
+    /// they are literals. Later reads of such constants are folded into the
+    /// chunk instead of looking up the global.
+    consts: HashMap<Name, Option<ExprLiteral>, BuildHasherDefault<FxHasher>>,
+    /// Errors from statements that failed to compile. Compilation carries on
+    /// with the next statement, so that every error is reported at once.
+    errors: Vec<ErrorS>,
 }
 
 impl Compiler {
     /// Creates a compiler for a new script.
-    pub fn new(gc: &mut Gc) -> Self {
+    pub fn new(gc: &mut Gc, options: CompilerOptions) -> Self {
         let name = gc.alloc("<script>");
         Self {
             ctx: CompilerCtx {
@@ -31,8 +86,13 @@ impl Compiler {
                 upvalues: ArrayVec::new(),
                 parent: None,
                 scope_depth: 0,
+                handlers: 0,
+                finally: Vec::new(),
             },
             class_ctx: Vec::new(),
+            options,
+            consts: HashMap::default(),
+            errors: Vec::new(),
         }
     }
 
@@ -41,40 +101,244 @@ impl Compiler {
         offset: usize,
         gc: &mut Gc,
     ) -> Result<*mut ObjectFunction, Vec<ErrorS>> {
-        let mut compiler = Self::new(gc);
+        Self::compile_with(source, offset, gc, CompilerOptions::default())
+    }
 
+    pub fn compile_with(
+        source: &str,
+        offset: usize,
+        gc: &mut Gc,
+        options: CompilerOptions,
+    ) -> Result<*mut ObjectFunction, Vec<ErrorS>> {
         let program = crate::syntax::parse(source, offset)?;
-        for stmt in &program.stmts {
-            compiler.compile_stmt(stmt, gc).map_err(|e| vec![e])?;
+        Self::compile_program(&program, gc, options)
+    }
+
+    /// Compiles a program that has already been parsed.
+    pub fn compile_program(
+        program: &Program,
+        gc: &mut Gc,
+        options: CompilerOptions,
+    ) -> Result<*mut ObjectFunction, Vec<ErrorS>> {
+        Self::compile_script(program, gc, options, ScriptKind::Program)
+    }
+
+    /// Compiles a program like [`Compiler::compile_program`], but if its last
+    /// statement is an expression, the script returns its value instead of
+    /// `nil`.
+    pub fn compile_eval(
+        program: &Program,
+        gc: &mut Gc,
+        options: CompilerOptions,
+    ) -> Result<*mut ObjectFunction, Vec<ErrorS>> {
+        Self::compile_script(program, gc, options, ScriptKind::Eval)
+    }
+
+    pub(crate) fn compile_script(
+        program: &Program,
+        gc: &mut Gc,
+        mut options: CompilerOptions,
+        kind: ScriptKind,
+    ) -> Result<*mut ObjectFunction, Vec<ErrorS>> {
+        // Constants from outside the script are already defined, but their
+        // values are unknown.
+        let consts = options.const_globals.iter().map(|name| (Name::new(name), None)).collect();
+
+        // Functions may assign to globals that are declared after them, so
+        // constants are collected up front.
+        for (stmt, _) in &program.stmts {
+            if let Stmt::Var(var) = stmt {
+                if var.is_const {
+                    options.const_globals.insert(var.var.name.to_string());
+                }
+            }
+        }
+
+        // In strict mode, collect all the globals defined by the script, so
+        // that functions can refer to globals that are defined after them.
+        if let Some(globals) = &mut options.strict_globals {
+            for (stmt, _) in &program.stmts {
+                match stmt {
+                    Stmt::Class(class) => globals.insert(class.name.to_string()),
+                    Stmt::Fun(fun) => globals.insert(fun.name.to_string()),
+                    Stmt::Var(var) => globals.insert(var.var.name.to_string()),
+                    _ => continue,
+                };
+            }
         }
 
-        compiler.emit_u8(op::NIL, &NO_SPAN);
+        // Natives that the script assigns to anywhere are called through their
+        // globals like any other value.
+        if !options.native_globals.is_empty() {
+            let assigned = shrink::assigned_globals(program);
+            options.native_globals.retain(|native| !assigned.contains(native.to_string().as_str()));
+        }
+
+        let is_live = if options.eliminate_dead_globals {
+            shrink::live_stmts(program)
+        } else {
+            vec![true; program.stmts.len()]
+        };
+
+        let mut stmts = program.stmts.iter().zip(is_live).filter(|(_, is_live)| *is_live);
+        let last = match program.stmts.last() {
+            Some((Stmt::Expr(expr), span)) if kind != ScriptKind::Program => {
+                stmts.next_back();
+                Some((&expr.value, span))
+            }
+            _ => None,
+        };
+
+        let mut compiler = Self::new(gc, options);
+        compiler.consts = consts;
+        let mut frame_size = 0;
+        if let ScriptKind::InFrame(locals) = kind {
+            compiler.declare_frame_locals(locals).map_err(|e| vec![e])?;
+            frame_size = locals.len() + 1;
+        }
+        for (stmt, _) in stmts {
+            compiler.compile_stmt_recover(stmt, gc);
+        }
+        match last {
+            Some((expr, span)) => {
+                compiler.mark_safe_point();
+                if let Err(e) = compiler.compile_expr(expr, gc) {
+                    compiler.errors.push(e);
+                }
+                compiler.emit_u8(op::RETURN, span);
+            }
+            None => compiler.emit_u8(op::RETURN_NIL, &NO_SPAN),
+        }
+        if !compiler.errors.is_empty() {
+            return Err(compiler.errors);
+        }
+
+        compiler.set_max_stack(frame_size).map_err(|e| vec![e])?;
+        compiler.ctx.locals.clear();
+        compiler.end_local_infos();
+
+        Ok(compiler.ctx.function)
+    }
+
+    /// Declares the parameters of a script compiled for
+    /// [`ScriptKind::InFrame`]: the function itself, followed by `locals`.
+    fn declare_frame_locals(&mut self, locals: &[Name]) -> Result<()> {
+        let arity =
+            locals.len().try_into().map_err(|_| (OverflowError::TooManyParams.into(), NO_SPAN))?;
+        unsafe { (*self.ctx.function).arity = arity };
+        self.declare_local(Name::EMPTY, &NO_SPAN)?;
+        self.define_local();
+        for &name in locals {
+            self.declare_local(name, &NO_SPAN)?;
+            self.define_local();
+            self.ctx.locals.last_mut().expect("local should be declared").is_const = true;
+        }
+        // `this` is only allowed inside a class.
+        if locals.contains(&Name::THIS) {
+            self.class_ctx.push(ClassCtx { has_super: false });
+        }
+        Ok(())
+    }
+
+    /// Compiles a script that calls `main` with `args` as strings and returns
+    /// its result. If `main` takes more parameters than there are arguments,
+    /// the rest are `nil`, unless they have default values.
+    pub(crate) fn compile_main(
+        main: *mut ObjectClosure,
+        args: &[String],
+        gc: &mut Gc,
+    ) -> Result<*mut ObjectFunction> {
+        let min_arity = unsafe { (*(*main).function).min_arity() } as usize;
+        let mut values = args.iter().map(|arg| gc.alloc(arg.as_str()).into()).collect::<Vec<_>>();
+        values.resize(values.len().max(min_arity), Value::NIL);
+        Self::compile_call(main.into(), &values, gc)
+    }
+
+    /// Compiles a script that calls `callee` with `args` and returns its
+    /// result.
+    pub(crate) fn compile_call(
+        callee: Value,
+        args: &[Value],
+        gc: &mut Gc,
+    ) -> Result<*mut ObjectFunction> {
+        let arg_count =
+            u8::try_from(args.len()).map_err(|_| (OverflowError::TooManyArgs.into(), NO_SPAN))?;
+        let mut compiler = Self::new(gc, CompilerOptions::default());
+        for &value in std::iter::once(&callee).chain(args) {
+            compiler.emit_u8(op::CONSTANT, &NO_SPAN);
+            compiler.emit_constant(value, &NO_SPAN)?;
+        }
+        compiler.emit_u8(op::CALL, &NO_SPAN);
+        compiler.emit_u8(arg_count, &NO_SPAN);
         compiler.emit_u8(op::RETURN, &NO_SPAN);
+        compiler.set_max_stack(0)?;
 
         Ok(compiler.ctx.function)
     }
 
     fn compile_stmt(&mut self, (stmt, span): &StmtS, gc: &mut Gc) -> Result<()> {
+        if !matches!(stmt, Stmt::Block(_)) {
+            self.mark_safe_point();
+        }
         match stmt {
+            Stmt::Assert(assert) => {
+                // Comparisons are checked by ASSERT_INFIX, so that their
+                // operands can be shown if the assertion fails.
+                let (value, value_span) = &assert.value;
+                let op = match value {
+                    Expr::Infix(infix) => match infix.op {
+                        OpInfix::Equal => Some(op::EQUAL),
+                        OpInfix::NotEqual => Some(op::NOT_EQUAL),
+                        OpInfix::Greater => Some(op::GREATER),
+                        OpInfix::GreaterEqual => Some(op::GREATER_EQUAL),
+                        OpInfix::Less => Some(op::LESS),
+                        OpInfix::LessEqual => Some(op::LESS_EQUAL),
+                        OpInfix::Is => Some(op::IS),
+                        _ => None,
+                    }
+                    .map(|op| (infix, op)),
+                    _ => None,
+                };
+                match op {
+                    Some((infix, op)) => {
+                        self.compile_expr(&infix.lt, gc)?;
+                        self.compile_expr(&infix.rt, gc)?;
+                        self.emit_u8(op::ASSERT_INFIX, value_span);
+                        self.emit_u8(op, value_span);
+                    }
+                    None => {
+                        self.compile_expr(&assert.value, gc)?;
+                        self.emit_u8(op::ASSERT, value_span);
+                    }
+                }
+            }
             Stmt::Block(block) => {
                 self.begin_scope();
                 for stmt in &block.stmts {
-                    self.compile_stmt(stmt, gc)?;
+                    self.compile_stmt_recover(stmt, gc);
                 }
                 self.end_scope(span);
             }
             Stmt::Class(class) => {
                 let has_super = class.super_.is_some();
 
-                let name = gc.alloc(&class.name).into();
+                let name = gc.alloc(class.name).into();
                 self.emit_u8(op::CLASS, span);
                 self.emit_constant(name, span)?;
 
-                if self.is_global() {
+                // A global class that only has methods is kept on the stack for
+                // them, instead of being looked up again after it is defined.
+                let is_global = self.is_global();
+                let keep_class = is_global && !has_super && !class.methods.is_empty();
+                if keep_class {
+                    self.emit_u8(op::DUP, span);
+                }
+                if is_global {
+                    self.check_redefine_global(class.name, span)?;
                     self.emit_u8(op::DEFINE_GLOBAL, span);
                     self.emit_constant(name, span)?;
                 } else {
-                    self.declare_local(&class.name, span)?;
+                    self.declare_local(class.name, span)?;
                     self.define_local();
                 }
 
@@ -97,25 +361,27 @@ impl Compiler {
                     };
 
                     self.begin_scope();
-                    self.declare_local("super", &NO_SPAN)?;
+                    self.declare_local(Name::SUPER, &NO_SPAN)?;
                     self.define_local();
 
                     self.compile_expr(super_, gc)?;
-                    self.get_variable(&class.name, span, gc)?;
+                    self.get_variable(class.name, span, gc)?;
                     self.emit_u8(op::INHERIT, span);
                 }
 
                 if !class.methods.is_empty() {
-                    self.get_variable(&class.name, span, gc)?;
+                    if !keep_class {
+                        self.get_variable(class.name, span, gc)?;
+                    }
                     for (method, span) in &class.methods {
-                        let type_ = if method.name == "init" {
+                        let type_ = if method.name == Name::INIT {
                             FunctionType::Initializer
                         } else {
                             FunctionType::Method
                         };
                         self.compile_function(method, span, type_, gc)?;
 
-                        let name = gc.alloc(&method.name).into();
+                        let name = gc.alloc(method.name).into();
                         self.emit_u8(op::METHOD, span);
                         self.emit_constant(name, span)?;
                     }
@@ -127,11 +393,38 @@ impl Compiler {
                 }
                 self.class_ctx.pop().expect("attempted to pop the global context");
             }
-            Stmt::Error => panic!("tried to compile despite parser errors"),
+            // The parser reports an error for every statement it could not
+            // parse, so this is only reached with a syntax tree that was not
+            // built by the parser.
+            Stmt::Error => return Err((SyntaxError::InvalidToken.into(), span.clone())),
             Stmt::Expr(expr) => {
                 self.compile_expr(&expr.value, gc)?;
                 self.emit_u8(op::POP, span);
             }
+            Stmt::Extend(extend) => {
+                if !self.options.class_extensions {
+                    return Err((SyntaxError::ExtendNotEnabled.into(), span.clone()));
+                }
+                self.compile_expr(&extend.class, gc)?;
+
+                // The class may not have been declared here, so its methods
+                // cannot use `super`.
+                self.class_ctx.push(ClassCtx { has_super: false });
+                for (method, span) in &extend.methods {
+                    let type_ = if method.name == Name::INIT {
+                        FunctionType::Initializer
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.compile_function(method, span, type_, gc)?;
+
+                    let name = gc.alloc(method.name).into();
+                    self.emit_u8(op::EXTEND, span);
+                    self.emit_constant(name, span)?;
+                }
+                self.class_ctx.pop().expect("attempted to pop the global context");
+                self.emit_u8(op::POP, span);
+            }
             Stmt::For(for_) => {
                 self.begin_scope();
 
@@ -143,22 +436,29 @@ impl Compiler {
 
                 // START:
                 let loop_start = self.start_loop();
+                // The condition and the increment are stepped over like
+                // statements of their own, since they run on every iteration.
+                self.mark_safe_point();
 
-                // Evaluate the condition, if it exists.
+                // Evaluate the condition, if it exists and is not constant.
+                let cond = for_.cond.as_ref().map(|cond| (cond, self.eval_cond(cond)));
                 let mut jump_to_end = None;
-                if let Some(cond) = &for_.cond {
-                    self.compile_expr(cond, gc)?;
+                if let Some((cond, None)) = cond {
                     // If the condition is false, go to END.
-                    jump_to_end = Some(self.emit_jump(op::JUMP_IF_FALSE, span));
-                    // Discard the condition.
-                    self.emit_u8(op::POP, span);
+                    jump_to_end = Some(self.compile_cond(cond, span, gc)?);
                 }
 
                 // Evaluate the body.
-                self.compile_stmt(&for_.body, gc)?;
+                match &for_.init {
+                    Some((Stmt::Var(var), _)) if self.options.per_iteration_bindings => {
+                        self.compile_for_body_per_iteration(var.var.name, &for_.body, span, gc)?;
+                    }
+                    _ => self.compile_stmt(&for_.body, gc)?,
+                }
 
                 // Evaluate the increment expression, if it exists.
                 if let Some(incr) = &for_.incr {
+                    self.mark_safe_point();
                     self.compile_expr(incr, gc)?;
                     // Discard the result of the expression.
                     self.emit_u8(op::POP, span);
@@ -166,11 +466,14 @@ impl Compiler {
 
                 // Go to START.
                 self.emit_loop(loop_start, span)?;
+                // A loop that never runs is still compiled, so that its errors
+                // are reported, but its code is left out.
+                if let Some((_, Some(false))) = cond {
+                    self.truncate(loop_start);
+                }
                 // END:
                 if let Some(jump_to_end) = jump_to_end {
                     self.patch_jump(jump_to_end, span)?;
-                    // Discard the condition.
-                    self.emit_u8(op::POP, span);
                 }
 
                 self.end_scope(span);
@@ -178,37 +481,63 @@ impl Compiler {
             Stmt::Fun(fun) => {
                 self.compile_function(fun, span, FunctionType::Function, gc)?;
                 if self.is_global() {
-                    let name = gc.alloc(&fun.name).into();
+                    self.check_redefine_global(fun.name, span)?;
+                    let name = gc.alloc(fun.name).into();
                     self.emit_u8(op::DEFINE_GLOBAL, span);
                     self.emit_constant(name, span)?;
                 } else {
-                    self.declare_local(&fun.name, span)?;
+                    self.declare_local(fun.name, span)?;
                     self.define_local();
                 }
             }
+            // A branch that the condition always skips is still compiled, so
+            // that its errors are reported, but its code is left out.
+            Stmt::If(if_) if self.eval_cond(&if_.cond).is_some() => {
+                let cond = self.eval_cond(&if_.cond) == Some(true);
+                let start = self.ops_len();
+                self.compile_stmt(&if_.then, gc)?;
+                if !cond {
+                    self.truncate(start);
+                }
+                if let Some(else_) = &if_.else_ {
+                    let start = self.ops_len();
+                    self.compile_stmt(else_, gc)?;
+                    if cond {
+                        self.truncate(start);
+                    }
+                }
+            }
             Stmt::If(if_) => {
-                self.compile_expr(&if_.cond, gc)?;
                 // If the condition is false, go to ELSE.
-                let jump_to_else = self.emit_jump(op::JUMP_IF_FALSE, span);
-                // Discard the condition.
-                self.emit_u8(op::POP, span);
+                let jump_to_else = self.compile_cond(&if_.cond, span, gc)?;
                 // Evaluate the if branch.
                 self.compile_stmt(&if_.then, gc)?;
-                // Go to END.
-                let jump_to_end = self.emit_jump(op::JUMP, span);
 
-                // ELSE:
-                self.patch_jump(jump_to_else, span)?;
-                self.emit_u8(op::POP, span); // Discard the condition.
-                if let Some(else_) = &if_.else_ {
-                    self.compile_stmt(else_, gc)?;
-                }
+                match &if_.else_ {
+                    Some(else_) => {
+                        // Go to END.
+                        let jump_to_end = self.emit_jump(op::JUMP, span);
 
-                // END:
-                self.patch_jump(jump_to_end, span)?;
+                        // ELSE:
+                        self.patch_jump(jump_to_else, span)?;
+                        self.compile_stmt(else_, gc)?;
+
+                        // END:
+                        self.patch_jump(jump_to_end, span)?;
+                    }
+                    // ELSE:
+                    None => self.patch_jump(jump_to_else, span)?,
+                }
             }
             Stmt::Print(print) => {
-                self.compile_expr(&print.value, gc)?;
+                // Each value is printed as soon as it is evaluated, so that
+                // output is not lost if a later value fails.
+                let (last, values) = print.values.split_last().expect("print without values");
+                for value in values {
+                    self.compile_expr(value, gc)?;
+                    self.emit_u8(op::PRINT_SPACED, span);
+                }
+                self.compile_expr(last, gc)?;
                 self.emit_u8(op::PRINT, span);
             }
             Stmt::Return(return_) => {
@@ -225,21 +554,86 @@ impl Compiler {
                             self.emit_u8(0, span);
                         }
                     },
-                    FunctionType::Function | FunctionType::Method => match &return_.value {
-                        Some(value) => self.compile_expr(value, gc)?,
-                        None => self.emit_u8(op::NIL, span),
-                    },
+                    FunctionType::Function | FunctionType::Lambda | FunctionType::Method => {
+                        match &return_.value {
+                            Some(value) => self.compile_expr(value, gc)?,
+                            None if self.ctx.finally.is_empty() => {
+                                self.emit_u8(op::RETURN_NIL, span);
+                                return Ok(());
+                            }
+                            None => self.emit_u8(op::NIL, span),
+                        }
+                    }
+                }
+                if self.ctx.finally.is_empty() {
+                    self.emit_u8(op::RETURN, span);
+                } else {
+                    // The return value is kept in a hidden local while the
+                    // enclosing finally blocks run.
+                    self.begin_scope();
+                    self.declare_local(Name::EMPTY, span)?;
+                    self.define_local();
+                    self.compile_finally_blocks(gc)?;
+                    self.emit_u8(op::RETURN, span);
+                    self.end_scope_unreachable();
                 }
-                self.emit_u8(op::RETURN, span);
+            }
+            Stmt::Try(try_) => {
+                let Some(finally) = &try_.finally else {
+                    let catch = try_.catch.as_ref().expect("try without catch or finally");
+                    return self.compile_try_catch(&try_.body, catch, span, gc);
+                };
+
+                // If the body or the catch block fails, go to FINALLY_ERROR.
+                let outer_handlers = self.ctx.handlers;
+                let jump_to_finally_error = self.emit_jump(op::TRY_FINALLY, span);
+                self.ctx.handlers += 1;
+                self.ctx.finally.push((finally.clone(), outer_handlers));
+                match &try_.catch {
+                    Some(catch) => self.compile_try_catch(&try_.body, catch, span, gc)?,
+                    None => self.compile_stmt(&try_.body, gc)?,
+                }
+                self.ctx.finally.pop();
+                self.ctx.handlers = outer_handlers;
+                self.emit_u8(op::END_TRY, span);
+
+                // Run the finally block, then go to END.
+                self.compile_stmt(finally, gc)?;
+                let jump_to_end = self.emit_jump(op::JUMP, span);
+
+                // FINALLY_ERROR:
+                // A placeholder for the error is on top of the stack. Run the
+                // finally block, then raise the error again.
+                self.patch_jump(jump_to_finally_error, span)?;
+                self.begin_scope();
+                self.declare_local(Name::EMPTY, span)?;
+                self.define_local();
+                self.compile_stmt(finally, gc)?;
+                self.emit_u8(op::RETHROW, span);
+                self.end_scope_unreachable();
+
+                // END:
+                self.patch_jump(jump_to_end, span)?;
             }
             Stmt::Var(var) => {
-                let name = &var.var.name;
+                let name = var.var.name;
                 if self.is_global() {
-                    let name = gc.alloc(name);
-                    match &var.value {
-                        Some(value) => self.compile_expr(value, gc)?,
-                        None => self.emit_u8(op::NIL, span),
+                    self.check_redefine_global(name, span)?;
+                    // Initializers that only use literals and constants are
+                    // evaluated here, so that the global is defined from a
+                    // single constant.
+                    let literal = var.value.as_ref().and_then(|value| self.eval_const(value));
+                    match (&literal, &var.value) {
+                        (Some(literal), Some((_, value_span))) => {
+                            self.compile_literal(literal, value_span, gc)?
+                        }
+                        (_, Some(value)) => self.compile_expr(value, gc)?,
+                        (_, None) => self.emit_u8(op::NIL, span),
                     }
+                    if var.is_const {
+                        self.consts.insert(name, literal);
+                    }
+                    let name = gc.alloc(name);
                     self.emit_u8(op::DEFINE_GLOBAL, span);
                     self.emit_constant(name.into(), span)?;
                 } else {
@@ -249,29 +643,124 @@ impl Compiler {
                         None => self.emit_u8(op::NIL, span),
                     }
                     self.define_local();
+                    if var.is_const {
+                        self.ctx.locals.last_mut().expect("local should be declared").is_const =
+                            true;
+                    }
                 }
             }
             Stmt::While(while_) => {
                 // START:
                 let loop_start = self.start_loop();
 
-                // Evaluate condition.
-                self.compile_expr(&while_.cond, gc)?;
-                // If the condition is false, go to END.
-                let jump_to_end = self.emit_jump(op::JUMP_IF_FALSE, span);
-                // Discard the condition.
-                self.emit_u8(op::POP, span);
+                // Evaluate the condition, unless it is constant. If it is
+                // false, go to END.
+                let cond = self.eval_cond(&while_.cond);
+                let jump_to_end = match cond {
+                    Some(_) => None,
+                    None => Some(self.compile_cond(&while_.cond, span, gc)?),
+                };
                 // Evaluate the body of the loop.
                 self.compile_stmt(&while_.body, gc)?;
                 // Go to START.
                 self.emit_loop(loop_start, span)?;
+                // A loop that never runs is still compiled, so that its errors
+                // are reported, but its code is left out.
+                if cond == Some(false) {
+                    self.truncate(loop_start);
+                }
 
                 // END:
-                self.patch_jump(jump_to_end, span)?;
-                // Discard the condition.
-                self.emit_u8(op::POP, span);
+                if let Some(jump_to_end) = jump_to_end {
+                    self.patch_jump(jump_to_end, span)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the body of a `for` loop, such that it runs with a fresh copy
+    /// of the loop variable. The copy is written back to the loop variable at
+    /// the end of the body, so that the increment sees any changes made to it.
+    fn compile_for_body_per_iteration(
+        &mut self,
+        name: Name,
+        body: &StmtS,
+        span: &Span,
+        gc: &mut Gc,
+    ) -> Result<()> {
+        let outer_idx = self
+            .ctx
+            .resolve_local(name, false, span)?
+            .expect("loop variable should be declared as a local");
+
+        self.begin_scope();
+        self.declare_local(name, span)?;
+        self.emit_u8(op::GET_LOCAL, span);
+        self.emit_u8(outer_idx, span);
+        self.define_local();
+        let inner_idx = self
+            .ctx
+            .resolve_local(name, false, span)?
+            .expect("loop variable copy should be declared as a local");
+
+        self.compile_stmt(body, gc)?;
+
+        self.emit_u8(op::GET_LOCAL, span);
+        self.emit_u8(inner_idx, span);
+        self.emit_u8(op::SET_LOCAL, span);
+        self.emit_u8(outer_idx, span);
+        self.emit_u8(op::POP, span);
+        self.end_scope(span);
+        Ok(())
+    }
+
+    fn compile_try_catch(
+        &mut self,
+        body: &StmtS,
+        catch: &Catch,
+        span: &Span,
+        gc: &mut Gc,
+    ) -> Result<()> {
+        // If the body fails, go to CATCH.
+        let jump_to_catch = self.emit_jump(op::TRY, span);
+        self.ctx.handlers += 1;
+        self.compile_stmt(body, gc)?;
+        self.ctx.handlers -= 1;
+        self.emit_u8(op::END_TRY, span);
+        // Go to END.
+        let jump_to_end = self.emit_jump(op::JUMP, span);
+
+        // CATCH:
+        // The error is on top of the stack, and becomes a local.
+        self.patch_jump(jump_to_catch, span)?;
+        self.begin_scope();
+        self.declare_local(catch.name, span)?;
+        self.define_local();
+        self.compile_stmt(&catch.body, gc)?;
+        self.end_scope(span);
+
+        // END:
+        self.patch_jump(jump_to_end, span)
+    }
+
+    /// Runs the finally blocks that enclose a `return`, innermost first.
+    /// Before each one runs, the handlers installed inside of its `try` block
+    /// are removed, so that errors in it are not caught there.
+    fn compile_finally_blocks(&mut self, gc: &mut Gc) -> Result<()> {
+        let handlers = self.ctx.handlers;
+        let finally = self.ctx.finally.clone();
+        for (idx, (block, outer_handlers)) in finally.iter().enumerate().rev() {
+            for _ in *outer_handlers..self.ctx.handlers {
+                self.emit_u8(op::END_TRY, &block.1);
             }
+            self.ctx.handlers = *outer_handlers;
+            // A return in the block only runs the blocks outside of it.
+            self.ctx.finally.truncate(idx);
+            self.compile_stmt(block, gc)?;
         }
+        self.ctx.handlers = handlers;
+        self.ctx.finally = finally;
         Ok(())
     }
 
@@ -282,7 +771,7 @@ impl Compiler {
         type_: FunctionType,
         gc: &mut Gc,
     ) -> Result<()> {
-        let name = gc.alloc(&fun.name);
+        let name = gc.alloc(fun.name);
         let arity = fun
             .params
             .len()
@@ -296,29 +785,65 @@ impl Compiler {
             upvalues: ArrayVec::new(),
             parent: None,
             scope_depth: self.ctx.scope_depth + 1,
+            handlers: 0,
+            finally: Vec::new(),
         };
         self.begin_ctx(ctx);
 
         match type_ {
-            FunctionType::Initializer | FunctionType::Method => self.declare_local("this", span),
-            FunctionType::Function | FunctionType::Script => self.declare_local(&fun.name, span),
+            FunctionType::Initializer | FunctionType::Method => {
+                self.declare_local(Name::THIS, span)
+            }
+            FunctionType::Function | FunctionType::Script => self.declare_local(fun.name, span),
+            // Like the hidden locals used by return, an empty name can never be
+            // referred to.
+            FunctionType::Lambda => self.declare_local(Name::EMPTY, span),
         }?;
         self.define_local();
 
-        for param in &fun.params {
+        for &param in &fun.params {
             self.declare_local(param, span)?;
             self.define_local();
         }
 
         for stmt in &fun.body.stmts {
-            self.compile_stmt(stmt, gc)?;
+            self.compile_stmt_recover(stmt, gc);
         }
 
-        // Implicit return at the end of the function.
-        if unsafe { (*self.ctx.function).chunk.ops.last() } != Some(&op::RETURN) {
-            let stmt = (Stmt::Return(StmtReturn { value: None }), NO_SPAN);
-            self.compile_stmt(&stmt, gc)?;
+        // Implicit return at the end of the function. This is needed even if
+        // the last op is a return, since a jump may still land after it.
+        let stmt = (Stmt::Return(StmtReturn { value: None }), NO_SPAN);
+        self.compile_stmt(&stmt, gc)?;
+
+        // Calls that leave out parameters with default values start here
+        // instead, at the first parameter that was left out, so that calls
+        // with every argument pay nothing for them. Each default replaces the
+        // nil that the VM passed in its place, and then the body runs.
+        if !fun.defaults.is_empty() {
+            // The defaults can only see the parameters, not the locals of the
+            // body.
+            self.ctx.locals.truncate(1 + fun.params.len());
+            self.end_local_infos();
+            let first_slot = 1 + fun.params.len() - fun.defaults.len();
+            let mut entries = Vec::with_capacity(fun.defaults.len());
+            for (slot, default) in (first_slot..).zip(&fun.defaults) {
+                entries.push(unsafe { (*self.ctx.function).chunk.ops.len() });
+                self.mark_safe_point();
+                self.compile_expr(default, gc)?;
+                self.emit_u8(op::SET_LOCAL, &default.1);
+                self.emit_u8(slot as u8, &default.1);
+                self.emit_u8(op::POP, &default.1);
+            }
+            self.emit_loop(0, span)?;
+            unsafe { (*self.ctx.function).entries = entries };
+        }
+        // The bytecode of a function with errors in it is never run, and may
+        // not even be consistent.
+        if self.errors.is_empty() {
+            self.set_max_stack(arity as usize + 1)?;
         }
+        self.ctx.locals.clear();
+        self.end_local_infos();
 
         let (function, upvalues) = self.end_ctx();
         let value = function.into();
@@ -333,12 +858,40 @@ impl Compiler {
         Ok(())
     }
 
+    /// Compiles a statement of a block or function body. If it fails, the
+    /// error is recorded, and the compiler is put back in the state it was in
+    /// before the statement, as if it was not there.
+    fn compile_stmt_recover(&mut self, stmt: &StmtS, gc: &mut Gc) {
+        let ctx_depth = self.ctx_depth();
+        let locals = self.ctx.locals.len();
+        let scope_depth = self.ctx.scope_depth;
+        let handlers = self.ctx.handlers;
+        let finally = self.ctx.finally.len();
+        let class_ctx = self.class_ctx.len();
+
+        if let Err(e) = self.compile_stmt(stmt, gc) {
+            while self.ctx_depth() > ctx_depth {
+                self.end_ctx();
+            }
+            self.ctx.locals.truncate(locals);
+            self.ctx.scope_depth = scope_depth;
+            self.ctx.handlers = handlers;
+            self.ctx.finally.truncate(finally);
+            self.class_ctx.truncate(class_ctx);
+            // A `finally` block is compiled once for each way out of its
+            // `try`, so its errors would otherwise be repeated.
+            if !self.errors.contains(&e) {
+                self.errors.push(e);
+            }
+        }
+    }
+
     /// Compute an expression and push it onto the stack.
     fn compile_expr(&mut self, (expr, span): &ExprS, gc: &mut Gc) -> Result<()> {
         match expr {
             Expr::Assign(assign) => {
                 self.compile_expr(&assign.value, gc)?;
-                self.set_variable(&assign.var.name, span, gc)?;
+                self.set_variable(assign.var.name, span, gc)?;
             }
             Expr::Call(call) => {
                 let arg_count = call
@@ -354,7 +907,7 @@ impl Compiler {
                             self.compile_expr(arg, gc)?;
                         }
 
-                        let name = gc.alloc(&get.name).into();
+                        let name = gc.alloc(get.name).into();
                         self.emit_u8(op::INVOKE, span);
                         self.emit_constant(name, span)?;
                         self.emit_u8(arg_count, span);
@@ -364,13 +917,13 @@ impl Compiler {
                             return Err((SyntaxError::SuperWithoutSuperclass.into(), span.clone()));
                         }
                         Some(_) => {
-                            self.get_variable("this", span, gc)?;
+                            self.get_variable(Name::THIS, span, gc)?;
                             for arg in &call.args {
                                 self.compile_expr(arg, gc)?;
                             }
-                            self.get_variable("super", span, gc)?;
+                            self.get_variable(Name::SUPER, span, gc)?;
 
-                            let name = gc.alloc(&super_.name).into();
+                            let name = gc.alloc(super_.name).into();
                             self.emit_u8(op::SUPER_INVOKE, span);
                             self.emit_constant(name, span)?;
                             self.emit_u8(arg_count, span);
@@ -379,24 +932,41 @@ impl Compiler {
                             return Err((SyntaxError::SuperOutsideClass.into(), span.clone()));
                         }
                     },
-                    _ => {
-                        self.compile_expr(&call.callee, gc)?;
-                        for arg in &call.args {
-                            self.compile_expr(arg, gc)?;
+                    _ => match self.direct_native(&call.callee) {
+                        Some(native_idx) => {
+                            for arg in &call.args {
+                                self.compile_expr(arg, gc)?;
+                            }
+
+                            self.emit_u8(op::CALL_NATIVE, span);
+                            self.emit_u8(native_idx, span);
+                            self.emit_u8(arg_count, span);
                         }
+                        None => {
+                            self.compile_expr(&call.callee, gc)?;
+                            for arg in &call.args {
+                                self.compile_expr(arg, gc)?;
+                            }
 
-                        self.emit_u8(op::CALL, span);
-                        self.emit_u8(arg_count, span);
-                    }
+                            self.emit_u8(op::CALL, span);
+                            self.emit_u8(arg_count, span);
+                        }
+                    },
                 }
             }
+            Expr::Fun(fun) => self.compile_function(fun, span, FunctionType::Lambda, gc)?,
             Expr::Get(get) => {
                 self.compile_expr(&get.object, gc)?;
 
-                let name = gc.alloc(&get.name).into();
+                let name = gc.alloc(get.name).into();
                 self.emit_u8(op::GET_PROPERTY, span);
                 self.emit_constant(name, span)?;
             }
+            Expr::Index(index) => {
+                self.compile_expr(&index.object, gc)?;
+                self.compile_expr(&index.index, gc)?;
+                self.emit_u8(op::GET_INDEX, span);
+            }
             Expr::Infix(infix) => {
                 self.compile_expr(&infix.lt, gc)?;
                 match infix.op {
@@ -416,6 +986,14 @@ impl Compiler {
                         self.compile_expr(&infix.rt, gc)?;
                         self.emit_u8(op::DIVIDE, span);
                     }
+                    OpInfix::Modulo => {
+                        self.compile_expr(&infix.rt, gc)?;
+                        self.emit_u8(op::MODULO, span);
+                    }
+                    OpInfix::Power => {
+                        self.compile_expr(&infix.rt, gc)?;
+                        self.emit_u8(op::POWER, span);
+                    }
                     OpInfix::Less => {
                         self.compile_expr(&infix.rt, gc)?;
                         self.emit_u8(op::LESS, span);
@@ -440,6 +1018,14 @@ impl Compiler {
                         self.compile_expr(&infix.rt, gc)?;
                         self.emit_u8(op::NOT_EQUAL, span);
                     }
+                    OpInfix::Is => {
+                        self.compile_expr(&infix.rt, gc)?;
+                        self.emit_u8(op::IS, span);
+                    }
+                    OpInfix::Concat => {
+                        self.compile_expr(&infix.rt, gc)?;
+                        self.emit_u8(op::CONCAT, span);
+                    }
                     OpInfix::LogicAnd => {
                         // If the first expression is false, go to END.
                         let jump_to_end = self.emit_jump(op::JUMP_IF_FALSE, span);
@@ -470,23 +1056,35 @@ impl Compiler {
                     }
                 };
             }
-            Expr::Literal(literal) => match literal {
-                ExprLiteral::Bool(true) => self.emit_u8(op::TRUE, span),
-                ExprLiteral::Bool(false) => self.emit_u8(op::FALSE, span),
-                ExprLiteral::Nil => self.emit_u8(op::NIL, span),
-                ExprLiteral::Number(number) => {
-                    let value = (*number).into();
-                    self.emit_u8(op::CONSTANT, span);
-                    self.emit_constant(value, span)?;
-                }
-                ExprLiteral::String(string) => {
-                    let string = gc.alloc(string);
-                    unsafe { (*string).common.is_marked = true };
-                    let value = string.into();
-                    self.emit_u8(op::CONSTANT, span);
-                    self.emit_constant(value, span)?;
+            Expr::List(list) => {
+                let count = list
+                    .elements
+                    .len()
+                    .try_into()
+                    .map_err(|_| (OverflowError::TooManyElements.into(), span.clone()))?;
+                for element in &list.elements {
+                    self.compile_expr(element, gc)?;
                 }
-            },
+                self.emit_u8(op::LIST, span);
+                self.emit_u8(count, span);
+            }
+            Expr::Literal(literal) => self.compile_literal(literal, span, gc)?,
+            // As with `Stmt::Error`, the parser reports an error for every
+            // missing expression.
+            Expr::Missing => return Err((SyntaxError::InvalidToken.into(), span.clone())),
+            Expr::Map(map) => {
+                let count = map
+                    .entries
+                    .len()
+                    .try_into()
+                    .map_err(|_| (OverflowError::TooManyElements.into(), span.clone()))?;
+                for (key, value) in &map.entries {
+                    self.compile_expr(key, gc)?;
+                    self.compile_expr(value, gc)?;
+                }
+                self.emit_u8(op::MAP, span);
+                self.emit_u8(count, span);
+            }
             Expr::Prefix(prefix) => {
                 self.compile_expr(&prefix.rt, gc)?;
                 match prefix.op {
@@ -498,34 +1096,99 @@ impl Compiler {
                 self.compile_expr(&set.value, gc)?;
                 self.compile_expr(&set.object, gc)?;
 
-                let name = gc.alloc(&set.name).into();
+                let name = gc.alloc(set.name).into();
                 self.emit_u8(op::SET_PROPERTY, span);
                 self.emit_constant(name, span)?;
             }
+            Expr::SetIndex(set) => {
+                self.compile_expr(&set.object, gc)?;
+                self.compile_expr(&set.index, gc)?;
+                self.compile_expr(&set.value, gc)?;
+                self.emit_u8(op::SET_INDEX, span);
+            }
             Expr::Super(super_) => match self.class_ctx.last() {
                 Some(class_ctx) if !class_ctx.has_super => {
                     return Err((SyntaxError::SuperWithoutSuperclass.into(), span.clone()));
                 }
                 Some(_) => {
-                    let name = gc.alloc(&super_.name).into();
-                    self.get_variable("this", span, gc)?;
-                    self.get_variable("super", span, gc)?;
+                    let name = gc.alloc(super_.name).into();
+                    self.get_variable(Name::THIS, span, gc)?;
+                    self.get_variable(Name::SUPER, span, gc)?;
                     self.emit_u8(op::GET_SUPER, span);
                     self.emit_constant(name, span)?;
                 }
                 None => return Err((SyntaxError::SuperOutsideClass.into(), span.clone())),
             },
-            Expr::Var(var) => self.get_variable(&var.var.name, span, gc)?,
+            Expr::Var(var) => self.get_variable(var.var.name, span, gc)?,
+        }
+        Ok(())
+    }
+
+    fn compile_literal(&mut self, literal: &ExprLiteral, span: &Span, gc: &mut Gc) -> Result<()> {
+        match literal {
+            ExprLiteral::Bool(true) => self.emit_u8(op::TRUE, span),
+            ExprLiteral::Bool(false) => self.emit_u8(op::FALSE, span),
+            ExprLiteral::Nil => self.emit_u8(op::NIL, span),
+            // Comparing bits rules out -0, which prints differently.
+            &ExprLiteral::Number(number) if (number as u8 as f64).to_bits() == number.to_bits() => {
+                self.emit_u8(op::SMALL_INT, span);
+                self.emit_u8(number as u8, span);
+            }
+            ExprLiteral::Number(number) => {
+                let value = (*number).into();
+                self.emit_u8(op::CONSTANT, span);
+                self.emit_constant(value, span)?;
+            }
+            ExprLiteral::String(string) => {
+                let string = gc.alloc(string);
+                unsafe { (*string).common.is_marked = true };
+                let value = string.into();
+                self.emit_u8(op::CONSTANT, span);
+                self.emit_constant(value, span)?;
+            }
         }
         Ok(())
     }
 
+    /// Evaluates `expr` if it is pure and cannot fail. See [`consts::eval`].
+    fn eval_const(&self, expr: &ExprS) -> Option<ExprLiteral> {
+        consts::eval(expr, &|name| self.const_value(name))
+    }
+
+    /// Returns whether the condition `expr` is known to always hold or never
+    /// to. See [`consts::cond`].
+    fn eval_cond(&self, expr: &ExprS) -> Option<bool> {
+        consts::cond(expr, &|name| self.const_value(name))
+    }
+
+    /// Returns the value of the global constant `name`, if it is known and
+    /// not shadowed by a local.
+    fn const_value(&self, name: Name) -> Option<ExprLiteral> {
+        match self.ctx.is_const(name) {
+            Some(_) => None,
+            None => self.consts.get(&name)?.clone(),
+        }
+    }
+
     /// Pushes the current ctx to parent and assigns it to the given ctx.
     fn begin_ctx(&mut self, ctx: CompilerCtx) {
         let ctx = mem::replace(&mut self.ctx, ctx);
         self.ctx.parent = Some(Box::new(ctx));
     }
 
+    /// Records how deep the stack of the current function gets, given the
+    /// number of slots that its frame starts out with.
+    fn set_max_stack(&mut self, frame_size: usize) -> Result<()> {
+        let function = self.ctx.function;
+        unsafe { (*function).max_stack = verify::max_stack(&*function, frame_size)? };
+        Ok(())
+    }
+
+    /// Returns the number of functions that enclose the current ctx.
+    fn ctx_depth(&self) -> usize {
+        std::iter::successors(self.ctx.parent.as_deref(), |ctx| ctx.parent.as_deref()).count()
+    }
+
     /// Pops the current ctx and extracts a [`Function`] from it.
     fn end_ctx(&mut self) -> (*mut ObjectFunction, ArrayVec<Upvalue, 256>) {
         let parent = self.ctx.parent.take().expect("tried to end context in a script");
@@ -533,8 +1196,8 @@ impl Compiler {
         (ctx.function, ctx.upvalues)
     }
 
-    fn get_variable(&mut self, name: &str, span: &Span, gc: &mut Gc) -> Result<()> {
-        if name == "this" && self.class_ctx.is_empty() {
+    fn get_variable(&mut self, name: Name, span: &Span, gc: &mut Gc) -> Result<()> {
+        if name == Name::THIS && self.class_ctx.is_empty() {
             return Err((SyntaxError::ThisOutsideClass.into(), span.clone()));
         }
         if let Some(local_idx) = self.ctx.resolve_local(name, false, span)? {
@@ -543,7 +1206,11 @@ impl Compiler {
         } else if let Some(upvalue_idx) = self.ctx.resolve_upvalue(name, span)? {
             self.emit_u8(op::GET_UPVALUE, span);
             self.emit_u8(upvalue_idx, span);
+        } else if let Some(Some(literal)) = self.consts.get(&name) {
+            let literal = literal.clone();
+            self.compile_literal(&literal, span, gc)?;
         } else {
+            self.check_global(name, span)?;
             let name = gc.alloc(name);
             self.emit_u8(op::GET_GLOBAL, span);
             self.emit_constant(name.into(), span)?;
@@ -551,7 +1218,15 @@ impl Compiler {
         Ok(())
     }
 
-    fn set_variable(&mut self, name: &str, span: &Span, gc: &mut Gc) -> Result<()> {
+    fn set_variable(&mut self, name: Name, span: &Span, gc: &mut Gc) -> Result<()> {
+        let is_const = match self.ctx.is_const(name) {
+            Some(is_const) => is_const,
+            None => self.options.const_globals.contains(name.as_str()),
+        };
+        if is_const {
+            return Err((NameError::AssignToConst { name: name.to_string() }.into(), span.clone()));
+        }
+
         if let Some(local_idx) = self.ctx.resolve_local(name, false, span)? {
             self.emit_u8(op::SET_LOCAL, span);
             self.emit_u8(local_idx, span);
@@ -559,6 +1234,7 @@ impl Compiler {
             self.emit_u8(op::SET_UPVALUE, span);
             self.emit_u8(upvalue_idx, span);
         } else {
+            self.check_global(name, span)?;
             let name = gc.alloc(name);
             self.emit_u8(op::SET_GLOBAL, span);
             self.emit_constant(name.into(), span)?;
@@ -566,7 +1242,46 @@ impl Compiler {
         Ok(())
     }
 
-    fn declare_local(&mut self, name: &str, span: &Span) -> Result<()> {
+    /// Returns the index in [`Native::ALL`] of the native that `callee` always
+    /// refers to, if it is the global of one of `native_globals`.
+    fn direct_native(&self, (callee, _): &ExprS) -> Option<u8> {
+        let Expr::Var(var) = callee else { return None };
+        let name = var.var.name;
+        if self.ctx.is_const(name).is_some() {
+            return None;
+        }
+        let native = self
+            .options
+            .native_globals
+            .iter()
+            .find(|native| native.to_string() == name.as_str())?;
+        let native_idx = Native::ALL.iter().position(|other| other == native)?;
+        Some(native_idx as u8)
+    }
+
+    /// In strict mode, checks that the given global is defined.
+    fn check_global(&self, name: Name, span: &Span) -> Result<()> {
+        match &self.options.strict_globals {
+            Some(globals) if !globals.contains(name.as_str()) => {
+                Err((NameError::NotDefined { name: name.to_string() }.into(), span.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that a global being defined does not redefine a constant. The
+    /// declaration of the constant itself is the first to define it.
+    fn check_redefine_global(&self, name: Name, span: &Span) -> Result<()> {
+        if self.consts.contains_key(&name) {
+            return Err((
+                NameError::AlreadyDefined { name: name.to_string() }.into(),
+                span.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn declare_local(&mut self, name: Name, span: &Span) -> Result<()> {
         for local in self.ctx.locals.iter().rev() {
             if local.depth < self.ctx.scope_depth {
                 break;
@@ -580,10 +1295,11 @@ impl Compiler {
         }
 
         let local = Local {
-            name: name.to_string(),
+            name,
             depth: self.ctx.scope_depth,
             is_initialized: false,
             is_captured: false,
+            is_const: false,
         };
         self.ctx
             .locals
@@ -592,11 +1308,30 @@ impl Compiler {
     }
 
     fn define_local(&mut self) {
-        self.ctx
-            .locals
-            .last_mut()
-            .expect("tried to define a local without declaring it")
-            .is_initialized = true;
+        let local =
+            self.ctx.locals.last_mut().expect("tried to define a local without declaring it");
+        local.is_initialized = true;
+        let name = local.name;
+
+        // Hidden locals are left out of the debug info, since they cannot be
+        // referred to.
+        if name != Name::EMPTY {
+            let slot = (self.ctx.locals.len() - 1).try_into().expect("local index overflow");
+            let local = LocalInfo { name, slot, ops: self.ops_len()..usize::MAX };
+            unsafe { (*self.ctx.function).chunk.locals.push(local) };
+        }
+    }
+
+    /// Ends the debug info of the locals that have gone out of scope, at the
+    /// next op.
+    fn end_local_infos(&mut self) {
+        let (len, end) = (self.ctx.locals.len(), self.ops_len());
+        let locals = unsafe { &mut (*self.ctx.function).chunk.locals };
+        for local in locals.iter_mut().filter(|local| local.ops.end == usize::MAX) {
+            if local.slot as usize >= len {
+                local.ops.end = end;
+            }
+        }
     }
 
     /// A jump takes 1 byte for the instruction followed by 2 bytes for the
@@ -604,6 +1339,22 @@ impl Compiler {
     /// to the correct value.
     ///
     /// It returns the index of the offset which is to be patched.
+    /// Compiles the condition of a statement, followed by a jump that is taken
+    /// if it is false, and returns the index of the jump offset to be patched.
+    /// The condition is popped whether or not the jump is taken.
+    fn compile_cond(&mut self, cond: &ExprS, span: &Span, gc: &mut Gc) -> Result<usize> {
+        match &cond.0 {
+            Expr::Prefix(prefix) if prefix.op == OpPrefix::Not => {
+                self.compile_expr(&prefix.rt, gc)?;
+                Ok(self.emit_jump(op::JUMP_IF_TRUE_POP, span))
+            }
+            _ => {
+                self.compile_expr(cond, gc)?;
+                Ok(self.emit_jump(op::JUMP_IF_FALSE_POP, span))
+            }
+        }
+    }
+
     fn emit_jump(&mut self, opcode: u8, span: &Span) -> usize {
         self.emit_u8(opcode, span);
         self.emit_u8(0xFF, span);
@@ -628,6 +1379,16 @@ impl Compiler {
         Ok(())
     }
 
+    fn ops_len(&self) -> usize {
+        unsafe { (*self.ctx.function).chunk.ops.len() }
+    }
+
+    /// Drops the code emitted from `len` onwards, which must not be the
+    /// target of any jump outside of it.
+    fn truncate(&mut self, len: usize) {
+        unsafe { (*self.ctx.function).chunk.truncate(len) };
+    }
+
     fn start_loop(&self) -> usize {
         unsafe { (*self.ctx.function).chunk.ops.len() }
     }
@@ -667,6 +1428,22 @@ impl Compiler {
                 break;
             }
         }
+        self.end_local_infos();
+    }
+
+    /// Ends a scope whose end is never reached, since it ends in a return or
+    /// a RETHROW. Its locals are forgotten without popping them.
+    fn end_scope_unreachable(&mut self) {
+        self.ctx.scope_depth -= 1;
+        while self.ctx.locals.last().is_some_and(|local| local.depth > self.ctx.scope_depth) {
+            self.ctx.locals.pop();
+        }
+        self.end_local_infos();
+    }
+
+    /// Marks the next op as the start of a statement.
+    fn mark_safe_point(&mut self) {
+        unsafe { (*self.ctx.function).chunk.add_safe_point() };
     }
 
     fn emit_u8(&mut self, byte: u8, span: &Span) {
@@ -693,10 +1470,25 @@ pub struct CompilerCtx {
     upvalues: ArrayVec<Upvalue, 256>,
     parent: Option<Box<CompilerCtx>>,
     scope_depth: usize,
+    /// The number of handlers installed by the `try` blocks that enclose the
+    /// current statement.
+    handlers: usize,
+    /// The `finally` blocks that enclose the current statement, innermost
+    /// last, each with the value of `handlers` outside of its `try` block.
+    finally: Vec<(StmtS, usize)>,
 }
 
 impl CompilerCtx {
-    fn resolve_local(&mut self, name: &str, capture: bool, span: &Span) -> Result<Option<u8>> {
+    /// Returns whether the local or upvalue that `name` resolves to is a
+    /// constant, or [`None`] if it resolves to a global.
+    fn is_const(&self, name: Name) -> Option<bool> {
+        match self.locals.iter().rfind(|local| local.name == name) {
+            Some(local) => Some(local.is_const),
+            None => self.parent.as_ref()?.is_const(name),
+        }
+    }
+
+    fn resolve_local(&mut self, name: Name, capture: bool, span: &Span) -> Result<Option<u8>> {
         match self.locals.iter_mut().enumerate().rfind(|(_, local)| local.name == name) {
             Some((idx, local)) => {
                 if local.is_initialized {
@@ -715,14 +1507,14 @@ impl CompilerCtx {
         }
     }
 
-    fn resolve_upvalue(&mut self, name: &str, span: &Span) -> Result<Option<u8>> {
+    fn resolve_upvalue(&mut self, name: Name, span: &Span) -> Result<Option<u8>> {
         let local_idx = match &mut self.parent {
             Some(parent) => parent.resolve_local(name, true, span)?,
             None => return Ok(None),
         };
 
         if let Some(local_idx) = local_idx {
-            let upvalue_idx = self.add_upvalue(local_idx, true, span)?;
+            let upvalue_idx = self.add_upvalue(name, local_idx, true, span)?;
             return Ok(Some(upvalue_idx));
         };
 
@@ -732,14 +1524,14 @@ impl CompilerCtx {
         };
 
         if let Some(upvalue_idx) = upvalue_idx {
-            let upvalue_idx = self.add_upvalue(upvalue_idx, false, span)?;
+            let upvalue_idx = self.add_upvalue(name, upvalue_idx, false, span)?;
             return Ok(Some(upvalue_idx));
         };
 
         Ok(None)
     }
 
-    fn add_upvalue(&mut self, idx: u8, is_local: bool, span: &Span) -> Result<u8> {
+    fn add_upvalue(&mut self, name: Name, idx: u8, is_local: bool, span: &Span) -> Result<u8> {
         let upvalue = Upvalue { idx, is_local };
         let upvalue_idx = match self.upvalues.iter().position(|u| u == &upvalue) {
             Some(upvalue_idx) => upvalue_idx,
@@ -750,7 +1542,8 @@ impl CompilerCtx {
                 let upvalues = self.upvalues.len();
                 unsafe {
                     (*self.function).upvalue_count =
-                        upvalues.try_into().expect("upvalue index overflow")
+                        upvalues.try_into().expect("upvalue index overflow");
+                    (*self.function).chunk.upvalue_names.push(name);
                 };
                 upvalues - 1
             }
@@ -760,6 +1553,22 @@ impl CompilerCtx {
     }
 }
 
+/// What a script compiled by [`Compiler::compile_script`] returns, and which
+/// locals it can see.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum ScriptKind<'a> {
+    /// A program, which returns `nil`.
+    Program,
+    /// A program that returns the value of its last statement, if that is an
+    /// expression.
+    Eval,
+    /// Like [`ScriptKind::Eval`], but compiled as a function that takes the
+    /// values of these locals as arguments, so that the program can read them
+    /// as its own. They are constants, since assigning to them would only
+    /// change the copies.
+    InFrame(&'a [Name]),
+}
+
 #[derive(Debug)]
 struct ClassCtx {
     has_super: bool,
@@ -768,13 +1577,14 @@ struct ClassCtx {
 #[derive(Debug, Default)]
 struct Local {
     /// The name of the variable.
-    name: String,
+    name: Name,
     /// The scope depth of the variable, i.e. the number of nested scopes that
     /// surround it. This starts at 1, because global scopes don't have local
     /// variables.
     depth: usize,
     is_initialized: bool,
     is_captured: bool,
+    is_const: bool,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -787,6 +1597,8 @@ struct Upvalue {
 enum FunctionType {
     /// A function that has been defined in code.
     Function,
+    /// An anonymous function, which cannot refer to itself by name.
+    Lambda,
     /// A class initializer.
     Initializer,
     /// A bound method.
@@ -796,3 +1608,267 @@ enum FunctionType {
 }
 
 const NO_SPAN: Span = 0..0;
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::error::Error;
+    use crate::vm::{VM, VmOptions};
+
+    fn compile_strict(source: &str) -> Result<(), Vec<ErrorS>> {
+        let mut gc = Gc::default();
+        let options = CompilerOptions {
+            strict_globals: Some(["clock".to_string()].into_iter().collect()),
+            ..Default::default()
+        };
+        Compiler::compile_with(source, 0, &mut gc, options).map(|_| ())
+    }
+
+    fn run(source: &str, options: VmOptions) -> String {
+        let mut output = Vec::new();
+        VM::new(options).run(source, &mut output).expect("program exited with errors");
+        str::from_utf8(&output).expect("invalid UTF-8 in output").to_string()
+    }
+
+    #[test]
+    fn multiple_errors() {
+        let source = r#"
+            print this;
+            fun f(a, a) { return; }
+            {
+                var b = 1;
+                var b = 2;
+                try {} finally { return 1; }
+            }
+            class A { init() { return 1; } }
+            fun g() { print b; return super.x; }
+        "#;
+        let errors = Compiler::compile(source, 0, &mut Gc::default())
+            .unwrap_err()
+            .into_iter()
+            .map(|(e, _)| e)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                Error::SyntaxError(SyntaxError::ThisOutsideClass),
+                Error::NameError(NameError::AlreadyDefined { name: "a".to_string() }),
+                Error::NameError(NameError::AlreadyDefined { name: "b".to_string() }),
+                Error::SyntaxError(SyntaxError::ReturnOutsideFunction),
+                Error::SyntaxError(SyntaxError::ReturnInInitializer),
+                Error::SyntaxError(SyntaxError::SuperOutsideClass),
+            ]
+        );
+    }
+
+    #[test]
+    fn strict_allows_defined_globals() {
+        let source = r#"
+            fun foo() { return bar + clock(); }
+            var bar = 1;
+            class Baz < Qux {}
+            class Qux {}
+            bar = foo();
+        "#;
+        assert_eq!(compile_strict(source), Ok(()));
+    }
+
+    #[test]
+    fn strict_rejects_undefined_globals() {
+        let source = "fun foo() { return bar; }";
+        let exp = Err(vec![(
+            Error::NameError(NameError::NotDefined { name: "bar".to_string() }),
+            19..22,
+        )]);
+        assert_eq!(compile_strict(source), exp);
+
+        let source = "{ var foo = 1; } foo = 2;";
+        let exp = Err(vec![(
+            Error::NameError(NameError::NotDefined { name: "foo".to_string() }),
+            17..24,
+        )]);
+        assert_eq!(compile_strict(source), exp);
+    }
+
+    #[test]
+    fn per_iteration_bindings() {
+        let source = r#"
+            var fns = nil;
+            class Node {}
+            for (var i = 0; i < 3; i = i + 1) {
+                fun f() { print i; }
+                var node = Node();
+                node.f = f;
+                node.next = fns;
+                fns = node;
+                if (i == 1) i = i + 10;
+            }
+            while (fns != nil) {
+                fns.f();
+                fns = fns.next;
+            }
+        "#;
+        assert_eq!(run(source, VmOptions::default()), "12\n12\n");
+        let options = VmOptions { per_iteration_bindings: true, ..Default::default() };
+        assert_eq!(run(source, options), "11\n0\n");
+    }
+
+    #[test]
+    fn class_extensions() {
+        let source = r#"
+            class A { f() { return "A.f"; } g() { return "A.g"; } }
+            class B < A { g() { return "B.g"; } }
+            class C < B {}
+            var c = C();
+            extend A {
+                init(x) { this.x = x; }
+                f() { return "new A.f"; }
+                g() { return "new A.g"; }
+                h() { return this.x; }
+            }
+            print c.f(), c.g();
+            print C(1).h(), A(2).h();
+        "#;
+        // Subclasses get the new methods, unless they override them.
+        let options = VmOptions { class_extensions: true, ..Default::default() };
+        assert_eq!(run(source, options.clone()), "new A.f B.g\n1 2\n");
+
+        let error = |source, options| {
+            let errors = VM::new(options).run(source, &mut Vec::new()).unwrap_err();
+            errors[0].0.to_string()
+        };
+        assert_eq!(
+            error("class A {} extend A {}", VmOptions::default()),
+            r#"SyntaxError: "extend" is only allowed with class extensions enabled"#
+        );
+        assert_eq!(
+            error("var A = 1; extend A { f() {} }", options.clone()),
+            r#"TypeError: extended value should be of type "class", not "number""#
+        );
+        assert_eq!(
+            error("class A {} extend A { f() { super.f(); } }", options),
+            r#"SyntaxError: "super" used in class without a superclass"#
+        );
+    }
+
+    #[test]
+    fn max_stack() {
+        let mut gc = Gc::default();
+        let script = Compiler::compile("fun f(a, b) { return a + (b + 1); } f(1, 2);", 0, &mut gc)
+            .expect("program should compile");
+        // f, 1, 2
+        assert_eq!(unsafe { (*script).max_stack }, 3);
+
+        let f = unsafe { (*script).chunk.constants[0].as_object().function };
+        // f, a, b, a, b, 1
+        assert_eq!(unsafe { (*f).max_stack }, 6);
+    }
+
+    #[test]
+    fn const_fold() {
+        let mut gc = Gc::default();
+        let script = Compiler::compile("const a = 1.5; fun f() { return a; }", 0, &mut gc)
+            .expect("program should compile");
+        let f = unsafe { (*script).chunk.constants[2].as_object().function };
+        // The constant is read from the chunk instead of the global.
+        assert_eq!(unsafe { &(*f).chunk.ops }, &[op::CONSTANT, 0, op::RETURN, op::RETURN_NIL]);
+    }
+
+    #[test]
+    fn const_eval() {
+        let mut gc = Gc::default();
+        let script = Compiler::compile(
+            r#"const a = 60 * 60 * 24; const b = "x" + "y"; var c = -a > 0 and !nil; var d = a + nil;"#,
+            0,
+            &mut gc,
+        )
+        .expect("program should compile");
+        let chunk = unsafe { &(*script).chunk };
+        // Each pure initializer is a single constant, while `a + nil` is left
+        // to fail at runtime.
+        assert_eq!(chunk.constants[0].as_number(), 86400.0);
+        assert_eq!(unsafe { chunk.constants[2].as_object().string.as_ref().unwrap().value }, "xy");
+        assert_eq!(
+            chunk.ops,
+            [
+                op::CONSTANT,
+                0,
+                op::DEFINE_GLOBAL,
+                1,
+                op::CONSTANT,
+                2,
+                op::DEFINE_GLOBAL,
+                3,
+                op::FALSE,
+                op::DEFINE_GLOBAL,
+                4,
+                op::CONSTANT,
+                0,
+                op::NIL,
+                op::ADD,
+                op::DEFINE_GLOBAL,
+                5,
+                op::RETURN_NIL,
+            ]
+        );
+    }
+
+    #[test]
+    fn dead_branches() {
+        let mut gc = Gc::default();
+        let script = Compiler::compile(
+            "const debug = false; if (debug) print 1; else print 2; while (debug) print 3; for (; debug;) print 4;",
+            0,
+            &mut gc,
+        )
+        .expect("program should compile");
+        // Only the else branch is left.
+        assert_eq!(
+            unsafe { &(*script).chunk.ops },
+            &[op::FALSE, op::DEFINE_GLOBAL, 0, op::SMALL_INT, 2, op::PRINT, op::RETURN_NIL]
+        );
+
+        // Branches that never run must still compile.
+        let errors = Compiler::compile("if (false) { print this; }", 0, &mut gc)
+            .expect_err("program should not compile");
+        assert!(matches!(errors[..], [(Error::SyntaxError(_), _)]), "{errors:?}");
+    }
+
+    #[test]
+    fn small_int() {
+        let mut gc = Gc::default();
+        let script = Compiler::compile("print 0, 255, 256, 1.5, 7; const z = -0;", 0, &mut gc)
+            .expect("program should compile");
+        let chunk = unsafe { &(*script).chunk };
+        // Only 256, 1.5, -0 and the name of z need constants.
+        assert_eq!(chunk.constants.len(), 4);
+        assert_eq!(
+            chunk.ops[..8],
+            [
+                op::SMALL_INT,
+                0,
+                op::PRINT_SPACED,
+                op::SMALL_INT,
+                255,
+                op::PRINT_SPACED,
+                op::CONSTANT,
+                0
+            ]
+        );
+    }
+
+    #[test]
+    fn const_across_runs() {
+        let mut vm = VM::new(VmOptions::default());
+        vm.run("const a = 1;", &mut Vec::new()).expect("program should run");
+        for source in ["a = 2;", "var a = 2;", "fun f() { a = 2; }"] {
+            let errors = vm.run(source, &mut Vec::new()).expect_err("program should not compile");
+            assert!(matches!(errors[..], [(Error::NameError(_), _)]), "{errors:?}");
+        }
+        vm.run("{ var a = 2; a = 3; }", &mut Vec::new()).expect("program should run");
+    }
+}
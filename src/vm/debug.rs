@@ -0,0 +1,359 @@
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use crate::error::ErrorS;
+use crate::syntax::ast::Name;
+use crate::types::{LineIndex, Span};
+use crate::vm::compiler::ScriptKind;
+use crate::vm::object::{ObjectClosure, ObjectFunction};
+use crate::vm::value::Value;
+use crate::vm::{CallFrame, Step, VM};
+
+/// Called by [`VM::run_with_hook`] before each op of a script, while the
+/// [`VM`] can be inspected, and code can be evaluated with
+/// [`VM::eval_in_frame`].
+pub trait DebugHook {
+    /// Returning [`ControlFlow::Break`] stops the script before the op runs.
+    fn before_op(&mut self, vm: &mut VM) -> ControlFlow<()>;
+}
+
+/// A function that is running, as seen from a [`DebugHook`].
+#[derive(Debug)]
+pub struct DebugFrame<'a> {
+    pub function: &'a ObjectFunction,
+    pub name: &'a str,
+    /// The number of functions running, counting this one and the ones that
+    /// called it. The script itself is at depth 1.
+    pub depth: usize,
+    /// The index of the op that runs next in this frame.
+    pub ip: usize,
+    /// The span of the op that runs next in the innermost frame, or of the
+    /// call that is running in the frames that called it.
+    pub span: Span,
+    /// The stack slots of this frame, from the bottom up. Slot 0 holds the
+    /// function itself, or `this` in methods, followed by the arguments, the
+    /// locals, and the temporary values of the expression being run.
+    pub slots: &'a [Value],
+    closure: &'a ObjectClosure,
+}
+
+impl DebugFrame<'_> {
+    /// Returns whether the next op of this frame starts a statement.
+    pub fn at_statement(&self) -> bool {
+        self.function.chunk.is_safe_point(self.ip)
+    }
+
+    /// The locals and upvalues that are in scope at the next op of this
+    /// frame, with their values. Where a name is shadowed, only the innermost
+    /// variable is returned.
+    pub fn variables(&self) -> Vec<(Name, Value)> {
+        let chunk = &self.function.chunk;
+        let mut variables = chunk
+            .upvalue_names
+            .iter()
+            .zip(&self.closure.upvalues)
+            .map(|(&name, &upvalue)| (name, unsafe { *(*upvalue).location }))
+            .collect::<Vec<_>>();
+        for local in chunk.locals_at(self.ip) {
+            if let Some(&value) = self.slots.get(local.slot as usize) {
+                variables.retain(|&(name, _)| name != local.name);
+                variables.push((local.name, value));
+            }
+        }
+        variables
+    }
+}
+
+impl VM {
+    /// Runs `source` like [`VM::run`], calling `hook` before each op. If the
+    /// hook stops the script, this returns without an error.
+    pub fn run_with_hook(
+        &mut self,
+        source: &str,
+        hook: &mut impl DebugHook,
+        stdout: &mut impl Write,
+    ) -> Result<(), Vec<ErrorS>> {
+        self.load(source)?;
+        loop {
+            if hook.before_op(self).is_break() {
+                self.is_running = false;
+                return Ok(());
+            }
+            match self.step(stdout) {
+                Ok(Step::Running) => {}
+                Ok(Step::Finished(_)) => return Ok(()),
+                Err(e) => return Err(vec![e]),
+            }
+        }
+    }
+
+    /// Evaluates `source` like [`VM::eval`], while a script is paused in a
+    /// [`DebugHook`], as if it were code in the function at `frame_idx` of
+    /// [`VM::debug_frames`]. The locals and upvalues in scope there can be
+    /// read, but not assigned to. The paused script carries on as if nothing
+    /// had run, other than the changes that `source` made to globals and
+    /// objects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no frame at `frame_idx`.
+    pub fn eval_in_frame(
+        &mut self,
+        frame_idx: usize,
+        source: &str,
+        stdout: &mut impl Write,
+    ) -> Result<Value, Vec<ErrorS>> {
+        let frames = self.debug_frames();
+        let frame = frames.get(frame_idx).expect("frame index out of range");
+        let (names, values): (Vec<_>, Vec<_>) = frame.variables().into_iter().unzip();
+
+        let offset = self.source.len();
+        self.source.reserve(source.len() + 1);
+        self.source.push_str(source);
+        self.source.push('\n');
+
+        let function =
+            self.compile_phases(source, offset, ScriptKind::InFrame(&names), &mut None)?;
+        let closure = self.gc.alloc(ObjectClosure::new(function, Vec::new()));
+        self.call_function(closure.into(), &values, stdout).map_err(|e| vec![e])
+    }
+
+    /// The innermost function that is running, if any.
+    pub fn current_frame(&self) -> Option<DebugFrame<'_>> {
+        if !self.is_running || self.frame.closure.is_null() {
+            return None;
+        }
+        let end = self.stack_len();
+        Some(self.debug_frame(&self.frame, self.frames.len() + 1, end, true))
+    }
+
+    /// The functions that are running, innermost first.
+    pub fn debug_frames(&self) -> Vec<DebugFrame<'_>> {
+        let Some(current) = self.current_frame() else {
+            return Vec::new();
+        };
+        let mut end = self.slot_of(self.frame.stack);
+        let mut frames = vec![current];
+        for (idx, frame) in self.frames.iter().enumerate().rev() {
+            frames.push(self.debug_frame(frame, idx + 1, end, false));
+            end = self.slot_of(frame.stack);
+        }
+        frames
+    }
+
+    fn debug_frame(
+        &self,
+        frame: &CallFrame,
+        depth: usize,
+        end: usize,
+        innermost: bool,
+    ) -> DebugFrame<'_> {
+        let function = unsafe { &*(*frame.closure).function };
+        let ip = unsafe { frame.ip.offset_from(function.chunk.ops.as_ptr()) } as usize;
+        let span_idx = if innermost { Some(ip) } else { ip.checked_sub(1) };
+        let span = span_idx.and_then(|idx| function.chunk.span_at(idx));
+        DebugFrame {
+            function,
+            name: unsafe { (*function.name).value },
+            depth,
+            ip,
+            span: span.cloned().unwrap_or_default(),
+            slots: &self.stack[self.slot_of(frame.stack)..end],
+            closure: unsafe { &*frame.closure },
+        }
+    }
+
+    fn slot_of(&self, ptr: *const Value) -> usize {
+        unsafe { ptr.offset_from(self.stack.as_ptr()) as usize }
+    }
+}
+
+/// What a [`Debugger`] does until it next pauses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    /// Pause at the next statement on another line, in any function.
+    Step,
+    /// Pause at the next statement on another line, in the current function
+    /// or one that called it.
+    Next { depth: usize },
+    /// Pause at the next statement after the current function returns.
+    Finish { depth: usize },
+    /// Pause only at breakpoints.
+    Continue,
+}
+
+/// Decides where a script pauses while it is being debugged: at
+/// breakpoints, and after each step. Pauses happen only where a statement
+/// starts, and at most once per line, so that a statement is not paused at
+/// twice while its ops run.
+#[derive(Debug)]
+pub struct Debugger {
+    breakpoints: BTreeSet<usize>,
+    mode: Mode,
+    lines: LineIndex,
+    /// The depth and line of the last pause, until the script moves on to
+    /// another line.
+    last: Option<(usize, usize)>,
+}
+
+impl Debugger {
+    /// Creates a debugger for a script compiled from `source`, which must be
+    /// the full source of the [`VM`]. It pauses at the first statement.
+    pub fn new(source: &str) -> Self {
+        let lines = LineIndex::new(source);
+        Self { breakpoints: BTreeSet::new(), mode: Mode::Step, lines, last: None }
+    }
+
+    /// Returns the 1-indexed line of `offset` in the source.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.lines.line(offset) + 1
+    }
+
+    /// Adds a breakpoint on a 1-indexed line. Returns false if there already
+    /// was one.
+    pub fn add_breakpoint(&mut self, line: usize) -> bool {
+        self.breakpoints.insert(line)
+    }
+
+    /// Removes the breakpoint on a line. Returns false if there was none.
+    pub fn remove_breakpoint(&mut self, line: usize) -> bool {
+        self.breakpoints.remove(&line)
+    }
+
+    /// Adds a breakpoint on the line where `span` starts.
+    pub fn add_breakpoint_at(&mut self, span: &Span) -> bool {
+        self.add_breakpoint(self.line_of(span.start))
+    }
+
+    /// The lines that have breakpoints, in order.
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Pauses at the next statement, stepping into calls.
+    pub fn step(&mut self) {
+        self.mode = Mode::Step;
+    }
+
+    /// Pauses at the next statement, stepping over calls.
+    pub fn next(&mut self) {
+        let depth = self.last.map_or(usize::MAX, |(depth, _)| depth);
+        self.mode = Mode::Next { depth };
+    }
+
+    /// Pauses once the current function returns.
+    pub fn finish(&mut self) {
+        let depth = self.last.map_or(0, |(depth, _)| depth);
+        self.mode = Mode::Finish { depth };
+    }
+
+    /// Pauses at the next breakpoint.
+    pub fn continue_(&mut self) {
+        self.mode = Mode::Continue;
+    }
+
+    /// Returns the line to pause at, if the script should pause before the
+    /// next op of `frame`. Call this before every op, so that the debugger can
+    /// tell when the script leaves a line.
+    pub fn should_pause(&mut self, frame: &DebugFrame) -> Option<usize> {
+        let here = (frame.depth, self.line_of(frame.span.start));
+        if self.last == Some(here) {
+            return None;
+        }
+        self.last = None;
+        if !frame.at_statement() {
+            return None;
+        }
+        let (depth, line) = here;
+        let pause = self.breakpoints.contains(&line)
+            || match self.mode {
+                Mode::Step => true,
+                Mode::Next { depth: max } => depth <= max,
+                Mode::Finish { depth: max } => depth < max,
+                Mode::Continue => false,
+            };
+        if pause {
+            self.last = Some(here);
+            return Some(line);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    /// Pauses once, at the first statement of `inner` after `a` is declared,
+    /// and evaluates each source in the given frame.
+    struct Eval<'a> {
+        sources: &'a [(usize, &'a str)],
+        results: Vec<String>,
+    }
+
+    impl DebugHook for Eval<'_> {
+        fn before_op(&mut self, vm: &mut VM) -> ControlFlow<()> {
+            let is_paused = vm.current_frame().is_some_and(|frame| {
+                frame.name == "inner"
+                    && frame.at_statement()
+                    && frame.variables().iter().any(|&(name, _)| name.as_str() == "a")
+            });
+            if is_paused && self.results.is_empty() {
+                for &(frame_idx, source) in self.sources {
+                    let result = vm.eval_in_frame(frame_idx, source, &mut Vec::new());
+                    self.results.push(match result {
+                        Ok(value) => value.to_string(),
+                        Err(errors) => errors[0].0.to_string(),
+                    });
+                }
+            }
+            ControlFlow::Continue(())
+        }
+    }
+
+    #[test]
+    fn eval_in_frame() {
+        let source = r#"
+            var g = "global";
+            fun outer(a) {
+                var captured = a * 2;
+                fun inner(b) {
+                    var a = "shadow";
+                    print captured + b;
+                    return captured;
+                }
+                return inner(1);
+            }
+            print outer(10);
+        "#;
+        let sources = [
+            (0, r#"a + " " + g;"#),
+            (0, "captured + b;"),
+            (0, "b = 2;"),
+            (1, "a;"),
+            (1, "captured * 10;"),
+            (1, "b;"),
+        ];
+        let mut hook = Eval { sources: &sources, results: Vec::new() };
+        let mut stdout = Vec::new();
+        let mut vm = VM::default();
+        vm.run_with_hook(source, &mut hook, &mut stdout).unwrap();
+
+        assert_eq!(
+            hook.results,
+            [
+                "shadow global",
+                "21",
+                r#"NameError: cannot assign to constant "b""#,
+                "10",
+                "200",
+                r#"NameError: name "b" is not defined"#,
+            ]
+        );
+        // The script carries on as if nothing had been evaluated.
+        assert_eq!(String::from_utf8(stdout).unwrap(), "21\n20\n");
+    }
+}
@@ -0,0 +1,170 @@
+use std::any::{Any, TypeId};
+use std::fmt::{self, Debug, Formatter};
+use std::hash::BuildHasherDefault;
+use std::marker::PhantomData;
+use std::rc::Rc;
+use std::slice;
+
+use hashbrown::HashMap;
+use rustc_hash::FxHasher;
+
+use crate::error::{AttributeError, Error, Result, TypeError};
+use crate::vm::VM;
+use crate::vm::gc::Gc;
+use crate::vm::object::{ObjectForeign, ObjectType};
+use crate::vm::value::Value;
+
+type Method = dyn Fn(&mut dyn Any, &[Value], &mut Gc) -> Result<Value, Error>;
+type Trace = dyn Fn(&dyn Any, &mut Gc);
+
+/// The methods of a Rust type whose values are passed into Lox as foreign
+/// objects. Built with a [`ForeignClassBuilder`], and registered with
+/// [`VM::register_foreign`].
+pub struct ForeignClass {
+    name: String,
+    type_id: TypeId,
+    methods: HashMap<String, (usize, Box<Method>), BuildHasherDefault<FxHasher>>,
+    trace: Option<Box<Trace>>,
+}
+
+impl ForeignClass {
+    /// The name of the class, as shown when its objects are printed.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Marks the values that `value` holds, if the class was given a way to
+    /// find them.
+    pub(super) fn trace(&self, value: &dyn Any, gc: &mut Gc) {
+        if let Some(trace) = &self.trace {
+            trace(value, gc);
+        }
+    }
+}
+
+impl Debug for ForeignClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut methods = self.methods.keys().collect::<Vec<_>>();
+        methods.sort_unstable();
+        f.debug_struct("ForeignClass").field("name", &self.name).field("methods", &methods).finish()
+    }
+}
+
+/// Builds the [`ForeignClass`] of `T`.
+pub struct ForeignClassBuilder<T> {
+    class: ForeignClass,
+    _type: PhantomData<T>,
+}
+
+impl<T: Any> ForeignClassBuilder<T> {
+    pub fn new(name: impl Into<String>) -> Self {
+        let class = ForeignClass {
+            name: name.into(),
+            type_id: TypeId::of::<T>(),
+            methods: HashMap::default(),
+            trace: None,
+        };
+        Self { class, _type: PhantomData }
+    }
+
+    /// Adds a method that takes `arity` arguments. Values that the method
+    /// returns can be allocated with `gc`, since the arguments are still
+    /// reachable while it runs.
+    pub fn method(
+        mut self,
+        name: impl Into<String>,
+        arity: usize,
+        method: impl Fn(&mut T, &[Value], &mut Gc) -> Result<Value, Error> + 'static,
+    ) -> Self {
+        let method = move |value: &mut dyn Any, args: &[Value], gc: &mut Gc| {
+            method(value.downcast_mut().expect("foreign object has the wrong type"), args, gc)
+        };
+        self.class.methods.insert(name.into(), (arity, Box::new(method)));
+        self
+    }
+
+    /// Sets how to mark the Lox values that a `T` holds. Without this, a `T`
+    /// must not hold any, since they could be collected while it does.
+    pub fn trace(mut self, trace: impl Fn(&T, &mut Gc) + 'static) -> Self {
+        self.class.trace = Some(Box::new(move |value: &dyn Any, gc: &mut Gc| {
+            trace(value.downcast_ref().expect("foreign object has the wrong type"), gc)
+        }));
+        self
+    }
+
+    pub fn build(self) -> ForeignClass {
+        self.class
+    }
+}
+
+impl VM {
+    /// Lets values of the type that `class` was built for be passed into Lox
+    /// with [`VM::foreign`]. Registering a type again replaces its class for
+    /// objects created after that.
+    pub fn register_foreign(&mut self, class: ForeignClass) {
+        self.foreign_classes.insert(class.type_id, Rc::new(class));
+    }
+
+    /// Wraps `value` in a foreign object, whose methods can be called from
+    /// Lox, as in `object.method()`. Methods cannot be read without calling
+    /// them, and foreign objects have no fields.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no [`ForeignClass`] was registered for `T`.
+    pub fn foreign<T: Any>(&mut self, value: T) -> Value {
+        let Some(class) = self.foreign_classes.get(&TypeId::of::<T>()) else {
+            panic!("no foreign class is registered for {}", std::any::type_name::<T>());
+        };
+        let object = ObjectForeign::new(Rc::clone(class), Box::new(value));
+        self.alloc(object).into()
+    }
+
+    /// Returns the Rust value inside a foreign object, if `value` is one that
+    /// holds a `T`.
+    pub fn foreign_ref<T: Any>(&self, value: Value) -> Option<&T> {
+        let foreign = foreign_object(value)?;
+        unsafe { (*foreign).value.downcast_ref() }
+    }
+
+    /// Like [`VM::foreign_ref`], but lets the value be changed.
+    pub fn foreign_mut<T: Any>(&mut self, value: Value) -> Option<&mut T> {
+        let foreign = foreign_object(value)?;
+        unsafe { (*foreign).value.downcast_mut() }
+    }
+
+    /// Calls the method `name` of the foreign object under the arguments on
+    /// top of the stack, and replaces them with its result.
+    pub(super) fn invoke_foreign(
+        &mut self,
+        foreign: *mut ObjectForeign,
+        name: &str,
+        arg_count: usize,
+    ) -> Result<()> {
+        let class = Rc::clone(unsafe { &(*foreign).class });
+        let Some((arity, method)) = class.methods.get(name) else {
+            return self.err(AttributeError::NoSuchAttribute {
+                type_: class.name.clone(),
+                name: name.to_string(),
+            });
+        };
+        if arg_count != *arity {
+            return self.err(TypeError::ArityMismatch {
+                name: name.to_string(),
+                exp_args: *arity,
+                got_args: arg_count,
+            });
+        }
+        let args = unsafe { slice::from_raw_parts(self.stack_top.sub(arg_count), arg_count) };
+        let value = method(unsafe { &mut *(*foreign).value }, args, &mut self.gc)
+            .or_else(|e| self.err(e))?;
+        self.stack_top = self.peek(arg_count);
+        self.push(value);
+        Ok(())
+    }
+}
+
+fn foreign_object(value: Value) -> Option<*mut ObjectForeign> {
+    let is_foreign = value.is_object() && value.as_object().type_() == ObjectType::Foreign;
+    is_foreign.then(|| unsafe { value.as_object().foreign })
+}
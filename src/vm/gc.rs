@@ -1,11 +1,18 @@
+use std::any::TypeId;
+use std::fmt::{self, Display, Formatter};
 use std::hash::BuildHasherDefault;
-use std::mem;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
 
 use hashbrown::HashMap;
 use hashbrown::hash_map::RawEntryMut;
 use rustc_hash::FxHasher;
+use serde::Serialize;
 
-use crate::vm::object::{Object, ObjectString, ObjectType};
+use crate::vm::object::{
+    Object, ObjectBoundMethod, ObjectClass, ObjectString, ObjectType, ObjectUpvalue,
+};
+use crate::vm::root::Roots;
 use crate::vm::value::Value;
 
 #[derive(Debug, Default)]
@@ -13,6 +20,13 @@ pub struct Gc {
     strings: HashMap<String, *mut ObjectString, BuildHasherDefault<FxHasher>>,
     objects: Vec<Object>,
     gray_objects: Vec<Object>,
+    /// The live classes that have subclasses, found while tracing, whose
+    /// lists of subclasses are pruned when the rest are swept.
+    superclasses: Vec<*mut ObjectClass>,
+    counts: ObjectCounts,
+    free_lists: FreeLists,
+    /// Values held outside the VM, which are marked by every collection.
+    pub(super) roots: Roots,
 }
 
 impl Gc {
@@ -20,6 +34,17 @@ impl Gc {
         object.alloc(self)
     }
 
+    /// The number of objects of each type that have been allocated, and that
+    /// are still live.
+    pub fn counts(&self) -> &ObjectCounts {
+        &self.counts
+    }
+
+    /// The objects that have been allocated, and not yet swept.
+    pub fn objects(&self) -> impl Iterator<Item = Object> + '_ {
+        self.objects.iter().copied()
+    }
+
     pub fn mark(&mut self, object: impl GcMark) {
         object.mark(self);
     }
@@ -27,7 +52,7 @@ impl Gc {
     pub fn trace(&mut self) {
         while let Some(object) = self.gray_objects.pop() {
             if cfg!(feature = "gc-trace") {
-                eprintln!("blacken {}: {object}", object.type_());
+                tracing::trace!("blacken {}: {object}", object.type_());
             }
             match unsafe { (*object.common).type_ } {
                 ObjectType::BoundMethod => {
@@ -38,10 +63,17 @@ impl Gc {
                 ObjectType::Class => {
                     let class = unsafe { object.class };
                     self.mark(unsafe { (*class).name });
+                    if let Some(super_) = unsafe { (*class).super_ } {
+                        self.mark(super_);
+                    }
                     for (&name, &method) in unsafe { &(*class).methods } {
                         self.mark(name);
                         self.mark(method);
                     }
+                    unsafe { (*class).shape.mark(self) };
+                    if unsafe { !(*class).subclasses.is_empty() } {
+                        self.superclasses.push(class);
+                    }
                 }
                 ObjectType::Closure => {
                     let closure = unsafe { object.closure };
@@ -50,6 +82,10 @@ impl Gc {
                         self.mark(upvalue);
                     }
                 }
+                ObjectType::Foreign => {
+                    let foreign = unsafe { &*object.foreign };
+                    foreign.class.trace(&*foreign.value, self);
+                }
                 ObjectType::Function => {
                     let function = unsafe { object.function };
                     self.mark(unsafe { (*function).name });
@@ -61,10 +97,27 @@ impl Gc {
                 }
                 ObjectType::Instance => {
                     self.mark(unsafe { (*object.instance).class });
-                    for (&name, &value) in unsafe { (*object.instance).fields.iter() } {
+                    for &value in unsafe { &(*object.instance).slots } {
+                        self.mark(value);
+                    }
+                    for (&name, &value) in unsafe { &(*object.instance).overflow } {
                         self.mark(name);
                         self.mark(value);
                     }
+                    for &bound_method in unsafe { (*object.instance).bound_methods.values() } {
+                        self.mark(bound_method);
+                    }
+                }
+                ObjectType::List => {
+                    for &element in unsafe { &(*object.list).elements } {
+                        self.mark(element);
+                    }
+                }
+                ObjectType::Map => {
+                    for &(key, value) in unsafe { &(*object.map).entries } {
+                        self.mark(key);
+                        self.mark(value);
+                    }
                 }
                 ObjectType::Native => {}
                 ObjectType::String => {}
@@ -77,33 +130,166 @@ impl Gc {
     }
 
     pub fn sweep(&mut self) {
+        for class in self.superclasses.drain(..) {
+            let subclasses = unsafe { &mut (*class).subclasses };
+            subclasses.retain(|&subclass| unsafe { (*subclass).common.is_marked });
+        }
+
         for idx in (0..self.objects.len()).rev() {
             let object = *unsafe { self.objects.get_unchecked(idx) };
             if !mem::take(unsafe { &mut (*object.common).is_marked }) {
                 self.objects.swap_remove(idx);
-                object.free();
+                self.counts.free(object.type_());
+                self.free_lists.free(object);
             }
         }
 
+        let counts = &mut self.counts;
         self.strings.retain(|_, &mut string| {
             if mem::take(unsafe { &mut (*string).common.is_marked }) {
                 true
             } else {
+                counts.free(ObjectType::String);
                 let _ = unsafe { Box::from_raw(string) };
                 false
             }
         });
     }
-}
 
-impl Drop for Gc {
-    fn drop(&mut self) {
-        for object in &self.objects {
+    /// Frees every object, whether or not it is reachable, and returns how
+    /// many were freed.
+    fn free_all(&mut self) -> usize {
+        let freed = self.objects.len() + self.strings.len();
+        // Each object only owns its own memory, and refers to others through
+        // pointers, so freeing one never frees another, however the objects
+        // refer to each other.
+        for object in self.objects.drain(..) {
+            self.counts.free(object.type_());
             object.free();
         }
-        for &string in self.strings.values() {
+        for (_, string) in self.strings.drain() {
+            self.counts.free(ObjectType::String);
             let _ = unsafe { Box::from_raw(string) };
         }
+        freed
+    }
+}
+
+/// The most freed objects of a type that a [`FreeLists`] keeps. Beyond this,
+/// objects go back to the global allocator, so that a program that once had
+/// many of them does not hold on to their memory.
+const FREE_LIST_LEN: usize = 1024;
+
+/// Freed objects of the small types that programs allocate and drop the
+/// most, whose memory is reused by the next object of the same type instead
+/// of being returned to the global allocator. The objects have already been
+/// dropped, so their memory is uninitialized.
+#[derive(Debug, Default)]
+struct FreeLists {
+    bound_methods: Vec<*mut ObjectBoundMethod>,
+    upvalues: Vec<*mut ObjectUpvalue>,
+}
+
+impl FreeLists {
+    /// Returns memory for a `T`, if one was freed.
+    fn take<T: 'static>(&mut self) -> Option<*mut T> {
+        let type_id = TypeId::of::<T>();
+        if type_id == TypeId::of::<ObjectBoundMethod>() {
+            self.bound_methods.pop().map(<*mut _>::cast)
+        } else if type_id == TypeId::of::<ObjectUpvalue>() {
+            self.upvalues.pop().map(<*mut _>::cast)
+        } else {
+            None
+        }
+    }
+
+    /// Frees `object`, keeping its memory if there is room for it.
+    fn free(&mut self, object: Object) {
+        match object.type_() {
+            ObjectType::BoundMethod if self.bound_methods.len() < FREE_LIST_LEN => {
+                unsafe { ptr::drop_in_place(object.bound_method) };
+                self.bound_methods.push(unsafe { object.bound_method });
+            }
+            ObjectType::Upvalue if self.upvalues.len() < FREE_LIST_LEN => {
+                unsafe { ptr::drop_in_place(object.upvalue) };
+                self.upvalues.push(unsafe { object.upvalue });
+            }
+            _ => object.free(),
+        }
+    }
+}
+
+/// The objects in the lists have already been dropped, so only their memory
+/// is freed.
+impl Drop for FreeLists {
+    fn drop(&mut self) {
+        for &bound_method in &self.bound_methods {
+            let _ = unsafe { Box::from_raw(bound_method.cast::<MaybeUninit<ObjectBoundMethod>>()) };
+        }
+        for &upvalue in &self.upvalues {
+            let _ = unsafe { Box::from_raw(upvalue.cast::<MaybeUninit<ObjectUpvalue>>()) };
+        }
+    }
+}
+
+/// How many objects of a type a [`Gc`] has allocated.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct ObjectCount {
+    /// Every object of the type allocated so far.
+    pub allocated: u64,
+    /// The objects of the type that have not yet been freed. Some of these
+    /// may be garbage that the next collection frees.
+    pub live: u64,
+}
+
+/// The [`ObjectCount`] of each [`ObjectType`] in a [`Gc`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ObjectCounts([ObjectCount; ObjectType::ALL.len()]);
+
+impl ObjectCounts {
+    pub fn get(&self, type_: ObjectType) -> ObjectCount {
+        self.0[type_ as usize]
+    }
+
+    /// Returns the count of each type, in the order of [`ObjectType::ALL`].
+    pub fn iter(&self) -> impl Iterator<Item = (ObjectType, ObjectCount)> + '_ {
+        ObjectType::ALL.into_iter().map(|type_| (type_, self.get(type_)))
+    }
+
+    fn alloc(&mut self, type_: ObjectType) {
+        let count = &mut self.0[type_ as usize];
+        count.allocated += 1;
+        count.live += 1;
+    }
+
+    fn free(&mut self, type_: ObjectType) {
+        self.0[type_ as usize].live -= 1;
+    }
+}
+
+impl Display for ObjectCounts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<12} {:>12} {:>12}", "objects", "allocated", "live")?;
+        for (type_, count) in self.iter().filter(|(_, count)| count.allocated != 0) {
+            write!(f, "\n{:<12} {:>12} {:>12}", type_.id(), count.allocated, count.live)?;
+        }
+        Ok(())
+    }
+}
+
+/// Objects are freed one at a time from a flat list, rather than by dropping
+/// the objects they refer to, so arbitrarily deep object graphs created by a
+/// program can be dropped without recursion.
+impl Drop for Gc {
+    fn drop(&mut self) {
+        let _span = tracing::debug_span!(
+            "drop gc",
+            objects = self.objects.len(),
+            strings = self.strings.len()
+        )
+        .entered();
+        let freed = self.free_all();
+        tracing::debug!(freed, "freed every object");
     }
 }
 
@@ -111,19 +297,26 @@ pub trait GcAlloc<T> {
     fn alloc(self, gc: &mut Gc) -> T;
 }
 
-impl<T> GcAlloc<*mut T> for T
+impl<T: 'static> GcAlloc<*mut T> for T
 where
     *mut T: Into<Object>,
 {
     fn alloc(self, gc: &mut Gc) -> *mut T {
-        let object_ptr = Box::into_raw(Box::new(self));
+        let object_ptr = match gc.free_lists.take::<T>() {
+            Some(object_ptr) => {
+                unsafe { object_ptr.write(self) };
+                object_ptr
+            }
+            None => Box::into_raw(Box::new(self)),
+        };
         let object = object_ptr.into();
 
         if cfg!(feature = "gc-trace") {
-            eprintln!("allocate {}: {object}", object.type_());
+            tracing::trace!("allocate {}: {object}", object.type_());
         }
 
         gc.objects.push(object);
+        gc.counts.alloc(object.type_());
         object_ptr
     }
 }
@@ -138,12 +331,13 @@ where
             RawEntryMut::Vacant(entry) => {
                 let string = self.into();
                 if cfg!(feature = "gc-trace") {
-                    eprintln!("allocate string: {string}");
+                    tracing::trace!("allocate string: {string}");
                 }
                 let object = Box::into_raw(Box::new(ObjectString::new(unsafe {
                     mem::transmute::<&str, &str>(string.as_str())
                 })));
                 entry.insert(string, object);
+                gc.counts.alloc(ObjectType::String);
                 object
             }
         }
@@ -167,10 +361,48 @@ impl<T: Into<Object>> GcMark for T {
         let object = self.into();
         if !unsafe { (*object.common).is_marked } {
             if cfg!(feature = "gc-trace") {
-                eprintln!("mark {}: {object}", object.type_());
+                tracing::trace!("mark {}: {object}", object.type_());
             }
             unsafe { (*object.common).is_marked = true };
             gc.gray_objects.push(object);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::object::ObjectList;
+
+    #[test]
+    fn free_lists() {
+        let mut gc = Gc::default();
+        let upvalue = gc.alloc(ObjectUpvalue::new(ptr::null_mut()));
+        gc.sweep();
+        assert!(gc.free_lists.upvalues.contains(&upvalue));
+
+        // The next upvalue reuses the memory of the one that was swept.
+        let reused = gc.alloc(ObjectUpvalue::new(ptr::null_mut()));
+        assert_eq!(reused, upvalue);
+        assert!(gc.free_lists.upvalues.is_empty());
+        assert_eq!(gc.counts().get(ObjectType::Upvalue), ObjectCount { allocated: 2, live: 1 });
+    }
+
+    #[test]
+    fn drop_large_graph() {
+        // A million lists, each holding the one before it and itself, which
+        // would overflow the stack if dropping a list dropped what it holds.
+        let mut gc = Gc::default();
+        let mut prev = Value::NIL;
+        for _ in 0..1_000_000 {
+            let list = gc.alloc(ObjectList::new(vec![prev]));
+            unsafe { (*list).elements.push(list.into()) };
+            prev = list.into();
+        }
+        // Every list is freed, and none of them twice.
+        assert_eq!(gc.free_all(), 1_000_000);
+        let lists = ObjectCount { allocated: 1_000_000, live: 0 };
+        assert_eq!(gc.counts().get(ObjectType::List), lists);
+        drop(gc);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+
+use crate::types::Span;
+
+/// The last ops run by a [`VM`](crate::vm::VM), kept when
+/// [`VmOptions::history`](crate::vm::VmOptions::history) is set. When a
+/// program fails, this shows how it got there: which ops ran, what each took
+/// off the stack and put on it, and which globals it wrote.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+}
+
+/// An op in a [`History`]. Values are recorded as they were printed at the
+/// time, since the objects they point to may since have been collected.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HistoryEntry {
+    /// The name of the function that the op ran in.
+    pub function: String,
+    /// The disassembly of the op, starting with its index in the chunk.
+    pub op: String,
+    pub span: Span,
+    /// The values that the op took off the stack, from the bottom up.
+    pub popped: Vec<String>,
+    /// The values that the op left on the stack, from the bottom up. This is
+    /// empty for ops that call or return from a Lox function, since their
+    /// result is pushed by a later op.
+    pub pushed: Vec<String>,
+    /// The name and new value of the global that the op defined or assigned.
+    pub global: Option<(String, String)>,
+    /// The error that the op raised, whether or not it was caught.
+    pub error: Option<String>,
+}
+
+impl History {
+    /// Creates a history that keeps the last `capacity` ops.
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// The ops that were kept, oldest first.
+    pub fn entries(&self) -> impl ExactSizeIterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Display for History {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let width = self.entries.iter().map(|entry| entry.function.len()).max().unwrap_or(0);
+        let s = if self.entries.len() == 1 { "" } else { "s" };
+        write!(f, "previous {} operation{s}:", self.entries.len())?;
+        for entry in &self.entries {
+            write!(f, "\n  {:width$}  {entry}", entry.function)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for HistoryEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<36} [{}]", self.op, self.popped.join(", "))?;
+        match &self.error {
+            Some(error) => write!(f, " -> {error}"),
+            None => {
+                write!(f, " -> [{}]", self.pushed.join(", "))?;
+                if let Some((name, value)) = &self.global {
+                    write!(f, "  {name} = {value}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn ring() {
+        let entry = |op: &str| HistoryEntry { op: op.to_string(), ..Default::default() };
+        let mut history = History::new(2);
+        for op in ["a", "b", "c"] {
+            history.push(entry(op));
+        }
+        assert_eq!(history.entries().cloned().collect::<Vec<_>>(), [entry("b"), entry("c")]);
+
+        let mut history = History::new(0);
+        history.push(entry("a"));
+        assert!(history.is_empty());
+    }
+}
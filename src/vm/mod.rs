@@ -1,43 +1,258 @@
 mod allocator;
-mod chunk;
+mod builder;
+pub mod chunk;
 mod compiler;
+mod debug;
+mod foreign;
 mod gc;
+mod history;
+mod native;
 mod object;
-mod op;
+pub mod op;
+mod root;
+mod scheduler;
+pub mod serialize;
+mod shape;
+mod shrink;
+mod snapshot;
 mod util;
 mod value;
+mod verify;
 
+use std::any::TypeId;
 use std::hash::BuildHasherDefault;
 use std::io::Write;
-use std::{mem, ptr};
-
-use arrayvec::ArrayVec;
-pub use compiler::Compiler;
-pub use gc::Gc;
-use hashbrown::HashMap;
-use hashbrown::hash_map::Entry;
+use std::rc::Rc;
+use std::{fmt, mem, ptr, slice};
+
+pub use builder::{ChunkBuilder, Constant, FunctionChunk};
+pub use compiler::{Compiler, CompilerOptions};
+pub use debug::{DebugFrame, DebugHook, Debugger};
+pub use foreign::{ForeignClass, ForeignClassBuilder};
+pub use gc::{Gc, ObjectCount, ObjectCounts};
+use hashbrown::{HashMap, HashSet};
+pub use history::{History, HistoryEntry};
+use indexmap::IndexMap;
+use indexmap::map::Entry;
+pub use native::{CallableInfo, CallableKind, callable_info, inspect};
+pub use object::{Native, ObjectFunction, ObjectType};
+pub use root::Root;
 use rustc_hash::FxHasher;
+pub use scheduler::Scheduler;
+pub use snapshot::Snapshot;
+pub use value::Value;
+pub use verify::verify;
 
 use crate::error::{
-    AttributeError, Error, ErrorS, IoError, NameError, OverflowError, Result, TypeError,
+    AssertionError, AttributeError, Error, ErrorS, IndexError, IoError, NameError, OverflowError,
+    Result, TypeError,
 };
+use crate::syntax::ast::Stmt;
+use crate::syntax::lexer::Lexer;
+use crate::syntax::{DEFAULT_MAX_DEPTH, ParseOptions};
+use crate::timings::{self, Timings};
+use crate::types::Span;
+use crate::vfs::{FileSystem, OsFs};
 use crate::vm::allocator::GLOBAL;
+use crate::vm::compiler::ScriptKind;
 use crate::vm::gc::GcAlloc;
 use crate::vm::object::{
-    Native, ObjectBoundMethod, ObjectClass, ObjectClosure, ObjectFunction, ObjectInstance,
-    ObjectNative, ObjectString, ObjectType, ObjectUpvalue,
+    ObjectBoundMethod, ObjectClass, ObjectClosure, ObjectInstance, ObjectList, ObjectMap,
+    ObjectNative, ObjectString, ObjectUpvalue,
 };
-use crate::vm::value::Value;
+use crate::vm::snapshot::SavedGlobals;
+use crate::vm::value::ValueType;
+
+/// Asserts an invariant of the bytecode that the VM otherwise trusts the
+/// compiler to uphold. These checks only run with the `vm-checked` feature,
+/// which turns what would be undefined behaviour on bad bytecode into a panic.
+macro_rules! check_bytecode {
+    ($cond:expr, $($arg:tt)+) => {
+        if cfg!(feature = "vm-checked") {
+            assert!($cond, "invalid bytecode: {}", format_args!($($arg)+));
+        }
+    };
+}
 
 const GC_HEAP_GROW_FACTOR: usize = 2;
-const FRAMES_MAX: usize = 64;
-const STACK_MAX: usize = FRAMES_MAX * STACK_MAX_PER_FRAME;
 const STACK_MAX_PER_FRAME: usize = u8::MAX as usize + 1;
 
+/// The default for [`VmOptions::max_call_depth`].
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 64;
+/// The largest allowed value of [`VmOptions::max_call_depth`]. Since the stack
+/// is allocated upfront, this bounds the memory used by the [`VM`].
+pub const MAX_CALL_DEPTH_LIMIT: usize = 1 << 14;
+
+/// The classes of the runtime errors that `try` can catch. Each is defined as
+/// a global, and inherits from `Error`, which comes first.
+pub const ERROR_CLASSES: &[&str] = &[
+    "Error",
+    "AssertionError",
+    "AttributeError",
+    "IndexError",
+    "NameError",
+    "OverflowError",
+    "TypeError",
+];
+
+/// The state of a program after [`VM::step`] runs one of its ops.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Step {
+    /// The program has more ops to run.
+    Running,
+    /// The program has finished, and returned this value.
+    Finished(Value),
+}
+
+/// Counters for the most recent script run by a [`VM`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct VmStats {
+    /// The number of ops run. This is only counted with the `vm-stats`
+    /// feature, since it slows down every op.
+    pub ops: u64,
+    /// The number of garbage collections.
+    pub gc_count: u64,
+    /// The most memory allocated at once by the whole process, in bytes. This
+    /// is sampled whenever the VM allocates an object.
+    pub peak_heap: usize,
+}
+
+impl fmt::Display for VmStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if cfg!(feature = "vm-stats") {
+            writeln!(f, "{:<12} {:>12}", "ops", self.ops)?;
+        }
+        writeln!(f, "{:<12} {:>12}", "gc runs", self.gc_count)?;
+        write!(f, "{:<12} {:>12}", "peak heap", format!("{} KiB", (self.peak_heap + 1023) / 1024))
+    }
+}
+
+/// What calls to a function have cost, from [`VM::function_costs`]. The ops
+/// and allocations of a call are those of the function itself, not of the
+/// functions that it calls in turn.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FunctionCost<'a> {
+    pub name: &'a str,
+    pub calls: u64,
+    pub ops: u64,
+    pub allocs: u64,
+}
+
+impl fmt::Display for FunctionCost<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<20} {:>10} {:>12} {:>10} {:>10}",
+            format!("{}()", self.name),
+            self.calls,
+            self.ops,
+            self.ops / self.calls.max(1),
+            self.allocs
+        )
+    }
+}
+
+/// Configuration for a [`VM`].
+#[derive(Clone, Debug)]
+pub struct VmOptions {
+    /// Report accesses to undefined globals at compile time, instead of at
+    /// runtime.
+    pub strict: bool,
+    /// Give each iteration of a `for` loop a fresh copy of the loop variable.
+    pub per_iteration_bindings: bool,
+    /// Allow `extend` statements, which add methods to existing classes.
+    pub class_extensions: bool,
+    /// Record how long each phase of [`VM::run`] takes, in [`VM::timings`].
+    pub timings: bool,
+    /// The maximum number of nested calls, beyond which the program fails
+    /// with a stack overflow error. This is clamped to
+    /// `1..=MAX_CALL_DEPTH_LIMIT`.
+    pub max_call_depth: usize,
+    /// How deeply the program may nest statements and expressions. See
+    /// [`ParseOptions::max_depth`].
+    pub max_depth: usize,
+    /// The maximum number of bytes that a single call to [`VM::run`] may
+    /// print, beyond which the program fails. This stops runaway programs
+    /// from exhausting the memory of whatever is collecting their output.
+    pub max_output: Option<usize>,
+    /// Run [`verify`] on each program after compiling it. The compiler always
+    /// produces valid bytecode, so this is only useful for debugging the
+    /// compiler itself. Bytecode that was not compiled from source, like that
+    /// run by [`VM::run_chunk`], is always verified.
+    pub verify: bool,
+    /// Fail when a condition is not a bool, or when `==` or `!=` compare
+    /// values of different types other than `nil`. Lox allows both, but they
+    /// are usually mistakes.
+    pub strict_types: bool,
+    /// Log each op as it runs, followed by the stack of the current frame.
+    /// This is always on when built with the `vm-trace` feature.
+    pub trace: bool,
+    /// Leave out functions and classes declared at the top level that the
+    /// program never uses, to save memory. This assumes that the [`VM`] only
+    /// runs a single program, which nothing else refers to.
+    pub eliminate_dead_globals: bool,
+    /// Call natives directly, instead of through their globals, where the
+    /// program never assigns to those globals. Like `eliminate_dead_globals`,
+    /// this assumes that the [`VM`] only runs a single program, since a later
+    /// one could reassign a native that an earlier one calls directly.
+    pub direct_native_calls: bool,
+    /// The number of ops to keep in [`VM::history`], so that a failing
+    /// program can show how it reached the error. This slows down every op,
+    /// so it is off when zero.
+    pub history: usize,
+    /// The number of digits after the decimal point with which `print` shows
+    /// numbers, or [`None`] to show them exactly. See
+    /// [`number::display`](crate::number::display).
+    pub precision: Option<usize>,
+}
+
+impl Default for VmOptions {
+    fn default() -> Self {
+        Self {
+            strict: false,
+            per_iteration_bindings: false,
+            class_extensions: false,
+            timings: false,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_output: None,
+            verify: false,
+            strict_types: false,
+            trace: false,
+            eliminate_dead_globals: false,
+            direct_native_calls: false,
+            history: 0,
+            precision: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct VM {
-    pub globals: HashMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
+    pub options: VmOptions,
+    /// Timings for the most recent call to [`VM::run`], if enabled in
+    /// [`VmOptions`].
+    pub timings: Option<Timings>,
+    /// Counters for the script that is running, or that ran most recently.
+    pub stats: VmStats,
+    /// The last ops run by the script that is running, or that ran most
+    /// recently, if enabled in [`VmOptions`].
+    pub history: History,
+    /// The globals by name. Use [`VM::globals`] to read them from outside the
+    /// VM.
+    pub(crate) globals: IndexMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
     pub open_upvalues: Vec<*mut ObjectUpvalue>,
+    /// Globals declared with `const` by previous calls to [`VM::run`].
+    const_globals: HashSet<String, BuildHasherDefault<FxHasher>>,
+    /// The copies of the globals taken by [`VM::snapshot_globals`], oldest
+    /// first.
+    snapshots: Vec<SavedGlobals>,
+    /// The ID of the next snapshot. This only goes up, so that the handle of
+    /// a dropped snapshot never refers to a later one.
+    next_snapshot_id: u64,
+    /// The classes registered with [`VM::register_foreign`], by the type that
+    /// each is for.
+    foreign_classes: HashMap<TypeId, Rc<ForeignClass>, BuildHasherDefault<FxHasher>>,
 
     pub gc: Gc,
     next_gc: usize,
@@ -46,22 +261,58 @@ pub struct VM {
     ///
     /// The topmost frame points to the currently running closure, but does not
     /// include a valid instruction pointer / stack pointer.
-    frames: ArrayVec<CallFrame, FRAMES_MAX>,
+    frames: Vec<CallFrame>,
     frame: CallFrame,
+    /// The handlers installed by `try` blocks that are still running,
+    /// innermost last.
+    handlers: Vec<Handler>,
+    /// Errors set aside while `finally` blocks run, to be raised again by
+    /// RETHROW, innermost last. Each is paired with the length of `frames` in
+    /// the frame running the block.
+    unwinding: Vec<(usize, ErrorS)>,
+    /// Whether a program is running, i.e. whether [`VM::call_function`] is
+    /// being called from a native.
+    is_running: bool,
+    /// The length of `frames` below the function that the innermost call to
+    /// [`VM::call_function`] from a native is running. Returning from that
+    /// function returns to the native, and `try` blocks outside of it cannot
+    /// catch errors inside it.
+    base_frames: usize,
+    /// The number of calls from natives into Lox functions that are running.
+    /// Each counts towards [`VmOptions::max_call_depth`], since it pushes a
+    /// callee and arguments outside of the space reserved for frames.
+    native_calls: usize,
 
     /// `stack` can be safely accessed without bounds checking because:
     /// - Each frame can store a theoretical maximum of `STACK_MAX_PER_FRAME`
     ///   values on the stack.
-    /// - The frame count can never exceed `MAX_FRAMES`, otherwise we throw a
-    ///   stack overflow error.
-    /// - Thus, we can statically allocate a stack of size
-    ///   `STACK_MAX = FRAMES_MAX * STACK_MAX_PER_FRAME` and we are
+    /// - The frame count can never exceed `max_call_depth + 1` (including the
+    ///   current frame), otherwise we throw a stack overflow error.
+    /// - Thus, we can allocate a stack of size
+    ///   `(max_call_depth + 1) * STACK_MAX_PER_FRAME` upfront and we are
     ///   guaranteed to never exceed this size.
-    stack: Box<[Value; STACK_MAX]>,
+    stack: Box<[Value]>,
     stack_top: *mut Value,
 
     init_string: *mut ObjectString,
+    message_string: *mut ObjectString,
+    /// Never collected, since so many string operations produce it that it
+    /// would otherwise be freed and interned again after most collections.
+    empty_string: *mut ObjectString,
+    /// The classes named in [`ERROR_CLASSES`], in the same order.
+    error_classes: Vec<*mut ObjectClass>,
+    /// The instances held by the globals named in [`Native::MODULES`], in the
+    /// same order.
+    modules: Vec<*mut ObjectInstance>,
     pub source: String,
+    /// Where `read_file` reads files from. This is the file system of the OS,
+    /// unless the host replaces it, e.g. with a [`MemoryFs`](crate::vfs::MemoryFs).
+    pub fs: Box<dyn FileSystem>,
+    /// The number of bytes printed by the current call to [`VM::run`].
+    output_len: usize,
+    /// The state of each script loaded by a [`Scheduler`], other than the one
+    /// that is running. These are kept here so that the GC can mark them.
+    contexts: Vec<Context>,
 }
 
 impl VM {
@@ -72,105 +323,688 @@ impl VM {
         self.source.push_str(source);
         self.source.push('\n');
 
-        let function = Compiler::compile(source, offset, &mut self.gc)?;
-        self.run_function(function, stdout).map_err(|e| vec![e])?;
+        self.output_len = 0;
+        let mut timings = self.options.timings.then(Timings::default);
+        let result = self.run_phases(source, offset, stdout, &mut timings);
+        self.timings = timings;
+        result
+    }
+
+    /// Runs `source` like [`VM::run`], and returns the value of its last
+    /// statement if that is an expression, or `nil` otherwise. The value is
+    /// only kept alive until the [`VM`] runs again, unless it is rooted with
+    /// [`VM::root`].
+    pub fn eval(&mut self, source: &str, stdout: &mut impl Write) -> Result<Value, Vec<ErrorS>> {
+        let offset = self.source.len();
+
+        self.source.reserve(source.len() + 1);
+        self.source.push_str(source);
+        self.source.push('\n');
+
+        self.output_len = 0;
+        let function = self.compile_phases(source, offset, ScriptKind::Eval, &mut None)?;
+        self.run_function(function, stdout).map_err(|e| vec![e])
+    }
+
+    /// Every global, including natives, error classes and modules, with its
+    /// value, in the order in which they were first defined: natives, then
+    /// error classes, then modules, then the globals of the scripts that have
+    /// run. The values stay valid until the VM next runs code.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, Value)> {
+        self.globals.iter().map(|(&name, &value)| (unsafe { (*name).value }, value))
+    }
+
+    /// The globals defined so far, sorted by name. Natives, error classes and
+    /// modules are left out, unless they have been reassigned.
+    pub fn defined_globals(&self) -> Vec<(&str, Value)> {
+        let mut globals = self
+            .globals()
+            .filter(|&(name, value)| !self.is_builtin(name, value))
+            .collect::<Vec<_>>();
+        globals.sort_unstable_by_key(|&(name, _)| name);
+        globals
+    }
+
+    /// The `count` functions that have been called most often, with how many
+    /// times each was called, most first. Calls are only counted with the
+    /// `vm-stats` feature, so this is empty without it.
+    pub fn hot_functions(&self, count: usize) -> Vec<(&str, u64)> {
+        self.top_functions(count, |cost| cost.calls)
+            .into_iter()
+            .map(|cost| (cost.name, cost.calls))
+            .collect()
+    }
+
+    /// The `count` functions that cost the most to run, by the number of ops
+    /// run in them, most first. Like [`VM::hot_functions`], this is empty
+    /// without the `vm-stats` feature.
+    pub fn function_costs(&self, count: usize) -> Vec<FunctionCost<'_>> {
+        self.top_functions(count, |cost| cost.ops)
+    }
+
+    /// The `count` functions that have been called, with the most by `key`
+    /// first, and ties broken by name.
+    fn top_functions(&self, count: usize, key: fn(&FunctionCost) -> u64) -> Vec<FunctionCost<'_>> {
+        let mut functions = self
+            .gc
+            .objects()
+            .filter(|object| object.type_() == ObjectType::Function)
+            .map(|object| unsafe { &*object.function })
+            .filter(|function| function.calls > 0)
+            .map(|function| FunctionCost {
+                name: unsafe { (*function.name).value },
+                calls: function.calls,
+                ops: function.ops,
+                allocs: function.allocs,
+            })
+            .collect::<Vec<_>>();
+        functions.sort_unstable_by(|a, b| key(b).cmp(&key(a)).then(a.name.cmp(b.name)));
+        functions.truncate(count);
+        functions
+    }
+
+    /// Returns whether `value` is the native, error class or module that the
+    /// global `name` holds when the [`VM`] starts.
+    fn is_builtin(&self, name: &str, value: Value) -> bool {
+        if !value.is_object() {
+            return false;
+        }
+        let object = value.as_object();
+        match object.type_() {
+            ObjectType::Native => unsafe { (*object.native).native }.to_string() == name,
+            ObjectType::Class => {
+                let class = unsafe { object.class };
+                self.error_classes.contains(&class) && unsafe { (*(*class).name).value } == name
+            }
+            ObjectType::Instance => {
+                let class = unsafe { (*object.instance).class };
+                self.modules.iter().any(|&module| unsafe { (*module).class } == class)
+                    && unsafe { (*(*class).name).value } == name
+            }
+            _ => false,
+        }
+    }
+
+    /// Runs a script that was assembled with a [`ChunkBuilder`], instead of
+    /// being compiled from source. The script is checked with [`verify`] before
+    /// it runs. Errors have empty spans, since there is no source to point
+    /// into.
+    pub fn run_chunk(&mut self, chunk: &ChunkBuilder, stdout: &mut impl Write) -> Result<()> {
+        let function = chunk.load(&mut self.gc)?;
+        self.output_len = 0;
+        self.run_function(function, stdout).map(|_| ())
+    }
+
+    /// Calls the `main` function defined by a previous call to [`VM::run`],
+    /// if there is one, and returns the exit code that it asks for.
+    ///
+    /// `main` is called with `args` as strings. If it takes more parameters
+    /// than there are arguments, the rest are `nil`, and if it takes fewer,
+    /// the call fails like any other. It should return an integer, which is
+    /// the exit code, or `nil`, which means 0. A `main` that is not a
+    /// function is ignored.
+    pub fn run_main(
+        &mut self,
+        args: &[String],
+        stdout: &mut impl Write,
+    ) -> Result<Option<i32>, Vec<ErrorS>> {
+        let name = self.gc.alloc("main");
+        let main = match self.globals.get(&name) {
+            Some(&value) if value.type_() == ValueType::Object(ObjectType::Closure) => unsafe {
+                value.as_object().closure
+            },
+            _ => return Ok(None),
+        };
+
+        let function = Compiler::compile_main(main, args, &mut self.gc).map_err(|e| vec![e])?;
+        self.output_len = 0;
+        let value = self.run_function(function, stdout).map_err(|e| vec![e])?;
+        if value.is_nil() {
+            return Ok(Some(0));
+        }
+        if value.is_number() {
+            let number = value.as_number();
+            if number.fract() == 0.0 && number >= i32::MIN as f64 && number <= i32::MAX as f64 {
+                return Ok(Some(number as i32));
+            }
+        }
+        Err(vec![(TypeError::MainInvalidReturn { value: value.to_string() }.into(), 0..0)])
+    }
+
+    /// Calls `callee` with `args`, and returns the value that it returns.
+    ///
+    /// This can be used by natives to call back into Lox code while a program
+    /// is running, and by embedders to call functions defined by an earlier
+    /// call to [`VM::run`]. Errors that are not caught inside the call are
+    /// returned, rather than being caught by `try` blocks around the native,
+    /// so that the native can clean up before raising them again.
+    pub fn call_function(
+        &mut self,
+        callee: Value,
+        args: &[Value],
+        stdout: &mut impl Write,
+    ) -> Result<Value> {
+        if !self.is_running {
+            let function = Compiler::compile_call(callee, args, &mut self.gc)?;
+            self.output_len = 0;
+            return self.run_function(function, stdout);
+        }
+
+        if self.frames.len() + self.native_calls + 1 >= self.options.max_call_depth {
+            return self.err(OverflowError::StackOverflow);
+        }
+        let stack_top = self.stack_top;
+        let frames = self.frames.len();
+        self.push(callee);
+        for &arg in args {
+            self.push(arg);
+        }
+        if let Err(e) = self.call_value(callee, args.len(), stdout) {
+            self.stack_top = stack_top;
+            return Err(e);
+        }
+        // Natives and classes without an initializer return immediately,
+        // without pushing a frame.
+        if self.frames.len() == frames {
+            let value = self.pop();
+            self.stack_top = stack_top;
+            return Ok(value);
+        }
+
+        let base_frames = mem::replace(&mut self.base_frames, self.frames.len());
+        self.native_calls += 1;
+        let result = self.execute(stdout);
+        self.native_calls -= 1;
+        self.base_frames = base_frames;
+
+        // If the call failed, unwind the frames that it left behind.
+        while self.handlers.last().is_some_and(|h| h.frames > frames) {
+            self.handlers.pop();
+        }
+        while self.unwinding.last().is_some_and(|&(unwinding, _)| unwinding > frames) {
+            self.unwinding.pop();
+        }
+        self.close_upvalues(stack_top);
+        self.frames.truncate(frames + 1);
+        self.frame = self.frames.pop().expect("caller frame should exist");
+        self.stack_top = stack_top;
+        result
+    }
+
+    /// Compiles `source` into a program that [`VM::run_bytecode`] can run
+    /// later, with the same options and globals as this [`VM`].
+    pub fn compile(&mut self, source: &str) -> Result<Vec<u8>, Vec<ErrorS>> {
+        let _span = tracing::debug_span!("compile", len = source.len()).entered();
+        let program = crate::syntax::parse_with(source, 0, &self.parse_options())?;
+        let options = self.compiler_options();
+        let function = Compiler::compile_program(&program, &mut self.gc, options)?;
+        Ok(serialize::encode(unsafe { &*function }, source))
+    }
+
+    /// Runs a program from [`VM::compile`], which must have been compiled from
+    /// `source` by the same version of loxcraft. `source` is used to report
+    /// errors. The program is checked with [`verify`] before it runs.
+    pub fn run_bytecode(
+        &mut self,
+        source: &str,
+        bytecode: &[u8],
+        stdout: &mut impl Write,
+    ) -> Result<(), Vec<ErrorS>> {
+        let offset = self.source.len();
+        let function = serialize::decode(bytecode, source, &mut self.gc, offset)
+            .map_err(|e| vec![(e.into(), 0..0)])?;
+        verify(unsafe { &*function }).map_err(|e| vec![e])?;
+
+        self.source.reserve(source.len() + 1);
+        self.source.push_str(source);
+        self.source.push('\n');
+
+        self.output_len = 0;
+        self.run_function(function, stdout).map(|_| ()).map_err(|e| vec![e])
+    }
+
+    /// Runs a program from [`VM::compile`] whose source is not at hand, such
+    /// as a .loxc file. Errors are reported at spans in the original source,
+    /// which this [`VM`] does not have. The program is checked with
+    /// [`verify`] before it runs.
+    pub fn run_compiled(
+        &mut self,
+        bytecode: &[u8],
+        stdout: &mut impl Write,
+    ) -> Result<(), Vec<ErrorS>> {
+        let offset = self.source.len();
+        let function = serialize::decode_without_source(bytecode, &mut self.gc, offset)
+            .map_err(|e| vec![(e.into(), 0..0)])?;
+        verify(unsafe { &*function }).map_err(|e| vec![e])?;
+
+        self.output_len = 0;
+        self.run_function(function, stdout).map(|_| ()).map_err(|e| vec![e])
+    }
+
+    fn parse_options(&self) -> ParseOptions {
+        ParseOptions { max_depth: self.options.max_depth }
+    }
+
+    fn compiler_options(&self) -> CompilerOptions {
+        CompilerOptions {
+            strict_globals: self.options.strict.then(|| {
+                self.globals.keys().map(|&name| unsafe { (*name).value }.to_string()).collect()
+            }),
+            per_iteration_bindings: self.options.per_iteration_bindings,
+            class_extensions: self.options.class_extensions,
+            const_globals: self.const_globals.clone(),
+            eliminate_dead_globals: self.options.eliminate_dead_globals,
+            native_globals: if self.options.direct_native_calls {
+                self.native_globals()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Returns the natives whose globals have not been reassigned.
+    fn native_globals(&self) -> Vec<Native> {
+        let mut natives = Vec::new();
+        for (&name, &value) in &self.globals {
+            if self.is_builtin(unsafe { (*name).value }, value)
+                && value.as_object().type_() == ObjectType::Native
+            {
+                natives.push(unsafe { (*value.as_object().native).native });
+            }
+        }
+        natives
+    }
+
+    /// Compiles `source` into a script, like [`VM::run`], but does not run
+    /// it. Instead, each op of the script is run by a call to [`VM::step`],
+    /// which lets the caller inspect the [`VM`] in between, or interleave the
+    /// script with other work.
+    pub fn load(&mut self, source: &str) -> Result<(), Vec<ErrorS>> {
+        let offset = self.source.len();
+
+        self.source.reserve(source.len() + 1);
+        self.source.push_str(source);
+        self.source.push('\n');
 
+        self.output_len = 0;
+        let function = self.compile_phases(source, offset, ScriptKind::Program, &mut None)?;
+        self.start(function);
         Ok(())
     }
 
+    /// Runs the next op of the script loaded by [`VM::load`]. Once this
+    /// returns [`Step::Finished`] or an error, the script is no longer
+    /// loaded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no script is loaded.
+    pub fn step(&mut self, stdout: &mut impl Write) -> Result<Step> {
+        assert!(self.is_running && self.base_frames == 0, "no script is loaded");
+        let trace = cfg!(feature = "vm-trace") || self.options.trace;
+        let record = self.options.history != 0;
+        match self.execute_op(stdout, trace, record) {
+            Ok(None) => Ok(Step::Running),
+            Ok(Some(value)) => {
+                self.is_running = false;
+                Ok(Step::Finished(value))
+            }
+            Err(e) => {
+                self.is_running = false;
+                Err(e)
+            }
+        }
+    }
+
+    /// The functions that were running when the last script stopped,
+    /// innermost first, each with the span of the op it was running. After an
+    /// error, this shows the calls that led to it, until the [`VM`] runs
+    /// again.
+    pub fn traceback(&self) -> Vec<(&str, Span)> {
+        if self.frame.closure.is_null() {
+            return Vec::new();
+        }
+        std::iter::once(&self.frame)
+            .chain(self.frames.iter().rev())
+            .map(|frame| {
+                let function = unsafe { &*(*frame.closure).function };
+                let idx = unsafe { frame.ip.offset_from(function.chunk.ops.as_ptr()) } as usize;
+                let span = idx.checked_sub(1).and_then(|idx| function.chunk.span_at(idx));
+                (unsafe { (*function.name).value }, span.cloned().unwrap_or_default())
+            })
+            .collect()
+    }
+
+    /// The values on the stack, from the bottom up. These include the
+    /// locals of every function that is running, and the temporary values of
+    /// the op being run.
+    pub fn stack(&self) -> &[Value] {
+        &self.stack[..self.stack_len()]
+    }
+
+    /// Swaps the state of the running script with `context`. Pointers into
+    /// the stack stay valid, since the stack itself is not moved.
+    fn swap_context(&mut self, context: &mut Context) {
+        mem::swap(&mut self.globals, &mut context.globals);
+        mem::swap(&mut self.open_upvalues, &mut context.open_upvalues);
+        mem::swap(&mut self.const_globals, &mut context.const_globals);
+        mem::swap(&mut self.frames, &mut context.frames);
+        mem::swap(&mut self.frame, &mut context.frame);
+        mem::swap(&mut self.handlers, &mut context.handlers);
+        mem::swap(&mut self.unwinding, &mut context.unwinding);
+        mem::swap(&mut self.is_running, &mut context.is_running);
+        mem::swap(&mut self.base_frames, &mut context.base_frames);
+        mem::swap(&mut self.native_calls, &mut context.native_calls);
+        mem::swap(&mut self.stack, &mut context.stack);
+        mem::swap(&mut self.stack_top, &mut context.stack_top);
+        mem::swap(&mut self.output_len, &mut context.output_len);
+    }
+
+    /// Runs each phase of [`VM::run`], recording timings if enabled.
+    fn run_phases(
+        &mut self,
+        source: &str,
+        offset: usize,
+        stdout: &mut impl Write,
+        timings: &mut Option<Timings>,
+    ) -> Result<(), Vec<ErrorS>> {
+        let function = self.compile_phases(source, offset, ScriptKind::Program, timings)?;
+        timings::record(timings, "execute", || self.run_function(function, stdout))
+            .map(|_| ())
+            .map_err(|e| vec![e])
+    }
+
+    /// Parses and compiles `source`, recording timings if enabled. Since the
+    /// lexer runs lazily during parsing, it is timed with a separate pass over
+    /// the source. If `eval` is set, the script returns the value of its last
+    /// statement, as for [`VM::eval`].
+    fn compile_phases(
+        &mut self,
+        source: &str,
+        offset: usize,
+        kind: ScriptKind,
+        timings: &mut Option<Timings>,
+    ) -> Result<*mut ObjectFunction, Vec<ErrorS>> {
+        let _span = tracing::debug_span!("compile", len = source.len()).entered();
+        if let Some(timings) = timings {
+            timings.record("lex", || Lexer::new(source).count());
+        }
+        let options = self.parse_options();
+        let program = timings::record(timings, "parse", || {
+            crate::syntax::parse_with(source, offset, &options)
+        })?;
+
+        let options = self.compiler_options();
+        let function = timings::record(timings, "compile", || {
+            Compiler::compile_script(&program, &mut self.gc, options, kind)
+        })?;
+        for (stmt, _) in &program.stmts {
+            if let Stmt::Var(var) = stmt {
+                if var.is_const {
+                    self.const_globals.insert(var.var.name.to_string());
+                }
+            }
+        }
+        if self.options.verify {
+            let function = unsafe { &*function };
+            match kind {
+                ScriptKind::InFrame(_) => verify::verify_callable(function),
+                ScriptKind::Program | ScriptKind::Eval => verify(function),
+            }
+            .map_err(|e| vec![e])?;
+        }
+        Ok(function)
+    }
+
+    /// Runs a script, and returns the value that it returns.
     fn run_function(
         &mut self,
         function: *mut ObjectFunction,
         stdout: &mut impl Write,
-    ) -> Result<()> {
+    ) -> Result<Value> {
+        let _span = tracing::debug_span!("run").entered();
+        self.start(function);
+        let value = self.execute(stdout);
+        self.is_running = false;
+        let value = value?;
+
+        debug_assert_eq!(
+            self.frame.stack, self.stack_top,
+            "VM finished executing but stack is not empty"
+        );
+        Ok(value)
+    }
+
+    /// Sets up the [`VM`] to run a script from its first op.
+    fn start(&mut self, function: *mut ObjectFunction) {
         self.stack_top = self.stack.as_mut_ptr();
+        self.stats = VmStats { peak_heap: GLOBAL.allocated_bytes(), ..VmStats::default() };
+        self.history = History::new(self.options.history);
 
         self.frames.clear();
+        self.handlers.clear();
+        self.unwinding.clear();
+        self.base_frames = 0;
+        self.native_calls = 0;
         self.frame = CallFrame {
             closure: self.gc.alloc(ObjectClosure::new(function, Vec::new())),
             ip: unsafe { (*function).chunk.ops.as_ptr() },
             stack: self.stack_top,
         };
 
+        self.is_running = true;
+    }
+
+    /// Runs ops until the function running at [`VM::base_frames`] returns,
+    /// and returns its value. The function and its arguments are left on the
+    /// stack.
+    fn execute(&mut self, stdout: &mut impl Write) -> Result<Value> {
+        let trace = cfg!(feature = "vm-trace") || self.options.trace;
+        let record = self.options.history != 0;
         loop {
-            if cfg!(feature = "vm-trace") {
-                let function = unsafe { (*self.frame.closure).function };
-                let idx = unsafe { self.frame.ip.offset_from((*function).chunk.ops.as_ptr()) };
-                unsafe { (*function).chunk.debug_op(idx as usize) };
-            }
-
-            match self.read_u8() {
-                op::CONSTANT => self.op_constant(),
-                op::NIL => self.op_nil(),
-                op::TRUE => self.op_true(),
-                op::FALSE => self.op_false(),
-                op::POP => self.op_pop(),
-                op::GET_LOCAL => self.op_get_local(),
-                op::SET_LOCAL => self.op_set_local(),
-                op::GET_GLOBAL => self.op_get_global(),
-                op::DEFINE_GLOBAL => self.op_define_global(),
-                op::SET_GLOBAL => self.op_set_global(),
-                op::GET_UPVALUE => self.op_get_upvalue(),
-                op::SET_UPVALUE => self.op_set_upvalue(),
-                op::GET_PROPERTY => self.op_get_property(),
-                op::SET_PROPERTY => self.op_set_property(),
-                op::GET_SUPER => self.op_get_super(),
-                op::EQUAL => self.op_equal(),
-                op::NOT_EQUAL => self.op_not_equal(),
-                op::GREATER => self.op_greater(),
-                op::GREATER_EQUAL => self.op_greater_equal(),
-                op::LESS => self.op_less(),
-                op::LESS_EQUAL => self.op_less_equal(),
-                op::ADD => self.op_add(),
-                op::SUBTRACT => self.op_subtract(),
-                op::MULTIPLY => self.op_multiply(),
-                op::DIVIDE => self.op_divide(),
-                op::NOT => self.op_not(),
-                op::NEGATE => self.op_negate(),
-                op::PRINT => self.op_print(stdout),
-                op::JUMP => self.op_jump(),
-                op::JUMP_IF_FALSE => self.op_jump_if_false(),
-                op::LOOP => self.op_loop(),
-                op::CALL => self.op_call(),
-                op::INVOKE => self.op_invoke(),
-                op::SUPER_INVOKE => self.op_super_invoke(),
-                op::CLOSURE => self.op_closure(),
-                op::CLOSE_UPVALUE => self.op_close_upvalue(),
-                op::RETURN => {
-                    let value = self.pop();
-                    self.close_upvalues(self.frame.stack);
-
-                    self.stack_top = self.frame.stack;
-                    match self.frames.pop() {
-                        Some(frame) => self.frame = frame,
-                        None => break,
-                    }
-                    self.push(value);
+            if let Some(value) = self.execute_op(stdout, trace, record)? {
+                return Ok(value);
+            }
+        }
+    }
 
-                    Ok(())
+    /// Runs a single op. If it returns from the function running at
+    /// [`VM::base_frames`], this returns the value that it returned. If
+    /// `record` is set, the op is added to [`VM::history`].
+    #[inline(always)]
+    fn execute_op(
+        &mut self,
+        stdout: &mut impl Write,
+        trace: bool,
+        record: bool,
+    ) -> Result<Option<Value>> {
+        if trace {
+            let function = unsafe { (*self.frame.closure).function };
+            let idx = unsafe { self.frame.ip.offset_from((*function).chunk.ops.as_ptr()) };
+            tracing::info!("{}", unsafe { (*function).chunk.disassemble_at(idx as usize) });
+        }
+        let entry = if record { Some(self.begin_history_entry()) } else { None };
+
+        if cfg!(feature = "vm-stats") {
+            self.stats.ops += 1;
+            unsafe { (*(*self.frame.closure).function).ops += 1 };
+        }
+
+        let result = match self.read_u8() {
+            op::CONSTANT => self.op_constant(),
+            op::SMALL_INT => self.op_small_int(),
+            op::NIL => self.op_nil(),
+            op::TRUE => self.op_true(),
+            op::FALSE => self.op_false(),
+            op::POP => self.op_pop(),
+            op::DUP => self.op_dup(),
+            op::SWAP => self.op_swap(),
+            op::ROT => self.op_rot(),
+            op::GET_LOCAL => self.op_get_local(),
+            op::SET_LOCAL => self.op_set_local(),
+            op::GET_GLOBAL => self.op_get_global(),
+            op::DEFINE_GLOBAL => self.op_define_global(),
+            op::SET_GLOBAL => self.op_set_global(),
+            op::GET_UPVALUE => self.op_get_upvalue(),
+            op::SET_UPVALUE => self.op_set_upvalue(),
+            op::GET_PROPERTY => self.op_get_property(),
+            op::SET_PROPERTY => self.op_set_property(),
+            op::GET_SUPER => self.op_get_super(),
+            op::GET_INDEX => self.op_get_index(),
+            op::SET_INDEX => self.op_set_index(),
+            op::EQUAL => self.op_equal(),
+            op::NOT_EQUAL => self.op_not_equal(),
+            op::GREATER => self.op_greater(),
+            op::GREATER_EQUAL => self.op_greater_equal(),
+            op::LESS => self.op_less(),
+            op::LESS_EQUAL => self.op_less_equal(),
+            op::IS => self.op_is(),
+            op::ADD => self.op_add(),
+            op::SUBTRACT => self.op_subtract(),
+            op::MULTIPLY => self.op_multiply(),
+            op::DIVIDE => self.op_divide(),
+            op::MODULO => self.op_modulo(),
+            op::POWER => self.op_power(),
+            op::NOT => self.op_not(),
+            op::NEGATE => self.op_negate(),
+            op::ASSERT => self.op_assert(),
+            op::ASSERT_INFIX => self.op_assert_infix(),
+            op::PRINT => self.op_print(stdout, "\n"),
+            op::PRINT_SPACED => self.op_print(stdout, " "),
+            op::JUMP => self.op_jump(),
+            op::JUMP_IF_FALSE => self.op_jump_if_false(),
+            op::JUMP_IF_FALSE_POP => self.op_jump_if_false_pop(),
+            op::JUMP_IF_TRUE_POP => self.op_jump_if_true_pop(),
+            op::LOOP => self.op_loop(),
+            op::TRY => self.op_try(false),
+            op::TRY_FINALLY => self.op_try(true),
+            op::END_TRY => self.op_end_try(),
+            op::RETHROW => self.op_rethrow(),
+            op::CALL => self.op_call(stdout),
+            op::CALL_NATIVE => self.op_call_native(stdout),
+            op::INVOKE => self.op_invoke(stdout),
+            op::SUPER_INVOKE => self.op_super_invoke(),
+            op::CLOSURE => self.op_closure(),
+            op::CLOSE_UPVALUE => self.op_close_upvalue(),
+            op @ (op::RETURN | op::RETURN_NIL) => {
+                let value = if op == op::RETURN { self.pop() } else { Value::NIL };
+                self.close_upvalues(self.frame.stack);
+                // Discard the handlers of any `try` blocks being returned
+                // from, and any errors that a `finally` block being
+                // returned from would have raised.
+                while self.handlers.last().is_some_and(|h| h.frames == self.frames.len()) {
+                    self.handlers.pop();
                 }
-                op::CLASS => self.op_class(),
-                op::INHERIT => self.op_inherit(),
-                op::METHOD => self.op_method(),
-                _ => util::unreachable(),
-            }?;
-
-            if cfg!(feature = "vm-trace") {
-                eprint!("     ");
-                let mut stack_ptr = self.frame.stack;
-                while stack_ptr < self.stack_top {
-                    eprint!("[ {} ]", unsafe { *stack_ptr });
-                    stack_ptr = unsafe { stack_ptr.add(1) };
+                while self.unwinding.last().is_some_and(|&(frames, _)| frames == self.frames.len())
+                {
+                    self.unwinding.pop();
+                }
+
+                self.stack_top = self.frame.stack;
+                if self.frames.len() == self.base_frames {
+                    if let Some((entry, ..)) = entry {
+                        self.history.push(entry);
+                    }
+                    return Ok(Some(value));
                 }
-                eprintln!();
+                self.frame = self.frames.pop().expect("caller frame should exist");
+                self.push(value);
+
+                Ok(())
             }
+            op::CLASS => self.op_class(),
+            op::INHERIT => self.op_inherit(),
+            op::METHOD => self.op_method(),
+            op::EXTEND => self.op_extend(),
+            op::LIST => self.op_list(),
+            op::MAP => self.op_map(),
+            op::CONCAT => self.op_concat(),
+            _ => util::unreachable(),
+        };
+        if let Some((entry, pushes, frames)) = entry {
+            self.end_history_entry(entry, pushes, frames, &result);
+        }
+        if let Err(error) = result {
+            self.catch(error)?;
         }
 
-        debug_assert_eq!(
-            self.frame.stack, self.stack_top,
-            "VM finished executing but stack is not empty"
+        debug_assert!(
+            self.frame_len() <= unsafe { (*(*self.frame.closure).function).max_stack },
+            "stack grew past the maximum computed for its frame"
         );
-        Ok(())
+
+        if trace {
+            let mut stack = String::from("     ");
+            let mut stack_ptr = self.frame.stack;
+            while stack_ptr < self.stack_top {
+                stack.push_str(&format!("[ {} ]", unsafe { *stack_ptr }));
+                stack_ptr = unsafe { stack_ptr.add(1) };
+            }
+            tracing::info!("{stack}");
+        }
+        Ok(None)
+    }
+
+    /// Records the op about to run, and what it takes off the stack. This
+    /// returns the entry, the number of values the op pushes, and the length of
+    /// `frames`, for [`VM::end_history_entry`].
+    #[cold]
+    fn begin_history_entry(&self) -> (HistoryEntry, usize, usize) {
+        let function = unsafe { &*(*self.frame.closure).function };
+        let chunk = &function.chunk;
+        let idx = self.ip_offset();
+        let op = chunk.ops[idx];
+        let operands = &chunk.ops[idx + 1..idx + chunk.op_len(idx)];
+        let (pops, pushes) = op::stack_effect(op, operands).unwrap_or_default();
+
+        let stack = self.stack();
+        let popped = stack[stack.len().saturating_sub(pops)..]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let global = match op {
+            op::DEFINE_GLOBAL | op::SET_GLOBAL => Some((
+                chunk.constants[operands[0] as usize].to_string(),
+                popped.last().cloned().unwrap_or_default(),
+            )),
+            _ => None,
+        };
+        let entry = HistoryEntry {
+            function: unsafe { (*function.name).value.to_string() },
+            op: chunk.disassemble_at(idx),
+            span: chunk.span_at(idx).cloned().unwrap_or_default(),
+            popped,
+            global,
+            ..HistoryEntry::default()
+        };
+        (entry, pushes, self.frames.len())
+    }
+
+    /// Records what the op begun by [`VM::begin_history_entry`] left on the
+    /// stack, or the error it raised, and adds it to [`VM::history`].
+    #[cold]
+    fn end_history_entry(
+        &mut self,
+        mut entry: HistoryEntry,
+        pushes: usize,
+        frames: usize,
+        result: &Result<()>,
+    ) {
+        match result {
+            Ok(()) if self.frames.len() == frames => {
+                let stack = self.stack();
+                entry.pushed = stack[stack.len().saturating_sub(pushes)..]
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect();
+            }
+            Ok(()) => {}
+            Err((error, _)) => {
+                entry.global = None;
+                entry.error = Some(error.to_string());
+            }
+        }
+        self.history.push(entry);
     }
 
     fn op_constant(&mut self) -> Result<()> {
@@ -179,6 +1013,12 @@ impl VM {
         Ok(())
     }
 
+    fn op_small_int(&mut self) -> Result<()> {
+        let value = self.read_u8();
+        self.push(Value::from(f64::from(value)));
+        Ok(())
+    }
+
     fn op_nil(&mut self) -> Result<()> {
         self.push(Value::NIL);
         Ok(())
@@ -199,8 +1039,26 @@ impl VM {
         Ok(())
     }
 
+    fn op_dup(&mut self) -> Result<()> {
+        let value = unsafe { *self.peek(0) };
+        self.push(value);
+        Ok(())
+    }
+
+    fn op_swap(&mut self) -> Result<()> {
+        unsafe { ptr::swap(self.peek(0), self.peek(1)) };
+        Ok(())
+    }
+
+    fn op_rot(&mut self) -> Result<()> {
+        let values = unsafe { slice::from_raw_parts_mut(self.peek(2), 3) };
+        values.rotate_left(1);
+        Ok(())
+    }
+
     fn op_get_local(&mut self) -> Result<()> {
         let stack_idx = self.read_u8() as usize;
+        self.check_local(stack_idx);
         let local = unsafe { *self.frame.stack.add(stack_idx) };
         self.push(local);
         Ok(())
@@ -208,6 +1066,7 @@ impl VM {
 
     fn op_set_local(&mut self) -> Result<()> {
         let stack_idx = self.read_u8() as usize;
+        self.check_local(stack_idx);
         let local = unsafe { self.frame.stack.add(stack_idx) };
         let value = self.peek(0);
         unsafe { *local = *value };
@@ -215,7 +1074,7 @@ impl VM {
     }
 
     fn op_get_global(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         match self.globals.get(&name) {
             Some(&value) => {
                 self.push(value);
@@ -226,14 +1085,14 @@ impl VM {
     }
 
     fn op_define_global(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let value = self.pop();
         self.globals.insert(name, value);
         Ok(())
     }
 
     fn op_set_global(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let value = unsafe { *self.peek(0) };
         match self.globals.entry(name) {
             Entry::Occupied(mut entry) => {
@@ -248,6 +1107,7 @@ impl VM {
 
     fn op_get_upvalue(&mut self) -> Result<()> {
         let upvalue_idx = self.read_u8() as usize;
+        self.check_upvalue(upvalue_idx);
         let object = *unsafe { (*self.frame.closure).upvalues.get_unchecked(upvalue_idx) };
         let value = unsafe { *(*object).location };
         self.push(value);
@@ -256,6 +1116,7 @@ impl VM {
 
     fn op_set_upvalue(&mut self) -> Result<()> {
         let upvalue_idx = self.read_u8() as usize;
+        self.check_upvalue(upvalue_idx);
         let object = *unsafe { (*self.frame.closure).upvalues.get_unchecked(upvalue_idx) };
         let value = unsafe { (*object).location };
         unsafe { *value = *self.peek(0) };
@@ -263,13 +1124,28 @@ impl VM {
     }
 
     fn op_get_property(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let instance = {
             let value = unsafe { *self.peek(0) };
             let object = value.as_object();
 
             if value.is_object() && object.type_() == ObjectType::Instance {
                 unsafe { object.instance }
+            } else if value.is_object() && object.type_() == ObjectType::Class {
+                let class = unsafe { object.class };
+                let property = match unsafe { (*name).value } {
+                    "name" => unsafe { (*class).name }.into(),
+                    "superclass" => unsafe { (*class).super_ }.map_or(Value::NIL, Value::from),
+                    name => {
+                        return self.err(AttributeError::NoSuchAttribute {
+                            type_: value.type_().to_string(),
+                            name: name.to_string(),
+                        });
+                    }
+                };
+                self.pop();
+                self.push(property);
+                return Ok(());
             } else {
                 return self.err(AttributeError::NoSuchAttribute {
                     type_: value.type_().to_string(),
@@ -278,17 +1154,23 @@ impl VM {
             }
         };
 
-        match unsafe { (*instance).fields.get(&name) } {
-            Some(&field) => {
+        match unsafe { (*instance).get(name) } {
+            Some(field) => {
                 self.pop();
                 self.push(field);
             }
             None => match unsafe { (*(*instance).class).methods.get(&name) } {
                 Some(&method) => {
-                    let bound_method = self.alloc(ObjectBoundMethod::new(instance, method));
+                    let bound_method = self.bind_method(instance, method);
                     self.pop();
                     self.push(bound_method.into());
                 }
+                // "class" is a keyword, so no field or method can be named
+                // after it.
+                None if unsafe { (*name).value } == "class" => {
+                    self.pop();
+                    self.push(unsafe { (*instance).class }.into());
+                }
                 None => {
                     return self.err(AttributeError::NoSuchAttribute {
                         type_: unsafe { (*(*(*instance).class).name).value.to_string() },
@@ -302,7 +1184,7 @@ impl VM {
     }
 
     fn op_set_property(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let instance = {
             let value = self.pop();
             let object = value.as_object();
@@ -317,17 +1199,132 @@ impl VM {
             }
         };
         let value = unsafe { *self.peek(0) };
-        unsafe { (*instance).fields.insert(name, value) };
+        unsafe { (*instance).set(name, value) };
+        Ok(())
+    }
+
+    fn op_get_index(&mut self) -> Result<()> {
+        let index = unsafe { *self.peek(0) };
+        let value = unsafe { *self.peek(1) };
+        let element = match value.is_object().then(|| value.as_object().type_()) {
+            Some(ObjectType::List) => {
+                let elements = unsafe { &(*value.as_object().list).elements };
+                let idx = self.list_index(ObjectType::List, index, elements.len())?;
+                elements[idx]
+            }
+            Some(ObjectType::Map) => match unsafe { (*value.as_object().map).get(index) } {
+                Some(element) => element,
+                None => return self.err(IndexError::KeyNotFound { key: index.repr() }),
+            },
+            Some(ObjectType::String) => {
+                let string = unsafe { (*value.as_object().string).value };
+                let idx = self.list_index(ObjectType::String, index, string.chars().count())?;
+                let char = string.chars().nth(idx).expect("index should be in range");
+                self.alloc(char.to_string()).into()
+            }
+            _ => return self.err(TypeError::NotIndexable { type_: value.type_().to_string() }),
+        };
+        self.stack_top = self.peek(1);
+        self.push(element);
+        Ok(())
+    }
+
+    fn op_set_index(&mut self) -> Result<()> {
+        let element = unsafe { *self.peek(0) };
+        let index = unsafe { *self.peek(1) };
+        let value = unsafe { *self.peek(2) };
+        match value.is_object().then(|| value.as_object().type_()) {
+            Some(ObjectType::List) => {
+                let elements = unsafe { &mut (*value.as_object().list).elements };
+                let idx = self.list_index(ObjectType::List, index, elements.len())?;
+                elements[idx] = element;
+            }
+            Some(ObjectType::Map) => unsafe { (*value.as_object().map).insert(index, element) },
+            _ => {
+                return self
+                    .err(TypeError::NotIndexAssignable { type_: value.type_().to_string() });
+            }
+        }
+        self.stack_top = self.peek(2);
+        self.push(element);
+        Ok(())
+    }
+
+    /// Checks that `index` is a valid index into an object of type `type_`
+    /// with `len` elements.
+    fn list_index(&mut self, type_: ObjectType, index: Value, len: usize) -> Result<usize> {
+        if !index.is_number() || index.as_number().fract() != 0.0 {
+            return self.err(IndexError::NotAnInteger {
+                type_: type_.to_string(),
+                index: index.to_string(),
+            });
+        }
+        let number = index.as_number();
+        if number < 0.0 || number >= len as f64 {
+            return self.err(IndexError::OutOfRange {
+                type_: type_.to_string(),
+                index: index.to_string(),
+                len,
+            });
+        }
+        Ok(number as usize)
+    }
+
+    fn op_concat(&mut self) -> Result<()> {
+        let b = unsafe { *self.peek(0) };
+        let a = unsafe { *self.peek(1) };
+        // The operands are left on the stack until the result is allocated,
+        // so that they remain reachable if it triggers a garbage collection.
+        let string = self.alloc(format!("{a}{b}"));
+        self.stack_top = self.peek(1);
+        self.push(string.into());
+        Ok(())
+    }
+
+    fn op_list(&mut self) -> Result<()> {
+        let count = self.read_u8() as usize;
+        // The elements are left on the stack until the list is allocated, so
+        // that they remain reachable if it triggers a garbage collection.
+        let start = unsafe { self.stack_top.sub(count) };
+        let elements = unsafe { slice::from_raw_parts(start, count) }.to_vec();
+        let list = self.alloc(ObjectList::new(elements));
+        self.stack_top = start;
+        self.push(list.into());
+        Ok(())
+    }
+
+    fn op_map(&mut self) -> Result<()> {
+        let count = self.read_u8() as usize;
+        let start = unsafe { self.stack_top.sub(2 * count) };
+        let mut map = ObjectMap::new();
+        for entry in unsafe { slice::from_raw_parts(start, 2 * count) }.chunks_exact(2) {
+            map.insert(entry[0], entry[1]);
+        }
+        let map = self.alloc(map);
+        self.stack_top = start;
+        self.push(map.into());
         Ok(())
     }
 
     fn op_get_super(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let super_ = unsafe { self.pop().as_object().class };
         match unsafe { (*super_).methods.get(&name) } {
             Some(&method) => {
-                let instance = unsafe { (*self.peek(0)).as_object().instance };
-                let bound_method = self.alloc(ObjectBoundMethod::new(instance, method));
+                let instance = {
+                    let value = unsafe { *self.peek(0) };
+                    let object = value.as_object();
+
+                    if value.is_object() && object.type_() == ObjectType::Instance {
+                        unsafe { object.instance }
+                    } else {
+                        return self.err(AttributeError::NoSuchAttribute {
+                            type_: value.type_().to_string(),
+                            name: unsafe { (*name).value.to_string() },
+                        });
+                    }
+                };
+                let bound_method = self.bind_method(instance, method);
                 self.pop();
                 self.push(bound_method.into());
             }
@@ -342,15 +1339,36 @@ impl VM {
     }
 
     fn op_equal(&mut self) -> Result<()> {
+        self.check_equality_types("==")?;
         self.binary_op(|a, b| Value::from(a == b));
         Ok(())
     }
 
     fn op_not_equal(&mut self) -> Result<()> {
+        self.check_equality_types("!=")?;
         self.binary_op(|a, b| Value::from(a != b));
         Ok(())
     }
 
+    /// With [`VmOptions::strict_types`], rejects comparisons between values of
+    /// different types, which would otherwise always be unequal. Comparing
+    /// with `nil` is always allowed.
+    fn check_equality_types(&mut self, op: &str) -> Result<()> {
+        if !self.options.strict_types {
+            return Ok(());
+        }
+        let a = unsafe { *self.peek(1) };
+        let b = unsafe { *self.peek(0) };
+        if !a.is_nil() && !b.is_nil() && a.type_() != b.type_() {
+            return self.err(TypeError::UnsupportedOperandInfix {
+                op: op.to_string(),
+                lt_type: a.type_().to_string(),
+                rt_type: b.type_().to_string(),
+            });
+        }
+        Ok(())
+    }
+
     fn op_greater(&mut self) -> Result<()> {
         self.binary_op_number(|a, b| Value::from(a > b), ">")
     }
@@ -367,9 +1385,37 @@ impl VM {
         self.binary_op_number(|a, b| Value::from(a <= b), "<=")
     }
 
-    fn op_add(&mut self) -> Result<()> {
-        let b = self.pop();
-        let a = self.pop();
+    fn op_is(&mut self) -> Result<()> {
+        let class = self.pop();
+        let value = self.pop();
+
+        if !class.is_object() || class.as_object().type_() != ObjectType::Class {
+            return self.err(TypeError::UnsupportedOperandInfix {
+                op: "is".to_string(),
+                lt_type: value.type_().to_string(),
+                rt_type: class.type_().to_string(),
+            });
+        }
+        let class = unsafe { class.as_object().class };
+
+        let mut is_instance = false;
+        if value.is_object() && value.as_object().type_() == ObjectType::Instance {
+            let mut current = Some(unsafe { (*value.as_object().instance).class });
+            while let Some(current_class) = current {
+                if current_class == class {
+                    is_instance = true;
+                    break;
+                }
+                current = unsafe { (*current_class).super_ };
+            }
+        }
+        self.push(is_instance.into());
+        Ok(())
+    }
+
+    fn op_add(&mut self) -> Result<()> {
+        let b = self.pop();
+        let a = self.pop();
 
         if a.is_number() && b.is_number() {
             self.push((a.as_number() + b.as_number()).into());
@@ -407,6 +1453,14 @@ impl VM {
         self.binary_op_number(|a, b| Value::from(a / b), "/")
     }
 
+    fn op_modulo(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| Value::from(a % b), "%")
+    }
+
+    fn op_power(&mut self) -> Result<()> {
+        self.binary_op_number(|a, b| Value::from(a.powf(b)), "**")
+    }
+
     fn op_not(&mut self) -> Result<()> {
         let value = self.pop();
         self.push(!value);
@@ -426,22 +1480,111 @@ impl VM {
         }
     }
 
-    fn op_print(&mut self, stdout: &mut impl Write) -> Result<()> {
+    fn op_assert(&mut self) -> Result<()> {
+        if self.pop().to_bool() {
+            return Ok(());
+        }
+        self.err(AssertionError::Failed { expr: self.source_of_op() })
+    }
+
+    fn op_assert_infix(&mut self) -> Result<()> {
+        let op = self.read_u8();
+        let lt = unsafe { *self.peek(1) };
+        let rt = unsafe { *self.peek(0) };
+        match op {
+            op::EQUAL => self.op_equal(),
+            op::NOT_EQUAL => self.op_not_equal(),
+            op::GREATER => self.op_greater(),
+            op::GREATER_EQUAL => self.op_greater_equal(),
+            op::LESS => self.op_less(),
+            op::LESS_EQUAL => self.op_less_equal(),
+            op::IS => self.op_is(),
+            _ => util::unreachable(),
+        }?;
+        if self.pop().to_bool() {
+            return Ok(());
+        }
+        self.err(AssertionError::FailedInfix {
+            expr: self.source_of_op(),
+            lt: native::inspect(lt),
+            rt: native::inspect(rt),
+        })
+    }
+
+    fn op_print(&mut self, stdout: &mut impl Write, terminator: &str) -> Result<()> {
         let value = self.pop();
-        writeln!(stdout, "{value}")
-            .or_else(|_| self.err(IoError::WriteError { file: "stdout".to_string() }))
+        match self.options.precision {
+            Some(precision) if value.is_number() => {
+                let number = crate::number::display(value.as_number(), Some(precision));
+                self.write_output(stdout, format_args!("{number}{terminator}"))
+            }
+            _ => self.write_output(stdout, format_args!("{value}{terminator}")),
+        }
     }
 
     fn op_jump(&mut self) -> Result<()> {
         let offset = self.read_u16() as usize;
+        self.check_jump(offset as isize);
         self.frame.ip = unsafe { self.frame.ip.add(offset) };
         Ok(())
     }
 
+    fn op_try(&mut self, finally: bool) -> Result<()> {
+        let offset = self.read_u16() as usize;
+        self.check_jump(offset as isize);
+        self.handlers.push(Handler {
+            frames: self.frames.len(),
+            ip: unsafe { self.frame.ip.add(offset) },
+            stack: self.stack_top,
+            unwinding: self.unwinding.len(),
+            finally,
+        });
+        Ok(())
+    }
+
+    fn op_end_try(&mut self) -> Result<()> {
+        self.handlers.pop();
+        Ok(())
+    }
+
+    fn op_rethrow(&mut self) -> Result<()> {
+        self.pop();
+        match self.unwinding.pop() {
+            Some((_, error)) => Err(error),
+            None => Ok(()),
+        }
+    }
+
     fn op_jump_if_false(&mut self) -> Result<()> {
         let offset = self.read_u16() as usize;
-        let value = self.peek(0);
-        if !(unsafe { *value }.to_bool()) {
+        self.check_jump(offset as isize);
+        let value = unsafe { *self.peek(0) };
+        if self.options.strict_types && !value.is_bool() {
+            return self.err(TypeError::ConditionInvalidType { type_: value.type_().to_string() });
+        }
+        if !value.to_bool() {
+            self.frame.ip = unsafe { self.frame.ip.add(offset) };
+        }
+        Ok(())
+    }
+
+    fn op_jump_if_false_pop(&mut self) -> Result<()> {
+        let offset = self.read_u16() as usize;
+        self.check_jump(offset as isize);
+        let value = self.pop();
+        if self.options.strict_types && !value.is_bool() {
+            return self.err(TypeError::ConditionInvalidType { type_: value.type_().to_string() });
+        }
+        if !value.to_bool() {
+            self.frame.ip = unsafe { self.frame.ip.add(offset) };
+        }
+        Ok(())
+    }
+
+    fn op_jump_if_true_pop(&mut self) -> Result<()> {
+        let offset = self.read_u16() as usize;
+        self.check_jump(offset as isize);
+        if self.pop().to_bool() {
             self.frame.ip = unsafe { self.frame.ip.add(offset) };
         }
         Ok(())
@@ -449,23 +1592,51 @@ impl VM {
 
     fn op_loop(&mut self) -> Result<()> {
         let offset = self.read_u16() as usize;
+        self.check_jump(-(offset as isize));
         self.frame.ip = unsafe { self.frame.ip.sub(offset) };
         Ok(())
     }
 
-    fn op_call(&mut self) -> Result<()> {
+    fn op_call(&mut self, stdout: &mut impl Write) -> Result<()> {
         let arg_count = self.read_u8() as usize;
         let callee = unsafe { *self.peek(arg_count) };
-        self.call_value(callee, arg_count)
+        self.call_value(callee, arg_count, stdout)
     }
 
-    fn op_invoke(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+    fn op_call_native(&mut self, stdout: &mut impl Write) -> Result<()> {
+        let native = Native::ALL[self.read_u8() as usize];
         let arg_count = self.read_u8() as usize;
-        let instance = unsafe { (*self.peek(arg_count)).as_object().instance };
+        let value = self.run_native(native, arg_count, stdout)?;
+        self.stack_top = unsafe { self.stack_top.sub(arg_count) };
+        self.push(value);
+        Ok(())
+    }
+
+    fn op_invoke(&mut self, stdout: &mut impl Write) -> Result<()> {
+        let name = self.read_string();
+        let arg_count = self.read_u8() as usize;
+        let instance = {
+            let value = unsafe { *self.peek(arg_count) };
+            let object = value.as_object();
+
+            if value.is_object() && object.type_() == ObjectType::Instance {
+                unsafe { object.instance }
+            } else if value.is_object() && object.type_() == ObjectType::Foreign {
+                return self.invoke_foreign(
+                    unsafe { object.foreign },
+                    unsafe { (*name).value },
+                    arg_count,
+                );
+            } else {
+                return self.err(AttributeError::NoSuchAttribute {
+                    type_: value.type_().to_string(),
+                    name: unsafe { (*name).value.to_string() },
+                });
+            }
+        };
 
-        match unsafe { (*instance).fields.get(&name) } {
-            Some(&value) => self.call_value(value, arg_count),
+        match unsafe { (*instance).get(name) } {
+            Some(value) => self.call_value(value, arg_count, stdout),
             None => match unsafe { (*(*instance).class).methods.get(&name) } {
                 Some(&method) => self.call_closure(method, arg_count),
                 None => self.err(AttributeError::NoSuchAttribute {
@@ -477,7 +1648,7 @@ impl VM {
     }
 
     fn op_super_invoke(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let arg_count = self.read_u8() as usize;
         let super_ = unsafe { self.pop().as_object().class };
 
@@ -491,7 +1662,12 @@ impl VM {
     }
 
     fn op_closure(&mut self) -> Result<()> {
-        let function = unsafe { self.read_value().as_object().function };
+        let function = self.read_value();
+        check_bytecode!(
+            function.is_object() && function.as_object().type_() == ObjectType::Function,
+            "closure constant is not a function: {function}"
+        );
+        let function = unsafe { function.as_object().function };
 
         let upvalue_count = unsafe { (*function).upvalue_count } as usize;
         let mut upvalues = Vec::with_capacity(upvalue_count);
@@ -504,6 +1680,7 @@ impl VM {
                 let location = unsafe { self.frame.stack.add(upvalue_idx) };
                 self.capture_upvalue(location)
             } else {
+                self.check_upvalue(upvalue_idx);
                 unsafe { *(*self.frame.closure).upvalues.get_unchecked(upvalue_idx) }
             };
             upvalues.push(upvalue);
@@ -522,7 +1699,7 @@ impl VM {
     }
 
     fn op_class(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let class = self.alloc(ObjectClass::new(name)).into();
         self.push(class);
         Ok(())
@@ -542,33 +1719,79 @@ impl VM {
             }
         };
 
+        unsafe { (*class).super_ = Some(super_) };
+        unsafe { (*super_).subclasses.push(class) };
         unsafe { (*class).methods.clone_from(&(*super_).methods) };
         Ok(())
     }
 
     fn op_method(&mut self) -> Result<()> {
-        let name = unsafe { self.read_value().as_object().string };
+        let name = self.read_string();
         let method = unsafe { self.pop().as_object().closure };
         let class = unsafe { (*self.peek(0)).as_object().class };
         unsafe { (*class).methods.insert(name, method) };
         Ok(())
     }
 
+    fn op_extend(&mut self) -> Result<()> {
+        let name = self.read_string();
+        let method = unsafe { self.pop().as_object().closure };
+        let value = unsafe { *self.peek(0) };
+        if !(value.is_object() && value.as_object().type_() == ObjectType::Class) {
+            return self.err(TypeError::ExtendInvalidType { type_: value.type_().to_string() });
+        }
+        let class = unsafe { value.as_object().class };
+
+        // Subclasses hold copies of the methods that they inherit, so those
+        // copies are replaced as well. Bound methods are cached by closure,
+        // so those of the old method are simply no longer found.
+        let old = unsafe { (*class).methods.insert(name, method) };
+        let mut subclasses = unsafe { (*class).subclasses.clone() };
+        while let Some(subclass) = subclasses.pop() {
+            let methods = unsafe { &mut (*subclass).methods };
+            // A subclass that overrides the method keeps its own, and so do
+            // the classes that inherit from it.
+            if methods.get(&name).copied() == old {
+                methods.insert(name, method);
+                subclasses.extend_from_slice(unsafe { &(*subclass).subclasses });
+            }
+        }
+        Ok(())
+    }
+
     fn alloc<T>(&mut self, object: impl GcAlloc<T>) -> T {
-        if !cfg!(feature = "gc-off")
-            && (cfg!(feature = "gc-stress") || GLOBAL.allocated_bytes() > self.next_gc)
-        {
+        let allocated = GLOBAL.allocated_bytes();
+        self.stats.peak_heap = self.stats.peak_heap.max(allocated);
+        if cfg!(feature = "vm-stats") && !self.frame.closure.is_null() {
+            unsafe { (*(*self.frame.closure).function).allocs += 1 };
+        }
+        if !cfg!(feature = "gc-off") && (cfg!(feature = "gc-stress") || allocated > self.next_gc) {
             self.gc();
         }
         self.gc.alloc(object)
     }
 
     fn gc(&mut self) {
-        if cfg!(feature = "gc-trace") {
-            eprintln!("-- gc begin");
-        }
+        let _span = tracing::debug_span!("gc", count = self.stats.gc_count).entered();
+        let before = GLOBAL.allocated_bytes();
+        self.stats.gc_count += 1;
 
         self.gc.mark(self.init_string);
+        self.gc.mark(self.message_string);
+        self.gc.mark(self.empty_string);
+        for &class in &self.error_classes {
+            self.gc.mark(class);
+        }
+        for &module in &self.modules {
+            self.gc.mark(module);
+        }
+        for context in &self.contexts {
+            context.mark(&mut self.gc);
+        }
+        for saved in &self.snapshots {
+            saved.mark(&mut self.gc);
+        }
+        self.gc.mark_roots();
 
         let mut stack_ptr = self.stack.as_ptr();
         while stack_ptr < self.stack_top {
@@ -581,7 +1804,11 @@ impl VM {
             self.gc.mark(value);
         }
 
-        self.gc.mark(self.frame.closure);
+        // There is no current closure until the VM first runs code, but the
+        // embedder can allocate before then, e.g. with `VM::foreign`.
+        if !self.frame.closure.is_null() {
+            self.gc.mark(self.frame.closure);
+        }
         for frame in &self.frames {
             self.gc.mark(frame.closure);
         }
@@ -593,14 +1820,17 @@ impl VM {
         self.gc.trace();
         self.gc.sweep();
 
-        self.next_gc = GLOBAL.allocated_bytes() * GC_HEAP_GROW_FACTOR;
-
-        if cfg!(feature = "gc-trace") {
-            eprintln!("-- gc end");
-        }
+        let after = GLOBAL.allocated_bytes();
+        self.next_gc = after * GC_HEAP_GROW_FACTOR;
+        tracing::debug!(before, after, next_gc = self.next_gc, "collected garbage");
     }
 
-    fn call_value(&mut self, value: Value, arg_count: usize) -> Result<()> {
+    fn call_value(
+        &mut self,
+        value: Value,
+        arg_count: usize,
+        stdout: &mut impl Write,
+    ) -> Result<()> {
         if value.is_object() {
             let object = value.as_object();
             match object.type_() {
@@ -608,8 +1838,19 @@ impl VM {
                     self.call_bound_method(unsafe { object.bound_method }, arg_count)
                 }
                 ObjectType::Class => self.call_class(unsafe { object.class }, arg_count),
-                ObjectType::Closure => self.call_closure(unsafe { object.closure }, arg_count),
-                ObjectType::Native => self.call_native(unsafe { object.native }, arg_count),
+                ObjectType::Closure => {
+                    // A method taken off its instance with `unbind` has no
+                    // `this` until it is bound again.
+                    let function = unsafe { (*object.closure).function };
+                    if unsafe { (*function).is_method() } {
+                        let name = unsafe { (*(*function).name).value }.to_string();
+                        return self.err(TypeError::UnboundMethodCall { name });
+                    }
+                    self.call_closure(unsafe { object.closure }, arg_count)
+                }
+                ObjectType::Native => {
+                    self.call_native(unsafe { (*object.native).native }, arg_count, stdout)
+                }
                 _ => self.err(TypeError::NotCallable { type_: value.type_().to_string() }),
             }
         } else {
@@ -642,46 +1883,554 @@ impl VM {
     }
 
     fn call_closure(&mut self, closure: *mut ObjectClosure, arg_count: usize) -> Result<()> {
-        if self.frames.len() >= self.frames.capacity() {
+        if self.frames.len() + self.native_calls >= self.options.max_call_depth {
             return self.err(OverflowError::StackOverflow);
         }
 
         let function = unsafe { (*closure).function };
+        if cfg!(feature = "vm-stats") {
+            unsafe { (*function).calls += 1 };
+        }
         let arity = unsafe { (*function).arity } as usize;
+        let mut ip = unsafe { (*function).chunk.ops.as_ptr() };
+        let stack = self.peek(arg_count);
         if arg_count != arity {
+            let min_arity = unsafe { (*function).min_arity() } as usize;
+            let name = || unsafe { (*(*function).name).value }.to_string();
+            if min_arity == arity {
+                return self.err(TypeError::ArityMismatch {
+                    name: name(),
+                    exp_args: arity,
+                    got_args: arg_count,
+                });
+            }
+            if !(min_arity..arity).contains(&arg_count) {
+                return self.err(TypeError::ArityRangeMismatch {
+                    name: name(),
+                    min_args: min_arity,
+                    max_args: arity,
+                    got_args: arg_count,
+                });
+            }
+            // The parameters that were left out start out as nil, and the
+            // function computes their defaults before its body runs.
+            for _ in arg_count..arity {
+                self.push(Value::NIL);
+            }
+            let entry = unsafe { (&(*function).entries)[arg_count - min_arity] };
+            ip = unsafe { ip.add(entry) };
+        }
+
+        let frame = CallFrame { closure, ip, stack };
+        self.frames.push(mem::replace(&mut self.frame, frame));
+
+        Ok(())
+    }
+
+    fn call_native(
+        &mut self,
+        native: Native,
+        arg_count: usize,
+        stdout: &mut impl Write,
+    ) -> Result<()> {
+        let value = self.run_native(native, arg_count, stdout)?;
+        self.stack_top = self.peek(arg_count);
+        self.push(value);
+        Ok(())
+    }
+
+    /// Runs `native` with the arguments on top of the stack, and returns its
+    /// result. The arguments are left on the stack for the caller to pop.
+    fn run_native(
+        &mut self,
+        native: Native,
+        arg_count: usize,
+        stdout: &mut impl Write,
+    ) -> Result<Value> {
+        if native.is_variadic() {
+            if arg_count < native.arity() {
+                return self.err(TypeError::TooFewArgs {
+                    name: native.to_string(),
+                    exp_args: native.arity(),
+                    got_args: arg_count,
+                });
+            }
+        } else if arg_count != native.arity() {
             return self.err(TypeError::ArityMismatch {
-                name: unsafe { (*(*function).name).value }.to_string(),
-                exp_args: arity,
+                name: native.to_string(),
+                exp_args: native.arity(),
                 got_args: arg_count,
             });
         }
 
-        let frame = CallFrame {
-            closure,
-            ip: unsafe { (*function).chunk.ops.as_ptr() },
-            stack: self.peek(arg_count),
+        // The arguments are left on the stack until the native returns, so
+        // that they remain reachable if it triggers a garbage collection.
+        let value = match native {
+            Native::Abs => self.number_arg(native, 0, 1)?.abs().into(),
+            Native::Arity => {
+                let value = unsafe { *self.peek(0) };
+                match native::callable_info(value) {
+                    Some(info) => (info.arity() as f64).into(),
+                    None => {
+                        return self.err(TypeError::ArgTypeMismatch {
+                            name: native.to_string(),
+                            pos: 1,
+                            exp_type: ObjectType::Function.to_string(),
+                            got_type: value.type_().to_string(),
+                        });
+                    }
+                }
+            }
+            Native::Bind => self.native_bind()?,
+            Native::Char => self.native_char()?,
+            Native::Chars => {
+                let string = self.string_arg(native, 0, 1)?;
+                let chars = string.chars().map(String::from).collect();
+                self.alloc_strings(chars, 0).into()
+            }
+            Native::Clock => util::now().into(),
+            Native::Filter => self.native_filter(stdout)?,
+            Native::Floor => self.number_arg(native, 0, 1)?.floor().into(),
+            Native::Hash => (ObjectMap::hash(unsafe { *self.peek(0) }) as f64).into(),
+            Native::Inspect => {
+                let value = unsafe { *self.peek(0) };
+                self.alloc(native::inspect(value)).into()
+            }
+            Native::Len => {
+                let value = unsafe { *self.peek(0) };
+                match value.is_object().then(|| value.as_object().type_()) {
+                    Some(ObjectType::List) => {
+                        (unsafe { (*value.as_object().list).elements.len() } as f64).into()
+                    }
+                    Some(ObjectType::Map) => {
+                        (unsafe { (*value.as_object().map).len() } as f64).into()
+                    }
+                    _ => {
+                        let string = self.string_arg(Native::Len, 0, 1)?;
+                        (string.chars().count() as f64).into()
+                    }
+                }
+            }
+            Native::Lower => {
+                let string = self.string_arg(native, 0, 1)?;
+                self.alloc(string.to_lowercase()).into()
+            }
+            Native::Map => self.native_map(stdout)?,
+            Native::Pow => {
+                let base = self.number_arg(native, 1, 1)?;
+                let exponent = self.number_arg(native, 0, 2)?;
+                base.powf(exponent).into()
+            }
+            Native::Printf => {
+                self.native_printf(arg_count, stdout)?;
+                Value::NIL
+            }
+            Native::ReadFile => {
+                let path = self.string_arg(native, 0, 1)?;
+                match self.fs.read_to_string(path) {
+                    Ok(contents) => self.alloc(contents).into(),
+                    Err(_) => return self.err(IoError::ReadError { file: path.to_string() }),
+                }
+            }
+            Native::Reduce => self.native_reduce(stdout)?,
+            Native::Sort => self.native_sort(stdout)?,
+            Native::Split => self.native_split()?,
+            Native::Sqrt => self.number_arg(native, 0, 1)?.sqrt().into(),
+            Native::Substr => self.native_substr()?,
+            Native::Trim => {
+                let string = self.string_arg(native, 0, 1)?;
+                self.alloc(string.trim()).into()
+            }
+            Native::Type => {
+                let type_ = unsafe { *self.peek(0) }.type_().to_string();
+                self.alloc(type_).into()
+            }
+            Native::Unbind => self.native_unbind()?,
+            Native::Upper => {
+                let string = self.string_arg(native, 0, 1)?;
+                self.alloc(string.to_uppercase()).into()
+            }
+            Native::Write => {
+                let value = unsafe { *self.peek(0) };
+                self.write_output(stdout, format_args!("{value}"))?;
+                self.flush_output(stdout)?;
+                Value::NIL
+            }
         };
-        unsafe { self.frames.push_unchecked(mem::replace(&mut self.frame, frame)) };
+        Ok(value)
+    }
 
-        Ok(())
+    /// Implements `printf(format, ...)`, which prints its arguments according
+    /// to a format string, without a trailing newline. Like `write()`, it
+    /// flushes its output, since it may not end a line.
+    fn native_printf(&mut self, arg_count: usize, stdout: &mut impl Write) -> Result<()> {
+        let args = unsafe { slice::from_raw_parts(self.peek(arg_count - 1), arg_count) };
+        let (&format, args) = args.split_first().expect("printf called without a format");
+        if !(format.is_object() && format.as_object().type_() == ObjectType::String) {
+            return self.err(TypeError::ArgTypeMismatch {
+                name: Native::Printf.to_string(),
+                pos: 1,
+                exp_type: ObjectType::String.to_string(),
+                got_type: format.type_().to_string(),
+            });
+        }
+        let format = unsafe { (*format.as_object().string).value };
+        match native::printf(format, args) {
+            Ok(output) => {
+                self.write_output(stdout, format_args!("{output}"))?;
+                self.flush_output(stdout)
+            }
+            Err(e) => self.err(e),
+        }
     }
 
-    fn call_native(&mut self, native: *mut ObjectNative, arg_count: usize) -> Result<()> {
-        self.pop();
-        let value = match unsafe { (*native).native } {
-            Native::Clock => {
-                if arg_count != 0 {
-                    return self.err(TypeError::ArityMismatch {
-                        name: "clock".to_string(),
-                        exp_args: 0,
-                        got_args: arg_count,
-                    });
-                }
-                util::now().into()
+    /// Implements `bind(method, instance)`, which binds a method (or a bound
+    /// method, which is first unbound) to the given instance.
+    fn native_bind(&mut self) -> Result<Value> {
+        let method = unsafe { *self.peek(1) };
+        let closure = match method.is_object().then(|| method.as_object().type_()) {
+            Some(ObjectType::BoundMethod) => unsafe { (*method.as_object().bound_method).closure },
+            Some(ObjectType::Closure) => unsafe { method.as_object().closure },
+            _ => {
+                return self.err(TypeError::ArgTypeMismatch {
+                    name: Native::Bind.to_string(),
+                    pos: 1,
+                    exp_type: ObjectType::BoundMethod.to_string(),
+                    got_type: method.type_().to_string(),
+                });
             }
         };
-        self.push(value);
-        Ok(())
+
+        let instance = unsafe { *self.peek(0) };
+        if !(instance.is_object() && instance.as_object().type_() == ObjectType::Instance) {
+            return self.err(TypeError::ArgTypeMismatch {
+                name: Native::Bind.to_string(),
+                pos: 2,
+                exp_type: ObjectType::Instance.to_string(),
+                got_type: instance.type_().to_string(),
+            });
+        }
+
+        // Only the methods of the instance's class are cached, since any
+        // other closure would be kept alive by the instance, and there may be
+        // any number of them.
+        let instance = unsafe { instance.as_object().instance };
+        let class = unsafe { (*instance).class };
+        if unsafe { (*class).methods.values() }.any(|&method| method == closure) {
+            Ok(self.bind_method(instance, closure).into())
+        } else {
+            Ok(self.alloc(ObjectBoundMethod::new(instance, closure)).into())
+        }
+    }
+
+    /// Implements `char(string, index)`, which returns the character at
+    /// `index` in `string`, counting Unicode scalar values. Negative indices
+    /// are not supported.
+    fn native_char(&mut self) -> Result<Value> {
+        let string = self.string_arg(Native::Char, 1, 1)?;
+        let index = self.string_index_arg(Native::Char, 0, 2)?;
+        let char = (index >= 0.0).then(|| string.chars().nth(index as usize)).flatten();
+        match char {
+            Some(char) => Ok(self.alloc(char.to_string()).into()),
+            None => self.err(IndexError::OutOfRange {
+                type_: ObjectType::String.to_string(),
+                index: Value::from(index).to_string(),
+                len: string.chars().count(),
+            }),
+        }
+    }
+
+    /// Implements `substr(string, start, end)`, which returns the characters
+    /// of `string` from `start` up to but not including `end`, counting
+    /// Unicode scalar values. If `end` is not after `start`, the result is
+    /// empty.
+    fn native_substr(&mut self) -> Result<Value> {
+        let string = self.string_arg(Native::Substr, 2, 1)?;
+        let start = self.string_index_arg(Native::Substr, 1, 2)?;
+        let end = self.string_index_arg(Native::Substr, 0, 3)?;
+        let len = string.chars().count();
+        for index in [start, end] {
+            if !(0.0..=len as f64).contains(&index) {
+                return self.err(IndexError::OutOfRange {
+                    type_: ObjectType::String.to_string(),
+                    index: Value::from(index).to_string(),
+                    len,
+                });
+            }
+        }
+        let substr = string.chars().skip(start as usize).take((end - start).max(0.0) as usize);
+        Ok(self.alloc(substr.collect::<String>()).into())
+    }
+
+    /// Implements `split(string, separator)`, which returns a list of the
+    /// parts of `string` between each occurrence of `separator`. An empty
+    /// separator splits the string into its characters.
+    fn native_split(&mut self) -> Result<Value> {
+        let string = self.string_arg(Native::Split, 1, 1)?;
+        let separator = self.string_arg(Native::Split, 0, 2)?;
+        let parts = if separator.is_empty() {
+            string.chars().map(String::from).collect()
+        } else {
+            string.split(separator).map(String::from).collect()
+        };
+        Ok(self.alloc_strings(parts, 0).into())
+    }
+
+    /// Allocates a list of `strings`. The list takes the place of the argument
+    /// `n` places below the top of the stack, so that it remains reachable
+    /// while its strings are allocated. The caller pops the arguments anyway.
+    fn alloc_strings(&mut self, strings: Vec<String>, n: usize) -> *mut ObjectList {
+        let list = self.alloc(ObjectList::new(Vec::with_capacity(strings.len())));
+        unsafe { *self.peek(n) = list.into() };
+        for string in strings {
+            let string = self.alloc(string);
+            unsafe { (*list).elements.push(string.into()) };
+        }
+        list
+    }
+
+    /// Implements `map(list, function)`, which returns a new list with the
+    /// result of calling `function` on each element of `list`.
+    fn native_map(&mut self, stdout: &mut impl Write) -> Result<Value> {
+        let list = self.list_arg(Native::Map, 1, 1)?;
+        let function = self.callable_arg(Native::Map, 0, 2)?;
+        let list = self.copy_list_arg(list, 1);
+        for idx in 0..unsafe { (*list).elements.len() } {
+            let element = unsafe { (&(*list).elements)[idx] };
+            let value = self.call_function(function, &[element], stdout)?;
+            unsafe { (&mut (*list).elements)[idx] = value };
+        }
+        Ok(list.into())
+    }
+
+    /// Implements `filter(list, function)`, which returns a new list with the
+    /// elements of `list` for which `function` returns a truthy value.
+    fn native_filter(&mut self, stdout: &mut impl Write) -> Result<Value> {
+        let list = self.list_arg(Native::Filter, 1, 1)?;
+        let function = self.callable_arg(Native::Filter, 0, 2)?;
+        let list = self.copy_list_arg(list, 1);
+        let mut len = 0;
+        for idx in 0..unsafe { (*list).elements.len() } {
+            let element = unsafe { (&(*list).elements)[idx] };
+            let keep = self.call_function(function, &[element], stdout)?;
+            if self.options.strict_types && !keep.is_bool() {
+                return self
+                    .err(TypeError::ConditionInvalidType { type_: keep.type_().to_string() });
+            }
+            // The elements that are kept move to the front of the list, past
+            // those that have already been passed to `function`.
+            if keep.to_bool() {
+                unsafe { (&mut (*list).elements)[len] = element };
+                len += 1;
+            }
+        }
+        unsafe { (*list).elements.truncate(len) };
+        Ok(list.into())
+    }
+
+    /// Implements `reduce(list, function, initial)`, which calls
+    /// `function(acc, element)` on each element of `list` in turn, where `acc`
+    /// is `initial` for the first element and the previous result after that,
+    /// and returns the last result.
+    fn native_reduce(&mut self, stdout: &mut impl Write) -> Result<Value> {
+        let list = self.list_arg(Native::Reduce, 2, 1)?;
+        let function = self.callable_arg(Native::Reduce, 1, 2)?;
+        let list = self.copy_list_arg(list, 2);
+        // The result takes the place of `initial` on the stack, so that it
+        // remains reachable between calls.
+        for idx in 0..unsafe { (*list).elements.len() } {
+            let element = unsafe { (&(*list).elements)[idx] };
+            let acc = unsafe { *self.peek(0) };
+            let acc = self.call_function(function, &[acc, element], stdout)?;
+            unsafe { *self.peek(0) = acc };
+        }
+        Ok(unsafe { *self.peek(0) })
+    }
+
+    /// Implements `sort(list, less)`, which returns a new list with the
+    /// elements of `list` in order, where `less(a, b)` returns a truthy value
+    /// if `a` goes before `b`. The sort is stable.
+    fn native_sort(&mut self, stdout: &mut impl Write) -> Result<Value> {
+        let list = self.list_arg(Native::Sort, 1, 1)?;
+        let function = self.callable_arg(Native::Sort, 0, 2)?;
+        let list = self.copy_list_arg(list, 1);
+
+        // A bottom-up merge sort. Unlike `slice::sort_by`, this can stop at
+        // the first error, and does not panic if `less` is inconsistent. Every
+        // value in the buffers is also in `list`, so none of them can be
+        // collected while `less` runs.
+        let mut elements = unsafe { (*list).elements.clone() };
+        let mut merged = elements.clone();
+        let len = elements.len();
+        let mut width = 1;
+        while width < len {
+            for start in (0..len).step_by(2 * width) {
+                let (mid, end) = ((start + width).min(len), (start + 2 * width).min(len));
+                let (mut left, mut right) = (start, mid);
+                for slot in &mut merged[start..end] {
+                    let take_right = if left == mid {
+                        true
+                    } else if right == end {
+                        false
+                    } else {
+                        let less = self.call_function(
+                            function,
+                            &[elements[right], elements[left]],
+                            stdout,
+                        )?;
+                        if self.options.strict_types && !less.is_bool() {
+                            return self.err(TypeError::ConditionInvalidType {
+                                type_: less.type_().to_string(),
+                            });
+                        }
+                        less.to_bool()
+                    };
+                    if take_right {
+                        *slot = elements[right];
+                        right += 1;
+                    } else {
+                        *slot = elements[left];
+                        left += 1;
+                    }
+                }
+            }
+            mem::swap(&mut elements, &mut merged);
+            width *= 2;
+        }
+        unsafe { (*list).elements = elements };
+        Ok(list.into())
+    }
+
+    /// Returns the argument `n` places below the top of the stack, which is
+    /// argument `pos` of `native`, if it is a list.
+    fn list_arg(&mut self, native: Native, n: usize, pos: usize) -> Result<*mut ObjectList> {
+        let value = unsafe { *self.peek(n) };
+        if value.is_object() && value.as_object().type_() == ObjectType::List {
+            Ok(unsafe { value.as_object().list })
+        } else {
+            self.err(TypeError::ArgTypeMismatch {
+                name: native.to_string(),
+                pos,
+                exp_type: ObjectType::List.to_string(),
+                got_type: value.type_().to_string(),
+            })
+        }
+    }
+
+    /// Returns the argument `n` places below the top of the stack, which is
+    /// argument `pos` of `native`, if it can be called.
+    fn callable_arg(&mut self, native: Native, n: usize, pos: usize) -> Result<Value> {
+        let value = unsafe { *self.peek(n) };
+        if native::callable_info(value).is_some() {
+            Ok(value)
+        } else {
+            self.err(TypeError::ArgTypeMismatch {
+                name: native.to_string(),
+                pos,
+                exp_type: ObjectType::Function.to_string(),
+                got_type: value.type_().to_string(),
+            })
+        }
+    }
+
+    /// Copies `list`, the argument `n` places below the top of the stack, into
+    /// a new list that takes its place on the stack. Natives that call back
+    /// into Lox work on the copy, so that its elements remain reachable, and
+    /// so that the functions that they call cannot change it.
+    fn copy_list_arg(&mut self, list: *mut ObjectList, n: usize) -> *mut ObjectList {
+        let elements = unsafe { (*list).elements.clone() };
+        let copy = self.alloc(ObjectList::new(elements));
+        unsafe { *self.peek(n) = copy.into() };
+        copy
+    }
+
+    /// Returns the argument `n` places below the top of the stack, which is
+    /// argument `pos` of `native`, if it is a whole number that can index a
+    /// string. Whether it is in range is left to the caller.
+    fn string_index_arg(&mut self, native: Native, n: usize, pos: usize) -> Result<f64> {
+        let index = unsafe { *self.peek(n) };
+        if !index.is_number() {
+            return self.err(TypeError::ArgTypeMismatch {
+                name: native.to_string(),
+                pos,
+                exp_type: ValueType::Number.to_string(),
+                got_type: index.type_().to_string(),
+            });
+        }
+        let number = index.as_number();
+        if number.fract() != 0.0 {
+            return self.err(IndexError::NotAnInteger {
+                type_: ObjectType::String.to_string(),
+                index: index.to_string(),
+            });
+        }
+        Ok(number)
+    }
+
+    /// Returns the argument `n` places below the top of the stack, which is
+    /// argument `pos` of `native`, if it is a number.
+    fn number_arg(&mut self, native: Native, n: usize, pos: usize) -> Result<f64> {
+        let value = unsafe { *self.peek(n) };
+        if value.is_number() {
+            Ok(value.as_number())
+        } else {
+            self.err(TypeError::ArgTypeMismatch {
+                name: native.to_string(),
+                pos,
+                exp_type: ValueType::Number.to_string(),
+                got_type: value.type_().to_string(),
+            })
+        }
+    }
+
+    /// Returns the argument `n` places below the top of the stack, which is
+    /// argument `pos` of `native`, if it is a string.
+    fn string_arg(&mut self, native: Native, n: usize, pos: usize) -> Result<&'static str> {
+        let value = unsafe { *self.peek(n) };
+        if value.is_object() && value.as_object().type_() == ObjectType::String {
+            Ok(unsafe { (*value.as_object().string).value })
+        } else {
+            self.err(TypeError::ArgTypeMismatch {
+                name: native.to_string(),
+                pos,
+                exp_type: ObjectType::String.to_string(),
+                got_type: value.type_().to_string(),
+            })
+        }
+    }
+
+    /// Implements `unbind(method)`, which returns the function underlying a
+    /// bound method.
+    fn native_unbind(&mut self) -> Result<Value> {
+        let method = unsafe { *self.peek(0) };
+        if method.is_object() && method.as_object().type_() == ObjectType::BoundMethod {
+            Ok(unsafe { (*method.as_object().bound_method).closure }.into())
+        } else {
+            self.err(TypeError::ArgTypeMismatch {
+                name: Native::Unbind.to_string(),
+                pos: 1,
+                exp_type: ObjectType::BoundMethod.to_string(),
+                got_type: method.type_().to_string(),
+            })
+        }
+    }
+
+    /// Binds a method of the instance's class to the instance. Bound methods
+    /// are cached on the instance, so binding the same method twice returns
+    /// the same object. This makes
+    /// `obj.method == obj.method` hold, and avoids allocating a new object on
+    /// every access.
+    fn bind_method(
+        &mut self,
+        instance: *mut ObjectInstance,
+        method: *mut ObjectClosure,
+    ) -> *mut ObjectBoundMethod {
+        if let Some(&bound_method) = unsafe { (*instance).bound_methods.get(&method) } {
+            return bound_method;
+        }
+        let bound_method = self.alloc(ObjectBoundMethod::new(instance, method));
+        unsafe { (*instance).bound_methods.insert(method, bound_method) };
+        bound_method
     }
 
     /// Binary operator that acts on any [`Value`].
@@ -709,8 +2458,32 @@ impl VM {
         }
     }
 
+    /// Writes the output of the program to `stdout`, while enforcing
+    /// [`VmOptions::max_output`].
+    fn write_output(&mut self, stdout: &mut impl Write, output: fmt::Arguments) -> Result<()> {
+        let result = match self.options.max_output {
+            Some(limit) => {
+                let output = output.to_string();
+                self.output_len += output.len();
+                if self.output_len > limit {
+                    return self.err(IoError::OutputLimitExceeded { limit });
+                }
+                stdout.write_all(output.as_bytes())
+            }
+            None => stdout.write_fmt(output),
+        };
+        result.or_else(|_| self.err(IoError::WriteError { file: "stdout".to_string() }))
+    }
+
+    /// Flushes `stdout`, so that output without a trailing newline is shown
+    /// right away, even if `stdout` is line-buffered.
+    fn flush_output(&mut self, stdout: &mut impl Write) -> Result<()> {
+        stdout.flush().or_else(|_| self.err(IoError::WriteError { file: "stdout".to_string() }))
+    }
+
     /// Reads an instruction / byte from the current [`Chunk`].
     fn read_u8(&mut self) -> u8 {
+        check_bytecode!(self.ip_offset() < self.ops_len(), "read past the end of the chunk");
         let byte = unsafe { *self.frame.ip };
         self.frame.ip = unsafe { self.frame.ip.add(1) };
         byte
@@ -727,26 +2500,85 @@ impl VM {
     fn read_value(&mut self) -> Value {
         let constant_idx = self.read_u8() as usize;
         let function = unsafe { (*self.frame.closure).function };
+        check_bytecode!(
+            constant_idx < unsafe { (*function).chunk.constants.len() },
+            "constant index out of bounds: {constant_idx}"
+        );
         *unsafe { (*function).chunk.constants.get_unchecked(constant_idx) }
     }
 
+    /// Reads a [`Value`] from the current [`Chunk`] that is known to be a
+    /// string, like the name of a variable.
+    fn read_string(&mut self) -> *mut ObjectString {
+        let value = self.read_value();
+        check_bytecode!(
+            value.is_object() && value.as_object().type_() == ObjectType::String,
+            "name constant is not a string: {value}"
+        );
+        unsafe { value.as_object().string }
+    }
+
     /// Pushes a [`Value`] to the stack.
     fn push(&mut self, value: Value) {
+        check_bytecode!(self.stack_len() < self.stack.len(), "stack overflow");
         unsafe { *self.stack_top = value };
         self.stack_top = unsafe { self.stack_top.add(1) };
     }
 
     /// Pops a [`Value`] from the stack.
     fn pop(&mut self) -> Value {
+        check_bytecode!(self.stack_len() > 0, "stack underflow");
         self.stack_top = unsafe { self.stack_top.sub(1) };
         unsafe { *self.stack_top }
     }
 
     /// Peeks a [`Value`] from the stack.
     fn peek(&mut self, n: usize) -> *mut Value {
+        check_bytecode!(n < self.stack_len(), "stack underflow");
         unsafe { self.stack_top.sub(n + 1) }
     }
 
+    fn stack_len(&self) -> usize {
+        unsafe { self.stack_top.offset_from(self.stack.as_ptr()) as usize }
+    }
+
+    /// The number of values on the stack of the current frame.
+    fn frame_len(&self) -> usize {
+        unsafe { self.stack_top.offset_from(self.frame.stack) as usize }
+    }
+
+    fn ip_offset(&self) -> usize {
+        let function = unsafe { (*self.frame.closure).function };
+        unsafe { self.frame.ip.offset_from((*function).chunk.ops.as_ptr()) as usize }
+    }
+
+    fn ops_len(&self) -> usize {
+        unsafe { (*(*self.frame.closure).function).chunk.ops.len() }
+    }
+
+    fn check_local(&self, stack_idx: usize) {
+        check_bytecode!(
+            unsafe { self.frame.stack.add(stack_idx) } < self.stack_top,
+            "local slot out of bounds: {stack_idx}"
+        );
+    }
+
+    fn check_upvalue(&self, upvalue_idx: usize) {
+        check_bytecode!(
+            upvalue_idx < unsafe { (*self.frame.closure).upvalues.len() },
+            "upvalue index out of bounds: {upvalue_idx}"
+        );
+    }
+
+    /// Checks that jumping by `offset` from the current instruction lands
+    /// inside the chunk.
+    fn check_jump(&self, offset: isize) {
+        check_bytecode!(
+            (self.ip_offset() as isize + offset) as usize <= self.ops_len(),
+            "jump target out of bounds: {offset}"
+        );
+    }
+
     fn capture_upvalue(&mut self, location: *mut Value) -> *mut ObjectUpvalue {
         match self.open_upvalues.iter().find(|&&upvalue| unsafe { (*upvalue).location } == location)
         {
@@ -770,47 +2602,252 @@ impl VM {
         }
     }
 
+    /// Unwinds the stack to the innermost `try` block, and runs its handler.
+    /// A `catch` block gets `error` converted to an instance of the matching
+    /// class in [`ERROR_CLASSES`], while a `finally` block sets it aside for
+    /// RETHROW. Errors that have no such class, or that happen outside of a
+    /// `try` block, are returned instead.
+    fn catch(&mut self, (error, span): ErrorS) -> Result<()> {
+        let class = self
+            .error_classes
+            .iter()
+            .copied()
+            .find(|&class| unsafe { (*(*class).name).value } == error.name());
+        // Handlers outside of the innermost call from a native are left for
+        // the native to return the error to.
+        let handler = match self.handlers.last() {
+            Some(handler) if handler.frames >= self.base_frames => self.handlers.pop(),
+            _ => None,
+        };
+        let (Some(class), Some(handler)) = (class, handler) else {
+            return Err((error, span));
+        };
+
+        if self.frames.len() > handler.frames {
+            self.frames.truncate(handler.frames + 1);
+            self.frame = self.frames.pop().expect("handler frame should exist");
+        }
+        self.close_upvalues(handler.stack);
+        self.stack_top = handler.stack;
+        self.frame.ip = handler.ip;
+        // Errors set aside by `finally` blocks that were running inside the
+        // `try` block are replaced by this one.
+        self.unwinding.truncate(handler.unwinding);
+
+        if handler.finally {
+            self.unwinding.push((self.frames.len(), (error, span)));
+            self.push(Value::NIL);
+            return Ok(());
+        }
+
+        let instance = self.alloc(ObjectInstance::new(class));
+        self.push(instance.into());
+        let message = self.alloc(error.message());
+        unsafe { (*instance).set(self.message_string, message.into()) };
+        Ok(())
+    }
+
     /// Wraps an [`Error`] in a span using the offset of the last executed
     /// instruction.
     #[cold]
-    fn err(&self, err: impl Into<Error>) -> Result<()> {
+    fn err<T>(&self, err: impl Into<Error>) -> Result<T> {
+        Err((err.into(), self.span_of_op()))
+    }
+
+    /// Returns the span of the last executed instruction.
+    fn span_of_op(&self) -> Span {
         let function = unsafe { (*self.frame.closure).function };
         let idx = unsafe { self.frame.ip.offset_from((*function).chunk.ops.as_ptr()) } as usize;
-        let span = unsafe { (*function).chunk.spans[idx - 1].clone() };
-        Err((err.into(), span))
+        unsafe { (*function).chunk.spans[idx - 1].clone() }
+    }
+
+    /// Returns the source code of the last executed instruction, or an empty
+    /// string if it was not compiled from source.
+    #[cold]
+    fn source_of_op(&self) -> String {
+        self.source.get(self.span_of_op()).unwrap_or_default().to_string()
     }
 }
 
-impl Default for VM {
-    fn default() -> Self {
+impl VM {
+    pub fn new(mut options: VmOptions) -> Self {
+        options.max_call_depth = options.max_call_depth.clamp(1, MAX_CALL_DEPTH_LIMIT);
+        let frames = Vec::with_capacity(options.max_call_depth);
+        let stack_size = (options.max_call_depth + 1) * STACK_MAX_PER_FRAME;
+
         let mut gc = Gc::default();
 
-        let mut globals = HashMap::with_capacity_and_hasher(256, BuildHasherDefault::default());
-        let clock_string = gc.alloc("clock");
-        let clock_native = Value::from(gc.alloc(ObjectNative::new(Native::Clock)));
-        globals.insert(clock_string, clock_native);
+        let mut globals = native_globals(&mut gc);
+        let mut error_classes = Vec::with_capacity(ERROR_CLASSES.len());
+        for &name in ERROR_CLASSES {
+            let name = gc.alloc(name);
+            let class = gc.alloc(ObjectClass::new(name));
+            if let Some(&error) = error_classes.first() {
+                unsafe { (*class).super_ = Some(error) };
+                unsafe { (*error).subclasses.push(class) };
+            }
+            globals.insert(name, class.into());
+            error_classes.push(class);
+        }
+        let mut modules = Vec::with_capacity(Native::MODULES.len());
+        for &(name, natives) in Native::MODULES {
+            let name = gc.alloc(name);
+            let class = gc.alloc(ObjectClass::new(name));
+            let module = gc.alloc(ObjectInstance::new(class));
+            for &native in natives {
+                let field = gc.alloc(native.to_string());
+                let native = gc.alloc(ObjectNative::new(native));
+                unsafe { (*module).set(field, native.into()) };
+            }
+            globals.insert(name, module.into());
+            modules.push(module);
+        }
 
         let init_string = gc.alloc("init");
+        let message_string = gc.alloc("message");
+        let empty_string = gc.alloc("");
 
         Self {
+            options,
+            timings: None,
+            stats: VmStats::default(),
+            history: History::default(),
             globals,
             open_upvalues: Vec::with_capacity(256),
+            const_globals: HashSet::default(),
+            snapshots: Vec::new(),
+            next_snapshot_id: 0,
+            foreign_classes: HashMap::default(),
             gc,
             next_gc: 1024 * 1024,
-            frames: ArrayVec::new(),
+            frames,
             frame: CallFrame {
                 closure: ptr::null_mut(),
                 ip: ptr::null_mut(),
                 stack: ptr::null_mut(),
             },
-            stack: Box::new([Value::default(); STACK_MAX]),
+            handlers: Vec::new(),
+            unwinding: Vec::new(),
+            is_running: false,
+            base_frames: 0,
+            native_calls: 0,
+            stack: vec![Value::default(); stack_size].into_boxed_slice(),
             stack_top: ptr::null_mut(),
             init_string,
+            message_string,
+            empty_string,
+            error_classes,
+            modules,
             source: String::new(),
+            fs: Box::new(OsFs),
+            output_len: 0,
+            contexts: Vec::new(),
         }
     }
 }
 
+/// Allocates a global for each [`Native`] that is not only in a module.
+fn native_globals(gc: &mut Gc) -> IndexMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>> {
+    let mut globals = IndexMap::with_capacity_and_hasher(256, BuildHasherDefault::default());
+    for &native in Native::ALL.iter().filter(|native| native.is_global()) {
+        let name = gc.alloc(native.to_string());
+        let native = Value::from(gc.alloc(ObjectNative::new(native)));
+        globals.insert(name, native);
+    }
+    globals
+}
+
+/// The state of a single script, which is swapped in and out of the [`VM`]
+/// by [`VM::swap_context`]. Everything else, including the GC, is shared
+/// between scripts.
+#[derive(Debug)]
+struct Context {
+    globals: IndexMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
+    open_upvalues: Vec<*mut ObjectUpvalue>,
+    const_globals: HashSet<String, BuildHasherDefault<FxHasher>>,
+    frames: Vec<CallFrame>,
+    frame: CallFrame,
+    handlers: Vec<Handler>,
+    unwinding: Vec<(usize, ErrorS)>,
+    is_running: bool,
+    base_frames: usize,
+    native_calls: usize,
+    stack: Box<[Value]>,
+    stack_top: *mut Value,
+    output_len: usize,
+}
+
+impl Context {
+    /// Creates a context with its own stack and a fresh set of globals, which
+    /// shares the error classes of `vm` so that `catch` clauses in any script
+    /// match errors raised by the [`VM`]. Each module is copied, so that a
+    /// script that changes one does not change it for the others.
+    fn new(vm: &mut VM) -> Self {
+        let mut globals = native_globals(&mut vm.gc);
+        for &class in &vm.error_classes {
+            globals.insert(unsafe { (*class).name }, class.into());
+        }
+        for &module in &vm.modules {
+            let class = unsafe { (*module).class };
+            let copy = vm.gc.alloc(ObjectInstance::new(class));
+            for (name, value) in unsafe { (*module).fields() } {
+                unsafe { (*copy).set(name, value) };
+            }
+            globals.insert(unsafe { (*class).name }, copy.into());
+        }
+        Self {
+            globals,
+            open_upvalues: Vec::new(),
+            const_globals: HashSet::default(),
+            frames: Vec::with_capacity(vm.options.max_call_depth),
+            frame: CallFrame {
+                closure: ptr::null_mut(),
+                ip: ptr::null_mut(),
+                stack: ptr::null_mut(),
+            },
+            handlers: Vec::new(),
+            unwinding: Vec::new(),
+            is_running: false,
+            base_frames: 0,
+            native_calls: 0,
+            stack: vec![Value::default(); vm.stack.len()].into_boxed_slice(),
+            stack_top: ptr::null_mut(),
+            output_len: 0,
+        }
+    }
+
+    /// Marks everything that the script can reach.
+    fn mark(&self, gc: &mut Gc) {
+        let mut stack_ptr = self.stack.as_ptr();
+        while stack_ptr < self.stack_top {
+            gc.mark(unsafe { *stack_ptr });
+            stack_ptr = unsafe { stack_ptr.add(1) };
+        }
+
+        for (&name, &value) in &self.globals {
+            gc.mark(name);
+            gc.mark(value);
+        }
+
+        if !self.frame.closure.is_null() {
+            gc.mark(self.frame.closure);
+        }
+        for frame in &self.frames {
+            gc.mark(frame.closure);
+        }
+
+        for &upvalue in &self.open_upvalues {
+            gc.mark(upvalue);
+        }
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new(VmOptions::default())
+    }
+}
+
 #[derive(Debug)]
 pub struct CallFrame {
     closure: *mut ObjectClosure,
@@ -822,3 +2859,754 @@ pub struct CallFrame {
     ip: *const u8,
     stack: *mut Value,
 }
+
+/// Where to resume when a runtime error happens inside a `try` block.
+#[derive(Debug)]
+struct Handler {
+    /// The length of [`VM::frames`] when the handler was installed, which
+    /// identifies the frame it belongs to.
+    frames: usize,
+    /// The start of the `catch` or `finally` block.
+    ip: *const u8,
+    /// The top of the stack when the handler was installed.
+    stack: *mut Value,
+    /// The length of [`VM::unwinding`] when the handler was installed.
+    unwinding: usize,
+    /// Whether the handler runs a `finally` block, rather than a `catch`
+    /// block.
+    finally: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::error::BytecodeError;
+    use crate::vfs::MemoryFs;
+
+    fn run_recursive(depth: usize, max_call_depth: usize) -> Result<(), Vec<ErrorS>> {
+        let source = format!(
+            "fun f(n) {{ if (n > 0) f(n - 1); }}
+            f({depth});"
+        );
+        let mut vm = VM::new(VmOptions { max_call_depth, ..Default::default() });
+        vm.run(&source, &mut Vec::new())
+    }
+
+    #[test]
+    fn deep_recursion() {
+        assert_eq!(run_recursive(63, DEFAULT_MAX_CALL_DEPTH), Ok(()));
+        assert_eq!(run_recursive(5000, 10000), Ok(()));
+    }
+
+    #[test]
+    fn history() {
+        let source = "var a = 1; fun f(x) { return x + nil; } a = 2; f(a);";
+        let mut vm = VM::new(VmOptions { history: 4, ..Default::default() });
+        assert!(vm.run(source, &mut Vec::new()).is_err());
+        let entries = vm.history.entries().cloned().collect::<Vec<_>>();
+        let summary = entries
+            .iter()
+            .map(|entry| {
+                let op = entry.op.split_whitespace().nth(1).unwrap();
+                (entry.function.as_str(), op, entry.popped.join(" "), entry.pushed.join(" "))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("<script>", "OP_CALL", "<function f> 2".to_string(), String::new()),
+                ("f", "OP_GET_LOCAL", String::new(), "2".to_string()),
+                ("f", "OP_NIL", String::new(), "nil".to_string()),
+                ("f", "OP_ADD", "2 nil".to_string(), String::new()),
+            ]
+        );
+        assert_eq!(
+            entries[3].error.as_deref(),
+            Some(r#"TypeError: unsupported operand type(s) for +: "number" and "nil""#)
+        );
+        assert_eq!(&vm.source[entries[3].span.clone()], "x + nil");
+
+        // Global writes are recorded with their new value.
+        vm.options.history = 8;
+        vm.run("a = 3; a + nil;", &mut Vec::new()).unwrap_err();
+        let global = vm.history.entries().find_map(|entry| entry.global.clone());
+        assert_eq!(global, Some(("a".to_string(), "3".to_string())));
+        assert!(vm.history.to_string().starts_with("previous 6 operations:\n  <script>  0000"));
+
+        let mut vm = VM::new(VmOptions::default());
+        vm.run(source, &mut Vec::new()).unwrap_err();
+        assert!(vm.history.is_empty());
+    }
+
+    #[test]
+    fn output_limit() {
+        let source = "for (var i = 0; i < 1000; i = i + 1) print i;";
+        let mut vm = VM::new(VmOptions { max_output: Some(10), ..Default::default() });
+        let mut output = Vec::new();
+        let errors = vm.run(source, &mut output).unwrap_err();
+        assert_eq!(errors[0].0, Error::IoError(IoError::OutputLimitExceeded { limit: 10 }));
+        assert_eq!(output, b"0\n1\n2\n3\n4\n");
+
+        // The limit applies to each run separately.
+        let mut output = Vec::new();
+        assert_eq!(vm.run("print 123;", &mut output), Ok(()));
+        assert_eq!(output, b"123\n");
+
+        // Catching the error would let the program print without limit.
+        let source = "try { while (true) print 1; } catch (e) { print e; }";
+        let errors = vm.run(source, &mut Vec::new()).unwrap_err();
+        assert_eq!(errors[0].0, Error::IoError(IoError::OutputLimitExceeded { limit: 10 }));
+    }
+
+    #[test]
+    fn run_main() {
+        let run = |source: &str, args: &[&str]| {
+            let mut vm = VM::new(VmOptions::default());
+            let mut output = Vec::new();
+            vm.run(source, &mut output).expect("program should run");
+            let args = args.iter().map(ToString::to_string).collect::<Vec<_>>();
+            let code = vm.run_main(&args, &mut output).map_err(|errors| errors[0].0.to_string());
+            (code, String::from_utf8(output).unwrap())
+        };
+        assert_eq!(run("print 1;", &["a"]), (Ok(None), "1\n".to_string()));
+        assert_eq!(run("var main = 1;", &[]), (Ok(None), String::new()));
+        assert_eq!(
+            run("fun main(a, b) { print a; print b; }", &["x"]),
+            (Ok(Some(0)), "x\nnil\n".to_string())
+        );
+        assert_eq!(run("fun main() { return 42; }", &[]), (Ok(Some(42)), String::new()));
+        assert_eq!(
+            run("fun main() {}", &["x"]),
+            (
+                Err("TypeError: main() takes 0 arguments but 1 were given".to_string()),
+                String::new()
+            )
+        );
+        assert_eq!(
+            run("fun main() { return \"1\"; }", &[]),
+            (
+                Err("TypeError: main() should return an integer or nil, not 1".to_string()),
+                String::new()
+            )
+        );
+    }
+
+    #[test]
+    fn step() {
+        let mut vm = VM::default();
+        let mut output = Vec::new();
+        vm.load("var a = 1; print a + 2;").unwrap();
+        let mut stacks = Vec::new();
+        loop {
+            match vm.step(&mut output).unwrap() {
+                Step::Running => stacks.push(vm.stack().to_vec()),
+                Step::Finished(value) => {
+                    assert_eq!(value, Value::NIL);
+                    break;
+                }
+            }
+        }
+        assert_eq!(output, b"3\n");
+        // SMALL_INT, DEFINE_GLOBAL, GET_GLOBAL, SMALL_INT, ADD, PRINT
+        let (one, two, three) = (Value::from(1.0), Value::from(2.0), Value::from(3.0));
+        assert_eq!(stacks, [vec![one], vec![], vec![one], vec![one, two], vec![three], vec![]]);
+
+        // A script that fails is unloaded, and the VM can run others.
+        vm.load("print nil + 1;").unwrap();
+        assert_eq!(vm.step(&mut output), Ok(Step::Running));
+        assert_eq!(vm.step(&mut output), Ok(Step::Running));
+        let (error, _) = vm.step(&mut output).unwrap_err();
+        assert_eq!(error.name(), "TypeError");
+        assert_eq!(vm.run("print a;", &mut output), Ok(()));
+        assert_eq!(output, b"3\n1\n");
+    }
+
+    #[test]
+    fn stats() {
+        let mut vm = VM::default();
+        vm.run("var a = 1; print a + 2;", &mut Vec::new()).unwrap();
+        assert_eq!(vm.stats.ops, if cfg!(feature = "vm-stats") { 8 } else { 0 });
+        assert!(vm.stats.peak_heap > 0);
+
+        // A string that grows on each iteration soon triggers a collection.
+        let source = r#"var s = ""; for (var i = 0; i < 2000; i = i + 1) s = s + "a";"#;
+        vm.run(source, &mut Vec::new()).unwrap();
+        assert!(vm.stats.ops > 8 || !cfg!(feature = "vm-stats"));
+        assert_eq!(vm.stats.gc_count > 0, !cfg!(feature = "gc-off"));
+    }
+
+    #[test]
+    fn precision() {
+        let mut vm = VM::new(VmOptions { precision: Some(2), ..Default::default() });
+        let mut output = Vec::new();
+        vm.run("print 2 / 3; print 4; print [1 / 3]; print \"0.125\";", &mut output).unwrap();
+        // Only numbers printed by themselves are rounded.
+        assert_eq!(output, b"0.67\n4\n[0.3333333333333333]\n0.125\n");
+    }
+
+    #[test]
+    fn read_file_from_memory() {
+        let mut fs = MemoryFs::default();
+        fs.add_file("data/a.txt", "hello");
+        let mut vm = VM::new(VmOptions::default());
+        vm.fs = Box::new(fs);
+        let mut output = Vec::new();
+        vm.run("print read_file(\"./data/a.txt\");", &mut output).unwrap();
+        assert_eq!(output, b"hello\n");
+
+        let errors = vm.run("read_file(\"b.txt\");", &mut output).unwrap_err();
+        assert_eq!(errors[0].0, Error::IoError(IoError::ReadError { file: "b.txt".to_string() }));
+    }
+
+    #[test]
+    fn shapes() {
+        let mut vm = VM::default();
+        let source = "
+            class P {}
+            var a = P(); a.x = 1; a.y = 2;
+            var b = P(); b.x = 3; b.y = 4; b.x = 5;
+            var c = P(); c.y = 6; c.x = 7;";
+        vm.run(source, &mut Vec::new()).unwrap();
+        let globals = vm.defined_globals();
+        let shape = |name| {
+            let &(_, value) = globals.iter().find(|&&(global, _)| global == name).unwrap();
+            Rc::as_ptr(unsafe { &(*value.as_object().instance).shape })
+        };
+        // Instances that add the same fields in the same order share a shape.
+        assert_eq!(shape("a"), shape("b"));
+        assert_ne!(shape("a"), shape("c"));
+    }
+
+    #[test]
+    fn hot_functions() {
+        let mut vm = VM::default();
+        let source = "
+            fun f() {}
+            fun g() { f(); f(); }
+            for (var i = 0; i < 3; i = i + 1) g();";
+        vm.run(source, &mut Vec::new()).unwrap();
+        let expected: &[(&str, u64)] =
+            if cfg!(feature = "vm-stats") { &[("f", 6), ("g", 3)] } else { &[] };
+        assert_eq!(vm.hot_functions(10), expected);
+        assert_eq!(vm.hot_functions(1).len(), expected.len().min(1));
+    }
+
+    #[test]
+    fn function_costs() {
+        let mut vm = VM::default();
+        let source = "
+            fun f() { return [1, 2]; }
+            fun g() { f(); f(); }
+            for (var i = 0; i < 3; i = i + 1) g();";
+        vm.run(source, &mut Vec::new()).unwrap();
+        let costs = vm.function_costs(10);
+        if !cfg!(feature = "vm-stats") {
+            assert_eq!(costs, []);
+            return;
+        }
+        let cost = |name| *costs.iter().find(|cost| cost.name == name).unwrap();
+        assert_eq!(costs.len(), 2);
+        assert_eq!((cost("f").calls, cost("g").calls), (6, 3));
+        // Each call to `f` allocates a list, which `g` does not count.
+        assert_eq!((cost("f").allocs, cost("g").allocs), (6, 0));
+        assert_eq!(cost("f").ops % 6, 0);
+        assert!(costs[0].ops >= costs[1].ops);
+    }
+
+    #[test]
+    fn object_counts() {
+        let mut vm = VM::default();
+        let before = vm.gc.counts().clone();
+        let source = "class A {} fun f() {} for (var i = 0; i < 3; i = i + 1) { var a = A(); }";
+        vm.run(source, &mut Vec::new()).unwrap();
+        let count = |type_| vm.gc.counts().get(type_);
+        // Each module is an instance, allocated when the VM starts.
+        let modules = Native::MODULES.len() as u64;
+        assert_eq!(count(ObjectType::Instance).allocated, modules + 3);
+        assert_eq!(count(ObjectType::Class).allocated, before.get(ObjectType::Class).allocated + 1);
+        // The script and `f` are each a function and a closure.
+        assert_eq!(count(ObjectType::Function).allocated, 2);
+        assert_eq!(count(ObjectType::Closure).allocated, 2);
+
+        vm.gc();
+        let instances = ObjectCount { allocated: modules + 3, live: modules };
+        assert_eq!(vm.gc.counts().get(ObjectType::Instance), instances);
+        assert!(vm.gc.counts().to_string().contains("\ninstance                5            2"));
+    }
+
+    #[test]
+    fn rooted_values() {
+        let mut vm = VM::default();
+        vm.run("class A { init(x) { this.x = x; } }", &mut Vec::new()).unwrap();
+        let value = vm.eval("A(\"a\" + \"b\");", &mut Vec::new()).unwrap();
+        let root = vm.root(value);
+        vm.run("var b = A(1);", &mut Vec::new()).unwrap();
+        vm.gc();
+        let modules = Native::MODULES.len() as u64;
+        assert_eq!(vm.gc.counts().get(ObjectType::Instance).live, modules + 2);
+        assert_eq!(inspect(root.get()), "<object A { x: \"ab\" }>");
+
+        drop(root);
+        vm.gc();
+        assert_eq!(vm.gc.counts().get(ObjectType::Instance).live, modules + 1);
+    }
+
+    #[test]
+    fn call_function() {
+        let source = r#"
+            fun add(a, b) { return a + b; }
+            fun fail() { try { nil.x; } finally { print "finally"; } }
+            class Point { init(x) { this.x = x; } }
+        "#;
+        let mut vm = VM::default();
+        vm.run(source, &mut Vec::new()).unwrap();
+        let call = |vm: &mut VM, name: &str, args: &[Value]| {
+            let name = vm.gc.alloc(name);
+            let callee = vm.globals[&name];
+            let mut output = Vec::new();
+            let result = vm.call_function(callee, args, &mut output);
+            let output = String::from_utf8(output).unwrap();
+            (result.map(|value| value.to_string()).map_err(|(e, _)| e.to_string()), output)
+        };
+
+        let args = [1.0.into(), 2.0.into()];
+        assert_eq!(call(&mut vm, "add", &args), (Ok("3".to_string()), String::new()));
+        assert_eq!(call(&mut vm, "type", &[true.into()]), (Ok("bool".to_string()), String::new()));
+        assert_eq!(
+            call(&mut vm, "Point", &[1.0.into()]),
+            (Ok("<object Point>".to_string()), String::new())
+        );
+        assert_eq!(
+            call(&mut vm, "add", &[]),
+            (Err("TypeError: add() takes 2 arguments but 0 were given".to_string()), String::new())
+        );
+        assert_eq!(
+            call(&mut vm, "fail", &[]),
+            (
+                Err(r#"AttributeError: "nil" object has no attribute "x""#.to_string()),
+                "finally\n".to_string()
+            )
+        );
+
+        // The VM can still run programs afterwards.
+        let mut output = Vec::new();
+        vm.run("print add(3, 4);", &mut output).unwrap();
+        assert_eq!(output, b"7\n");
+    }
+
+    #[test]
+    fn run_bytecode() {
+        let source = r#"
+            fun counter() {
+                var n = 0;
+                fun incr() { n = n + 1; return n; }
+                return incr;
+            }
+            var c = counter();
+            c();
+            print c() + 0.5;
+            print "done";
+            nil.x;
+        "#;
+        let bytecode = VM::default().compile(source).expect("program should compile");
+
+        // Errors are reported at the same spans as if the program was compiled
+        // by the VM that runs it.
+        let mut vm = VM::default();
+        vm.run("print 1;", &mut Vec::new()).unwrap();
+        let mut output = Vec::new();
+        let errors = vm.run_bytecode(source, &bytecode, &mut output).unwrap_err();
+        assert_eq!(output, b"2.5\ndone\n");
+        let mut expected = VM::default();
+        expected.run("print 1;", &mut Vec::new()).unwrap();
+        assert_eq!(errors, expected.run(source, &mut Vec::new()).unwrap_err());
+
+        let run = |source: &str, bytecode: &[u8]| {
+            let errors = VM::default().run_bytecode(source, bytecode, &mut Vec::new()).unwrap_err();
+            errors.into_iter().next().unwrap().0
+        };
+        assert_eq!(run("print 2;", &bytecode), Error::BytecodeError(BytecodeError::SourceMismatch));
+        for len in [0, 4, 20, bytecode.len() - 1] {
+            assert_eq!(
+                run(source, &bytecode[..len]),
+                Error::BytecodeError(BytecodeError::Malformed)
+            );
+        }
+        let mut bytecode = bytecode;
+        bytecode[8] = b'x';
+        assert!(matches!(
+            run(source, &bytecode),
+            Error::BytecodeError(BytecodeError::VersionMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn eval() {
+        let mut vm = VM::default();
+        let mut output = Vec::new();
+        let value = vm.eval("var a = 1; print a; a + 1;", &mut output).unwrap();
+        assert_eq!(output, b"1\n");
+        assert_eq!(value.to_string(), "2");
+        assert!(vm.eval("var b = 2;", &mut output).unwrap().is_nil());
+        let value = vm.eval("\"a\" + \"b\";", &mut output).unwrap();
+        assert_eq!(value.type_(), ValueType::Object(ObjectType::String));
+        assert!(vm.eval("1 +;", &mut output).is_err());
+    }
+
+    #[test]
+    fn defined_globals() {
+        let mut vm = VM::default();
+        assert_eq!(vm.defined_globals(), []);
+        vm.run("var b = 1; fun a() {} clock = nil; class TypeError {}", &mut Vec::new()).unwrap();
+        let names = vm.defined_globals().into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names, ["TypeError", "a", "b", "clock"]);
+
+        // Natives and error classes are globals too.
+        let globals = vm.globals().collect::<std::collections::BTreeMap<_, _>>();
+        assert_eq!(globals.len(), vm.globals.len());
+        assert_eq!(globals["b"], Value::from(1.0));
+        assert_eq!(globals["len"].to_string(), "<native len>");
+        assert!(globals.contains_key("NameError"));
+    }
+
+    #[test]
+    fn iteration_order() {
+        let mut vm = VM::default();
+        let fields = (0..40).rev().map(|idx| format!("o.f{idx} = {idx};")).collect::<String>();
+        let source = format!(
+            "var z = 1; var a = 2; z = 3;
+            class A {{ c() {{}} b() {{}} }}
+            class B < A {{ d() {{}} b() {{}} a() {{}} }}
+            var o = B(); {fields}"
+        );
+        vm.run(&source, &mut Vec::new()).unwrap();
+
+        // Globals, methods and fields are kept in the order in which they were
+        // added, whatever their addresses, so that anything that lists them
+        // does so the same way on every run.
+        let natives = Native::ALL
+            .iter()
+            .filter(|native| native.is_global())
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let names = vm.globals().map(|(name, _)| name.to_string()).collect::<Vec<_>>();
+        assert_eq!(names[..natives.len()], natives);
+        assert_eq!(names[names.len() - 5..], ["z", "a", "A", "B", "o"]);
+
+        let globals = vm.globals().collect::<std::collections::BTreeMap<_, _>>();
+        let methods = unsafe { (*globals["B"].as_object().class).methods.keys() }
+            .map(|&name| unsafe { (*name).value })
+            .collect::<Vec<_>>();
+        assert_eq!(methods, ["c", "b", "d", "a"]);
+        let fields = unsafe { (*globals["o"].as_object().instance).fields() }
+            .map(|(name, _)| unsafe { (*name).value }.to_string())
+            .collect::<Vec<_>>();
+        assert!(fields.into_iter().eq((0..40).rev().map(|idx| format!("f{idx}"))));
+    }
+
+    #[test]
+    fn snapshot_globals() {
+        let mut vm = VM::default();
+        let setup = "
+            var list = [1]; var map = {\"a\": list};
+            fun counter() { var n = 0; fun inc() { n = n + 1; return n; } return inc; }
+            var inc = counter(); inc();
+            class C {} var c = C(); c.self = c; c.x = 0;";
+        vm.run(setup, &mut Vec::new()).unwrap();
+        let snapshot = vm.snapshot_globals();
+
+        let change = "list[0] = 2; inc(); c.x = 1; var added = true; const k = 1;";
+        vm.run(change, &mut Vec::new()).unwrap();
+        vm.gc();
+        assert!(vm.restore(snapshot));
+        let mut stdout = Vec::new();
+        let check = "print list; print map[\"a\"] == list; print inc(); print c.self == c;
+            print c.x; var added = 1; var k = 2;";
+        vm.run(check, &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "[1]\ntrue\n2\ntrue\n0\n");
+
+        // The snapshot is copied again when it is restored, so it can be
+        // restored more than once.
+        vm.run("list[0] = 3;", &mut Vec::new()).unwrap();
+        assert!(vm.restore(snapshot));
+        let mut stdout = Vec::new();
+        vm.run("print list;", &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "[1]\n");
+
+        vm.drop_snapshot(snapshot);
+        assert!(!vm.restore(snapshot));
+
+        // A dropped snapshot stays dropped, even after others are taken.
+        let dropped = vm.snapshot_globals();
+        vm.drop_snapshot(dropped);
+        vm.run("list[0] = 4;", &mut Vec::new()).unwrap();
+        let later = vm.snapshot_globals();
+        assert_ne!(later, dropped);
+        assert!(!vm.restore(dropped));
+        assert!(vm.restore(later));
+    }
+
+    #[test]
+    fn bind_caches_class_methods() {
+        let mut vm = VM::default();
+        let source = "
+            class A { m() {} } var a = A();
+            for (var i = 0; i < 10; i = i + 1) { bind(a.m, a); bind(() -> 1, a); }";
+        vm.run(source, &mut Vec::new()).unwrap();
+        let a = vm.defined_globals().into_iter().find(|&(name, _)| name == "a").unwrap().1;
+        // Only the method of the class is cached, not each of the lambdas.
+        assert_eq!(unsafe { (*a.as_object().instance).bound_methods.len() }, 1);
+    }
+
+    #[test]
+    fn snapshot_keeps_extensions() {
+        let mut vm = VM::new(VmOptions { class_extensions: true, ..Default::default() });
+        vm.run("class A {} var a = A();", &mut Vec::new()).unwrap();
+        let snapshot = vm.snapshot_globals();
+
+        // Classes are shared with the snapshot, so methods added to them are
+        // not undone.
+        vm.run("extend A { m() { return 1; } }", &mut Vec::new()).unwrap();
+        assert!(vm.restore(snapshot));
+        let mut stdout = Vec::new();
+        vm.run("print a.m();", &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "1\n");
+    }
+
+    #[test]
+    fn extend_freed_subclasses() {
+        let mut vm = VM::new(VmOptions { class_extensions: true, ..Default::default() });
+        let source = "class A {} class B < A {} fun f() { class C < A {} } f(); f();";
+        vm.run(source, &mut Vec::new()).unwrap();
+        let a = vm.defined_globals().into_iter().find(|&(name, _)| name == "A").unwrap().1;
+        let a = unsafe { a.as_object().class };
+
+        // Subclasses are forgotten when they are freed, so extending the
+        // class does not touch them.
+        vm.gc();
+        assert_eq!(unsafe { (*a).subclasses.len() }, 1);
+        let mut stdout = Vec::new();
+        vm.run("extend A { m() { return 1; } } print B().m();", &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "1\n");
+    }
+
+    #[test]
+    fn callable_info() {
+        let mut vm = VM::default();
+        let source = "fun f(a, b = 1) {} class C { init(x) {} m() {} } var m = C(1).m;";
+        vm.run(source, &mut Vec::new()).unwrap();
+        let info = |name: &str| {
+            let value = vm.defined_globals().into_iter().find(|&(global, _)| global == name);
+            let info = native::callable_info(value.map_or(Value::NIL, |(_, value)| value))?;
+            Some((info.name, info.kind, info.min_arity, info.max_arity))
+        };
+        assert_eq!(info("f"), Some(("f".to_string(), CallableKind::Function, 1, Some(2))));
+        assert_eq!(info("C"), Some(("C".to_string(), CallableKind::Class, 1, Some(1))));
+        assert_eq!(info("m"), Some(("m".to_string(), CallableKind::BoundMethod, 0, Some(0))));
+        assert_eq!(info("missing"), None);
+
+        let printf = vm.globals[&vm.gc.alloc("printf")];
+        let info = native::callable_info(printf).unwrap();
+        assert_eq!((info.kind, info.min_arity, info.max_arity), (CallableKind::Native, 1, None));
+    }
+
+    #[test]
+    fn foreign() {
+        struct Counter {
+            count: f64,
+            label: Value,
+        }
+
+        let mut vm = VM::default();
+        vm.register_foreign(
+            ForeignClassBuilder::<Counter>::new("Counter")
+                .method("add", 1, |counter, args, _| {
+                    if !args[0].is_number() {
+                        return Err(TypeError::ArgTypeMismatch {
+                            name: "add".to_string(),
+                            pos: 1,
+                            exp_type: "number".to_string(),
+                            got_type: args[0].type_().to_string(),
+                        }
+                        .into());
+                    }
+                    counter.count += args[0].as_number();
+                    Ok(Value::NIL)
+                })
+                .method("get", 0, |counter, _, _| Ok(counter.count.into()))
+                .method("label", 0, |counter, _, _| Ok(counter.label))
+                .method("setLabel", 1, |counter, args, _| {
+                    counter.label = args[0];
+                    Ok(Value::NIL)
+                })
+                .trace(|counter, gc| gc.mark(counter.label))
+                .build(),
+        );
+        let counter = vm.foreign(Counter { count: 0.0, label: Value::NIL });
+        let name = vm.gc.alloc("counter");
+        vm.globals.insert(name, counter);
+
+        let mut stdout = Vec::new();
+        let source = "counter.add(2); counter.add(3); print counter.get(); print counter;
+            counter.setLabel(\"a\" + \"b\");";
+        vm.run(source, &mut stdout).unwrap();
+        vm.gc();
+        vm.run("print counter.label(); print type(counter);", &mut stdout).unwrap();
+        assert_eq!(String::from_utf8(stdout).unwrap(), "5\n<foreign Counter>\nab\nforeign\n");
+        assert_eq!(vm.foreign_ref::<Counter>(counter).map(|counter| counter.count), Some(5.0));
+        assert!(vm.foreign_ref::<String>(counter).is_none());
+
+        for (source, error) in [
+            (
+                "counter.add(nil);",
+                "TypeError: add() argument 1 must be of type \"number\", not \"nil\"",
+            ),
+            ("counter.add();", "TypeError: add() takes 1 arguments but 0 were given"),
+            ("counter.nope();", "AttributeError: \"Counter\" object has no attribute \"nope\""),
+            ("counter.get;", "AttributeError: \"foreign\" object has no attribute \"get\""),
+        ] {
+            let errors = vm.run(source, &mut Vec::new()).unwrap_err();
+            assert_eq!(errors[0].0.to_string(), error);
+        }
+    }
+
+    #[test]
+    fn foreign_before_run() {
+        struct Point(f64);
+
+        // Nothing has run, so there is no current closure when these
+        // allocations collect garbage, as each of them does with `gc-stress`.
+        let mut vm = VM::default();
+        vm.register_foreign(ForeignClassBuilder::<Point>::new("Point").build());
+        let point = vm.foreign(Point(1.0));
+        let root = vm.root(point);
+        let other = vm.foreign(Point(2.0));
+        assert_eq!(vm.foreign_ref::<Point>(other).map(|point| point.0), Some(2.0));
+        vm.gc();
+        assert_eq!(vm.foreign_ref::<Point>(root.get()).map(|point| point.0), Some(1.0));
+    }
+
+    #[test]
+    fn traceback() {
+        let source = "fun inner() { nil.x; }\nfun outer() { inner(); }\nouter();";
+        let mut vm = VM::default();
+        assert_eq!(vm.traceback(), []);
+        vm.run(source, &mut Vec::new()).unwrap_err();
+        let traceback = vm
+            .traceback()
+            .into_iter()
+            .map(|(name, span)| (name, &source[span]))
+            .collect::<Vec<_>>();
+        assert_eq!(traceback, [("inner", "nil.x"), ("outer", "inner()"), ("<script>", "outer()")]);
+    }
+
+    #[test]
+    fn run_compiled() {
+        let source = "var a = \"compiled\"; print a; nil.x;";
+        let bytecode = VM::default().compile(source).unwrap();
+        let mut output = Vec::new();
+        let errors = VM::default().run_compiled(&bytecode, &mut output).unwrap_err();
+        assert_eq!(output, b"compiled\n");
+        assert_eq!(errors, VM::default().run(source, &mut Vec::new()).unwrap_err());
+
+        let mut bytecode = bytecode;
+        bytecode[8] = b'x';
+        let errors = VM::default().run_compiled(&bytecode, &mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            errors[..],
+            [(Error::BytecodeError(BytecodeError::VersionMismatch { .. }), _)]
+        ));
+    }
+
+    #[test]
+    fn decode_unchecked() {
+        let source = "fun f() { return 1; } print f();";
+        let bytecode = VM::default().compile(source).unwrap();
+        let mut gc = Gc::default();
+        let (header, script) = serialize::decode_unchecked(&bytecode, &mut gc).unwrap();
+        assert_eq!(header.version, serialize::VERSION);
+        assert_eq!(header.source_hash, serialize::source_hash(source));
+        let script = unsafe { &*script };
+        assert_eq!(verify(script), Ok(()));
+        assert_eq!(script.chunk.safe_points.len(), 2);
+        let f = unsafe { &*script.chunk.constants[0].as_object().function };
+        // The implicit return at the end of f is a statement too.
+        assert_eq!(f.chunk.safe_points, [0, 3]);
+        let locals = f.chunk.locals.iter().map(|local| (local.name.as_str(), local.slot));
+        assert_eq!(locals.collect::<Vec<_>>(), [("f", 0)]);
+
+        let disassembly = script.disassemble();
+        let headings =
+            disassembly.lines().filter(|line| line.starts_with("==")).collect::<Vec<_>>();
+        assert_eq!(headings, ["== <script> ==", "== f =="]);
+        assert!(disassembly.contains("OP_RETURN"));
+
+        assert_eq!(
+            serialize::decode_unchecked(&bytecode[..bytecode.len() - 1], &mut gc).unwrap_err(),
+            BytecodeError::Malformed
+        );
+    }
+
+    #[test]
+    fn strict_types() {
+        let run = |source: &str| {
+            let mut vm = VM::new(VmOptions { strict_types: true, ..Default::default() });
+            vm.run(source, &mut Vec::new()).map_err(|errors| errors.into_iter().next().unwrap().0)
+        };
+        assert_eq!(run("print 1 == 1; print \"a\" != nil; print nil == false;"), Ok(()));
+        assert_eq!(run("if (1 < 2) {} while (false) {} print true and 1;"), Ok(()));
+        // Negating a value always gives a bool.
+        assert_eq!(run("if (!nil) {} while (!1) {}"), Ok(()));
+        assert_eq!(
+            run("print 1 == \"1\";"),
+            Err(Error::TypeError(TypeError::UnsupportedOperandInfix {
+                op: "==".to_string(),
+                lt_type: "number".to_string(),
+                rt_type: "string".to_string(),
+            }))
+        );
+        assert_eq!(
+            run("if (\"yes\") {}"),
+            Err(Error::TypeError(TypeError::ConditionInvalidType { type_: "string".to_string() }))
+        );
+        assert_eq!(
+            run("print nil or 1;"),
+            Err(Error::TypeError(TypeError::ConditionInvalidType { type_: "nil".to_string() }))
+        );
+    }
+
+    #[test]
+    fn drop_deep_object_graphs() {
+        let source = r#"
+            class Node {}
+            var list = nil;
+            for (var i = 0; i < 200000; i = i + 1) {
+                var node = Node();
+                node.next = list;
+                list = node;
+            }
+
+            fun wrap(f) { fun g() { return f; } return g; }
+            var closure = nil;
+            for (var i = 0; i < 100000; i = i + 1) closure = wrap(closure);
+        "#;
+        let mut vm = VM::default();
+        assert_eq!(vm.run(source, &mut Vec::new()), Ok(()));
+        drop(vm);
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let overflow = |result: Result<(), Vec<ErrorS>>| {
+            matches!(
+                &result.unwrap_err()[..],
+                [(Error::OverflowError(OverflowError::StackOverflow), _)]
+            )
+        };
+        assert!(overflow(run_recursive(64, DEFAULT_MAX_CALL_DEPTH)));
+        assert!(overflow(run_recursive(1000, 100)));
+        assert!(overflow(run_recursive(MAX_CALL_DEPTH_LIMIT, usize::MAX)));
+    }
+}
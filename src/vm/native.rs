@@ -0,0 +1,306 @@
+use std::fmt::Write;
+
+use crate::error::TypeError;
+use crate::vm::object::{Native, ObjectFunction, ObjectInstance, ObjectType};
+use crate::vm::value::Value;
+
+/// The maximum depth up to which nested instances are expanded by
+/// [`inspect`].
+const INSPECT_MAX_DEPTH: usize = 4;
+
+/// The maximum number of decimal places that [`printf`] will print, so that a
+/// format string cannot make it allocate without bound.
+const PRINTF_MAX_PRECISION: usize = 64;
+
+/// Returns a detailed, human-readable description of a [`Value`]. Unlike its
+/// [`Display`](std::fmt::Display) implementation, this expands the fields of
+/// instances, and shows the arity of callables.
+pub fn inspect(value: Value) -> String {
+    let mut output = String::new();
+    inspect_value(&mut output, value, &mut Vec::new());
+    output
+}
+
+fn inspect_value(output: &mut String, value: Value, parents: &mut Vec<*mut ObjectInstance>) {
+    if !value.is_object() {
+        let _ = write!(output, "{value}");
+        return;
+    }
+
+    let object = value.as_object();
+    match object.type_() {
+        ObjectType::BoundMethod => {
+            let closure = unsafe { (*object.bound_method).closure };
+            let class = unsafe { (*(*object.bound_method).this).class };
+            let _ = write!(
+                output,
+                "<bound method {}.{} arity={}>",
+                unsafe { (*(*class).name).value },
+                unsafe { (*(*(*closure).function).name).value },
+                unsafe { (*(*closure).function).arity },
+            );
+        }
+        ObjectType::Class => {
+            let class = unsafe { object.class };
+            let mut methods = unsafe { (*class).methods.keys() }
+                .map(|&name| unsafe { (*name).value })
+                .collect::<Vec<_>>();
+            methods.sort_unstable();
+            let _ = write!(
+                output,
+                "<class {} methods=[{}]>",
+                unsafe { (*(*class).name).value },
+                methods.join(", ")
+            );
+        }
+        ObjectType::Closure => {
+            inspect_value(output, unsafe { (*object.closure).function }.into(), parents);
+        }
+        ObjectType::Function => {
+            let function = unsafe { object.function };
+            let _ = write!(
+                output,
+                "<function {} arity={}>",
+                unsafe { (*(*function).name).value },
+                unsafe { (*function).arity }
+            );
+        }
+        ObjectType::Instance => inspect_instance(output, unsafe { object.instance }, parents),
+        ObjectType::Native => {
+            let native = unsafe { (*object.native).native };
+            let _ = write!(output, "<native {native} arity={}>", native.arity());
+        }
+        ObjectType::String => {
+            let _ = write!(output, "{:?}", unsafe { (*object.string).value });
+        }
+        ObjectType::Foreign | ObjectType::List | ObjectType::Map | ObjectType::Upvalue => {
+            let _ = write!(output, "{object}");
+        }
+    }
+}
+
+/// What kind of value a [`CallableInfo`] describes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CallableKind {
+    BoundMethod,
+    Class,
+    Function,
+    Native,
+}
+
+/// The name and arity of a value that can be called.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CallableInfo {
+    pub name: String,
+    pub kind: CallableKind,
+    /// The fewest arguments that a call can pass.
+    pub min_arity: usize,
+    /// The most arguments that a call can pass, or `None` if there is no
+    /// limit.
+    pub max_arity: Option<usize>,
+}
+
+impl CallableInfo {
+    /// The arity as `arity()` returns it: the number of parameters, or the
+    /// number of required ones if there is no limit.
+    pub fn arity(&self) -> usize {
+        self.max_arity.unwrap_or(self.min_arity)
+    }
+}
+
+/// Returns the name and arity of `value`, if it can be called. A class takes
+/// the arguments of its initializer, and a bound method those of its method.
+pub fn callable_info(value: Value) -> Option<CallableInfo> {
+    if !value.is_object() {
+        return None;
+    }
+    let object = value.as_object();
+    let function_info = |function: *mut ObjectFunction, kind| {
+        let function = unsafe { &*function };
+        CallableInfo {
+            name: unsafe { (*function.name).value }.to_string(),
+            kind,
+            min_arity: function.min_arity().into(),
+            max_arity: Some(function.arity.into()),
+        }
+    };
+    let info = match object.type_() {
+        ObjectType::BoundMethod => function_info(
+            unsafe { (*(*object.bound_method).closure).function },
+            CallableKind::BoundMethod,
+        ),
+        ObjectType::Class => {
+            let class = unsafe { &*object.class };
+            let init = class.methods.iter().find(|&(&name, _)| unsafe { (*name).value } == "init");
+            let (min_arity, max_arity) = match init {
+                Some((_, &init)) => {
+                    let info = function_info(unsafe { (*init).function }, CallableKind::Class);
+                    (info.min_arity, info.max_arity)
+                }
+                None => (0, Some(0)),
+            };
+            let name = unsafe { (*class.name).value }.to_string();
+            CallableInfo { name, kind: CallableKind::Class, min_arity, max_arity }
+        }
+        ObjectType::Closure => {
+            function_info(unsafe { (*object.closure).function }, CallableKind::Function)
+        }
+        ObjectType::Function => function_info(unsafe { object.function }, CallableKind::Function),
+        ObjectType::Native => {
+            let native = unsafe { (*object.native).native };
+            CallableInfo {
+                name: native.to_string(),
+                kind: CallableKind::Native,
+                min_arity: native.arity(),
+                max_arity: (!native.is_variadic()).then(|| native.arity()),
+            }
+        }
+        ObjectType::Foreign
+        | ObjectType::Instance
+        | ObjectType::List
+        | ObjectType::Map
+        | ObjectType::String
+        | ObjectType::Upvalue => return None,
+    };
+    Some(info)
+}
+
+fn inspect_instance(
+    output: &mut String,
+    instance: *mut ObjectInstance,
+    parents: &mut Vec<*mut ObjectInstance>,
+) {
+    let _ = write!(output, "<object {}", unsafe { (*(*(*instance).class).name).value });
+
+    let mut fields = unsafe { (*instance).fields() }
+        .map(|(name, value)| (unsafe { (*name).value }, value))
+        .collect::<Vec<_>>();
+    fields.sort_unstable_by_key(|&(name, _)| name);
+
+    if fields.is_empty() {
+        output.push_str(" {}>");
+        return;
+    }
+    if parents.len() >= INSPECT_MAX_DEPTH {
+        output.push_str(" { ... }>");
+        return;
+    }
+
+    parents.push(instance);
+    output.push_str(" { ");
+    for (idx, (name, value)) in fields.into_iter().enumerate() {
+        if idx != 0 {
+            output.push_str(", ");
+        }
+        let _ = write!(output, "{name}: ");
+
+        let is_cycle = value.is_object()
+            && value.as_object().type_() == ObjectType::Instance
+            && parents.contains(&unsafe { value.as_object().instance });
+        if is_cycle {
+            output.push_str("<cycle>");
+        } else {
+            inspect_value(output, value, parents);
+        }
+    }
+    output.push_str(" }>");
+    parents.pop();
+}
+
+/// A piece of a `printf` format string.
+enum FormatPiece<'a> {
+    Literal(&'a str),
+    /// `%s`: any value, as `print` would show it.
+    String,
+    /// `%d`: a number, rounded towards zero.
+    Integer,
+    /// `%f` or `%.Nf`: a number, with the given number of decimal places.
+    Float {
+        precision: usize,
+    },
+}
+
+/// Formats `args` according to `format`, for [`Native::Printf`].
+pub fn printf(format: &str, args: &[Value]) -> Result<String, TypeError> {
+    let pieces = parse_format(format)?;
+    let exp_args = pieces.iter().filter(|piece| !matches!(piece, FormatPiece::Literal(_))).count();
+    if exp_args != args.len() {
+        return Err(TypeError::FormatArityMismatch { exp_args, got_args: args.len() });
+    }
+
+    let mut output = String::new();
+    let mut args = args.iter().enumerate();
+    for piece in pieces {
+        let piece = match piece {
+            FormatPiece::Literal(literal) => {
+                output.push_str(literal);
+                continue;
+            }
+            piece => piece,
+        };
+        let (idx, &arg) = args.next().expect("argument count should have been checked");
+        if let FormatPiece::String = piece {
+            let _ = write!(output, "{arg}");
+            continue;
+        }
+
+        if !arg.is_number() {
+            return Err(TypeError::ArgTypeMismatch {
+                name: Native::Printf.to_string(),
+                // The format string is the first argument.
+                pos: idx + 2,
+                exp_type: "number".to_string(),
+                got_type: arg.type_().to_string(),
+            });
+        }
+        let number = arg.as_number();
+        let _ = match piece {
+            FormatPiece::Float { precision } => write!(output, "{number:.precision$}"),
+            _ => write!(output, "{}", number.trunc()),
+        };
+    }
+    Ok(output)
+}
+
+fn parse_format(format: &str) -> Result<Vec<FormatPiece<'_>>, TypeError> {
+    let mut pieces = Vec::new();
+    let mut rest = format;
+    while let Some(idx) = rest.find('%') {
+        if idx != 0 {
+            pieces.push(FormatPiece::Literal(&rest[..idx]));
+        }
+        let spec = &rest[idx..];
+        let (piece, len) = if spec.starts_with("%%") {
+            (FormatPiece::Literal("%"), 2)
+        } else if spec.starts_with("%s") {
+            (FormatPiece::String, 2)
+        } else if spec.starts_with("%d") {
+            (FormatPiece::Integer, 2)
+        } else if spec.starts_with("%f") {
+            (FormatPiece::Float { precision: 6 }, 2)
+        } else {
+            let digits = spec
+                .strip_prefix("%.")
+                .map_or(0, |spec| spec.bytes().take_while(u8::is_ascii_digit).count());
+            let precision = spec
+                .get(2..2 + digits)
+                .and_then(|digits| digits.parse().ok())
+                .filter(|&precision| precision <= PRINTF_MAX_PRECISION);
+            match precision {
+                Some(precision) if spec[2 + digits..].starts_with('f') => {
+                    (FormatPiece::Float { precision }, 3 + digits)
+                }
+                _ => {
+                    let end = spec.char_indices().nth(2).map_or(spec.len(), |(idx, _)| idx);
+                    return Err(TypeError::InvalidFormatSpec { spec: spec[..end].to_string() });
+                }
+            }
+        };
+        pieces.push(piece);
+        rest = &rest[idx + len..];
+    }
+    if !rest.is_empty() {
+        pieces.push(FormatPiece::Literal(rest));
+    }
+    Ok(pieces)
+}
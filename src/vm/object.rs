@@ -1,11 +1,17 @@
+use std::any::Any;
 use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::BuildHasherDefault;
 use std::mem;
+use std::rc::Rc;
 
 use hashbrown::HashMap;
+use indexmap::IndexMap;
 use rustc_hash::FxHasher;
 
+use crate::syntax::ast::Name;
 use crate::vm::chunk::Chunk;
+use crate::vm::foreign::ForeignClass;
+use crate::vm::shape::Shape;
 use crate::vm::value::Value;
 
 const _: () = assert!(mem::size_of::<Object>() == 4 || mem::size_of::<Object>() == 8);
@@ -17,8 +23,11 @@ pub union Object {
     pub bound_method: *mut ObjectBoundMethod,
     pub class: *mut ObjectClass,
     pub closure: *mut ObjectClosure,
+    pub foreign: *mut ObjectForeign,
     pub function: *mut ObjectFunction,
     pub instance: *mut ObjectInstance,
+    pub list: *mut ObjectList,
+    pub map: *mut ObjectMap,
     pub native: *mut ObjectNative,
     pub string: *mut ObjectString,
     pub upvalue: *mut ObjectUpvalue,
@@ -43,12 +52,21 @@ impl Object {
             ObjectType::Closure => {
                 let _ = unsafe { Box::from_raw(self.closure) };
             }
+            ObjectType::Foreign => {
+                let _ = unsafe { Box::from_raw(self.foreign) };
+            }
             ObjectType::Function => {
                 let _ = unsafe { Box::from_raw(self.function) };
             }
             ObjectType::Instance => {
                 let _ = unsafe { Box::from_raw(self.instance) };
             }
+            ObjectType::List => {
+                let _ = unsafe { Box::from_raw(self.list) };
+            }
+            ObjectType::Map => {
+                let _ = unsafe { Box::from_raw(self.map) };
+            }
             ObjectType::Native => {
                 let _ = unsafe { Box::from_raw(self.native) };
             }
@@ -70,6 +88,15 @@ impl Debug for Object {
 
 impl Display for Object {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_nested(f, &mut Vec::new())
+    }
+}
+
+impl Object {
+    /// Formats the object for [`Display`]. `parents` are the lists and maps
+    /// that contain it, so that one that contains itself is shown as `[...]`
+    /// or `{...}` instead of recursing forever.
+    fn fmt_nested(&self, f: &mut Formatter<'_>, parents: &mut Vec<Object>) -> fmt::Result {
         match self.type_() {
             ObjectType::BoundMethod => {
                 write!(f, "<bound method {}>", unsafe {
@@ -82,6 +109,9 @@ impl Display for Object {
             ObjectType::Closure => {
                 write!(f, "{}", unsafe { Object::from((*self.closure).function) })
             }
+            ObjectType::Foreign => {
+                write!(f, "<foreign {}>", unsafe { (*self.foreign).class.name() })
+            }
             ObjectType::Function => {
                 let name = unsafe { (*(*self.function).name).value };
                 write!(f, "<function {name}>")
@@ -89,6 +119,34 @@ impl Display for Object {
             ObjectType::Instance => {
                 write!(f, "<object {}>", unsafe { (*(*(*self.instance).class).name).value })
             }
+            ObjectType::List if parents.contains(self) => write!(f, "[...]"),
+            ObjectType::List => {
+                parents.push(*self);
+                write!(f, "[")?;
+                for (idx, &element) in unsafe { &(*self.list).elements }.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    fmt_element(f, element, parents)?;
+                }
+                parents.pop();
+                write!(f, "]")
+            }
+            ObjectType::Map if parents.contains(self) => write!(f, "{{...}}"),
+            ObjectType::Map => {
+                parents.push(*self);
+                write!(f, "{{")?;
+                for (idx, &(key, value)) in unsafe { &(*self.map).entries }.iter().enumerate() {
+                    if idx != 0 {
+                        write!(f, ", ")?;
+                    }
+                    fmt_element(f, key, parents)?;
+                    write!(f, ": ")?;
+                    fmt_element(f, value, parents)?;
+                }
+                parents.pop();
+                write!(f, "}}")
+            }
             ObjectType::Native => write!(f, "<native {}>", unsafe { (*self.native).native }),
             ObjectType::String => write!(f, "{}", unsafe { (*self.string).value }),
             ObjectType::Upvalue => write!(f, "<upvalue>"),
@@ -96,6 +154,19 @@ impl Display for Object {
     }
 }
 
+/// Formats a value inside a list or a map. Strings are quoted there, so that
+/// `["a, b"]` can be told apart from `["a", "b"]`.
+fn fmt_element(f: &mut Formatter<'_>, value: Value, parents: &mut Vec<Object>) -> fmt::Result {
+    if !value.is_object() {
+        return write!(f, "{value}");
+    }
+    let object = value.as_object();
+    match object.type_() {
+        ObjectType::String => write!(f, "{:?}", unsafe { (*object.string).value }),
+        _ => object.fmt_nested(f, parents),
+    }
+}
+
 macro_rules! impl_from_object {
     ($name:tt, $type_:ty) => {
         impl From<*mut $type_> for Object {
@@ -110,8 +181,11 @@ impl_from_object!(common, ObjectCommon);
 impl_from_object!(bound_method, ObjectBoundMethod);
 impl_from_object!(class, ObjectClass);
 impl_from_object!(closure, ObjectClosure);
+impl_from_object!(foreign, ObjectForeign);
 impl_from_object!(function, ObjectFunction);
 impl_from_object!(instance, ObjectInstance);
+impl_from_object!(list, ObjectList);
+impl_from_object!(map, ObjectMap);
 impl_from_object!(native, ObjectNative);
 impl_from_object!(string, ObjectString);
 impl_from_object!(upvalue, ObjectUpvalue);
@@ -135,21 +209,61 @@ pub enum ObjectType {
     BoundMethod,
     Class,
     Closure,
+    Foreign,
     Function,
     Native,
     Instance,
+    List,
+    Map,
     String,
     Upvalue,
 }
 
+impl ObjectType {
+    pub const ALL: [ObjectType; 11] = [
+        ObjectType::BoundMethod,
+        ObjectType::Class,
+        ObjectType::Closure,
+        ObjectType::Foreign,
+        ObjectType::Function,
+        ObjectType::Native,
+        ObjectType::Instance,
+        ObjectType::List,
+        ObjectType::Map,
+        ObjectType::String,
+        ObjectType::Upvalue,
+    ];
+
+    /// A name for the type which, unlike its [`Display`] form, is different
+    /// for every type.
+    pub fn id(self) -> &'static str {
+        match self {
+            ObjectType::BoundMethod => "bound_method",
+            ObjectType::Class => "class",
+            ObjectType::Closure => "closure",
+            ObjectType::Foreign => "foreign",
+            ObjectType::Function => "function",
+            ObjectType::Native => "native",
+            ObjectType::Instance => "instance",
+            ObjectType::List => "list",
+            ObjectType::Map => "map",
+            ObjectType::String => "string",
+            ObjectType::Upvalue => "upvalue",
+        }
+    }
+}
+
 impl Display for ObjectType {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             ObjectType::BoundMethod => write!(f, "bound method"),
             ObjectType::Class => write!(f, "class"),
             ObjectType::Closure => write!(f, "function"),
+            ObjectType::Foreign => write!(f, "foreign"),
             ObjectType::Function => write!(f, "function"),
             ObjectType::Instance => write!(f, "instance"),
+            ObjectType::List => write!(f, "list"),
+            ObjectType::Map => write!(f, "map"),
             ObjectType::Native => write!(f, "native"),
             ObjectType::String => write!(f, "string"),
             ObjectType::Upvalue => write!(f, "upvalue"),
@@ -177,13 +291,31 @@ impl ObjectBoundMethod {
 pub struct ObjectClass {
     pub common: ObjectCommon,
     pub name: *mut ObjectString,
-    pub methods: HashMap<*mut ObjectString, *mut ObjectClosure, BuildHasherDefault<FxHasher>>,
+    /// The class this inherits from. Its methods have already been copied
+    /// into [`ObjectClass::methods`], so this is only kept for reflection.
+    pub super_: Option<*mut ObjectClass>,
+    /// The classes that inherit directly from this one, so that `extend` can
+    /// update the methods they copied. These are not kept alive by the class:
+    /// the GC forgets the ones that it frees.
+    pub subclasses: Vec<*mut ObjectClass>,
+    /// The methods of the class, inherited ones first, in the order in which
+    /// they were declared. Overriding a method keeps its place.
+    pub methods: IndexMap<*mut ObjectString, *mut ObjectClosure, BuildHasherDefault<FxHasher>>,
+    /// The shape of a new instance of the class, which has no fields.
+    pub shape: Rc<Shape>,
 }
 
 impl ObjectClass {
     pub fn new(name: *mut ObjectString) -> Self {
         let common = ObjectCommon { type_: ObjectType::Class, is_marked: false };
-        Self { common, name, methods: HashMap::default() }
+        Self {
+            common,
+            name,
+            super_: None,
+            subclasses: Vec::new(),
+            methods: IndexMap::default(),
+            shape: Rc::default(),
+        }
     }
 }
 
@@ -202,6 +334,22 @@ impl ObjectClosure {
     }
 }
 
+/// A Rust value passed into Lox by [`VM::foreign`](crate::vm::VM::foreign).
+#[derive(Debug)]
+#[repr(C)]
+pub struct ObjectForeign {
+    pub common: ObjectCommon,
+    pub class: Rc<ForeignClass>,
+    pub value: Box<dyn Any>,
+}
+
+impl ObjectForeign {
+    pub fn new(class: Rc<ForeignClass>, value: Box<dyn Any>) -> Self {
+        let common = ObjectCommon { type_: ObjectType::Foreign, is_marked: false };
+        Self { common, class, value }
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct ObjectFunction {
@@ -209,13 +357,67 @@ pub struct ObjectFunction {
     pub name: *mut ObjectString,
     pub arity: u8,
     pub upvalue_count: u16,
+    /// The largest number of values that a call to this function ever has on
+    /// its frame, including the callee and its arguments.
+    pub max_stack: usize,
+    /// Where calls that leave out parameters with default values start, so
+    /// that they compute the defaults of those parameters before running the
+    /// body: a call with `min_arity() + i` arguments starts at `entries[i]`.
+    /// Calls with every argument start at the first op.
+    pub entries: Vec<usize>,
     pub chunk: Chunk,
+    /// The number of times the function has been called. This is only
+    /// counted with the `vm-stats` feature, and shows which functions a
+    /// faster tier, like a JIT, would pay off for.
+    pub calls: u64,
+    /// The number of ops run in calls to the function, not counting those run
+    /// in the functions that it calls. Also only counted with `vm-stats`.
+    pub ops: u64,
+    /// The number of objects allocated while the function was running, not
+    /// counting those allocated in the functions that it calls. Also only
+    /// counted with `vm-stats`.
+    pub allocs: u64,
 }
 
 impl ObjectFunction {
     pub fn new(name: *mut ObjectString, arity: u8) -> Self {
         let common = ObjectCommon { type_: ObjectType::Function, is_marked: false };
-        Self { common, name, arity, upvalue_count: 0, chunk: Chunk::default() }
+        Self {
+            common,
+            name,
+            arity,
+            upvalue_count: 0,
+            max_stack: 0,
+            entries: Vec::new(),
+            chunk: Chunk::default(),
+            calls: 0,
+            ops: 0,
+            allocs: 0,
+        }
+    }
+
+    /// The number of parameters without a default value.
+    pub fn min_arity(&self) -> u8 {
+        self.arity - self.entries.len() as u8
+    }
+
+    /// Whether the function is a method, whose first slot holds `this`
+    /// rather than the function itself.
+    pub fn is_method(&self) -> bool {
+        self.chunk.locals.first().is_some_and(|local| local.slot == 0 && local.name == Name::THIS)
+    }
+
+    /// Returns the disassembly of this function, followed by that of each
+    /// function nested in it.
+    pub fn disassemble(&self) -> String {
+        let mut out = self.chunk.disassemble(unsafe { (*self.name).value });
+        for constant in &self.chunk.constants {
+            if constant.is_object() && constant.as_object().type_() == ObjectType::Function {
+                out.push('\n');
+                out.push_str(&unsafe { &*constant.as_object().function }.disassemble());
+            }
+        }
+        out
     }
 }
 
@@ -224,13 +426,160 @@ impl ObjectFunction {
 pub struct ObjectInstance {
     pub common: ObjectCommon,
     pub class: *mut ObjectClass,
-    pub fields: HashMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
+    /// Gives the name of the field in each of [`ObjectInstance::slots`].
+    pub shape: Rc<Shape>,
+    pub slots: Vec<Value>,
+    /// The fields added after the shape was full, in the order in which they
+    /// were added.
+    pub overflow: IndexMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
+    /// Methods that have been bound to this instance, keyed by the method.
+    pub bound_methods:
+        HashMap<*mut ObjectClosure, *mut ObjectBoundMethod, BuildHasherDefault<FxHasher>>,
 }
 
 impl ObjectInstance {
     pub fn new(class: *mut ObjectClass) -> Self {
         let common = ObjectCommon { type_: ObjectType::Instance, is_marked: false };
-        Self { common, class, fields: HashMap::default() }
+        Self {
+            common,
+            class,
+            shape: Rc::clone(unsafe { &(*class).shape }),
+            slots: Vec::new(),
+            overflow: IndexMap::default(),
+            bound_methods: HashMap::default(),
+        }
+    }
+
+    pub fn get(&self, name: *mut ObjectString) -> Option<Value> {
+        match self.shape.slot(name) {
+            Some(slot) => Some(self.slots[slot]),
+            None if self.overflow.is_empty() => None,
+            None => self.overflow.get(&name).copied(),
+        }
+    }
+
+    /// Sets the field `name`, moving the instance to a new shape if it did not
+    /// have the field yet.
+    pub fn set(&mut self, name: *mut ObjectString, value: Value) {
+        if let Some(slot) = self.shape.slot(name) {
+            self.slots[slot] = value;
+            return;
+        }
+        if self.overflow.is_empty() {
+            if let Some(shape) = self.shape.with(name) {
+                self.shape = shape;
+                self.slots.push(value);
+                return;
+            }
+        }
+        self.overflow.insert(name, value);
+    }
+
+    /// The fields of the instance, in the order in which they were added.
+    pub fn fields(&self) -> impl Iterator<Item = (*mut ObjectString, Value)> + '_ {
+        let slots = self.shape.names().iter().copied().zip(self.slots.iter().copied());
+        slots.chain(self.overflow.iter().map(|(&name, &value)| (name, value)))
+    }
+}
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct ObjectList {
+    pub common: ObjectCommon,
+    pub elements: Vec<Value>,
+}
+
+impl ObjectList {
+    pub fn new(elements: Vec<Value>) -> Self {
+        let common = ObjectCommon { type_: ObjectType::List, is_marked: false };
+        Self { common, elements }
+    }
+}
+
+/// A map from values to values, which keeps its entries in the order in which
+/// their keys were first inserted. Keys are compared like `==` compares them,
+/// except that `0` and `-0` are the same key, and so is every `NaN`, however
+/// it was computed.
+#[derive(Debug)]
+#[repr(C)]
+pub struct ObjectMap {
+    pub common: ObjectCommon,
+    pub entries: Vec<(Value, Value)>,
+    /// The index of each key in [`ObjectMap::entries`].
+    index: HashMap<Value, usize, BuildHasherDefault<FxHasher>>,
+}
+
+impl ObjectMap {
+    pub fn new() -> Self {
+        let common = ObjectCommon { type_: ObjectType::Map, is_marked: false };
+        Self { common, entries: Vec::new(), index: HashMap::default() }
+    }
+
+    pub fn get(&self, key: Value) -> Option<Value> {
+        let &idx = self.index.get(&Self::normalize(key))?;
+        Some(self.entries[idx].1)
+    }
+
+    /// Sets the value of `key`, keeping its position if it is already in the
+    /// map.
+    pub fn insert(&mut self, key: Value, value: Value) {
+        let key = Self::normalize(key);
+        match self.index.get(&key) {
+            Some(&idx) => self.entries[idx].1 = value,
+            None => {
+                self.index.insert(key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Hashes `key`, so that keys that are the same in a map have the same
+    /// hash. Nil, booleans, numbers and strings hash by their value, which
+    /// gives the same hash in every run and on every platform. Other objects
+    /// are only the same key as themselves, so they hash by their address.
+    pub fn hash(key: Value) -> u32 {
+        // FNV-1a, which unlike the hasher of the index does not depend on the
+        // width of `usize`.
+        let fnv = |bytes: &[u8]| {
+            let hash = bytes.iter().fold(0xcbf29ce484222325_u64, |hash, &byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            });
+            (hash ^ (hash >> 32)) as u32
+        };
+        let key = Self::normalize(key);
+        if key.is_object() && key.as_object().type_() == ObjectType::String {
+            fnv(unsafe { (*key.as_object().string).value }.as_bytes())
+        } else {
+            fnv(&key.to_bits().to_le_bytes())
+        }
+    }
+
+    fn normalize(key: Value) -> Value {
+        if !key.is_number() {
+            return key;
+        }
+        let number = key.as_number();
+        if number == 0.0 {
+            Value::from(0.0)
+        } else if number.is_nan() {
+            Value::from(f64::NAN)
+        } else {
+            key
+        }
+    }
+}
+
+impl Default for ObjectMap {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -250,13 +599,160 @@ impl ObjectNative {
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Native {
+    Abs,
+    Arity,
+    Bind,
+    Char,
+    Chars,
     Clock,
+    Filter,
+    Floor,
+    Hash,
+    Inspect,
+    Len,
+    Lower,
+    Map,
+    Pow,
+    Printf,
+    ReadFile,
+    Reduce,
+    Sort,
+    Split,
+    Sqrt,
+    Substr,
+    Trim,
+    Type,
+    Unbind,
+    Upper,
+    Write,
+}
+
+impl Native {
+    /// All natives, in the order in which they are defined as globals.
+    /// Compiled programs refer to natives by their index in this list, so new
+    /// natives go at the end.
+    pub const ALL: &'static [Native] = &[
+        Native::Bind,
+        Native::Char,
+        Native::Clock,
+        Native::Inspect,
+        Native::Len,
+        Native::Lower,
+        Native::Printf,
+        Native::Split,
+        Native::Substr,
+        Native::Trim,
+        Native::Type,
+        Native::Unbind,
+        Native::Upper,
+        Native::Write,
+        Native::Arity,
+        Native::Abs,
+        Native::Floor,
+        Native::Pow,
+        Native::Sqrt,
+        Native::Hash,
+        Native::ReadFile,
+        Native::Map,
+        Native::Filter,
+        Native::Reduce,
+        Native::Sort,
+        Native::Chars,
+    ];
+
+    /// The built-in modules, each with the natives that it holds. A module is
+    /// a global holding an instance of a class of the same name, with a field
+    /// for each of its natives, so that they are called like methods, as in
+    /// `math.sqrt(2)`.
+    pub const MODULES: &'static [(&'static str, &'static [Native])] = &[
+        ("math", &[Native::Abs, Native::Floor, Native::Pow, Native::Sqrt]),
+        (
+            "string",
+            &[
+                Native::Char,
+                Native::Chars,
+                Native::Lower,
+                Native::Split,
+                Native::Substr,
+                Native::Trim,
+                Native::Upper,
+            ],
+        ),
+    ];
+
+    /// The number of arguments that the native takes. For variadic natives,
+    /// this is the minimum.
+    pub fn arity(self) -> usize {
+        match self {
+            Native::Abs => 1,
+            Native::Arity => 1,
+            Native::Bind => 2,
+            Native::Char => 2,
+            Native::Chars => 1,
+            Native::Clock => 0,
+            Native::Filter => 2,
+            Native::Floor => 1,
+            Native::Hash => 1,
+            Native::Inspect => 1,
+            Native::Len => 1,
+            Native::Lower => 1,
+            Native::Map => 2,
+            Native::Pow => 2,
+            Native::Printf => 1,
+            Native::ReadFile => 1,
+            Native::Reduce => 3,
+            Native::Sort => 2,
+            Native::Split => 2,
+            Native::Sqrt => 1,
+            Native::Substr => 3,
+            Native::Trim => 1,
+            Native::Type => 1,
+            Native::Unbind => 1,
+            Native::Upper => 1,
+            Native::Write => 1,
+        }
+    }
+
+    pub fn is_variadic(self) -> bool {
+        matches!(self, Native::Printf)
+    }
+
+    /// Whether the native is defined as a global of its own. The others are
+    /// only reachable through a module in [`Native::MODULES`].
+    pub fn is_global(self) -> bool {
+        !matches!(self, Native::Abs | Native::Floor | Native::Pow | Native::Sqrt)
+    }
 }
 
 impl Display for Native {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
+            Native::Abs => write!(f, "abs"),
+            Native::Arity => write!(f, "arity"),
+            Native::Bind => write!(f, "bind"),
+            Native::Char => write!(f, "char"),
+            Native::Chars => write!(f, "chars"),
             Native::Clock => write!(f, "clock"),
+            Native::Filter => write!(f, "filter"),
+            Native::Floor => write!(f, "floor"),
+            Native::Hash => write!(f, "hash"),
+            Native::Inspect => write!(f, "inspect"),
+            Native::Len => write!(f, "len"),
+            Native::Lower => write!(f, "lower"),
+            Native::Map => write!(f, "map"),
+            Native::Pow => write!(f, "pow"),
+            Native::Printf => write!(f, "printf"),
+            Native::ReadFile => write!(f, "read_file"),
+            Native::Reduce => write!(f, "reduce"),
+            Native::Sort => write!(f, "sort"),
+            Native::Split => write!(f, "split"),
+            Native::Sqrt => write!(f, "sqrt"),
+            Native::Substr => write!(f, "substr"),
+            Native::Trim => write!(f, "trim"),
+            Native::Type => write!(f, "type"),
+            Native::Unbind => write!(f, "unbind"),
+            Native::Upper => write!(f, "upper"),
+            Native::Write => write!(f, "write"),
         }
     }
 }
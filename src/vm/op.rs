@@ -5,6 +5,9 @@ iota! {
     // Reads a 1-byte constant index, and pushes the constant at that index onto
     // the stack.
     CONSTANT: u8 = iota;,
+    // Reads a 1-byte integer, and pushes it onto the stack as a number. Small
+    // integers are common enough that they are not given a constant each.
+    SMALL_INT,
     // Pushes a nil value onto the stack.
     NIL,
     // Pushes a true value onto the stack.
@@ -13,6 +16,13 @@ iota! {
     FALSE,
     // Pops a value from the stack.
     POP,
+    // Pushes a copy of the value on top of the stack.
+    DUP,
+    // Swaps the 2 values on top of the stack.
+    SWAP,
+    // Moves the third value from the top of the stack to the top, so that
+    // `a b c` becomes `b c a`.
+    ROT,
     // Reads a 1-byte stack slot, and pushes the value at that slot onto the
     // stack.
     GET_LOCAL,
@@ -45,6 +55,10 @@ iota! {
     // Pops 2 values from the stack, tests the second for being less than or
     // equal to the first, and pushes the result onto the stack.
     LESS_EQUAL,
+    // Pops a class and a value from the stack, tests if the value is an
+    // instance of the class or of one of its subclasses, and pushes the result
+    // onto the stack.
+    IS,
     // Pops 2 values from the stack, adds (in case of numbers) or concatenates
     // (in case of strings) them, and pushes the result onto the stack.
     ADD,
@@ -57,24 +71,65 @@ iota! {
     // Pops 2 numbers from the stack, divides the second by the first, and
     // pushes the result onto the stack.
     DIVIDE,
+    // Pops 2 numbers from the stack, and pushes the remainder of dividing the
+    // second by the first, which has the sign of the second.
+    MODULO,
+    // Pops 2 numbers from the stack, raises the second to the power of the
+    // first, and pushes the result onto the stack.
+    POWER,
     // Pops a value from the stack, checks if it is "falsey", and pushes the
     // result onto the stack.
     NOT,
     // Pops a number from the stack, negates it, and pushes the result onto the
     // stack.
     NEGATE,
-    // Pops a value from the stack and prints it.
+    // Pops a value from the stack, and fails with an AssertionError if it is
+    // falsey.
+    ASSERT,
+    // Reads a 1-byte op, which must be a comparison. Pops 2 values from the
+    // stack, compares them with that op, and fails with an AssertionError that
+    // shows both values if the result is falsey.
+    ASSERT_INFIX,
+    // Pops a value from the stack and prints it, followed by a newline.
     PRINT,
+    // Pops a value from the stack and prints it, followed by a space. Used for
+    // all but the last value of a print statement.
+    PRINT_SPACED,
     // Reads a 2-byte offset, and increments the instruction pointer by that
     // offset.
     JUMP,
     // Reads a 2-byte offset, and peeks at the value on top of the stack. If the
     // value is falsey, increments the instruction pointer by that offset.
     JUMP_IF_FALSE,
+    // Reads a 2-byte offset, and pops a value from the stack. If the value is
+    // falsey, increments the instruction pointer by that offset.
+    JUMP_IF_FALSE_POP,
+    // Reads a 2-byte offset, and pops a value from the stack. If the value is
+    // truthy, increments the instruction pointer by that offset. This is used
+    // for negated conditions, so unlike JUMP_IF_FALSE_POP, the value does not
+    // need to be a bool in strict mode.
+    JUMP_IF_TRUE_POP,
     // Reads a 2-byte offset, and decrements the instruction pointer by that
     // offset.
     LOOP,
+    // Reads a 2-byte offset, and installs a handler for runtime errors. If an
+    // error occurs before the matching END_TRY, the stack is unwound to where
+    // it was at this op, the error is pushed onto it, and the instruction
+    // pointer is incremented by the offset from the end of this op.
+    TRY,
+    // Like TRY, but the handler runs a `finally` block. Instead of the error,
+    // a placeholder is pushed, and the error is set aside for RETHROW.
+    TRY_FINALLY,
+    // Removes the handler installed by the most recent TRY or TRY_FINALLY.
+    END_TRY,
+    // Pops the placeholder pushed by TRY_FINALLY, and raises the error that
+    // was set aside again.
+    RETHROW,
     CALL,
+    // Reads a 1-byte index into `Native::ALL` and a 1-byte argument count,
+    // and calls that native with the arguments on top of the stack. Unlike
+    // CALL, there is no callee on the stack below the arguments.
+    CALL_NATIVE,
     INVOKE,
     SUPER_INVOKE,
     CLOSURE,
@@ -82,5 +137,72 @@ iota! {
     RETURN,
     CLASS,
     INHERIT,
-    METHOD
+    METHOD,
+    // Reads a 1-byte count, pops that many values from the stack, and pushes a
+    // list of them, with the value that was lowest on the stack first.
+    LIST,
+    // Reads a 1-byte count, pops that many pairs of a key and a value from the
+    // stack, and pushes a map of them, inserted from the lowest pair up.
+    MAP,
+    // Pops an index and an object from the stack, and pushes the element of
+    // the object at that index.
+    GET_INDEX,
+    // Pops a value, an index and an object from the stack, sets the element of
+    // the object at that index to the value, and pushes the value.
+    SET_INDEX,
+    // Pops 2 values from the stack, and pushes the string of the first as it
+    // would be printed, followed by that of the second.
+    CONCAT,
+    // Returns nil from the current function. This is the same as NIL followed
+    // by RETURN, which every function without an explicit return ends with.
+    RETURN_NIL,
+    // Like METHOD, but for an `extend` statement, where the class already
+    // exists. Fails with a TypeError if the value below the method is not a
+    // class. Subclasses that inherited the method being replaced, or that had
+    // no method with that name, get the new method too.
+    EXTEND
+}
+
+/// Returns true if `op` compares 2 values, and so can be used by
+/// [`ASSERT_INFIX`].
+pub fn is_comparison(op: u8) -> bool {
+    matches!(op, EQUAL | NOT_EQUAL | GREATER | GREATER_EQUAL | LESS | LESS_EQUAL | IS)
+}
+
+/// Returns the number of values that `op` pops from the stack, followed by the
+/// number that it pushes, or [`None`] if `op` is unknown. Ops that only peek at
+/// a value count as popping it and pushing it back.
+///
+/// `operands` are the bytes that follow `op`. They must include every operand
+/// of the op, since the effect of a call depends on its argument count.
+pub fn stack_effect(op: u8, operands: &[u8]) -> Option<(usize, usize)> {
+    let effect = match op {
+        CONSTANT | SMALL_INT | NIL | TRUE | FALSE | GET_LOCAL | GET_GLOBAL | GET_UPVALUE
+        | CLOSURE | CLASS => (0, 1),
+        POP | DEFINE_GLOBAL | ASSERT | PRINT | PRINT_SPACED | CLOSE_UPVALUE | RETURN | RETHROW
+        | JUMP_IF_FALSE_POP | JUMP_IF_TRUE_POP => (1, 0),
+        ASSERT_INFIX => (2, 0),
+        DUP => (1, 2),
+        SWAP => (2, 2),
+        ROT => (3, 3),
+        SET_LOCAL | SET_GLOBAL | SET_UPVALUE | GET_PROPERTY | NOT | NEGATE | JUMP_IF_FALSE => {
+            (1, 1)
+        }
+        SET_PROPERTY | GET_SUPER | EQUAL | NOT_EQUAL | GREATER | GREATER_EQUAL | LESS
+        | LESS_EQUAL | IS | ADD | SUBTRACT | MULTIPLY | DIVIDE | MODULO | POWER | INHERIT
+        | METHOD | EXTEND | GET_INDEX | CONCAT => (2, 1),
+        SET_INDEX => (3, 1),
+        // The value that TRY pushes is only there if its handler runs.
+        JUMP | LOOP | TRY | TRY_FINALLY | END_TRY | RETURN_NIL => (0, 0),
+        // The callee and its arguments are replaced by the return value.
+        CALL => (operands[0] as usize + 1, 1),
+        CALL_NATIVE => (operands[1] as usize, 1),
+        INVOKE => (operands[1] as usize + 1, 1),
+        // The superclass is popped as well.
+        SUPER_INVOKE => (operands[1] as usize + 2, 1),
+        LIST => (operands[0] as usize, 1),
+        MAP => (2 * operands[0] as usize, 1),
+        _ => return None,
+    };
+    Some(effect)
 }
@@ -0,0 +1,167 @@
+//! Roots for values held outside the VM, such as by a program that embeds it.
+//! The GC only sees values on the stack, in globals, and in the objects that
+//! they refer to, so any other value may be freed by the next collection,
+//! unless it is rooted.
+
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+use crate::vm::VM;
+use crate::vm::gc::Gc;
+use crate::vm::value::Value;
+
+/// The values that are rooted, shared between a [`Gc`] and its [`Root`]s.
+#[derive(Default)]
+pub(super) struct Roots(Rc<RefCell<RootList>>);
+
+/// Slots for rooted values, which are reused once their roots are dropped.
+#[derive(Default)]
+struct RootList {
+    values: Vec<Option<Value>>,
+    free: Vec<usize>,
+    /// Set when the [`Gc`] is dropped, after which rooted values have been
+    /// freed along with every other object.
+    is_dropped: bool,
+}
+
+impl RootList {
+    fn add(list: &Rc<RefCell<Self>>, value: Value) -> Root {
+        let mut borrowed = list.borrow_mut();
+        let idx = match borrowed.free.pop() {
+            Some(idx) => {
+                borrowed.values[idx] = Some(value);
+                idx
+            }
+            None => {
+                borrowed.values.push(Some(value));
+                borrowed.values.len() - 1
+            }
+        };
+        Root { list: Rc::clone(list), idx }
+    }
+}
+
+impl Debug for Roots {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let list = self.0.borrow();
+        f.debug_struct("Roots").field("len", &(list.values.len() - list.free.len())).finish()
+    }
+}
+
+impl Drop for Roots {
+    fn drop(&mut self) {
+        self.0.borrow_mut().is_dropped = true;
+    }
+}
+
+/// Keeps a value alive, however many collections run, until the root is
+/// dropped. Cloning a root roots the value again, so that it stays alive
+/// until every clone has been dropped.
+pub struct Root {
+    list: Rc<RefCell<RootList>>,
+    idx: usize,
+}
+
+impl Root {
+    /// The value that this root keeps alive.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the [`VM`] that rooted the value has been dropped, since the
+    /// value was freed along with it.
+    pub fn get(&self) -> Value {
+        let list = self.list.borrow();
+        assert!(!list.is_dropped, "rooted value outlived its VM");
+        list.values[self.idx].expect("root should hold a value")
+    }
+}
+
+impl Clone for Root {
+    fn clone(&self) -> Self {
+        RootList::add(&self.list, self.get())
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let mut list = self.list.borrow_mut();
+        list.values[self.idx] = None;
+        list.free.push(self.idx);
+    }
+}
+
+impl Debug for Root {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Root").field("idx", &self.idx).finish()
+    }
+}
+
+impl Gc {
+    /// Roots `value`, so that it is not freed while the returned [`Root`] is
+    /// alive.
+    pub fn root(&mut self, value: Value) -> Root {
+        RootList::add(&self.roots.0, value)
+    }
+
+    /// Marks the values that have been rooted with [`Gc::root`].
+    pub fn mark_roots(&mut self) {
+        let list = Rc::clone(&self.roots.0);
+        for &value in list.borrow().values.iter().flatten() {
+            self.mark(value);
+        }
+    }
+}
+
+impl VM {
+    /// Roots `value`, so that it is not freed while the returned [`Root`] is
+    /// alive. Values returned by the VM, like those of [`VM::eval`] and
+    /// [`VM::call_function`], must be rooted to be used after it runs again.
+    pub fn root(&mut self, value: Value) -> Root {
+        self.gc.root(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::vm::object::{ObjectFunction, ObjectType};
+
+    #[test]
+    fn roots() {
+        let mut gc = Gc::default();
+        let name = gc.alloc("f");
+        let function = gc.alloc(ObjectFunction::new(name, 0));
+        let root = gc.root(function.into());
+        let clone = root.clone();
+        let collect = |gc: &mut Gc| {
+            gc.mark_roots();
+            gc.trace();
+            gc.sweep();
+            gc.counts().get(ObjectType::Function).live
+        };
+
+        assert_eq!(collect(&mut gc), 1);
+        assert_eq!(root.get(), Value::from(function));
+        drop(root);
+        assert_eq!(collect(&mut gc), 1);
+        drop(clone);
+        assert_eq!(collect(&mut gc), 0);
+
+        // Slots are reused, so the list does not grow as values are rooted
+        // and dropped.
+        let _root = gc.root(Value::from(1.0));
+        assert_eq!(gc.roots.0.borrow().values.len(), 2);
+    }
+
+    #[test]
+    #[should_panic = "rooted value outlived its VM"]
+    fn root_outlives_gc() {
+        let mut gc = Gc::default();
+        let root = gc.root(Value::from(1.0));
+        drop(gc);
+        root.get();
+    }
+}
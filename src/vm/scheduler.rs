@@ -0,0 +1,141 @@
+use std::io::Write;
+use std::mem;
+
+use crate::error::{ErrorS, Result};
+use crate::vm::{Context, Step, VM, VmOptions};
+
+/// Runs several scripts in a single [`VM`], taking turns.
+///
+/// Each script has its own globals and stack, so scripts cannot see each
+/// other's variables, but they share a GC and the error classes. On each
+/// [`Scheduler::tick`], every script that is still running gets to run up to
+/// its fuel in ops before the next one takes over. Since a script only stops
+/// between ops, a long-running native still holds up the others.
+#[derive(Debug)]
+pub struct Scheduler {
+    vm: VM,
+    /// The fuel of each script, indexed by id. The state of each script is
+    /// kept in [`VM::contexts`], at the same index.
+    fuel: Vec<usize>,
+}
+
+impl Scheduler {
+    pub fn new(options: VmOptions) -> Self {
+        Self { vm: VM::new(options), fuel: Vec::new() }
+    }
+
+    /// Compiles `source` as a new script, which runs for up to `fuel` ops (at
+    /// least one) on each tick. Returns the id of the script.
+    pub fn load(&mut self, source: &str, fuel: usize) -> Result<usize, Vec<ErrorS>> {
+        let context = Context::new(&mut self.vm);
+        self.vm.contexts.push(context);
+        let id = self.vm.contexts.len() - 1;
+
+        self.switch(id);
+        let result = self.vm.load(source);
+        self.switch(id);
+
+        if let Err(e) = result {
+            self.vm.contexts.pop();
+            return Err(e);
+        }
+        self.fuel.push(fuel.max(1));
+        Ok(id)
+    }
+
+    /// Gives each running script its turn, in the order they were loaded.
+    /// Returns the id and result of each script that finished during this
+    /// tick.
+    pub fn tick(&mut self, stdout: &mut impl Write) -> Vec<(usize, Result<()>)> {
+        let mut finished = Vec::new();
+        for id in 0..self.fuel.len() {
+            if !self.is_running(id) {
+                continue;
+            }
+            self.switch(id);
+            let result = self.run(self.fuel[id], stdout);
+            self.switch(id);
+            if let Some(result) = result {
+                finished.push((id, result));
+            }
+        }
+        finished
+    }
+
+    /// Returns whether the script `id` has yet to finish.
+    pub fn is_running(&self, id: usize) -> bool {
+        self.vm.contexts[id].is_running
+    }
+
+    /// Returns whether every script has finished.
+    pub fn is_finished(&self) -> bool {
+        self.vm.contexts.iter().all(|context| !context.is_running)
+    }
+
+    /// The [`VM`] that runs the scripts. Its source includes the source of
+    /// every script, so the spans of their errors can be resolved against it.
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    /// Runs up to `fuel` ops of the current script, and returns its result if
+    /// it finished.
+    fn run(&mut self, fuel: usize, stdout: &mut impl Write) -> Option<Result<()>> {
+        for _ in 0..fuel {
+            match self.vm.step(stdout) {
+                Ok(Step::Running) => {}
+                Ok(Step::Finished(_)) => return Some(Ok(())),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+
+    /// Swaps the state of the script `id` into the [`VM`], or back out of it.
+    fn switch(&mut self, id: usize) {
+        let mut contexts = mem::take(&mut self.vm.contexts);
+        self.vm.swap_context(&mut contexts[id]);
+        self.vm.contexts = contexts;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::error::{Error, NameError};
+
+    #[test]
+    fn round_robin() {
+        let mut scheduler = Scheduler::new(VmOptions::default());
+        let a = scheduler
+            .load("var name = \"a\"; for (var i = 0; i < 3; i = i + 1) print name + \"\";", 8)
+            .unwrap();
+        let b = scheduler
+            .load("var name = \"b\"; for (var i = 0; i < 2; i = i + 1) print name + \"\";", 8)
+            .unwrap();
+        let c = scheduler.load("print name;", 8).unwrap();
+
+        let mut stdout = Vec::new();
+        let mut finished = Vec::new();
+        while !scheduler.is_finished() {
+            finished.extend(
+                scheduler
+                    .tick(&mut stdout)
+                    .into_iter()
+                    .map(|(id, result)| (id, result.map_err(|(e, _)| e))),
+            );
+        }
+
+        assert_eq!(String::from_utf8(stdout).unwrap(), "a\nb\na\nb\na\n");
+        assert_eq!(
+            finished,
+            [
+                (c, Err(Error::NameError(NameError::NotDefined { name: "name".to_string() }))),
+                (b, Ok(())),
+                (a, Ok(())),
+            ]
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! A binary format for compiled programs, so that they can be run again
+//! without being compiled again.
+//!
+//! Numbers are little-endian. A program starts with [`MAGIC`], followed by the
+//! version of loxcraft that compiled it, as a length-prefixed string, and the
+//! [`source_hash`] of its source. Then comes the script, which is encoded like
+//! any other function:
+//! - its name, as a length-prefixed string.
+//! - its arity (`u8`), upvalue count (`u16`) and maximum stack size (`u32`).
+//! - its entry points, for calls that leave out parameters with default
+//!   values, prefixed by their count (`u8`). Each is an offset (`u32`).
+//! - its ops, prefixed by their length (`u32`).
+//! - the spans of its ops, as runs of identical spans, prefixed by the number
+//!   of runs (`u32`). Each run is a start, an end and a count (all `u32`).
+//! - its safe points, the indices of the ops that start a statement, in
+//!   increasing order and prefixed by their count (all `u32`).
+//! - its named locals, prefixed by their count (`u32`). Each is a
+//!   length-prefixed name, a slot (`u8`), and the start and end of the ops
+//!   that it is in scope for (both `u32`).
+//! - the names of its upvalues, as length-prefixed strings prefixed by their
+//!   count (`u16`).
+//! - its constants, prefixed by their count (`u16`). Each is a tag byte,
+//!   followed by a number (`f64`), a length-prefixed string, or a function.
+//!
+//! Decoding checks that the format is well-formed, but not that the bytecode
+//! is safe to run, which is left to [`verify`](crate::vm::verify).
+
+use std::hash::{Hash, Hasher};
+use std::str;
+
+use rustc_hash::FxHasher;
+
+use crate::error::BytecodeError;
+use crate::syntax::ast::Name;
+use crate::types::Span;
+use crate::vm::chunk::LocalInfo;
+use crate::vm::gc::Gc;
+use crate::vm::object::{ObjectFunction, ObjectType};
+use crate::vm::value::{Value, ValueType};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+
+/// The version of loxcraft, which is recorded in every program. Programs
+/// compiled by other versions are rejected, since their bytecode may differ.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+/// Functions nested deeper than this are rejected, so that a malformed
+/// program cannot overflow the stack of the decoder.
+const MAX_NESTING: usize = 256;
+
+type Result<T, E = BytecodeError> = std::result::Result<T, E>;
+
+/// The metadata at the start of every program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    /// The version of loxcraft that compiled the program.
+    pub version: String,
+    /// The [`source_hash`] of the source it was compiled from.
+    pub source_hash: u64,
+}
+
+/// Returns a hash of `source`, which is recorded in the programs compiled from
+/// it.
+pub fn source_hash(source: &str) -> u64 {
+    let mut hasher = FxHasher::default();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Encodes a script that was compiled from `source`.
+pub fn encode(script: &ObjectFunction, source: &str) -> Vec<u8> {
+    let mut output = MAGIC.to_vec();
+    write_str(&mut output, VERSION);
+    output.extend(source_hash(source).to_le_bytes());
+    write_function(&mut output, script);
+    output
+}
+
+fn write_function(output: &mut Vec<u8>, function: &ObjectFunction) {
+    write_str(output, unsafe { (*function.name).value });
+    output.push(function.arity);
+    output.extend(function.upvalue_count.to_le_bytes());
+    output.extend((function.max_stack as u32).to_le_bytes());
+    output.push(function.entries.len() as u8);
+    for &entry in &function.entries {
+        output.extend((entry as u32).to_le_bytes());
+    }
+
+    let chunk = &function.chunk;
+    output.extend((chunk.ops.len() as u32).to_le_bytes());
+    output.extend(&chunk.ops);
+
+    let mut runs = Vec::<(&Span, u32)>::new();
+    for span in (0..chunk.ops.len()).filter_map(|idx| chunk.span_at(idx)) {
+        match runs.last_mut() {
+            Some((last, count)) if *last == span => *count += 1,
+            _ => runs.push((span, 1)),
+        }
+    }
+    output.extend((runs.len() as u32).to_le_bytes());
+    for (span, count) in runs {
+        output.extend((span.start as u32).to_le_bytes());
+        output.extend((span.end as u32).to_le_bytes());
+        output.extend(count.to_le_bytes());
+    }
+
+    output.extend((chunk.safe_points.len() as u32).to_le_bytes());
+    for &idx in &chunk.safe_points {
+        output.extend((idx as u32).to_le_bytes());
+    }
+
+    output.extend((chunk.locals.len() as u32).to_le_bytes());
+    for local in &chunk.locals {
+        write_str(output, local.name.as_str());
+        output.push(local.slot);
+        output.extend((local.ops.start as u32).to_le_bytes());
+        output.extend((local.ops.end as u32).to_le_bytes());
+    }
+    output.extend((chunk.upvalue_names.len() as u16).to_le_bytes());
+    for name in &chunk.upvalue_names {
+        write_str(output, name.as_str());
+    }
+
+    output.extend((chunk.constants.len() as u16).to_le_bytes());
+    for &constant in &chunk.constants {
+        match constant.type_() {
+            ValueType::Number => {
+                output.push(TAG_NUMBER);
+                output.extend(constant.as_number().to_le_bytes());
+            }
+            ValueType::Object(ObjectType::String) => {
+                output.push(TAG_STRING);
+                write_str(output, unsafe { (*constant.as_object().string).value });
+            }
+            ValueType::Object(ObjectType::Function) => {
+                output.push(TAG_FUNCTION);
+                write_function(output, unsafe { &*constant.as_object().function });
+            }
+            type_ => unreachable!("constant of type {type_} cannot be encoded"),
+        }
+    }
+}
+
+fn write_str(output: &mut Vec<u8>, string: &str) {
+    output.extend((string.len() as u32).to_le_bytes());
+    output.extend(string.as_bytes());
+}
+
+/// Decodes a script that was compiled from `source`, allocating it in `gc`.
+/// Its spans are moved forward by `offset`, for a [`VM`](crate::vm::VM) that
+/// has already run other source.
+pub fn decode(
+    bytes: &[u8],
+    source: &str,
+    gc: &mut Gc,
+    offset: usize,
+) -> Result<*mut ObjectFunction> {
+    let mut reader = Reader { bytes, source_len: source.len(), offset };
+    let header = reader.header()?;
+    if header.version != VERSION {
+        return Err(BytecodeError::VersionMismatch { version: header.version });
+    }
+    if header.source_hash != source_hash(source) {
+        return Err(BytecodeError::SourceMismatch);
+    }
+    reader.script(gc)
+}
+
+/// Decodes the header of a program, without the script that follows it.
+pub fn decode_header(bytes: &[u8]) -> Result<Header> {
+    Reader { bytes, source_len: 0, offset: 0 }.header()
+}
+
+/// Decodes a script like [`decode`], for when its source is not at hand. The
+/// version of loxcraft that compiled it is still checked, but its spans may
+/// point past the end of the source that the [`VM`](crate::vm::VM) has.
+pub fn decode_without_source(
+    bytes: &[u8],
+    gc: &mut Gc,
+    offset: usize,
+) -> Result<*mut ObjectFunction> {
+    let mut reader = Reader { bytes, source_len: usize::MAX, offset };
+    let header = reader.header()?;
+    if header.version != VERSION {
+        return Err(BytecodeError::VersionMismatch { version: header.version });
+    }
+    reader.script(gc)
+}
+
+/// Decodes a program without checking which version of loxcraft compiled it,
+/// or which source it was compiled from, so that tools can inspect it. Since
+/// there is no source, spans are not checked either.
+pub fn decode_unchecked(bytes: &[u8], gc: &mut Gc) -> Result<(Header, *mut ObjectFunction)> {
+    let mut reader = Reader { bytes, source_len: usize::MAX, offset: 0 };
+    let header = reader.header()?;
+    Ok((header, reader.script(gc)?))
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    source_len: usize,
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn header(&mut self) -> Result<Header> {
+        if self.take(MAGIC.len())? != MAGIC {
+            return Err(BytecodeError::Malformed);
+        }
+        let version = self.str()?.to_string();
+        Ok(Header { version, source_hash: self.u64()? })
+    }
+
+    /// Reads the script, which must be the rest of the program.
+    fn script(&mut self, gc: &mut Gc) -> Result<*mut ObjectFunction> {
+        let script = self.function(gc, 0)?;
+        if !self.bytes.is_empty() {
+            return Err(BytecodeError::Malformed);
+        }
+        Ok(script)
+    }
+
+    fn function(&mut self, gc: &mut Gc, nesting: usize) -> Result<*mut ObjectFunction> {
+        if nesting > MAX_NESTING {
+            return Err(BytecodeError::Malformed);
+        }
+        let name = gc.alloc(self.str()?);
+        let function = gc.alloc(ObjectFunction::new(name, self.u8()?));
+        let function = unsafe { &mut *function };
+        function.upvalue_count = self.u16()?;
+        function.max_stack = self.u32()? as usize;
+        // There is an entry point for each parameter with a default value.
+        let entries = self.u8()? as usize;
+        if entries > function.arity as usize {
+            return Err(BytecodeError::Malformed);
+        }
+        for _ in 0..entries {
+            function.entries.push(self.u32()? as usize);
+        }
+
+        let len = self.u32()? as usize;
+        let ops = self.take(len)?;
+        let mut ops = ops.iter();
+        for _ in 0..self.u32()? {
+            let (start, end) = (self.u32()? as usize, self.u32()? as usize);
+            if start > end || end > self.source_len {
+                return Err(BytecodeError::Malformed);
+            }
+            let span = start + self.offset..end + self.offset;
+            for _ in 0..self.u32()? {
+                let &op = ops.next().ok_or(BytecodeError::Malformed)?;
+                function.chunk.write_u8(op, &span);
+            }
+        }
+        if ops.next().is_some() {
+            return Err(BytecodeError::Malformed);
+        }
+
+        for _ in 0..self.u32()? {
+            let idx = self.u32()? as usize;
+            let chunk = &mut function.chunk;
+            if idx >= chunk.ops.len() || chunk.safe_points.last().is_some_and(|&last| idx <= last) {
+                return Err(BytecodeError::Malformed);
+            }
+            chunk.safe_points.push(idx);
+        }
+
+        for _ in 0..self.u32()? {
+            let name = Name::new(self.str()?);
+            let slot = self.u8()?;
+            let (start, end) = (self.u32()? as usize, self.u32()? as usize);
+            if start > end || end > function.chunk.ops.len() {
+                return Err(BytecodeError::Malformed);
+            }
+            function.chunk.locals.push(LocalInfo { name, slot, ops: start..end });
+        }
+        for _ in 0..self.u16()? {
+            let name = Name::new(self.str()?);
+            function.chunk.upvalue_names.push(name);
+        }
+
+        for _ in 0..self.u16()? {
+            let value = match self.u8()? {
+                TAG_NUMBER => {
+                    // A NaN with an arbitrary payload could look like a boxed
+                    // object, so only the canonical NaN is allowed in.
+                    let number = f64::from_bits(self.u64()?);
+                    Value::from(if number.is_nan() { f64::NAN } else { number })
+                }
+                TAG_STRING => {
+                    let string = gc.alloc(self.str()?);
+                    unsafe { (*string).common.is_marked = true };
+                    Value::from(string)
+                }
+                TAG_FUNCTION => Value::from(self.function(gc, nesting + 1)?),
+                _ => return Err(BytecodeError::Malformed),
+            };
+            function.chunk.constants.try_push(value).map_err(|_| BytecodeError::Malformed)?;
+        }
+        Ok(function)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() {
+            return Err(BytecodeError::Malformed);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        let len = self.u32()? as usize;
+        str::from_utf8(self.take(len)?).map_err(|_| BytecodeError::Malformed)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().expect("slice should have 2 bytes")))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().expect("slice should have 4 bytes")))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().expect("slice should have 8 bytes")))
+    }
+}
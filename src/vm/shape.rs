@@ -0,0 +1,81 @@
+//! Shapes, or hidden classes, which let instances keep their fields in a
+//! [`Vec`] of slots instead of a map of their own. Instances that add the same
+//! fields in the same order, like those created by the same initializer,
+//! share a shape, which gives the name of each slot.
+
+use std::cell::RefCell;
+use std::hash::BuildHasherDefault;
+use std::rc::Rc;
+
+use hashbrown::HashMap;
+use rustc_hash::FxHasher;
+
+use crate::vm::gc::Gc;
+use crate::vm::object::ObjectString;
+
+/// The most fields that a shape has. Fields added to an instance beyond this
+/// are kept in a map, so that an instance that keeps adding new fields does
+/// not grow its class's tree of shapes without bound.
+pub const MAX_SLOTS: usize = 32;
+
+/// The most fields that a shape finds by comparing each name. Beyond this,
+/// hashing the name is faster, so the shape keeps an index of its slots.
+const SCAN_LEN: usize = 8;
+
+/// The names of the fields in an instance, in the order in which they were
+/// added. Each class has an empty shape, at the root of a tree in which each
+/// shape leads to the shapes that add one more field to it.
+#[derive(Debug, Default)]
+pub struct Shape {
+    names: Vec<*mut ObjectString>,
+    /// The slot of each name, if there are more than [`SCAN_LEN`].
+    index: Option<HashMap<*mut ObjectString, usize, BuildHasherDefault<FxHasher>>>,
+    transitions: RefCell<HashMap<*mut ObjectString, Rc<Shape>, BuildHasherDefault<FxHasher>>>,
+}
+
+impl Shape {
+    /// The slot that holds the field `name`.
+    pub fn slot(&self, name: *mut ObjectString) -> Option<usize> {
+        match &self.index {
+            Some(index) => index.get(&name).copied(),
+            None => self.names.iter().position(|&slot_name| slot_name == name),
+        }
+    }
+
+    /// The name of the field in each slot.
+    pub fn names(&self) -> &[*mut ObjectString] {
+        &self.names
+    }
+
+    /// Returns the shape with `name` added after the fields of this one, or
+    /// [`None`] if this shape already has [`MAX_SLOTS`] fields. The shape is
+    /// created the first time, and shared after that.
+    pub fn with(self: &Rc<Self>, name: *mut ObjectString) -> Option<Rc<Self>> {
+        if self.names.len() >= MAX_SLOTS {
+            return None;
+        }
+        let mut transitions = self.transitions.borrow_mut();
+        let shape = transitions.entry(name).or_insert_with(|| {
+            let mut names = Vec::with_capacity(self.names.len() + 1);
+            names.extend_from_slice(&self.names);
+            names.push(name);
+            let index = (names.len() > SCAN_LEN)
+                .then(|| names.iter().enumerate().map(|(slot, &name)| (name, slot)).collect());
+            Rc::new(Self { names, index, transitions: RefCell::default() })
+        });
+        Some(Rc::clone(shape))
+    }
+
+    /// Marks the names in this shape and in every shape that it leads to.
+    /// Names are compared by address, so the names of shapes that no instance
+    /// has yet must not be freed either, or a new string could take the
+    /// place of one.
+    pub fn mark(&self, gc: &mut Gc) {
+        for &name in &self.names {
+            gc.mark(name);
+        }
+        for shape in self.transitions.borrow().values() {
+            shape.mark(gc);
+        }
+    }
+}
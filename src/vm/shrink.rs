@@ -0,0 +1,209 @@
+use std::hash::BuildHasherDefault;
+
+use hashbrown::{HashMap, HashSet};
+use rustc_hash::FxHasher;
+
+use crate::syntax::ast::{Expr, ExprS, Program, Stmt, StmtFun, StmtS};
+
+/// Globals that are used from outside the program, and so are always live.
+const ROOTS: &[&str] = &["main"];
+
+/// Returns whether each top-level statement of `program` should be compiled.
+/// Functions and classes declared at the top level are dead if nothing else
+/// in the program can refer to them, directly or through other live
+/// declarations.
+///
+/// Any use of a name counts as a reference to the global, even if a local
+/// shadows it there, so this never removes a declaration that is used. It
+/// does assume that no other program will run afterwards in the same
+/// [`VM`](crate::vm::VM), since that could refer to the removed globals too.
+pub fn live_stmts(program: &Program) -> Vec<bool> {
+    let mut decls = HashMap::<_, Vec<_>, BuildHasherDefault<FxHasher>>::default();
+    for (idx, (stmt, _)) in program.stmts.iter().enumerate() {
+        match stmt {
+            Stmt::Class(class) => decls.entry(class.name.as_str()).or_default().push(idx),
+            Stmt::Fun(fun) => decls.entry(fun.name.as_str()).or_default().push(idx),
+            _ => {}
+        }
+    }
+
+    // Declarations are only walked once they are known to be live.
+    let is_decl = |stmt: &Stmt| matches!(stmt, Stmt::Class(_) | Stmt::Fun(_));
+    let mut is_live = program.stmts.iter().map(|(stmt, _)| !is_decl(stmt)).collect::<Vec<_>>();
+    let mut names = Names { pending: ROOTS.to_vec(), assigned: Vec::new() };
+    for (stmt, _) in program.stmts.iter().zip(&is_live).filter(|(_, &is_live)| is_live) {
+        names.stmt(stmt);
+    }
+
+    let mut visited = HashSet::<_, BuildHasherDefault<FxHasher>>::default();
+    while let Some(name) = names.pending.pop() {
+        if !visited.insert(name) {
+            continue;
+        }
+        for &idx in decls.get(name).into_iter().flatten() {
+            is_live[idx] = true;
+            names.stmt(&program.stmts[idx]);
+        }
+    }
+    is_live
+}
+
+/// Returns the names of the globals that `program` may define or assign to.
+/// Like [`live_stmts`], this counts assignments to locals with the same name
+/// as a global.
+pub fn assigned_globals(program: &Program) -> HashSet<&str, BuildHasherDefault<FxHasher>> {
+    let mut names = Names { pending: Vec::new(), assigned: Vec::new() };
+    for stmt in &program.stmts {
+        match &stmt.0 {
+            Stmt::Class(class) => names.assigned.push(&class.name),
+            Stmt::Fun(fun) => names.assigned.push(&fun.name),
+            Stmt::Var(var) => names.assigned.push(&var.var.name),
+            _ => {}
+        }
+        names.stmt(stmt);
+    }
+    names.assigned.into_iter().collect()
+}
+
+/// Collects the names used by statements and expressions.
+struct Names<'a> {
+    /// Names that have been used, but whose declarations may not have been
+    /// walked yet.
+    pending: Vec<&'a str>,
+    /// Names that have been assigned to.
+    assigned: Vec<&'a str>,
+}
+
+impl<'a> Names<'a> {
+    fn stmt(&mut self, (stmt, _): &'a StmtS) {
+        match stmt {
+            Stmt::Assert(assert) => self.expr(&assert.value),
+            Stmt::Block(block) => block.stmts.iter().for_each(|stmt| self.stmt(stmt)),
+            Stmt::Class(class) => {
+                class.super_.iter().for_each(|super_| self.expr(super_));
+                class.methods.iter().for_each(|(method, _)| self.fun(method));
+            }
+            Stmt::Expr(expr) => self.expr(&expr.value),
+            Stmt::Extend(extend) => {
+                self.expr(&extend.class);
+                extend.methods.iter().for_each(|(method, _)| self.fun(method));
+            }
+            Stmt::For(for_) => {
+                for_.init.iter().for_each(|init| self.stmt(init));
+                for_.cond.iter().chain(&for_.incr).for_each(|expr| self.expr(expr));
+                self.stmt(&for_.body);
+            }
+            Stmt::Fun(fun) => self.fun(fun),
+            Stmt::If(if_) => {
+                self.expr(&if_.cond);
+                self.stmt(&if_.then);
+                if_.else_.iter().for_each(|else_| self.stmt(else_));
+            }
+            Stmt::Print(print) => print.values.iter().for_each(|value| self.expr(value)),
+            Stmt::Return(return_) => return_.value.iter().for_each(|value| self.expr(value)),
+            Stmt::Try(try_) => {
+                self.stmt(&try_.body);
+                try_.catch.iter().for_each(|catch| self.stmt(&catch.body));
+                try_.finally.iter().for_each(|finally| self.stmt(finally));
+            }
+            Stmt::Var(var) => var.value.iter().for_each(|value| self.expr(value)),
+            Stmt::While(while_) => {
+                self.expr(&while_.cond);
+                self.stmt(&while_.body);
+            }
+            Stmt::Error => {}
+        }
+    }
+
+    fn fun(&mut self, fun: &'a StmtFun) {
+        fun.defaults.iter().for_each(|default| self.expr(default));
+        fun.body.stmts.iter().for_each(|stmt| self.stmt(stmt));
+    }
+
+    fn expr(&mut self, (expr, _): &'a ExprS) {
+        match expr {
+            Expr::Assign(assign) => {
+                self.assigned.push(&assign.var.name);
+                self.name(&assign.var.name);
+                self.expr(&assign.value);
+            }
+            Expr::Call(call) => {
+                self.expr(&call.callee);
+                call.args.iter().for_each(|arg| self.expr(arg));
+            }
+            Expr::Fun(fun) => self.fun(fun),
+            Expr::Get(get) => self.expr(&get.object),
+            Expr::Index(index) => {
+                self.expr(&index.object);
+                self.expr(&index.index);
+            }
+            Expr::Infix(infix) => {
+                self.expr(&infix.lt);
+                self.expr(&infix.rt);
+            }
+            Expr::List(list) => list.elements.iter().for_each(|element| self.expr(element)),
+            Expr::Literal(_) | Expr::Missing | Expr::Super(_) => {}
+            Expr::Map(map) => map.entries.iter().for_each(|(key, value)| {
+                self.expr(key);
+                self.expr(value);
+            }),
+            Expr::Prefix(prefix) => self.expr(&prefix.rt),
+            Expr::Set(set) => {
+                self.expr(&set.object);
+                self.expr(&set.value);
+            }
+            Expr::SetIndex(set) => {
+                self.expr(&set.object);
+                self.expr(&set.index);
+                self.expr(&set.value);
+            }
+            Expr::Var(var) => self.name(&var.var.name),
+        }
+    }
+
+    fn name(&mut self, name: &'a str) {
+        self.pending.push(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    fn live_stmts(source: &str) -> Vec<bool> {
+        super::live_stmts(&crate::syntax::parse(source, 0).unwrap())
+    }
+
+    #[test]
+    fn live_stmts_through_declarations() {
+        let source = r#"
+            fun unused() { used(); }
+            fun used() { return Used(); }
+            class Base {}
+            class Used < Base {}
+            class Unused {}
+            print used();
+        "#;
+        assert_eq!(live_stmts(source), [false, true, true, true, false, true]);
+    }
+
+    #[test]
+    fn assigned_globals() {
+        let source = "var a; fun b() { c = 1; } { var d; e = 2; } print f;";
+        let program = crate::syntax::parse(source, 0).unwrap();
+        let mut names = super::assigned_globals(&program).into_iter().collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, ["a", "b", "c", "e"]);
+    }
+
+    #[test]
+    fn live_stmts_roots() {
+        let source = "fun main() { helper(); } fun helper() {} fun unused() {}";
+        assert_eq!(live_stmts(source), [true, true, false]);
+
+        // Assigning to a global needs it to be defined. Uses of a local with
+        // the same name as a global count too.
+        let source = "fun f() {} fun g() {} { var g = 1; print g; } f = nil;";
+        assert_eq!(live_stmts(source), [true, true, true, true]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::hash::BuildHasherDefault;
+
+use hashbrown::{HashMap, HashSet};
+use indexmap::IndexMap;
+use rustc_hash::FxHasher;
+
+use crate::vm::VM;
+use crate::vm::gc::Gc;
+use crate::vm::object::{
+    Object, ObjectBoundMethod, ObjectClosure, ObjectCommon, ObjectInstance, ObjectList, ObjectMap,
+    ObjectString, ObjectType, ObjectUpvalue,
+};
+use crate::vm::value::Value;
+
+/// A copy of the globals of a [`VM`], taken by [`VM::snapshot_globals`].
+/// This is only a handle: the copy itself is kept by the [`VM`], so that the
+/// GC can see it, until [`VM::drop_snapshot`] is called.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Snapshot(u64);
+
+/// The state kept for a [`Snapshot`].
+#[derive(Debug)]
+pub(super) struct SavedGlobals {
+    id: u64,
+    globals: IndexMap<*mut ObjectString, Value, BuildHasherDefault<FxHasher>>,
+    const_globals: HashSet<String, BuildHasherDefault<FxHasher>>,
+}
+
+impl SavedGlobals {
+    pub(super) fn mark(&self, gc: &mut Gc) {
+        for (&name, &value) in &self.globals {
+            gc.mark(name);
+            gc.mark(value);
+        }
+    }
+}
+
+impl VM {
+    /// Saves a copy of the globals, which [`VM::restore`] can bring back
+    /// later, e.g. to undo a definition. Every object reachable from the
+    /// globals is copied, so changes to lists, maps, instances and captured
+    /// variables after this are undone too. Strings, functions and natives
+    /// cannot change once they are created, so they are shared, and so are
+    /// foreign objects, which cannot be copied. Classes are shared as well,
+    /// since instances and subclasses refer to them, so methods added to a
+    /// class by `extend` after this are kept.
+    pub fn snapshot_globals(&mut self) -> Snapshot {
+        let id = self.next_snapshot_id;
+        self.next_snapshot_id += 1;
+        let mut copier = Copier::new(&mut self.gc);
+        let globals =
+            self.globals.iter().map(|(&name, &value)| (name, copier.copy_value(value))).collect();
+        self.snapshots.push(SavedGlobals {
+            id,
+            globals,
+            const_globals: self.const_globals.clone(),
+        });
+        Snapshot(id)
+    }
+
+    /// Replaces the globals with those saved in `snapshot`. The snapshot is
+    /// copied again, so it can be restored more than once. Returns false if
+    /// it was dropped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a script is running.
+    pub fn restore(&mut self, snapshot: Snapshot) -> bool {
+        assert!(!self.is_running, "cannot restore globals while a script is running");
+        let Some(saved) = self.snapshots.iter().find(|saved| saved.id == snapshot.0) else {
+            return false;
+        };
+        let mut copier = Copier::new(&mut self.gc);
+        self.globals =
+            saved.globals.iter().map(|(&name, &value)| (name, copier.copy_value(value))).collect();
+        self.const_globals.clone_from(&saved.const_globals);
+        true
+    }
+
+    /// Forgets `snapshot`, so that the objects it kept can be collected.
+    pub fn drop_snapshot(&mut self, snapshot: Snapshot) {
+        self.snapshots.retain(|saved| saved.id != snapshot.0);
+    }
+}
+
+/// Copies a graph of objects, keeping track of the objects that have already
+/// been copied, so that objects that are shared or that contain themselves
+/// are shared in the copy too.
+struct Copier<'a> {
+    gc: &'a mut Gc,
+    copies: HashMap<*mut ObjectCommon, Object, BuildHasherDefault<FxHasher>>,
+}
+
+impl<'a> Copier<'a> {
+    /// Objects are allocated without running the GC, which would not see the
+    /// copies that are not yet reachable from the globals.
+    fn new(gc: &'a mut Gc) -> Self {
+        Self { gc, copies: HashMap::default() }
+    }
+
+    fn copy_value(&mut self, value: Value) -> Value {
+        if value.is_object() { Value::from(self.copy_object(value.as_object())) } else { value }
+    }
+
+    fn copy_object(&mut self, object: Object) -> Object {
+        if let Some(&copy) = self.copies.get(&unsafe { object.common }) {
+            return copy;
+        }
+        match object.type_() {
+            ObjectType::Class
+            | ObjectType::Foreign
+            | ObjectType::Function
+            | ObjectType::Native
+            | ObjectType::String => object,
+            ObjectType::BoundMethod => {
+                let bound_method = unsafe { &*object.bound_method };
+                let this = unsafe { self.copy_object(Object::from(bound_method.this)).instance };
+                let closure =
+                    unsafe { self.copy_object(Object::from(bound_method.closure)).closure };
+                let copy = self.gc.alloc(ObjectBoundMethod::new(this, closure));
+                self.insert(object, copy)
+            }
+            ObjectType::Closure => {
+                let closure = unsafe { &*object.closure };
+                // Without upvalues, a closure cannot change.
+                if closure.upvalues.is_empty() {
+                    return object;
+                }
+                let copy = self.gc.alloc(ObjectClosure::new(closure.function, Vec::new()));
+                self.insert(object, copy);
+                let upvalues = closure
+                    .upvalues
+                    .iter()
+                    .map(|&upvalue| unsafe { self.copy_object(Object::from(upvalue)).upvalue })
+                    .collect();
+                unsafe { (*copy).upvalues = upvalues };
+                Object::from(copy)
+            }
+            ObjectType::Instance => {
+                let instance = unsafe { &*object.instance };
+                let copy = self.gc.alloc(ObjectInstance::new(instance.class));
+                self.insert(object, copy);
+                for (name, value) in instance.fields() {
+                    let value = self.copy_value(value);
+                    unsafe { (*copy).set(name, value) };
+                }
+                Object::from(copy)
+            }
+            ObjectType::List => {
+                let copy = self.gc.alloc(ObjectList::new(Vec::new()));
+                self.insert(object, copy);
+                for &element in unsafe { &(*object.list).elements } {
+                    let element = self.copy_value(element);
+                    unsafe { (*copy).elements.push(element) };
+                }
+                Object::from(copy)
+            }
+            ObjectType::Map => {
+                let copy = self.gc.alloc(ObjectMap::new());
+                self.insert(object, copy);
+                for &(key, value) in unsafe { &(*object.map).entries } {
+                    let (key, value) = (self.copy_value(key), self.copy_value(value));
+                    unsafe { (*copy).insert(key, value) };
+                }
+                Object::from(copy)
+            }
+            ObjectType::Upvalue => {
+                // An open upvalue is copied as a closed one, since the stack
+                // slot it points to does not belong to the copy.
+                let copy = self.gc.alloc(ObjectUpvalue::new(std::ptr::null_mut()));
+                self.insert(object, copy);
+                let value = self.copy_value(unsafe { *(*object.upvalue).location });
+                unsafe {
+                    (*copy).closed = value;
+                    (*copy).location = &mut (*copy).closed;
+                }
+                Object::from(copy)
+            }
+        }
+    }
+
+    /// Records `copy` as the copy of `object`, before the objects it points
+    /// to are copied, in case they point back to it.
+    fn insert(&mut self, object: Object, copy: impl Into<Object>) -> Object {
+        let copy = copy.into();
+        self.copies.insert(unsafe { object.common }, copy);
+        copy
+    }
+}
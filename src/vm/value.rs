@@ -2,12 +2,13 @@ use std::fmt::{self, Debug, Display, Formatter};
 use std::mem;
 use std::ops::Not;
 
+use crate::number;
 use crate::vm::object::{Object, ObjectType};
 use crate::vm::util;
 
 const _: () = assert!(mem::size_of::<Value>() == 8);
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
 pub struct Value(u64);
 
 impl Default for Value {
@@ -31,7 +32,7 @@ impl Display for Value {
         } else if self.is_false() {
             write!(f, "false")
         } else if self.is_number() {
-            write!(f, "{}", self.as_number())
+            write!(f, "{}", number::display(self.as_number(), None))
         } else if self.is_object() {
             write!(f, "{}", self.as_object())
         } else {
@@ -40,6 +41,18 @@ impl Display for Value {
     }
 }
 
+impl Value {
+    /// Formats the value as it is shown inside a list or a map, where strings
+    /// are quoted.
+    pub fn repr(&self) -> String {
+        if self.is_object() && self.as_object().type_() == ObjectType::String {
+            format!("{:?}", unsafe { (*self.as_object().string).value })
+        } else {
+            self.to_string()
+        }
+    }
+}
+
 impl From<bool> for Value {
     fn from(value: bool) -> Self {
         Self(value as u64 | Self::FALSE.0)
@@ -130,6 +143,12 @@ impl Value {
         Object { common: (self.0 & !(Self::SIGN_BIT | Self::QNAN)) as _ }
     }
 
+    /// The bits that encode the value, which are the same for values that
+    /// `==` finds equal.
+    pub const fn to_bits(self) -> u64 {
+        self.0
+    }
+
     pub const fn to_bool(self) -> bool {
         !matches!(self, Self::FALSE | Self::NIL)
     }
@@ -0,0 +1,472 @@
+use crate::error::{BytecodeError, ErrorS, Result};
+use crate::vm::object::{Native, ObjectFunction, ObjectType};
+use crate::vm::value::Value;
+use crate::vm::{STACK_MAX_PER_FRAME, op};
+
+/// Checks that the bytecode of a script, and of every function nested in it,
+/// is safe to run.
+///
+/// The [`VM`](crate::vm::VM) trusts its bytecode: operands are read without
+/// bounds checks, and the stack is never checked for underflow. The compiler
+/// always produces bytecode that upholds these invariants, but bytecode from
+/// anywhere else should be verified first. Verification checks that:
+/// - every op is known, and has all of its operands.
+/// - constant indices are in bounds, and names refer to strings.
+/// - jumps and entry points land on the start of an op.
+/// - local slots and upvalue indices are in bounds.
+/// - every path through a function has the same stack depth at each op, never
+///   underflows the frame or grows past its declared maximum, and ends in a
+///   return.
+///
+/// It does not check the types of values on the stack, which are checked at
+/// runtime instead.
+pub fn verify(script: &ObjectFunction) -> Result<()> {
+    verify_function(script, 0)
+}
+
+/// Verifies a script that is called like a function, with its arguments on
+/// its frame, such as one compiled by [`VM::eval_in_frame`](crate::vm::VM::eval_in_frame).
+pub(super) fn verify_callable(script: &ObjectFunction) -> Result<()> {
+    verify_function(script, script.arity as usize + 1)
+}
+
+/// Verifies a function whose frame starts out with `frame_size` slots: none
+/// for a script, or the callee and its arguments for a function. The frame
+/// may not grow past [`ObjectFunction::max_stack`].
+fn verify_function(function: &ObjectFunction, frame_size: usize) -> Result<()> {
+    check_function(function, frame_size, function.max_stack.min(STACK_MAX_PER_FRAME))?;
+    for constant in &function.chunk.constants {
+        if constant.type_() == ObjectType::Function.into() {
+            let function = unsafe { &*constant.as_object().function };
+            verify_function(function, function.arity as usize + 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the largest number of values that the frame of `function` ever
+/// holds, where `frame_size` is as in [`verify_function`]. The bytecode of
+/// `function` is checked along the way, but not that of the functions nested
+/// in it.
+pub(super) fn max_stack(function: &ObjectFunction, frame_size: usize) -> Result<usize> {
+    check_function(function, frame_size, usize::MAX)
+}
+
+/// Checks the bytecode of `function`, without descending into the functions
+/// nested in it, and returns the largest stack depth it reaches. Fails if that
+/// would exceed `max`.
+fn check_function(function: &ObjectFunction, frame_size: usize, max: usize) -> Result<usize> {
+    let ops = &function.chunk.ops;
+
+    // Find the start of every op, so that jumps can be checked against them.
+    let mut is_op_start = vec![false; ops.len()];
+    let mut offset = 0;
+    while offset < ops.len() {
+        is_op_start[offset] = true;
+        offset += op_len(function, offset)?;
+    }
+
+    // Follow every path through the function, from each of its entry points,
+    // recording the stack depth at the start of each op.
+    let mut depths = vec![None; ops.len()];
+    let mut max_depth = frame_size;
+    let mut pending = vec![(0, frame_size)];
+    for &offset in &function.entries {
+        if !is_op_start.get(offset).copied().unwrap_or(false) {
+            return Err(err(function, offset, BytecodeError::InvalidEntry { offset }));
+        }
+        pending.push((offset, frame_size));
+    }
+    while let Some((offset, depth)) = pending.pop() {
+        if offset == ops.len() {
+            let name = unsafe { (*function.name).value }.to_string();
+            return Err(err(function, offset, BytecodeError::MissingReturn { name }));
+        }
+        match depths[offset] {
+            Some(depth1) if depth1 == depth => continue,
+            Some(depth1) => {
+                let error = BytecodeError::StackMismatch { offset, depth1, depth2: depth };
+                return Err(err(function, offset, error));
+            }
+            None => depths[offset] = Some(depth),
+        }
+
+        let next = offset + op_len(function, offset)?;
+        let operands = &ops[offset + 1..next];
+        match ops[offset] {
+            op::CONSTANT => {
+                constant(function, offset, operands[0] as usize)?;
+            }
+            op::GET_LOCAL | op::SET_LOCAL => {
+                let slot = operands[0] as usize;
+                if slot >= depth {
+                    let error = BytecodeError::LocalOutOfBounds { offset, slot };
+                    return Err(err(function, offset, error));
+                }
+            }
+            op::GET_UPVALUE | op::SET_UPVALUE => {
+                upvalue(function, offset, operands[0] as usize)?;
+            }
+            op::CLOSURE => check_closure(function, offset, depth)?,
+            _ => {}
+        }
+
+        let (pops, pushes) =
+            op::stack_effect(ops[offset], operands).expect("unknown op should have been rejected");
+        let Some(depth) = depth.checked_sub(pops) else {
+            return Err(err(function, offset, BytecodeError::StackUnderflow { offset }));
+        };
+        let depth = depth + pushes;
+        if depth > max {
+            return Err(err(function, offset, BytecodeError::StackOverflow { offset, max }));
+        }
+        max_depth = max_depth.max(depth);
+
+        let jump = || u16::from_le_bytes([operands[0], operands[1]]) as usize;
+        let target = match ops[offset] {
+            op::JUMP | op::JUMP_IF_FALSE | op::JUMP_IF_FALSE_POP | op::JUMP_IF_TRUE_POP => {
+                Some((next + jump(), depth))
+            }
+            op::LOOP => Some((next.checked_sub(jump()).unwrap_or(usize::MAX), depth)),
+            // The handler starts with the error on top of the stack.
+            op::TRY | op::TRY_FINALLY => {
+                if depth + 1 > max {
+                    return Err(err(
+                        function,
+                        offset,
+                        BytecodeError::StackOverflow { offset, max },
+                    ));
+                }
+                max_depth = max_depth.max(depth + 1);
+                Some((next + jump(), depth + 1))
+            }
+            _ => None,
+        };
+        if let Some((target, depth)) = target {
+            if !is_op_start.get(target).copied().unwrap_or(false) {
+                return Err(err(function, offset, BytecodeError::InvalidJump { offset }));
+            }
+            pending.push((target, depth));
+        }
+        if !matches!(ops[offset], op::JUMP | op::LOOP | op::RETURN | op::RETURN_NIL) {
+            pending.push((next, depth));
+        }
+    }
+    Ok(max_depth)
+}
+
+/// Checks the upvalues captured by the `CLOSURE` op at `offset`, which runs
+/// with `depth` values on the stack.
+fn check_closure(function: &ObjectFunction, offset: usize, depth: usize) -> Result<()> {
+    let ops = &function.chunk.ops;
+    let closure = constant(function, offset, ops[offset + 1] as usize)?;
+    let closure = unsafe { &*closure.as_object().function };
+
+    for idx in 0..closure.upvalue_count as usize {
+        let is_local = ops[offset + 2 + 2 * idx];
+        let upvalue_idx = ops[offset + 3 + 2 * idx] as usize;
+        if is_local != 0 {
+            if upvalue_idx >= depth {
+                let error = BytecodeError::LocalOutOfBounds { offset, slot: upvalue_idx };
+                return Err(err(function, offset, error));
+            }
+        } else {
+            upvalue(function, offset, upvalue_idx)?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the length in bytes of the op at `offset`, including its operands,
+/// after checking that all of them are present. Constants that the length or
+/// meaning of the op depends on are checked here too.
+fn op_len(function: &ObjectFunction, offset: usize) -> Result<usize> {
+    let ops = &function.chunk.ops;
+    let len = match ops[offset] {
+        op::CONSTANT
+        | op::SMALL_INT
+        | op::GET_LOCAL
+        | op::SET_LOCAL
+        | op::GET_UPVALUE
+        | op::SET_UPVALUE
+        | op::CALL
+        | op::LIST
+        | op::MAP => 2,
+        op::GET_GLOBAL
+        | op::DEFINE_GLOBAL
+        | op::SET_GLOBAL
+        | op::GET_PROPERTY
+        | op::SET_PROPERTY
+        | op::GET_SUPER
+        | op::CLASS
+        | op::METHOD
+        | op::EXTEND
+        | op::INVOKE
+        | op::SUPER_INVOKE => {
+            if offset + 1 < ops.len() {
+                name(function, offset, ops[offset + 1] as usize)?;
+            }
+            if matches!(ops[offset], op::INVOKE | op::SUPER_INVOKE) { 3 } else { 2 }
+        }
+        op::ASSERT_INFIX => {
+            if let Some(&op) = ops.get(offset + 1) {
+                if !op::is_comparison(op) {
+                    let offset = offset + 1;
+                    return Err(err(function, offset, BytecodeError::InvalidOp { offset, op }));
+                }
+            }
+            2
+        }
+        op::JUMP
+        | op::JUMP_IF_FALSE
+        | op::JUMP_IF_FALSE_POP
+        | op::JUMP_IF_TRUE_POP
+        | op::LOOP
+        | op::TRY
+        | op::TRY_FINALLY => 3,
+        op::CALL_NATIVE => {
+            if let Some(&idx) = ops.get(offset + 1) {
+                if idx as usize >= Native::ALL.len() {
+                    let error = BytecodeError::NativeOutOfBounds { offset, idx: idx as usize };
+                    return Err(err(function, offset, error));
+                }
+            }
+            3
+        }
+        op::CLOSURE => match ops.get(offset + 1) {
+            Some(&idx) => {
+                let constant = constant(function, offset, idx as usize)?;
+                if constant.type_() != ObjectType::Function.into() {
+                    return Err(type_mismatch(
+                        function,
+                        offset,
+                        idx as usize,
+                        constant,
+                        "function",
+                    ));
+                }
+                let closure = unsafe { constant.as_object().function };
+                2 + 2 * unsafe { (*closure).upvalue_count } as usize
+            }
+            None => 2,
+        },
+        op::NIL
+        | op::TRUE
+        | op::FALSE
+        | op::POP
+        | op::DUP
+        | op::SWAP
+        | op::ROT
+        | op::EQUAL
+        | op::NOT_EQUAL
+        | op::GREATER
+        | op::GREATER_EQUAL
+        | op::LESS
+        | op::LESS_EQUAL
+        | op::IS
+        | op::ADD
+        | op::SUBTRACT
+        | op::MULTIPLY
+        | op::DIVIDE
+        | op::MODULO
+        | op::POWER
+        | op::NOT
+        | op::NEGATE
+        | op::ASSERT
+        | op::PRINT
+        | op::PRINT_SPACED
+        | op::CLOSE_UPVALUE
+        | op::RETURN
+        | op::INHERIT
+        | op::END_TRY
+        | op::RETHROW
+        | op::GET_INDEX
+        | op::SET_INDEX
+        | op::CONCAT
+        | op::RETURN_NIL => 1,
+        op => return Err(err(function, offset, BytecodeError::InvalidOp { offset, op })),
+    };
+    if offset + len > ops.len() {
+        return Err(err(function, offset, BytecodeError::Truncated { offset }));
+    }
+    Ok(len)
+}
+
+fn constant(function: &ObjectFunction, offset: usize, idx: usize) -> Result<Value> {
+    match function.chunk.constants.get(idx) {
+        Some(&constant) => Ok(constant),
+        None => Err(err(function, offset, BytecodeError::ConstantOutOfBounds { offset, idx })),
+    }
+}
+
+/// Checks that the constant at `idx` is a string, as the names of variables
+/// and properties must be.
+fn name(function: &ObjectFunction, offset: usize, idx: usize) -> Result<()> {
+    let constant = constant(function, offset, idx)?;
+    if constant.type_() != ObjectType::String.into() {
+        return Err(type_mismatch(function, offset, idx, constant, "string"));
+    }
+    Ok(())
+}
+
+fn upvalue(function: &ObjectFunction, offset: usize, idx: usize) -> Result<()> {
+    if idx >= function.upvalue_count as usize {
+        return Err(err(function, offset, BytecodeError::UpvalueOutOfBounds { offset, idx }));
+    }
+    Ok(())
+}
+
+fn type_mismatch(
+    function: &ObjectFunction,
+    offset: usize,
+    idx: usize,
+    constant: Value,
+    exp_type: &str,
+) -> ErrorS {
+    let error = BytecodeError::ConstantTypeMismatch {
+        offset,
+        idx,
+        exp_type: exp_type.to_string(),
+        got_type: constant.type_().to_string(),
+    };
+    err(function, offset, error)
+}
+
+/// Wraps a [`BytecodeError`] in the span of the op at `offset`, or of the
+/// last op if `offset` is past the end of the chunk.
+fn err(function: &ObjectFunction, offset: usize, error: BytecodeError) -> ErrorS {
+    let spans = &function.chunk.spans;
+    let span = spans.get(offset).or_else(|| offset.checked_sub(1).and_then(|idx| spans.get(idx)));
+    (error.into(), span.cloned().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+    use crate::error::Error;
+    use crate::vm::{ChunkBuilder, Compiler, Constant, Gc, VM};
+
+    fn verify_chunk(chunk: &ChunkBuilder) -> Result<(), Error> {
+        VM::default().run_chunk(chunk, &mut Vec::new()).map_err(|(e, _)| e)
+    }
+
+    fn chunk(ops: &[u8]) -> ChunkBuilder {
+        let mut chunk = ChunkBuilder::new();
+        chunk.constant(Constant::Number(1.0)).unwrap();
+        chunk.constant(Constant::String("a".to_string())).unwrap();
+        for &byte in ops {
+            chunk.emit(byte);
+        }
+        chunk
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(verify_chunk(&chunk(&[op::NIL, op::RETURN])), Ok(()));
+        assert_eq!(verify_chunk(&chunk(&[op::RETURN_NIL])), Ok(()));
+
+        // var x = 1; if (x) print x;
+        let mut chunk = chunk(&[op::CONSTANT, 0, op::GET_LOCAL, 0]);
+        let else_jump = chunk.emit_jump(op::JUMP_IF_FALSE);
+        chunk.emit(op::POP).emit(op::GET_LOCAL).emit(0).emit(op::PRINT);
+        let end_jump = chunk.emit_jump(op::JUMP);
+        chunk.patch_jump(else_jump).unwrap();
+        chunk.emit(op::POP);
+        chunk.patch_jump(end_jump).unwrap();
+        chunk.emit(op::POP).emit(op::NIL).emit(op::RETURN);
+        assert_eq!(verify_chunk(&chunk), Ok(()));
+
+        // try {} catch (e) {}
+        let mut chunk = ChunkBuilder::new();
+        let catch_jump = chunk.emit_jump(op::TRY);
+        chunk.emit(op::END_TRY);
+        let end_jump = chunk.emit_jump(op::JUMP);
+        chunk.patch_jump(catch_jump).unwrap();
+        chunk.emit(op::POP);
+        chunk.patch_jump(end_jump).unwrap();
+        chunk.emit(op::NIL).emit(op::RETURN);
+        assert_eq!(verify_chunk(&chunk), Ok(()));
+    }
+
+    #[test]
+    fn entries() {
+        let mut gc = Gc::default();
+        let program = crate::syntax::parse("fun f(a, b = 1) {}", 0).unwrap();
+        let script = Compiler::compile_program(&program, &mut gc, Default::default()).unwrap();
+        let script = unsafe { &*script };
+        assert_eq!(verify(script), Ok(()));
+
+        let f = script.chunk.constants.iter().find(|c| c.type_() == ObjectType::Function.into());
+        let f = unsafe { &mut *f.unwrap().as_object().function };
+        assert_eq!(f.min_arity(), 1);
+        f.entries[0] += 1;
+        let offset = f.entries[0];
+        let (error, _) = verify(script).unwrap_err();
+        assert_eq!(error, Error::BytecodeError(BytecodeError::InvalidEntry { offset }));
+    }
+
+    #[test]
+    fn invalid() {
+        let verify_err = |ops: &[u8]| match verify_chunk(&chunk(ops)) {
+            Err(Error::BytecodeError(e)) => e,
+            result => panic!("expected a BytecodeError, got {result:?}"),
+        };
+
+        assert_eq!(verify_err(&[0xff]), BytecodeError::InvalidOp { offset: 0, op: 0xff });
+        assert_eq!(verify_err(&[op::NIL, op::JUMP, 0]), BytecodeError::Truncated { offset: 1 });
+        assert_eq!(
+            verify_err(&[op::CONSTANT, 2, op::RETURN]),
+            BytecodeError::ConstantOutOfBounds { offset: 0, idx: 2 }
+        );
+        assert_eq!(
+            verify_err(&[op::GET_GLOBAL, 0, op::RETURN]),
+            BytecodeError::ConstantTypeMismatch {
+                offset: 0,
+                idx: 0,
+                exp_type: "string".to_string(),
+                got_type: "number".to_string()
+            }
+        );
+        assert_eq!(
+            verify_err(&[op::NIL, op::GET_LOCAL, 1, op::RETURN]),
+            BytecodeError::LocalOutOfBounds { offset: 1, slot: 1 }
+        );
+        assert_eq!(
+            verify_err(&[op::GET_UPVALUE, 0, op::RETURN]),
+            BytecodeError::UpvalueOutOfBounds { offset: 0, idx: 0 }
+        );
+        assert_eq!(
+            verify_err(&[op::JUMP, 2, 0, op::NIL, op::RETURN]),
+            BytecodeError::InvalidJump { offset: 0 }
+        );
+        assert_eq!(
+            verify_err(&[op::LOOP, 4, 0, op::NIL, op::RETURN]),
+            BytecodeError::InvalidJump { offset: 0 }
+        );
+        assert_eq!(
+            verify_err(&[op::NIL, op::NIL, op::ASSERT_INFIX, op::ADD, op::NIL, op::RETURN]),
+            BytecodeError::InvalidOp { offset: 3, op: op::ADD }
+        );
+        assert_eq!(
+            verify_err(&[op::POP, op::NIL, op::RETURN]),
+            BytecodeError::StackUnderflow { offset: 0 }
+        );
+        assert_eq!(
+            verify_err(&[op::TRUE, op::JUMP_IF_FALSE, 1, 0, op::NIL, op::NIL, op::RETURN]),
+            BytecodeError::StackMismatch { offset: 5, depth1: 2, depth2: 1 }
+        );
+        assert_eq!(
+            verify_err(&[op::NIL, op::PRINT]),
+            BytecodeError::MissingReturn { name: "<script>".to_string() }
+        );
+
+        let mut overflow = vec![op::NIL; STACK_MAX_PER_FRAME + 1];
+        overflow.push(op::RETURN);
+        assert_eq!(
+            verify_err(&overflow),
+            BytecodeError::StackOverflow { offset: STACK_MAX_PER_FRAME, max: STACK_MAX_PER_FRAME }
+        );
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "watch")]
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+/// How long to wait for more changes after the first one, so that a save
+/// which touches a file several times only triggers a single rerun.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// Calls `f` once, then again every time a Lox file in `paths` changes,
+/// clearing the screen each time. This only returns if watching fails.
+pub fn watch(paths: &[PathBuf], mut f: impl FnMut() -> Result<()>) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).context("could not start file watcher")?;
+
+    let mut files = Vec::new();
+    for path in paths {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("could not watch path: {}", path.display()))?;
+        if path.is_dir() {
+            watcher.watch(&path, RecursiveMode::Recursive)
+        } else {
+            // Editors often save by replacing the file, which would end a
+            // watch on the file itself, so watch its directory instead.
+            let dir = path.parent().unwrap_or(&path);
+            watcher.watch(dir, RecursiveMode::NonRecursive)
+        }
+        .with_context(|| format!("could not watch path: {}", path.display()))?;
+        files.push(path);
+    }
+
+    let is_relevant = |event: &notify::Event| {
+        !matches!(event.kind, EventKind::Access(_))
+            && event.paths.iter().any(|path| is_watched(&files, path))
+    };
+
+    loop {
+        print!("\x1b[2J\x1b[H");
+        if let Err(e) = f() {
+            eprintln!("error: {e:#}");
+        }
+
+        loop {
+            let event = rx.recv().context("file watcher stopped")?;
+            if event.is_ok_and(|event| is_relevant(&event)) {
+                break;
+            }
+        }
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+    }
+}
+
+fn is_watched(files: &[PathBuf], path: &Path) -> bool {
+    files.iter().any(|file| {
+        if file.is_dir() {
+            path.starts_with(file) && path.extension().is_some_and(|extension| extension == "lox")
+        } else {
+            path == file
+        }
+    })
+}
@@ -0,0 +1,63 @@
+//! Tests for the stable API at the root of the crate. These only use items
+//! that are re-exported there, so that they keep compiling for as long as the
+//! API is compatible, however the internals change.
+
+use loxcraft::{Category, Diagnostic, Position, Severity, Vm};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn run() {
+    let mut stdout = Vec::new();
+    assert_eq!(loxcraft::run("print \"hello\";", &mut stdout), Ok(()));
+    assert_eq!(stdout, b"hello\n");
+
+    let errors = loxcraft::run("var a = 1;\nprint a + nil;", &mut stdout).unwrap_err();
+    let [error] = &errors[..] else { panic!("expected one error: {errors:?}") };
+    assert_eq!(error.severity, Severity::Error);
+    assert_eq!(error.code.as_deref(), Some("E0211"));
+    assert_eq!(
+        error.message,
+        r#"TypeError: unsupported operand type(s) for +: "number" and "nil""#
+    );
+    assert_eq!(error.start, Position { line: 1, character: 6 });
+    assert_eq!(error.end, Position { line: 1, character: 13 });
+}
+
+#[test]
+fn vm_keeps_globals() {
+    let mut vm = Vm::default();
+    let mut stdout = Vec::new();
+    vm.run("fun greet(name) { print \"hi \" + name; }", &mut stdout).unwrap();
+    vm.run("greet(\"lox\");", &mut stdout).unwrap();
+    assert_eq!(stdout, b"hi lox\n");
+
+    let errors = vm.run("greet(1);", &mut stdout).unwrap_err();
+    assert_eq!(errors[0].code.as_deref(), Some("E0211"));
+    assert_eq!(errors[0].start.line, 0);
+}
+
+#[test]
+fn check() {
+    assert_eq!(loxcraft::check("print 1;"), []);
+
+    let diagnostics = loxcraft::check("while (true) {}\nprint this;\nreturn;");
+    let summary = |diagnostic: &Diagnostic| {
+        (diagnostic.severity, diagnostic.category, diagnostic.code.clone(), diagnostic.start.line)
+    };
+    assert_eq!(
+        diagnostics.iter().map(summary).collect::<Vec<_>>(),
+        [
+            (Severity::Error, Category::Resolve, Some("E0010".to_string()), 1),
+            (Severity::Error, Category::Resolve, Some("E0007".to_string()), 2),
+            (Severity::Warning, Category::Lint, None, 0),
+        ]
+    );
+}
+
+#[test]
+fn diagnostic_json() {
+    let diagnostics = loxcraft::check("print 1 +;");
+    assert_eq!(diagnostics[0].category, Category::Parse);
+    let json = serde_json::to_string(&diagnostics).unwrap();
+    assert_eq!(serde_json::from_str::<Vec<Diagnostic>>(&json).unwrap(), diagnostics);
+}
@@ -0,0 +1,85 @@
+//! Tests for `loxcraft run --inspect`, which talk to the program over TCP,
+//! the way `loxcraft attach` does.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use pretty_assertions::assert_eq;
+
+/// How long to wait for the program before failing the test, instead of
+/// hanging.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const SOURCE: &str = "\
+var done = false;
+var i = 0;
+while (!done) {
+  i = i + 1;
+}
+print \"finished\";
+";
+
+/// Reads from the program until it shows a prompt, and returns what it sent.
+fn read_prompt(stream: &mut TcpStream) -> String {
+    let mut output = Vec::new();
+    let mut buf = [0; 256];
+    while !output.ends_with(b"(lox) ") {
+        let len = stream.read(&mut buf).expect("program did not respond");
+        assert_ne!(len, 0, "program disconnected: {}", String::from_utf8_lossy(&output));
+        output.extend(&buf[..len]);
+    }
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn pause_eval_and_detach() {
+    let mut program = Command::new(env!("CARGO_BIN_EXE_loxcraft"))
+        .args(["run", "--inspect", "127.0.0.1:0", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("could not start program");
+    program.stdin.take().unwrap().write_all(SOURCE.as_bytes()).unwrap();
+
+    let mut stderr = BufReader::new(program.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let addr = line.trim().strip_prefix("inspector listening on ").expect(&line);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut greeting = String::new();
+    reader.read_line(&mut greeting).unwrap();
+    assert_eq!(greeting, "attached; send pause to pause the program, or help\n");
+
+    stream.write_all(b"pause\n").unwrap();
+    let paused = read_prompt(&mut stream);
+    assert!(paused.contains(" | "), "{paused}");
+    stream.write_all(b"e i > 0\n").unwrap();
+    assert_eq!(read_prompt(&mut stream), "true\n(lox) ");
+    stream.write_all(b"e done = true\n").unwrap();
+    assert_eq!(read_prompt(&mut stream), "true\n(lox) ");
+
+    // Once the client disconnects, the program carries on.
+    drop(reader);
+    drop(stream);
+    let output = program.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "finished\n");
+}
+
+#[test]
+fn reject_remote_addr() {
+    let output = Command::new(env!("CARGO_BIN_EXE_loxcraft"))
+        .args(["run", "--inspect", "0.0.0.0:0", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("could not start program");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("refusing to inspect on 0.0.0.0:0"), "{stderr}");
+}
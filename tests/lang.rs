@@ -1,10 +1,14 @@
-use std::io::Write;
-use std::{fs, str};
+use std::fs;
 
-use loxcraft::vm::VM;
+use loxcraft::vm::{VM, VmOptions};
 use pretty_assertions::assert_eq;
 use test_generator::test_resources;
 
+#[path = "lox_test/mod.rs"]
+mod lox_test;
+
+use lox_test::Backend;
+
 #[test_resources("res/examples/**/*.lox")]
 fn lox(path: &str) {
     // Miri is too slow to run these tests.
@@ -24,11 +28,23 @@ fn lox(path: &str) {
         }
     }
 
-    let mut got_output = Vec::new();
-    if let Err(e) = VM::default().run(&source, &mut got_output) {
-        let (e, _) = e.first().expect("received empty error");
-        writeln!(&mut got_output, "{e}").expect("could not write to output");
+    // Class extensions are off by default, as with `lox run`, so only the
+    // examples of them turn them on.
+    let class_extensions = path.starts_with("res/examples/extend/");
+    let options = VmOptions { class_extensions, ..Default::default() };
+    for backend in Backend::ALL {
+        let output = lox_test::run(backend, &source, &options);
+        assert_eq!(exp_output, output, "on backend {backend:?}");
+    }
+}
+
+/// The benchmarks take too long to run as tests, but they should at least
+/// compile, so that `cargo xtask pgo` can train on them.
+#[test_resources("res/benchmarks/**/*.lox")]
+fn benchmark(path: &str) {
+    let source = fs::read_to_string(path).expect("unable to read benchmark");
+    let mut vm = VM::new(VmOptions { verify: true, ..Default::default() });
+    if let Err(e) = vm.compile(&source) {
+        panic!("benchmark does not compile: {:?}", e.first());
     }
-    let got_output = str::from_utf8(&got_output).expect("invalid UTF-8 in output");
-    assert_eq!(exp_output, got_output);
 }
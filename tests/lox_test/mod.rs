@@ -0,0 +1,110 @@
+//! Runs Lox programs on every backend, and checks what they print.
+//!
+//! A program can be run from source, or compiled to bytecode first and then
+//! decoded, verified and run from that, and natives can be called through
+//! their globals or directly. All of these should behave the same, so every
+//! behavior test runs on each.
+
+use std::io::Write;
+use std::str;
+
+use loxcraft::vm::{VM, VmOptions};
+use pretty_assertions::assert_eq;
+
+/// Defines a test for each Lox program, which checks what the program prints
+/// on every [`Backend`], followed by its first error, if any. A program that
+/// needs options other than the defaults names them before its source:
+///
+/// ```ignore
+/// lox_test! {
+///     add: "print 1 + 2;" => "3\n";
+///     add_nil: "print 1; print 1 + nil;" => "1\n" / "TypeError: ...";
+///     extend { class_extensions: true }: "class A {} extend A {}" => "";
+/// }
+/// ```
+///
+/// This also defines `programs()`, the source of every program with the
+/// options it is run with, so that a test can check what they cover between
+/// them.
+#[allow(unused_macros)]
+macro_rules! lox_test {
+    ($(
+        $name:ident $({ $($option:ident: $value:expr),* })?:
+            $source:literal => $stdout:literal $(/ $error:literal)?;
+    )*) => {
+        $(
+            #[test]
+            fn $name() {
+                let options = ::loxcraft::vm::VmOptions {
+                    $($($option: $value,)*)?
+                    ..Default::default()
+                };
+                $crate::lox_test::check($source, &options, $stdout, None $(.or(Some($error)))?);
+            }
+        )*
+
+        #[allow(dead_code)]
+        fn programs() -> Vec<(&'static str, ::loxcraft::vm::VmOptions)> {
+            vec![$((
+                $source,
+                ::loxcraft::vm::VmOptions { $($($option: $value,)*)? ..Default::default() },
+            )),*]
+        }
+    };
+}
+
+/// The ways in which a program can be run.
+#[derive(Clone, Copy, Debug)]
+pub enum Backend {
+    /// [`VM::run`], which compiles the program and runs it.
+    Source,
+    /// [`VM::compile`], followed by [`VM::run_bytecode`].
+    Bytecode,
+    /// [`VM::run`] with [`VmOptions::direct_native_calls`], as with `lox run`.
+    DirectNatives,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 3] = [Backend::Source, Backend::Bytecode, Backend::DirectNatives];
+
+    /// The options that `backend` runs a program with, given those that the
+    /// program asks for.
+    pub fn options(self, options: &VmOptions) -> VmOptions {
+        // Verifying the bytecode of every program checks the compiler against
+        // the verifier, and vice versa.
+        let direct_native_calls = matches!(self, Backend::DirectNatives);
+        VmOptions { verify: true, direct_native_calls, ..options.clone() }
+    }
+}
+
+/// Runs `source` on `backend` with `options`, and returns what it printed,
+/// followed by its first error on a line of its own.
+pub fn run(backend: Backend, source: &str, options: &VmOptions) -> String {
+    let mut vm = VM::new(backend.options(options));
+    let mut output = Vec::new();
+    let result = match backend {
+        Backend::Source | Backend::DirectNatives => vm.run(source, &mut output),
+        Backend::Bytecode => {
+            vm.compile(source).and_then(|bytecode| vm.run_bytecode(source, &bytecode, &mut output))
+        }
+    };
+    if let Err(e) = result {
+        let (e, _) = e.first().expect("received empty error");
+        writeln!(&mut output, "{e}").expect("could not write to output");
+    }
+    str::from_utf8(&output).expect("invalid UTF-8 in output").to_string()
+}
+
+/// Checks that `source` prints `stdout` on every backend, followed by `error`
+/// if it fails.
+#[allow(dead_code)]
+pub fn check(source: &str, options: &VmOptions, stdout: &str, error: Option<&str>) {
+    let mut expected = stdout.to_string();
+    if let Some(error) = error {
+        expected += error;
+        expected += "\n";
+    }
+    for backend in Backend::ALL {
+        assert_eq!(expected, run(backend, source, options), "on backend {backend:?}");
+    }
+}
@@ -0,0 +1,207 @@
+//! Tests for the language server, which run `loxcraft lsp` and talk to it over
+//! stdio, the way an editor would.
+
+#![cfg(feature = "lsp")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pretty_assertions::assert_eq;
+use serde_json::{Value, json};
+
+/// How long to wait for the server before failing the test, instead of
+/// hanging.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const URI: &str = "file:///test.lox";
+
+struct Client {
+    server: Child,
+    stdin: ChildStdin,
+    messages: Receiver<Value>,
+    next_id: u64,
+}
+
+impl Client {
+    fn new() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_loxcraft"))
+            .args(["lsp", "--stdio"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("could not start language server");
+        let stdin = server.stdin.take().unwrap();
+        let stdout = server.stdout.take().unwrap();
+
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Some(message) = read_message(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { server, stdin, messages, next_id: 0 }
+    }
+
+    /// Starts a session, as every client must before any other request.
+    fn initialized() -> Self {
+        let mut client = Self::new();
+        let response = client.request("initialize", json!({ "capabilities": {} }));
+        assert!(response.get("result").is_some(), "{response}");
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    /// Sends a request, and returns the response to it.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        self.recv(|message| message["id"] == id && message.get("method").is_none())
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Returns the params of the next notification with this method, skipping
+    /// any others, such as log messages.
+    fn notification(&mut self, method: &str) -> Value {
+        let mut message = self.recv(|message| message["method"] == method);
+        message["params"].take()
+    }
+
+    fn recv(&mut self, is_wanted: impl Fn(&Value) -> bool) -> Value {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let message = self.messages.recv_timeout(timeout).expect("server did not respond");
+            if is_wanted(&message) {
+                return message;
+            }
+        }
+    }
+
+    fn wait(&mut self) -> ExitStatus {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.server.try_wait().unwrap() {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "server did not exit");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.server.kill();
+    }
+}
+
+/// Reads a message in the LSP base protocol, or returns [`None`] once the
+/// server closes its stdout.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            content_length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; content_length.expect("missing Content-Length header")];
+    reader.read_exact(&mut body).ok()?;
+    Some(serde_json::from_slice(&body).expect("invalid message"))
+}
+
+fn open(client: &mut Client, text: &str) -> Value {
+    let document = json!({ "uri": URI, "languageId": "lox", "version": 1, "text": text });
+    client.notify("textDocument/didOpen", json!({ "textDocument": document }));
+    client.notification("textDocument/publishDiagnostics")
+}
+
+#[test]
+fn initialize() {
+    let mut client = Client::new();
+    let response = client.request("initialize", json!({ "capabilities": {} }));
+    let result = &response["result"];
+    assert_eq!(
+        result["serverInfo"],
+        json!({ "name": "loxcraft", "version": env!("CARGO_PKG_VERSION") })
+    );
+
+    let capabilities = &result["capabilities"];
+    // Clients send the whole document on every change.
+    assert_eq!(capabilities["textDocumentSync"]["change"], 1);
+    assert_eq!(capabilities["textDocumentSync"]["openClose"], true);
+    for provider in [
+        "documentFormattingProvider",
+        "documentHighlightProvider",
+        "documentSymbolProvider",
+        "hoverProvider",
+        "inlayHintProvider",
+    ] {
+        assert_eq!(capabilities[provider], true, "{provider}");
+    }
+}
+
+#[test]
+fn request_before_initialize() {
+    let mut client = Client::new();
+    let response = client.request("textDocument/documentSymbol", json!({}));
+    assert_eq!(response["error"]["code"], -32002, "{response}");
+}
+
+#[test]
+fn diagnostics() {
+    let mut client = Client::initialized();
+    let diagnostics = open(&mut client, "print 1 +;\n");
+    assert_eq!(diagnostics["uri"], URI);
+    let [diagnostic] = diagnostics["diagnostics"].as_array().unwrap().as_slice() else {
+        panic!("expected one diagnostic: {diagnostics}");
+    };
+    assert_eq!(diagnostic["severity"], 1);
+    assert_eq!(diagnostic["range"]["start"], json!({ "line": 0, "character": 9 }));
+
+    let change = json!({
+        "textDocument": { "uri": URI, "version": 2 },
+        "contentChanges": [{ "text": "print 1 + 2;\n" }],
+    });
+    client.notify("textDocument/didChange", change);
+    let diagnostics = client.notification("textDocument/publishDiagnostics");
+    assert_eq!(diagnostics["version"], 2);
+    assert_eq!(diagnostics["diagnostics"], json!([]));
+}
+
+#[test]
+fn shutdown() {
+    let mut client = Client::initialized();
+    let response = client.request("shutdown", Value::Null);
+    assert_eq!(response["result"], Value::Null, "{response}");
+    // Only `exit` is allowed after `shutdown`.
+    let response = client.request("textDocument/documentSymbol", json!({}));
+    assert_eq!(response["error"]["code"], -32600, "{response}");
+
+    client.notify("exit", Value::Null);
+    assert!(client.wait().success());
+}
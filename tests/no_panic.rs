@@ -0,0 +1,54 @@
+//! Checks that no input makes loxcraft panic. The fuzz target in fuzz/ looks
+//! for such inputs at random; this runs the same checks on the examples, on
+//! prefixes of the smaller ones, and on the inputs in res/fuzz, which made
+//! earlier versions panic.
+
+use std::fs;
+
+use test_generator::test_resources;
+
+#[path = "no_panic/exercise.rs"]
+mod exercise;
+
+/// Examples larger than this are only checked whole, since checking their
+/// prefixes takes time quadratic in their size.
+const MAX_PREFIX_LEN: usize = 512;
+
+#[test_resources("res/examples/**/*.lox")]
+fn example(path: &str) {
+    check_prefixes(path);
+}
+
+#[test_resources("res/fuzz/*.lox")]
+fn fuzz(path: &str) {
+    check_prefixes(path);
+}
+
+fn check_prefixes(path: &str) {
+    let source = fs::read_to_string(path).expect("unable to read test file");
+    if source.len() > MAX_PREFIX_LEN {
+        check(path, &source);
+        return;
+    }
+    for idx in token_boundaries(&source) {
+        check(path, &source[..idx]);
+    }
+}
+
+fn check(path: &str, source: &str) {
+    if std::panic::catch_unwind(|| exercise::exercise(source)).is_err() {
+        panic!("{path} panicked on input: {source:?}");
+    }
+}
+
+/// Returns the indices in `source` that are not inside a word, which is where
+/// an editor is most likely to run the analyses while the source is typed.
+fn token_boundaries(source: &str) -> impl Iterator<Item = usize> + '_ {
+    let is_word = |idx: usize| source[idx..].starts_with(char::is_alphanumeric);
+    (0..=source.len()).filter(move |&idx| {
+        source.is_char_boundary(idx)
+            && (idx == source.len()
+                || !is_word(idx)
+                || !source[..idx].ends_with(char::is_alphanumeric))
+    })
+}
@@ -0,0 +1,43 @@
+use loxcraft::callgraph::CallGraph;
+use loxcraft::vm::{VM, VmOptions};
+use loxcraft::{analysis, syntax};
+
+/// Runs `source` through the analyses that editors run on every keystroke,
+/// and then through the VM for a bounded number of ops. None of this should
+/// panic, whatever the input.
+pub fn exercise(source: &str) {
+    loxcraft::check(source);
+    syntax::parse_partial(source);
+    analysis::completions(source);
+    for ch in ['}', ';'] {
+        analysis::on_type_edits(source, source.len(), ch, "  ");
+    }
+
+    // Editors get a syntax tree even for sources with errors.
+    let (program, _) = syntax::parse_tolerant(source);
+    analysis::document_symbols(source, &program);
+    analysis::folding_ranges(source, &program);
+    analysis::inlay_hints(source, &program);
+    CallGraph::new(source, &program);
+    let mut idx = source.len() / 2;
+    while !source.is_char_boundary(idx) {
+        idx -= 1;
+    }
+    for idx in [0, idx, source.len()] {
+        analysis::highlights(source, &program, idx);
+        analysis::hover(source, &program, idx);
+        analysis::selection_ranges(&program, idx);
+    }
+
+    const MAX_OPS: usize = 1000;
+    let mut vm = VM::new(VmOptions { max_output: Some(1 << 16), ..Default::default() });
+    let mut stdout = Vec::new();
+    if vm.load(source).is_ok() {
+        for _ in 0..MAX_OPS {
+            match vm.step(&mut stdout) {
+                Ok(loxcraft::vm::Step::Running) => {}
+                _ => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! A test for each op, and for how it fails, on every backend. Longer programs
+//! are in res/examples, and run by tests/lang.rs.
+
+use loxcraft::vm::serialize::decode_unchecked;
+use loxcraft::vm::{Gc, VM, op};
+
+#[path = "lox_test/mod.rs"]
+#[macro_use]
+mod lox_test;
+
+use lox_test::Backend;
+
+lox_test! {
+    // Literals and the stack.
+    constant: r#"print 1.5; print "a";"# => "1.5\na\n";
+    literals: "print nil; print true; print false;" => "nil\ntrue\nfalse\n";
+    pop: "1 + 2; print 3;" => "3\n";
+    dup: "class A { f() { return 1; } } print A().f();" => "1\n";
+
+    // Variables.
+    local: "{ var a = 1; a = a + 1; print a; }" => "2\n";
+    global: "var a = 1; a = a + 1; print a;" => "2\n";
+    global_undefined: "print a;" => "" / r#"NameError: name "a" is not defined"#;
+    global_assign_undefined: "a = 1;" => "" / r#"NameError: name "a" is not defined"#;
+    upvalue: "fun f() { var a = 1; fun g() { a = a + 1; return a; } return g; } var g = f(); g(); print g();" => "3\n";
+    close_upvalue: r#"var f; { var a = "closed"; fun g() { print a; } f = g; } f();"# => "closed\n";
+
+    // Classes and properties.
+    property: "class A {} var a = A(); a.x = 1; print a.x;" => "1\n";
+    property_undefined: "class A {} print A().x;" => "" / r#"AttributeError: "A" object has no attribute "x""#;
+    property_not_instance: "var a = 1; a.x = 1;" => "" / r#"AttributeError: "number" object has no attribute "x""#;
+    class: "class A {} print A; print A();" => "<class A>\n<object A>\n";
+    method: r#"class A { f() { return "f"; } } var f = A().f; print f();"# => "f\n";
+    invoke: r#"class A { f(x) { return x; } } print A().f("x");"# => "x\n";
+    invoke_undefined: "class A {} A().f();" => "" / r#"AttributeError: "A" object has no attribute "f""#;
+    inherit: r#"class A { f() { return "A"; } } class B < A {} print B().f();"# => "A\n";
+    inherit_not_class: "var A = 1; class B < A {}" => "" / r#"TypeError: superclass should be of type "class", not "number""#;
+    super_: r#"class A { f() { return "A"; } } class B < A { f() { var f = super.f; return "B" + f() + super.f(); } } print B().f();"# => "BAA\n";
+    is: "class A {} class B < A {} print B() is A; print A() is B;" => "true\nfalse\n";
+    is_not_class: "print 1 is 1;" => "" / r#"TypeError: unsupported operand type(s) for is: "number" and "number""#;
+    extend { class_extensions: true }: r#"class A {} class B < A {} var b = B(); extend A { f() { return "A"; } } print b.f();"# => "A\n";
+    extend_not_class { class_extensions: true }: "var A = 1; extend A { f() {} }" => "" / r#"TypeError: extended value should be of type "class", not "number""#;
+    extend_not_enabled: "class A {} extend A { f() {} }" => "" / r#"SyntaxError: "extend" is only allowed with class extensions enabled"#;
+
+    // Lists and maps.
+    list: "var a = [1, 2]; a[0] = 3; print a, a[1];" => "[3, 2] 2\n";
+    list_out_of_range: "[1][1];" => "" / "IndexError: list index 1 is out of range for length 1";
+    map: r#"var m = {"a": 1}; m["b"] = 2; print m, m["a"];"# => "{\"a\": 1, \"b\": 2} 1\n";
+    map_missing_key: r#"({})["a"];"# => "" / r#"IndexError: key "a" is not in the map"#;
+    index_not_indexable: "nil[0];" => "" / r#"TypeError: "nil" object is not indexable"#;
+    set_index_not_assignable: r#""a"[0] = "b";"# => "" / r#"TypeError: "string" object does not support index assignment"#;
+
+    // Operators.
+    equal: r#"print 1 == 1; print "a" == "b"; print nil == false;"# => "true\nfalse\nfalse\n";
+    not_equal: r#"print 1 != 1; print "a" != "b";"# => "false\ntrue\n";
+    comparison: "print 1 > 2; print 1 >= 1; print 1 < 2; print 2 <= 1;" => "false\ntrue\ntrue\nfalse\n";
+    comparison_mismatch: r#"print 1 < "2";"# => "" / r#"TypeError: unsupported operand type(s) for <: "number" and "string""#;
+    arithmetic: "print 1 + 2; print 1 - 2; print 2 * 3; print 1 / 4;" => "3\n-1\n6\n0.25\n";
+    modulo_power: "var a = 7; print a % 3; print -a % 3; print a ** 2;" => "1\n-1\n49\n";
+    power_mismatch: r#"var a = "2"; print a ** 2;"# => "" / r#"TypeError: unsupported operand type(s) for **: "string" and "number""#;
+    concat: r#"print "a" + "b";"# => "ab\n";
+    interpolate: r#"var a = 1; print "a = ${a}, b = ${[a]}";"# => "a = 1, b = [1]\n";
+    add_mismatch: r#"print 1 + "a";"# => "" / r#"TypeError: unsupported operand type(s) for +: "number" and "string""#;
+    not: "print !nil; print !0;" => "true\nfalse\n";
+    negate: "var a = 1; print -a;" => "-1\n";
+    negate_mismatch: r#"print -"a";"# => "" / r#"TypeError: unsupported operand type for -: "string""#;
+
+    // Control flow.
+    if_else: r#"var a = 1; if (a < 2) print "yes"; else print "no";"# => "yes\n";
+    if_not: r#"var no = false; if (!no) print "yes";"# => "yes\n";
+    logical: "print nil and 1; print nil or 1;" => "nil\n1\n";
+    loop_: "var i = 0; while (i < 3) i = i + 1; print i;" => "3\n";
+
+    // Errors.
+    try_catch: r#"try { nil.x; } catch (e) { print "caught"; } try {} catch (e) {}"# => "caught\n";
+    try_finally: r#"try { nil.x; } finally { print "finally"; }"# => "finally\n" / r#"AttributeError: "nil" object has no attribute "x""#;
+    assert: "assert true; assert nil;" => "" / "AssertionError: assertion failed: nil";
+    assert_infix: "assert 1 < 2; assert 1 == 2;" => "" / "AssertionError: assertion failed: 1 == 2 (left: 1, right: 2)";
+
+    // Output.
+    print_spaced: r#"print 1, "a", nil;"# => "1 a nil\n";
+
+    // Calls.
+    call: "fun f(a, b) { return a + b; } print f(1, 2);" => "3\n";
+    call_arity: "fun f(a) {} f();" => "" / "TypeError: f() takes 1 arguments but 0 were given";
+    call_default: "fun f(a, b = a + 1, c = b) { print a, b, c; } f(1); f(1, 5); f(1, 2, 3);" => "1 2 2\n1 5 5\n1 2 3\n";
+    call_default_arity: "fun f(a = 1) {} f(1, 2);" => "" / "TypeError: f() takes from 0 to 1 arguments but 2 were given";
+    call_not_callable: "var f = 1; f();" => "" / r#"TypeError: "number" object is not callable"#;
+    call_native: r#"print len("abc");"# => "3\n";
+    call_native_arity: "len();" => "" / "TypeError: len() takes 1 arguments but 0 were given";
+}
+
+/// Ops that the compiler never emits, and so are only tested with bytecode
+/// from a [`ChunkBuilder`](loxcraft::vm::ChunkBuilder).
+const BUILDER_ONLY: &[&str] = &["OP_SWAP", "OP_ROT"];
+
+#[test]
+fn covers_every_op() {
+    let mut covered = BUILDER_ONLY.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let mut gc = Gc::default();
+    for (source, options) in programs() {
+        for backend in Backend::ALL {
+            let mut vm = VM::new(backend.options(&options));
+            // Programs that test compile errors have no bytecode.
+            let Ok(bytecode) = vm.compile(source) else { continue };
+            let (_, script) = decode_unchecked(&bytecode, &mut gc).unwrap();
+            let disassembly = unsafe { (*script).disassemble() };
+            for line in disassembly.lines() {
+                if let Some(name) =
+                    line.split_whitespace().nth(1).filter(|name| name.starts_with("OP_"))
+                {
+                    covered.push(name.to_string());
+                }
+            }
+        }
+    }
+    covered.sort();
+    covered.dedup();
+
+    let ops = (0..=u8::MAX).filter(|&op| op::stack_effect(op, &[0, 0]).is_some()).count();
+    assert_eq!(covered.len(), ops, "{covered:?}");
+}
@@ -0,0 +1,145 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+use crate::{exec, project_root};
+
+#[derive(Debug, clap::Args)]
+pub struct BenchCompareArgs {
+    /// Run only the benchmarks whose file names contain this, instead of the
+    /// whole suite.
+    #[arg(long, value_name = "NAME")]
+    only: Option<String>,
+    /// How many times to run each benchmark with each implementation. The
+    /// fastest run is the one reported.
+    #[arg(long, default_value_t = 3)]
+    runs: usize,
+    /// The clox executable, which is skipped if it cannot be found.
+    #[arg(long, value_name = "PATH", default_value = "clox")]
+    clox: PathBuf,
+    /// The jlox executable, which is skipped if it cannot be found.
+    #[arg(long, value_name = "PATH", default_value = "jlox")]
+    jlox: PathBuf,
+    /// Print the results as JSON, instead of as a table.
+    #[arg(long)]
+    json: bool,
+}
+
+/// How long a benchmark took with each implementation.
+#[derive(Debug, Serialize)]
+struct Comparison {
+    benchmark: String,
+    timings: Vec<Timing>,
+}
+
+#[derive(Debug, Serialize)]
+struct Timing {
+    implementation: &'static str,
+    /// The fastest time, or [`None`] if the benchmark failed.
+    seconds: Option<f64>,
+}
+
+/// Runs the benchmark suite in res/benchmarks with a release build of
+/// loxcraft, and with clox and jlox where they can be found, and compares the
+/// times. Each benchmark prints how long it took as its last line, so that
+/// this measures the same work in each implementation, and not how long each
+/// takes to start up.
+pub fn run(args: &BenchCompareArgs) -> Result<()> {
+    let root = project_root();
+    exec(Command::new("cargo").args(["build", "--release"]).current_dir(&root))?;
+    let exe = if cfg!(windows) { "loxcraft.exe" } else { "loxcraft" };
+    let loxcraft = root.join("target").join("release").join(exe);
+
+    let mut loxcraft = Command::new(loxcraft);
+    loxcraft.args(["run", "--no-cache"]);
+    let mut implementations = vec![("loxcraft", loxcraft)];
+    for (name, path) in [("clox", &args.clox), ("jlox", &args.jlox)] {
+        match Command::new(path).arg("--version").output() {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                eprintln!("skipping {name}, which could not be found at {}", path.display());
+            }
+            _ => implementations.push((name, Command::new(path))),
+        }
+    }
+
+    let benchmarks = crate::pgo::benchmarks(&root, args.only.as_deref())?;
+    if benchmarks.is_empty() {
+        bail!("no benchmarks to run");
+    }
+    let mut comparisons = Vec::with_capacity(benchmarks.len());
+    for benchmark in &benchmarks {
+        let name = benchmark.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let mut timings = Vec::with_capacity(implementations.len());
+        for (implementation, command) in &implementations {
+            let seconds = time(command, benchmark, args.runs)?;
+            if seconds.is_none() {
+                eprintln!("{name} failed with {implementation}");
+            }
+            timings.push(Timing { implementation, seconds });
+        }
+        comparisons.push(Comparison { benchmark: name, timings });
+    }
+
+    if args.json {
+        let json =
+            serde_json::to_string_pretty(&comparisons).context("could not serialize results")?;
+        println!("{json}");
+    } else {
+        print!("{}", table(&comparisons));
+    }
+    Ok(())
+}
+
+/// Runs `benchmark` with `command` `runs` times, and returns the fastest time
+/// that it printed, or [`None`] if it failed.
+fn time(command: &Command, benchmark: &Path, runs: usize) -> Result<Option<f64>> {
+    let mut fastest = None::<f64>;
+    for _ in 0..runs.max(1) {
+        let output = Command::new(command.get_program())
+            .args(command.get_args())
+            .arg(benchmark)
+            .output()
+            .with_context(|| format!("could not run: {command:?}"))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let Some(time) = output.status.success().then(|| elapsed(&stdout)).flatten() else {
+            return Ok(None);
+        };
+        fastest = Some(fastest.map_or(time, |fastest| fastest.min(time)));
+    }
+    Ok(fastest)
+}
+
+/// Parses the time that a benchmark printed as its last line, in seconds.
+fn elapsed(stdout: &str) -> Option<f64> {
+    stdout.lines().rev().find(|line| !line.trim().is_empty())?.trim().parse().ok()
+}
+
+/// Formats `comparisons` as a table, with each time followed by how many
+/// times slower than loxcraft it was.
+fn table(comparisons: &[Comparison]) -> String {
+    let Some(first) = comparisons.first() else { return String::new() };
+    let mut table = format!("{:<20}", "benchmark");
+    for Timing { implementation, .. } in &first.timings {
+        table.push_str(&format!(" {implementation:>18}"));
+    }
+    table.push('\n');
+    for comparison in comparisons {
+        table.push_str(&format!("{:<20}", comparison.benchmark));
+        let baseline = comparison.timings[0].seconds;
+        for timing in &comparison.timings {
+            let cell = match (timing.seconds, baseline) {
+                (Some(time), Some(baseline)) if baseline > 0.0 => {
+                    format!("{time:.3}s ({:.2}x)", time / baseline)
+                }
+                (Some(time), _) => format!("{time:.3}s"),
+                (None, _) => "failed".to_string(),
+            };
+            table.push_str(&format!(" {cell:>18}"));
+        }
+        table.push('\n');
+    }
+    table
+}
@@ -0,0 +1,160 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::{copy_dir, exec, output, project_root};
+
+#[derive(Debug, clap::Args)]
+pub struct DistArgs {
+    /// Build for this target instead of the host. Can be given more than
+    /// once. The targets must be installed, e.g. with `rustup target add`.
+    #[arg(long = "target", value_name = "TRIPLE")]
+    targets: Vec<String>,
+    /// Build with `cross` instead of cargo, for targets that need a foreign
+    /// linker or C toolchain.
+    #[arg(long)]
+    cross: bool,
+    /// Leave out the playground, which needs pnpm to build.
+    #[arg(long)]
+    no_playground: bool,
+    /// Ship the minimal playground from `cargo xtask playground` instead,
+    /// which does not need pnpm.
+    #[arg(long, conflicts_with = "no_playground")]
+    static_playground: bool,
+}
+
+/// The files from the repository root that go into every archive.
+const EXTRA_FILES: &[&str] = &["LICENSE", "README.md"];
+
+pub fn run(args: &DistArgs) -> Result<()> {
+    let root = project_root();
+    let dist_dir = root.join("target").join("dist");
+    fs::create_dir_all(&dist_dir)
+        .with_context(|| format!("could not create directory: {}", dist_dir.display()))?;
+
+    let playground = !args.no_playground;
+    if args.static_playground {
+        crate::playground::run()?;
+    } else if playground {
+        build_playground(&root)?;
+    }
+
+    let version = version(&root)?;
+    let targets = if args.targets.is_empty() { vec![host_target()?] } else { args.targets.clone() };
+    for target in &targets {
+        let binary = build(&root, target, args)?;
+        let name = format!("loxcraft-{version}-{target}");
+        let staging = dist_dir.join(&name);
+        stage(&root, &staging, &binary, playground)?;
+
+        let archive = archive(&dist_dir, &name, target.contains("windows"))?;
+        let checksum = write_checksum(&archive)?;
+        eprintln!("built {} ({checksum})", archive.display());
+    }
+    Ok(())
+}
+
+/// Builds the playground assets into playground/out/, from which the binary
+/// embeds them.
+fn build_playground(root: &Path) -> Result<()> {
+    let dir = root.join("playground");
+    exec(Command::new("pnpm").args(["run", "build:wasm"]).current_dir(&dir))?;
+    exec(Command::new("pnpm").args(["install", "--prefer-frozen-lockfile"]).current_dir(&dir))?;
+    exec(Command::new("pnpm").args(["run", "build"]).current_dir(&dir))
+}
+
+/// Builds a release binary for `target`, and returns its path. The release
+/// profile already strips symbols.
+fn build(root: &Path, target: &str, args: &DistArgs) -> Result<PathBuf> {
+    let mut command = Command::new(if args.cross { "cross" } else { "cargo" });
+    command.args(["build", "--release", "--target", target]).current_dir(root);
+    if !args.no_playground {
+        command.args(["--features", "playground"]);
+    }
+    exec(&mut command)?;
+
+    let exe = if target.contains("windows") { "loxcraft.exe" } else { "loxcraft" };
+    Ok(root.join("target").join(target).join("release").join(exe))
+}
+
+/// Gathers everything that goes into an archive into `staging`.
+fn stage(root: &Path, staging: &Path, binary: &Path, playground: bool) -> Result<()> {
+    if staging.exists() {
+        fs::remove_dir_all(staging)
+            .with_context(|| format!("could not remove directory: {}", staging.display()))?;
+    }
+    fs::create_dir_all(staging)
+        .with_context(|| format!("could not create directory: {}", staging.display()))?;
+
+    let mut files = vec![binary.to_path_buf()];
+    files.extend(EXTRA_FILES.iter().map(|file| root.join(file)));
+    for file in files {
+        let dest = staging.join(file.file_name().unwrap());
+        fs::copy(&file, &dest).with_context(|| format!("could not copy: {}", file.display()))?;
+    }
+
+    // The binary serves these itself, but they can also be hosted as a
+    // static site.
+    if playground {
+        copy_dir(&root.join("playground").join("out"), &staging.join("playground"))?;
+    }
+    Ok(())
+}
+
+/// Archives the directory `name` in `dist_dir`, as a zip file for Windows
+/// and a gzipped tarball otherwise. Returns the path of the archive.
+fn archive(dist_dir: &Path, name: &str, zip: bool) -> Result<PathBuf> {
+    let archive = dist_dir.join(if zip { format!("{name}.zip") } else { format!("{name}.tar.gz") });
+    if archive.exists() {
+        fs::remove_file(&archive)
+            .with_context(|| format!("could not remove file: {}", archive.display()))?;
+    }
+    // The tar that ships with Windows can write zip files, but GNU tar
+    // cannot.
+    let (program, flags): (&str, &[&str]) = match (zip, cfg!(windows)) {
+        (true, false) => ("zip", &["-qr"]),
+        (true, true) => ("tar", &["-a", "-cf"]),
+        (false, _) => ("tar", &["-czf"]),
+    };
+    exec(Command::new(program).args(flags).arg(&archive).arg(name).current_dir(dist_dir))?;
+    Ok(archive)
+}
+
+/// Writes the SHA-256 checksum of `file` next to it, in the format that
+/// `sha256sum --check` reads. Returns the checksum.
+fn write_checksum(file: &Path) -> Result<String> {
+    let data = fs::read(file).with_context(|| format!("could not read: {}", file.display()))?;
+    let checksum = Sha256::digest(data).iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    });
+
+    let name = file.file_name().unwrap().to_string_lossy();
+    let mut path = file.as_os_str().to_owned();
+    path.push(".sha256");
+    fs::write(&path, format!("{checksum}  {name}\n"))
+        .with_context(|| format!("could not write checksum for: {}", file.display()))?;
+    Ok(checksum)
+}
+
+/// The version of the loxcraft package, e.g. `0.1.1`.
+fn version(root: &Path) -> Result<String> {
+    let pkgid =
+        output(Command::new("cargo").args(["pkgid", "--package", "loxcraft"]).current_dir(root))?;
+    // e.g. path+file:///path/to/loxcraft#0.1.1 or ...#loxcraft@0.1.1
+    let version = pkgid.trim().rsplit(['#', '@']).next().unwrap_or_default();
+    Ok(version.to_string())
+}
+
+/// The target triple of the toolchain that runs this task.
+pub fn host_target() -> Result<String> {
+    let info = output(Command::new("rustc").arg("-vV"))?;
+    info.lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+        .context("could not find the host target in `rustc -vV`")
+}
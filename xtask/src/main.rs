@@ -0,0 +1,84 @@
+//! Development tasks for loxcraft that need more than a single command. Run
+//! them with `cargo xtask <task>`.
+
+mod bench_compare;
+mod dist;
+mod pgo;
+mod playground;
+mod wasm;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use clap::Parser;
+
+#[derive(Debug, Parser)]
+enum Task {
+    /// Build release archives of loxcraft, with checksums, into target/dist/.
+    Dist(dist::DistArgs),
+    /// Build the WebAssembly module for the playground without wasm-pack,
+    /// optimize it for size, and check that it has not grown too much.
+    Wasm(wasm::WasmArgs),
+    /// Build a minimal playground into playground/out/ with only cargo and
+    /// the wasm-bindgen CLI, for machines without npm.
+    Playground,
+    /// Build an optimized release binary with profile-guided optimization,
+    /// trained on the benchmark suite, into target/pgo/.
+    Pgo(pgo::PgoArgs),
+    /// Time the benchmark suite with loxcraft, clox and jlox, and compare
+    /// them as a table or as JSON.
+    BenchCompare(bench_compare::BenchCompareArgs),
+}
+
+fn main() -> Result<()> {
+    match Task::parse() {
+        Task::Dist(args) => dist::run(&args),
+        Task::Wasm(args) => wasm::run(&args),
+        Task::Playground => playground::run(),
+        Task::Pgo(args) => pgo::run(&args),
+        Task::BenchCompare(args) => bench_compare::run(&args),
+    }
+}
+
+/// The root of the loxcraft repository.
+fn project_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap().to_path_buf()
+}
+
+/// Runs `command`, and fails if it does not succeed.
+fn exec(command: &mut Command) -> Result<()> {
+    eprintln!("$ {command:?}");
+    let status = command.status().with_context(|| format!("could not run: {command:?}"))?;
+    if !status.success() {
+        bail!("command exited with {status}: {command:?}");
+    }
+    Ok(())
+}
+
+/// Runs `command`, and returns what it printed to stdout.
+fn output(command: &mut Command) -> Result<String> {
+    let output = command.output().with_context(|| format!("could not run: {command:?}"))?;
+    if !output.status.success() {
+        bail!("command exited with {}: {command:?}", output.status);
+    }
+    String::from_utf8(output.stdout).context("command printed invalid UTF-8")
+}
+
+/// Copies a directory recursively.
+fn copy_dir(src: &Path, dest: &Path) -> Result<()> {
+    fs::create_dir_all(dest)
+        .with_context(|| format!("could not create directory: {}", dest.display()))?;
+    for entry in fs::read_dir(src).with_context(|| format!("could not read: {}", src.display()))? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            copy_dir(&path, &dest.join(entry.file_name()))?;
+        } else {
+            fs::copy(&path, dest.join(entry.file_name()))
+                .with_context(|| format!("could not copy: {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+
+use crate::{exec, output, project_root};
+
+#[derive(Debug, clap::Args)]
+pub struct PgoArgs {
+    /// Train on only the benchmarks whose file names contain this, instead of
+    /// the whole suite.
+    #[arg(long, value_name = "NAME")]
+    only: Option<String>,
+}
+
+/// Builds loxcraft with profile-guided optimization. The profile is recorded
+/// by running the benchmark suite in res/benchmarks, so that what the
+/// compiler optimizes for is what the benchmarks measure.
+pub fn run(args: &PgoArgs) -> Result<()> {
+    let root = project_root();
+    let profile_dir = root.join("target").join("pgo-profiles");
+    if profile_dir.exists() {
+        fs::remove_dir_all(&profile_dir)
+            .with_context(|| format!("could not remove directory: {}", profile_dir.display()))?;
+    }
+    fs::create_dir_all(&profile_dir)
+        .with_context(|| format!("could not create directory: {}", profile_dir.display()))?;
+    let llvm_profdata = llvm_profdata()?;
+
+    let flags = format!("-Cprofile-generate={}", profile_dir.display());
+    let binary = build(&root, &flags)?;
+    let benchmarks = benchmarks(&root, args.only.as_deref())?;
+    if benchmarks.is_empty() {
+        bail!("no benchmarks to train on");
+    }
+    for benchmark in &benchmarks {
+        exec(Command::new(&binary).arg("run").arg(benchmark))?;
+    }
+
+    let profile = profile_dir.join("merged.profdata");
+    exec(Command::new(llvm_profdata).arg("merge").arg("-o").arg(&profile).arg(&profile_dir))?;
+
+    let flags =
+        format!("-Cprofile-use={} -Cllvm-args=-pgo-warn-missing-function", profile.display());
+    let binary = build(&root, &flags)?;
+    eprintln!("built {}", binary.display());
+    Ok(())
+}
+
+/// Builds a release binary with `flags` added to RUSTFLAGS, and returns its
+/// path. Each build goes into a target directory of its own, so that the
+/// instrumented binary does not replace the optimized one.
+fn build(root: &Path, flags: &str) -> Result<PathBuf> {
+    let target_dir = root.join("target").join("pgo");
+    let rustflags = match std::env::var("RUSTFLAGS") {
+        Ok(rustflags) if !rustflags.is_empty() => format!("{rustflags} {flags}"),
+        _ => flags.to_string(),
+    };
+    exec(
+        Command::new("cargo")
+            .args(["build", "--release", "--target-dir"])
+            .arg(&target_dir)
+            .env("RUSTFLAGS", rustflags)
+            .current_dir(root),
+    )?;
+    let exe = if cfg!(windows) { "loxcraft.exe" } else { "loxcraft" };
+    Ok(target_dir.join("release").join(exe))
+}
+
+/// The benchmark scripts in res/benchmarks whose names contain `only`, in a
+/// stable order.
+pub fn benchmarks(root: &Path, only: Option<&str>) -> Result<Vec<PathBuf>> {
+    let dir = root.join("res").join("benchmarks");
+    let mut benchmarks = Vec::new();
+    for entry in fs::read_dir(&dir).with_context(|| format!("could not read: {}", dir.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else { continue };
+        if name.ends_with(".lox") && only.is_none_or(|only| name.contains(only)) {
+            benchmarks.push(path);
+        }
+    }
+    benchmarks.sort();
+    Ok(benchmarks)
+}
+
+/// The llvm-profdata that matches the toolchain, which ships with the
+/// `llvm-tools` rustup component.
+fn llvm_profdata() -> Result<PathBuf> {
+    let sysroot = output(Command::new("rustc").args(["--print", "sysroot"]))?;
+    let host = crate::dist::host_target()?;
+    let exe = if cfg!(windows) { "llvm-profdata.exe" } else { "llvm-profdata" };
+    let path =
+        Path::new(sysroot.trim()).join("lib").join("rustlib").join(host).join("bin").join(exe);
+    if !path.exists() {
+        bail!(
+            "could not find llvm-profdata at {}; install it with `rustup component add \
+             llvm-tools`",
+            path.display()
+        );
+    }
+    Ok(path)
+}
@@ -0,0 +1,35 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+
+use crate::{copy_dir, project_root, wasm};
+
+/// The files from the wasm bundle that the page loads.
+const BUNDLE_FILES: &[&str] = &["lox_wasm.js", "lox_wasm_bg.wasm"];
+
+/// Builds the static page in playground/static/ together with the wasm
+/// bundle into playground/out/, from where `lox playground` serves it.
+pub fn run() -> Result<()> {
+    wasm::build()?;
+
+    let playground_dir = project_root().join("playground");
+    let out_dir = playground_dir.join("out");
+    if out_dir.exists() {
+        fs::remove_dir_all(&out_dir)
+            .with_context(|| format!("could not remove directory: {}", out_dir.display()))?;
+    }
+    copy_dir(&playground_dir.join("static"), &out_dir)?;
+
+    let pkg_dir = wasm::pkg_dir();
+    for file in BUNDLE_FILES {
+        fs::copy(pkg_dir.join(file), out_dir.join(file))
+            .with_context(|| format!("could not copy: {file}"))?;
+    }
+    // Inline JavaScript snippets from dependencies, if there are any.
+    if pkg_dir.join("snippets").exists() {
+        copy_dir(&pkg_dir.join("snippets"), &out_dir.join("snippets"))?;
+    }
+
+    eprintln!("built the playground into {}", out_dir.display());
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{exec, output, project_root};
+
+#[derive(Debug, clap::Args)]
+pub struct WasmArgs {
+    /// Fail if the bundle is more than this many percent larger than the last
+    /// recorded size.
+    #[arg(long, value_name = "PERCENT", default_value_t = 5.0)]
+    max_growth: f64,
+    /// Append the size of this build to the history.
+    #[arg(long)]
+    record: bool,
+    /// Skip optimizing the module and checking its size, for machines
+    /// without binaryen.
+    #[arg(long, conflicts_with = "record")]
+    no_opt: bool,
+}
+
+/// The sizes of the files in the bundle, in bytes.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Sizes {
+    /// The commit that was built.
+    commit: String,
+    wasm: u64,
+    js: u64,
+}
+
+impl Sizes {
+    fn total(&self) -> u64 {
+        self.wasm + self.js
+    }
+}
+
+/// Where the sizes of previous builds are recorded, relative to
+/// [`crate_dir`].
+const HISTORY_FILE: &str = "sizes.json";
+
+/// The package.json that lets the Next.js playground depend on the bundle,
+/// as wasm-pack would write it.
+const PACKAGE_JSON: &str = r#"{
+  "name": "lox-wasm",
+  "version": "0.1.0",
+  "type": "module",
+  "files": ["lox_wasm_bg.wasm", "lox_wasm.js", "lox_wasm.d.ts"],
+  "module": "lox_wasm.js",
+  "types": "lox_wasm.d.ts",
+  "sideEffects": ["./snippets/*"]
+}
+"#;
+
+pub fn crate_dir() -> PathBuf {
+    project_root().join("playground").join("rust").join("lox-wasm")
+}
+
+/// The directory into which the bundle is built.
+pub fn pkg_dir() -> PathBuf {
+    crate_dir().join("pkg")
+}
+
+pub fn run(args: &WasmArgs) -> Result<()> {
+    build()?;
+    if args.no_opt {
+        return Ok(());
+    }
+
+    let wasm = pkg_dir().join("lox_wasm_bg.wasm");
+    let js = pkg_dir().join("lox_wasm.js");
+    exec(Command::new("wasm-opt").arg("-Oz").arg("-o").arg(&wasm).arg(&wasm))
+        .context("could not optimize the bundle; is binaryen installed?")?;
+
+    let sizes = Sizes { commit: commit()?, wasm: file_size(&wasm)?, js: file_size(&js)? };
+    eprintln!("bundle size: {} bytes ({} wasm, {} js)", sizes.total(), sizes.wasm, sizes.js);
+
+    let history_file = crate_dir().join(HISTORY_FILE);
+    let mut history = read_history(&history_file)?;
+    if let Some(last) = history.last() {
+        let growth = (sizes.total() as f64 / last.total() as f64 - 1.0) * 100.0;
+        eprintln!("change since {}: {growth:+.1}%", last.commit);
+        if growth > args.max_growth {
+            bail!(
+                "bundle grew by {growth:.1}% since {}, more than the {}% allowed",
+                last.commit,
+                args.max_growth
+            );
+        }
+    }
+
+    if args.record {
+        // Rebuilding the same commit replaces its entry.
+        if history.last().is_some_and(|last| last.commit == sizes.commit) {
+            history.pop();
+        }
+        history.push(sizes);
+        let json = serde_json::to_string_pretty(&history)? + "\n";
+        fs::write(&history_file, json)
+            .with_context(|| format!("could not write: {}", history_file.display()))?;
+    }
+    Ok(())
+}
+
+/// Builds lox-wasm and generates its JavaScript bindings into [`pkg_dir`],
+/// with nothing but cargo and the wasm-bindgen CLI.
+pub fn build() -> Result<()> {
+    let crate_dir = crate_dir();
+    exec(
+        Command::new("cargo")
+            .args(["build", "--release", "--target", "wasm32-unknown-unknown"])
+            .current_dir(&crate_dir),
+    )
+    .context("could not build lox-wasm; is the wasm32-unknown-unknown target installed?")?;
+
+    // The CLI refuses to process modules built with a different version of
+    // the wasm-bindgen crate, so check up front and say how to fix it.
+    let exp_version = locked_version(&crate_dir, "wasm-bindgen")?;
+    let got_version = output(Command::new("wasm-bindgen").arg("--version"))
+        .context("could not run wasm-bindgen; is wasm-bindgen-cli installed?")?;
+    if got_version.split_whitespace().nth(1) != Some(exp_version.as_str()) {
+        bail!(
+            "lox-wasm needs wasm-bindgen {exp_version}, but found {}; install it with `cargo \
+             install wasm-bindgen-cli --version {exp_version}`",
+            got_version.trim()
+        );
+    }
+
+    let module = crate_dir
+        .join("target")
+        .join("wasm32-unknown-unknown")
+        .join("release")
+        .join("lox_wasm.wasm");
+    exec(
+        Command::new("wasm-bindgen")
+            .args(["--target", "web", "--out-name", "lox_wasm", "--out-dir"])
+            .arg(pkg_dir())
+            .arg(module),
+    )?;
+    let package_json = pkg_dir().join("package.json");
+    fs::write(&package_json, PACKAGE_JSON)
+        .with_context(|| format!("could not write: {}", package_json.display()))
+}
+
+/// Returns the version of `package` in the lockfile of the crate in `dir`.
+fn locked_version(dir: &Path, package: &str) -> Result<String> {
+    let lockfile = dir.join("Cargo.lock");
+    let lock = fs::read_to_string(&lockfile)
+        .with_context(|| format!("could not read: {}", lockfile.display()))?;
+    let name = format!("name = {package:?}");
+    let mut lines = lock.lines();
+    lines
+        .find(|line| *line == name)
+        .and_then(|_| lines.next()?.strip_prefix("version = "))
+        .map(|version| version.trim_matches('"').to_string())
+        .with_context(|| format!("could not find {package} in: {}", lockfile.display()))
+}
+
+fn read_history(path: &Path) -> Result<Vec<Sizes>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let json =
+        fs::read_to_string(path).with_context(|| format!("could not read: {}", path.display()))?;
+    serde_json::from_str(&json).with_context(|| format!("invalid size history: {}", path.display()))
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    let metadata =
+        fs::metadata(path).with_context(|| format!("could not read: {}", path.display()))?;
+    Ok(metadata.len())
+}
+
+/// The abbreviated hash of the commit being built.
+fn commit() -> Result<String> {
+    let hash = output(
+        Command::new("git").args(["rev-parse", "--short", "HEAD"]).current_dir(project_root()),
+    )?;
+    Ok(hash.trim().to_string())
+}